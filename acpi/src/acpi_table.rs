@@ -400,3 +400,102 @@ pub mod madt_subtable {
         }
     }
 }
+
+/// This module describes ACPI SRAT's sub-tables.
+pub mod srat_subtable {
+    use super::*;
+
+    /// Processor Local APIC/SAPIC Affinity structure.
+    #[cfg(target_arch = "x86_64")]
+    #[repr(C, packed)]
+    #[derive(Default, Copy, Clone)]
+    pub struct AcpiSratProcessorAffinity {
+        /// Type ID.
+        pub type_id: u8,
+        /// The length of this structure.
+        pub length: u8,
+        /// Bit [7:0] of the proximity domain to which the processor belongs.
+        pub proximity_lo: u8,
+        /// The processor's Local APIC ID.
+        pub local_apic_id: u8,
+        /// Flags.
+        pub flags: u32,
+        /// The processor's local SAPIC EID.
+        pub local_sapic_eid: u8,
+        /// Bit [31:8] of the proximity domain to which the processor belongs.
+        pub proximity_hi: [u8; 3],
+        /// The clock domain to which the processor belongs.
+        pub clock_domain: u32,
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    impl ByteCode for AcpiSratProcessorAffinity {}
+
+    #[cfg(target_arch = "x86_64")]
+    impl AmlBuilder for AcpiSratProcessorAffinity {
+        fn aml_bytes(&self) -> Vec<u8> {
+            Vec::from(self.as_bytes())
+        }
+    }
+
+    /// GICC Affinity structure.
+    #[cfg(target_arch = "aarch64")]
+    #[repr(C, packed)]
+    #[derive(Default, Copy, Clone)]
+    pub struct AcpiSratGiccAffinity {
+        /// Type ID.
+        pub type_id: u8,
+        /// The length of this structure.
+        pub length: u8,
+        /// The proximity domain to which the processor belongs.
+        pub proximity_domain: u32,
+        /// ACPI processor UID.
+        pub processor_uid: u32,
+        /// Flags.
+        pub flags: u32,
+        /// The clock domain to which the processor belongs.
+        pub clock_domain: u32,
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    impl ByteCode for AcpiSratGiccAffinity {}
+
+    #[cfg(target_arch = "aarch64")]
+    impl AmlBuilder for AcpiSratGiccAffinity {
+        fn aml_bytes(&self) -> Vec<u8> {
+            Vec::from(self.as_bytes())
+        }
+    }
+
+    /// Memory Affinity structure.
+    #[repr(C, packed)]
+    #[derive(Default, Copy, Clone)]
+    pub struct AcpiSratMemoryAffinity {
+        /// Type ID.
+        pub type_id: u8,
+        /// The length of this structure.
+        pub length: u8,
+        /// The proximity domain to which the memory range belongs.
+        pub proximity_domain: u32,
+        /// Reserved field.
+        pub reserved_1: u16,
+        /// The base address of the memory range.
+        pub base_addr: u64,
+        /// The length of the memory range.
+        pub range_length: u64,
+        /// Reserved field.
+        pub reserved_2: u32,
+        /// Flags.
+        pub flags: u32,
+        /// Reserved field.
+        pub reserved_3: u64,
+    }
+
+    impl ByteCode for AcpiSratMemoryAffinity {}
+
+    impl AmlBuilder for AcpiSratMemoryAffinity {
+        fn aml_bytes(&self) -> Vec<u8> {
+            Vec::from(self.as_bytes())
+        }
+    }
+}
@@ -25,6 +25,7 @@ mod table_loader;
 
 pub use acpi_device::{AcpiPMTimer, AcpiPmCtrl, AcpiPmEvent};
 pub use acpi_table::madt_subtable::*;
+pub use acpi_table::srat_subtable::*;
 pub use acpi_table::*;
 pub use aml_compiler::*;
 pub use table_loader::TableLoader;
@@ -11,15 +11,17 @@
 // See the Mulan PSL v2 for more details.
 
 use std::io::Write;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use arc_swap::ArcSwap;
 use util::byte_code::ByteCode;
+use util::unix::host_page_size;
 
 use crate::errors::{ErrorKind, Result, ResultExt};
 use crate::{
-    AddressRange, FlatRange, GuestAddress, Listener, ListenerReqType, Region, RegionIoEventFd,
-    RegionType,
+    AddressRange, DirtyBitmap, FlatRange, GuestAddress, Listener, ListenerReqType, Region,
+    RegionIoEventFd, RegionType,
 };
 
 /// Contain an array of `FlatRange`.
@@ -57,6 +59,47 @@ impl Default for RegionCache {
 
 type ListenerObj = Arc<Mutex<dyn Listener>>;
 
+/// Bitmap of guest pages written by the VMM itself, e.g. the data read from
+/// backends and the used rings filled by devices, which are not logged by KVM.
+struct UserDirtyLog {
+    /// One bit represents one host page starting from guest address 0.
+    bitmap: Vec<AtomicU64>,
+    /// Count of host pages covered by the bitmap.
+    nr_pages: u64,
+}
+
+impl UserDirtyLog {
+    fn new(mem_end: u64) -> Self {
+        let page_size = host_page_size();
+        let nr_pages = (mem_end + page_size - 1) / page_size;
+        let bitmap = (0..(nr_pages + 63) / 64)
+            .map(|_| AtomicU64::new(0))
+            .collect();
+        UserDirtyLog { bitmap, nr_pages }
+    }
+
+    fn mark(&self, addr: u64, len: u64) {
+        let page_size = host_page_size();
+        let first = addr / page_size;
+        let last = std::cmp::min((addr + len - 1) / page_size, self.nr_pages - 1);
+        for page in first..=last {
+            self.bitmap[(page / 64) as usize].fetch_or(1 << (page % 64), Ordering::SeqCst);
+        }
+    }
+
+    fn take(&self) -> DirtyBitmap {
+        DirtyBitmap {
+            guest_addr: 0,
+            size: self.nr_pages * host_page_size(),
+            bitmap: self
+                .bitmap
+                .iter()
+                .map(|bits| bits.swap(0, Ordering::SeqCst))
+                .collect(),
+        }
+    }
+}
+
 /// Address Space of memory.
 #[derive(Clone)]
 pub struct AddressSpace {
@@ -69,6 +112,8 @@ pub struct AddressSpace {
     listeners: Arc<Mutex<Vec<ListenerObj>>>,
     /// The current layout of ioeventfds, which is compared with new ones in topology-update stage.
     ioeventfds: Arc<Mutex<Vec<RegionIoEventFd>>>,
+    /// Pages written by the VMM while logging dirty pages, `None` if logging is off.
+    user_dirty_log: Arc<RwLock<Option<UserDirtyLog>>>,
 }
 
 impl AddressSpace {
@@ -83,6 +128,7 @@ impl AddressSpace {
             flat_view: ArcSwap::new(Arc::new(FlatView::default())),
             listeners: Arc::new(Mutex::new(Vec::new())),
             ioeventfds: Arc::new(Mutex::new(Vec::new())),
+            user_dirty_log: Arc::new(RwLock::new(None)),
         });
 
         root.set_belonged_address_space(&space);
@@ -453,7 +499,11 @@ impl AddressSpace {
                     region_base.raw_value(),
                     offset_in_region,
                     count
-                ))
+                ))?;
+        if fr.owner.region_type() == RegionType::Ram {
+            self.mark_dirty(addr, count);
+        }
+        Ok(())
     }
 
     /// Write an object to memory.
@@ -484,7 +534,9 @@ impl AddressSpace {
             std::slice::from_raw_parts_mut(host_addr as *mut u8, std::mem::size_of::<T>() as usize)
         };
         dst.write_all(data.as_bytes())
-            .chain_err(|| "Failed to write object via host address")
+            .chain_err(|| "Failed to write object via host address")?;
+        self.mark_dirty_host(host_addr, std::mem::size_of::<T>() as u64);
+        Ok(())
     }
 
     /// Read some data from memory to form an object.
@@ -546,6 +598,123 @@ impl AddressSpace {
             .chain_err(|| "Failed to generate and update ioeventfds")?;
         Ok(())
     }
+
+    /// Mark the guest memory written by the VMM as dirty, so that it is sent
+    /// again in live migration. It does nothing if dirty logging is off.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Start guest address of the written memory.
+    /// * `len` - Length of the written memory.
+    pub fn mark_dirty(&self, addr: GuestAddress, len: u64) {
+        if len == 0 {
+            return;
+        }
+        if let Some(log) = self.user_dirty_log.read().unwrap().as_ref() {
+            if addr.raw_value() < log.nr_pages * host_page_size() {
+                log.mark(addr.raw_value(), len);
+            }
+        }
+    }
+
+    /// Mark the guest memory written by the VMM through host address as dirty.
+    ///
+    /// # Arguments
+    ///
+    /// * `host_addr` - Start host address of the written memory.
+    /// * `len` - Length of the written memory.
+    pub fn mark_dirty_host(&self, host_addr: u64, len: u64) {
+        if len == 0 || self.user_dirty_log.read().unwrap().is_none() {
+            return;
+        }
+        let view = self.flat_view.load();
+        for fr in view.0.iter() {
+            if fr.owner.region_type() != RegionType::Ram {
+                continue;
+            }
+            let host_base = match fr.owner.get_host_address() {
+                Some(host) => host + fr.offset_in_region,
+                None => continue,
+            };
+            if host_addr >= host_base && host_addr < host_base + fr.addr_range.size {
+                let addr = fr.addr_range.base.unchecked_add(host_addr - host_base);
+                self.mark_dirty(addr, len);
+                return;
+            }
+        }
+    }
+
+    /// Start or stop logging dirty pages of memory by the VMM and all listeners.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - Start logging if `true`, otherwise stop logging.
+    pub fn set_dirty_log(&self, enable: bool) -> Result<()> {
+        *self.user_dirty_log.write().unwrap() = if enable {
+            Some(UserDirtyLog::new(self.memory_end_address().raw_value()))
+        } else {
+            None
+        };
+        for listener in self.listeners.lock().unwrap().iter() {
+            listener
+                .lock()
+                .unwrap()
+                .set_dirty_log(enable)
+                .chain_err(|| "Failed to set dirty log for listener")?;
+        }
+        Ok(())
+    }
+
+    /// Get and clear dirty pages logged by the VMM and all listeners since last call.
+    pub fn get_dirty_log(&self) -> Result<Vec<DirtyBitmap>> {
+        let mut dirty_bitmaps = Vec::new();
+        if let Some(log) = self.user_dirty_log.read().unwrap().as_ref() {
+            dirty_bitmaps.push(log.take());
+        }
+        for listener in self.listeners.lock().unwrap().iter() {
+            dirty_bitmaps.extend(
+                listener
+                    .lock()
+                    .unwrap()
+                    .get_dirty_log()
+                    .chain_err(|| "Failed to get dirty log from listener")?,
+            );
+        }
+        Ok(dirty_bitmaps)
+    }
+
+    /// Render the region tree of this `AddressSpace` as text, one region per line.
+    /// Sub-regions are indented under their parent and listed in descending
+    /// order of priority, with absolute address ranges.
+    pub fn mtree(&self) -> String {
+        let mut info = String::new();
+        Self::mtree_region(&self.root, 0, 1, &mut info);
+        info
+    }
+
+    fn mtree_region(region: &Region, base: u64, depth: usize, info: &mut String) {
+        let start = base.wrapping_add(region.offset().raw_value());
+        let end = start.saturating_add(region.size().saturating_sub(1));
+        let type_name = match region.region_type() {
+            RegionType::Ram => "ram",
+            RegionType::IO => "i/o",
+            RegionType::Container => "container",
+            RegionType::RomDevice => "romd",
+            RegionType::RamDevice => "ramd",
+        };
+        info.push_str(&format!(
+            "{:indent$}{:016x}-{:016x} (prio {}, {})\n",
+            "",
+            start,
+            end,
+            region.priority(),
+            type_name,
+            indent = depth * 2
+        ));
+        for sub in region.subregions().iter() {
+            Self::mtree_region(sub, start, depth + 1, info);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -911,4 +1080,35 @@ mod test {
         assert_eq!(data1, 10000);
         assert!(space.write_object(&data, GuestAddress(993)).is_err());
     }
+
+    #[test]
+    fn test_mtree() {
+        let root = Region::init_container_region(8000);
+        let space = AddressSpace::new(root.clone()).unwrap();
+        let ram1 = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 1000, None, false, false, false).unwrap(),
+        );
+        let container = Region::init_container_region(2000);
+        container.set_priority(1);
+        container
+            .add_subregion(Region::init_ram_region(ram1), 1000)
+            .unwrap();
+        root.add_subregion(container, 4000).unwrap();
+
+        let mtree = space.mtree();
+        let lines: Vec<&str> = mtree.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "  0000000000000000-0000000000001f3f (prio 0, container)"
+        );
+        assert_eq!(
+            lines[1],
+            "    0000000000000fa0-000000000000176f (prio 1, container)"
+        );
+        assert_eq!(
+            lines[2],
+            "      0000000000001388-000000000000176f (prio 0, ram)"
+        );
+    }
 }
@@ -16,14 +16,26 @@ use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::sync::Arc;
 use std::thread;
 
-use machine_manager::config::MachineMemConfig;
+use machine_manager::config::{HostMemPolicy, MachineMemConfig, MemBackendType, MemZoneConfig};
 
 use crate::errors::{Result, ResultExt};
 use crate::{AddressRange, GuestAddress};
-use util::unix::{do_mmap, host_page_size};
+use util::unix::{do_mmap, host_page_size, mbind};
 
 const MAX_PREALLOC_THREAD: u8 = 16;
 
+/// Memory policy modes and flags of mbind, refer to linux/mempolicy.h.
+const MPOL_DEFAULT: u32 = 0;
+const MPOL_PREFERRED: u32 = 1;
+const MPOL_BIND: u32 = 2;
+const MPOL_INTERLEAVE: u32 = 3;
+const MPOL_MF_STRICT: u32 = 1 << 0;
+const MPOL_MF_MOVE: u32 = 1 << 1;
+/// Shift of huge page size in memfd_create flags, refer to linux/memfd.h.
+const MFD_HUGE_SHIFT: u32 = 26;
+/// Magic number of hugetlbfs, refer to linux/magic.h.
+const HUGETLBFS_MAGIC: u64 = 0x9584_58f6;
+
 /// FileBackend represents backend-file of `HostMemMapping`.
 #[derive(Clone)]
 pub struct FileBackend {
@@ -107,10 +119,7 @@ impl FileBackend {
             file_ret
         };
 
-        // Safe because struct `statfs` only contains plain-data-type field,
-        // and set to all-zero will not cause any undefined behavior.
-        let mut fstat: libc::statfs = unsafe { std::mem::zeroed() };
-        unsafe { libc::fstatfs(file.as_raw_fd(), &mut fstat) };
+        let fstat = fs_stat(&file)?;
         info!(
             "Using memory backing file, the page size is {}",
             fstat.f_bsize
@@ -134,6 +143,124 @@ impl FileBackend {
             page_size: fstat.f_bsize as u64,
         })
     }
+
+    /// Construct a new FileBackend with an anonymous memfd.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of memfd.
+    /// * `file_len` - The size of memfd.
+    /// * `hugetlb` - Back the memfd with huge pages or not.
+    /// * `hugetlb_size` - Size of huge page, use the default huge page size if not set.
+    pub fn new_memfd(
+        name: &str,
+        file_len: u64,
+        hugetlb: bool,
+        hugetlb_size: Option<u64>,
+    ) -> Result<FileBackend> {
+        let mut flags = 0;
+        if hugetlb {
+            flags |= libc::MFD_HUGETLB;
+            if let Some(page_size) = hugetlb_size {
+                flags |= page_size.trailing_zeros() << MFD_HUGE_SHIFT;
+            }
+        }
+        let name = std::ffi::CString::new(name).unwrap();
+        let anon_fd =
+            unsafe { libc::syscall(libc::SYS_memfd_create, name.as_ptr(), flags) } as RawFd;
+        if anon_fd < 0 {
+            return Err(std::io::Error::last_os_error()).chain_err(|| "Failed to create memfd");
+        }
+
+        let anon_file = unsafe { File::from_raw_fd(anon_fd) };
+        anon_file
+            .set_len(file_len)
+            .chain_err(|| "Failed to set the length of anonymous file that backs memory")?;
+        let page_size = if hugetlb {
+            fs_stat(&anon_file)?.f_bsize as u64
+        } else {
+            host_page_size()
+        };
+
+        Ok(FileBackend {
+            file: Arc::new(anon_file),
+            offset: 0,
+            page_size,
+        })
+    }
+}
+
+/// Get the statistics of file system which the file belongs to.
+///
+/// # Arguments
+///
+/// * `file` - Opened file.
+fn fs_stat(file: &File) -> Result<libc::statfs> {
+    // Safe because struct `statfs` only contains plain-data-type field,
+    // and set to all-zero will not cause any undefined behavior.
+    let mut fstat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatfs(file.as_raw_fd(), &mut fstat) } != 0 {
+        return Err(std::io::Error::last_os_error()).chain_err(|| "Failed to get file system stat");
+    }
+    Ok(fstat)
+}
+
+/// Create the FileBackend of memory backend, `None` for anonymous private memory.
+///
+/// # Arguments
+///
+/// * `mem_zone` - Config of memory backend.
+/// * `share` - The memory is shared or not.
+fn create_backend_file(mem_zone: &MemZoneConfig, share: bool) -> Result<Option<FileBackend>> {
+    let f_back = match mem_zone.backend_type {
+        MemBackendType::File => {
+            // It's safe to unwrap, as mem-path is checked for memory-backend-file.
+            let path = mem_zone.mem_path.as_ref().unwrap();
+            let f_back = FileBackend::new_mem(path, mem_zone.size)?;
+            if mem_zone.hugetlb {
+                let fstat = fs_stat(f_back.file.as_ref())?;
+                if fstat.f_type as u64 != HUGETLBFS_MAGIC {
+                    bail!("Mem-path {} of {} is not on hugetlbfs", path, mem_zone.id);
+                }
+                if let Some(page_size) = mem_zone.hugetlb_size {
+                    if page_size != f_back.page_size {
+                        bail!(
+                            "Hugetlbsize 0x{:x} of {} mismatches page size 0x{:x} of {}",
+                            page_size,
+                            mem_zone.id,
+                            f_back.page_size,
+                            path
+                        );
+                    }
+                }
+            }
+            Some(f_back)
+        }
+        MemBackendType::Memfd => Some(FileBackend::new_memfd(
+            &mem_zone.id,
+            mem_zone.size,
+            mem_zone.hugetlb,
+            mem_zone.hugetlb_size,
+        )?),
+        MemBackendType::Ram if share => Some(FileBackend::new_memfd(
+            "stratovirt_anon_mem",
+            mem_zone.size,
+            false,
+            None,
+        )?),
+        MemBackendType::Ram => None,
+    };
+
+    if let Some(fb) = &f_back {
+        if mem_zone.size % fb.page_size != 0 {
+            bail!(
+                "Size of {} is not aligned with page size 0x{:x}",
+                mem_zone.id,
+                fb.page_size
+            );
+        }
+    }
+    Ok(f_back)
 }
 
 /// Get the max number of threads that can be used to touch pages.
@@ -142,8 +269,10 @@ impl FileBackend {
 ///
 /// * `nr_vcpus` - Number of vcpus.
 fn max_nr_threads(nr_vcpus: u8) -> u8 {
-    let nr_host_cpu = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) as u8 };
-    min(min(nr_host_cpu, MAX_PREALLOC_THREAD), nr_vcpus)
+    let nr_host_cpu = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    let nr_threads = min(nr_host_cpu, i64::from(MAX_PREALLOC_THREAD));
+    // The number of host cpu is at least 1, it's safe to cast as it's below MAX_PREALLOC_THREAD.
+    min(nr_threads.max(1) as u8, nr_vcpus)
 }
 
 /// Touch pages to pre-alloc memory for VM.
@@ -175,10 +304,9 @@ fn touch_pages(start: u64, page_size: u64, nr_pages: u64) {
 ///
 /// * `host_addr` - The start host address of memory of the virtual machine.
 /// * `size` - Size of memory.
-/// * `nr_vcpus` - Number of vcpus.
-fn mem_prealloc(host_addr: u64, size: u64, nr_vcpus: u8) {
-    let page_size = host_page_size();
-    let threads = max_nr_threads(nr_vcpus);
+/// * `page_size` - Size of the pages backing the memory.
+/// * `threads` - Number of threads to touch pages.
+fn mem_prealloc(host_addr: u64, size: u64, page_size: u64, threads: u8) {
     let nr_pages = (size + page_size - 1) / page_size;
     let pages_per_thread = nr_pages / (threads as u64);
     let left = nr_pages % (threads as u64);
@@ -204,6 +332,37 @@ fn mem_prealloc(host_addr: u64, size: u64, nr_vcpus: u8) {
     }
 }
 
+/// Bind the host memory to host numa nodes according to the policy of memory backend.
+///
+/// # Arguments
+///
+/// * `host_addr` - The start host address of memory.
+/// * `size` - Size of memory.
+/// * `mem_zone` - Config of memory backend.
+fn set_host_memory_policy(host_addr: u64, size: u64, mem_zone: &MemZoneConfig) -> Result<()> {
+    let host_nodes = match &mem_zone.host_numa_nodes {
+        Some(nodes) if !nodes.is_empty() => nodes,
+        _ => return Ok(()),
+    };
+
+    // It's safe to unwrap, as host_nodes is not empty.
+    let max_node = u64::from(*host_nodes.iter().max().unwrap()) + 1;
+    let mut node_mask = vec![0_u64; (max_node / 64 + 1) as usize];
+    for node in host_nodes.iter() {
+        node_mask[(node / 64) as usize] |= 1_u64 << (node % 64);
+    }
+    let (mode, flags) = match mem_zone.policy {
+        HostMemPolicy::Default => (MPOL_DEFAULT, 0),
+        HostMemPolicy::Preferred => (MPOL_PREFERRED, 0),
+        HostMemPolicy::Bind => (MPOL_BIND, MPOL_MF_STRICT | MPOL_MF_MOVE),
+        HostMemPolicy::Interleave => (MPOL_INTERLEAVE, MPOL_MF_STRICT | MPOL_MF_MOVE),
+    };
+
+    // The kernel drops the last bit of node mask, so pass one more bit.
+    mbind(host_addr, size, mode, &node_mask, max_node + 1, flags)
+        .chain_err(|| format!("Failed to bind memory to host numa nodes {:?}", host_nodes))
+}
+
 /// Create HostMemMappings according to address ranges.
 ///
 /// # Arguments
@@ -215,48 +374,96 @@ pub fn create_host_mmaps(
     mem_config: &MachineMemConfig,
     nr_vcpus: u8,
 ) -> Result<Vec<Arc<HostMemMapping>>> {
-    let mut f_back: Option<FileBackend> = None;
-
-    if let Some(path) = &mem_config.mem_path {
-        let file_len = ranges.iter().fold(0, |acc, x| acc + x.1);
-        f_back = Some(
-            FileBackend::new_mem(&path, file_len)
+    let mem_size = ranges.iter().fold(0, |acc, x| acc + x.1);
+    let f_back = if let Some(path) = &mem_config.mem_path {
+        Some(
+            FileBackend::new_mem(path, mem_size)
                 .chain_err(|| "Failed to create file that backs memory")?,
-        );
+        )
     } else if mem_config.mem_share {
-        let file_len = ranges.iter().fold(0, |acc, x| acc + x.1);
-        let anon_mem_name = String::from("stratovirt_anon_mem");
-
-        let anon_fd =
-            unsafe { libc::syscall(libc::SYS_memfd_create, anon_mem_name.as_ptr(), 0) } as RawFd;
-        if anon_fd < 0 {
-            return Err(std::io::Error::last_os_error()).chain_err(|| "Failed to create memfd");
-        }
+        Some(FileBackend::new_memfd(
+            "stratovirt_anon_mem",
+            mem_size,
+            false,
+            None,
+        )?)
+    } else {
+        None
+    };
 
-        let anon_file = unsafe { File::from_raw_fd(anon_fd) };
-        anon_file
-            .set_len(file_len)
-            .chain_err(|| "Failed to set the length of anonymous file that backs memory")?;
+    let host_addr = mmap_backend(&f_back, mem_size, mem_config.mem_share, mem_config)?;
+    if mem_config.mem_prealloc {
+        let page_size = f_back.as_ref().map_or(host_page_size(), |fb| fb.page_size);
+        mem_prealloc(host_addr, mem_size, page_size, max_nr_threads(nr_vcpus));
+    }
+    create_mappings(ranges, host_addr, f_back, mem_config.mem_share, mem_config)
+}
 
-        f_back = Some(FileBackend {
-            file: Arc::new(anon_file),
-            offset: 0,
-            page_size: host_page_size(),
-        });
+/// Create HostMemMappings of memory backend according to address ranges, the
+/// memory is bound to host numa nodes before it is touched.
+///
+/// # Arguments
+///
+/// * `ranges` - The guest address ranges backed by the memory backend.
+/// * `mem_config` - Machine memory config.
+/// * `mem_zone` - Config of the memory backend.
+pub fn create_backend_mmaps(
+    ranges: &[(u64, u64)],
+    mem_config: &MachineMemConfig,
+    nr_vcpus: u8,
+    mem_zone: &MemZoneConfig,
+) -> Result<Vec<Arc<HostMemMapping>>> {
+    let mem_size = ranges.iter().fold(0, |acc, x| acc + x.1);
+    if mem_size != mem_zone.size {
+        bail!(
+            "Size of {} 0x{:x} mismatches the guest ram size 0x{:x}",
+            mem_zone.id,
+            mem_zone.size,
+            mem_size
+        );
+    }
+    // Vhost-user devices require the guest memory to be shared.
+    let share = mem_zone.share || mem_config.mem_share;
+    let f_back = create_backend_file(mem_zone, share)
+        .chain_err(|| format!("Failed to create memory backend {}", mem_zone.id))?;
+
+    let host_addr = mmap_backend(&f_back, mem_size, share, mem_config)?;
+    set_host_memory_policy(host_addr, mem_size, mem_zone)?;
+    if mem_zone.prealloc || mem_config.mem_prealloc {
+        let page_size = f_back.as_ref().map_or(host_page_size(), |fb| fb.page_size);
+        let threads = mem_zone
+            .prealloc_threads
+            .unwrap_or_else(|| max_nr_threads(nr_vcpus));
+        mem_prealloc(host_addr, mem_size, page_size, threads);
     }
+    create_mappings(ranges, host_addr, f_back, share, mem_config)
+}
 
-    let backend = (&f_back).as_ref();
-    let mut host_addr = do_mmap(
+fn mmap_backend(
+    f_back: &Option<FileBackend>,
+    mem_size: u64,
+    share: bool,
+    mem_config: &MachineMemConfig,
+) -> Result<u64> {
+    let backend = f_back.as_ref();
+    let host_addr = do_mmap(
         &backend.map(|fb| fb.file.as_ref()),
-        mem_config.mem_size,
+        mem_size,
         backend.map_or(0, |fb| fb.offset),
         false,
-        mem_config.mem_share,
+        share,
         mem_config.dump_guest_core,
     )?;
-    if mem_config.mem_prealloc {
-        mem_prealloc(host_addr, mem_config.mem_size, nr_vcpus);
-    }
+    Ok(host_addr)
+}
+
+fn create_mappings(
+    ranges: &[(u64, u64)],
+    mut host_addr: u64,
+    mut f_back: Option<FileBackend>,
+    share: bool,
+    mem_config: &MachineMemConfig,
+) -> Result<Vec<Arc<HostMemMapping>>> {
     let mut mappings = Vec::new();
     for range in ranges.iter() {
         mappings.push(Arc::new(HostMemMapping::new(
@@ -265,7 +472,7 @@ pub fn create_host_mmaps(
             range.1,
             f_back.clone(),
             mem_config.dump_guest_core,
-            mem_config.mem_share,
+            share,
             false,
         )?));
         host_addr += range.1;
@@ -490,6 +697,7 @@ mod test {
             dump_guest_core: false,
             mem_share: false,
             mem_prealloc: false,
+            mem_backend: None,
         };
 
         let host_mmaps = create_host_mmaps(&addr_ranges, &mem_config, 1).unwrap();
@@ -515,4 +723,38 @@ mod test {
         assert_eq!(total_mem_size, total_file_size);
         assert_eq!(total_mem_size, total_mmaps_size);
     }
+
+    #[test]
+    fn test_create_backend_mmaps() {
+        let addr_ranges = [(0x0, 0x10_0000), (0x100000, 0x10_0000)];
+        let mem_config = MachineMemConfig {
+            mem_size: 0x20_0000,
+            dump_guest_core: false,
+            ..Default::default()
+        };
+        let mem_zone = MemZoneConfig {
+            id: "mem0".to_string(),
+            size: 0x20_0000,
+            backend_type: MemBackendType::Memfd,
+            share: true,
+            prealloc: true,
+            prealloc_threads: Some(2),
+            ..Default::default()
+        };
+
+        let host_mmaps = create_backend_mmaps(&addr_ranges, &mem_config, 1, &mem_zone).unwrap();
+        assert_eq!(host_mmaps.len(), 2);
+        for (index, mmap) in host_mmaps.iter().enumerate() {
+            assert_eq!(mmap.start_address().raw_value(), addr_ranges[index].0);
+            assert_eq!(mmap.size(), addr_ranges[index].1);
+            assert_eq!(mmap.file_backend().unwrap().offset, addr_ranges[index].0);
+        }
+
+        // The size of memory backend must be equal to the size of ranges.
+        let mem_zone = MemZoneConfig {
+            size: 0x10_0000,
+            ..mem_zone
+        };
+        assert!(create_backend_mmaps(&addr_ranges, &mem_config, 1, &mem_zone).is_err());
+    }
 }
@@ -93,11 +93,11 @@ mod state;
 
 pub use crate::address_space::{AddressSpace, RegionCache};
 pub use address::{AddressRange, GuestAddress};
-pub use host_mmap::{create_host_mmaps, FileBackend, HostMemMapping};
+pub use host_mmap::{create_backend_mmaps, create_host_mmaps, FileBackend, HostMemMapping};
 #[cfg(target_arch = "x86_64")]
 pub use listener::KvmIoListener;
 pub use listener::KvmMemoryListener;
-pub use listener::{Listener, ListenerReqType};
+pub use listener::{DirtyBitmap, Listener, ListenerReqType};
 pub use region::{FlatRange, Region, RegionIoEventFd, RegionType};
 
 pub mod errors {
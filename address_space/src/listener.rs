@@ -10,7 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
 use error_chain::ChainedError;
@@ -22,6 +22,7 @@ use crate::errors::{ErrorKind, Result, ResultExt};
 use crate::{AddressRange, FlatRange, RegionIoEventFd, RegionType};
 use util::{num_ops::round_down, unix::host_page_size};
 
+const MEM_LOG_DIRTY_PAGES: u32 = 1 << 0;
 const MEM_READ_ONLY: u32 = 1 << 1;
 
 /// Request type of listener.
@@ -67,6 +68,31 @@ pub trait Listener: Send + Sync {
     ) -> Result<()> {
         Ok(())
     }
+
+    /// Start or stop logging dirty pages of the memory managed by this listener.
+    ///
+    /// # Arguments
+    ///
+    /// * `_enable` - Start logging if `true`, otherwise stop logging.
+    fn set_dirty_log(&self, _enable: bool) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get and clear the dirty pages logged since last call.
+    fn get_dirty_log(&self) -> Result<Vec<DirtyBitmap>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Bitmap of dirty pages in a piece of guest memory.
+#[derive(Debug, Default, Clone)]
+pub struct DirtyBitmap {
+    /// Start guest address of the memory.
+    pub guest_addr: u64,
+    /// Size of the memory.
+    pub size: u64,
+    /// One bit represents one host page, bit set means the page is dirty.
+    pub bitmap: Vec<u64>,
 }
 
 /// Records information that manage the slot resource and current usage.
@@ -93,6 +119,8 @@ pub struct KvmMemoryListener {
     as_id: Arc<AtomicU32>,
     /// Record all MemSlots.
     slots: Arc<Mutex<Vec<MemSlot>>>,
+    /// Whether dirty pages of memory slots are logged.
+    dirty_log: Arc<AtomicBool>,
 }
 
 impl KvmMemoryListener {
@@ -105,6 +133,7 @@ impl KvmMemoryListener {
         KvmMemoryListener {
             as_id: Arc::new(AtomicU32::new(0)),
             slots: Arc::new(Mutex::new(vec![MemSlot::default(); nr_slots as usize])),
+            dirty_log: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -115,13 +144,14 @@ impl KvmMemoryListener {
     /// * `guest_addr` - Guest address.
     /// * `size` - Size of slot.
     /// * `host_addr` - Host address.
+    /// * `flag` - Flags of the memory slot.
     ///
     /// # Errors
     ///
     /// Return Error if
     /// * No available Kvm slot.
     /// * Given memory slot overlap with existed one.
-    fn get_free_slot(&self, guest_addr: u64, size: u64, host_addr: u64) -> Result<u32> {
+    fn get_free_slot(&self, guest_addr: u64, size: u64, host_addr: u64, flag: u32) -> Result<u32> {
         let mut slots = self.slots.lock().unwrap();
 
         // check if the given address range overlaps with exist ones
@@ -144,6 +174,7 @@ impl KvmMemoryListener {
                 slot.guest_addr = guest_addr;
                 slot.size = size;
                 slot.host_addr = host_addr;
+                slot.flag = flag;
                 return Ok(slot.index);
             }
         }
@@ -244,14 +275,17 @@ impl KvmMemoryListener {
             + flat_range.offset_in_region
             + align_adjust;
 
-        let slot_idx = self
-            .get_free_slot(aligned_addr.raw_value(), aligned_size, aligned_hva)
-            .chain_err(|| "Failed to get available KVM mem slot")?;
-
         let mut flags = 0_u32;
         if flat_range.owner.get_rom_device_romd().unwrap_or(false) {
             flags |= MEM_READ_ONLY;
         }
+        let slot_idx = self
+            .get_free_slot(aligned_addr.raw_value(), aligned_size, aligned_hva, flags)
+            .chain_err(|| "Failed to get available KVM mem slot")?;
+
+        if self.dirty_log.load(Ordering::SeqCst) && flags & MEM_READ_ONLY == 0 {
+            flags |= MEM_LOG_DIRTY_PAGES;
+        }
         let kvm_region = kvm_userspace_memory_region {
             slot: slot_idx | (self.as_id.load(Ordering::SeqCst) << 16),
             guest_phys_addr: aligned_addr.raw_value(),
@@ -332,6 +366,49 @@ impl KvmMemoryListener {
         Ok(())
     }
 
+    /// Update flags of all writable memory slots to start or stop logging dirty pages.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - Start logging if `true`, otherwise stop logging.
+    fn update_dirty_log(&self, enable: bool) -> Result<()> {
+        let slots = self.slots.lock().unwrap();
+        for slot in slots.iter() {
+            if slot.size == 0 || slot.flag & MEM_READ_ONLY != 0 {
+                continue;
+            }
+
+            let kvm_region = kvm_userspace_memory_region {
+                slot: slot.index | (self.as_id.load(Ordering::SeqCst) << 16),
+                guest_phys_addr: slot.guest_addr,
+                memory_size: slot.size,
+                userspace_addr: slot.host_addr,
+                flags: if enable {
+                    slot.flag | MEM_LOG_DIRTY_PAGES
+                } else {
+                    slot.flag
+                },
+            };
+            unsafe {
+                KVM_FDS
+                    .load()
+                    .vm_fd
+                    .as_ref()
+                    .unwrap()
+                    .set_user_memory_region(kvm_region)
+                    .chain_err(|| {
+                        format!(
+                            "KVM update dirty log flag of memory region failed: addr 0x{:X}, size 0x{:X}",
+                            slot.guest_addr, slot.size
+                        )
+                    })?;
+            }
+        }
+        self.dirty_log.store(enable, Ordering::SeqCst);
+
+        Ok(())
+    }
+
     /// Register a IoEvent to `/dev/kvm`.
     ///
     /// # Arguments
@@ -450,6 +527,47 @@ impl Listener for KvmMemoryListener {
 
         req_ret.chain_err(|| ErrorKind::ListenerRequest(req_type))
     }
+
+    fn set_dirty_log(&self, enable: bool) -> Result<()> {
+        self.update_dirty_log(enable)
+    }
+
+    fn get_dirty_log(&self) -> Result<Vec<DirtyBitmap>> {
+        if !self.dirty_log.load(Ordering::SeqCst) {
+            return Ok(Vec::new());
+        }
+
+        let mut dirty_bitmaps = Vec::new();
+        let slots = self.slots.lock().unwrap();
+        for slot in slots.iter() {
+            if slot.size == 0 || slot.flag & MEM_READ_ONLY != 0 {
+                continue;
+            }
+
+            let bitmap = KVM_FDS
+                .load()
+                .vm_fd
+                .as_ref()
+                .unwrap()
+                .get_dirty_log(
+                    slot.index | (self.as_id.load(Ordering::SeqCst) << 16),
+                    slot.size as usize,
+                )
+                .chain_err(|| {
+                    format!(
+                        "KVM get dirty log failed: addr 0x{:X}, size 0x{:X}",
+                        slot.guest_addr, slot.size
+                    )
+                })?;
+            dirty_bitmaps.push(DirtyBitmap {
+                guest_addr: slot.guest_addr,
+                size: slot.size,
+                bitmap,
+            });
+        }
+
+        Ok(dirty_bitmaps)
+    }
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -624,18 +742,18 @@ mod test {
         let kml = KvmMemoryListener::new(4);
         let host_addr = 0u64;
 
-        assert_eq!(kml.get_free_slot(0, 100, host_addr).unwrap(), 0);
-        assert_eq!(kml.get_free_slot(200, 100, host_addr).unwrap(), 1);
-        assert_eq!(kml.get_free_slot(300, 100, host_addr).unwrap(), 2);
-        assert_eq!(kml.get_free_slot(500, 100, host_addr).unwrap(), 3);
-        assert!(kml.get_free_slot(200, 100, host_addr).is_err());
+        assert_eq!(kml.get_free_slot(0, 100, host_addr, 0).unwrap(), 0);
+        assert_eq!(kml.get_free_slot(200, 100, host_addr, 0).unwrap(), 1);
+        assert_eq!(kml.get_free_slot(300, 100, host_addr, 0).unwrap(), 2);
+        assert_eq!(kml.get_free_slot(500, 100, host_addr, 0).unwrap(), 3);
+        assert!(kml.get_free_slot(200, 100, host_addr, 0).is_err());
         // no available KVM mem slot
-        assert!(kml.get_free_slot(600, 100, host_addr).is_err());
+        assert!(kml.get_free_slot(600, 100, host_addr, 0).is_err());
 
         kml.delete_slot(200, 100).unwrap();
         assert!(kml.delete_slot(150, 100).is_err());
         assert!(kml.delete_slot(700, 100).is_err());
-        assert_eq!(kml.get_free_slot(200, 100, host_addr).unwrap(), 1);
+        assert_eq!(kml.get_free_slot(200, 100, host_addr, 0).unwrap(), 1);
     }
 
     #[test]
@@ -690,6 +808,35 @@ mod test {
             .is_err());
     }
 
+    #[test]
+    #[serial]
+    fn test_dirty_log() {
+        let kvm_fds = KVMFds::new();
+        if kvm_fds.vm_fd.is_none() {
+            return;
+        }
+        KVM_FDS.store(Arc::new(kvm_fds));
+
+        let kml = KvmMemoryListener::new(34);
+        let page_size = host_page_size();
+        let ram_fr = create_ram_range(0, 4 * page_size, 0);
+        kml.handle_request(Some(&ram_fr), None, ListenerReqType::AddRegion)
+            .unwrap();
+
+        // No dirty bitmap before dirty log started.
+        assert!(kml.get_dirty_log().unwrap().is_empty());
+
+        kml.set_dirty_log(true).unwrap();
+        let dirty_bitmaps = kml.get_dirty_log().unwrap();
+        assert_eq!(dirty_bitmaps.len(), 1);
+        assert_eq!(dirty_bitmaps[0].guest_addr, 0);
+        assert_eq!(dirty_bitmaps[0].size, 4 * page_size);
+        assert_eq!(dirty_bitmaps[0].bitmap, vec![0_u64]);
+
+        kml.set_dirty_log(false).unwrap();
+        assert!(kml.get_dirty_log().unwrap().is_empty());
+    }
+
     #[test]
     #[serial]
     fn test_add_del_ioeventfd() {
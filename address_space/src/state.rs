@@ -11,11 +11,11 @@
 // See the Mulan PSL v2 for more details.
 
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::mem::size_of;
 use std::sync::Arc;
 
-use crate::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region};
+use crate::{AddressSpace, FileBackend, GuestAddress, HostMemMapping, Region, RegionType};
 use migration::errors::{ErrorKind, Result, ResultExt};
 use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
 use util::byte_code::ByteCode;
@@ -131,4 +131,83 @@ impl MigrationHook for AddressSpace {
 
         Ok(())
     }
+
+    fn start_dirty_log(&self) -> Result<()> {
+        self.set_dirty_log(true)
+            .map_err(|e| ErrorKind::SaveVmMemoryErr(e.to_string()))?;
+        Ok(())
+    }
+
+    fn stop_dirty_log(&self) -> Result<()> {
+        self.set_dirty_log(false)
+            .map_err(|e| ErrorKind::SaveVmMemoryErr(e.to_string()))?;
+        Ok(())
+    }
+
+    fn get_dirty_ranges(&self, full: bool) -> Result<Vec<(u64, u64)>> {
+        let mut ranges = Vec::new();
+        if full {
+            for region in self.root().subregions().iter() {
+                if region.region_type() != RegionType::Ram {
+                    continue;
+                }
+                if let Some(start_addr) = region.start_addr() {
+                    ranges.push((start_addr.raw_value(), region.size()));
+                }
+            }
+            return Ok(ranges);
+        }
+
+        let page_size = host_page_size();
+        let dirty_bitmaps = self
+            .get_dirty_log()
+            .map_err(|e| ErrorKind::SaveVmMemoryErr(e.to_string()))?;
+        for dirty_bitmap in dirty_bitmaps.iter() {
+            let nr_pages = dirty_bitmap.size / page_size;
+            // Merge adjacent dirty pages into one range.
+            let mut start_page: Option<u64> = None;
+            for page in 0..=nr_pages {
+                let dirty = page < nr_pages
+                    && dirty_bitmap.bitmap[(page / 64) as usize] & (1 << (page % 64)) != 0;
+                match (dirty, start_page) {
+                    (true, None) => start_page = Some(page),
+                    (false, Some(start)) => {
+                        ranges.push((
+                            dirty_bitmap.guest_addr + start * page_size,
+                            (page - start) * page_size,
+                        ));
+                        start_page = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Pages written by both the guest and the VMM are logged in more than
+        // one bitmap, merge the overlapping ranges to send them only once.
+        ranges.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for (addr, len) in ranges {
+            match merged.last_mut() {
+                Some((last_addr, last_len)) if addr <= *last_addr + *last_len => {
+                    *last_len = std::cmp::max(*last_len, addr + len - *last_addr);
+                }
+                _ => merged.push((addr, len)),
+            }
+        }
+
+        Ok(merged)
+    }
+
+    fn save_memory_range(&self, addr: u64, len: u64, writer: &mut dyn Write) -> Result<()> {
+        self.read(writer, GuestAddress(addr), len)
+            .map_err(|e| ErrorKind::SaveVmMemoryErr(e.to_string()))?;
+        Ok(())
+    }
+
+    fn load_memory_range(&self, addr: u64, len: u64, reader: &mut dyn Read) -> Result<()> {
+        self.write(reader, GuestAddress(addr), len)
+            .chain_err(|| ErrorKind::RestoreVmMemoryErr)?;
+        Ok(())
+    }
 }
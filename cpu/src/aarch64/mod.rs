@@ -161,6 +161,33 @@ impl ArmCPUState {
         Ok(())
     }
 
+    /// Read the core registers from kvm and format them as text, the saved
+    /// `ArmCPUState` is left untouched.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    pub fn dump_registers(&self, vcpu_fd: &VcpuFd) -> Result<String> {
+        let core_regs = get_core_regs(vcpu_fd)
+            .chain_err(|| format!("Failed to get core register for CPU {}", self.apic_id))?;
+
+        let mut info = String::new();
+        for (i, reg) in core_regs.regs.regs.iter().enumerate() {
+            info.push_str(&format!("X{:02}={:016x}", i, reg));
+            info.push(if i % 4 == 3 { '\n' } else { ' ' });
+        }
+        info.push_str(&format!(
+            "SP={:016x}\nPC={:016x} PSTATE={:08x}\nSP_EL1={:016x} ELR_EL1={:016x}\n",
+            core_regs.regs.sp,
+            core_regs.regs.pc,
+            core_regs.regs.pstate,
+            core_regs.sp_el1,
+            core_regs.elr_el1
+        ));
+
+        Ok(info)
+    }
+
     /// Reset register value in `Kvm` with `ArmCPUState`.
     ///
     /// # Arguments
@@ -113,7 +113,7 @@ pub use x86_64::X86CPUState as ArchCPU;
 
 use std::cell::RefCell;
 use std::sync::atomic::fence;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
 use std::thread;
 use std::time::Duration;
@@ -129,13 +129,16 @@ use errors::{ErrorKind, Result, ResultExt};
 // SIGRTMIN = 34 (GNU, in MUSL is 35) and SIGRTMAX = 64  in linux, VCPU signal
 // number should be assigned to SIGRTMIN + n, (n = 0...30).
 #[cfg(not(target_env = "musl"))]
-const VCPU_TASK_SIGNAL: i32 = 34;
+pub const VCPU_TASK_SIGNAL: i32 = 34;
 #[cfg(target_env = "musl")]
-const VCPU_TASK_SIGNAL: i32 = 35;
+pub const VCPU_TASK_SIGNAL: i32 = 35;
 #[cfg(not(target_env = "musl"))]
-const VCPU_RESET_SIGNAL: i32 = 35;
+pub const VCPU_RESET_SIGNAL: i32 = 35;
 #[cfg(target_env = "musl")]
-const VCPU_RESET_SIGNAL: i32 = 36;
+pub const VCPU_RESET_SIGNAL: i32 = 36;
+
+// Maximum time waiting for vcpu thread to exit when parking `CPU`.
+const PARK_TIMEOUT_MS: u64 = 1000;
 
 /// State for `CPU` lifecycle.
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -152,6 +155,74 @@ pub enum CpuLifecycleState {
     Stopping = 4,
     /// `CPU` structure destroyed, will be dropped soon.
     Stopped = 5,
+    /// `CPU` thread exited because of hot-unplug, kvm vcpu is kept for reuse.
+    Parked = 6,
+}
+
+/// Reason of `CPU` exits from kvm, counted for metrics.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CpuExitReason {
+    #[cfg(target_arch = "x86_64")]
+    IoIn,
+    #[cfg(target_arch = "x86_64")]
+    IoOut,
+    MmioRead,
+    MmioWrite,
+    #[cfg(target_arch = "x86_64")]
+    Hlt,
+    #[cfg(target_arch = "x86_64")]
+    Shutdown,
+    #[cfg(target_arch = "aarch64")]
+    SystemEvent,
+    FailEntry,
+    InternalError,
+    /// `KVM_RUN` returned without exit because of a signal.
+    Interrupted,
+    Other,
+}
+
+const CPU_EXIT_REASON_NUM: usize = CpuExitReason::Other as usize + 1;
+
+impl CpuExitReason {
+    pub const ALL: [CpuExitReason; CPU_EXIT_REASON_NUM] = [
+        #[cfg(target_arch = "x86_64")]
+        CpuExitReason::IoIn,
+        #[cfg(target_arch = "x86_64")]
+        CpuExitReason::IoOut,
+        CpuExitReason::MmioRead,
+        CpuExitReason::MmioWrite,
+        #[cfg(target_arch = "x86_64")]
+        CpuExitReason::Hlt,
+        #[cfg(target_arch = "x86_64")]
+        CpuExitReason::Shutdown,
+        #[cfg(target_arch = "aarch64")]
+        CpuExitReason::SystemEvent,
+        CpuExitReason::FailEntry,
+        CpuExitReason::InternalError,
+        CpuExitReason::Interrupted,
+        CpuExitReason::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            CpuExitReason::IoIn => "io_in",
+            #[cfg(target_arch = "x86_64")]
+            CpuExitReason::IoOut => "io_out",
+            CpuExitReason::MmioRead => "mmio_read",
+            CpuExitReason::MmioWrite => "mmio_write",
+            #[cfg(target_arch = "x86_64")]
+            CpuExitReason::Hlt => "hlt",
+            #[cfg(target_arch = "x86_64")]
+            CpuExitReason::Shutdown => "shutdown",
+            #[cfg(target_arch = "aarch64")]
+            CpuExitReason::SystemEvent => "system_event",
+            CpuExitReason::FailEntry => "fail_entry",
+            CpuExitReason::InternalError => "internal_error",
+            CpuExitReason::Interrupted => "interrupted",
+            CpuExitReason::Other => "other",
+        }
+    }
 }
 
 /// Trait to handle `CPU` lifetime.
@@ -183,6 +254,18 @@ pub trait CPUInterface {
     /// Make `CPU` lifecycle to `Stopping`, then `Stopped`.
     fn destroy(&self) -> Result<()>;
 
+    /// Stop `CPU` thread and make `CPU` lifecycle to `Parked`.
+    ///
+    /// # Notes
+    ///
+    /// A kvm vcpu can't be destroyed once created, so the hot-unplugged
+    /// `CPU` is parked and its kvm vcpu will be reused by next hotplug.
+    fn park(&self) -> Result<()>;
+
+    /// Make `CPU` lifecycle from `Parked` to `Created`, so that it can be
+    /// realized and started again.
+    fn unpark(&self) -> Result<()>;
+
     /// Reset registers value for `CPU`.
     fn reset(&self) -> Result<()>;
 
@@ -217,6 +300,8 @@ pub struct CPU {
     caps: CPUCaps,
     /// The state backup of architecture CPU right before boot.
     boot_state: Arc<Mutex<ArchCPU>>,
+    /// Count of kvm exits, indexed by `CpuExitReason`.
+    exit_counts: [AtomicU64; CPU_EXIT_REASON_NUM],
 }
 
 impl CPU {
@@ -244,6 +329,7 @@ impl CPU {
             vm: Arc::downgrade(&vm),
             caps: CPUCaps::init_capabilities(),
             boot_state: Arc::new(Mutex::new(ArchCPU::default())),
+            exit_counts: Default::default(),
         }
     }
 
@@ -287,6 +373,45 @@ impl CPU {
     fn set_tid(&self) {
         *self.tid.lock().unwrap() = Some(util::unix::gettid());
     }
+
+    /// Call `f` with the kvm vcpu kicked out of guest mode, so that its registers
+    /// can be accessed from other threads. A running `CPU` is resumed afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - The function to access the kvm vcpu.
+    pub fn with_vcpu_paused<T, F: FnOnce(&VcpuFd) -> T>(&self, f: F) -> Result<T> {
+        let (cpu_state, _) = &*self.state;
+        let running = *cpu_state.lock().unwrap() == CpuLifecycleState::Running;
+        if running {
+            self.pause()?;
+        }
+        let ret = f(&self.fd);
+        if running {
+            self.resume()?;
+        }
+        Ok(ret)
+    }
+
+    /// Get the registers of this `CPU` as human readable text.
+    pub fn dump_registers(&self) -> Result<String> {
+        self.with_vcpu_paused(|fd| self.arch_cpu.lock().unwrap().dump_registers(fd))?
+    }
+
+    /// Get the count of kvm exits of this `CPU` for each reason.
+    pub fn exit_counts(&self) -> Vec<(CpuExitReason, u64)> {
+        CpuExitReason::ALL
+            .iter()
+            .map(|reason| {
+                let count = self.exit_counts[*reason as usize].load(Ordering::Relaxed);
+                (*reason, count)
+            })
+            .collect()
+    }
+
+    fn count_exit(&self, reason: CpuExitReason) {
+        self.exit_counts[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 impl CPUInterface for CPU {
@@ -420,6 +545,65 @@ impl CPUInterface for CPU {
         }
     }
 
+    fn park(&self) -> Result<()> {
+        let (cpu_state_locked, cvar) = &*self.state;
+        let mut cpu_state = cpu_state_locked.lock().unwrap();
+        match *cpu_state {
+            CpuLifecycleState::Running | CpuLifecycleState::Paused => {
+                *cpu_state = CpuLifecycleState::Stopping;
+            }
+            CpuLifecycleState::Created | CpuLifecycleState::Stopped => {
+                *cpu_state = CpuLifecycleState::Parked;
+                return Ok(());
+            }
+            CpuLifecycleState::Parked => return Ok(()),
+            state => {
+                return Err(ErrorKind::DestroyVcpu(format!(
+                    "VCPU can't be parked in {:?} state",
+                    state
+                ))
+                .into())
+            }
+        }
+        drop(cpu_state);
+
+        // Wake up the paused vcpu thread, and kick the running one out of kvm.
+        cvar.notify_all();
+        self.kick()?;
+        let cpu_state = cpu_state_locked.lock().unwrap();
+        let (mut cpu_state, _) = cvar
+            .wait_timeout_while(cpu_state, Duration::from_millis(PARK_TIMEOUT_MS), |state| {
+                *state != CpuLifecycleState::Stopped
+            })
+            .unwrap();
+        if *cpu_state != CpuLifecycleState::Stopped {
+            return Err(
+                ErrorKind::DestroyVcpu(format!("VCPU still in {:?} state", *cpu_state)).into(),
+            );
+        }
+        *cpu_state = CpuLifecycleState::Parked;
+        drop(cpu_state);
+
+        // The vcpu thread has exited, join it.
+        self.set_task(None);
+        *self.tid.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn unpark(&self) -> Result<()> {
+        let (cpu_state, _) = &*self.state;
+        let mut cpu_state = cpu_state.lock().unwrap();
+        if *cpu_state != CpuLifecycleState::Parked {
+            return Err(ErrorKind::StartVcpu(format!(
+                "VCPU{} is not parked, current state {:?}",
+                self.id, *cpu_state
+            ))
+            .into());
+        }
+        *cpu_state = CpuLifecycleState::Created;
+        Ok(())
+    }
+
     fn guest_shutdown(&self) -> Result<()> {
         let (cpu_state, _) = &*self.state;
         *cpu_state.lock().unwrap() = CpuLifecycleState::Stopped;
@@ -467,25 +651,31 @@ impl CPUInterface for CPU {
             Ok(run) => match run {
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoIn(addr, data) => {
+                    self.count_exit(CpuExitReason::IoIn);
                     vm.lock().unwrap().pio_in(u64::from(addr), data);
                 }
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoOut(addr, data) => {
+                    self.count_exit(CpuExitReason::IoOut);
                     vm.lock().unwrap().pio_out(u64::from(addr), data);
                 }
                 VcpuExit::MmioRead(addr, data) => {
+                    self.count_exit(CpuExitReason::MmioRead);
                     vm.lock().unwrap().mmio_read(addr, data);
                 }
                 VcpuExit::MmioWrite(addr, data) => {
+                    self.count_exit(CpuExitReason::MmioWrite);
                     vm.lock().unwrap().mmio_write(addr, data);
                 }
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::Hlt => {
+                    self.count_exit(CpuExitReason::Hlt);
                     info!("Vcpu{} received KVM_EXIT_HLT signal", self.id());
                     return Err(ErrorKind::VcpuHltEvent(self.id()).into());
                 }
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::Shutdown => {
+                    self.count_exit(CpuExitReason::Shutdown);
                     info!("Vcpu{} received an KVM_EXIT_SHUTDOWN signal", self.id());
                     self.guest_shutdown()?;
 
@@ -493,6 +683,7 @@ impl CPUInterface for CPU {
                 }
                 #[cfg(target_arch = "aarch64")]
                 VcpuExit::SystemEvent(event, flags) => {
+                    self.count_exit(CpuExitReason::SystemEvent);
                     if event == kvm_bindings::KVM_SYSTEM_EVENT_SHUTDOWN {
                         info!(
                             "Vcpu{} received an KVM_SYSTEM_EVENT_SHUTDOWN signal",
@@ -520,21 +711,27 @@ impl CPUInterface for CPU {
                     return Ok(false);
                 }
                 VcpuExit::FailEntry => {
+                    self.count_exit(CpuExitReason::FailEntry);
                     info!("Vcpu{} received KVM_EXIT_FAIL_ENTRY signal", self.id());
                     return Ok(false);
                 }
                 VcpuExit::InternalError => {
+                    self.count_exit(CpuExitReason::InternalError);
                     info!("Vcpu{} received KVM_EXIT_INTERNAL_ERROR signal", self.id());
                     return Ok(false);
                 }
                 r => {
+                    self.count_exit(CpuExitReason::Other);
                     return Err(ErrorKind::VcpuExitReason(self.id(), format!("{:?}", r)).into());
                 }
             },
             Err(ref e) => {
                 match e.errno() {
-                    libc::EAGAIN => {}
+                    libc::EAGAIN => {
+                        self.count_exit(CpuExitReason::Interrupted);
+                    }
                     libc::EINTR => {
+                        self.count_exit(CpuExitReason::Interrupted);
                         self.fd.set_kvm_immediate_exit(0);
                     }
                     _ => {
@@ -636,7 +833,9 @@ impl CPUThreadWorker {
                 CpuLifecycleState::Running => {
                     return Ok(true);
                 }
-                CpuLifecycleState::Stopping | CpuLifecycleState::Stopped => {
+                CpuLifecycleState::Stopping
+                | CpuLifecycleState::Stopped
+                | CpuLifecycleState::Parked => {
                     info!("Vcpu{} shutdown", self.thread_cpu.id);
                     return Ok(false);
                 }
@@ -706,7 +905,7 @@ pub struct CpuTopology {
     pub threads: u8,
     /// Number of vcpus in VM.
     pub nrcpus: u8,
-    /// Number of online vcpus in VM.
+    /// Maximum number of vcpus in VM, including the hotpluggable ones.
     pub max_cpus: u8,
     /// Online mask number of all vcpus.
     pub online_mask: Arc<Mutex<Vec<u8>>>,
@@ -718,14 +917,16 @@ impl CpuTopology {
     /// # Arguments
     ///
     /// * `nr_cpus`: Number of vcpus.
-    pub fn new(nr_cpus: u8) -> Self {
-        let mask: Vec<u8> = vec![1; nr_cpus as usize];
+    /// * `max_cpus`: Maximum number of vcpus.
+    pub fn new(nr_cpus: u8, max_cpus: u8) -> Self {
+        let mut mask: Vec<u8> = vec![0; max_cpus as usize];
+        mask[..nr_cpus as usize].fill(1);
         Self {
-            sockets: nr_cpus,
+            sockets: max_cpus,
             cores: 1,
             threads: 1,
             nrcpus: nr_cpus,
-            max_cpus: nr_cpus,
+            max_cpus,
             online_mask: Arc::new(Mutex::new(mask)),
         }
     }
@@ -745,6 +946,17 @@ impl CpuTopology {
         mask[vcpu_id]
     }
 
+    /// Set online mask for a cpu when it is hot-plugged or hot-unplugged.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_id` - ID of vcpu.
+    /// * `online` - Vcpu is online or not.
+    pub fn set_mask(&self, vcpu_id: usize, online: bool) {
+        let mut mask = self.online_mask.lock().unwrap();
+        mask[vcpu_id] = online as u8;
+    }
+
     /// Get single cpu topology for vcpu, return this vcpu's `socket-id`,
     /// `core-id` and `thread-id`.
     ///
@@ -922,6 +1134,68 @@ mod tests {
         drop(cpu_state);
     }
 
+    #[test]
+    #[serial]
+    fn test_cpu_park() {
+        let kvm_fds = KVMFds::new();
+        if kvm_fds.vm_fd.is_none() {
+            return;
+        }
+        KVM_FDS.store(Arc::new(kvm_fds));
+
+        let vm = Arc::new(Mutex::new(TestVm::new()));
+        let cpu = Arc::new(CPU::new(
+            Arc::new(
+                KVM_FDS
+                    .load()
+                    .vm_fd
+                    .as_ref()
+                    .unwrap()
+                    .create_vcpu(0)
+                    .unwrap(),
+            ),
+            0,
+            Arc::new(Mutex::new(ArchCPU::default())),
+            vm,
+        ));
+
+        // Parked cpu can't be started before unparked.
+        assert!(cpu.unpark().is_err());
+
+        // Test cpu life cycle as:
+        // Created -> Running -> Parked -> Created -> Paused -> Parked
+        let barrier = Arc::new(Barrier::new(2));
+        CPU::start(cpu.clone(), barrier.clone(), false).unwrap();
+        barrier.wait();
+        assert!(cpu.park().is_ok());
+        assert_eq!(*cpu.state.0.lock().unwrap(), CpuLifecycleState::Parked);
+        assert!(cpu.task.lock().unwrap().is_none());
+        assert_eq!(cpu.tid(), 0);
+
+        assert!(cpu.unpark().is_ok());
+        assert_eq!(*cpu.state.0.lock().unwrap(), CpuLifecycleState::Created);
+        CPU::start(cpu.clone(), barrier.clone(), true).unwrap();
+        barrier.wait();
+        assert_eq!(*cpu.state.0.lock().unwrap(), CpuLifecycleState::Paused);
+        assert!(cpu.park().is_ok());
+        assert_eq!(*cpu.state.0.lock().unwrap(), CpuLifecycleState::Parked);
+    }
+
+    #[test]
+    fn test_cpu_topo_mask() {
+        let cpu_topo = CpuTopology::new(2, 4);
+        assert_eq!(cpu_topo.sockets, 4);
+        assert_eq!(cpu_topo.nrcpus, 2);
+        assert_eq!(cpu_topo.max_cpus, 4);
+        assert_eq!(cpu_topo.get_mask(1), 1);
+        assert_eq!(cpu_topo.get_mask(2), 0);
+
+        cpu_topo.set_mask(2, true);
+        assert_eq!(cpu_topo.get_mask(2), 1);
+        cpu_topo.set_mask(1, false);
+        assert_eq!(cpu_topo.get_mask(1), 0);
+    }
+
     #[test]
     fn test_cpu_get_topu() {
         let test_nr_cpus: u8 = 16;
@@ -153,6 +153,91 @@ impl X86CPUState {
         Ok(())
     }
 
+    /// Read the general purpose and segment registers from kvm and format them
+    /// as text, the saved `X86CPUState` is left untouched.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    pub fn dump_registers(&self, vcpu_fd: &VcpuFd) -> Result<String> {
+        let regs = vcpu_fd
+            .get_regs()
+            .chain_err(|| format!("Failed to get regs for CPU {}", self.apic_id))?;
+        let sregs = vcpu_fd
+            .get_sregs()
+            .chain_err(|| format!("Failed to get sregs for CPU {}", self.apic_id))?;
+
+        let mut info = format!(
+            "RAX={:016x} RBX={:016x} RCX={:016x} RDX={:016x}\n\
+             RSI={:016x} RDI={:016x} RBP={:016x} RSP={:016x}\n\
+             R8 ={:016x} R9 ={:016x} R10={:016x} R11={:016x}\n\
+             R12={:016x} R13={:016x} R14={:016x} R15={:016x}\n\
+             RIP={:016x} RFL={:08x}\n",
+            regs.rax,
+            regs.rbx,
+            regs.rcx,
+            regs.rdx,
+            regs.rsi,
+            regs.rdi,
+            regs.rbp,
+            regs.rsp,
+            regs.r8,
+            regs.r9,
+            regs.r10,
+            regs.r11,
+            regs.r12,
+            regs.r13,
+            regs.r14,
+            regs.r15,
+            regs.rip,
+            regs.rflags
+        );
+        let segments = [
+            ("ES ", &sregs.es),
+            ("CS ", &sregs.cs),
+            ("SS ", &sregs.ss),
+            ("DS ", &sregs.ds),
+            ("FS ", &sregs.fs),
+            ("GS ", &sregs.gs),
+            ("LDT", &sregs.ldt),
+            ("TR ", &sregs.tr),
+        ];
+        for (name, seg) in segments.iter() {
+            info.push_str(&format!(
+                "{}={:04x} {:016x} {:08x} type={:x} dpl={} present={} db={} l={} g={}\n",
+                name,
+                seg.selector,
+                seg.base,
+                seg.limit,
+                seg.type_,
+                seg.dpl,
+                seg.present,
+                seg.db,
+                seg.l,
+                seg.g
+            ));
+        }
+        info.push_str(&format!(
+            "GDT=     {:016x} {:08x}\n\
+             IDT=     {:016x} {:08x}\n\
+             CR0={:08x} CR2={:016x} CR3={:016x} CR4={:08x}\n\
+             CR8={:016x} EFER={:016x} APIC_BASE={:016x}\n",
+            sregs.gdt.base,
+            sregs.gdt.limit,
+            sregs.idt.base,
+            sregs.idt.limit,
+            sregs.cr0,
+            sregs.cr2,
+            sregs.cr3,
+            sregs.cr4,
+            sregs.cr8,
+            sregs.efer,
+            sregs.apic_base
+        ));
+
+        Ok(info)
+    }
+
     /// Reset register value with `X86CPUState`.
     ///
     /// # Arguments
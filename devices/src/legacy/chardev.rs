@@ -10,19 +10,28 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::fs::{read_link, File, OpenOptions};
-use std::io::{Stdin, Stdout};
-use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{read_link, rename, File, OpenOptions};
+use std::io::{Sink, Stdin, Stdout, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 
+use error_chain::ChainedError;
 use libc::{cfmakeraw, tcgetattr, tcsetattr, termios};
+use machine_manager::event_loop::EventLoop;
 use machine_manager::machine::{PathInfo, PTY_PATH};
+use machine_manager::qmp::qmp_schema::DataFormat;
 use machine_manager::{
-    config::{ChardevConfig, ChardevType},
+    config::{ChardevConfig, ChardevLogConfig, ChardevType},
     temp_cleaner::TempCleaner,
 };
+use once_cell::sync::Lazy;
+use util::base64;
 use util::loop_context::{EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation};
 use util::set_termi_raw_mode;
 use util::unix::limit_permission;
@@ -30,6 +39,9 @@ use vmm_sys_util::epoll::EventSet;
 
 use super::errors::{Result, ResultExt};
 
+/// Timeout of connecting to the server of tcp-type chardev.
+const TCP_CONNECT_TIMEOUT_MS: u64 = 1000;
+
 /// Provide the trait that helps handle the input data.
 pub trait InputReceiver: Send {
     fn input_handle(&mut self, buffer: &[u8]);
@@ -37,14 +49,29 @@ pub trait InputReceiver: Send {
     fn get_remain_space_size(&mut self) -> usize;
 }
 
+/// Listener of server-mode socket chardev.
+pub enum SocketListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl AsRawFd for SocketListener {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            SocketListener::Unix(listener) => listener.as_raw_fd(),
+            SocketListener::Tcp(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
 /// Character device structure.
 pub struct Chardev {
     /// Id of chardev.
     pub id: String,
     /// Type of backend device.
     pub backend: ChardevType,
-    /// UnixListener for socket-type chardev.
-    pub listener: Option<UnixListener>,
+    /// Listener for server-mode socket chardev.
+    pub listener: Option<SocketListener>,
     /// Chardev input.
     pub input: Option<Arc<Mutex<dyn CommunicatInInterface>>>,
     /// Chardev output.
@@ -55,6 +82,21 @@ pub struct Chardev {
     receive: Option<Arc<dyn Fn(&[u8]) + Send + Sync>>,
     /// Return the remain space size of receiver buffer.
     get_remain_space_size: Option<Arc<dyn Fn() -> usize + Send + Sync>>,
+    /// Whether the events of chardev are registered to the event loop.
+    registered: bool,
+    /// Whether the reconnection of client-mode socket chardev has been scheduled.
+    reconnect_pending: bool,
+    /// Addresses of the server of client-mode tcp chardev, they are resolved at realize
+    /// as the resolver can't be used after seccomp rules registered.
+    addrs: Vec<SocketAddr>,
+    /// Index of the address to connect to in the next reconnection.
+    next_addr: usize,
+    /// Stream of client-mode tcp chardev whose connection is in progress.
+    connecting: Option<TcpStream>,
+    /// Config of the log file.
+    logfile: Option<ChardevLogConfig>,
+    /// Log file recording the output.
+    log: Option<Arc<Mutex<ChardevLog>>>,
 }
 
 impl Chardev {
@@ -68,15 +110,30 @@ impl Chardev {
             stream_fd: None,
             receive: None,
             get_remain_space_size: None,
+            registered: false,
+            reconnect_pending: false,
+            addrs: Vec::new(),
+            next_addr: 0,
+            connecting: None,
+            logfile: chardev_cfg.logfile,
+            log: None,
         }
     }
 
     pub fn realize(&mut self) -> Result<()> {
+        if let Some(logfile) = &self.logfile {
+            let log = ChardevLog::new(logfile).chain_err(|| {
+                format!("Failed to open log file of chardev, path:{}", logfile.path)
+            })?;
+            self.log = Some(Arc::new(Mutex::new(log)));
+            self.set_output(None);
+        }
+
         match &self.backend {
             ChardevType::Stdio => {
                 set_termi_raw_mode().chain_err(|| "Failed to set terminal to raw mode")?;
                 self.input = Some(Arc::new(Mutex::new(std::io::stdin())));
-                self.output = Some(Arc::new(Mutex::new(std::io::stdout())));
+                self.set_output(Some(Arc::new(Mutex::new(std::io::stdout()))));
             }
             ChardevType::Pty => {
                 let (master, path) =
@@ -90,12 +147,17 @@ impl Chardev {
                 // Safe because `master_arc` is the only one owner for the file descriptor.
                 let master_arc = unsafe { Arc::new(Mutex::new(File::from_raw_fd(master))) };
                 self.input = Some(master_arc.clone());
-                self.output = Some(master_arc);
+                self.set_output(Some(master_arc));
             }
-            ChardevType::Socket(path) => {
+            ChardevType::Socket {
+                path,
+                server: true,
+                nowait,
+                ..
+            } => {
                 let sock = UnixListener::bind(path.clone())
                     .chain_err(|| format!("Failed to bind socket for chardev, path:{}", path))?;
-                self.listener = Some(sock);
+                self.listener = Some(SocketListener::Unix(sock));
                 // add file to temporary pool, so it could be cleaned when vm exit.
                 TempCleaner::add_path(path.clone());
                 limit_permission(path).chain_err(|| {
@@ -104,6 +166,53 @@ impl Chardev {
                         path
                     )
                 })?;
+                if !*nowait {
+                    info!("Chardev {} is waiting for connection on {}", self.id, path);
+                    self.accept_client()?;
+                }
+            }
+            ChardevType::TcpSocket {
+                host,
+                port,
+                server: true,
+                nowait,
+                ..
+            } => {
+                let sock = TcpListener::bind((host.as_str(), *port)).chain_err(|| {
+                    format!("Failed to bind socket for chardev, addr:{}:{}", host, port)
+                })?;
+                self.listener = Some(SocketListener::Tcp(sock));
+                if !*nowait {
+                    info!(
+                        "Chardev {} is waiting for connection on {}:{}",
+                        self.id, host, port
+                    );
+                    self.accept_client()?;
+                }
+            }
+            ChardevType::Socket { reconnect, .. } | ChardevType::TcpSocket { reconnect, .. } => {
+                let reconnect = *reconnect;
+                if let ChardevType::TcpSocket { host, port, .. } = &self.backend {
+                    self.addrs = (host.as_str(), *port)
+                        .to_socket_addrs()
+                        .chain_err(|| {
+                            format!(
+                                "Failed to resolve address for chardev, addr:{}:{}",
+                                host, port
+                            )
+                        })?
+                        .collect();
+                }
+                if let Err(e) = self.connect() {
+                    if reconnect == 0 {
+                        return Err(e);
+                    }
+                    warn!(
+                        "Chardev {} failed to connect and will retry later: {}",
+                        self.id,
+                        e.display_chain()
+                    );
+                }
             }
             ChardevType::File(path) => {
                 let file = Arc::new(Mutex::new(
@@ -113,7 +222,18 @@ impl Chardev {
                         .create(true)
                         .open(path)?,
                 ));
-                self.output = Some(file);
+                self.set_output(Some(file));
+            }
+            ChardevType::Ringbuf { size } => {
+                let ringbuf = Arc::new(Mutex::new(RingBuffer::new(*size)));
+                RING_BUFFERS
+                    .lock()
+                    .unwrap()
+                    .insert(self.id.clone(), Arc::downgrade(&ringbuf));
+                self.set_output(Some(ringbuf));
+            }
+            ChardevType::Null => {
+                self.set_output(Some(Arc::new(Mutex::new(std::io::sink()))));
             }
         };
         Ok(())
@@ -129,6 +249,325 @@ impl Chardev {
             cloned_dev.lock().unwrap().get_remain_space_size()
         }));
     }
+
+    /// Get the notifiers which remove the events of chardev from the event loop.
+    pub fn remove_notifiers(&mut self) -> Vec<EventNotifier> {
+        if !self.registered {
+            return Vec::new();
+        }
+        self.registered = false;
+
+        let mut fds = Vec::new();
+        match &self.backend {
+            ChardevType::Stdio | ChardevType::Pty => {
+                if let Some(input) = &self.input {
+                    fds.push(input.lock().unwrap().as_raw_fd());
+                }
+            }
+            ChardevType::Socket { .. } | ChardevType::TcpSocket { .. } => {
+                if let Some(listener) = &self.listener {
+                    fds.push(listener.as_raw_fd());
+                }
+                if let Some(stream_fd) = self.stream_fd {
+                    fds.push(stream_fd);
+                }
+                if let Some(stream) = &self.connecting {
+                    fds.push(stream.as_raw_fd());
+                }
+            }
+            _ => (),
+        }
+        fds.into_iter()
+            .map(|fd| {
+                EventNotifier::new(
+                    NotifierOperation::Delete,
+                    fd,
+                    None,
+                    EventSet::IN,
+                    Vec::new(),
+                )
+            })
+            .collect()
+    }
+
+    /// Seconds to wait before reconnecting, zero for chardev which never reconnects.
+    fn reconnect_interval(&self) -> u64 {
+        match &self.backend {
+            ChardevType::Socket {
+                server: false,
+                reconnect,
+                ..
+            }
+            | ChardevType::TcpSocket {
+                server: false,
+                reconnect,
+                ..
+            } => *reconnect,
+            _ => 0,
+        }
+    }
+
+    fn set_stream<T: CommunicatInInterface + CommunicatOutInterface + 'static>(
+        &mut self,
+        stream: T,
+    ) {
+        self.stream_fd = Some(stream.as_raw_fd());
+        let stream_arc = Arc::new(Mutex::new(stream));
+        self.input = Some(stream_arc.clone());
+        self.set_output(Some(stream_arc));
+    }
+
+    fn clear_stream(&mut self) {
+        self.input = None;
+        self.set_output(None);
+        self.stream_fd = None;
+    }
+
+    /// Set the output of backend, which is wrapped to record the output in the log file
+    /// if the log file is set.
+    fn set_output(&mut self, output: Option<Arc<Mutex<dyn CommunicatOutInterface>>>) {
+        self.output = match &self.log {
+            Some(log) => Some(Arc::new(Mutex::new(LogOutput {
+                output,
+                log: log.clone(),
+            }))),
+            None => output,
+        };
+    }
+
+    /// Accept a client of server-mode socket chardev. Only one client can be connected,
+    /// the others are disconnected at once.
+    ///
+    /// Return true if the client is accepted.
+    fn accept_client(&mut self) -> Result<bool> {
+        let connected = self.stream_fd.is_some();
+        match &self.listener {
+            Some(SocketListener::Unix(listener)) => {
+                let (stream, _) = listener.accept().chain_err(|| "Failed to accept client")?;
+                if !connected {
+                    self.set_stream(stream);
+                }
+            }
+            Some(SocketListener::Tcp(listener)) => {
+                let (stream, addr) = listener.accept().chain_err(|| "Failed to accept client")?;
+                if !connected {
+                    info!("Chardev {} is connected by {}", self.id, addr);
+                    self.set_stream(stream);
+                }
+            }
+            None => bail!("Chardev {} is not in server mode", self.id),
+        }
+        if connected {
+            warn!(
+                "Chardev {} rejects the new client, only one client is supported",
+                self.id
+            );
+        }
+        Ok(!connected)
+    }
+
+    /// Connect to the server of client-mode socket chardev. It blocks until the
+    /// connection of tcp chardev completes or times out, so it's only used at realize.
+    fn connect(&mut self) -> Result<()> {
+        match self.backend.clone() {
+            ChardevType::Socket { path, .. } => {
+                let stream = UnixStream::connect(&path)
+                    .chain_err(|| format!("Failed to connect socket for chardev, path:{}", path))?;
+                self.set_stream(stream);
+            }
+            ChardevType::TcpSocket { host, port, .. } => {
+                let timeout = Duration::from_millis(TCP_CONNECT_TIMEOUT_MS);
+                let stream = self
+                    .addrs
+                    .iter()
+                    .filter_map(|addr| TcpStream::connect_timeout(addr, timeout).ok())
+                    .next();
+                if let Some(stream) = stream {
+                    self.set_stream(stream);
+                } else {
+                    bail!(
+                        "Failed to connect socket for chardev, addr:{}:{}",
+                        host,
+                        port
+                    );
+                }
+            }
+            _ => bail!("Chardev {} is not a socket", self.id),
+        }
+        Ok(())
+    }
+
+    /// Start connecting to the server of client-mode tcp chardev without blocking,
+    /// the resolved addresses are tried in turn by each reconnection.
+    ///
+    /// Return the fd of the socket, which becomes writable when the connection completes.
+    fn start_connect(&mut self) -> Result<RawFd> {
+        if self.addrs.is_empty() {
+            bail!("Chardev {} has no address to connect to", self.id);
+        }
+        let addr = self.addrs[self.next_addr % self.addrs.len()];
+        self.next_addr = self.next_addr.wrapping_add(1);
+        let stream = connect_nonblocking(&addr)
+            .chain_err(|| format!("Failed to connect socket for chardev, addr:{}", addr))?;
+        let fd = stream.as_raw_fd();
+        self.connecting = Some(stream);
+        Ok(fd)
+    }
+
+    /// Complete the connection started by `start_connect`.
+    fn finish_connect(&mut self) -> Result<()> {
+        let stream = match self.connecting.take() {
+            Some(stream) => stream,
+            None => bail!("Chardev {} is not connecting", self.id),
+        };
+        if let Some(e) = stream
+            .take_error()
+            .chain_err(|| "Failed to get the result of connection")?
+        {
+            bail!("Failed to connect socket for chardev {}: {}", self.id, e);
+        }
+        stream
+            .set_nonblocking(false)
+            .chain_err(|| "Failed to set chardev stream to blocking mode")?;
+        self.set_stream(stream);
+        Ok(())
+    }
+}
+
+/// Create a tcp stream and connect it to `addr` in nonblocking mode, the connection
+/// may be still in progress when it returns.
+fn connect_nonblocking(addr: &SocketAddr) -> std::io::Result<TcpStream> {
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    // Safe because this only creates a new socket, and the result is checked.
+    let fd = unsafe {
+        libc::socket(
+            family,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // Safe because the fd is just created and owned by nobody else.
+    let stream = unsafe { TcpStream::from_raw_fd(fd) };
+
+    let ret = match addr {
+        SocketAddr::V4(addr) => {
+            let sockaddr = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: addr.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from_ne_bytes(addr.ip().octets()),
+                },
+                sin_zero: [0; 8],
+            };
+            // Safe because the address is valid with the given length.
+            unsafe {
+                libc::connect(
+                    fd,
+                    &sockaddr as *const _ as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        SocketAddr::V6(addr) => {
+            let sockaddr = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                },
+                sin6_scope_id: addr.scope_id(),
+            };
+            // Safe because the address is valid with the given length.
+            unsafe {
+                libc::connect(
+                    fd,
+                    &sockaddr as *const _ as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(err);
+        }
+    }
+    Ok(stream)
+}
+
+/// Reconnect the client-mode socket chardev after the reconnect interval, and
+/// retry until the connection succeeds.
+fn schedule_reconnect(chardev: Arc<Mutex<Chardev>>) {
+    let mut locked_chardev = chardev.lock().unwrap();
+    let interval = locked_chardev.reconnect_interval();
+    if interval == 0 || locked_chardev.reconnect_pending {
+        return;
+    }
+    locked_chardev.reconnect_pending = true;
+    drop(locked_chardev);
+
+    let cloned_chardev = chardev.clone();
+    let reconnect = Box::new(move || {
+        let mut locked_chardev = cloned_chardev.lock().unwrap();
+        locked_chardev.reconnect_pending = false;
+        if locked_chardev.stream_fd.is_some() || locked_chardev.connecting.is_some() {
+            return;
+        }
+        if let ChardevType::TcpSocket { .. } = locked_chardev.backend {
+            // The connection is waited in the event loop, it's started when the chardev
+            // is registered next time.
+            if !locked_chardev.registered {
+                return;
+            }
+            match locked_chardev.start_connect() {
+                Ok(fd) => {
+                    drop(locked_chardev);
+                    if let Err(e) = EventLoop::update_event(
+                        vec![get_connect_notifier(cloned_chardev.clone(), fd)],
+                        None,
+                    ) {
+                        error!("Failed to register events of chardev connection: {}", e);
+                    }
+                }
+                Err(e) => {
+                    debug!("Chardev {} failed to reconnect: {}", locked_chardev.id, e);
+                    drop(locked_chardev);
+                    schedule_reconnect(cloned_chardev.clone());
+                }
+            }
+            return;
+        }
+        if let Err(e) = locked_chardev.connect() {
+            debug!("Chardev {} failed to reconnect: {}", locked_chardev.id, e);
+            drop(locked_chardev);
+            schedule_reconnect(cloned_chardev.clone());
+            return;
+        }
+        info!("Chardev {} is reconnected", locked_chardev.id);
+        // The events are registered when the chardev is registered next time.
+        if !locked_chardev.registered {
+            return;
+        }
+        let stream_fd = locked_chardev.stream_fd.unwrap();
+        drop(locked_chardev);
+        if let Err(e) = EventLoop::update_event(
+            vec![get_stream_notifier(cloned_chardev.clone(), stream_fd)],
+            None,
+        ) {
+            error!("Failed to register events of chardev stream: {}", e);
+        }
+    });
+    if let Some(ctx) = EventLoop::get_ctx(None) {
+        ctx.delay_call(reconnect, interval * 1_000_000_000);
+    }
 }
 
 fn set_pty_raw_mode() -> Result<(i32, PathBuf)> {
@@ -180,87 +619,124 @@ fn set_pty_raw_mode() -> Result<(i32, PathBuf)> {
     Ok((master, path))
 }
 
+/// Read the input data of chardev and pass it to the receiver.
+fn read_input(chardev: &Chardev) {
+    let buff_size = chardev.get_remain_space_size.as_ref().unwrap()();
+    let mut buffer = vec![0_u8; buff_size];
+    if let Some(input) = chardev.input.clone() {
+        if let Ok(index) = input.lock().unwrap().chr_read_raw(&mut buffer) {
+            chardev.receive.as_ref().unwrap()(&mut buffer[..index]);
+        } else {
+            error!("Failed to read input data");
+        }
+    } else {
+        error!("Failed to get chardev input fd");
+    }
+}
+
+#[allow(clippy::arc_with_non_send_sync)]
+fn get_stream_notifier(chardev: Arc<Mutex<Chardev>>, stream_fd: RawFd) -> EventNotifier {
+    let handler: Box<NotifierCallback> = Box::new(move |event, _| {
+        let mut locked_chardev = chardev.lock().unwrap();
+        if event & EventSet::IN == EventSet::IN {
+            read_input(&locked_chardev);
+        }
+        if event & (EventSet::HANG_UP | EventSet::READ_HANG_UP) != EventSet::empty() {
+            info!("Chardev {} is disconnected", locked_chardev.id);
+            locked_chardev.clear_stream();
+            drop(locked_chardev);
+            schedule_reconnect(chardev.clone());
+            Some(vec![EventNotifier::new(
+                NotifierOperation::Delete,
+                stream_fd,
+                None,
+                EventSet::IN | EventSet::HANG_UP | EventSet::READ_HANG_UP,
+                Vec::new(),
+            )])
+        } else {
+            None
+        }
+    });
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        stream_fd,
+        None,
+        EventSet::IN | EventSet::HANG_UP | EventSet::READ_HANG_UP,
+        vec![Arc::new(Mutex::new(handler))],
+    )
+}
+
+/// Get the notifier which completes the connection of tcp chardev when the socket
+/// becomes writable.
+#[allow(clippy::arc_with_non_send_sync)]
+fn get_connect_notifier(chardev: Arc<Mutex<Chardev>>, fd: RawFd) -> EventNotifier {
+    let handler: Box<NotifierCallback> = Box::new(move |_, _| {
+        let mut locked_chardev = chardev.lock().unwrap();
+        let mut notifiers = vec![EventNotifier::new(
+            NotifierOperation::Delete,
+            fd,
+            None,
+            EventSet::OUT,
+            Vec::new(),
+        )];
+        if let Err(e) = locked_chardev.finish_connect() {
+            debug!("Chardev {} failed to reconnect: {}", locked_chardev.id, e);
+            drop(locked_chardev);
+            schedule_reconnect(chardev.clone());
+            return Some(notifiers);
+        }
+        info!("Chardev {} is reconnected", locked_chardev.id);
+        drop(locked_chardev);
+        notifiers.push(get_stream_notifier(chardev.clone(), fd));
+        Some(notifiers)
+    });
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        fd,
+        None,
+        EventSet::OUT,
+        vec![Arc::new(Mutex::new(handler))],
+    )
+}
+
 fn get_notifier_handler(
     chardev: Arc<Mutex<Chardev>>,
     backend: ChardevType,
 ) -> Box<NotifierCallback> {
     match backend {
         ChardevType::Stdio | ChardevType::Pty => Box::new(move |_, _| {
-            let locked_chardev = chardev.lock().unwrap();
-            let buff_size = locked_chardev.get_remain_space_size.as_ref().unwrap()();
-            let mut buffer = vec![0_u8; buff_size];
-            if let Some(input) = locked_chardev.input.clone() {
-                if let Ok(index) = input.lock().unwrap().chr_read_raw(&mut buffer) {
-                    locked_chardev.receive.as_ref().unwrap()(&mut buffer[..index]);
-                } else {
-                    error!("Failed to read input data");
-                }
-            } else {
-                error!("Failed to get chardev input fd");
-            }
+            read_input(&chardev.lock().unwrap());
             None
         }),
-        ChardevType::Socket(_) => Box::new(move |_, _| {
+        ChardevType::Socket { .. } | ChardevType::TcpSocket { .. } => Box::new(move |_, _| {
             let mut locked_chardev = chardev.lock().unwrap();
-            let (stream, _) = locked_chardev.listener.as_ref().unwrap().accept().unwrap();
-            let listener_fd = locked_chardev.listener.as_ref().unwrap().as_raw_fd();
-            let stream_fd = stream.as_raw_fd();
-            locked_chardev.stream_fd = Some(stream_fd);
-            let stream_arc = Arc::new(Mutex::new(stream));
-            locked_chardev.input = Some(stream_arc.clone());
-            locked_chardev.output = Some(stream_arc);
-
-            let cloned_chardev = chardev.clone();
-            let inner_handler = Box::new(move |event, _| {
-                if event == EventSet::IN {
-                    let locked_chardev = cloned_chardev.lock().unwrap();
-                    let buff_size = locked_chardev.get_remain_space_size.as_ref().unwrap()();
-                    let mut buffer = vec![0_u8; buff_size];
-                    if let Some(input) = locked_chardev.input.clone() {
-                        if let Ok(index) = input.lock().unwrap().chr_read_raw(&mut buffer) {
-                            locked_chardev.receive.as_ref().unwrap()(&mut buffer[..index]);
-                        } else {
-                            error!("Failed to read input data");
-                        }
-                    } else {
-                        error!("Failed to get chardev input fd");
-                    }
+            match locked_chardev.accept_client() {
+                Ok(true) => {
+                    let stream_fd = locked_chardev.stream_fd.unwrap();
+                    drop(locked_chardev);
+                    Some(vec![get_stream_notifier(chardev.clone(), stream_fd)])
                 }
-                if event & EventSet::HANG_UP == EventSet::HANG_UP {
-                    cloned_chardev.lock().unwrap().input = None;
-                    cloned_chardev.lock().unwrap().output = None;
-                    cloned_chardev.lock().unwrap().stream_fd = None;
-                    Some(vec![EventNotifier::new(
-                        NotifierOperation::Delete,
-                        stream_fd,
-                        Some(listener_fd),
-                        EventSet::IN | EventSet::HANG_UP,
-                        Vec::new(),
-                    )])
-                } else {
+                Ok(false) => None,
+                Err(e) => {
+                    error!("Chardev {}: {}", locked_chardev.id, e.display_chain());
                     None
                 }
-            });
-            Some(vec![EventNotifier::new(
-                NotifierOperation::AddShared,
-                stream_fd,
-                Some(listener_fd),
-                EventSet::IN | EventSet::HANG_UP,
-                vec![Arc::new(Mutex::new(inner_handler))],
-            )])
+            }
         }),
-        ChardevType::File(_) => Box::new(move |_, _| None),
+        _ => Box::new(move |_, _| None),
     }
 }
 
 impl EventNotifierHelper for Chardev {
     fn internal_notifiers(chardev: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut notifiers = Vec::new();
-        let backend = chardev.lock().unwrap().backend.clone();
+        let mut locked_chardev = chardev.lock().unwrap();
+        locked_chardev.registered = true;
+        let backend = locked_chardev.backend.clone();
         let cloned_chardev = chardev.clone();
         match backend {
             ChardevType::Stdio | ChardevType::Pty => {
-                if let Some(input) = chardev.lock().unwrap().input.clone() {
+                if let Some(input) = locked_chardev.input.clone() {
                     notifiers.push(EventNotifier::new(
                         NotifierOperation::AddShared,
                         input.lock().unwrap().as_raw_fd(),
@@ -273,8 +749,8 @@ impl EventNotifierHelper for Chardev {
                     ));
                 }
             }
-            ChardevType::Socket(_) => {
-                if let Some(listener) = chardev.lock().unwrap().listener.as_ref() {
+            ChardevType::Socket { .. } | ChardevType::TcpSocket { .. } => {
+                if let Some(listener) = locked_chardev.listener.as_ref() {
                     notifiers.push(EventNotifier::new(
                         NotifierOperation::AddShared,
                         listener.as_raw_fd(),
@@ -286,8 +762,18 @@ impl EventNotifierHelper for Chardev {
                         )))],
                     ));
                 }
+                let stream_fd = locked_chardev.stream_fd;
+                let connecting_fd = locked_chardev.connecting.as_ref().map(|s| s.as_raw_fd());
+                drop(locked_chardev);
+                if let Some(stream_fd) = stream_fd {
+                    notifiers.push(get_stream_notifier(chardev.clone(), stream_fd));
+                } else if let Some(fd) = connecting_fd {
+                    notifiers.push(get_connect_notifier(chardev.clone(), fd));
+                } else {
+                    schedule_reconnect(chardev.clone());
+                }
             }
-            ChardevType::File(_) => (),
+            _ => (),
         }
         notifiers
     }
@@ -309,10 +795,235 @@ pub trait CommunicatInInterface: std::marker::Send + std::os::unix::io::AsRawFd
 /// Provide backend trait object processing the output from the guest.
 pub trait CommunicatOutInterface: std::io::Write + std::marker::Send {}
 
+/// Memory buffer of ringbuf-type chardev, the oldest data is overwritten when it is full.
+pub struct RingBuffer {
+    data: VecDeque<u8>,
+    size: usize,
+}
+
+impl RingBuffer {
+    pub fn new(size: usize) -> Self {
+        RingBuffer {
+            data: VecDeque::new(),
+            size,
+        }
+    }
+
+    /// Take at most `size` bytes of the oldest data out of the buffer.
+    pub fn read(&mut self, size: usize) -> Vec<u8> {
+        let len = cmp::min(size, self.data.len());
+        self.data.drain(..len).collect()
+    }
+}
+
+impl Write for RingBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for byte in buf {
+            if self.data.len() == self.size {
+                self.data.pop_front();
+            }
+            self.data.push_back(*byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Log file of chardev, which is rotated when it exceeds the size limit.
+struct ChardevLog {
+    path: String,
+    file: File,
+    /// Size of the current log file.
+    written: u64,
+    /// Size limit of the log file, zero means no limit.
+    max_size: u64,
+    /// Number of rotated log files kept.
+    count: u32,
+}
+
+impl ChardevLog {
+    fn new(cfg: &ChardevLogConfig) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&cfg.path)?;
+        let written = file.metadata()?.len();
+        Ok(ChardevLog {
+            path: cfg.path.clone(),
+            file,
+            written,
+            max_size: cfg.size,
+            count: cfg.count,
+        })
+    }
+
+    /// Rename `path` to `path.1`, `path.1` to `path.2`, ..., and drop the oldest one,
+    /// then start a new log file.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.count > 0 {
+            for index in (1..self.count).rev() {
+                let from = format!("{}.{}", self.path, index);
+                if Path::new(&from).exists() {
+                    rename(&from, format!("{}.{}", self.path, index + 1))?;
+                }
+            }
+            rename(&self.path, format!("{}.1", self.path))?;
+        }
+        self.file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+
+    fn record(&mut self, buf: &[u8]) {
+        if self.max_size != 0
+            && self.written != 0
+            && self.written + buf.len() as u64 > self.max_size
+        {
+            if let Err(e) = self.rotate() {
+                error!("Failed to rotate chardev log file {}: {}", self.path, e);
+            }
+        }
+        match self.file.write_all(buf) {
+            Ok(()) => self.written += buf.len() as u64,
+            Err(e) => error!("Failed to write chardev log file {}: {}", self.path, e),
+        }
+    }
+}
+
+/// Output of chardev with log file, which writes to both the backend and the log file.
+/// The output is only recorded in the log file if the backend is not connected.
+struct LogOutput {
+    output: Option<Arc<Mutex<dyn CommunicatOutInterface>>>,
+    log: Arc<Mutex<ChardevLog>>,
+}
+
+impl Write for LogOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.log.lock().unwrap().record(buf);
+        if let Some(output) = &self.output {
+            output.lock().unwrap().write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(output) = &self.output {
+            output.lock().unwrap().flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Ring buffers of ringbuf-type chardevs, which are accessed by QMP.
+static RING_BUFFERS: Lazy<Mutex<HashMap<String, Weak<Mutex<RingBuffer>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn get_ring_buffer(id: &str) -> Result<Arc<Mutex<RingBuffer>>> {
+    let ring_buffers = RING_BUFFERS.lock().unwrap();
+    if let Some(ringbuf) = ring_buffers.get(id).and_then(|ringbuf| ringbuf.upgrade()) {
+        Ok(ringbuf)
+    } else {
+        bail!("Ringbuf-type chardev {} not found", id);
+    }
+}
+
+/// Read at most `size` bytes of the oldest data from the ringbuf-type chardev `id`,
+/// the data read is removed from the ring buffer.
+///
+/// # Arguments
+///
+/// * `id` - The id of the chardev.
+/// * `size` - The maximum number of bytes to read.
+/// * `format` - The encoding of the returned data, utf8 by default.
+pub fn qmp_ringbuf_read(id: &str, size: usize, format: Option<DataFormat>) -> Result<String> {
+    let data = get_ring_buffer(id)?.lock().unwrap().read(size);
+    match format.unwrap_or(DataFormat::Utf8) {
+        DataFormat::Utf8 => Ok(String::from_utf8_lossy(&data).to_string()),
+        DataFormat::Base64 => Ok(base64::encode(&data)),
+    }
+}
+
+/// Write `data` to the ringbuf-type chardev `id`.
+///
+/// # Arguments
+///
+/// * `id` - The id of the chardev.
+/// * `data` - The data to write.
+/// * `format` - The encoding of `data`, utf8 by default.
+pub fn qmp_ringbuf_write(id: &str, data: &str, format: Option<DataFormat>) -> Result<()> {
+    let ringbuf = get_ring_buffer(id)?;
+    match format.unwrap_or(DataFormat::Utf8) {
+        DataFormat::Utf8 => ringbuf.lock().unwrap().write_all(data.as_bytes())?,
+        DataFormat::Base64 => ringbuf
+            .lock()
+            .unwrap()
+            .write_all(&base64::decode(data).chain_err(|| "Invalid base64 data")?)?,
+    }
+    Ok(())
+}
+
 impl CommunicatInInterface for UnixStream {}
+impl CommunicatInInterface for TcpStream {}
 impl CommunicatInInterface for File {}
 impl CommunicatInInterface for Stdin {}
 
 impl CommunicatOutInterface for UnixStream {}
+impl CommunicatOutInterface for TcpStream {}
 impl CommunicatOutInterface for File {}
 impl CommunicatOutInterface for Stdout {}
+impl CommunicatOutInterface for RingBuffer {}
+impl CommunicatOutInterface for Sink {}
+impl CommunicatOutInterface for LogOutput {}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn tcp_chardev(port: u16) -> Chardev {
+        Chardev::new(ChardevConfig {
+            id: "chardev0".to_string(),
+            backend: ChardevType::TcpSocket {
+                host: "127.0.0.1".to_string(),
+                port,
+                server: false,
+                nowait: false,
+                reconnect: 1,
+            },
+            logfile: None,
+        })
+    }
+
+    #[test]
+    fn test_tcp_chardev_reconnect() {
+        // Find a free port without listening on it, so that the chardev is realized
+        // without connection and the address is resolved.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut chardev = tcp_chardev(port);
+        chardev.realize().unwrap();
+        assert!(chardev.stream_fd.is_none());
+        assert_eq!(chardev.addrs.len(), 1);
+
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        let fd = chardev.start_connect().unwrap();
+        assert!(chardev.connecting.is_some());
+        // The connection completes once the server accepts it.
+        let _client = listener.accept().unwrap();
+        chardev.finish_connect().unwrap();
+        assert!(chardev.connecting.is_none());
+        assert_eq!(chardev.stream_fd, Some(fd));
+        assert!(chardev.finish_connect().is_err());
+    }
+}
@@ -98,7 +98,7 @@ mod serial;
 
 #[cfg(target_arch = "x86_64")]
 pub use self::rtc::{RTC, RTC_IRQ, RTC_PORT_INDEX};
-pub use chardev::{Chardev, InputReceiver};
+pub use chardev::{qmp_ringbuf_read, qmp_ringbuf_write, Chardev, InputReceiver};
 #[cfg(target_arch = "x86_64")]
 pub use fwcfg::FwCfgIO;
 #[cfg(target_arch = "aarch64")]
@@ -450,6 +450,7 @@ mod test {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            logfile: None,
         };
         let mut pl011_dev = PL011::new(SerialConfig {
             chardev: chardev_cfg,
@@ -0,0 +1,292 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Dumps of machine internals for the human monitor, shared by micro and
+//! standard machines to implement `HumanMonitorInterface`.
+
+use std::sync::{Arc, Mutex};
+
+use address_space::{AddressSpace, GuestAddress};
+use cpu::CPU;
+#[cfg(target_arch = "x86_64")]
+use kvm_bindings::kvm_sregs;
+use machine_manager::errors::{Result, ResultExt};
+use pci::config::{SECONDARY_BUS_NUM, VENDOR_ID};
+use pci::{pci_func, pci_slot, PciBus, PciHost};
+use sysbus::{SysBus, SysBusDevType};
+
+/// Guest memory is read page by page, as each page may be mapped separately.
+const PAGE_SIZE: u64 = 4096;
+
+#[cfg(target_arch = "x86_64")]
+const CR0_PG: u64 = 1 << 31;
+#[cfg(target_arch = "x86_64")]
+const CR4_PSE: u64 = 1 << 4;
+#[cfg(target_arch = "x86_64")]
+const CR4_PAE: u64 = 1 << 5;
+#[cfg(target_arch = "x86_64")]
+const CR4_LA57: u64 = 1 << 12;
+#[cfg(target_arch = "x86_64")]
+const EFER_LMA: u64 = 1 << 10;
+#[cfg(target_arch = "x86_64")]
+const PTE_PRESENT: u64 = 1;
+#[cfg(target_arch = "x86_64")]
+const PTE_PAGE_SIZE: u64 = 1 << 7;
+#[cfg(target_arch = "x86_64")]
+const PTE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Dump the region trees of memory and I/O address spaces.
+pub(crate) fn info_mtree(
+    sys_mem: &AddressSpace,
+    #[cfg(target_arch = "x86_64")] sys_io: &AddressSpace,
+) -> String {
+    #[allow(unused_mut)]
+    let mut info = format!("address-space: memory\n{}", sys_mem.mtree());
+    #[cfg(target_arch = "x86_64")]
+    info.push_str(&format!("\naddress-space: I/O\n{}", sys_io.mtree()));
+    info
+}
+
+/// Dump the devices on the buses of PCI host.
+pub(crate) fn info_pci(pci_host: &Arc<Mutex<PciHost>>) -> String {
+    let mut info = String::new();
+    let root_bus = pci_host.lock().unwrap().root_bus.clone();
+    pci_bus_info(&root_bus, &mut info);
+    info
+}
+
+fn pci_bus_info(bus: &Arc<Mutex<PciBus>>, info: &mut String) {
+    let locked_bus = bus.lock().unwrap();
+    let bus_num = locked_bus.number(SECONDARY_BUS_NUM as usize);
+    let mut devfns: Vec<&u8> = locked_bus.devices.keys().collect();
+    devfns.sort();
+    for devfn in devfns {
+        let locked_dev = locked_bus.devices[devfn].lock().unwrap();
+        let mut ids = [0_u8; 4];
+        locked_dev.read_config(VENDOR_ID as usize, &mut ids);
+        let mut class = [0_u8; 4];
+        locked_dev.read_config(pci::config::REVISION_ID, &mut class);
+        info.push_str(&format!(
+            "  Bus {:2}, device {:3}, function {}:\n",
+            bus_num,
+            pci_slot(*devfn),
+            pci_func(*devfn)
+        ));
+        info.push_str(&format!(
+            "    Class {:02x}{:02x}: PCI device {:02x}{:02x}:{:02x}{:02x}\n      id \"{}\"\n",
+            class[3],
+            class[2],
+            ids[1],
+            ids[0],
+            ids[3],
+            ids[2],
+            locked_dev.name()
+        ));
+    }
+    for child_bus in locked_bus.child_buses.iter() {
+        pci_bus_info(child_bus, info);
+    }
+}
+
+/// Dump the devices of system bus and PCI buses.
+pub(crate) fn info_qtree(sysbus: &SysBus, pci_host: Option<&Arc<Mutex<PciHost>>>) -> String {
+    let mut info = String::from("bus: main-system-bus\n  type System\n");
+    for dev in sysbus.devices.iter() {
+        let mut locked_dev = dev.lock().unwrap();
+        let type_name = match locked_dev.get_type() {
+            SysBusDevType::Serial => "serial",
+            SysBusDevType::Rtc => "rtc",
+            SysBusDevType::VirtioMmio => "virtio-mmio",
+            #[cfg(target_arch = "aarch64")]
+            SysBusDevType::PL011 => "pl011",
+            SysBusDevType::FwCfg => "fw-cfg",
+            SysBusDevType::Flash => "pflash",
+            SysBusDevType::Others => "sysbus-device",
+        };
+        info.push_str(&format!("  dev: {}", type_name));
+        if let Some(res) = locked_dev.get_sys_resource() {
+            if res.region_size > 0 {
+                info.push_str(&format!(
+                    ", mmio {:016x}-{:016x}",
+                    res.region_base,
+                    res.region_base + res.region_size - 1
+                ));
+            }
+            if res.irq >= 0 {
+                info.push_str(&format!(", irq {}", res.irq));
+            }
+        }
+        info.push('\n');
+    }
+    if let Some(pci_host) = pci_host {
+        info.push_str("  dev: pcie-host\n");
+        let root_bus = pci_host.lock().unwrap().root_bus.clone();
+        pci_bus_qtree(&root_bus, 2, &mut info);
+    }
+    info
+}
+
+fn pci_bus_qtree(bus: &Arc<Mutex<PciBus>>, depth: usize, info: &mut String) {
+    let locked_bus = bus.lock().unwrap();
+    info.push_str(&format!(
+        "{:indent$}bus: {}\n{:indent$}  type PCIE\n",
+        "",
+        locked_bus.name,
+        "",
+        indent = depth * 2
+    ));
+    let mut devfns: Vec<&u8> = locked_bus.devices.keys().collect();
+    devfns.sort();
+    for devfn in devfns {
+        let dev = &locked_bus.devices[devfn];
+        info.push_str(&format!(
+            "{:indent$}  dev: {}, addr {:02x}.{:x}\n",
+            "",
+            dev.lock().unwrap().name(),
+            pci_slot(*devfn),
+            pci_func(*devfn),
+            indent = depth * 2
+        ));
+        // Buses behind the bridge are listed under the bridge.
+        for child_bus in locked_bus.child_buses.iter() {
+            let bridge = child_bus
+                .lock()
+                .unwrap()
+                .parent_bridge
+                .as_ref()
+                .and_then(|bridge| bridge.upgrade());
+            let is_child = match bridge {
+                Some(bridge) => Arc::as_ptr(&bridge) as *const u8 == Arc::as_ptr(dev) as *const u8,
+                None => false,
+            };
+            if is_child {
+                pci_bus_qtree(child_bus, depth + 2, info);
+            }
+        }
+    }
+}
+
+fn find_cpu(cpus: &[Arc<CPU>], cpu_index: usize) -> Result<&Arc<CPU>> {
+    match cpus.iter().find(|cpu| cpu.id() as usize == cpu_index) {
+        Some(cpu) => Ok(cpu),
+        None => bail!("CPU {} not found", cpu_index),
+    }
+}
+
+/// Dump the registers of the vCPU with index `cpu_index`.
+pub(crate) fn info_registers(cpus: &[Arc<CPU>], cpu_index: usize) -> Result<String> {
+    find_cpu(cpus, cpu_index)?
+        .dump_registers()
+        .chain_err(|| format!("Failed to dump registers of CPU {}", cpu_index))
+}
+
+/// Read guest memory, see `HumanMonitorInterface::hmp_read_memory`.
+pub(crate) fn read_memory(
+    sys_mem: &AddressSpace,
+    cpus: &[Arc<CPU>],
+    addr: u64,
+    len: u64,
+    cpu_index: Option<usize>,
+) -> Result<Vec<u8>> {
+    #[cfg(target_arch = "x86_64")]
+    let sregs = match cpu_index {
+        Some(index) => Some(
+            find_cpu(cpus, index)?
+                .with_vcpu_paused(|fd| fd.get_sregs())
+                .chain_err(|| format!("Failed to pause CPU {}", index))?
+                .chain_err(|| format!("Failed to get sregs of CPU {}", index))?,
+        ),
+        None => None,
+    };
+    #[cfg(target_arch = "aarch64")]
+    if let Some(index) = cpu_index {
+        find_cpu(cpus, index)?;
+        bail!("Virtual address is not supported on aarch64, use 'xp' instead");
+    }
+
+    let end = match addr.checked_add(len) {
+        Some(end) => end,
+        None => bail!("Address 0x{:x} with length {} overflows", addr, len),
+    };
+    let mut data = Vec::new();
+    let mut cur = addr;
+    while cur < end {
+        let size = std::cmp::min(end - cur, PAGE_SIZE - (cur & (PAGE_SIZE - 1)));
+        #[cfg(target_arch = "x86_64")]
+        let gpa = match sregs.as_ref() {
+            Some(sregs) => gva_to_gpa(sys_mem, sregs, cur)?,
+            None => cur,
+        };
+        #[cfg(target_arch = "aarch64")]
+        let gpa = cur;
+        sys_mem
+            .read(&mut data, GuestAddress(gpa), size)
+            .chain_err(|| format!("Cannot access memory at 0x{:x}", gpa))?;
+        cur += size;
+    }
+    Ok(data)
+}
+
+/// Translate guest virtual address by walking the page tables of guest.
+#[cfg(target_arch = "x86_64")]
+fn gva_to_gpa(sys_mem: &AddressSpace, sregs: &kvm_sregs, gva: u64) -> Result<u64> {
+    if sregs.cr0 & CR0_PG == 0 {
+        return Ok(gva);
+    }
+
+    let read_entry = |addr: u64, wide: bool| -> Result<u64> {
+        let entry = if wide {
+            sys_mem.read_object::<u64>(GuestAddress(addr))
+        } else {
+            sys_mem
+                .read_object::<u32>(GuestAddress(addr))
+                .map(u64::from)
+        };
+        let entry = entry.chain_err(|| format!("Failed to read page table at 0x{:x}", addr))?;
+        if entry & PTE_PRESENT == 0 {
+            bail!("Page is not present for virtual address 0x{:x}", gva);
+        }
+        Ok(entry)
+    };
+
+    // 32-bit paging.
+    if sregs.cr4 & CR4_PAE == 0 {
+        let gva = gva & 0xffff_ffff;
+        let pde = read_entry((sregs.cr3 & 0xffff_f000) + ((gva >> 22) & 0x3ff) * 4, false)?;
+        if pde & PTE_PAGE_SIZE != 0 && sregs.cr4 & CR4_PSE != 0 {
+            return Ok((pde & 0xffc0_0000) | (gva & 0x3f_ffff));
+        }
+        let pte = read_entry((pde & 0xffff_f000) + ((gva >> 12) & 0x3ff) * 4, false)?;
+        return Ok((pte & 0xffff_f000) | (gva & 0xfff));
+    }
+
+    let (mut table, mut shift) = if sregs.efer & EFER_LMA != 0 {
+        let shift = if sregs.cr4 & CR4_LA57 != 0 { 48 } else { 39 };
+        (sregs.cr3 & PTE_ADDR_MASK, shift)
+    } else {
+        // PAE paging, the page directory pointer table has 4 entries.
+        let gva = gva & 0xffff_ffff;
+        let pdpte = read_entry((sregs.cr3 & 0xffff_ffe0) + ((gva >> 30) & 0x3) * 8, true)?;
+        (pdpte & PTE_ADDR_MASK, 21)
+    };
+    loop {
+        let entry = read_entry(table + ((gva >> shift) & 0x1ff) * 8, true)?;
+        // Only the entries of page directory pointer table and page directory
+        // may map 1GiB and 2MiB pages.
+        if shift == 12 || (entry & PTE_PAGE_SIZE != 0 && (shift == 21 || shift == 30)) {
+            let offset_mask = (1_u64 << shift) - 1;
+            return Ok((entry & PTE_ADDR_MASK & !offset_mask) | (gva & offset_mask));
+        }
+        table = entry & PTE_ADDR_MASK;
+        shift -= 9;
+    }
+}
@@ -100,6 +100,8 @@ pub mod errors {
     }
 }
 
+mod hmp;
+mod metrics;
 mod micro_vm;
 mod standard_vm;
 
@@ -107,42 +109,96 @@ pub use micro_vm::LightMachine;
 use pci::{PciBus, PciDevOps, PciHost, RootPort};
 pub use standard_vm::StdMachine;
 use virtio::{
-    BlockState, RngState, VhostKern, VirtioConsoleState, VirtioDevice, VirtioMmioState,
-    VirtioNetState,
+    BlockState, RngState, ScsiCntlrState, VhostKern, VhostUser, VirtioConsoleState, VirtioDevice,
+    VirtioMmioState, VirtioNetState,
 };
 
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
 use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
 use std::path::Path;
-use std::sync::{Arc, Barrier, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 #[cfg(target_arch = "x86_64")]
 use address_space::KvmIoListener;
-use address_space::{create_host_mmaps, AddressSpace, KvmMemoryListener, Region};
+use address_space::{
+    create_backend_mmaps, create_host_mmaps, AddressSpace, KvmMemoryListener, Region,
+};
 use cpu::{ArchCPU, CPUBootConfig, CPUInterface, CPU};
 use devices::legacy::FwCfgOps;
 #[cfg(target_arch = "aarch64")]
 use devices::InterruptController;
+use error_chain::ChainedError;
 use hypervisor::kvm::KVM_FDS;
 use kvm_ioctls::VcpuFd;
 use machine_manager::config::{
-    get_multi_function, get_pci_bdf, parse_balloon, parse_blk, parse_device_id, parse_net,
-    parse_rng_dev, parse_root_port, parse_vfio, parse_virtconsole, parse_virtio_serial,
-    parse_vsock, MachineMemConfig, PFlashConfig, PciBdf, SerialConfig, VfioConfig, VmConfig,
-    FAST_UNPLUG_ON,
+    get_multi_function, get_pci_bdf, parse_balloon, parse_blk, parse_device_id, parse_fs,
+    parse_net, parse_rng_dev, parse_root_port, parse_scsi_controller, parse_scsi_device,
+    parse_vfio, parse_vhost_user_blk, parse_virtconsole, parse_virtio_mem, parse_virtio_serial,
+    parse_virtserialport, parse_vsock, MachineMemConfig, MemZoneConfig, NumaNodes, PFlashConfig,
+    PciBdf, SerialConfig, VfioConfig, VmConfig, FAST_UNPLUG_ON,
 };
 use machine_manager::event_loop::EventLoop;
 use machine_manager::machine::{KvmVmState, MachineInterface};
-use migration::MigrationManager;
+use machine_manager::qmp::QmpChannel;
+use migration::{MigrationManager, MigrationStatus};
 use util::loop_context::{EventNotifier, NotifierCallback, NotifierOperation};
+use util::num_ops::round_up;
 use util::seccomp::{BpfRule, SeccompOpt, SyscallFilter};
+use util::unix::UnixPath;
 use vfio::{VfioDevice, VfioPciDevice};
-use virtio::{balloon_allow_list, Balloon, Block, Console, Rng, VirtioMmioDevice, VirtioPciDevice};
+use virtio::{
+    balloon_allow_list, register_virtio_mem_device, register_virtio_serial, virtio_mem_end_address,
+    virtio_serial_attach_port, Balloon, Block, Console, Rng, VirtioMem, VirtioMmioDevice,
+    VirtioPciDevice,
+};
 use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
 
 use errors::{ErrorKind, Result, ResultExt};
 use standard_vm::errors::Result as StdResult;
 
+/// Split the guest ram ranges into the ranges of each numa node. The ram of numa
+/// nodes is laid out in ascending order of node id.
+///
+/// # Arguments
+///
+/// * `ram_ranges` - Guest ram ranges, element represents (start_addr, size).
+/// * `numa_nodes` - Guest numa nodes.
+pub(crate) fn numa_ram_ranges(
+    ram_ranges: &[(u64, u64)],
+    numa_nodes: &NumaNodes,
+) -> BTreeMap<u32, Vec<(u64, u64)>> {
+    let mut node_ranges = BTreeMap::new();
+    let mut ranges = ram_ranges.iter().copied();
+    let mut current = ranges.next();
+    for (id, node) in numa_nodes.iter() {
+        let mut left = node.size;
+        let mut result = Vec::new();
+        while left > 0 {
+            let (base, size) = match current {
+                Some(range) => range,
+                None => break,
+            };
+            let len = std::cmp::min(size, left);
+            result.push((base, len));
+            left -= len;
+            current = if len == size {
+                ranges.next()
+            } else {
+                Some((base + len, size - len))
+            };
+        }
+        node_ranges.insert(*id, result);
+    }
+    node_ranges
+}
+
 pub trait MachineOps {
     /// Calculate the ranges of memory according to architecture.
     ///
@@ -158,6 +214,22 @@ pub trait MachineOps {
 
     fn load_boot_source(&self, fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>) -> Result<CPUBootConfig>;
 
+    /// Get the guest numa nodes, `None` if numa is not configured.
+    fn get_numa_nodes(&self) -> Option<&NumaNodes> {
+        None
+    }
+
+    /// Get the memory backend which provides guest memory, `None` if not configured.
+    fn get_mem_backend(&self) -> Option<&MemZoneConfig> {
+        None
+    }
+
+    /// Get the guest physical range `(base, size)` reserved for hotpluggable memory
+    /// devices, `None` if memory hotplug is not supported.
+    fn get_device_mem_range(&self) -> Option<(u64, u64)> {
+        None
+    }
+
     /// Init I/O & memory address space and mmap guest memory.
     ///
     /// # Arguments
@@ -180,8 +252,26 @@ pub trait MachineOps {
         let mut mem_mappings = Vec::new();
         if !is_migrate {
             let ram_ranges = self.arch_ram_ranges(mem_config.mem_size);
-            mem_mappings = create_host_mmaps(&ram_ranges, &mem_config, nr_cpus)
-                .chain_err(|| "Failed to mmap guest ram.")?;
+            if let Some(numa_nodes) = self.get_numa_nodes() {
+                if mem_config.mem_path.is_some() {
+                    bail!(
+                        "Memory path is not supported with numa nodes, use memory backend instead"
+                    );
+                }
+                for (id, node_ranges) in numa_ram_ranges(&ram_ranges, numa_nodes) {
+                    let mem_zone = &numa_nodes[&id].mem_zone;
+                    let mappings =
+                        create_backend_mmaps(&node_ranges, mem_config, nr_cpus, mem_zone)
+                            .chain_err(|| format!("Failed to mmap ram of numa node {}.", id))?;
+                    mem_mappings.extend(mappings);
+                }
+            } else if let Some(mem_zone) = self.get_mem_backend() {
+                mem_mappings = create_backend_mmaps(&ram_ranges, mem_config, nr_cpus, mem_zone)
+                    .chain_err(|| "Failed to mmap guest ram.")?;
+            } else {
+                mem_mappings = create_host_mmaps(&ram_ranges, mem_config, nr_cpus)
+                    .chain_err(|| "Failed to mmap guest ram.")?;
+            }
         }
 
         sys_mem
@@ -216,11 +306,13 @@ pub trait MachineOps {
     ///
     /// * `vm` - `MachineInterface` to obtain functions cpu can use.
     /// * `nr_cpus` - The number of vcpus.
+    /// * `max_cpus` - The maximum number of vcpus.
     /// * `fds` - File descriptors obtained by creating new Vcpu in KVM.
     /// * `boot_cfg` - Boot message generated by reading boot source to guest memory.
     fn init_vcpu(
         vm: Arc<Mutex<dyn MachineInterface + Send + Sync>>,
         nr_cpus: u8,
+        #[cfg(target_arch = "x86_64")] max_cpus: u8,
         fds: &[Arc<VcpuFd>],
         boot_cfg: &Option<CPUBootConfig>,
     ) -> Result<Vec<Arc<CPU>>>
@@ -233,7 +325,7 @@ pub trait MachineOps {
             #[cfg(target_arch = "aarch64")]
             let arch_cpu = ArchCPU::new(u32::from(vcpu_id));
             #[cfg(target_arch = "x86_64")]
-            let arch_cpu = ArchCPU::new(u32::from(vcpu_id), u32::from(nr_cpus));
+            let arch_cpu = ArchCPU::new(u32::from(vcpu_id), u32::from(max_cpus));
 
             let cpu = Arc::new(CPU::new(
                 fds[vcpu_id as usize].clone(),
@@ -348,6 +440,58 @@ pub trait MachineOps {
         bail!("Virtio mmio device Not supported!");
     }
 
+    /// Add vhost-user-blk device, whose IO is handled by the vhost-user slave.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration.
+    fn add_vhost_user_blk(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_vhost_user_blk(vm_config, cfg_args)?;
+        let sys_mem = self.get_sys_mem().clone();
+        let blk = Arc::new(Mutex::new(VhostUser::Block::new(&device_cfg, &sys_mem)));
+        if cfg_args.contains("vhost-user-blk-device") {
+            let device = VirtioMmioDevice::new(&sys_mem, blk);
+            MigrationManager::register_device_instance_mutex(
+                VirtioMmioState::descriptor(),
+                self.realize_virtio_mmio_device(device)
+                    .chain_err(|| ErrorKind::RlzVirtioMmioErr)?,
+            );
+        } else {
+            let bdf = get_pci_bdf(cfg_args)?;
+            let multi_func = get_multi_function(cfg_args)?;
+            self.add_virtio_pci_device(&device_cfg.id, &bdf, blk, multi_func)?;
+            self.reset_bus(&device_cfg.id)?;
+        }
+        Ok(())
+    }
+
+    /// Add vhost-user-fs device, whose requests are handled by the vhost-user slave.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration.
+    fn add_vhost_user_fs(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_fs(vm_config, cfg_args)?;
+        let sys_mem = self.get_sys_mem().clone();
+        let fs = Arc::new(Mutex::new(VhostUser::Fs::new(&device_cfg, &sys_mem)));
+        if cfg_args.contains("vhost-user-fs-device") {
+            let device = VirtioMmioDevice::new(&sys_mem, fs);
+            MigrationManager::register_device_instance_mutex(
+                VirtioMmioState::descriptor(),
+                self.realize_virtio_mmio_device(device)
+                    .chain_err(|| ErrorKind::RlzVirtioMmioErr)?,
+            );
+        } else {
+            let bdf = get_pci_bdf(cfg_args)?;
+            let multi_func = get_multi_function(cfg_args)?;
+            self.add_virtio_pci_device(&device_cfg.id, &bdf, fs, multi_func)?;
+            self.reset_bus(&device_cfg.id)?;
+        }
+        Ok(())
+    }
+
     fn add_virtio_balloon(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
         let device_cfg = parse_balloon(vm_config, cfg_args)?;
         let sys_mem = self.get_sys_mem();
@@ -372,58 +516,67 @@ pub trait MachineOps {
         Ok(())
     }
 
-    /// Add console device.
+    /// Add port of virtio-serial device, such as virtconsole and virtserialport.
     ///
     /// # Arguments
     ///
     /// * `vm_config` - VM configuration.
     /// * `cfg_args` - Device configuration args.
-    fn add_virtio_console(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
-        let device_cfg = parse_virtconsole(vm_config, cfg_args)?;
-        let sys_mem = self.get_sys_mem();
-        let console = Arc::new(Mutex::new(Console::new(device_cfg.clone())));
-        if let Some(serial) = &vm_config.virtio_serial {
-            if serial.pci_bdf.is_none() {
-                let device = VirtioMmioDevice::new(sys_mem, console.clone());
-                MigrationManager::register_device_instance_mutex(
-                    VirtioMmioState::descriptor(),
-                    self.realize_virtio_mmio_device(device)
-                        .chain_err(|| ErrorKind::RlzVirtioMmioErr)?,
-                );
-            } else {
-                let name = device_cfg.id;
-                let virtio_serial_info = if let Some(serial_info) = &vm_config.virtio_serial {
-                    serial_info
-                } else {
-                    bail!("No virtio-serial-pci device configured for virtconsole");
-                };
-                // Reasonable, because for virtio-serial-pci device, the bdf has been checked.
-                let bdf = virtio_serial_info.pci_bdf.clone().unwrap();
-                let multi_func = virtio_serial_info.multifunction;
-                let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
-                let sys_mem = self.get_sys_mem().clone();
-                let virtio_pci_device = VirtioPciDevice::new(
-                    name,
-                    devfn,
-                    sys_mem,
-                    console.clone(),
-                    parent_bus,
-                    multi_func,
-                );
-                virtio_pci_device
-                    .realize()
-                    .chain_err(|| "Failed  to add virtio pci console device")?;
-            }
+    /// * `is_console` - Whether the port is a console port.
+    fn add_virtio_serial_port(
+        &mut self,
+        vm_config: &mut VmConfig,
+        cfg_args: &str,
+        is_console: bool,
+    ) -> Result<()> {
+        let port_cfg = if is_console {
+            parse_virtconsole(vm_config, cfg_args)?
         } else {
-            bail!("No virtio-serial-bus specified");
-        }
-        MigrationManager::register_device_instance_mutex(VirtioConsoleState::descriptor(), console);
+            parse_virtserialport(vm_config, cfg_args)?
+        };
+        virtio_serial_attach_port(&port_cfg)
+            .chain_err(|| format!("Failed to add virtio-serial port {}", port_cfg.id))?;
 
         Ok(())
     }
 
+    /// Add virtio-serial device.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration args.
     fn add_virtio_serial(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
         parse_virtio_serial(vm_config, cfg_args)?;
+        // Reasonable, because the virtio-serial config has just been parsed.
+        let serial_cfg = vm_config.virtio_serial.clone().unwrap();
+        let serial = Arc::new(Mutex::new(Console::new(serial_cfg.clone())));
+        if let Some(bdf) = &serial_cfg.pci_bdf {
+            let multi_func = serial_cfg.multifunction;
+            let (devfn, parent_bus) = self.get_devfn_and_parent_bus(bdf)?;
+            let sys_mem = self.get_sys_mem().clone();
+            let virtio_pci_device = VirtioPciDevice::new(
+                serial_cfg.id,
+                devfn,
+                sys_mem,
+                serial.clone(),
+                parent_bus,
+                multi_func,
+            );
+            virtio_pci_device
+                .realize()
+                .chain_err(|| "Failed to add virtio pci serial device")?;
+        } else {
+            let device = VirtioMmioDevice::new(self.get_sys_mem(), serial.clone());
+            MigrationManager::register_device_instance_mutex(
+                VirtioMmioState::descriptor(),
+                self.realize_virtio_mmio_device(device)
+                    .chain_err(|| ErrorKind::RlzVirtioMmioErr)?,
+            );
+        }
+        register_virtio_serial(&serial);
+        MigrationManager::register_device_instance_mutex(VirtioConsoleState::descriptor(), serial);
+
         Ok(())
     }
 
@@ -462,6 +615,86 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Add virtio-mem device.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration args.
+    fn add_virtio_mem(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_virtio_mem(vm_config, cfg_args)?;
+        if let Some(numa_nodes) = self.get_numa_nodes() {
+            if let Some(node) = device_cfg.node {
+                if !numa_nodes.contains_key(&node) {
+                    bail!(
+                        "Numa node {} of virtio-mem {} not found",
+                        node,
+                        device_cfg.id
+                    );
+                }
+            }
+            if numa_nodes
+                .values()
+                .any(|node| node.mem_zone.id == device_cfg.mem_zone.id)
+            {
+                bail!(
+                    "Memory backend {} is already used by numa node",
+                    device_cfg.mem_zone.id
+                );
+            }
+        } else if device_cfg.node.is_some() {
+            bail!("Numa is not configured for virtio-mem {}", device_cfg.id);
+        }
+
+        let (base, size) = match self.get_device_mem_range() {
+            Some(range) => range,
+            None => bail!("Memory hotplug is not supported by this machine"),
+        };
+        // Regions of virtio-mem devices are aligned to 1G, which is the largest
+        // memory block size of linux.
+        let used_end = virtio_mem_end_address().map_or(base, |end| end.raw_value());
+        let addr = round_up(cmp::max(base, used_end), 1 << 30)
+            .chain_err(|| "Failed to align the address of virtio-mem region")?;
+        if addr + device_cfg.mem_zone.size > base + size {
+            bail!(
+                "No space for memory region of virtio-mem {}, size 0x{:x}",
+                device_cfg.id,
+                device_cfg.mem_zone.size
+            );
+        }
+
+        let mem_config = &vm_config.machine_config.mem_config;
+        let mem_mapping = create_backend_mmaps(
+            &[(addr, device_cfg.mem_zone.size)],
+            mem_config,
+            vm_config.machine_config.nr_cpus,
+            &device_cfg.mem_zone,
+        )
+        .chain_err(|| "Failed to mmap memory of virtio-mem")?
+        .remove(0);
+        let share = device_cfg.mem_zone.share || mem_config.mem_share;
+
+        let bdf = get_pci_bdf(cfg_args)?;
+        let multi_func = get_multi_function(cfg_args)?;
+        let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
+        let sys_mem = self.get_sys_mem().clone();
+        let name = device_cfg.id.clone();
+        let mem_dev = Arc::new(Mutex::new(VirtioMem::new(device_cfg, mem_mapping, share)));
+        let virtio_pci_device = VirtioPciDevice::new(
+            name,
+            devfn,
+            sys_mem,
+            mem_dev.clone(),
+            parent_bus,
+            multi_func,
+        );
+        virtio_pci_device
+            .realize()
+            .chain_err(|| "Failed to add virtio pci mem device")?;
+        register_virtio_mem_device(&mem_dev);
+        Ok(())
+    }
+
     fn get_pci_host(&mut self) -> StdResult<&Arc<Mutex<PciHost>>> {
         bail!("No pci host found");
     }
@@ -473,7 +706,9 @@ pub trait MachineOps {
             if name.is_empty() {
                 bail!("Device id is empty");
             }
-            if PciBus::find_attached_bus(&pci_host.lock().unwrap().root_bus, name).is_some() {
+            if PciBus::find_attached_bus(&pci_host.lock().unwrap().root_bus, name).is_some()
+                || virtio::scsi_device_existed(name)
+            {
                 bail!("Device id {} existed", name);
             }
         }
@@ -486,28 +721,59 @@ pub trait MachineOps {
         let device_cfg = parse_blk(vm_config, cfg_args)?;
         let device = Arc::new(Mutex::new(Block::new(device_cfg.clone())));
         self.add_virtio_pci_device(&device_cfg.id, &bdf, device.clone(), multi_func)?;
+        virtio::register_block_device(&device);
         MigrationManager::register_device_instance_mutex(BlockState::descriptor(), device);
         self.reset_bus(&device_cfg.id)?;
         Ok(())
     }
 
+    fn add_virtio_scsi_cntlr(&mut self, cfg_args: &str) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let multi_func = get_multi_function(cfg_args)?;
+        let device_cfg = parse_scsi_controller(cfg_args)?;
+        let device = Arc::new(Mutex::new(virtio::ScsiCntlr::new(device_cfg.clone())));
+        virtio::register_scsi_cntlr(&device);
+        self.add_virtio_pci_device(&device_cfg.id, &bdf, device.clone(), multi_func)?;
+        MigrationManager::register_device_instance_mutex(ScsiCntlrState::descriptor(), device);
+        self.reset_bus(&device_cfg.id)?;
+        Ok(())
+    }
+
+    fn add_scsi_device(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let device_cfg = parse_scsi_device(vm_config, cfg_args)?;
+        virtio::scsi_attach_device(&device_cfg)
+            .chain_err(|| format!("Failed to attach scsi device {}", device_cfg.id))?;
+        Ok(())
+    }
+
     fn add_virtio_pci_net(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
         let bdf = get_pci_bdf(cfg_args)?;
         let multi_func = get_multi_function(cfg_args)?;
         let device_cfg = parse_net(vm_config, cfg_args)?;
-        let device: Arc<Mutex<dyn VirtioDevice>> = if device_cfg.vhost_type.is_some() {
-            Arc::new(Mutex::new(VhostKern::Net::new(
-                &device_cfg,
-                self.get_sys_mem(),
-            )))
-        } else {
-            let device = Arc::new(Mutex::new(virtio::Net::new(device_cfg.clone())));
-            MigrationManager::register_device_instance_mutex(
-                VirtioNetState::descriptor(),
-                device.clone(),
-            );
-            device
-        };
+        let device: Arc<Mutex<dyn VirtioDevice>> =
+            if let Some(vhost_type) = device_cfg.vhost_type.as_ref() {
+                if vhost_type == "vhost-user" {
+                    Arc::new(Mutex::new(VhostUser::Net::new(
+                        &device_cfg,
+                        self.get_sys_mem(),
+                    )))
+                } else {
+                    let device = Arc::new(Mutex::new(VhostKern::Net::new(
+                        &device_cfg,
+                        self.get_sys_mem(),
+                    )));
+                    VhostKern::register_net_device(&device);
+                    device
+                }
+            } else {
+                let device = Arc::new(Mutex::new(virtio::Net::new(device_cfg.clone())));
+                virtio::register_net_device(&device);
+                MigrationManager::register_device_instance_mutex(
+                    VirtioNetState::descriptor(),
+                    device.clone(),
+                );
+                device
+            };
         self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func)?;
         self.reset_bus(&device_cfg.id)?;
         Ok(())
@@ -697,6 +963,12 @@ pub trait MachineOps {
                 "virtio-blk-pci" => {
                     self.add_virtio_pci_blk(vm_config, cfg_args)?;
                 }
+                "virtio-scsi-pci" => {
+                    self.add_virtio_scsi_cntlr(cfg_args)?;
+                }
+                "scsi-hd" | "scsi-cd" => {
+                    self.add_scsi_device(vm_config, cfg_args)?;
+                }
                 "virtio-net-device" => {
                     self.add_virtio_mmio_net(vm_config, cfg_args)?;
                 }
@@ -709,6 +981,12 @@ pub trait MachineOps {
                 "vhost-vsock-pci" | "vhost-vsock-device" => {
                     self.add_virtio_vsock(cfg_args)?;
                 }
+                "vhost-user-blk-pci" | "vhost-user-blk-device" => {
+                    self.add_vhost_user_blk(vm_config, cfg_args)?;
+                }
+                "vhost-user-fs-pci" | "vhost-user-fs-device" => {
+                    self.add_vhost_user_fs(vm_config, cfg_args)?;
+                }
                 "virtio-balloon-device" | "virtio-balloon-pci" => {
                     self.add_virtio_balloon(vm_config, cfg_args)?;
                 }
@@ -716,11 +994,17 @@ pub trait MachineOps {
                     self.add_virtio_serial(vm_config, cfg_args)?;
                 }
                 "virtconsole" => {
-                    self.add_virtio_console(vm_config, cfg_args)?;
+                    self.add_virtio_serial_port(vm_config, cfg_args, true)?;
+                }
+                "virtserialport" => {
+                    self.add_virtio_serial_port(vm_config, cfg_args, false)?;
                 }
                 "virtio-rng-device" | "virtio-rng-pci" => {
                     self.add_virtio_rng(vm_config, cfg_args)?;
                 }
+                "virtio-mem-pci" => {
+                    self.add_virtio_mem(vm_config, cfg_args)?;
+                }
                 "vfio-pci" => {
                     self.add_vfio_device(cfg_args)?;
                 }
@@ -856,6 +1140,92 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Start live migration to the destination in a separate thread, VM keeps
+    /// running until the dirty memory converges, then it will be paused.
+    ///
+    /// # Arguments
+    ///
+    /// * `path_type` - The type of migration uri, only `Tcp` and `Unix` are supported.
+    /// * `path` - The address of destination.
+    /// * `cpus` - Cpus vector restore cpu structure.
+    /// * `vm_state` - Vm kvm vm state.
+    fn start_live_migration(
+        path_type: UnixPath,
+        path: &str,
+        cpus: &[Arc<CPU>],
+        #[cfg(target_arch = "aarch64")] irq_chip: &Option<Arc<InterruptController>>,
+        vm_state: &Arc<(Mutex<KvmVmState>, Condvar)>,
+    ) -> Result<()>
+    where
+        Self: Sized + 'static,
+    {
+        let cpus = cpus.to_vec();
+        #[cfg(target_arch = "aarch64")]
+        let irq_chip = irq_chip.clone();
+        let vm_state = vm_state.clone();
+        // Whether the VM is paused by migration, it is resumed if migration fails.
+        let paused_vm = Arc::new(AtomicBool::new(false));
+        let pause_cpus = cpus.clone();
+        let pause_vm_state = vm_state.clone();
+        let paused = paused_vm.clone();
+        let pause_vm = move || {
+            let mut state = pause_vm_state.0.lock().unwrap();
+            match *state {
+                KvmVmState::Paused => {}
+                KvmVmState::Running => {
+                    if let Err(e) = <Self as MachineOps>::vm_pause(
+                        &pause_cpus,
+                        #[cfg(target_arch = "aarch64")]
+                        &irq_chip,
+                        &mut state,
+                    ) {
+                        error!("Failed to pause vm for migration: {}", e.display_chain());
+                        return false;
+                    }
+                    paused.store(true, Ordering::SeqCst);
+                    event!(Stop);
+                }
+                _ => return false,
+            }
+            drop(state);
+
+            if let Err(e) = quiesce_devices() {
+                error!("Failed to quiesce devices: {}", e.display_chain());
+                return false;
+            }
+            true
+        };
+        let resume_vm = move |failed: bool| {
+            EventLoop::resume_loops();
+            if !failed || !paused_vm.load(Ordering::SeqCst) {
+                return;
+            }
+            let mut state = vm_state.0.lock().unwrap();
+            if *state != KvmVmState::Paused {
+                return;
+            }
+            if let Err(e) = <Self as MachineOps>::vm_resume(&cpus, &mut state) {
+                error!("Failed to resume vm after migration: {}", e.display_chain());
+                return;
+            }
+            event!(Resume);
+        };
+
+        match path_type {
+            UnixPath::Tcp => {
+                let stream = TcpStream::connect(path)
+                    .chain_err(|| format!("Failed to connect to {}", path))?;
+                spawn_migration_thread(stream, pause_vm, resume_vm)
+            }
+            UnixPath::Unix => {
+                let stream = UnixStream::connect(path)
+                    .chain_err(|| format!("Failed to connect to {}", path))?;
+                spawn_migration_thread(stream, pause_vm, resume_vm)
+            }
+            _ => bail!("Unsupported live migration uri type."),
+        }
+    }
+
     /// Resume VM as `Running` state, awaken all vcpu thread.
     ///
     /// # Arguments
@@ -952,3 +1322,51 @@ pub trait MachineOps {
         Ok(())
     }
 }
+
+/// Send migration data through `stream` in a new thread named `migration`.
+///
+/// # Arguments
+///
+/// * `stream` - The stream connected with destination.
+/// * `pause_vm` - Callback to pause VM and quiesce devices before sending device state.
+/// * `resume_vm` - Callback to resume devices, and VM if migration fails.
+fn spawn_migration_thread<T, P, R>(mut stream: T, pause_vm: P, resume_vm: R) -> Result<()>
+where
+    T: Read + Write + Send + 'static,
+    P: FnOnce() -> bool + Send + 'static,
+    R: FnOnce(bool) + Send + 'static,
+{
+    std::thread::Builder::new()
+        .name("migration".to_string())
+        .spawn(move || {
+            if let Err(e) = MigrationManager::send_migration(&mut stream, pause_vm, resume_vm) {
+                error!("Failed to do live migration: {}", e.display_chain());
+                let _ = MigrationManager::set_status(MigrationStatus::Failed)
+                    .map_err(|e| error!("{}", e));
+            }
+        })
+        .chain_err(|| "Failed to create migration thread")?;
+
+    Ok(())
+}
+
+/// The max time to wait for the in-flight io requests of devices to complete.
+const QUIESCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Park all event loops once no io request of devices is in flight, so that
+/// devices stop writing guest memory until the loops are resumed.
+fn quiesce_devices() -> Result<()> {
+    let start = Instant::now();
+    loop {
+        EventLoop::pause_loops(QUIESCE_TIMEOUT)?;
+        if util::aio::inflight_requests() == 0 {
+            return Ok(());
+        }
+        // Let the loops handle the completions of in-flight requests.
+        EventLoop::resume_loops();
+        if start.elapsed() > QUIESCE_TIMEOUT {
+            bail!("Timeout to wait for in-flight io requests");
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Metrics of vCPUs and devices, shared by micro and standard machines to
+//! implement `MetricsInterface`.
+
+use std::sync::Arc;
+
+use cpu::CPU;
+use error_chain::ChainedError;
+use machine_manager::metrics::Metric;
+use virtio::{qmp_query_balloon, qmp_query_blockstats, qmp_query_netdev_stats, query_notify_stats};
+
+/// Collect the metrics of the vCPUs, virtio devices and balloon.
+pub(crate) fn collect_metrics(cpus: &[Arc<CPU>]) -> Vec<Metric> {
+    let mut metrics = vec![vcpu_metrics(cpus)];
+    metrics.append(&mut virtio_notify_metrics());
+    metrics.append(&mut block_metrics());
+    metrics.append(&mut net_metrics());
+    if let Some(actual) = qmp_query_balloon() {
+        let mut balloon = Metric::gauge(
+            "balloon_actual_bytes",
+            "Memory size of the guest after ballooning.",
+        );
+        balloon.add_sample(&[], actual);
+        metrics.push(balloon);
+    }
+    metrics
+}
+
+fn vcpu_metrics(cpus: &[Arc<CPU>]) -> Metric {
+    let mut exits = Metric::counter("vcpu_exits_total", "Number of vCPU exits from KVM.");
+    for cpu in cpus {
+        let cpu_id = cpu.id().to_string();
+        for (reason, count) in cpu.exit_counts() {
+            exits.add_sample(&[("cpu", &cpu_id), ("reason", reason.name())], count);
+        }
+    }
+    exits
+}
+
+fn virtio_notify_metrics() -> Vec<Metric> {
+    let mut notifies = Metric::counter(
+        "virtio_queue_notifies_total",
+        "Number of queue notifies from guest handled by the virtio device.",
+    );
+    let mut interrupts = Metric::counter(
+        "virtio_interrupts_total",
+        "Number of interrupts sent to guest by the virtio device.",
+    );
+    for (name, stats) in query_notify_stats() {
+        let labels = [("device", name.as_str())];
+        notifies.add_sample(&labels, stats.queue_notifies());
+        interrupts.add_sample(&labels, stats.interrupts());
+    }
+    vec![notifies, interrupts]
+}
+
+fn block_metrics() -> Vec<Metric> {
+    let mut bytes = Metric::counter("block_bytes_total", "Number of bytes of block operations.");
+    let mut ops = Metric::counter("block_operations_total", "Number of block operations.");
+    let mut time = Metric::counter(
+        "block_operation_time_ns_total",
+        "Total latency of block operations, in nanoseconds.",
+    );
+    let mut failed = Metric::counter(
+        "block_failed_operations_total",
+        "Number of failed block operations.",
+    );
+    for blk in qmp_query_blockstats() {
+        let device = blk.device.as_str();
+        let stats = &blk.stats;
+        for (op, value) in [
+            ("read", stats.rd_bytes),
+            ("write", stats.wr_bytes),
+            ("unmap", stats.unmap_bytes),
+        ] {
+            bytes.add_sample(&[("device", device), ("op", op)], value);
+        }
+        for (op, value) in [
+            ("read", stats.rd_operations),
+            ("write", stats.wr_operations),
+            ("flush", stats.flush_operations),
+            ("unmap", stats.unmap_operations),
+        ] {
+            ops.add_sample(&[("device", device), ("op", op)], value);
+        }
+        for (op, value) in [
+            ("read", stats.rd_total_time_ns),
+            ("write", stats.wr_total_time_ns),
+            ("flush", stats.flush_total_time_ns),
+            ("unmap", stats.unmap_total_time_ns),
+        ] {
+            time.add_sample(&[("device", device), ("op", op)], value);
+        }
+        for (op, value) in [
+            ("read", stats.failed_rd_operations),
+            ("write", stats.failed_wr_operations),
+            ("flush", stats.failed_flush_operations),
+            ("unmap", stats.failed_unmap_operations),
+        ] {
+            failed.add_sample(&[("device", device), ("op", op)], value);
+        }
+    }
+    vec![bytes, ops, time, failed]
+}
+
+fn net_metrics() -> Vec<Metric> {
+    let mut packets = Metric::counter("net_packets_total", "Number of packets of net devices.");
+    let mut bytes = Metric::counter("net_bytes_total", "Number of bytes of net devices.");
+    let mut dropped = Metric::counter(
+        "net_dropped_packets_total",
+        "Number of packets dropped by net devices.",
+    );
+    let mut errors = Metric::counter(
+        "net_errors_total",
+        "Number of failures of reading or writing the backends of net devices.",
+    );
+    let mut queue_full = Metric::counter(
+        "net_rx_queue_full_total",
+        "Times that receiving stops as the guest provides no receive buffer.",
+    );
+    let all_stats = match qmp_query_netdev_stats(None, false) {
+        Ok(all_stats) => all_stats,
+        Err(e) => {
+            error!(
+                "Failed to query stats of net devices: {}",
+                e.display_chain()
+            );
+            return Vec::new();
+        }
+    };
+    for net in all_stats {
+        let id = net.id.as_str();
+        let stats = &net.stats;
+        let rx = [("device", id), ("direction", "rx")];
+        let tx = [("device", id), ("direction", "tx")];
+        packets.add_sample(&rx, stats.rx_packets);
+        packets.add_sample(&tx, stats.tx_packets);
+        bytes.add_sample(&rx, stats.rx_bytes);
+        bytes.add_sample(&tx, stats.tx_bytes);
+        dropped.add_sample(&rx, stats.rx_dropped);
+        dropped.add_sample(&tx, stats.tx_dropped);
+        errors.add_sample(&rx, stats.rx_errors);
+        errors.add_sample(&tx, stats.tx_errors);
+        queue_full.add_sample(&[("device", id)], stats.rx_queue_full);
+    }
+    vec![packets, bytes, dropped, errors, queue_full]
+}
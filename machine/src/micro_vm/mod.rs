@@ -71,7 +71,7 @@ use cpu::{CPUBootConfig, CpuLifecycleState, CpuTopology, CPU};
 use devices::legacy::PL031;
 #[cfg(target_arch = "x86_64")]
 use devices::legacy::SERIAL_ADDR;
-use devices::legacy::{FwCfgOps, Serial};
+use devices::legacy::{qmp_ringbuf_read, qmp_ringbuf_write, FwCfgOps, Serial};
 #[cfg(target_arch = "aarch64")]
 use devices::{InterruptController, InterruptControllerConfig};
 use error_chain::ChainedError;
@@ -80,33 +80,45 @@ use hypervisor::kvm::KVM_FDS;
 use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
 use machine_manager::config::parse_blk;
 use machine_manager::config::parse_net;
-use machine_manager::config::BlkDevConfig;
+use machine_manager::config::{
+    get_blockdev_throttle, get_io_throttle_config, get_net_rate_limit_config,
+    get_netdev_rate_limit, parse_discard, BlkDevConfig, DiskFormat, WriteZeroesState,
+};
 use machine_manager::machine::{
-    DeviceInterface, KvmVmState, MachineAddressInterface, MachineExternalInterface,
-    MachineInterface, MachineLifecycle, MigrateInterface,
+    DeviceInterface, HumanMonitorInterface, KvmVmState, MachineAddressInterface,
+    MachineExternalInterface, MachineInterface, MachineLifecycle, MetricsInterface,
+    MigrateInterface,
 };
+use machine_manager::metrics::Metric;
 use machine_manager::{
-    config::{BootSource, ConfigCheck, NetworkInterfaceConfig, SerialConfig, VmConfig},
+    config::{
+        complete_mem_backend, BootSource, ConfigCheck, MemZoneConfig, NetworkInterfaceConfig,
+        SerialConfig, VmConfig,
+    },
     qmp::{qmp_schema, QmpChannel, Response},
 };
 use migration::{MigrationManager, MigrationStatus};
 use sysbus::SysBus;
 #[cfg(target_arch = "aarch64")]
 use sysbus::{SysBusDevType, SysRes};
+use util::aio::AioEngine;
 #[cfg(target_arch = "aarch64")]
 use util::device_tree::{self, CompileFDT, FdtBuilder};
 use util::loop_context::EventLoopManager;
 use util::seccomp::BpfRule;
 use util::set_termi_canon_mode;
 use virtio::{
-    create_tap, qmp_balloon, qmp_query_balloon, Block, BlockState, Net, VhostKern, VirtioDevice,
+    create_tap, hmp_info_network, qmp_balloon, qmp_block_set_io_throttle, qmp_net_set_rate_limit,
+    qmp_query_balloon, qmp_query_balloon_stats, qmp_query_block, qmp_query_blockstats,
+    qmp_query_named_block_nodes, qmp_query_netdev_stats, qmp_set_link, register_block_device,
+    register_net_device, Block, BlockState, Net, VhostKern, VhostUser, VirtioDevice,
     VirtioMmioDevice, VirtioMmioState, VirtioNetState,
 };
 use vmm_sys_util::eventfd::EventFd;
 
 use super::{
     errors::{ErrorKind as MachineErrorKind, Result as MachineResult},
-    MachineOps,
+    hmp, metrics, MachineOps,
 };
 use errors::{ErrorKind, Result};
 use mem_layout::{LayoutEntryType, MEM_LAYOUT};
@@ -182,6 +194,8 @@ pub struct LightMachine {
     boot_source: Arc<Mutex<BootSource>>,
     // VM power button, handle VM `Shutdown` event.
     power_button: EventFd,
+    // Memory backend which provides guest memory.
+    mem_backend: Option<MemZoneConfig>,
 }
 
 impl LightMachine {
@@ -223,8 +237,15 @@ impl LightMachine {
             error!("{}", e);
         }
 
+        if vm_config.machine_config.max_cpus != vm_config.machine_config.nr_cpus {
+            bail!("CPU hotplug is not supported by microvm");
+        }
+
         Ok(LightMachine {
-            cpu_topo: CpuTopology::new(vm_config.machine_config.nr_cpus),
+            cpu_topo: CpuTopology::new(
+                vm_config.machine_config.nr_cpus,
+                vm_config.machine_config.max_cpus,
+            ),
             cpus: Vec::new(),
             #[cfg(target_arch = "aarch64")]
             irq_chip: None,
@@ -236,6 +257,7 @@ impl LightMachine {
             boot_source: Arc::new(Mutex::new(vm_config.clone().boot_source)),
             vm_state,
             power_button,
+            mem_backend: complete_mem_backend(vm_config)?,
         })
     }
 
@@ -268,6 +290,7 @@ impl LightMachine {
             let block = Arc::new(Mutex::new(Block::default()));
             let virtio_mmio = VirtioMmioDevice::new(&self.sys_mem, block.clone());
             rpl_devs.push(virtio_mmio);
+            register_block_device(&block);
 
             MigrationManager::register_device_instance_mutex(BlockState::descriptor(), block);
         }
@@ -275,6 +298,7 @@ impl LightMachine {
             let net = Arc::new(Mutex::new(Net::default()));
             let virtio_mmio = VirtioMmioDevice::new(&self.sys_mem, net.clone());
             rpl_devs.push(virtio_mmio);
+            register_net_device(&net);
 
             MigrationManager::register_device_instance_mutex(VirtioNetState::descriptor(), net);
         }
@@ -506,6 +530,10 @@ impl MachineOps for LightMachine {
     }
 
     #[cfg(target_arch = "x86_64")]
+    fn get_mem_backend(&self) -> Option<&MemZoneConfig> {
+        self.mem_backend.as_ref()
+    }
+
     fn load_boot_source(
         &self,
         fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
@@ -651,8 +679,17 @@ impl MachineOps for LightMachine {
         cfg_args: &str,
     ) -> MachineResult<()> {
         let device_cfg = parse_net(vm_config, cfg_args)?;
-        if device_cfg.vhost_type.is_some() {
-            let net = Arc::new(Mutex::new(VhostKern::Net::new(&device_cfg, &self.sys_mem)));
+        if device_cfg.mq {
+            bail!("Multiqueue is not supported by virtio-net-device");
+        }
+        if let Some(vhost_type) = device_cfg.vhost_type.as_ref() {
+            let net: Arc<Mutex<dyn VirtioDevice>> = if vhost_type == "vhost-user" {
+                Arc::new(Mutex::new(VhostUser::Net::new(&device_cfg, &self.sys_mem)))
+            } else {
+                let net = Arc::new(Mutex::new(VhostKern::Net::new(&device_cfg, &self.sys_mem)));
+                VhostKern::register_net_device(&net);
+                net
+            };
             let device = VirtioMmioDevice::new(&self.sys_mem, net);
             self.realize_virtio_mmio_device(device)?;
         } else {
@@ -742,6 +779,8 @@ impl MachineOps for LightMachine {
         locked_vm.cpus.extend(<Self as MachineOps>::init_vcpu(
             vm.clone(),
             vm_config.machine_config.nr_cpus,
+            #[cfg(target_arch = "x86_64")]
+            vm_config.machine_config.max_cpus,
             &vcpu_fds,
             &boot_config,
         )?);
@@ -996,7 +1035,10 @@ impl DeviceInterface for LightMachine {
 
     fn query_balloon(&self) -> Response {
         if let Some(actual) = qmp_query_balloon() {
-            let ret = qmp_schema::BalloonInfo { actual };
+            let ret = qmp_schema::BalloonInfo {
+                actual,
+                guest_stats: qmp_query_balloon_stats(),
+            };
             return Response::create_response(serde_json::to_value(&ret).unwrap(), None);
         }
         Response::create_error_response(
@@ -1007,6 +1049,56 @@ impl DeviceInterface for LightMachine {
         )
     }
 
+    fn query_netdev_stats(&self, id: Option<String>, reset: Option<bool>) -> Response {
+        match qmp_query_netdev_stats(id.as_deref(), reset.unwrap_or(false)) {
+            Ok(stats) => Response::create_response(serde_json::to_value(stats).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn set_link(&self, name: String, up: bool) -> Response {
+        match qmp_set_link(&name, up) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn ringbuf_read(
+        &self,
+        device: String,
+        size: u64,
+        format: Option<qmp_schema::DataFormat>,
+    ) -> Response {
+        match qmp_ringbuf_read(&device, size as usize, format) {
+            Ok(data) => Response::create_response(serde_json::to_value(data).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn ringbuf_write(
+        &self,
+        device: String,
+        data: String,
+        format: Option<qmp_schema::DataFormat>,
+    ) -> Response {
+        match qmp_ringbuf_write(&device, &data, format) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
     fn device_add(&mut self, args: Box<qmp_schema::DeviceAddArgument>) -> Response {
         // get slot of bus by addr or lun
         let mut slot = 0;
@@ -1056,7 +1148,51 @@ impl DeviceInterface for LightMachine {
 
     fn blockdev_add(&self, args: Box<qmp_schema::BlockDevAddArgument>) -> Response {
         const MAX_STRING_LENGTH: usize = 255;
+        let format = match args
+            .driver
+            .as_deref()
+            .unwrap_or("raw")
+            .parse::<DiskFormat>()
+        {
+            Ok(format) => format,
+            Err(_) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!(
+                        "Unsupported block driver: {:?}",
+                        args.driver
+                    )),
+                    None,
+                );
+            }
+        };
+        let discard = match args.discard.as_deref().map(parse_discard).transpose() {
+            Ok(discard) => discard.unwrap_or(false),
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+        };
+        let write_zeroes = match args
+            .detect_zeroes
+            .as_deref()
+            .unwrap_or("off")
+            .parse::<WriteZeroesState>()
+        {
+            Ok(write_zeroes) => write_zeroes,
+            Err(_) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!(
+                        "Invalid detect-zeroes: {:?}",
+                        args.detect_zeroes
+                    )),
+                    None,
+                );
+            }
+        };
         let read_only = args.read_only.unwrap_or(false);
+        let throttle = get_blockdev_throttle(&args);
 
         let direct = if let Some(cache) = args.cache {
             match cache.direct {
@@ -1066,6 +1202,17 @@ impl DeviceInterface for LightMachine {
         } else {
             true
         };
+        let aio = match args.aio.as_deref().map(str::parse::<AioEngine>) {
+            Some(Ok(aio)) => aio,
+            Some(Err(_)) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!("Invalid aio: {:?}", args.aio)),
+                    None,
+                );
+            }
+            None if direct => AioEngine::Native,
+            None => AioEngine::Threads,
+        };
 
         let blk = Path::new(&args.file.filename);
         match metadata(blk) {
@@ -1109,13 +1256,26 @@ impl DeviceInterface for LightMachine {
 
         let config = BlkDevConfig {
             id: args.node_name.clone(),
+            node_name: args.node_name.clone(),
             path_on_host: args.file.filename,
             read_only,
             direct,
             serial_num: None,
             iothread: None,
-            iops: None,
+            throttle,
+            throttle_group: args.throttle_group,
+            format,
+            discard,
+            write_zeroes,
+            aio,
         };
+        if let Err(ref e) = config.check() {
+            error!("{}", e.display_chain());
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
         match self.add_replaceable_config(&args.node_name, Arc::new(config)) {
             Ok(()) => Response::create_empty_response(),
             Err(ref e) => {
@@ -1135,15 +1295,80 @@ impl DeviceInterface for LightMachine {
         )
     }
 
+    fn query_block(&self) -> Response {
+        Response::create_response(serde_json::to_value(qmp_query_block()).unwrap(), None)
+    }
+
+    fn query_named_block_nodes(&self) -> Response {
+        Response::create_response(
+            serde_json::to_value(qmp_query_named_block_nodes()).unwrap(),
+            None,
+        )
+    }
+
+    fn query_blockstats(&self) -> Response {
+        Response::create_response(serde_json::to_value(qmp_query_blockstats()).unwrap(), None)
+    }
+
+    fn block_set_io_throttle(&self, args: Box<qmp_schema::BlockIoThrottleArgument>) -> Response {
+        let name = match args.id.as_ref().or(args.device.as_ref()) {
+            Some(name) => name.clone(),
+            None => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(
+                        "Need the id of the block device".to_string(),
+                    ),
+                    None,
+                );
+            }
+        };
+        let result = get_io_throttle_config(&args)
+            .map_err(|e| e.to_string())
+            .and_then(|config| {
+                qmp_block_set_io_throttle(&name, config, args.group.clone())
+                    .map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => {
+                Response::create_error_response(qmp_schema::QmpErrorClass::GenericError(e), None)
+            }
+        }
+    }
+
     fn netdev_add(&mut self, args: Box<qmp_schema::NetDevAddArgument>) -> Response {
+        if args.net_type.as_deref() == Some("vhost-user") {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(
+                    "vhost-user netdev is not supported by hot-replaceable device".to_string(),
+                ),
+                None,
+            );
+        }
+
+        let rate_limit = match get_netdev_rate_limit(&args) {
+            Ok(rate_limit) => rate_limit,
+            Err(ref e) => {
+                error!("{}", e.display_chain());
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+        };
         let mut config = NetworkInterfaceConfig {
             id: args.id.clone(),
             host_dev_name: "".to_string(),
             mac: None,
-            tap_fd: None,
+            tap_fds: None,
             vhost_type: None,
             vhost_fd: None,
             iothread: None,
+            queue_iothreads: None,
+            queues: 1,
+            mq: false,
+            socket_path: None,
+            rate_limit,
         };
 
         if let Some(fds) = args.fds {
@@ -1155,7 +1380,7 @@ impl DeviceInterface for LightMachine {
             };
 
             if let Some(fd_num) = QmpChannel::get_fd(&netdev_fd) {
-                config.tap_fd = Some(fd_num);
+                config.tap_fds = Some(vec![fd_num]);
             } else {
                 // try to convert string to RawFd
                 let fd_num = match netdev_fd.parse::<i32>() {
@@ -1173,11 +1398,11 @@ impl DeviceInterface for LightMachine {
                         );
                     }
                 };
-                config.tap_fd = Some(fd_num);
+                config.tap_fds = Some(vec![fd_num]);
             }
         } else if let Some(if_name) = args.if_name {
             config.host_dev_name = if_name.clone();
-            if create_tap(None, Some(&if_name)).is_err() {
+            if create_tap(None, Some(&if_name), 1).is_err() {
                 return Response::create_error_response(
                     qmp_schema::QmpErrorClass::GenericError(
                         "Tap device already in use".to_string(),
@@ -1199,6 +1424,18 @@ impl DeviceInterface for LightMachine {
         }
     }
 
+    fn netdev_set_rate_limit(&self, args: Box<qmp_schema::NetRateLimitArgument>) -> Response {
+        let result = get_net_rate_limit_config(&args)
+            .map_err(|e| e.to_string())
+            .and_then(|config| qmp_net_set_rate_limit(&args.id, config).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => {
+                Response::create_error_response(qmp_schema::QmpErrorClass::GenericError(e), None)
+            }
+        }
+    }
+
     fn netdev_del(&mut self, _node_name: String) -> Response {
         Response::create_error_response(
             qmp_schema::QmpErrorClass::GenericError("netdev_del not support yet".to_string()),
@@ -1238,6 +1475,22 @@ impl MigrateInterface for LightMachine {
                     );
                 }
             }
+            Ok((path_type, path)) if path_type == UnixPath::Tcp || path_type == UnixPath::Unix => {
+                if let Err(e) = <Self as MachineOps>::start_live_migration(
+                    path_type,
+                    &path,
+                    &self.cpus,
+                    #[cfg(target_arch = "aarch64")]
+                    &self.irq_chip,
+                    &self.vm_state,
+                ) {
+                    error!("Failed to migrate to \'{}\': {}", path, e.display_chain());
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+            }
             _ => {
                 return Response::create_error_response(
                     qmp_schema::QmpErrorClass::GenericError(format!("Invalid uri: {}", uri)),
@@ -1259,6 +1512,47 @@ impl MigrateInterface for LightMachine {
     }
 }
 
+impl HumanMonitorInterface for LightMachine {
+    fn hmp_info_network(&self) -> String {
+        hmp_info_network()
+    }
+
+    fn hmp_info_pci(&self) -> String {
+        String::new()
+    }
+
+    fn hmp_info_mtree(&self) -> String {
+        hmp::info_mtree(
+            &self.sys_mem,
+            #[cfg(target_arch = "x86_64")]
+            &self.sys_io,
+        )
+    }
+
+    fn hmp_info_qtree(&self) -> String {
+        hmp::info_qtree(&self.sysbus, None)
+    }
+
+    fn hmp_info_registers(&self, cpu_index: usize) -> machine_manager::errors::Result<String> {
+        hmp::info_registers(&self.cpus, cpu_index)
+    }
+
+    fn hmp_read_memory(
+        &self,
+        addr: u64,
+        len: u64,
+        cpu_index: Option<usize>,
+    ) -> machine_manager::errors::Result<Vec<u8>> {
+        hmp::read_memory(&self.sys_mem, &self.cpus, addr, len, cpu_index)
+    }
+}
+
+impl MetricsInterface for LightMachine {
+    fn collect_metrics(&self) -> Vec<Metric> {
+        metrics::collect_metrics(&self.cpus)
+    }
+}
+
 impl MachineInterface for LightMachine {}
 impl MachineExternalInterface for LightMachine {}
 
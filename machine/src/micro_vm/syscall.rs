@@ -12,7 +12,7 @@
 
 use hypervisor::kvm::*;
 use util::seccomp::{BpfRule, SeccompCmpOpt};
-use util::tap::{TUNSETIFF, TUNSETOFFLOAD, TUNSETVNETHDRSZ};
+use util::tap::{TUNGETIFF, TUNSETIFF, TUNSETOFFLOAD, TUNSETVNETHDRSZ};
 use virtio::VhostKern::*;
 
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/include/uapi/linux/futex.h
@@ -42,14 +42,23 @@ const FIOCLEX: u32 = 0x5451;
 const FIONBIO: u32 = 0x5421;
 const KVM_RUN: u32 = 0xae80;
 
+/// See: https://elixir.bootlin.com/linux/v4.19.123/source/include/uapi/linux/prctl.h
+const PR_SET_NAME: u32 = 15;
+
+// Syscall `rseq` is not defined in libc crate yet.
+#[cfg(target_arch = "x86_64")]
+const SYS_RSEQ: i64 = 334;
+#[cfg(target_arch = "aarch64")]
+const SYS_RSEQ: i64 = 293;
+
 /// Create a syscall whitelist for seccomp.
 ///
 /// # Notes
 /// This allowlist limit syscall with:
-/// * x86_64-unknown-gnu: 43 syscalls
-/// * x86_64-unknown-musl: 43 syscalls
-/// * aarch64-unknown-gnu: 41 syscalls
-/// * aarch64-unknown-musl: 42 syscalls
+/// * x86_64-unknown-gnu: 58 syscalls
+/// * x86_64-unknown-musl: 57 syscalls
+/// * aarch64-unknown-gnu: 56 syscalls
+/// * aarch64-unknown-musl: 57 syscalls
 /// To reduce performance losses, the syscall rules is ordered by frequency.
 pub fn syscall_whitelist() -> Vec<BpfRule> {
     vec![
@@ -64,6 +73,7 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_epoll_wait),
         BpfRule::new(libc::SYS_io_getevents),
         BpfRule::new(libc::SYS_io_submit),
+        BpfRule::new(libc::SYS_io_uring_enter),
         BpfRule::new(libc::SYS_dup),
         BpfRule::new(libc::SYS_close),
         BpfRule::new(libc::SYS_eventfd2),
@@ -74,6 +84,8 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_recvfrom),
         BpfRule::new(libc::SYS_mremap),
         BpfRule::new(libc::SYS_io_setup),
+        BpfRule::new(libc::SYS_io_uring_setup),
+        BpfRule::new(libc::SYS_io_uring_register),
         BpfRule::new(libc::SYS_brk),
         BpfRule::new(libc::SYS_fcntl)
             .add_constraint(SeccompCmpOpt::Eq, 1, F_DUPFD_CLOEXEC)
@@ -85,6 +97,7 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_openat),
         BpfRule::new(libc::SYS_sigaltstack),
         BpfRule::new(libc::SYS_mmap),
+        BpfRule::new(libc::SYS_mprotect),
         BpfRule::new(libc::SYS_munmap),
         BpfRule::new(libc::SYS_accept4),
         BpfRule::new(libc::SYS_lseek),
@@ -106,6 +119,7 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_fstat),
         BpfRule::new(libc::SYS_pread64),
         BpfRule::new(libc::SYS_pwrite64),
+        BpfRule::new(libc::SYS_fallocate),
         BpfRule::new(libc::SYS_statx),
         #[cfg(all(target_env = "musl", target_arch = "x86_64"))]
         BpfRule::new(libc::SYS_stat),
@@ -118,12 +132,46 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         #[cfg(target_arch = "aarch64")]
         BpfRule::new(libc::SYS_unlinkat),
         #[cfg(target_arch = "x86_64")]
+        BpfRule::new(libc::SYS_rename),
+        #[cfg(target_arch = "aarch64")]
+        BpfRule::new(libc::SYS_renameat),
+        #[cfg(target_arch = "x86_64")]
         BpfRule::new(libc::SYS_mkdir),
         #[cfg(target_arch = "aarch64")]
         BpfRule::new(libc::SYS_mkdirat),
         BpfRule::new(libc::SYS_madvise)
             .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
             .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32),
+        // QMP command `migrate` connects to the destination.
+        BpfRule::new(libc::SYS_socket),
+        BpfRule::new(libc::SYS_connect),
+        // Vhost-user devices reconnect to the slave and set the timeouts of the
+        // socket after seccomp rules registered.
+        BpfRule::new(libc::SYS_setsockopt)
+            .add_constraints(&[
+                (SeccompCmpOpt::Eq, 1, libc::SOL_SOCKET as u32),
+                (SeccompCmpOpt::Eq, 2, libc::SO_RCVTIMEO as u32),
+            ])
+            .add_constraints(&[
+                (SeccompCmpOpt::Eq, 1, libc::SOL_SOCKET as u32),
+                (SeccompCmpOpt::Eq, 2, libc::SO_SNDTIMEO as u32),
+            ]),
+        // Socket chardevs get the result of the nonblocking connection when
+        // reconnecting to the server.
+        BpfRule::new(libc::SYS_getsockopt).add_constraints(&[
+            (SeccompCmpOpt::Eq, 1, libc::SOL_SOCKET as u32),
+            (SeccompCmpOpt::Eq, 2, libc::SO_ERROR as u32),
+        ]),
+        // Threads created after seccomp rules registered, e.g. the migration
+        // thread and aio workers of devices activated by guest, need the
+        // syscalls below and `mprotect` for the guard page of thread stack.
+        BpfRule::new(libc::SYS_clone),
+        #[cfg(target_env = "gnu")]
+        BpfRule::new(libc::SYS_clone3),
+        BpfRule::new(libc::SYS_set_robust_list),
+        BpfRule::new(SYS_RSEQ),
+        // Set thread name.
+        BpfRule::new(libc::SYS_prctl).add_constraint(SeccompCmpOpt::Eq, 0, PR_SET_NAME),
     ]
 }
 
@@ -137,6 +185,9 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, FIONBIO)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_RUN)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_DEVICE_ATTR)
+        // Log dirty pages of memory slots in live migration.
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_USER_MEMORY_REGION)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DIRTY_LOG() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_VSOCK_SET_GUEST_CID() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_VSOCK_SET_RUNNING() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_SET_VRING_CALL() as u32)
@@ -150,6 +201,7 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_SET_MEM_TABLE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_NET_SET_BACKEND() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETIFF() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, TUNGETIFF() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETOFFLOAD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETVNETHDRSZ() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_API_VERSION() as u32)
@@ -14,9 +14,14 @@ mod pci_host_root;
 mod syscall;
 
 use std::fs::OpenOptions;
+use std::mem::size_of;
 use std::ops::Deref;
 use std::sync::{Arc, Condvar, Mutex};
 
+use acpi::{
+    AcpiSratGiccAffinity, AcpiTable, AmlBuilder, TableLoader, ACPI_TABLE_FILE,
+    TABLE_CHECKSUM_OFFSET,
+};
 use address_space::{AddressSpace, GuestAddress, Region};
 use boot_loader::{load_linux, BootLoaderConfig};
 use cpu::{CPUBootConfig, CPUInterface, CpuTopology, CPU};
@@ -26,11 +31,15 @@ use devices::legacy::{
 use devices::{InterruptController, InterruptControllerConfig};
 use error_chain::ChainedError;
 use hypervisor::kvm::KVM_FDS;
-use machine_manager::config::{BootSource, PFlashConfig, SerialConfig, VmConfig};
+use machine_manager::config::{
+    complete_mem_backend, complete_numa_nodes, BootSource, MemZoneConfig, NumaNodes, PFlashConfig,
+    SerialConfig, VmConfig,
+};
 use machine_manager::machine::{
-    KvmVmState, MachineAddressInterface, MachineExternalInterface, MachineInterface,
-    MachineLifecycle, MigrateInterface,
+    HumanMonitorInterface, KvmVmState, MachineAddressInterface, MachineExternalInterface,
+    MachineInterface, MachineLifecycle, MetricsInterface, MigrateInterface,
 };
+use machine_manager::metrics::Metric;
 use machine_manager::qmp::{qmp_schema, QmpChannel, Response};
 use migration::{MigrationManager, MigrationStatus};
 use pci::{PciDevOps, PciHost};
@@ -42,11 +51,11 @@ use util::seccomp::BpfRule;
 use util::set_termi_canon_mode;
 use vmm_sys_util::eventfd::EventFd;
 
-use super::{errors::Result as StdResult, AcpiBuilder, StdMachineOps};
+use super::{build_srat_mem_affinity, errors::Result as StdResult, AcpiBuilder, StdMachineOps};
 use crate::errors::{ErrorKind, Result};
-use crate::MachineOps;
+use crate::{hmp, metrics, numa_ram_ranges, MachineOps};
 use pci_host_root::PciHostRoot;
-use syscall::syscall_whitelist;
+pub(super) use syscall::syscall_whitelist;
 
 /// The type of memory layout entry on aarch64
 #[allow(dead_code)]
@@ -111,6 +120,10 @@ pub struct StdMachine {
     /// VM power button, handle VM `Shutdown` event.
     power_button: EventFd,
     vm_config: Mutex<VmConfig>,
+    /// Guest numa nodes.
+    numa_nodes: Option<NumaNodes>,
+    /// Memory backend which provides guest memory.
+    mem_backend: Option<MemZoneConfig>,
     /// Reset request, handle VM `Reset` event.
     reset_req: EventFd,
     /// Device Tree Blob.
@@ -121,7 +134,13 @@ impl StdMachine {
     pub fn new(vm_config: &VmConfig) -> Result<Self> {
         use crate::errors::ResultExt;
 
-        let cpu_topo = CpuTopology::new(vm_config.machine_config.nr_cpus);
+        if vm_config.machine_config.max_cpus != vm_config.machine_config.nr_cpus {
+            bail!("CPU hotplug is not supported by standard VM on aarch64");
+        }
+        let cpu_topo = CpuTopology::new(
+            vm_config.machine_config.nr_cpus,
+            vm_config.machine_config.max_cpus,
+        );
         let sys_mem = AddressSpace::new(Region::init_container_region(u64::max_value()))
             .chain_err(|| ErrorKind::CrtIoSpaceErr)?;
         let sysbus = SysBus::new(
@@ -149,6 +168,10 @@ impl StdMachine {
             power_button: EventFd::new(libc::EFD_NONBLOCK)
                 .chain_err(|| ErrorKind::InitEventFdErr("power_button".to_string()))?,
             vm_config: Mutex::new(vm_config.clone()),
+            numa_nodes: complete_numa_nodes(vm_config)
+                .chain_err(|| "Failed to complete numa nodes")?,
+            mem_backend: complete_mem_backend(vm_config)
+                .chain_err(|| "Failed to complete memory backend")?,
             reset_req: EventFd::new(libc::EFD_NONBLOCK)
                 .chain_err(|| ErrorKind::InitEventFdErr("reset_req".to_string()))?,
             dtb_vec: Vec::new(),
@@ -329,6 +352,24 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn get_numa_nodes(&self) -> Option<&NumaNodes> {
+        self.numa_nodes.as_ref()
+    }
+
+    fn get_mem_backend(&self) -> Option<&MemZoneConfig> {
+        self.mem_backend.as_ref()
+    }
+
+    fn get_device_mem_range(&self) -> Option<(u64, u64)> {
+        // Hotpluggable memory locates between the guest ram and the high mmio regions.
+        let base = self.sys_mem.memory_end_address().raw_value();
+        let end = MEM_LAYOUT[LayoutEntryType::HighGicRedist as usize].0;
+        if base >= end {
+            return None;
+        }
+        Some((base, end - base))
+    }
+
     fn load_boot_source(&self, fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>) -> Result<CPUBootConfig> {
         use crate::errors::ResultExt;
 
@@ -515,7 +556,60 @@ impl MachineOps for StdMachine {
     }
 }
 
-impl AcpiBuilder for StdMachine {}
+impl AcpiBuilder for StdMachine {
+    fn build_srat_table(
+        &self,
+        acpi_data: &Arc<Mutex<Vec<u8>>>,
+        loader: &mut TableLoader,
+    ) -> super::errors::Result<u64> {
+        let numa_nodes = match &self.numa_nodes {
+            Some(numa_nodes) => numa_nodes,
+            None => bail!("No numa nodes configured"),
+        };
+        let mut srat = AcpiTable::new(*b"SRAT", 3, *b"STRATO", *b"VIRTSRAT", 1);
+        // Reserved, set to 1 for backward compatibility.
+        srat.append_child(&[1_u8; 4_usize]);
+        // Reserved
+        srat.append_child(&[0_u8; 8_usize]);
+
+        for (id, node) in numa_nodes.iter() {
+            for cpu in node.cpus.iter() {
+                let gicc_affinity = AcpiSratGiccAffinity {
+                    type_id: 3,
+                    length: size_of::<AcpiSratGiccAffinity>() as u8,
+                    proximity_domain: *id,
+                    processor_uid: u32::from(*cpu),
+                    flags: 1, // Flags: enabled.
+                    clock_domain: 0,
+                };
+                srat.append_child(&gicc_affinity.aml_bytes());
+            }
+        }
+        let mem_size = self
+            .vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .mem_config
+            .mem_size;
+        build_srat_mem_affinity(&self.arch_ram_ranges(mem_size), numa_nodes, &mut srat);
+
+        let mut locked_acpi_data = acpi_data.lock().unwrap();
+        let srat_begin = locked_acpi_data.len() as u32;
+        locked_acpi_data.extend(srat.aml_bytes());
+        let srat_end = locked_acpi_data.len() as u32;
+        drop(locked_acpi_data);
+
+        loader.add_cksum_entry(
+            ACPI_TABLE_FILE,
+            srat_begin + TABLE_CHECKSUM_OFFSET,
+            srat_begin,
+            srat_end - srat_begin,
+        )?;
+
+        Ok(srat_begin as u64)
+    }
+}
 
 impl MachineLifecycle for StdMachine {
     fn pause(&self) -> bool {
@@ -601,6 +695,22 @@ impl MigrateInterface for StdMachine {
                     );
                 }
             }
+            Ok((path_type, path)) if path_type == UnixPath::Tcp || path_type == UnixPath::Unix => {
+                if let Err(e) = <Self as MachineOps>::start_live_migration(
+                    path_type,
+                    &path,
+                    &self.cpus,
+                    #[cfg(target_arch = "aarch64")]
+                    &self.irq_chip,
+                    &self.vm_state,
+                ) {
+                    error!("Failed to migrate to \'{}\': {}", path, e.display_chain());
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+            }
             _ => {
                 return Response::create_error_response(
                     qmp_schema::QmpErrorClass::GenericError(format!("Invalid uri: {}", uri)),
@@ -622,6 +732,43 @@ impl MigrateInterface for StdMachine {
     }
 }
 
+impl HumanMonitorInterface for StdMachine {
+    fn hmp_info_network(&self) -> String {
+        virtio::hmp_info_network()
+    }
+
+    fn hmp_info_pci(&self) -> String {
+        hmp::info_pci(&self.pci_host)
+    }
+
+    fn hmp_info_mtree(&self) -> String {
+        hmp::info_mtree(&self.sys_mem)
+    }
+
+    fn hmp_info_qtree(&self) -> String {
+        hmp::info_qtree(&self.sysbus, Some(&self.pci_host))
+    }
+
+    fn hmp_info_registers(&self, cpu_index: usize) -> machine_manager::errors::Result<String> {
+        hmp::info_registers(&self.cpus, cpu_index)
+    }
+
+    fn hmp_read_memory(
+        &self,
+        addr: u64,
+        len: u64,
+        cpu_index: Option<usize>,
+    ) -> machine_manager::errors::Result<Vec<u8>> {
+        hmp::read_memory(&self.sys_mem, &self.cpus, addr, len, cpu_index)
+    }
+}
+
+impl MetricsInterface for StdMachine {
+    fn collect_metrics(&self) -> Vec<Metric> {
+        metrics::collect_metrics(&self.cpus)
+    }
+}
+
 impl MachineInterface for StdMachine {}
 impl MachineExternalInterface for StdMachine {}
 
@@ -901,6 +1048,15 @@ impl CompileFDTHelper for StdMachine {
                 fdt.set_property_string("enable-method", "psci")?;
             }
             fdt.set_property_u64("reg", mpidr & 0x007F_FFFF)?;
+            if let Some(numa_nodes) = &self.numa_nodes {
+                if let Some(id) = numa_nodes
+                    .iter()
+                    .find(|(_, node)| node.cpus.contains(&cpu_index))
+                    .map(|(id, _)| *id)
+                {
+                    fdt.set_property_u32("numa-node-id", id)?;
+                }
+            }
             fdt.end_node(mpidr_node_dep)?;
         }
 
@@ -910,6 +1066,42 @@ impl CompileFDTHelper for StdMachine {
     }
 
     fn generate_memory_node(&self, fdt: &mut FdtBuilder) -> util::errors::Result<()> {
+        if let Some(numa_nodes) = &self.numa_nodes {
+            let mem_size = self
+                .vm_config
+                .lock()
+                .unwrap()
+                .machine_config
+                .mem_config
+                .mem_size;
+            let ram_ranges = self.arch_ram_ranges(mem_size);
+            for (id, node_ranges) in numa_ram_ranges(&ram_ranges, numa_nodes) {
+                for (base, size) in node_ranges {
+                    let node = format!("memory@{:x}", base);
+                    let memory_node_dep = fdt.begin_node(&node)?;
+                    fdt.set_property_string("device_type", "memory")?;
+                    fdt.set_property_array_u64("reg", &[base, size])?;
+                    fdt.set_property_u32("numa-node-id", id)?;
+                    fdt.end_node(memory_node_dep)?;
+                }
+            }
+
+            let mut distance_matrix = Vec::new();
+            for (source, node) in numa_nodes.iter() {
+                for (destination, distance) in node.distances.iter() {
+                    distance_matrix.push(*source);
+                    distance_matrix.push(*destination);
+                    distance_matrix.push(u32::from(*distance));
+                }
+            }
+            let distance_map_node_dep = fdt.begin_node("distance-map")?;
+            fdt.set_property_string("compatible", "numa-distance-map-v1")?;
+            fdt.set_property_array_u32("distance-matrix", &distance_matrix)?;
+            fdt.end_node(distance_map_node_dep)?;
+
+            return Ok(());
+        }
+
         let mem_base = MEM_LAYOUT[LayoutEntryType::Mem as usize].0;
         let mem_size = self.sys_mem.memory_end_address().raw_value()
             - MEM_LAYOUT[LayoutEntryType::Mem as usize].0;
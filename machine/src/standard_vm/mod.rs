@@ -34,6 +34,7 @@ pub mod errors {
             Legacy(devices::LegacyErrs::Error, devices::LegacyErrs::ErrorKind);
             PciErr(pci::errors::Error, pci::errors::ErrorKind);
             Acpi(acpi::errors::Error, acpi::errors::ErrorKind);
+            SysBus(sysbus::errors::Error, sysbus::errors::ErrorKind);
             MachineManager(machine_manager::config::errors::Error, machine_manager::config::errors::ErrorKind);
         }
         foreign_links{
@@ -63,27 +64,37 @@ use std::os::unix::prelude::AsRawFd;
 use std::sync::{Arc, Condvar, Mutex};
 
 use crate::errors::Result as MachineResult;
-use crate::MachineOps;
+use crate::{numa_ram_ranges, MachineOps};
 #[cfg(target_arch = "x86_64")]
 use acpi::AcpiGenericAddress;
 use acpi::{
-    AcpiRsdp, AcpiTable, AmlBuilder, TableLoader, ACPI_RSDP_FILE, ACPI_TABLE_FILE,
-    ACPI_TABLE_LOADER_FILE, TABLE_CHECKSUM_OFFSET,
+    AcpiRsdp, AcpiSratMemoryAffinity, AcpiTable, AmlBuilder, TableLoader, ACPI_RSDP_FILE,
+    ACPI_TABLE_FILE, ACPI_TABLE_LOADER_FILE, TABLE_CHECKSUM_OFFSET,
 };
 use cpu::{CpuTopology, CPU};
-use devices::legacy::FwCfgOps;
+use devices::legacy::{qmp_ringbuf_read, qmp_ringbuf_write, FwCfgOps};
 use error_chain::ChainedError;
 use errors::{Result, ResultExt};
 use machine_manager::config::{
-    get_netdev_config, get_pci_df, BlkDevConfig, ConfigCheck, DriveConfig, NetworkInterfaceConfig,
-    PciBdf, VmConfig,
+    get_blockdev_throttle, get_chardev_socket_path, get_io_throttle_config,
+    get_net_rate_limit_config, get_netdev_config, get_pci_df, get_scsi_cntlr_id, parse_discard,
+    set_scsi_drive, split_iothreads, BlkDevConfig, ConfigCheck, DiskFormat, DriveConfig, ExBool,
+    NetworkInterfaceConfig, NumaNodes, PciBdf, ScsiCntlrConfig, ScsiDevConfig, ScsiDevType,
+    VhostUserBlkDevConfig, VmConfig, WriteZeroesState,
 };
 use machine_manager::machine::{DeviceInterface, KvmVmState};
 use machine_manager::qmp::{qmp_schema, QmpChannel, Response};
 use pci::hotplug::{handle_plug, handle_unplug_request};
 use pci::PciBus;
+use util::aio::AioEngine;
 use util::byte_code::ByteCode;
-use virtio::{qmp_balloon, qmp_query_balloon, Block, VhostKern, VirtioDevice};
+use virtio::{
+    qmp_balloon, qmp_block_set_io_throttle, qmp_net_set_rate_limit, qmp_query_balloon,
+    qmp_query_balloon_stats, qmp_query_block, qmp_query_blockstats, qmp_query_named_block_nodes,
+    qmp_query_netdev_stats, qmp_set_link, qmp_set_requested_size, register_block_device,
+    register_net_device, register_scsi_cntlr, scsi_attach_device, scsi_detach_device,
+    scsi_device_existed, Block, ScsiCntlr, VhostKern, VhostUser, VirtioDevice,
+};
 
 #[cfg(target_arch = "aarch64")]
 use aarch64::{LayoutEntryType, MEM_LAYOUT};
@@ -93,6 +104,12 @@ use x86_64::{LayoutEntryType, MEM_LAYOUT};
 #[cfg(target_arch = "x86_64")]
 use self::x86_64::ich9_lpc::{PM_CTRL_OFFSET, PM_EVENT_OFFSET, RST_CTRL_OFFSET, SLEEP_CTRL_OFFSET};
 
+/// Type of the hotpluggable vcpu.
+#[cfg(target_arch = "x86_64")]
+const CPU_TYPE: &str = "host-x86-cpu";
+#[cfg(target_arch = "aarch64")]
+const CPU_TYPE: &str = "host-aarch64-cpu";
+
 trait StdMachineOps: AcpiBuilder {
     fn init_pci_host(&self) -> Result<()>;
 
@@ -103,7 +120,7 @@ trait StdMachineOps: AcpiBuilder {
     /// `fw_cfg` - FwCfgOps trait object.
     fn build_acpi_tables(&self, fw_cfg: &Arc<Mutex<dyn FwCfgOps>>) -> Result<()>
     where
-        Self: Sized,
+        Self: Sized + MachineOps,
     {
         let mut loader = TableLoader::new();
         let acpi_tables = Arc::new(Mutex::new(Vec::new()));
@@ -134,6 +151,17 @@ trait StdMachineOps: AcpiBuilder {
             .chain_err(|| "Failed to build ACPI MCFG table")?;
         xsdt_entries.push(mcfg_addr);
 
+        if let Some(numa_nodes) = self.get_numa_nodes() {
+            let srat_addr = self
+                .build_srat_table(&acpi_tables, &mut loader)
+                .chain_err(|| "Failed to build ACPI SRAT table")?;
+            xsdt_entries.push(srat_addr);
+
+            let slit_addr = Self::build_slit_table(numa_nodes, &acpi_tables, &mut loader)
+                .chain_err(|| "Failed to build ACPI SLIT table")?;
+            xsdt_entries.push(slit_addr);
+        }
+
         let xsdt_addr = Self::build_xsdt_table(&acpi_tables, &mut loader, xsdt_entries)?;
 
         let mut locked_fw_cfg = fw_cfg.lock().unwrap();
@@ -166,6 +194,26 @@ trait StdMachineOps: AcpiBuilder {
 
     fn get_vm_config(&self) -> &Mutex<VmConfig>;
 
+    /// Hotplug vcpu `vcpu_id` as device `id`.
+    fn plug_cpu(&mut self, _id: &str, _vcpu_id: u8) -> Result<()> {
+        bail!("CPU hotplug is not supported");
+    }
+
+    /// Request guest to eject the hotplugged vcpu `vcpu_id`.
+    fn unplug_cpu_request(&mut self, _vcpu_id: u8) -> Result<()> {
+        bail!("CPU hotplug is not supported");
+    }
+
+    /// Get the vcpu id of hotplugged vcpu device `id`.
+    fn get_hotplugged_cpu(&self, _id: &str) -> Option<u8> {
+        None
+    }
+
+    /// Get the device id of hotplugged vcpu `vcpu_id`.
+    fn get_hotplugged_cpu_id(&self, _vcpu_id: u8) -> Option<String> {
+        None
+    }
+
     /// Register event notifier for reset of standard machine.
     ///
     /// # Arguments
@@ -235,6 +283,67 @@ trait StdMachineOps: AcpiBuilder {
             .chain_err(|| "Failed to register event notifier.")?;
         Ok(())
     }
+
+    /// Register event notifier for vcpu ejection of standard machine.
+    ///
+    /// # Arguments
+    ///
+    /// * `eject_req` - Eventfd of the eject request.
+    /// * `clone_vm` - Reference of the StdMachine.
+    #[cfg(target_arch = "x86_64")]
+    fn register_cpu_eject_event(
+        &self,
+        eject_req: &EventFd,
+        clone_vm: Arc<Mutex<StdMachine>>,
+    ) -> MachineResult<()> {
+        let eject_req = eject_req.try_clone().unwrap();
+        let eject_req_fd = eject_req.as_raw_fd();
+        #[allow(clippy::arc_with_non_send_sync)]
+        let eject_req_handler: Arc<Mutex<Box<NotifierCallback>>> =
+            Arc::new(Mutex::new(Box::new(move |_, _| {
+                let _ret = eject_req.read().unwrap();
+                StdMachine::handle_cpu_eject_request(&clone_vm);
+                None
+            })));
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            eject_req_fd,
+            None,
+            EventSet::IN,
+            vec![eject_req_handler],
+        );
+        EventLoop::update_event(vec![notifier], None)
+            .chain_err(|| "Failed to register event notifier.")?;
+        Ok(())
+    }
+}
+
+/// Append the SRAT memory affinity structures of numa nodes to `srat`.
+///
+/// # Arguments
+///
+/// * `ram_ranges` - Guest ram ranges, element represents (start_addr, size).
+/// * `numa_nodes` - Guest numa nodes.
+/// * `srat` - ACPI SRAT table.
+fn build_srat_mem_affinity(
+    ram_ranges: &[(u64, u64)],
+    numa_nodes: &NumaNodes,
+    srat: &mut AcpiTable,
+) {
+    for (id, node_ranges) in numa_ram_ranges(ram_ranges, numa_nodes) {
+        for (base_addr, range_length) in node_ranges {
+            let mem_affinity = AcpiSratMemoryAffinity {
+                type_id: 1,
+                length: size_of::<AcpiSratMemoryAffinity>() as u8,
+                proximity_domain: id,
+                base_addr,
+                range_length,
+                flags: 1, // Flags: enabled.
+                ..Default::default()
+            };
+            srat.append_child(&mem_affinity.aml_bytes());
+        }
+    }
 }
 
 /// Trait that helps to build ACPI tables.
@@ -311,6 +420,61 @@ trait AcpiBuilder {
         Ok(mcfg_begin as u64)
     }
 
+    /// Build ACPI SRAT table, returns the offset of ACPI SRAT table in `acpi_data`.
+    ///
+    /// # Arguments
+    ///
+    /// `acpi_data` - Bytes streams that ACPI tables converts to.
+    /// `loader` - ACPI table loader.
+    fn build_srat_table(
+        &self,
+        _acpi_data: &Arc<Mutex<Vec<u8>>>,
+        _loader: &mut TableLoader,
+    ) -> Result<u64> {
+        bail!("Not implemented");
+    }
+
+    /// Build ACPI SLIT table, returns the offset of ACPI SLIT table in `acpi_data`.
+    ///
+    /// # Arguments
+    ///
+    /// `numa_nodes` - Guest numa nodes.
+    /// `acpi_data` - Bytes streams that ACPI tables converts to.
+    /// `loader` - ACPI table loader.
+    fn build_slit_table(
+        numa_nodes: &NumaNodes,
+        acpi_data: &Arc<Mutex<Vec<u8>>>,
+        loader: &mut TableLoader,
+    ) -> Result<u64>
+    where
+        Self: Sized,
+    {
+        let mut slit = AcpiTable::new(*b"SLIT", 1, *b"STRATO", *b"VIRTSLIT", 1);
+
+        // Number of System Localities
+        slit.append_child((numa_nodes.len() as u64).as_bytes());
+        // Relative distances between every two system localities.
+        for node in numa_nodes.values() {
+            for distance in node.distances.values() {
+                slit.append_child(distance.as_bytes());
+            }
+        }
+
+        let mut locked_acpi_data = acpi_data.lock().unwrap();
+        let slit_begin = locked_acpi_data.len() as u32;
+        locked_acpi_data.extend(slit.aml_bytes());
+        let slit_end = locked_acpi_data.len() as u32;
+        drop(locked_acpi_data);
+
+        loader.add_cksum_entry(
+            ACPI_TABLE_FILE,
+            slit_begin + TABLE_CHECKSUM_OFFSET,
+            slit_begin,
+            slit_end - slit_begin,
+        )?;
+        Ok(slit_begin as u64)
+    }
+
     /// Build ACPI FADT table, returns the offset of ACPI FADT table in `acpi_data`.
     ///
     /// # Arguments
@@ -549,24 +713,115 @@ impl StdMachine {
 
         let blk = if let Some(conf) = self.get_vm_config().lock().unwrap().drives.get(drive) {
             let dev = BlkDevConfig {
-                id: conf.id.clone(),
+                id: args.id.clone(),
+                node_name: conf.id.clone(),
                 path_on_host: conf.path_on_host.clone(),
                 read_only: conf.read_only,
                 direct: conf.direct,
                 serial_num: args.serial_num.clone(),
                 iothread: args.iothread.clone(),
-                iops: conf.iops,
+                throttle: conf.throttle,
+                throttle_group: conf.throttle_group.clone(),
+                format: conf.format,
+                discard: conf.discard,
+                write_zeroes: conf.write_zeroes,
+                aio: conf.aio,
             };
             dev.check()?;
             Arc::new(Mutex::new(Block::new(dev)))
         } else {
             bail!("Drive not found");
         };
+        register_block_device(&blk);
 
         self.add_virtio_pci_device(&args.id, pci_bdf, blk, multifunction)
             .chain_err(|| "Failed to add virtio pci block device")
     }
 
+    fn plug_virtio_scsi_cntlr(
+        &mut self,
+        pci_bdf: &PciBdf,
+        args: &qmp_schema::DeviceAddArgument,
+    ) -> Result<()> {
+        let multifunction = args.multifunction.unwrap_or(false);
+        let dev = ScsiCntlrConfig {
+            id: args.id.clone(),
+            iothread: args.iothread.clone(),
+            num_queues: args.num_queues.unwrap_or(1),
+        };
+        dev.check()?;
+        let cntlr = Arc::new(Mutex::new(ScsiCntlr::new(dev)));
+        register_scsi_cntlr(&cntlr);
+
+        self.add_virtio_pci_device(&args.id, pci_bdf, cntlr, multifunction)
+            .chain_err(|| "Failed to add virtio scsi controller")
+    }
+
+    fn plug_scsi_device(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let cntlr = if let Some(bus) = &args.bus {
+            get_scsi_cntlr_id(bus)?
+        } else {
+            bail!("Bus not set");
+        };
+        let drive = if let Some(drv) = &args.drive {
+            drv
+        } else {
+            bail!("Drive not set");
+        };
+        let lun = match args.lun {
+            Some(lun) if lun > u16::MAX as usize => bail!("Invalid lun {}", lun),
+            lun => lun.unwrap_or(0) as u16,
+        };
+
+        let mut dev = ScsiDevConfig {
+            id: args.id.clone(),
+            dev_type: if args.driver == "scsi-cd" {
+                ScsiDevType::CdRom
+            } else {
+                ScsiDevType::Disk
+            },
+            cntlr,
+            channel: args.channel.unwrap_or(0),
+            target: args.scsi_id.unwrap_or(0),
+            lun,
+            serial_num: args.serial_num.clone(),
+            ..Default::default()
+        };
+        dev.check()?;
+        if let Some(conf) = self.get_vm_config().lock().unwrap().drives.get(drive) {
+            set_scsi_drive(&mut dev, conf)?;
+        } else {
+            bail!("Drive not found");
+        }
+
+        scsi_attach_device(&dev).chain_err(|| "Failed to attach scsi device")
+    }
+
+    fn plug_cpu_device(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let cpu_topo = self.get_cpu_topo();
+        let socket_id = if let Some(socket_id) = args.socket_id {
+            socket_id
+        } else {
+            bail!("Socket id not set");
+        };
+        let core_id = args.core_id.unwrap_or(0);
+        let thread_id = args.thread_id.unwrap_or(0);
+        if socket_id >= cpu_topo.sockets || core_id >= cpu_topo.cores {
+            bail!("Invalid socket id {} or core id {}", socket_id, core_id);
+        }
+        if thread_id >= cpu_topo.threads {
+            bail!("Invalid thread id {}", thread_id);
+        }
+        let vcpu_id = (socket_id as u32 * cpu_topo.cores as u32 + core_id as u32)
+            * cpu_topo.threads as u32
+            + thread_id as u32;
+        if vcpu_id >= cpu_topo.max_cpus as u32 {
+            bail!("CPU {} exceeds the maximum number of cpus", vcpu_id);
+        }
+
+        self.plug_cpu(&args.id, vcpu_id as u8)
+    }
+
     fn plug_virtio_pci_net(
         &mut self,
         pci_bdf: &PciBdf,
@@ -579,32 +834,87 @@ impl StdMachine {
             bail!("Netdev not set");
         };
 
-        let dev = if let Some(conf) = self.get_vm_config().lock().unwrap().netdevs.get(netdev) {
+        let mq = if let Some(mq) = &args.mq {
+            match mq.parse::<ExBool>() {
+                Ok(mq) => mq.into(),
+                Err(_) => bail!("Invalid mq: {:?}", mq),
+            }
+        } else {
+            false
+        };
+
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        let dev = if let Some(conf) = locked_vmconfig.netdevs.get(netdev).cloned() {
+            let socket_path = if let Some(chardev) = &conf.chardev {
+                Some(get_chardev_socket_path(&mut locked_vmconfig, chardev)?)
+            } else {
+                None
+            };
+            let (iothread, queue_iothreads) = split_iothreads(args.iothread.clone());
             let dev = NetworkInterfaceConfig {
-                id: conf.id.clone(),
+                id: args.id.clone(),
                 host_dev_name: conf.ifname.clone(),
                 mac: args.mac.clone(),
-                tap_fd: conf.tap_fd,
+                tap_fds: conf.tap_fds.clone(),
                 vhost_type: conf.vhost_type.clone(),
                 vhost_fd: conf.vhost_fd,
-                iothread: args.iothread.clone(),
+                iothread,
+                queue_iothreads,
+                queues: conf.queues,
+                mq,
+                socket_path,
+                rate_limit: conf.rate_limit,
             };
             dev.check()?;
             dev
         } else {
             bail!("Netdev not found");
         };
-
-        let net: Arc<Mutex<dyn VirtioDevice>> = if dev.vhost_type.is_some() {
-            Arc::new(Mutex::new(VhostKern::Net::new(&dev, self.get_sys_mem())))
+        drop(locked_vmconfig);
+
+        let net: Arc<Mutex<dyn VirtioDevice>> = if dev.vhost_type.as_deref() == Some("vhost-user") {
+            Arc::new(Mutex::new(VhostUser::Net::new(&dev, self.get_sys_mem())))
+        } else if dev.vhost_type.is_some() {
+            let net = Arc::new(Mutex::new(VhostKern::Net::new(&dev, self.get_sys_mem())));
+            VhostKern::register_net_device(&net);
+            net
         } else {
-            Arc::new(Mutex::new(virtio::Net::new(dev)))
+            let net = Arc::new(Mutex::new(virtio::Net::new(dev)));
+            register_net_device(&net);
+            net
         };
 
         self.add_virtio_pci_device(&args.id, &pci_bdf, net, multifunction)
             .chain_err(|| "Failed to add virtio pci net device")
     }
 
+    fn plug_vhost_user_blk_pci(
+        &mut self,
+        pci_bdf: &PciBdf,
+        args: &qmp_schema::DeviceAddArgument,
+    ) -> Result<()> {
+        let multifunction = args.multifunction.unwrap_or(false);
+        let chardev = if let Some(dev) = &args.chardev {
+            dev
+        } else {
+            bail!("Chardev not set");
+        };
+
+        let vm_config = self.get_vm_config();
+        let socket_path = get_chardev_socket_path(&mut vm_config.lock().unwrap(), chardev)?;
+        let dev = VhostUserBlkDevConfig {
+            id: args.id.clone(),
+            socket_path,
+            num_queues: args.num_queues.unwrap_or(1),
+        };
+        dev.check()?;
+        let blk = Arc::new(Mutex::new(VhostUser::Block::new(&dev, self.get_sys_mem())));
+
+        self.add_virtio_pci_device(&args.id, pci_bdf, blk, multifunction)
+            .chain_err(|| "Failed to add vhost user blk pci device")
+    }
+
     fn plug_vfio_pci_device(
         &mut self,
         bdf: &PciBdf,
@@ -654,7 +964,10 @@ impl DeviceInterface for StdMachine {
         let cpus = self.get_cpus();
         for cpu_index in 0..cpu_topo.max_cpus {
             if cpu_topo.get_mask(cpu_index as usize) == 1 {
-                let thread_id = cpus[cpu_index as usize].tid();
+                let thread_id = cpus
+                    .iter()
+                    .find(|cpu| cpu.id() == cpu_index)
+                    .map_or(0, |cpu| cpu.tid());
                 let (socketid, coreid, threadid) = cpu_topo.get_topo(cpu_index as usize);
                 let cpu_instance = qmp_schema::CpuInstanceProperties {
                     node_id: None,
@@ -680,7 +993,32 @@ impl DeviceInterface for StdMachine {
     }
 
     fn query_hotpluggable_cpus(&self) -> Response {
-        Response::create_empty_response()
+        let mut hotplug_vec: Vec<serde_json::Value> = Vec::new();
+        let cpu_topo = self.get_cpu_topo();
+        for cpu_index in 0..cpu_topo.max_cpus {
+            let (socketid, coreid, threadid) = cpu_topo.get_topo(cpu_index as usize);
+            let cpu_instance = qmp_schema::CpuInstanceProperties {
+                node_id: None,
+                socket_id: Some(socketid as isize),
+                core_id: Some(coreid as isize),
+                thread_id: Some(threadid as isize),
+            };
+            let qom_path = if cpu_topo.get_mask(cpu_index as usize) == 0 {
+                None
+            } else if let Some(id) = self.get_hotplugged_cpu_id(cpu_index) {
+                Some(format!("/machine/peripheral/{}", id))
+            } else {
+                Some(format!("/machine/unattached/device[{}]", cpu_index))
+            };
+            let hotpluggable_cpu = qmp_schema::HotpluggableCPU {
+                type_: CPU_TYPE.to_string(),
+                vcpus_count: 1,
+                props: cpu_instance,
+                qom_path,
+            };
+            hotplug_vec.push(serde_json::to_value(hotpluggable_cpu).unwrap());
+        }
+        Response::create_response(hotplug_vec.into(), None)
     }
 
     fn balloon(&self, value: u64) -> Response {
@@ -697,7 +1035,10 @@ impl DeviceInterface for StdMachine {
 
     fn query_balloon(&self) -> Response {
         if let Some(actual) = qmp_query_balloon() {
-            let ret = qmp_schema::BalloonInfo { actual };
+            let ret = qmp_schema::BalloonInfo {
+                actual,
+                guest_stats: qmp_query_balloon_stats(),
+            };
             return Response::create_response(serde_json::to_value(&ret).unwrap(), None);
         }
         Response::create_error_response(
@@ -708,6 +1049,76 @@ impl DeviceInterface for StdMachine {
         )
     }
 
+    fn query_netdev_stats(&self, id: Option<String>, reset: Option<bool>) -> Response {
+        match qmp_query_netdev_stats(id.as_deref(), reset.unwrap_or(false)) {
+            Ok(stats) => Response::create_response(serde_json::to_value(stats).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn set_link(&self, name: String, up: bool) -> Response {
+        match qmp_set_link(&name, up) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn ringbuf_read(
+        &self,
+        device: String,
+        size: u64,
+        format: Option<qmp_schema::DataFormat>,
+    ) -> Response {
+        match qmp_ringbuf_read(&device, size as usize, format) {
+            Ok(data) => Response::create_response(serde_json::to_value(data).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn ringbuf_write(
+        &self,
+        device: String,
+        data: String,
+        format: Option<qmp_schema::DataFormat>,
+    ) -> Response {
+        match qmp_ringbuf_write(&device, &data, format) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn qom_set(&self, path: String, property: String, value: u64) -> Response {
+        if property != "requested-size" {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!(
+                    "Property {} is not supported",
+                    property
+                )),
+                None,
+            );
+        }
+        let id = path.trim_start_matches("/machine/peripheral/");
+        match qmp_set_requested_size(id, value) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
     fn device_add(&mut self, args: Box<qmp_schema::DeviceAddArgument>) -> Response {
         if let Err(e) = self.check_device_id_existed(&args.id) {
             return Response::create_error_response(
@@ -715,6 +1126,42 @@ impl DeviceInterface for StdMachine {
                 None,
             );
         }
+        if self.get_hotplugged_cpu(&args.id).is_some() {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("Device id {} existed", args.id)),
+                None,
+            );
+        }
+
+        // Vcpus are attached to the cpu hotplug controller rather than pci bus.
+        if args.driver == CPU_TYPE {
+            return match self.plug_cpu_device(args.as_ref()) {
+                Ok(()) => Response::create_empty_response(),
+                Err(e) => {
+                    error!("{}", e.display_chain());
+                    let err_str = format!("Failed to add cpu: {}", e);
+                    Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    )
+                }
+            };
+        }
+
+        // Scsi devices are attached to the virtio-scsi controller rather than pci bus.
+        if let "scsi-hd" | "scsi-cd" = args.driver.as_str() {
+            return match self.plug_scsi_device(args.as_ref()) {
+                Ok(()) => Response::create_empty_response(),
+                Err(e) => {
+                    error!("{}", e.display_chain());
+                    let err_str = format!("Failed to add scsi device: {}", e);
+                    Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    )
+                }
+            };
+        }
 
         // Use args.bus.clone() and args.addr.clone() because args borrowed in the following process.
         let pci_bdf = match get_device_bdf(args.bus.clone(), args.addr.clone()) {
@@ -739,6 +1186,16 @@ impl DeviceInterface for StdMachine {
                     );
                 }
             }
+            "virtio-scsi-pci" => {
+                if let Err(e) = self.plug_virtio_scsi_cntlr(&pci_bdf, args.as_ref()) {
+                    error!("{}", e.display_chain());
+                    let err_str = format!("Failed to add virtio scsi controller: {}", e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    );
+                }
+            }
             "virtio-net-pci" => {
                 if let Err(e) = self.plug_virtio_pci_net(&pci_bdf, args.as_ref()) {
                     error!("{}", e.display_chain());
@@ -749,6 +1206,16 @@ impl DeviceInterface for StdMachine {
                     );
                 }
             }
+            "vhost-user-blk-pci" => {
+                if let Err(e) = self.plug_vhost_user_blk_pci(&pci_bdf, args.as_ref()) {
+                    error!("{}", e.display_chain());
+                    let err_str = format!("Failed to add vhost user blk pci: {}", e);
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(err_str),
+                        None,
+                    );
+                }
+            }
             "vfio-pci" => {
                 if let Err(e) = self.plug_vfio_pci_device(&pci_bdf, args.as_ref()) {
                     return Response::create_error_response(
@@ -794,6 +1261,35 @@ impl DeviceInterface for StdMachine {
     }
 
     fn device_del(&mut self, device_id: String) -> Response {
+        if let Some(vcpu_id) = self.get_hotplugged_cpu(&device_id) {
+            // Vcpu is removed after guest ejects it.
+            return match self.unplug_cpu_request(vcpu_id) {
+                Ok(()) => Response::create_empty_response(),
+                Err(e) => Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                ),
+            };
+        }
+
+        if scsi_device_existed(&device_id) {
+            // Scsi device is removed at once, no need to wait for the guest.
+            return match scsi_detach_device(&device_id) {
+                Ok(_) => {
+                    let scsi_del_event = qmp_schema::DeviceDeleted {
+                        path: format!("/machine/peripheral/{}", &device_id),
+                        device: Some(device_id),
+                    };
+                    event!(DeviceDeleted; scsi_del_event);
+                    Response::create_empty_response()
+                }
+                Err(e) => Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                ),
+            };
+        }
+
         let pci_host = match self.get_pci_host() {
             Ok(host) => host,
             Err(e) => {
@@ -820,18 +1316,78 @@ impl DeviceInterface for StdMachine {
     }
 
     fn blockdev_add(&self, args: Box<qmp_schema::BlockDevAddArgument>) -> Response {
+        let format = match args
+            .driver
+            .as_deref()
+            .unwrap_or("raw")
+            .parse::<DiskFormat>()
+        {
+            Ok(format) => format,
+            Err(_) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!(
+                        "Unsupported block driver: {:?}",
+                        args.driver
+                    )),
+                    None,
+                );
+            }
+        };
+        let discard = match args.discard.as_deref().map(parse_discard).transpose() {
+            Ok(discard) => discard.unwrap_or(false),
+            Err(e) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+        };
+        let write_zeroes = match args
+            .detect_zeroes
+            .as_deref()
+            .unwrap_or("off")
+            .parse::<WriteZeroesState>()
+        {
+            Ok(write_zeroes) => write_zeroes,
+            Err(_) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!(
+                        "Invalid detect-zeroes: {:?}",
+                        args.detect_zeroes
+                    )),
+                    None,
+                );
+            }
+        };
+        let throttle = get_blockdev_throttle(&args);
         let read_only = args.read_only.unwrap_or(false);
         let direct = if let Some(cache) = args.cache {
             cache.direct.unwrap_or(true)
         } else {
             true
         };
+        let aio = match args.aio.as_deref().map(str::parse::<AioEngine>) {
+            Some(Ok(aio)) => aio,
+            Some(Err(_)) => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!("Invalid aio: {:?}", args.aio)),
+                    None,
+                );
+            }
+            None if direct => AioEngine::Native,
+            None => AioEngine::Threads,
+        };
         let config = DriveConfig {
             id: args.node_name,
             path_on_host: args.file.filename,
             read_only,
             direct,
-            iops: args.iops,
+            throttle,
+            throttle_group: args.throttle_group.clone(),
+            format,
+            discard,
+            write_zeroes,
+            aio,
         };
 
         if let Err(e) = config.check() {
@@ -876,6 +1432,47 @@ impl DeviceInterface for StdMachine {
         }
     }
 
+    fn query_block(&self) -> Response {
+        Response::create_response(serde_json::to_value(qmp_query_block()).unwrap(), None)
+    }
+
+    fn query_named_block_nodes(&self) -> Response {
+        Response::create_response(
+            serde_json::to_value(qmp_query_named_block_nodes()).unwrap(),
+            None,
+        )
+    }
+
+    fn query_blockstats(&self) -> Response {
+        Response::create_response(serde_json::to_value(qmp_query_blockstats()).unwrap(), None)
+    }
+
+    fn block_set_io_throttle(&self, args: Box<qmp_schema::BlockIoThrottleArgument>) -> Response {
+        let name = match args.id.as_ref().or(args.device.as_ref()) {
+            Some(name) => name.clone(),
+            None => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(
+                        "Need the id of the block device".to_string(),
+                    ),
+                    None,
+                );
+            }
+        };
+        let result = get_io_throttle_config(&args)
+            .map_err(|e| e.to_string())
+            .and_then(|config| {
+                qmp_block_set_io_throttle(&name, config, args.group.clone())
+                    .map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => {
+                Response::create_error_response(qmp_schema::QmpErrorClass::GenericError(e), None)
+            }
+        }
+    }
+
     fn netdev_add(&mut self, args: Box<qmp_schema::NetDevAddArgument>) -> Response {
         let config = match get_netdev_config(args) {
             Ok(conf) => conf,
@@ -908,6 +1505,18 @@ impl DeviceInterface for StdMachine {
         }
     }
 
+    fn netdev_set_rate_limit(&self, args: Box<qmp_schema::NetRateLimitArgument>) -> Response {
+        let result = get_net_rate_limit_config(&args)
+            .map_err(|e| e.to_string())
+            .and_then(|config| qmp_net_set_rate_limit(&args.id, config).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => {
+                Response::create_error_response(qmp_schema::QmpErrorClass::GenericError(e), None)
+            }
+        }
+    }
+
     fn netdev_del(&mut self, id: String) -> Response {
         match self.get_vm_config().lock().unwrap().del_netdev_by_id(&id) {
             Ok(()) => Response::create_empty_response(),
@@ -929,3 +1538,119 @@ impl DeviceInterface for StdMachine {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::net::UnixListener;
+
+    use address_space::{AddressSpace, Region};
+    use util::seccomp::{SeccompOpt, SyscallFilter};
+    use virtio::VhostUser::VhostUserClient;
+
+    #[cfg(target_arch = "aarch64")]
+    use super::aarch64::syscall_whitelist;
+    #[cfg(target_arch = "x86_64")]
+    use super::x86_64::syscall_whitelist;
+
+    /// Run `func` in a child process with the syscall allow-list registered, as
+    /// a forbidden syscall kills the process. `prepare` runs in the child process
+    /// before the rules are registered, and its result is passed to `func`.
+    /// Returns whether `func` succeeds.
+    fn run_with_seccomp<T, P, F>(prepare: P, func: F) -> bool
+    where
+        P: FnOnce() -> T,
+        F: FnOnce(T) -> bool,
+    {
+        // Safe because the child process only runs `prepare` and `func`, and exits.
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0);
+        if pid == 0 {
+            let prepared = prepare();
+            let mut seccomp_filter = SyscallFilter::new(SeccompOpt::Trap);
+            for bpf_rule in syscall_whitelist().iter_mut() {
+                seccomp_filter.push(bpf_rule);
+            }
+            let ret = seccomp_filter.realize().is_ok() && func(prepared);
+            // Safe because the child process exits without returning to the test.
+            unsafe { libc::_exit(if ret { 0 } else { 1 }) };
+        }
+
+        let mut status = 0;
+        // Safe because the child process is created above.
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+    }
+
+    #[test]
+    fn test_vhost_user_connect_with_seccomp() {
+        // Hotplugged vhost-user devices, and the devices reconnecting to the slave,
+        // connect to the slave after seccomp rules registered.
+        let path = format!("/tmp/test_vhost_user_seccomp_{}.sock", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        let _listener = UnixListener::bind(&path).unwrap();
+        let mem_space = AddressSpace::new(Region::init_container_region(u64::MAX)).unwrap();
+
+        assert!(run_with_seccomp(
+            || (),
+            |_| VhostUserClient::new(&mem_space, &path).is_ok()
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_chardev_reconnect_with_seccomp() {
+        // Tcp chardev reconnects to the server after seccomp rules registered, and
+        // gets the result of the nonblocking connection when the socket is writable.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        assert!(run_with_seccomp(
+            || (),
+            |_| match TcpStream::connect(addr) {
+                Ok(stream) => {
+                    matches!(stream.take_error(), Ok(None)) && stream.set_nonblocking(false).is_ok()
+                }
+                Err(_) => false,
+            }
+        ));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_vcpu_hotplug_with_seccomp() {
+        use cpu::{VCPU_RESET_SIGNAL, VCPU_TASK_SIGNAL};
+        use kvm_ioctls::{Cap, Kvm};
+        use libc::{c_int, c_void, siginfo_t};
+        use vmm_sys_util::signal::register_signal_handler;
+
+        extern "C" fn handle_signal(_: c_int, _: *mut siginfo_t, _: *mut c_void) {}
+
+        // Kvm may be not available in the test environment.
+        if Kvm::new().is_err() {
+            return;
+        }
+
+        assert!(run_with_seccomp(
+            // The vm is created before seccomp rules registered.
+            || Kvm::new().and_then(|kvm| kvm.create_vm()),
+            |vm_fd| {
+                let vm_fd = match vm_fd {
+                    Ok(vm_fd) => vm_fd,
+                    Err(_) => return false,
+                };
+                // Hotplugged vcpu is created and its capabilities are got in `CPU::new`,
+                // then its thread registers the handlers of vcpu signals.
+                let kvm = match Kvm::new() {
+                    Ok(kvm) => kvm,
+                    Err(_) => return false,
+                };
+                kvm.check_extension(Cap::Xsave);
+                vm_fd.create_vcpu(1).is_ok()
+                    && kvm.get_msr_index_list().is_ok()
+                    && register_signal_handler(VCPU_TASK_SIGNAL, handle_signal).is_ok()
+                    && register_signal_handler(VCPU_RESET_SIGNAL, handle_signal).is_ok()
+            }
+        ));
+    }
+}
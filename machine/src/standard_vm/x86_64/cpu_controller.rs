@@ -0,0 +1,456 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+
+use acpi::{
+    AcpiLocalApic, AmlAcquire, AmlActiveLevel, AmlAddressSpaceType, AmlArg, AmlBuffer, AmlBuilder,
+    AmlCallWithArgs1, AmlCallWithArgs3, AmlDevice, AmlEdgeLevel, AmlEisaId, AmlEqual,
+    AmlExtendedInterrupt, AmlField, AmlFieldAccessType, AmlFieldLockRule, AmlFieldUnit,
+    AmlFieldUpdateRule, AmlIf, AmlIntShare, AmlInteger, AmlLocal, AmlMethod, AmlMutex, AmlName,
+    AmlNameDecl, AmlNotify, AmlOne, AmlOpRegion, AmlRelease, AmlResTemplate, AmlResourceUsage,
+    AmlReturn, AmlScopeBuilder, AmlStore, AmlString, AmlZero,
+};
+use address_space::GuestAddress;
+use sysbus::{errors::Result as SysBusResult, SysBus, SysBusDevOps, SysRes};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::standard_vm::errors::Result;
+
+/// Register to select a vcpu slot, the other registers act on the selected one.
+const CPU_SELECTOR_OFFSET: u64 = 0;
+/// Status register of the selected vcpu slot.
+const CPU_STATUS_OFFSET: u64 = 4;
+/// Event code register of `_OST`.
+const CPU_OST_EVENT_OFFSET: u64 = 8;
+/// Status code register of `_OST`.
+const CPU_OST_STATUS_OFFSET: u64 = 12;
+
+/// Vcpu is plugged, read only.
+const CPU_STATUS_ENABLED: u8 = 1 << 0;
+/// Insert event is pending, write 1 to clear.
+const CPU_STATUS_INSERT: u8 = 1 << 1;
+/// Remove event is pending, write 1 to clear.
+const CPU_STATUS_REMOVE: u8 = 1 << 2;
+/// Guest ejects the vcpu, write only.
+const CPU_STATUS_EJECT: u8 = 1 << 3;
+
+/// Notification value of ACPI device check.
+const ACPI_NOTIFY_DEVICE_CHECK: u64 = 1;
+/// Notification value of ACPI eject request.
+const ACPI_NOTIFY_EJECT_REQUEST: u64 = 3;
+/// `_OST` source event of ejection.
+const ACPI_OST_EVENT_EJECT: u32 = 3;
+/// `_OST` status code of ejection in progress.
+const ACPI_OST_STATUS_EJECT_IN_PROGRESS: u32 = 0x84;
+
+#[derive(Default, Copy, Clone)]
+struct CpuSlot {
+    /// Vcpu of this slot is plugged.
+    present: bool,
+    /// Insert event is not handled by guest yet.
+    insert: bool,
+    /// Remove event is not handled by guest yet.
+    remove: bool,
+}
+
+/// ACPI cpu hotplug controller, which notifies guest through ACPI Generic
+/// Event Device, guest scans the vcpu slots and inserts or ejects vcpus.
+pub struct CpuController {
+    /// Status of all vcpu slots.
+    slots: Vec<CpuSlot>,
+    /// The selected vcpu slot.
+    selected: u32,
+    /// Source event of the last `_OST` evaluation.
+    ost_event: u32,
+    /// Interrupt of ACPI Generic Event Device.
+    interrupt_evt: EventFd,
+    /// System resource.
+    res: SysRes,
+    /// Vcpus ejected by guest, which are waiting to be parked.
+    eject_list: Vec<u8>,
+    /// Eject request triggered by guest.
+    pub eject_req: EventFd,
+}
+
+impl CpuController {
+    /// Create cpu hotplug controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `nr_cpus` - Number of vcpus plugged at boot.
+    /// * `max_cpus` - Maximum number of vcpus.
+    pub fn new(nr_cpus: u8, max_cpus: u8) -> Self {
+        let mut slots = vec![CpuSlot::default(); max_cpus as usize];
+        slots[..nr_cpus as usize]
+            .iter_mut()
+            .for_each(|slot| slot.present = true);
+
+        CpuController {
+            slots,
+            selected: 0,
+            ost_event: 0,
+            interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            res: SysRes::default(),
+            eject_list: Vec::new(),
+            eject_req: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        }
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<Arc<Mutex<CpuController>>> {
+        self.set_sys_resource(sysbus, region_base, region_size)?;
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_device(&dev, region_base, region_size)?;
+        Ok(dev)
+    }
+
+    /// Mark the vcpu as plugged and notify guest to insert it.
+    pub fn plug(&mut self, vcpu_id: u8) -> Result<()> {
+        let slot = self.slot_mut(vcpu_id)?;
+        if slot.present {
+            bail!("CPU {} is already plugged", vcpu_id);
+        }
+        slot.present = true;
+        slot.insert = true;
+        self.notify()
+    }
+
+    /// Notify guest to eject the vcpu, the vcpu is removed after guest
+    /// evaluates its `_EJ0`.
+    pub fn unplug_request(&mut self, vcpu_id: u8) -> Result<()> {
+        let slot = self.slot_mut(vcpu_id)?;
+        if !slot.present {
+            bail!("CPU {} is not plugged", vcpu_id);
+        }
+        slot.remove = true;
+        self.notify()
+    }
+
+    /// Mark the vcpu as unplugged after it is parked.
+    pub fn unplug_done(&mut self, vcpu_id: u8) {
+        if let Ok(slot) = self.slot_mut(vcpu_id) {
+            *slot = CpuSlot::default();
+        }
+    }
+
+    /// Take the vcpus which are ejected by guest.
+    pub fn take_eject_list(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.eject_list)
+    }
+
+    fn slot_mut(&mut self, vcpu_id: u8) -> Result<&mut CpuSlot> {
+        let max_cpus = self.slots.len();
+        match self.slots.get_mut(vcpu_id as usize) {
+            Some(slot) => Ok(slot),
+            None => bail!(
+                "CPU {} exceeds the maximum number of cpus {}",
+                vcpu_id,
+                max_cpus
+            ),
+        }
+    }
+
+    fn notify(&self) -> Result<()> {
+        self.interrupt_evt.write(1)?;
+        Ok(())
+    }
+
+    fn read_status(&self) -> u8 {
+        let slot = match self.slots.get(self.selected as usize) {
+            Some(slot) => slot,
+            None => return 0,
+        };
+        let mut status = 0_u8;
+        if slot.present {
+            status |= CPU_STATUS_ENABLED;
+        }
+        if slot.insert {
+            status |= CPU_STATUS_INSERT;
+        }
+        if slot.remove {
+            status |= CPU_STATUS_REMOVE;
+        }
+        status
+    }
+
+    fn write_status(&mut self, value: u8) {
+        let vcpu_id = self.selected as u8;
+        let slot = match self.slots.get_mut(self.selected as usize) {
+            Some(slot) => slot,
+            None => return,
+        };
+        if value & CPU_STATUS_INSERT != 0 {
+            slot.insert = false;
+        }
+        if value & CPU_STATUS_REMOVE != 0 {
+            slot.remove = false;
+        }
+        if value & CPU_STATUS_EJECT != 0 {
+            if !slot.present || self.eject_list.contains(&vcpu_id) {
+                return;
+            }
+            self.eject_list.push(vcpu_id);
+            if let Err(e) = self.eject_req.write(1) {
+                error!("Failed to write eject request of CPU {}: {}", vcpu_id, e);
+            }
+        }
+    }
+
+    fn write_ost_status(&mut self, status: u32) {
+        if self.ost_event != ACPI_OST_EVENT_EJECT
+            || status == 0
+            || status == ACPI_OST_STATUS_EJECT_IN_PROGRESS
+        {
+            return;
+        }
+        warn!(
+            "Guest failed to eject CPU {}, status code 0x{:x}",
+            self.selected, status
+        );
+        // Allow the vcpu to be unplugged again.
+        if let Some(slot) = self.slots.get_mut(self.selected as usize) {
+            slot.remove = false;
+        }
+    }
+
+    /// Build the processor container and vcpu devices.
+    fn cpus_aml(&self) -> AmlDevice {
+        let mut cpus = AmlDevice::new("CPUS");
+        cpus.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0010".to_string())));
+        cpus.append_child(AmlNameDecl::new("_CID", AmlEisaId::new("PNP0A05")));
+        cpus.append_child(AmlMutex::new("CPLK", 0));
+        cpus.append_child(AmlOpRegion::new(
+            "PRST",
+            AmlAddressSpaceType::SystemMemory,
+            self.res.region_base,
+            self.res.region_size,
+        ));
+
+        let mut field = AmlField::new(
+            "PRST",
+            AmlFieldAccessType::DWord,
+            AmlFieldLockRule::NoLock,
+            AmlFieldUpdateRule::Preserve,
+        );
+        field.append_child(AmlFieldUnit::new(Some("CSEL"), 32));
+        cpus.append_child(field);
+        let mut field = AmlField::new(
+            "PRST",
+            AmlFieldAccessType::Byte,
+            AmlFieldLockRule::NoLock,
+            AmlFieldUpdateRule::WriteAsZeros,
+        );
+        field.append_child(AmlFieldUnit::new(None, CPU_STATUS_OFFSET as u32 * 8));
+        field.append_child(AmlFieldUnit::new(Some("CPEN"), 1));
+        field.append_child(AmlFieldUnit::new(Some("CINS"), 1));
+        field.append_child(AmlFieldUnit::new(Some("CRMV"), 1));
+        field.append_child(AmlFieldUnit::new(Some("CEJ0"), 1));
+        cpus.append_child(field);
+        let mut field = AmlField::new(
+            "PRST",
+            AmlFieldAccessType::DWord,
+            AmlFieldLockRule::NoLock,
+            AmlFieldUpdateRule::Preserve,
+        );
+        field.append_child(AmlFieldUnit::new(None, CPU_OST_EVENT_OFFSET as u32 * 8));
+        field.append_child(AmlFieldUnit::new(Some("CEVT"), 32));
+        field.append_child(AmlFieldUnit::new(Some("CSTS"), 32));
+        cpus.append_child(field);
+
+        // Method CSTA: get status of the vcpu `Arg0`.
+        let mut method = AmlMethod::new("CSTA", 1, true);
+        method.append_child(AmlAcquire::new(AmlName("CPLK".to_string()), 0xFFFF));
+        method.append_child(AmlStore::new(AmlArg(0), AmlName("CSEL".to_string())));
+        method.append_child(AmlStore::new(AmlZero, AmlLocal(0)));
+        let mut if_scope = AmlIf::new(AmlEqual::new(AmlName("CPEN".to_string()), AmlOne));
+        if_scope.append_child(AmlStore::new(AmlInteger(0xF), AmlLocal(0)));
+        method.append_child(if_scope);
+        method.append_child(AmlRelease::new(AmlName("CPLK".to_string())));
+        method.append_child(AmlReturn::with_value(AmlLocal(0)));
+        cpus.append_child(method);
+
+        // Method CEJT: eject the vcpu `Arg0`.
+        let mut method = AmlMethod::new("CEJT", 1, true);
+        method.append_child(AmlAcquire::new(AmlName("CPLK".to_string()), 0xFFFF));
+        method.append_child(AmlStore::new(AmlArg(0), AmlName("CSEL".to_string())));
+        method.append_child(AmlStore::new(AmlOne, AmlName("CEJ0".to_string())));
+        method.append_child(AmlRelease::new(AmlName("CPLK".to_string())));
+        cpus.append_child(method);
+
+        // Method COST: report `_OST` of the vcpu `Arg0`.
+        let mut method = AmlMethod::new("COST", 3, true);
+        method.append_child(AmlAcquire::new(AmlName("CPLK".to_string()), 0xFFFF));
+        method.append_child(AmlStore::new(AmlArg(0), AmlName("CSEL".to_string())));
+        method.append_child(AmlStore::new(AmlArg(1), AmlName("CEVT".to_string())));
+        method.append_child(AmlStore::new(AmlArg(2), AmlName("CSTS".to_string())));
+        method.append_child(AmlRelease::new(AmlName("CPLK".to_string())));
+        cpus.append_child(method);
+
+        // Method CSCN: scan all vcpu slots and notify the pending events.
+        let mut method = AmlMethod::new("CSCN", 0, true);
+        method.append_child(AmlAcquire::new(AmlName("CPLK".to_string()), 0xFFFF));
+        for vcpu_id in 0..self.slots.len() {
+            let name = format!("C{:03}", vcpu_id);
+            method.append_child(AmlStore::new(
+                AmlInteger(vcpu_id as u64),
+                AmlName("CSEL".to_string()),
+            ));
+            let mut if_scope = AmlIf::new(AmlEqual::new(AmlName("CINS".to_string()), AmlOne));
+            if_scope.append_child(AmlNotify::new(
+                AmlName(name.clone()),
+                AmlInteger(ACPI_NOTIFY_DEVICE_CHECK),
+            ));
+            if_scope.append_child(AmlStore::new(AmlOne, AmlName("CINS".to_string())));
+            method.append_child(if_scope);
+            let mut if_scope = AmlIf::new(AmlEqual::new(AmlName("CRMV".to_string()), AmlOne));
+            if_scope.append_child(AmlNotify::new(
+                AmlName(name),
+                AmlInteger(ACPI_NOTIFY_EJECT_REQUEST),
+            ));
+            if_scope.append_child(AmlStore::new(AmlOne, AmlName("CRMV".to_string())));
+            method.append_child(if_scope);
+        }
+        method.append_child(AmlRelease::new(AmlName("CPLK".to_string())));
+        cpus.append_child(method);
+
+        for vcpu_id in 0..self.slots.len() {
+            cpus.append_child(Self::vcpu_aml(vcpu_id as u8));
+        }
+        cpus
+    }
+
+    /// Build the processor device of a vcpu.
+    fn vcpu_aml(vcpu_id: u8) -> AmlDevice {
+        let mut dev = AmlDevice::new(format!("C{:03}", vcpu_id).as_str());
+        dev.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0007".to_string())));
+        dev.append_child(AmlNameDecl::new("_UID", AmlInteger(vcpu_id as u64)));
+
+        let mut method = AmlMethod::new("_STA", 0, false);
+        method.append_child(AmlReturn::with_value(AmlCallWithArgs1::new(
+            "CSTA",
+            AmlInteger(vcpu_id as u64),
+        )));
+        dev.append_child(method);
+
+        // MADT entry of the vcpu, which is enabled once the vcpu is plugged.
+        let lapic = AcpiLocalApic {
+            type_id: 0,
+            length: size_of::<AcpiLocalApic>() as u8,
+            processor_uid: vcpu_id,
+            apic_id: vcpu_id,
+            flags: 1,
+        };
+        dev.append_child(AmlNameDecl::new("_MAT", AmlBuffer(lapic.aml_bytes())));
+
+        let mut method = AmlMethod::new("_EJ0", 1, false);
+        method.append_child(AmlCallWithArgs1::new("CEJT", AmlInteger(vcpu_id as u64)));
+        dev.append_child(method);
+
+        let mut method = AmlMethod::new("_OST", 3, false);
+        method.append_child(AmlCallWithArgs3::new(
+            "COST",
+            AmlInteger(vcpu_id as u64),
+            AmlArg(0),
+            AmlArg(1),
+        ));
+        dev.append_child(method);
+        dev
+    }
+
+    /// Build the Generic Event Device which notifies guest to scan vcpus.
+    fn ged_aml(&self) -> AmlDevice {
+        let mut ged = AmlDevice::new("GED");
+        ged.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0013".to_string())));
+        ged.append_child(AmlNameDecl::new("_UID", AmlInteger(0)));
+        let mut res = AmlResTemplate::new();
+        res.append_child(AmlExtendedInterrupt::new(
+            AmlResourceUsage::Consumer,
+            AmlEdgeLevel::Edge,
+            AmlActiveLevel::High,
+            AmlIntShare::Exclusive,
+            vec![self.res.irq as u32],
+        ));
+        ged.append_child(AmlNameDecl::new("_CRS", res));
+
+        let mut method = AmlMethod::new("_EVT", 1, true);
+        method.append_child(AmlName("\\_SB.CPUS.CSCN".to_string()));
+        ged.append_child(method);
+        ged
+    }
+}
+
+impl SysBusDevOps for CpuController {
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        let value = match offset {
+            CPU_SELECTOR_OFFSET => self.selected,
+            CPU_STATUS_OFFSET => self.read_status() as u32,
+            _ => 0,
+        };
+        let bytes = value.to_le_bytes();
+        let len = data.len().min(bytes.len());
+        data.fill(0);
+        data[..len].copy_from_slice(&bytes[..len]);
+        true
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        let mut bytes = [0_u8; 4];
+        let len = data.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&data[..len]);
+        let value = u32::from_le_bytes(bytes);
+
+        match offset {
+            CPU_SELECTOR_OFFSET => self.selected = value,
+            CPU_STATUS_OFFSET => self.write_status(value as u8),
+            CPU_OST_EVENT_OFFSET => self.ost_event = value,
+            CPU_OST_STATUS_OFFSET => self.write_ost_status(value),
+            _ => {
+                error!("Invalid offset 0x{:x} of cpu hotplug controller", offset);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn interrupt_evt(&self) -> Option<&EventFd> {
+        Some(&self.interrupt_evt)
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.res)
+    }
+
+    fn reset(&mut self) -> SysBusResult<()> {
+        self.selected = 0;
+        self.ost_event = 0;
+        self.slots.iter_mut().for_each(|slot| {
+            slot.insert = false;
+            slot.remove = false;
+        });
+        Ok(())
+    }
+}
+
+impl AmlBuilder for CpuController {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.cpus_aml().aml_bytes();
+        bytes.extend(self.ged_aml().aml_bytes());
+        bytes
+    }
+}
@@ -10,33 +10,39 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+mod cpu_controller;
 pub(crate) mod ich9_lpc;
 mod mch;
 mod syscall;
 
+use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::{Seek, SeekFrom};
 use std::mem::size_of;
 use std::ops::Deref;
-use std::sync::{Arc, Condvar, Mutex};
+use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
 
 use acpi::{
-    AcpiIoApic, AcpiLocalApic, AcpiTable, AmlBuilder, AmlDevice, AmlInteger, AmlNameDecl,
-    AmlPackage, AmlScope, AmlScopeBuilder, AmlString, TableLoader, ACPI_TABLE_FILE,
+    AcpiIoApic, AcpiLocalApic, AcpiSratProcessorAffinity, AcpiTable, AmlBuilder, AmlInteger,
+    AmlNameDecl, AmlPackage, AmlScope, AmlScopeBuilder, TableLoader, ACPI_TABLE_FILE,
     IOAPIC_BASE_ADDR, LAPIC_BASE_ADDR, TABLE_CHECKSUM_OFFSET,
 };
 use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
 use boot_loader::{load_linux, BootLoaderConfig};
-use cpu::{CPUBootConfig, CPUInterface, CpuTopology, CPU};
+use cpu::{ArchCPU, CPUBootConfig, CPUInterface, CpuTopology, CPU};
 use devices::legacy::{FwCfgEntryType, FwCfgIO, FwCfgOps, PFlash, Serial, RTC, SERIAL_ADDR};
 use error_chain::ChainedError;
 use hypervisor::kvm::KVM_FDS;
 use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
-use machine_manager::config::{BootSource, PFlashConfig, SerialConfig, VmConfig};
+use machine_manager::config::{
+    complete_mem_backend, complete_numa_nodes, BootSource, MemZoneConfig, NumaNodes, PFlashConfig,
+    SerialConfig, VmConfig,
+};
 use machine_manager::machine::{
-    KvmVmState, MachineAddressInterface, MachineExternalInterface, MachineInterface,
-    MachineLifecycle, MigrateInterface,
+    HumanMonitorInterface, KvmVmState, MachineAddressInterface, MachineExternalInterface,
+    MachineInterface, MachineLifecycle, MetricsInterface, MigrateInterface,
 };
+use machine_manager::metrics::Metric;
 use machine_manager::qmp::{qmp_schema, QmpChannel, Response};
 use migration::{MigrationManager, MigrationStatus};
 use pci::{PciDevOps, PciHost};
@@ -46,14 +52,15 @@ use util::seccomp::BpfRule;
 use util::set_termi_canon_mode;
 use vmm_sys_util::eventfd::EventFd;
 
+use self::cpu_controller::CpuController;
 use self::ich9_lpc::SLEEP_CTRL_OFFSET;
 
 use super::errors::{ErrorKind, Result};
-use super::{AcpiBuilder, StdMachineOps};
+use super::{build_srat_mem_affinity, AcpiBuilder, StdMachineOps};
 use crate::errors::{ErrorKind as MachineErrorKind, Result as MachineResult};
-use crate::MachineOps;
+use crate::{hmp, metrics, MachineOps};
 use mch::Mch;
-use syscall::syscall_whitelist;
+pub(super) use syscall::syscall_whitelist;
 use util::byte_code::ByteCode;
 
 const VENDOR_ID_INTEL: u16 = 0x8086;
@@ -105,13 +112,30 @@ pub struct StdMachine {
     /// VM power button, handle VM `Shutdown` event.
     power_button: EventFd,
     vm_config: Mutex<VmConfig>,
+    /// Guest numa nodes.
+    numa_nodes: Option<NumaNodes>,
+    /// Memory backend which provides guest memory.
+    mem_backend: Option<MemZoneConfig>,
+    /// Reference of this machine, used by hotplugged `vCPU`s.
+    self_ref: Weak<Mutex<StdMachine>>,
+    /// Boot config of `vCPU`s, used to realize hotplugged `vCPU`s.
+    boot_config: Option<CPUBootConfig>,
+    /// ACPI cpu hotplug controller.
+    cpu_controller: Option<Arc<Mutex<CpuController>>>,
+    /// Hotplugged `vCPU` devices, maps device id to `vCPU` id.
+    hotplugged_cpus: HashMap<String, u8>,
+    /// Hot-unplugged `vCPU`s, which are reused by next hotplug.
+    parked_cpus: Vec<Arc<CPU>>,
 }
 
 impl StdMachine {
     pub fn new(vm_config: &VmConfig) -> MachineResult<Self> {
         use crate::errors::ResultExt;
 
-        let cpu_topo = CpuTopology::new(vm_config.machine_config.nr_cpus);
+        let cpu_topo = CpuTopology::new(
+            vm_config.machine_config.nr_cpus,
+            vm_config.machine_config.max_cpus,
+        );
         let sys_io = AddressSpace::new(Region::init_container_region(1 << 16))
             .chain_err(|| MachineErrorKind::CrtMemSpaceErr)?;
         let sys_mem = AddressSpace::new(Region::init_container_region(u64::max_value()))
@@ -145,6 +169,15 @@ impl StdMachine {
             power_button: EventFd::new(libc::EFD_NONBLOCK)
                 .chain_err(|| MachineErrorKind::InitEventFdErr("power_button".to_string()))?,
             vm_config: Mutex::new(vm_config.clone()),
+            numa_nodes: complete_numa_nodes(vm_config)
+                .chain_err(|| "Failed to complete numa nodes")?,
+            mem_backend: complete_mem_backend(vm_config)
+                .chain_err(|| "Failed to complete memory backend")?,
+            self_ref: Weak::new(),
+            boot_config: None,
+            cpu_controller: None,
+            hotplugged_cpus: HashMap::new(),
+            parked_cpus: Vec::new(),
         })
     }
 
@@ -196,6 +229,49 @@ impl StdMachine {
         true
     }
 
+    /// Park the `vCPU`s ejected by guest, and report the deleted devices.
+    pub fn handle_cpu_eject_request(vm: &Arc<Mutex<Self>>) {
+        let controller = match &vm.lock().unwrap().cpu_controller {
+            Some(controller) => controller.clone(),
+            None => return,
+        };
+        let eject_list = controller.lock().unwrap().take_eject_list();
+        for vcpu_id in eject_list {
+            let mut locked_vm = vm.lock().unwrap();
+            let cpu = match locked_vm.cpus.iter().position(|cpu| cpu.id() == vcpu_id) {
+                Some(index) => locked_vm.cpus.remove(index),
+                None => continue,
+            };
+            // Vcpu thread may be waiting for the lock of machine.
+            drop(locked_vm);
+
+            let parked = cpu.park();
+            let mut locked_vm = vm.lock().unwrap();
+            if let Err(e) = parked {
+                error!("Failed to park vcpu{}, {}", vcpu_id, e.display_chain());
+                locked_vm.cpus.push(cpu);
+                continue;
+            }
+            locked_vm.parked_cpus.push(cpu);
+            locked_vm.cpu_topo.set_mask(vcpu_id as usize, false);
+            controller.lock().unwrap().unplug_done(vcpu_id);
+
+            let id = locked_vm
+                .hotplugged_cpus
+                .iter()
+                .find(|(_, id)| **id == vcpu_id)
+                .map(|(dev_id, _)| dev_id.clone());
+            if let Some(id) = id {
+                locked_vm.hotplugged_cpus.remove(&id);
+                let cpu_del_event = qmp_schema::DeviceDeleted {
+                    path: format!("/machine/peripheral/{}", &id),
+                    device: Some(id),
+                };
+                event!(DeviceDeleted; cpu_del_event);
+            }
+        }
+    }
+
     fn arch_init() -> MachineResult<()> {
         use crate::errors::ResultExt;
 
@@ -241,6 +317,23 @@ impl StdMachine {
         PciDevOps::realize(ich)?;
         Ok(())
     }
+
+    fn init_cpu_controller(&mut self, vm: Arc<Mutex<StdMachine>>) -> Result<()> {
+        use super::errors::ResultExt;
+
+        let region_base = self.sysbus.min_free_base;
+        let region_size = MEM_LAYOUT[LayoutEntryType::Mmio as usize].1;
+        let controller = CpuController::new(self.cpu_topo.nrcpus, self.cpu_topo.max_cpus)
+            .realize(&mut self.sysbus, region_base, region_size)
+            .chain_err(|| "Failed to realize cpu hotplug controller")?;
+        self.sysbus.min_free_base += region_size;
+
+        self.register_cpu_eject_event(&controller.lock().unwrap().eject_req, vm.clone())
+            .chain_err(|| "Fail to register eject event of cpu hotplug controller")?;
+        self.cpu_controller = Some(controller);
+        self.self_ref = Arc::downgrade(&vm);
+        Ok(())
+    }
 }
 
 impl StdMachineOps for StdMachine {
@@ -283,9 +376,10 @@ impl StdMachineOps for StdMachine {
         use super::errors::ResultExt;
 
         let mut fwcfg = FwCfgIO::new(self.sys_mem.clone());
-        let ncpus = self.cpus.len();
+        let ncpus = self.cpu_topo.nrcpus as usize;
+        let max_cpus = self.cpu_topo.max_cpus as usize;
         fwcfg.add_data_entry(FwCfgEntryType::NbCpus, ncpus.as_bytes().to_vec())?;
-        fwcfg.add_data_entry(FwCfgEntryType::MaxCpus, ncpus.as_bytes().to_vec())?;
+        fwcfg.add_data_entry(FwCfgEntryType::MaxCpus, max_cpus.as_bytes().to_vec())?;
         fwcfg.add_data_entry(FwCfgEntryType::Irq0Override, 1_u32.as_bytes().to_vec())?;
 
         let fwcfg_dev = FwCfgIO::realize(fwcfg, &mut self.sysbus)
@@ -309,6 +403,90 @@ impl StdMachineOps for StdMachine {
     fn get_vm_config(&self) -> &Mutex<VmConfig> {
         &self.vm_config
     }
+
+    fn plug_cpu(&mut self, id: &str, vcpu_id: u8) -> Result<()> {
+        use super::errors::ResultExt;
+
+        if self.cpu_topo.get_mask(vcpu_id as usize) == 1 {
+            bail!("CPU {} is already plugged", vcpu_id);
+        }
+        let controller = match &self.cpu_controller {
+            Some(controller) => controller.clone(),
+            None => bail!("Cpu hotplug controller not found"),
+        };
+        let boot_config = match &self.boot_config {
+            Some(boot_config) => boot_config,
+            None => bail!("CPU hotplug is not supported for migrated VM"),
+        };
+
+        let cpu = if let Some(index) = self.parked_cpus.iter().position(|cpu| cpu.id() == vcpu_id) {
+            let cpu = self.parked_cpus.remove(index);
+            cpu.unpark()?;
+            cpu
+        } else {
+            let vm = match self.self_ref.upgrade() {
+                Some(vm) => vm,
+                None => bail!("Standard machine not found"),
+            };
+            let vcpu_fd = KVM_FDS
+                .load()
+                .vm_fd
+                .as_ref()
+                .unwrap()
+                .create_vcpu(vcpu_id)
+                .chain_err(|| format!("Failed to create kvm vcpu {}", vcpu_id))?;
+            let arch_cpu = ArchCPU::new(u32::from(vcpu_id), u32::from(self.cpu_topo.max_cpus));
+            Arc::new(CPU::new(
+                Arc::new(vcpu_fd),
+                vcpu_id,
+                Arc::new(Mutex::new(arch_cpu)),
+                vm,
+            ))
+        };
+
+        if let Err(e) = cpu.realize(boot_config) {
+            // Keep the kvm vcpu for next hotplug.
+            cpu.park()?;
+            self.parked_cpus.push(cpu);
+            return Err(e).chain_err(|| format!("Failed to realize vcpu{}", vcpu_id));
+        }
+        // Vcpu is started by `vm_start` if VM is not started yet.
+        let vm_state = *self.vm_state.0.lock().unwrap();
+        if vm_state == KvmVmState::Running || vm_state == KvmVmState::Paused {
+            let barrier = Arc::new(Barrier::new(2));
+            if let Err(e) = CPU::start(cpu.clone(), barrier.clone(), vm_state == KvmVmState::Paused)
+            {
+                cpu.park()?;
+                self.parked_cpus.push(cpu);
+                return Err(e).chain_err(|| format!("Failed to run vcpu{}", vcpu_id));
+            }
+            barrier.wait();
+        }
+
+        self.cpus.push(cpu);
+        self.cpu_topo.set_mask(vcpu_id as usize, true);
+        self.hotplugged_cpus.insert(id.to_string(), vcpu_id);
+        controller.lock().unwrap().plug(vcpu_id)?;
+        Ok(())
+    }
+
+    fn unplug_cpu_request(&mut self, vcpu_id: u8) -> Result<()> {
+        match &self.cpu_controller {
+            Some(controller) => controller.lock().unwrap().unplug_request(vcpu_id),
+            None => bail!("Cpu hotplug controller not found"),
+        }
+    }
+
+    fn get_hotplugged_cpu(&self, id: &str) -> Option<u8> {
+        self.hotplugged_cpus.get(id).copied()
+    }
+
+    fn get_hotplugged_cpu_id(&self, vcpu_id: u8) -> Option<String> {
+        self.hotplugged_cpus
+            .iter()
+            .find(|(_, id)| **id == vcpu_id)
+            .map(|(dev_id, _)| dev_id.clone())
+    }
 }
 
 impl MachineOps for StdMachine {
@@ -344,6 +522,21 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn get_numa_nodes(&self) -> Option<&NumaNodes> {
+        self.numa_nodes.as_ref()
+    }
+
+    fn get_mem_backend(&self) -> Option<&MemZoneConfig> {
+        self.mem_backend.as_ref()
+    }
+
+    fn get_device_mem_range(&self) -> Option<(u64, u64)> {
+        // Hotpluggable memory locates behind the guest ram above 4G.
+        let high_mem = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize];
+        let base = std::cmp::max(self.sys_mem.memory_end_address().raw_value(), high_mem.0);
+        Some((base, high_mem.0 + high_mem.1 - base))
+    }
+
     fn load_boot_source(
         &self,
         fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
@@ -441,6 +634,9 @@ impl MachineOps for StdMachine {
         locked_vm
             .init_ich9_lpc(clone_vm)
             .chain_err(|| "Fail to init LPC bridge")?;
+        locked_vm
+            .init_cpu_controller(vm.clone())
+            .chain_err(|| "Fail to init cpu hotplug controller")?;
         locked_vm.add_devices(vm_config)?;
 
         let (boot_config, fwcfg) = if !is_migrate {
@@ -452,9 +648,11 @@ impl MachineOps for StdMachine {
         locked_vm.cpus.extend(<Self as MachineOps>::init_vcpu(
             vm.clone(),
             vm_config.machine_config.nr_cpus,
+            vm_config.machine_config.max_cpus,
             &vcpu_fds,
             &boot_config,
         )?);
+        locked_vm.boot_config = boot_config;
 
         if let Some(fwcfg) = fwcfg {
             locked_vm
@@ -558,24 +756,16 @@ impl AcpiBuilder for StdMachine {
     ) -> super::errors::Result<u64> {
         let mut dsdt = AcpiTable::new(*b"DSDT", 2, *b"STRATO", *b"VIRTDSDT", 1);
 
-        // 1. CPU info.
-        let cpus_count = self.cpus.len() as u64;
+        // 1. Create pci host bridge node.
         let mut sb_scope = AmlScope::new("\\_SB");
-        for cpu_id in 0..cpus_count {
-            let mut dev = AmlDevice::new(format!("C{:03}", cpu_id).as_str());
-            dev.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0007".to_string())));
-            dev.append_child(AmlNameDecl::new("_UID", AmlInteger(cpu_id)));
-            sb_scope.append_child(dev);
-        }
-
-        // 2. Create pci host bridge node.
         sb_scope.append_child(self.pci_host.lock().unwrap().clone());
         dsdt.append_child(sb_scope.aml_bytes().as_slice());
 
-        // 3. Info of devices attached to system bus.
+        // 2. Info of devices attached to system bus, including CPU info which
+        // is built by cpu hotplug controller.
         dsdt.append_child(self.sysbus.aml_bytes().as_slice());
 
-        // 4. Add _S5 sleep state.
+        // 3. Add _S5 sleep state.
         let mut package = AmlPackage::new(4);
         package.append_child(AmlInteger(5));
         package.append_child(AmlInteger(0));
@@ -622,16 +812,21 @@ impl AcpiBuilder for StdMachine {
         };
         madt.append_child(ioapic.aml_bytes().as_ref());
 
-        self.cpus.iter().for_each(|cpu| {
+        for cpu_id in 0..self.cpu_topo.max_cpus {
             let lapic = AcpiLocalApic {
                 type_id: 0,
                 length: size_of::<AcpiLocalApic>() as u8,
-                processor_uid: cpu.id(),
-                apic_id: cpu.id(),
-                flags: 1, // Flags: enabled.
+                processor_uid: cpu_id,
+                apic_id: cpu_id,
+                // Flags: enabled, or online capable for hotpluggable vcpus.
+                flags: if self.cpu_topo.get_mask(cpu_id as usize) == 1 {
+                    1
+                } else {
+                    1 << 1
+                },
             };
             madt.append_child(&lapic.aml_bytes());
-        });
+        }
 
         let mut locked_acpi_data = acpi_data.lock().unwrap();
         let madt_begin = locked_acpi_data.len() as u32;
@@ -650,6 +845,61 @@ impl AcpiBuilder for StdMachine {
 
         Ok(madt_begin as u64)
     }
+
+    fn build_srat_table(
+        &self,
+        acpi_data: &Arc<Mutex<Vec<u8>>>,
+        loader: &mut TableLoader,
+    ) -> super::errors::Result<u64> {
+        let numa_nodes = match &self.numa_nodes {
+            Some(numa_nodes) => numa_nodes,
+            None => bail!("No numa nodes configured"),
+        };
+        let mut srat = AcpiTable::new(*b"SRAT", 1, *b"STRATO", *b"VIRTSRAT", 1);
+        // Reserved, set to 1 for backward compatibility.
+        srat.append_child(&[1_u8; 4_usize]);
+        // Reserved
+        srat.append_child(&[0_u8; 8_usize]);
+
+        for (id, node) in numa_nodes.iter() {
+            for cpu in node.cpus.iter() {
+                let proximity = id.to_le_bytes();
+                let processor_affinity = AcpiSratProcessorAffinity {
+                    type_id: 0,
+                    length: size_of::<AcpiSratProcessorAffinity>() as u8,
+                    proximity_lo: proximity[0],
+                    local_apic_id: *cpu,
+                    flags: 1, // Flags: enabled.
+                    proximity_hi: [proximity[1], proximity[2], proximity[3]],
+                    ..Default::default()
+                };
+                srat.append_child(&processor_affinity.aml_bytes());
+            }
+        }
+        let mem_size = self
+            .vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .mem_config
+            .mem_size;
+        build_srat_mem_affinity(&self.arch_ram_ranges(mem_size), numa_nodes, &mut srat);
+
+        let mut locked_acpi_data = acpi_data.lock().unwrap();
+        let srat_begin = locked_acpi_data.len() as u32;
+        locked_acpi_data.extend(srat.aml_bytes());
+        let srat_end = locked_acpi_data.len() as u32;
+        drop(locked_acpi_data);
+
+        loader.add_cksum_entry(
+            ACPI_TABLE_FILE,
+            srat_begin + TABLE_CHECKSUM_OFFSET,
+            srat_begin,
+            srat_end - srat_begin,
+        )?;
+
+        Ok(srat_begin as u64)
+    }
 }
 
 impl MachineLifecycle for StdMachine {
@@ -765,6 +1015,22 @@ impl MigrateInterface for StdMachine {
                     );
                 }
             }
+            Ok((path_type, path)) if path_type == UnixPath::Tcp || path_type == UnixPath::Unix => {
+                if let Err(e) = <Self as MachineOps>::start_live_migration(
+                    path_type,
+                    &path,
+                    &self.cpus,
+                    #[cfg(target_arch = "aarch64")]
+                    &self.irq_chip,
+                    &self.vm_state,
+                ) {
+                    error!("Failed to migrate to \'{}\': {}", path, e.display_chain());
+                    return Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+            }
             _ => {
                 return Response::create_error_response(
                     qmp_schema::QmpErrorClass::GenericError(format!("Invalid uri: {}", uri)),
@@ -786,6 +1052,43 @@ impl MigrateInterface for StdMachine {
     }
 }
 
+impl HumanMonitorInterface for StdMachine {
+    fn hmp_info_network(&self) -> String {
+        virtio::hmp_info_network()
+    }
+
+    fn hmp_info_pci(&self) -> String {
+        hmp::info_pci(&self.pci_host)
+    }
+
+    fn hmp_info_mtree(&self) -> String {
+        hmp::info_mtree(&self.sys_mem, &self.sys_io)
+    }
+
+    fn hmp_info_qtree(&self) -> String {
+        hmp::info_qtree(&self.sysbus, Some(&self.pci_host))
+    }
+
+    fn hmp_info_registers(&self, cpu_index: usize) -> machine_manager::errors::Result<String> {
+        hmp::info_registers(&self.cpus, cpu_index)
+    }
+
+    fn hmp_read_memory(
+        &self,
+        addr: u64,
+        len: u64,
+        cpu_index: Option<usize>,
+    ) -> machine_manager::errors::Result<Vec<u8>> {
+        hmp::read_memory(&self.sys_mem, &self.cpus, addr, len, cpu_index)
+    }
+}
+
+impl MetricsInterface for StdMachine {
+    fn collect_metrics(&self) -> Vec<Metric> {
+        metrics::collect_metrics(&self.cpus)
+    }
+}
+
 impl MachineInterface for StdMachine {}
 impl MachineExternalInterface for StdMachine {}
 
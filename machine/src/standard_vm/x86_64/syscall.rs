@@ -10,9 +10,10 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use cpu::{VCPU_RESET_SIGNAL, VCPU_TASK_SIGNAL};
 use hypervisor::kvm::*;
 use util::seccomp::{BpfRule, SeccompCmpOpt};
-use util::tap::{TUNSETIFF, TUNSETOFFLOAD, TUNSETVNETHDRSZ};
+use util::tap::{TUNGETIFF, TUNSETIFF, TUNSETOFFLOAD, TUNSETQUEUE, TUNSETVNETHDRSZ};
 use vfio::{
     VFIO_CHECK_EXTENSION, VFIO_DEVICE_GET_INFO, VFIO_DEVICE_GET_IRQ_INFO,
     VFIO_DEVICE_GET_REGION_INFO, VFIO_DEVICE_RESET, VFIO_DEVICE_SET_IRQS, VFIO_GET_API_VERSION,
@@ -49,12 +50,21 @@ const FIOCLEX: u32 = 0x5451;
 const FIONBIO: u32 = 0x5421;
 const KVM_RUN: u32 = 0xae80;
 
+/// See: https://elixir.bootlin.com/linux/v4.19.123/source/include/uapi/linux/prctl.h
+const PR_SET_NAME: u32 = 15;
+
+// Syscall `rseq` is not defined in libc crate yet.
+#[cfg(target_arch = "x86_64")]
+const SYS_RSEQ: i64 = 334;
+#[cfg(target_arch = "aarch64")]
+const SYS_RSEQ: i64 = 293;
+
 /// Create a syscall whitelist for seccomp.
 ///
 /// # Notes
 /// This allowlist limit syscall with:
-/// * x86_64-unknown-gnu: 46 syscalls
-/// * x86_64-unknown-musl: 48 syscalls
+/// * x86_64-unknown-gnu: 61 syscalls
+/// * x86_64-unknown-musl: 63 syscalls
 /// To reduce performance losses, the syscall rules is ordered by frequency.
 pub fn syscall_whitelist() -> Vec<BpfRule> {
     vec![
@@ -68,6 +78,7 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_epoll_wait),
         BpfRule::new(libc::SYS_io_getevents),
         BpfRule::new(libc::SYS_io_submit),
+        BpfRule::new(libc::SYS_io_uring_enter),
         BpfRule::new(libc::SYS_dup),
         BpfRule::new(libc::SYS_close),
         BpfRule::new(libc::SYS_eventfd2),
@@ -78,6 +89,8 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_recvfrom),
         BpfRule::new(libc::SYS_mremap),
         BpfRule::new(libc::SYS_io_setup),
+        BpfRule::new(libc::SYS_io_uring_setup),
+        BpfRule::new(libc::SYS_io_uring_register),
         BpfRule::new(libc::SYS_brk),
         BpfRule::new(libc::SYS_fcntl)
             .add_constraint(SeccompCmpOpt::Eq, 1, F_DUPFD_CLOEXEC)
@@ -89,7 +102,6 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_openat),
         BpfRule::new(libc::SYS_sigaltstack),
         BpfRule::new(libc::SYS_mmap),
-        #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_mprotect),
         BpfRule::new(libc::SYS_munmap),
         BpfRule::new(libc::SYS_accept4),
@@ -115,9 +127,11 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_newfstatat),
         BpfRule::new(libc::SYS_pread64),
         BpfRule::new(libc::SYS_pwrite64),
+        BpfRule::new(libc::SYS_fallocate),
         BpfRule::new(libc::SYS_statx),
         BpfRule::new(libc::SYS_mkdir),
         BpfRule::new(libc::SYS_unlink),
+        BpfRule::new(libc::SYS_rename),
         BpfRule::new(libc::SYS_madvise)
             .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
             .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32)
@@ -126,6 +140,40 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_readlinkat),
         #[cfg(target_env = "musl")]
         BpfRule::new(libc::SYS_readlink),
+        // QMP command `migrate` connects to the destination.
+        BpfRule::new(libc::SYS_socket),
+        BpfRule::new(libc::SYS_connect),
+        // Vhost-user devices reconnect to the slave and set the timeouts of the
+        // socket after seccomp rules registered.
+        BpfRule::new(libc::SYS_setsockopt)
+            .add_constraints(&[
+                (SeccompCmpOpt::Eq, 1, libc::SOL_SOCKET as u32),
+                (SeccompCmpOpt::Eq, 2, libc::SO_RCVTIMEO as u32),
+            ])
+            .add_constraints(&[
+                (SeccompCmpOpt::Eq, 1, libc::SOL_SOCKET as u32),
+                (SeccompCmpOpt::Eq, 2, libc::SO_SNDTIMEO as u32),
+            ]),
+        // Socket chardevs get the result of the nonblocking connection when
+        // reconnecting to the server.
+        BpfRule::new(libc::SYS_getsockopt).add_constraints(&[
+            (SeccompCmpOpt::Eq, 1, libc::SOL_SOCKET as u32),
+            (SeccompCmpOpt::Eq, 2, libc::SO_ERROR as u32),
+        ]),
+        // Threads created after seccomp rules registered, e.g. the migration
+        // thread, hotplugged vcpus and aio workers, need the syscalls below
+        // and `mprotect` for the guard page of thread stack.
+        BpfRule::new(libc::SYS_clone),
+        #[cfg(target_env = "gnu")]
+        BpfRule::new(libc::SYS_clone3),
+        BpfRule::new(libc::SYS_set_robust_list),
+        BpfRule::new(SYS_RSEQ),
+        // Set thread name.
+        BpfRule::new(libc::SYS_prctl).add_constraint(SeccompCmpOpt::Eq, 0, PR_SET_NAME),
+        // Threads of hotplugged vcpus register the handlers of vcpu signals.
+        BpfRule::new(libc::SYS_rt_sigaction)
+            .add_constraint(SeccompCmpOpt::Eq, 0, VCPU_TASK_SIGNAL as u32)
+            .add_constraint(SeccompCmpOpt::Eq, 0, VCPU_RESET_SIGNAL as u32),
     ]
 }
 
@@ -139,6 +187,8 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, FIONBIO)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_RUN)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_DEVICE_ATTR)
+        // Get dirty pages of memory slots in live migration.
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DIRTY_LOG() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_USER_MEMORY_REGION)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_IOEVENTFD)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SIGNAL_MSI)
@@ -158,8 +208,10 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_GET_FEATURES() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_RESET_OWNER() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETIFF() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, TUNGETIFF() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETOFFLOAD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETVNETHDRSZ() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETQUEUE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_GSI_ROUTING() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_IRQFD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VFIO_DEVICE_SET_IRQS() as u32)
@@ -201,4 +253,8 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_LAPIC() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_MSRS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_VCPU_EVENTS() as u32)
+        // Create hotplugged vcpus and get their capabilities.
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_CREATE_VCPU() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_CHECK_EXTENSION() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_MSR_INDEX_LIST() as u32)
 }
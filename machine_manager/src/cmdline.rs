@@ -19,6 +19,7 @@ use util::unix::{limit_permission, parse_uri};
 use crate::{
     config::{add_trace_events, ChardevType, CmdParser, MachineType, VmConfig},
     errors::{Result, ResultExt},
+    socket::MonitorMode,
     temp_cleaner::TempCleaner,
 };
 
@@ -94,8 +95,8 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("smp")
             .long("smp")
-            .value_name("[cpus=]n")
-            .help("set the number of CPUs to 'n' (default: 1)")
+            .value_name("[cpus=]n[,maxcpus=cpus]")
+            .help("set the number of CPUs to 'n' (default: 1), and the maximum number of hotpluggable CPUs to 'maxcpus'")
             .takes_value(true),
         )
         .arg(
@@ -145,7 +146,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             Arg::with_name("drive")
             .multiple(true)
             .long("drive")
-            .value_name("file=path,id=str[,readonly=][,direct=][,serial=][,iothread=][iops=]")
+            .value_name("file=path,id=str[,readonly=][,direct=][,serial=][,iothread=][,throttling.*=]")
             .help("use 'file' as a drive image")
             .takes_values(true),
         )
@@ -154,7 +155,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .multiple(true)
             .long("netdev")
             .value_name(
-                "id=str,netdev=str[,mac=][,fds=][,vhost=on|off][,vhostfd=][,iothread=]",
+                "id=str,netdev=str[,mac=][,fds=][,vhost=on|off][,vhostfd=][,iothread=][,rx-bps=][,rx-pps=][,tx-bps=][,tx-pps=]",
             )
             .help("configure a host TAP network with ID 'str'")
             .takes_values(true),
@@ -222,7 +223,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("incoming")
             .long("incoming")
-            .help("wait for the URI to be specified via migrate_incoming")
+            .help("restore from snapshot with 'file:<dir>', or wait for live migration on 'tcp:<host>:<port>' or 'unix:<path>'")
             .value_name("incoming")
             .takes_value(true),
         )
@@ -234,11 +235,19 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .help("add object")
             .takes_values(true),
         )
+        .arg(
+            Arg::with_name("numa")
+            .multiple(true)
+            .long("numa")
+            .value_name("node,nodeid=0,cpus=0-1,memdev=mem0 | dist,src=0,dst=1,val=20")
+            .help("add guest numa node or distance between numa nodes")
+            .takes_values(true),
+        )
         .arg(
             Arg::with_name("mon")
             .long("mon")
-            .value_name("chardev=chardev_id,id=mon_id[,mode=control]")
-            .help("-mon is another way to create qmp channel. To use it, the chardev should be specified")
+            .value_name("chardev=chardev_id,id=mon_id[,mode=control|readline]")
+            .help("-mon is another way to create qmp channel, or human monitor channel with mode=readline. To use it, the chardev should be specified")
             .takes_value(true),
         )
         .arg(
@@ -363,6 +372,14 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .help("specify the file lists trace events to enable")
             .takes_value(true),
         )
+        .arg(
+            Arg::with_name("metrics")
+            .multiple(false)
+            .long("metrics")
+            .value_name("path=<file:path|unix:path>[,interval=<secs>][,format=prometheus|json]")
+            .help("periodically dump a snapshot of VMM metrics to a file or unix socket")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("global")
             .multiple(true)
@@ -415,11 +432,13 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
     );
     add_args_to_config_multi!((args.values_of("drive")), vm_cfg, add_drive);
     add_args_to_config_multi!((args.values_of("object")), vm_cfg, add_object);
+    add_args_to_config_multi!((args.values_of("numa")), vm_cfg, add_numa);
     add_args_to_config_multi!((args.values_of("netdev")), vm_cfg, add_netdev);
     add_args_to_config_multi!((args.values_of("chardev")), vm_cfg, add_chardev);
     add_args_to_config_multi!((args.values_of("device")), vm_cfg, add_devices);
     add_args_to_config_multi!((args.values_of("global")), vm_cfg, add_global_config);
     add_args_to_config!((args.value_of("serial")), vm_cfg, add_serial);
+    add_args_to_config!((args.value_of("metrics")), vm_cfg, add_metrics);
 
     if let Some(s) = args.value_of("trace") {
         add_trace_events(&s)?;
@@ -443,7 +462,10 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
 /// # Errors
 ///
 /// The value of `qmp` is illegel.
-pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<Vec<UnixListener>> {
+pub fn check_api_channel(
+    args: &ArgMatches,
+    vm_config: &mut VmConfig,
+) -> Result<Vec<(UnixListener, MonitorMode)>> {
     let mut sock_paths = Vec::new();
     if let Some(qmp_config) = args.value_of("qmp") {
         let mut cmd_parser = CmdParser::new("qmp");
@@ -453,7 +475,7 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
         if let Some(uri) = cmd_parser.get_value::<String>("")? {
             let (_api_type, api_path) =
                 parse_uri(&uri).chain_err(|| "Failed to parse qmp socket path")?;
-            sock_paths.push(api_path);
+            sock_paths.push((api_path, MonitorMode::Control));
         } else {
             bail!("No uri found for qmp");
         }
@@ -476,19 +498,23 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
             bail!("Argument \'chardev\'  is missing for \'mon\'");
         };
 
-        if let Some(mode) = cmd_parser.get_value::<String>("mode")? {
-            if mode != *"control" {
-                bail!("Invalid \'mode\' parameter: {:?} for monitor", &mode);
+        let mode = match cmd_parser.get_value::<String>("mode")? {
+            Some(mode) if mode == "control" => MonitorMode::Control,
+            Some(mode) if mode == "readline" => MonitorMode::Readline,
+            Some(mode) => bail!("Invalid \'mode\' parameter: {:?} for monitor", &mode),
+            None => {
+                bail!("Argument \'mode\' of \'mon\' should be set to \'control\' or \'readline\'.")
             }
-        } else {
-            bail!("Argument \'mode\' of \'mon\' should be set to \'control\'.");
-        }
+        };
 
         if let Some(cfg) = vm_config.chardev.remove(&chardev) {
-            if let ChardevType::Socket(path) = cfg.backend {
-                sock_paths.push(path);
+            if let ChardevType::Socket {
+                path, server: true, ..
+            } = cfg.backend
+            {
+                sock_paths.push((path, mode));
             } else {
-                bail!("Only socket-type of chardev can be used for monitor");
+                bail!("Only server socket-type of chardev can be used for monitor");
             }
         } else {
             bail!("No chardev found: {}", &chardev);
@@ -499,11 +525,12 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
         bail!("Please use \'-qmp\' or \'-mon\' to give a qmp path for Unix socket");
     }
     let mut listeners = Vec::new();
-    for path in sock_paths {
-        listeners.push(
+    for (path, mode) in sock_paths {
+        listeners.push((
             bind_socket(path.clone())
                 .chain_err(|| format!("Failed to bind socket for path: {:?}", &path))?,
-        )
+            mode,
+        ))
     }
 
     Ok(listeners)
@@ -22,6 +22,11 @@ use crate::config::{CmdParser, ExBool, VmConfig};
 pub struct BalloonConfig {
     pub id: String,
     pub deflate_on_oom: bool,
+    /// Guest reports free pages, which are released by host.
+    pub free_page_reporting: bool,
+    /// Interval in seconds to request memory statistics from guest, 0 means
+    /// the statistics queue is disabled.
+    pub stats_polling_interval: u32,
 }
 
 impl ConfigCheck for BalloonConfig {
@@ -49,7 +54,9 @@ pub fn parse_balloon(vm_config: &mut VmConfig, balloon_config: &str) -> Result<B
         .push("addr")
         .push("multifunction")
         .push("id")
-        .push("deflate-on-oom");
+        .push("deflate-on-oom")
+        .push("free-page-reporting")
+        .push("stats-polling-interval");
     cmd_parser.parse(balloon_config)?;
 
     pci_args_check(&cmd_parser)?;
@@ -57,6 +64,12 @@ pub fn parse_balloon(vm_config: &mut VmConfig, balloon_config: &str) -> Result<B
     if let Some(default) = cmd_parser.get_value::<ExBool>("deflate-on-oom")? {
         balloon.deflate_on_oom = default.into();
     }
+    if let Some(reporting) = cmd_parser.get_value::<ExBool>("free-page-reporting")? {
+        balloon.free_page_reporting = reporting.into();
+    }
+    if let Some(interval) = cmd_parser.get_value::<u32>("stats-polling-interval")? {
+        balloon.stats_polling_interval = interval;
+    }
     if let Some(id) = cmd_parser.get_value::<String>("id")? {
         balloon.id = id;
     }
@@ -82,6 +95,26 @@ mod tests {
         let balloon_configs = bln_cfg_res.unwrap();
         assert_eq!(balloon_configs.id, "balloon0".to_string());
         assert_eq!(balloon_configs.deflate_on_oom, true);
+        assert_eq!(balloon_configs.free_page_reporting, false);
+        assert_eq!(balloon_configs.stats_polling_interval, 0);
+
+        let mut vm_config = VmConfig::default();
+        let bln_cfg_res = parse_balloon(
+            &mut vm_config,
+            "virtio-balloon-device,free-page-reporting=on,stats-polling-interval=5,id=balloon0",
+        );
+        assert!(bln_cfg_res.is_ok());
+        let balloon_configs = bln_cfg_res.unwrap();
+        assert_eq!(balloon_configs.deflate_on_oom, false);
+        assert_eq!(balloon_configs.free_page_reporting, true);
+        assert_eq!(balloon_configs.stats_polling_interval, 5);
+
+        let mut vm_config = VmConfig::default();
+        assert!(parse_balloon(
+            &mut vm_config,
+            "virtio-balloon-device,stats-polling-interval=-1,id=balloon0",
+        )
+        .is_err());
     }
 
     #[test]
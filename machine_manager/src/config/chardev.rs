@@ -16,25 +16,117 @@ use super::{
     errors::{ErrorKind, Result, ResultExt},
     get_pci_bdf, pci_args_check, PciBdf,
 };
-use crate::config::{CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH, MAX_STRING_LENGTH};
+use crate::config::{
+    memory_unit_conversion, CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH,
+    MAX_STRING_LENGTH,
+};
+use crate::qmp::guest_agent::GUEST_AGENT_PORT_NAME;
 
 const MAX_GUEST_CID: u64 = 4_294_967_295;
 const MIN_GUEST_CID: u64 = 3;
+/// Default number of ports of virtio-serial device.
+const DEFAULT_SERIAL_PORTS: u32 = 3;
+/// Maximum number of ports of virtio-serial device.
+const MAX_SERIAL_PORTS: u32 = 31;
+/// Maximum number of ports of virtio-serial-device, which is limited by the
+/// number of virtqueues of virtio mmio device.
+const MAX_MMIO_SERIAL_PORTS: u32 = 3;
+/// Arguments of chardev which are only supported by some types of backend.
+const BACKEND_SPECIFIC_ARGS: [&str; 7] = [
+    "host",
+    "port",
+    "server",
+    "nowait",
+    "wait",
+    "reconnect",
+    "size",
+];
+/// Default number of rotated log files of chardev.
+const DEFAULT_LOG_COUNT: u32 = 1;
+/// Maximum number of rotated log files of chardev.
+const MAX_LOG_COUNT: u32 = 32;
+/// Default size of ringbuf-type chardev.
+const DEFAULT_RINGBUF_SIZE: usize = 65536;
 
 /// Charecter device options.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ChardevType {
     Stdio,
     Pty,
-    Socket(String),
+    /// Unix domain socket, listening on `path` if `server` is true, or connecting to it otherwise.
+    Socket {
+        path: String,
+        server: bool,
+        /// Do not wait for a client to connect before starting the VM, only for server mode.
+        nowait: bool,
+        /// Seconds to wait before connecting again after the connection fails or is closed,
+        /// only for client mode. Zero means never reconnect.
+        reconnect: u64,
+    },
+    /// TCP socket, listening on `host:port` if `server` is true, or connecting to it otherwise.
+    TcpSocket {
+        host: String,
+        port: u16,
+        server: bool,
+        nowait: bool,
+        reconnect: u64,
+    },
     File(String),
+    /// Memory buffer holding the latest `size` bytes of output.
+    Ringbuf {
+        size: usize,
+    },
+    /// Discard all output and never produce input.
+    Null,
 }
 
-/// Config structure for virtio-console.
+/// Config structure for port of virtio-serial, such as virtconsole and virtserialport.
 #[derive(Debug, Clone)]
-pub struct VirtioConsole {
+pub struct VirtioSerialPort {
     pub id: String,
-    pub chardev: ChardevConfig,
+    /// Character device of the port. The guest agent port without chardev is
+    /// served by the guest agent proxy of QMP.
+    pub chardev: Option<ChardevConfig>,
+    /// Port number, allocated by virtio-serial device if not set.
+    pub nr: Option<u32>,
+    /// Name of the port, which is reported to guest, such as `org.qemu.guest_agent.0`.
+    pub name: Option<String>,
+    /// Whether the port is a console port.
+    pub is_console: bool,
+}
+
+impl ConfigCheck for VirtioSerialPort {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "virtio serial port id".to_string(),
+                MAX_STRING_LENGTH,
+            )
+            .into());
+        }
+        if let Some(name) = &self.name {
+            if name.len() > MAX_STRING_LENGTH {
+                return Err(ErrorKind::StringLengthTooLong(
+                    "virtio serial port name".to_string(),
+                    MAX_STRING_LENGTH,
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Config structure for log file of character device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChardevLogConfig {
+    /// Path of the log file, which records the output of guest.
+    pub path: String,
+    /// The log file is rotated when it exceeds `size` bytes. Zero means never rotate.
+    pub size: u64,
+    /// Number of rotated log files kept, named as `path.1`, `path.2`, ...
+    pub count: u32,
 }
 
 /// Config structure for character device.
@@ -42,6 +134,7 @@ pub struct VirtioConsole {
 pub struct ChardevConfig {
     pub id: String,
     pub backend: ChardevType,
+    pub logfile: Option<ChardevLogConfig>,
 }
 
 impl ConfigCheck for ChardevConfig {
@@ -54,7 +147,7 @@ impl ConfigCheck for ChardevConfig {
             .into());
         }
 
-        if let ChardevType::Socket(path) | ChardevType::File(path) = &self.backend {
+        if let ChardevType::Socket { path, .. } | ChardevType::File(path) = &self.backend {
             if path.len() > MAX_PATH_LENGTH {
                 return Err(ErrorKind::StringLengthTooLong(
                     "socket path".to_string(),
@@ -63,52 +156,119 @@ impl ConfigCheck for ChardevConfig {
                 .into());
             }
         }
+        if let ChardevType::TcpSocket { host, .. } = &self.backend {
+            if host.len() > MAX_STRING_LENGTH {
+                return Err(ErrorKind::StringLengthTooLong(
+                    "socket host".to_string(),
+                    MAX_STRING_LENGTH,
+                )
+                .into());
+            }
+        }
+        if let Some(logfile) = &self.logfile {
+            if logfile.path.len() > MAX_PATH_LENGTH {
+                return Err(ErrorKind::StringLengthTooLong(
+                    "chardev logfile".to_string(),
+                    MAX_PATH_LENGTH,
+                )
+                .into());
+            }
+            if logfile.count > MAX_LOG_COUNT {
+                return Err(ErrorKind::IllegalValue(
+                    "chardev logcount".to_string(),
+                    0,
+                    true,
+                    MAX_LOG_COUNT as u64,
+                    true,
+                )
+                .into());
+            }
+        }
+        if let ChardevType::Ringbuf { size } = &self.backend {
+            if *size == 0 || !size.is_power_of_two() {
+                bail!(
+                    "Size of ringbuf-type chardev must be a power of 2, got {}",
+                    size
+                );
+            }
+        }
         Ok(())
     }
 }
 
-fn check_chardev_args(cmd_parser: CmdParser) -> Result<()> {
+fn check_chardev_args(cmd_parser: &CmdParser) -> Result<()> {
     if let Some(chardev_type) = cmd_parser.get_value::<String>("")? {
         let chardev_str = chardev_type.as_str();
-        let server = cmd_parser.get_value::<String>("server")?;
-        let nowait = cmd_parser.get_value::<String>("nowait")?;
-        match chardev_str {
-            "stdio" | "pty" | "file" => {
-                if server.is_some() {
-                    bail!(
-                        "Chardev of {}-type does not support \'server\' argument",
-                        chardev_str
-                    );
-                }
-                if nowait.is_some() {
-                    bail!(
-                        "Chardev of {}-type does not support \'nowait\' argument",
-                        chardev_str
-                    );
-                }
+        let supported_args: &[&str] = match chardev_str {
+            "stdio" | "pty" | "file" | "null" => &[],
+            "socket" => &["host", "port", "server", "nowait", "wait", "reconnect"],
+            "ringbuf" => &["size"],
+            _ => return Ok(()),
+        };
+        for arg in BACKEND_SPECIFIC_ARGS.iter() {
+            if cmd_parser.get_value::<String>(arg)?.is_some() && !supported_args.contains(arg) {
+                bail!(
+                    "Chardev of {}-type does not support \'{}\' argument",
+                    chardev_str,
+                    arg
+                );
             }
-            "socket" => {
-                if let Some(server) = server {
-                    if server.ne("") {
-                        bail!("No parameter needed for server");
-                    }
-                } else {
-                    bail!("Argument \'server\' is needed for socket-type chardev.");
-                }
-                if let Some(nowait) = nowait {
-                    if nowait.ne("") {
-                        bail!("No parameter needed for nowait");
-                    }
-                } else {
-                    bail!("Argument \'nowait\' is needed for socket-type chardev.");
+        }
+        for arg in ["server", "nowait"].iter() {
+            if let Some(value) = cmd_parser.get_value::<String>(arg)? {
+                if !value.is_empty() {
+                    bail!("No parameter needed for {}", arg);
                 }
             }
-            _ => (),
         }
     }
     Ok(())
 }
 
+fn parse_socket_chardev(cmd_parser: &CmdParser) -> Result<ChardevType> {
+    let server = cmd_parser.get_value::<String>("server")?.is_some();
+    let nowait = cmd_parser.get_value::<String>("nowait")?.is_some();
+    let wait = cmd_parser.get_value::<ExBool>("wait")?.map(bool::from);
+    let reconnect = cmd_parser.get_value::<u64>("reconnect")?;
+    if server {
+        if nowait && wait == Some(true) {
+            bail!("Argument \'nowait\' conflicts with \'wait=on\' for socket-type chardev.");
+        }
+        if reconnect.is_some() {
+            bail!("Argument \'reconnect\' is only supported by client socket-type chardev.");
+        }
+    } else if nowait || wait.is_some() {
+        bail!("Argument \'nowait\' and \'wait\' are only supported by server socket-type chardev.");
+    }
+    let nowait = nowait || wait == Some(false);
+    let reconnect = reconnect.unwrap_or(0);
+
+    let path = cmd_parser.get_value::<String>("path")?;
+    let host = cmd_parser.get_value::<String>("host")?;
+    let port = cmd_parser.get_value::<u16>("port")?;
+    match (path, host, port) {
+        (Some(path), None, None) => Ok(ChardevType::Socket {
+            path,
+            server,
+            nowait,
+            reconnect,
+        }),
+        (None, Some(host), Some(port)) => Ok(ChardevType::TcpSocket {
+            host,
+            port,
+            server,
+            nowait,
+            reconnect,
+        }),
+        (None, None, None) => Err(ErrorKind::FieldIsMissing("path", "socket-type chardev").into()),
+        (None, _, None) => Err(ErrorKind::FieldIsMissing("port", "socket-type chardev").into()),
+        (None, None, _) => Err(ErrorKind::FieldIsMissing("host", "socket-type chardev").into()),
+        _ => {
+            bail!("Argument \'path\' conflicts with \'host\' and \'port\' for socket-type chardev.")
+        }
+    }
+}
+
 pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
     let chardev_id = if let Some(chardev_id) = cmd_parser.get_value::<String>("id")? {
         chardev_id
@@ -117,18 +277,12 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
     };
     let backend = cmd_parser.get_value::<String>("")?;
     let path = cmd_parser.get_value::<String>("path")?;
-    check_chardev_args(cmd_parser)?;
+    check_chardev_args(&cmd_parser)?;
     let chardev_type = if let Some(backend) = backend {
         match backend.as_str() {
             "stdio" => ChardevType::Stdio,
             "pty" => ChardevType::Pty,
-            "socket" => {
-                if let Some(path) = path {
-                    ChardevType::Socket(path)
-                } else {
-                    return Err(ErrorKind::FieldIsMissing("path", "socket-type chardev").into());
-                }
-            }
+            "socket" => parse_socket_chardev(&cmd_parser)?,
             "file" => {
                 if let Some(path) = path {
                     ChardevType::File(path)
@@ -136,6 +290,12 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
                     return Err(ErrorKind::FieldIsMissing("path", "file-type chardev").into());
                 }
             }
+            "ringbuf" => ChardevType::Ringbuf {
+                size: cmd_parser
+                    .get_value::<usize>("size")?
+                    .unwrap_or(DEFAULT_RINGBUF_SIZE),
+            },
+            "null" => ChardevType::Null,
             _ => return Err(ErrorKind::InvalidParam(backend, "chardev".to_string()).into()),
         }
     } else {
@@ -145,33 +305,89 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
     Ok(ChardevConfig {
         id: chardev_id,
         backend: chardev_type,
+        logfile: parse_chardev_logfile(&cmd_parser)?,
     })
 }
 
-pub fn parse_virtconsole(vm_config: &mut VmConfig, config_args: &str) -> Result<VirtioConsole> {
-    let mut cmd_parser = CmdParser::new("virtconsole");
-    cmd_parser.push("").push("id").push("chardev");
-    cmd_parser.parse(config_args)?;
+fn parse_chardev_logfile(cmd_parser: &CmdParser) -> Result<Option<ChardevLogConfig>> {
+    let size = if let Some(size) = cmd_parser.get_value::<String>("logsize")? {
+        Some(memory_unit_conversion(&size)?)
+    } else {
+        None
+    };
+    let count = cmd_parser.get_value::<u32>("logcount")?;
+    if let Some(path) = cmd_parser.get_value::<String>("logfile")? {
+        Ok(Some(ChardevLogConfig {
+            path,
+            size: size.unwrap_or(0),
+            count: count.unwrap_or(DEFAULT_LOG_COUNT),
+        }))
+    } else if size.is_some() || count.is_some() {
+        bail!("Argument \'logsize\' and \'logcount\' are only supported with \'logfile\'.");
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn parse_virtconsole(vm_config: &mut VmConfig, config_args: &str) -> Result<VirtioSerialPort> {
+    parse_serial_port(vm_config, config_args, true)
+}
 
-    let chardev_name = if let Some(chardev) = cmd_parser.get_value::<String>("chardev")? {
-        chardev
+pub fn parse_virtserialport(
+    vm_config: &mut VmConfig,
+    config_args: &str,
+) -> Result<VirtioSerialPort> {
+    parse_serial_port(vm_config, config_args, false)
+}
+
+fn parse_serial_port(
+    vm_config: &mut VmConfig,
+    config_args: &str,
+    is_console: bool,
+) -> Result<VirtioSerialPort> {
+    let dev_type = if is_console {
+        "virtconsole"
     } else {
-        return Err(ErrorKind::FieldIsMissing("chardev", "virtconsole").into());
+        "virtserialport"
     };
+    let mut cmd_parser = CmdParser::new(dev_type);
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("chardev")
+        .push("nr")
+        .push("name");
+    cmd_parser.parse(config_args)?;
 
     let id = if let Some(chardev_id) = cmd_parser.get_value::<String>("id")? {
         chardev_id
     } else {
-        return Err(ErrorKind::FieldIsMissing("id", "virtconsole").into());
+        return Err(ErrorKind::FieldIsMissing("id", dev_type).into());
     };
+    let nr = cmd_parser.get_value::<u32>("nr")?;
+    let name = cmd_parser.get_value::<String>("name")?;
 
-    if let Some(char_dev) = vm_config.chardev.remove(&chardev_name) {
-        return Ok(VirtioConsole {
-            id,
-            chardev: char_dev,
-        });
-    }
-    bail!("Chardev {:?} not found or is in use", &chardev_name);
+    let chardev = if let Some(chardev_name) = cmd_parser.get_value::<String>("chardev")? {
+        if let Some(char_dev) = vm_config.chardev.remove(&chardev_name) {
+            Some(char_dev)
+        } else {
+            bail!("Chardev {:?} not found or is in use", &chardev_name);
+        }
+    } else if !is_console && name.as_deref() == Some(GUEST_AGENT_PORT_NAME) {
+        None
+    } else {
+        return Err(ErrorKind::FieldIsMissing("chardev", dev_type).into());
+    };
+
+    let port = VirtioSerialPort {
+        id,
+        chardev,
+        nr,
+        name,
+        is_console,
+    };
+    port.check()?;
+    Ok(port)
 }
 
 impl VmConfig {
@@ -182,8 +398,16 @@ impl VmConfig {
             .push("")
             .push("id")
             .push("path")
+            .push("host")
+            .push("port")
             .push("server")
-            .push("nowait");
+            .push("nowait")
+            .push("wait")
+            .push("reconnect")
+            .push("size")
+            .push("logfile")
+            .push("logsize")
+            .push("logcount");
 
         cmd_parser.parse(chardev_config)?;
 
@@ -304,6 +528,8 @@ pub struct VirtioSerialInfo {
     pub id: String,
     pub pci_bdf: Option<PciBdf>,
     pub multifunction: bool,
+    /// Maximum number of ports.
+    pub max_ports: u32,
 }
 
 impl ConfigCheck for VirtioSerialInfo {
@@ -315,6 +541,21 @@ impl ConfigCheck for VirtioSerialInfo {
             )
             .into());
         }
+        let max_ports = if self.pci_bdf.is_some() {
+            MAX_SERIAL_PORTS
+        } else {
+            MAX_MMIO_SERIAL_PORTS
+        };
+        if !(1..=max_ports).contains(&self.max_ports) {
+            return Err(ErrorKind::IllegalValue(
+                "virtio-serial max_ports".to_string(),
+                1,
+                true,
+                max_ports as u64,
+                true,
+            )
+            .into());
+        }
 
         Ok(())
     }
@@ -327,7 +568,8 @@ pub fn parse_virtio_serial(vm_config: &mut VmConfig, serial_config: &str) -> Res
         .push("id")
         .push("bus")
         .push("addr")
-        .push("multifunction");
+        .push("multifunction")
+        .push("max_ports");
     cmd_parser.parse(serial_config)?;
     pci_args_check(&cmd_parser)?;
 
@@ -342,18 +584,23 @@ pub fn parse_virtio_serial(vm_config: &mut VmConfig, serial_config: &str) -> Res
         } else {
             false
         };
+        let max_ports = cmd_parser
+            .get_value::<u32>("max_ports")?
+            .unwrap_or(DEFAULT_SERIAL_PORTS);
         let virtio_serial = if serial_config.contains("-pci") {
             let pci_bdf = get_pci_bdf(serial_config)?;
             VirtioSerialInfo {
                 id,
                 pci_bdf: Some(pci_bdf),
                 multifunction,
+                max_ports,
             }
         } else {
             VirtioSerialInfo {
                 id,
                 pci_bdf: None,
                 multifunction,
+                max_ports,
             }
         };
         virtio_serial.check()?;
@@ -385,8 +632,13 @@ mod tests {
         let console_cfg = virt_console.unwrap();
         assert_eq!(console_cfg.id, "console1");
         assert_eq!(
-            console_cfg.chardev.backend,
-            ChardevType::Socket("/path/to/socket".to_string())
+            console_cfg.chardev.unwrap().backend,
+            ChardevType::Socket {
+                path: "/path/to/socket".to_string(),
+                server: true,
+                nowait: true,
+                reconnect: 0,
+            }
         );
 
         let mut vm_config = VmConfig::default();
@@ -397,6 +649,21 @@ mod tests {
         assert!(vm_config
             .add_chardev("sock,id=test_console,path=/path/to/socket")
             .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=test_console,path=/path/to/socket,nowait")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=test_console,path=/path/to/socket")
+            .is_ok());
+        assert_eq!(
+            vm_config.chardev.get("test_console").unwrap().backend,
+            ChardevType::Socket {
+                path: "/path/to/socket".to_string(),
+                server: false,
+                nowait: false,
+                reconnect: 0,
+            }
+        );
 
         let mut vm_config = VmConfig::default();
         assert!(parse_virtio_serial(&mut vm_config, "virtio-serial-device").is_ok());
@@ -435,8 +702,13 @@ mod tests {
         assert_eq!(bdf.bus, "pcie.0");
         assert_eq!(bdf.addr, (1, 2));
         assert_eq!(
-            console_cfg.chardev.backend,
-            ChardevType::Socket("/path/to/socket".to_string())
+            console_cfg.chardev.unwrap().backend,
+            ChardevType::Socket {
+                path: "/path/to/socket".to_string(),
+                server: true,
+                nowait: true,
+                reconnect: 0,
+            }
         );
 
         let mut vm_config = VmConfig::default();
@@ -447,6 +719,203 @@ mod tests {
         .is_ok());
     }
 
+    #[test]
+    fn test_virtserialport_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(parse_virtio_serial(
+            &mut vm_config,
+            "virtio-serial-pci,bus=pcie.0,addr=0x1.0x2,max_ports=8"
+        )
+        .is_ok());
+        assert_eq!(vm_config.virtio_serial.as_ref().unwrap().max_ports, 8);
+        assert!(vm_config
+            .add_chardev("socket,id=qga0,path=/path/to/qga.sock,server,nowait")
+            .is_ok());
+        let port = parse_virtserialport(
+            &mut vm_config,
+            "virtserialport,chardev=qga0,id=channel0,name=org.qemu.guest_agent.0,nr=2",
+        )
+        .unwrap();
+        assert_eq!(port.id, "channel0");
+        assert_eq!(port.name, Some("org.qemu.guest_agent.0".to_string()));
+        assert_eq!(port.nr, Some(2));
+        assert!(!port.is_console);
+        // Chardev is in use.
+        assert!(
+            parse_virtserialport(&mut vm_config, "virtserialport,chardev=qga0,id=channel1")
+                .is_err()
+        );
+        // Only guest agent port can be set without chardev.
+        assert!(parse_virtserialport(&mut vm_config, "virtserialport,id=channel1").is_err());
+        let port = parse_virtserialport(
+            &mut vm_config,
+            "virtserialport,id=channel1,name=org.qemu.guest_agent.0",
+        )
+        .unwrap();
+        assert!(port.chardev.is_none());
+
+        let mut vm_config = VmConfig::default();
+        assert!(parse_virtio_serial(&mut vm_config, "virtio-serial-device").is_ok());
+        assert_eq!(
+            vm_config.virtio_serial.as_ref().unwrap().max_ports,
+            DEFAULT_SERIAL_PORTS
+        );
+        assert!(vm_config.add_chardev("pty,id=console0").is_ok());
+        let port =
+            parse_virtconsole(&mut vm_config, "virtconsole,chardev=console0,id=console0").unwrap();
+        assert!(port.nr.is_none());
+        assert!(port.name.is_none());
+        assert!(port.is_console);
+
+        // Too many ports for virtio-serial-device.
+        let mut vm_config = VmConfig::default();
+        assert!(parse_virtio_serial(&mut vm_config, "virtio-serial-device,max_ports=4").is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(parse_virtio_serial(
+            &mut vm_config,
+            "virtio-serial-pci,bus=pcie.0,addr=0x1.0x2,max_ports=32"
+        )
+        .is_err());
+        let mut vm_config = VmConfig::default();
+        assert!(parse_virtio_serial(&mut vm_config, "virtio-serial-device,max_ports=0").is_err());
+    }
+
+    #[test]
+    fn test_chardev_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_chardev("socket,id=chardev0,path=/path/to/socket,server")
+            .is_ok());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev1,path=/path/to/socket,server,wait=off")
+            .is_ok());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev2,host=127.0.0.1,port=4444,server,nowait")
+            .is_ok());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev3,host=127.0.0.1,port=4444,reconnect=5")
+            .is_ok());
+        assert!(vm_config.add_chardev("ringbuf,id=chardev4").is_ok());
+        assert!(vm_config
+            .add_chardev("ringbuf,id=chardev5,size=4096")
+            .is_ok());
+        assert!(vm_config.add_chardev("null,id=chardev6").is_ok());
+        assert_eq!(
+            vm_config.chardev.get("chardev0").unwrap().backend,
+            ChardevType::Socket {
+                path: "/path/to/socket".to_string(),
+                server: true,
+                nowait: false,
+                reconnect: 0,
+            }
+        );
+        assert_eq!(
+            vm_config.chardev.get("chardev1").unwrap().backend,
+            ChardevType::Socket {
+                path: "/path/to/socket".to_string(),
+                server: true,
+                nowait: true,
+                reconnect: 0,
+            }
+        );
+        assert_eq!(
+            vm_config.chardev.get("chardev2").unwrap().backend,
+            ChardevType::TcpSocket {
+                host: "127.0.0.1".to_string(),
+                port: 4444,
+                server: true,
+                nowait: true,
+                reconnect: 0,
+            }
+        );
+        assert_eq!(
+            vm_config.chardev.get("chardev3").unwrap().backend,
+            ChardevType::TcpSocket {
+                host: "127.0.0.1".to_string(),
+                port: 4444,
+                server: false,
+                nowait: false,
+                reconnect: 5,
+            }
+        );
+        assert_eq!(
+            vm_config.chardev.get("chardev4").unwrap().backend,
+            ChardevType::Ringbuf { size: 65536 }
+        );
+        assert_eq!(
+            vm_config.chardev.get("chardev5").unwrap().backend,
+            ChardevType::Ringbuf { size: 4096 }
+        );
+        assert_eq!(
+            vm_config.chardev.get("chardev6").unwrap().backend,
+            ChardevType::Null
+        );
+
+        // Wrong arguments of socket.
+        assert!(vm_config
+            .add_chardev("socket,id=chardev7,path=/path/to/socket,server,nowait,wait=on")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev7,path=/path/to/socket,wait=off")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev7,path=/path/to/socket,server,reconnect=1")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev7,host=127.0.0.1")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev7,port=4444")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev7,path=/path/to/socket,host=127.0.0.1,port=4444")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev7,host=127.0.0.1,port=65536")
+            .is_err());
+        // Wrong arguments of other backends.
+        assert!(vm_config
+            .add_chardev("ringbuf,id=chardev7,size=1000")
+            .is_err());
+        assert!(vm_config.add_chardev("ringbuf,id=chardev7,size=0").is_err());
+        assert!(vm_config.add_chardev("null,id=chardev7,size=4096").is_err());
+        assert!(vm_config
+            .add_chardev("stdio,id=chardev7,reconnect=1")
+            .is_err());
+        assert!(vm_config.add_chardev("pty,id=chardev7,server").is_err());
+
+        // Log file of chardev.
+        assert!(vm_config
+            .add_chardev("pty,id=chardev8,logfile=/path/to/log")
+            .is_ok());
+        assert!(vm_config
+            .add_chardev("socket,id=chardev9,path=/path/to/socket,server,nowait,logfile=/path/to/log,logsize=10M,logcount=3")
+            .is_ok());
+        assert_eq!(vm_config.chardev.get("chardev6").unwrap().logfile, None);
+        assert_eq!(
+            vm_config.chardev.get("chardev8").unwrap().logfile,
+            Some(ChardevLogConfig {
+                path: "/path/to/log".to_string(),
+                size: 0,
+                count: 1,
+            })
+        );
+        assert_eq!(
+            vm_config.chardev.get("chardev9").unwrap().logfile,
+            Some(ChardevLogConfig {
+                path: "/path/to/log".to_string(),
+                size: 10 * 1024 * 1024,
+                count: 3,
+            })
+        );
+        assert!(vm_config
+            .add_chardev("pty,id=chardev10,logsize=10M")
+            .is_err());
+        assert!(vm_config
+            .add_chardev("pty,id=chardev10,logfile=/path/to/log,logcount=33")
+            .is_err());
+    }
+
     #[test]
     fn test_vsock_config_cmdline_parser() {
         let vsock_cfg_op = parse_vsock("vhost-vsock-device,id=test_vsock,guest-cid=3");
@@ -16,41 +16,140 @@ extern crate serde_json;
 use std::fs::metadata;
 use std::os::linux::fs::MetadataExt;
 use std::path::Path;
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
+use util::aio::AioEngine;
+use util::throttle::{BucketType, ThrottleConfig, BUCKETS_COUNT};
 
 use super::{
     errors::{ErrorKind, Result},
     pci_args_check,
 };
-use crate::config::{CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH, MAX_STRING_LENGTH};
+use crate::config::{
+    get_chardev_socket_path, CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH,
+    MAX_STRING_LENGTH,
+};
+use crate::qmp::qmp_schema;
 
 const MAX_SERIAL_NUM: usize = 20;
 const MAX_IOPS: u64 = 1_000_000;
+/// The maximum of bytes per second, and of the burst of each throttle bucket.
+const MAX_BPS: u64 = 1_000_000_000_000_000;
 const MAX_UNIT_ID: usize = 2;
+/// The maximum number of queues of vhost-user-blk device.
+pub const MAX_VHOST_USER_BLK_QUEUES: u16 = 16;
+
+/// Options of the throttle buckets of drive.
+const THROTTLE_BUCKET_NAMES: [(BucketType, &str); BUCKETS_COUNT] = [
+    (BucketType::BpsTotal, "throttling.bps-total"),
+    (BucketType::BpsRead, "throttling.bps-read"),
+    (BucketType::BpsWrite, "throttling.bps-write"),
+    (BucketType::IopsTotal, "throttling.iops-total"),
+    (BucketType::IopsRead, "throttling.iops-read"),
+    (BucketType::IopsWrite, "throttling.iops-write"),
+];
+
+/// Format of the disk image.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiskFormat {
+    Raw,
+    Qcow2,
+}
+
+impl Default for DiskFormat {
+    fn default() -> Self {
+        DiskFormat::Raw
+    }
+}
+
+/// How to handle the write requests with all-zero data.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WriteZeroesState {
+    /// Write the zero data as usual.
+    Off,
+    /// Convert the write requests to write zeroes requests.
+    On,
+    /// Convert the write requests to write zeroes requests, and free the space if
+    /// discard is enabled.
+    Unmap,
+}
+
+impl Default for WriteZeroesState {
+    fn default() -> Self {
+        WriteZeroesState::Off
+    }
+}
+
+impl FromStr for WriteZeroesState {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(WriteZeroesState::Off),
+            "on" => Ok(WriteZeroesState::On),
+            "unmap" => Ok(WriteZeroesState::Unmap),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Parse the value of `discard` option, `unmap` or `on` enables discard, while
+/// `ignore` or `off` disables it.
+pub fn parse_discard(discard: &str) -> Result<bool> {
+    match discard {
+        "unmap" | "on" => Ok(true),
+        "ignore" | "off" => Ok(false),
+        _ => Err(ErrorKind::ConvertValueFailed("discard".to_string(), discard.to_string()).into()),
+    }
+}
+
+impl FromStr for DiskFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(DiskFormat::Raw),
+            "qcow2" => Ok(DiskFormat::Qcow2),
+            _ => Err(()),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct BlkDevConfig {
     pub id: String,
+    pub node_name: String,
     pub path_on_host: String,
     pub read_only: bool,
     pub direct: bool,
     pub serial_num: Option<String>,
     pub iothread: Option<String>,
-    pub iops: Option<u64>,
+    pub throttle: ThrottleConfig,
+    pub throttle_group: Option<String>,
+    pub format: DiskFormat,
+    pub discard: bool,
+    pub write_zeroes: WriteZeroesState,
+    pub aio: AioEngine,
 }
 
 impl Default for BlkDevConfig {
     fn default() -> Self {
         BlkDevConfig {
             id: "".to_string(),
+            node_name: "".to_string(),
             path_on_host: "".to_string(),
             read_only: false,
             direct: true,
             serial_num: None,
             iothread: None,
-            iops: None,
+            throttle: ThrottleConfig::default(),
+            throttle_group: None,
+            format: DiskFormat::Raw,
+            discard: false,
+            write_zeroes: WriteZeroesState::Off,
+            aio: AioEngine::Native,
         }
     }
 }
@@ -64,7 +163,12 @@ pub struct DriveConfig {
     pub path_on_host: String,
     pub read_only: bool,
     pub direct: bool,
-    pub iops: Option<u64>,
+    pub throttle: ThrottleConfig,
+    pub throttle_group: Option<String>,
+    pub format: DiskFormat,
+    pub discard: bool,
+    pub write_zeroes: WriteZeroesState,
+    pub aio: AioEngine,
 }
 
 impl Default for DriveConfig {
@@ -74,7 +178,12 @@ impl Default for DriveConfig {
             path_on_host: "".to_string(),
             read_only: false,
             direct: true,
-            iops: None,
+            throttle: ThrottleConfig::default(),
+            throttle_group: None,
+            format: DiskFormat::Raw,
+            discard: false,
+            write_zeroes: WriteZeroesState::Off,
+            aio: AioEngine::Native,
         }
     }
 }
@@ -112,6 +221,217 @@ impl DriveConfig {
     }
 }
 
+/// Native aio is only supported for the file opened with `O_DIRECT`.
+fn check_aio(aio: AioEngine, direct: bool) -> Result<()> {
+    if aio == AioEngine::Native && !direct {
+        bail!("aio=native requires direct=on");
+    }
+    Ok(())
+}
+
+/// Check the limits of I/O throttling.
+///
+/// # Arguments
+///
+/// * `throttle` - The limits of I/O throttling.
+/// * `group` - The name of the throttle group.
+pub fn check_throttle(throttle: &ThrottleConfig, group: Option<&String>) -> Result<()> {
+    if group.is_some() && group.unwrap().len() > MAX_STRING_LENGTH {
+        return Err(ErrorKind::StringLengthTooLong(
+            "throttle group name".to_string(),
+            MAX_STRING_LENGTH,
+        )
+        .into());
+    }
+
+    check_bucket_limits(throttle, &THROTTLE_BUCKET_NAMES, "block device", MAX_IOPS)?;
+
+    for (total, read, write) in [
+        (
+            BucketType::BpsTotal,
+            BucketType::BpsRead,
+            BucketType::BpsWrite,
+        ),
+        (
+            BucketType::IopsTotal,
+            BucketType::IopsRead,
+            BucketType::IopsWrite,
+        ),
+    ]
+    .iter()
+    {
+        let total = throttle.bucket(*total);
+        let read = throttle.bucket(*read);
+        let write = throttle.bucket(*write);
+        if (total.avg != 0 && (read.avg != 0 || write.avg != 0))
+            || (total.max != 0 && (read.max != 0 || write.max != 0))
+        {
+            bail!("Total and read/write limits of throttling cannot be used at the same time");
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the limits of the throttle buckets which are configured by the options `names`,
+/// the rest of the buckets are ignored.
+///
+/// # Arguments
+///
+/// * `throttle` - The limits of throttling.
+/// * `names` - The buckets to check and their option names.
+/// * `device` - The device type, used in error messages.
+/// * `max_ops` - The maximum of operations per second.
+pub(crate) fn check_bucket_limits(
+    throttle: &ThrottleConfig,
+    names: &[(BucketType, &str)],
+    device: &str,
+    max_ops: u64,
+) -> Result<()> {
+    for (bucket_type, name) in names.iter() {
+        let bucket = throttle.bucket(*bucket_type);
+        let limit = if bucket_type.is_bps() {
+            MAX_BPS
+        } else {
+            max_ops
+        };
+        for (value, suffix) in [(bucket.avg, ""), (bucket.max, "-max")].iter() {
+            if *value > limit {
+                return Err(ErrorKind::IllegalValue(
+                    format!("{}{} of {}", name, suffix, device),
+                    0,
+                    true,
+                    limit,
+                    true,
+                )
+                .into());
+            }
+        }
+        if bucket.max != 0 && bucket.avg == 0 {
+            bail!("{}-max requires {}", name, name);
+        }
+        if bucket.max != 0 && bucket.max < bucket.avg {
+            bail!("{}-max must not be lower than {}", name, name);
+        }
+        if bucket.max_length > 1 && bucket.max == 0 {
+            bail!("{}-max-length requires {}-max", name, name);
+        }
+        if bucket.max.saturating_mul(bucket.max_length) > MAX_BPS {
+            bail!("The burst of {} is too large", name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Push the options of the throttle buckets to the parser.
+pub(crate) fn push_bucket_options(cmd_parser: &mut CmdParser, names: &[(BucketType, &str)]) {
+    for (_, name) in names.iter() {
+        cmd_parser
+            .push(name)
+            .push(&format!("{}-max", name))
+            .push(&format!("{}-max-length", name));
+    }
+}
+
+/// Parse the options of the throttle buckets, which are pushed by `push_bucket_options`.
+pub(crate) fn parse_bucket_options(
+    cmd_parser: &CmdParser,
+    names: &[(BucketType, &str)],
+) -> Result<ThrottleConfig> {
+    let mut throttle = ThrottleConfig::default();
+    for (bucket_type, name) in names.iter() {
+        let bucket = throttle.bucket_mut(*bucket_type);
+        if let Some(avg) = cmd_parser.get_value::<u64>(name)? {
+            bucket.avg = avg;
+        }
+        if let Some(max) = cmd_parser.get_value::<u64>(&format!("{}-max", name))? {
+            bucket.max = max;
+        }
+        if let Some(length) = cmd_parser.get_value::<u64>(&format!("{}-max-length", name))? {
+            bucket.max_length = length;
+        }
+    }
+    Ok(throttle)
+}
+
+/// Parse the `throttling.*` options of drive.
+fn parse_throttle(cmd_parser: &CmdParser) -> Result<ThrottleConfig> {
+    let mut throttle = parse_bucket_options(cmd_parser, &THROTTLE_BUCKET_NAMES)?;
+    if let Some(iops_size) = cmd_parser.get_value::<u64>("throttling.iops-size")? {
+        throttle.iops_size = iops_size;
+    }
+    Ok(throttle)
+}
+
+/// Get the limits of I/O throttling from the arguments of `blockdev_add`.
+pub fn get_blockdev_throttle(args: &qmp_schema::BlockDevAddArgument) -> ThrottleConfig {
+    let limits = [
+        (
+            args.bps_total,
+            args.bps_total_max,
+            args.bps_total_max_length,
+        ),
+        (args.bps_read, args.bps_read_max, args.bps_read_max_length),
+        (
+            args.bps_write,
+            args.bps_write_max,
+            args.bps_write_max_length,
+        ),
+        (
+            args.iops_total,
+            args.iops_total_max,
+            args.iops_total_max_length,
+        ),
+        (
+            args.iops_read,
+            args.iops_read_max,
+            args.iops_read_max_length,
+        ),
+        (
+            args.iops_write,
+            args.iops_write_max,
+            args.iops_write_max_length,
+        ),
+    ];
+    let mut throttle = ThrottleConfig {
+        iops_size: args.iops_size.unwrap_or(0),
+        ..Default::default()
+    };
+    for (bucket, (avg, max, max_length)) in throttle.buckets.iter_mut().zip(limits.iter()) {
+        bucket.avg = avg.unwrap_or(0);
+        bucket.max = max.unwrap_or(0);
+        bucket.max_length = max_length.unwrap_or(0);
+    }
+    throttle
+}
+
+/// Get the limits of I/O throttling from the arguments of `block_set_io_throttle`,
+/// the limits are checked.
+pub fn get_io_throttle_config(
+    args: &qmp_schema::BlockIoThrottleArgument,
+) -> Result<ThrottleConfig> {
+    let limits = [
+        (args.bps, args.bps_max, args.bps_max_length),
+        (args.bps_rd, args.bps_rd_max, args.bps_rd_max_length),
+        (args.bps_wr, args.bps_wr_max, args.bps_wr_max_length),
+        (args.iops, args.iops_max, args.iops_max_length),
+        (args.iops_rd, args.iops_rd_max, args.iops_rd_max_length),
+        (args.iops_wr, args.iops_wr_max, args.iops_wr_max_length),
+    ];
+    let mut throttle = ThrottleConfig {
+        iops_size: args.iops_size.unwrap_or(0),
+        ..Default::default()
+    };
+    for (bucket, (avg, max, max_length)) in throttle.buckets.iter_mut().zip(limits.iter()) {
+        bucket.avg = *avg;
+        bucket.max = max.unwrap_or(0);
+        bucket.max_length = max_length.unwrap_or(0);
+    }
+    check_throttle(&throttle, args.group.as_ref())?;
+    Ok(throttle)
+}
+
 impl ConfigCheck for DriveConfig {
     fn check(&self) -> Result<()> {
         if self.id.len() > MAX_STRING_LENGTH {
@@ -126,16 +446,8 @@ impl ConfigCheck for DriveConfig {
             )
             .into());
         }
-        if self.iops.is_some() && self.iops.unwrap() > MAX_IOPS {
-            return Err(ErrorKind::IllegalValue(
-                "iops of block device".to_string(),
-                0,
-                true,
-                MAX_IOPS,
-                true,
-            )
-            .into());
-        }
+        check_throttle(&self.throttle, self.throttle_group.as_ref())?;
+        check_aio(self.aio, self.direct)?;
         Ok(())
     }
 }
@@ -174,16 +486,9 @@ impl ConfigCheck for BlkDevConfig {
             .into());
         }
 
-        if self.iops.is_some() && self.iops.unwrap() > MAX_IOPS {
-            return Err(ErrorKind::IllegalValue(
-                "iops of block device".to_string(),
-                0,
-                true,
-                MAX_IOPS,
-                true,
-            )
-            .into());
-        }
+        check_throttle(&self.throttle, self.throttle_group.as_ref())?;
+
+        check_aio(self.aio, self.direct)?;
 
         Ok(())
     }
@@ -192,10 +497,8 @@ impl ConfigCheck for BlkDevConfig {
 pub fn parse_drive(cmd_parser: CmdParser) -> Result<DriveConfig> {
     let mut drive = DriveConfig::default();
 
-    if let Some(format) = cmd_parser.get_value::<String>("format")? {
-        if format.ne("raw") {
-            bail!("Only \'raw\' type of block is supported");
-        }
+    if let Some(format) = cmd_parser.get_value::<DiskFormat>("format")? {
+        drive.format = format;
     }
 
     if let Some(id) = cmd_parser.get_value::<String>("id")? {
@@ -216,7 +519,19 @@ pub fn parse_drive(cmd_parser: CmdParser) -> Result<DriveConfig> {
     if let Some(direct) = cmd_parser.get_value::<ExBool>("direct")? {
         drive.direct = direct.into();
     }
-    drive.iops = cmd_parser.get_value::<u64>("throttling.iops-total")?;
+    drive.throttle = parse_throttle(&cmd_parser)?;
+    drive.throttle_group = cmd_parser.get_value::<String>("throttling.group")?;
+    if let Some(discard) = cmd_parser.get_value::<String>("discard")? {
+        drive.discard = parse_discard(&discard)?;
+    }
+    if let Some(write_zeroes) = cmd_parser.get_value::<WriteZeroesState>("detect-zeroes")? {
+        drive.write_zeroes = write_zeroes;
+    }
+    drive.aio = match cmd_parser.get_value::<AioEngine>("aio")? {
+        Some(aio) => aio,
+        None if drive.direct => AioEngine::Native,
+        None => AioEngine::Threads,
+    };
     Ok(drive)
 }
 
@@ -263,10 +578,16 @@ pub fn parse_blk(vm_config: &mut VmConfig, drive_config: &str) -> Result<BlkDevC
     }
 
     if let Some(drive_arg) = &vm_config.drives.remove(&blkdrive) {
+        blkdevcfg.node_name = drive_arg.id.clone();
         blkdevcfg.path_on_host = drive_arg.path_on_host.clone();
         blkdevcfg.read_only = drive_arg.read_only;
         blkdevcfg.direct = drive_arg.direct;
-        blkdevcfg.iops = drive_arg.iops;
+        blkdevcfg.throttle = drive_arg.throttle;
+        blkdevcfg.throttle_group = drive_arg.throttle_group.clone();
+        blkdevcfg.format = drive_arg.format;
+        blkdevcfg.discard = drive_arg.discard;
+        blkdevcfg.write_zeroes = drive_arg.write_zeroes;
+        blkdevcfg.aio = drive_arg.aio;
     } else {
         bail!("No drive configured matched for blk device");
     }
@@ -274,6 +595,99 @@ pub fn parse_blk(vm_config: &mut VmConfig, drive_config: &str) -> Result<BlkDevC
     Ok(blkdevcfg)
 }
 
+/// Config struct for `vhost-user-blk`.
+/// Contains vhost-user-blk device's attr.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VhostUserBlkDevConfig {
+    pub id: String,
+    /// Path of the unix domain socket which the vhost-user slave listens on.
+    pub socket_path: String,
+    pub num_queues: u16,
+}
+
+impl Default for VhostUserBlkDevConfig {
+    fn default() -> Self {
+        VhostUserBlkDevConfig {
+            id: "".to_string(),
+            socket_path: "".to_string(),
+            num_queues: 1,
+        }
+    }
+}
+
+impl ConfigCheck for VhostUserBlkDevConfig {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "vhost-user-blk device id".to_string(),
+                MAX_STRING_LENGTH,
+            )
+            .into());
+        }
+
+        if self.socket_path.len() > MAX_PATH_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "vhost-user-blk socket path".to_string(),
+                MAX_PATH_LENGTH,
+            )
+            .into());
+        }
+
+        if !(1..=MAX_VHOST_USER_BLK_QUEUES).contains(&self.num_queues) {
+            return Err(ErrorKind::IllegalValue(
+                "num-queues of vhost-user-blk device".to_string(),
+                1,
+                true,
+                MAX_VHOST_USER_BLK_QUEUES as u64,
+                true,
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_vhost_user_blk(
+    vm_config: &mut VmConfig,
+    blk_config: &str,
+) -> Result<VhostUserBlkDevConfig> {
+    let mut cmd_parser = CmdParser::new("vhost-user-blk");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("chardev")
+        .push("num-queues");
+
+    cmd_parser.parse(blk_config)?;
+
+    pci_args_check(&cmd_parser)?;
+
+    let mut blkdevcfg = VhostUserBlkDevConfig::default();
+    if let Some(id) = cmd_parser.get_value::<String>("id")? {
+        blkdevcfg.id = id;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("id", "vhost-user-blk").into());
+    }
+
+    if let Some(num_queues) = cmd_parser.get_value::<u16>("num-queues")? {
+        blkdevcfg.num_queues = num_queues;
+    }
+
+    if let Some(chardev) = cmd_parser.get_value::<String>("chardev")? {
+        blkdevcfg.socket_path = get_chardev_socket_path(vm_config, &chardev)?;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("chardev", "vhost-user-blk").into());
+    }
+
+    blkdevcfg.check()?;
+    Ok(blkdevcfg)
+}
+
 /// Config struct for `pflash`.
 /// Contains pflash device's attr.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -347,8 +761,13 @@ impl VmConfig {
             .push("direct")
             .push("format")
             .push("if")
-            .push("throttling.iops-total")
+            .push("throttling.iops-size")
+            .push("throttling.group")
+            .push("discard")
+            .push("detect-zeroes")
+            .push("aio")
             .push("serial");
+        push_bucket_options(&mut cmd_parser, &THROTTLE_BUCKET_NAMES);
 
         cmd_parser.parse(block_config)?;
         let drive_cfg = parse_drive(cmd_parser)?;
@@ -476,6 +895,77 @@ mod tests {
             "virtio-blk-device,drive=rootfs1,id=rootfs1,iothread=iothread1,iops=200,serial=111111",
         );
         assert!(blk_cfg_res.is_err()); // Can not find drive named "rootfs1".
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,format=qcow2")
+            .is_ok());
+        let blk_cfg_res = parse_blk(&mut vm_config, "virtio-blk-device,drive=rootfs,id=rootfs");
+        assert!(blk_cfg_res.is_ok());
+        assert_eq!(blk_cfg_res.unwrap().format, DiskFormat::Qcow2);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,format=vmdk")
+            .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,discard=unmap,detect-zeroes=unmap")
+            .is_ok());
+        let blk_cfg_res = parse_blk(&mut vm_config, "virtio-blk-device,drive=rootfs,id=rootfs");
+        assert!(blk_cfg_res.is_ok());
+        let blk_device_config = blk_cfg_res.unwrap();
+        assert!(blk_device_config.discard);
+        assert_eq!(blk_device_config.write_zeroes, WriteZeroesState::Unmap);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,discard=always")
+            .is_err());
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,detect-zeroes=yes")
+            .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,aio=io_uring")
+            .is_ok());
+        let blk_cfg_res = parse_blk(&mut vm_config, "virtio-blk-device,drive=rootfs,id=rootfs");
+        assert_eq!(blk_cfg_res.unwrap().aio, AioEngine::IoUring);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,direct=off")
+            .is_ok());
+        let blk_cfg_res = parse_blk(&mut vm_config, "virtio-blk-device,drive=rootfs,id=rootfs");
+        assert_eq!(blk_cfg_res.unwrap().aio, AioEngine::Threads);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,direct=off,aio=native")
+            .is_ok());
+        let blk_cfg_res = parse_blk(&mut vm_config, "virtio-blk-device,drive=rootfs,id=rootfs");
+        assert!(blk_cfg_res.is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,aio=off")
+            .is_ok());
+        let blk_cfg_res = parse_blk(&mut vm_config, "virtio-blk-device,drive=rootfs,id=rootfs");
+        assert_eq!(blk_cfg_res.unwrap().aio, AioEngine::Off);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,aio=posix")
+            .is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,aio=threads")
+            .is_ok());
+        let blk_cfg_res = parse_blk(&mut vm_config, "virtio-blk-device,drive=rootfs,id=rootfs");
+        assert_eq!(blk_cfg_res.unwrap().aio, AioEngine::Threads);
     }
 
     #[test]
@@ -513,6 +1003,80 @@ mod tests {
         assert!(parse_blk(&mut vm_config, blk_cfg).is_ok());
     }
 
+    #[test]
+    fn test_vhost_user_blk_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        vm_config.machine_config.mem_config.mem_share = true;
+        assert!(vm_config
+            .add_chardev("socket,id=char0,path=/path/to/vhost-user-blk.sock")
+            .is_ok());
+        let blk_cfg =
+            "vhost-user-blk-pci,id=blk0,bus=pcie.0,addr=0x3.0x0,chardev=char0,num-queues=4";
+        let blk_cfg_res = parse_vhost_user_blk(&mut vm_config, blk_cfg);
+        assert!(blk_cfg_res.is_ok());
+        let blk_cfg = blk_cfg_res.unwrap();
+        assert_eq!(blk_cfg.id, "blk0");
+        assert_eq!(blk_cfg.socket_path, "/path/to/vhost-user-blk.sock");
+        assert_eq!(blk_cfg.num_queues, 4);
+        // The chardev is in use.
+        assert!(parse_vhost_user_blk(
+            &mut vm_config,
+            "vhost-user-blk-device,id=blk1,chardev=char0"
+        )
+        .is_err());
+
+        assert!(vm_config
+            .add_chardev("socket,id=char1,path=/path/to/vhost-user-blk.sock")
+            .is_ok());
+        let blk_cfg_res = parse_vhost_user_blk(
+            &mut vm_config,
+            "vhost-user-blk-device,id=blk1,chardev=char1",
+        );
+        assert!(blk_cfg_res.is_ok());
+        assert_eq!(blk_cfg_res.unwrap().num_queues, 1);
+
+        // Chardev is missing.
+        assert!(parse_vhost_user_blk(&mut vm_config, "vhost-user-blk-device,id=blk2").is_err());
+        // Mmio device doesn't support bus.
+        assert!(vm_config
+            .add_chardev("socket,id=char2,path=/path/to/vhost-user-blk.sock")
+            .is_ok());
+        assert!(parse_vhost_user_blk(
+            &mut vm_config,
+            "vhost-user-blk-device,id=blk2,bus=pcie.0,chardev=char2"
+        )
+        .is_err());
+        // Invalid number of queues.
+        assert!(vm_config
+            .add_chardev("socket,id=char3,path=/path/to/vhost-user-blk.sock")
+            .is_ok());
+        assert!(parse_vhost_user_blk(
+            &mut vm_config,
+            "vhost-user-blk-device,id=blk3,chardev=char3,num-queues=0"
+        )
+        .is_err());
+        // Server socket chardev can't be used.
+        assert!(vm_config
+            .add_chardev("socket,id=char4,path=/path/to/vhost-user-blk.sock,server,nowait")
+            .is_ok());
+        assert!(parse_vhost_user_blk(
+            &mut vm_config,
+            "vhost-user-blk-device,id=blk4,chardev=char4"
+        )
+        .is_err());
+
+        // Guest memory is not shared.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_chardev("socket,id=char0,path=/path/to/vhost-user-blk.sock")
+            .is_ok());
+        assert!(parse_vhost_user_blk(
+            &mut vm_config,
+            "vhost-user-blk-device,id=blk0,chardev=char0"
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_pflash_config_cmdline_parser() {
         let mut vm_config = VmConfig::default();
@@ -585,18 +1149,56 @@ mod tests {
         assert!(drive_conf.check().is_err());
 
         let mut drive_conf = DriveConfig::default();
-        drive_conf.iops = Some(MAX_IOPS);
+        drive_conf.throttle.bucket_mut(BucketType::IopsTotal).avg = MAX_IOPS;
         assert!(drive_conf.check().is_ok());
 
         let mut drive_conf = DriveConfig::default();
-        drive_conf.iops = None;
+        drive_conf.throttle.bucket_mut(BucketType::IopsTotal).avg = 0;
         assert!(drive_conf.check().is_ok());
 
         // Overflow
-        drive_conf.iops = Some(MAX_IOPS + 1);
+        drive_conf.throttle.bucket_mut(BucketType::IopsTotal).avg = MAX_IOPS + 1;
         assert!(drive_conf.check().is_err());
     }
 
+    #[test]
+    fn test_drive_throttle_config() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive(
+                "id=rootfs,file=/path/to/rootfs,throttling.bps-read=1048576,\
+                throttling.bps-read-max=4194304,throttling.bps-read-max-length=10,\
+                throttling.iops-write=100,throttling.iops-size=4096,throttling.group=group0"
+            )
+            .is_ok());
+        let blk_cfg =
+            parse_blk(&mut vm_config, "virtio-blk-device,drive=rootfs,id=rootfs").unwrap();
+        let bucket = blk_cfg.throttle.bucket(BucketType::BpsRead);
+        assert_eq!(bucket.avg, 1048576);
+        assert_eq!(bucket.max, 4194304);
+        assert_eq!(bucket.max_length, 10);
+        assert_eq!(blk_cfg.throttle.bucket(BucketType::IopsWrite).avg, 100);
+        assert_eq!(blk_cfg.throttle.bucket(BucketType::IopsTotal).avg, 0);
+        assert_eq!(blk_cfg.throttle.iops_size, 4096);
+        assert_eq!(blk_cfg.throttle_group, Some("group0".to_string()));
+
+        let invalid_throttles = [
+            // Total and read/write limits cannot be used at the same time.
+            "throttling.bps-total=1000,throttling.bps-write=100",
+            // Burst requires the average limit, and is not lower than it.
+            "throttling.iops-total-max=1000",
+            "throttling.iops-total=1000,throttling.iops-total-max=100",
+            // Burst length requires the burst limit.
+            "throttling.iops-total=100,throttling.iops-total-max-length=10",
+        ];
+        for throttle in invalid_throttles.iter() {
+            let mut vm_config = VmConfig::default();
+            let drive = format!("id=rootfs,file=/path/to/rootfs,{}", throttle);
+            assert!(vm_config.add_drive(&drive).is_ok());
+            assert!(parse_blk(&mut vm_config, "virtio-blk-device,drive=rootfs,id=rootfs").is_err());
+        }
+    }
+
     #[test]
     fn test_add_drive_with_config() {
         let mut vm_config = VmConfig::default();
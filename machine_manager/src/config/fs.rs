@@ -0,0 +1,202 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use serde::{Deserialize, Serialize};
+
+use super::errors::{ErrorKind, Result};
+use super::pci_args_check;
+use crate::config::{
+    get_chardev_socket_path, CmdParser, ConfigCheck, VmConfig, MAX_PATH_LENGTH, MAX_STRING_LENGTH,
+};
+
+/// The maximum length of the mount tag of virtio-fs device, refer to Virtio Spec.
+pub const MAX_FS_TAG_LENGTH: usize = 36;
+/// The maximum number of request queues of virtio-fs device.
+pub const MAX_FS_REQUEST_QUEUES: u16 = 16;
+
+/// Config struct for `vhost-user-fs`.
+/// Contains vhost-user-fs device's attr.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FsConfig {
+    pub id: String,
+    /// Mount tag used by the guest to mount the file system.
+    pub tag: String,
+    /// Path of the unix domain socket which the vhost-user slave (e.g. virtiofsd) listens on.
+    pub socket_path: String,
+    pub num_request_queues: u16,
+}
+
+impl Default for FsConfig {
+    fn default() -> Self {
+        FsConfig {
+            id: "".to_string(),
+            tag: "".to_string(),
+            socket_path: "".to_string(),
+            num_request_queues: 1,
+        }
+    }
+}
+
+impl ConfigCheck for FsConfig {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "vhost-user-fs device id".to_string(),
+                MAX_STRING_LENGTH,
+            )
+            .into());
+        }
+
+        if self.tag.is_empty() || self.tag.len() > MAX_FS_TAG_LENGTH {
+            return Err(ErrorKind::IllegalValue(
+                "length of vhost-user-fs tag".to_string(),
+                1,
+                true,
+                MAX_FS_TAG_LENGTH as u64,
+                true,
+            )
+            .into());
+        }
+
+        if self.socket_path.len() > MAX_PATH_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "vhost-user-fs socket path".to_string(),
+                MAX_PATH_LENGTH,
+            )
+            .into());
+        }
+
+        if !(1..=MAX_FS_REQUEST_QUEUES).contains(&self.num_request_queues) {
+            return Err(ErrorKind::IllegalValue(
+                "num-request-queues of vhost-user-fs device".to_string(),
+                1,
+                true,
+                MAX_FS_REQUEST_QUEUES as u64,
+                true,
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_fs(vm_config: &mut VmConfig, fs_config: &str) -> Result<FsConfig> {
+    let mut cmd_parser = CmdParser::new("vhost-user-fs");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("chardev")
+        .push("tag")
+        .push("num-request-queues");
+
+    cmd_parser.parse(fs_config)?;
+
+    pci_args_check(&cmd_parser)?;
+
+    let mut fs_cfg = FsConfig::default();
+    if let Some(id) = cmd_parser.get_value::<String>("id")? {
+        fs_cfg.id = id;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("id", "vhost-user-fs").into());
+    }
+
+    if let Some(tag) = cmd_parser.get_value::<String>("tag")? {
+        fs_cfg.tag = tag;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("tag", "vhost-user-fs").into());
+    }
+
+    if let Some(num_queues) = cmd_parser.get_value::<u16>("num-request-queues")? {
+        fs_cfg.num_request_queues = num_queues;
+    }
+
+    if let Some(chardev) = cmd_parser.get_value::<String>("chardev")? {
+        fs_cfg.socket_path = get_chardev_socket_path(vm_config, &chardev)?;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("chardev", "vhost-user-fs").into());
+    }
+
+    fs_cfg.check()?;
+    Ok(fs_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fs_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        vm_config.machine_config.mem_config.mem_share = true;
+        assert!(vm_config
+            .add_chardev("socket,id=char0,path=/path/to/virtiofsd.sock")
+            .is_ok());
+        let fs_cfg_res = parse_fs(
+            &mut vm_config,
+            "vhost-user-fs-pci,id=fs0,chardev=char0,tag=myfs,bus=pcie.0,addr=0x4.0x0,num-request-queues=2",
+        );
+        assert!(fs_cfg_res.is_ok());
+        let fs_cfg = fs_cfg_res.unwrap();
+        assert_eq!(fs_cfg.id, "fs0");
+        assert_eq!(fs_cfg.tag, "myfs");
+        assert_eq!(fs_cfg.socket_path, "/path/to/virtiofsd.sock");
+        assert_eq!(fs_cfg.num_request_queues, 2);
+
+        assert!(vm_config
+            .add_chardev("socket,id=char1,path=/path/to/virtiofsd.sock")
+            .is_ok());
+        let fs_cfg_res = parse_fs(
+            &mut vm_config,
+            "vhost-user-fs-device,id=fs1,chardev=char1,tag=myfs",
+        );
+        assert!(fs_cfg_res.is_ok());
+        assert_eq!(fs_cfg_res.unwrap().num_request_queues, 1);
+
+        // Tag is missing or too long.
+        assert!(vm_config
+            .add_chardev("socket,id=char2,path=/path/to/virtiofsd.sock")
+            .is_ok());
+        assert!(parse_fs(&mut vm_config, "vhost-user-fs-device,id=fs2,chardev=char2").is_err());
+        let fs_cfg = format!(
+            "vhost-user-fs-device,id=fs2,chardev=char2,tag={}",
+            "a".repeat(MAX_FS_TAG_LENGTH + 1)
+        );
+        assert!(parse_fs(&mut vm_config, &fs_cfg).is_err());
+        // Invalid number of request queues.
+        assert!(vm_config
+            .add_chardev("socket,id=char3,path=/path/to/virtiofsd.sock")
+            .is_ok());
+        assert!(parse_fs(
+            &mut vm_config,
+            "vhost-user-fs-device,id=fs3,chardev=char3,tag=myfs,num-request-queues=0"
+        )
+        .is_err());
+        // Chardev is missing.
+        assert!(parse_fs(&mut vm_config, "vhost-user-fs-device,id=fs4,tag=myfs").is_err());
+
+        // Guest memory is not shared.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_chardev("socket,id=char0,path=/path/to/virtiofsd.sock")
+            .is_ok());
+        assert!(parse_fs(
+            &mut vm_config,
+            "vhost-user-fs-device,id=fs0,chardev=char0,tag=myfs"
+        )
+        .is_err());
+    }
+}
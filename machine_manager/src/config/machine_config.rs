@@ -18,7 +18,10 @@ use std::str::FromStr;
 use serde::{Deserialize, Serialize};
 
 use super::errors::{ErrorKind, Result, ResultExt};
-use crate::config::{CmdParser, ConfigCheck, ExBool, VmConfig};
+use crate::config::{
+    parse_id_list, CmdParser, ConfigCheck, ExBool, ObjConfig, VmConfig, MAX_PATH_LENGTH,
+    MAX_STRING_LENGTH,
+};
 
 const DEFAULT_CPUS: u8 = 1;
 const DEFAULT_MEMSIZE: u64 = 256;
@@ -60,6 +63,8 @@ pub struct MachineMemConfig {
     pub dump_guest_core: bool,
     pub mem_share: bool,
     pub mem_prealloc: bool,
+    /// Id of the memory backend object which provides guest memory.
+    pub mem_backend: Option<String>,
 }
 
 impl Default for MachineMemConfig {
@@ -70,16 +75,178 @@ impl Default for MachineMemConfig {
             dump_guest_core: true,
             mem_share: false,
             mem_prealloc: false,
+            mem_backend: None,
         }
     }
 }
 
+/// Policy to allocate the memory of memory backend from host numa nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HostMemPolicy {
+    /// Allocate from the node of the vcpu thread which touches the memory first.
+    Default,
+    /// Allocate from the preferred node, fall back to other nodes if it's exhausted.
+    Preferred,
+    /// Allocate from the bound nodes only.
+    Bind,
+    /// Interleave the allocation across the nodes.
+    Interleave,
+}
+
+impl Default for HostMemPolicy {
+    fn default() -> Self {
+        HostMemPolicy::Default
+    }
+}
+
+impl FromStr for HostMemPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(HostMemPolicy::Default),
+            "preferred" => Ok(HostMemPolicy::Preferred),
+            "bind" => Ok(HostMemPolicy::Bind),
+            "interleave" => Ok(HostMemPolicy::Interleave),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Type of memory backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemBackendType {
+    /// Anonymous memory, `memory-backend-ram`.
+    Ram,
+    /// Memory backed by a file, `memory-backend-file`.
+    File,
+    /// Memory backed by an anonymous memfd, `memory-backend-memfd`.
+    Memfd,
+}
+
+impl Default for MemBackendType {
+    fn default() -> Self {
+        MemBackendType::Ram
+    }
+}
+
+impl FromStr for MemBackendType {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "memory-backend-ram" => Ok(MemBackendType::Ram),
+            "memory-backend-file" => Ok(MemBackendType::File),
+            "memory-backend-memfd" => Ok(MemBackendType::Memfd),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Config of memory backend, which provides the memory of guest or a guest numa node.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemZoneConfig {
+    pub id: String,
+    pub size: u64,
+    pub backend_type: MemBackendType,
+    /// Path of the backend file, directory is also allowed, only for `memory-backend-file`.
+    pub mem_path: Option<String>,
+    /// Map the memory as shared.
+    pub share: bool,
+    /// Back the memory with huge pages.
+    pub hugetlb: bool,
+    /// Size of huge page, use the default huge page size of host if not set.
+    pub hugetlb_size: Option<u64>,
+    /// Host numa nodes which the memory is allocated from.
+    pub host_numa_nodes: Option<Vec<u32>>,
+    pub policy: HostMemPolicy,
+    /// Pre-allocate the memory when it's created.
+    pub prealloc: bool,
+    /// Number of threads to pre-allocate the memory.
+    pub prealloc_threads: Option<u8>,
+}
+
+impl ConfigCheck for MemZoneConfig {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "memory-backend id".to_string(),
+                MAX_STRING_LENGTH,
+            )
+            .into());
+        }
+        if self.size == 0 {
+            bail!("Size of memory-backend {} is not set", self.id);
+        }
+
+        match (self.backend_type, &self.mem_path) {
+            (MemBackendType::File, None) => {
+                return Err(ErrorKind::FieldIsMissing("mem-path", "memory-backend-file").into());
+            }
+            (MemBackendType::File, Some(path)) if path.len() > MAX_PATH_LENGTH => {
+                return Err(ErrorKind::StringLengthTooLong(
+                    "mem-path".to_string(),
+                    MAX_PATH_LENGTH,
+                )
+                .into());
+            }
+            (MemBackendType::Ram, _) if self.hugetlb => {
+                bail!(
+                    "Hugetlb is not supported for memory-backend-ram {}",
+                    self.id
+                )
+            }
+            _ => {}
+        }
+        if let Some(page_size) = self.hugetlb_size {
+            if !self.hugetlb {
+                bail!("Hugetlbsize of memory-backend {} requires hugetlb", self.id);
+            }
+            if !page_size.is_power_of_two() {
+                bail!(
+                    "Hugetlbsize of memory-backend {} is not power of 2",
+                    self.id
+                );
+            }
+            if self.size % page_size != 0 {
+                bail!(
+                    "Size of memory-backend {} is not aligned with hugetlbsize 0x{:x}",
+                    self.id,
+                    page_size
+                );
+            }
+        }
+        if self.prealloc_threads == Some(0) {
+            bail!(
+                "Prealloc-threads of memory-backend {} must be above 0",
+                self.id
+            );
+        }
+
+        match (&self.host_numa_nodes, self.policy) {
+            (None, HostMemPolicy::Default) => {}
+            (None, _) => bail!("Policy of memory-backend {} requires host-nodes", self.id),
+            (Some(_), HostMemPolicy::Default) => {
+                bail!("Host-nodes of memory-backend {} requires policy", self.id)
+            }
+            (Some(nodes), HostMemPolicy::Preferred) if nodes.len() != 1 => {
+                bail!("Only one host node can be preferred for {}", self.id)
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
 /// Config struct for machine-config.
 /// Contains some basic Vm config about cpu, memory, name.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MachineConfig {
     pub mach_type: MachineType,
     pub nr_cpus: u8,
+    /// Maximum number of vcpus, vcpus above `nr_cpus` can be hotplugged.
+    pub max_cpus: u8,
     pub mem_config: MachineMemConfig,
 }
 
@@ -89,6 +256,7 @@ impl Default for MachineConfig {
         MachineConfig {
             mach_type: MachineType::MicroVm,
             nr_cpus: DEFAULT_CPUS,
+            max_cpus: DEFAULT_CPUS,
             mem_config: MachineMemConfig::default(),
         }
     }
@@ -119,7 +287,8 @@ impl VmConfig {
             .push("accel")
             .push("usb")
             .push("dump-guest-core")
-            .push("mem-share");
+            .push("mem-share")
+            .push("memory-backend");
         #[cfg(target_arch = "aarch64")]
         cmd_parser.push("gic-version");
         cmd_parser.parse(mach_config)?;
@@ -159,6 +328,9 @@ impl VmConfig {
         if let Some(mem_share) = cmd_parser.get_value::<ExBool>("mem-share")? {
             self.machine_config.mem_config.mem_share = mem_share.into();
         }
+        if let Some(mem_backend) = cmd_parser.get_value::<String>("memory-backend")? {
+            self.machine_config.mem_config.mem_backend = Some(mem_backend);
+        }
 
         Ok(())
     }
@@ -191,7 +363,8 @@ impl VmConfig {
             .push("sockets")
             .push("cores")
             .push("threads")
-            .push("cpus");
+            .push("cpus")
+            .push("maxcpus");
 
         cmd_parser.parse(cpu_config)?;
 
@@ -203,9 +376,10 @@ impl VmConfig {
             return Err(ErrorKind::FieldIsMissing("cpus", "smp").into());
         };
 
+        let max_cpus = cmd_parser.get_value::<u64>("maxcpus")?.unwrap_or(cpu);
         if let Some(sockets) = cmd_parser.get_value::<u64>("sockets")? {
-            if sockets.ne(&cpu) {
-                bail!("Invalid \'sockets\' arguments for \'smp\', it should equal to the number of cpus");
+            if sockets.ne(&max_cpus) {
+                bail!("Invalid \'sockets\' arguments for \'smp\', it should equal to the maximum number of cpus");
             }
         }
         if let Some(cores) = cmd_parser.get_value::<u64>("cores")? {
@@ -231,8 +405,20 @@ impl VmConfig {
             .into());
         }
 
+        if !(cpu..=MAX_NR_CPUS).contains(&max_cpus) {
+            return Err(ErrorKind::IllegalValue(
+                "MAX CPU number".to_string(),
+                cpu,
+                true,
+                MAX_NR_CPUS,
+                true,
+            )
+            .into());
+        }
+
         // it is safe, as value limited before
         self.machine_config.nr_cpus = cpu as u8;
+        self.machine_config.max_cpus = max_cpus as u8;
 
         Ok(())
     }
@@ -247,7 +433,94 @@ impl VmConfig {
     }
 }
 
-fn memory_unit_conversion(origin_value: &str) -> Result<u64> {
+/// Parse the memory backend object.
+///
+/// # Arguments
+///
+/// * `object_args` - The args of memory-backend-ram/file/memfd object.
+pub fn parse_mem_zone(object_args: &str) -> Result<MemZoneConfig> {
+    let mut cmd_parser = CmdParser::new("memory-backend");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("size")
+        .push("mem-path")
+        .push("share")
+        .push("hugetlb")
+        .push("hugetlbsize")
+        .push("host-nodes")
+        .push("policy")
+        .push("prealloc")
+        .push("prealloc-threads");
+    cmd_parser.parse(object_args)?;
+
+    let mut zone_config = MemZoneConfig::default();
+    if let Some(backend_type) = cmd_parser.get_value::<String>("")? {
+        zone_config.backend_type = backend_type
+            .parse()
+            .map_err(|_| ErrorKind::InvalidParam(backend_type, "object".to_string()))?;
+    }
+    // Memory of memfd is shared by default.
+    zone_config.share = zone_config.backend_type == MemBackendType::Memfd;
+    if let Some(id) = cmd_parser.get_value::<String>("id")? {
+        zone_config.id = id;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("id", "memory-backend").into());
+    }
+    if let Some(size) = cmd_parser.get_value::<String>("size")? {
+        zone_config.size = memory_unit_conversion(&size)?;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("size", "memory-backend").into());
+    }
+    if let Some(mem_path) = cmd_parser.get_value::<String>("mem-path")? {
+        if zone_config.backend_type != MemBackendType::File {
+            bail!("Mem-path is only supported for memory-backend-file");
+        }
+        zone_config.mem_path = Some(mem_path);
+    }
+    if let Some(share) = cmd_parser.get_value::<ExBool>("share")? {
+        zone_config.share = share.into();
+    }
+    if let Some(hugetlb) = cmd_parser.get_value::<ExBool>("hugetlb")? {
+        zone_config.hugetlb = hugetlb.into();
+    }
+    if let Some(page_size) = cmd_parser.get_value::<String>("hugetlbsize")? {
+        zone_config.hugetlb_size = Some(memory_unit_conversion(&page_size)?);
+    }
+    if let Some(host_nodes) = cmd_parser.get_value::<String>("host-nodes")? {
+        zone_config.host_numa_nodes = Some(parse_id_list(&host_nodes)?);
+    }
+    if let Some(policy) = cmd_parser.get_value::<String>("policy")? {
+        zone_config.policy = policy
+            .parse()
+            .map_err(|_| ErrorKind::InvalidParam(policy, "policy".to_string()))?;
+    }
+    if let Some(prealloc) = cmd_parser.get_value::<ExBool>("prealloc")? {
+        zone_config.prealloc = prealloc.into();
+    }
+    zone_config.prealloc_threads = cmd_parser.get_value::<u8>("prealloc-threads")?;
+    zone_config.check()?;
+
+    Ok(zone_config)
+}
+
+/// Get the memory backend which provides guest memory, `None` if not configured.
+///
+/// # Arguments
+///
+/// * `vm_config` - Vm config with memory backend objects.
+pub fn complete_mem_backend(vm_config: &VmConfig) -> Result<Option<MemZoneConfig>> {
+    let mem_backend = match &vm_config.machine_config.mem_config.mem_backend {
+        Some(mem_backend) => mem_backend,
+        None => return Ok(None),
+    };
+    match vm_config.object.get(mem_backend) {
+        Some(ObjConfig::Zone(zone)) => Ok(Some(zone.clone())),
+        _ => bail!("Memory backend {} not found", mem_backend),
+    }
+}
+
+pub(crate) fn memory_unit_conversion(origin_value: &str) -> Result<u64> {
     if (origin_value.ends_with('M') | origin_value.ends_with('m'))
         && (origin_value.contains('M') ^ origin_value.contains('m'))
     {
@@ -305,10 +578,12 @@ mod tests {
             mem_share: false,
             dump_guest_core: false,
             mem_prealloc: false,
+            mem_backend: None,
         };
         let mut machine_config = MachineConfig {
             mach_type: MachineType::MicroVm,
             nr_cpus: MIN_NR_CPUS as u8,
+            max_cpus: MIN_NR_CPUS as u8,
             mem_config: memory_config,
         };
         assert!(machine_config.check().is_ok());
@@ -326,4 +601,80 @@ mod tests {
 
         assert!(machine_config.check().is_ok());
     }
+
+    #[test]
+    fn test_add_cpu() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_cpu("4").is_ok());
+        assert_eq!(vm_config.machine_config.nr_cpus, 4);
+        assert_eq!(vm_config.machine_config.max_cpus, 4);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_cpu("cpus=2,maxcpus=8,sockets=8,cores=1,threads=1")
+            .is_ok());
+        assert_eq!(vm_config.machine_config.nr_cpus, 2);
+        assert_eq!(vm_config.machine_config.max_cpus, 8);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_cpu("cpus=4,maxcpus=2").is_err());
+        assert!(vm_config.add_cpu("cpus=2,maxcpus=255").is_err());
+        assert!(vm_config.add_cpu("cpus=2,maxcpus=4,sockets=2").is_err());
+        assert!(vm_config.add_cpu("maxcpus=4").is_err());
+    }
+
+    #[test]
+    fn test_mem_zone_parser() {
+        let zone = parse_mem_zone("memory-backend-ram,id=mem0,size=2G").unwrap();
+        assert_eq!(zone.id, "mem0");
+        assert_eq!(zone.size, 2 * G);
+        assert!(zone.host_numa_nodes.is_none());
+        assert_eq!(zone.policy, HostMemPolicy::Default);
+
+        let zone =
+            parse_mem_zone("memory-backend-ram,id=mem1,size=512M,host-nodes=0-1,policy=interleave")
+                .unwrap();
+        assert_eq!(zone.size, 512 * M);
+        assert_eq!(zone.host_numa_nodes, Some(vec![0, 1]));
+        assert_eq!(zone.policy, HostMemPolicy::Interleave);
+
+        assert!(parse_mem_zone("memory-backend-ram,id=mem0").is_err());
+        assert!(parse_mem_zone("memory-backend-ram,size=2G").is_err());
+        assert!(parse_mem_zone("memory-backend-ram,id=mem0,size=2G,policy=bind").is_err());
+        assert!(parse_mem_zone("memory-backend-ram,id=mem0,size=2G,host-nodes=0").is_err());
+        assert!(parse_mem_zone(
+            "memory-backend-ram,id=mem0,size=2G,host-nodes=0-1,policy=preferred"
+        )
+        .is_err());
+        assert!(
+            parse_mem_zone("memory-backend-ram,id=mem0,size=2G,host-nodes=0,policy=x").is_err()
+        );
+
+        let zone = parse_mem_zone(
+            "memory-backend-file,id=mem0,size=2G,mem-path=/dev/hugepages,hugetlb=on,hugetlbsize=2M,prealloc=on,prealloc-threads=8",
+        )
+        .unwrap();
+        assert_eq!(zone.backend_type, MemBackendType::File);
+        assert_eq!(zone.mem_path, Some("/dev/hugepages".to_string()));
+        assert!(!zone.share);
+        assert!(zone.hugetlb);
+        assert_eq!(zone.hugetlb_size, Some(2 * M));
+        assert!(zone.prealloc);
+        assert_eq!(zone.prealloc_threads, Some(8));
+
+        let zone = parse_mem_zone("memory-backend-memfd,id=mem0,size=2G,hugetlb=on").unwrap();
+        assert_eq!(zone.backend_type, MemBackendType::Memfd);
+        assert!(zone.share);
+        assert!(zone.hugetlb_size.is_none());
+
+        assert!(parse_mem_zone("memory-backend-file,id=mem0,size=2G").is_err());
+        assert!(parse_mem_zone("memory-backend-memfd,id=mem0,size=2G,mem-path=/tmp").is_err());
+        assert!(parse_mem_zone("memory-backend-ram,id=mem0,size=2G,hugetlb=on").is_err());
+        assert!(parse_mem_zone("memory-backend-memfd,id=mem0,size=2G,hugetlbsize=2M").is_err());
+        assert!(
+            parse_mem_zone("memory-backend-memfd,id=mem0,size=2G,hugetlb=on,hugetlbsize=3M")
+                .is_err()
+        );
+        assert!(parse_mem_zone("memory-backend-memfd,id=mem0,size=2G,prealloc-threads=0").is_err());
+    }
 }
@@ -0,0 +1,174 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+
+use util::unix::{parse_uri, UnixPath};
+
+use super::errors::{ErrorKind, Result, ResultExt};
+use crate::config::{CmdParser, ConfigCheck, VmConfig, MAX_PATH_LENGTH};
+
+/// Default interval in seconds to take the snapshot of metrics.
+const DEFAULT_METRICS_INTERVAL: u64 = 10;
+/// Maximum interval in seconds to take the snapshot of metrics.
+const MAX_METRICS_INTERVAL: u64 = 3600;
+
+/// Format of the snapshot of metrics.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MetricsFormat {
+    /// Prometheus text exposition format.
+    Prometheus,
+    Json,
+}
+
+impl Default for MetricsFormat {
+    fn default() -> Self {
+        MetricsFormat::Prometheus
+    }
+}
+
+impl FromStr for MetricsFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "prometheus" => Ok(MetricsFormat::Prometheus),
+            "json" => Ok(MetricsFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Where the snapshot of metrics is dumped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetricsTarget {
+    /// The file is replaced by each snapshot.
+    File(String),
+    /// The latest snapshot is sent to each client connecting to the unix socket.
+    Unix(String),
+}
+
+/// Config structure for metrics.
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub target: MetricsTarget,
+    /// Interval in seconds to take the snapshot.
+    pub interval: u64,
+    pub format: MetricsFormat,
+}
+
+impl ConfigCheck for MetricsConfig {
+    fn check(&self) -> Result<()> {
+        let path = match &self.target {
+            MetricsTarget::File(path) | MetricsTarget::Unix(path) => path,
+        };
+        if path.len() > MAX_PATH_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "metrics path".to_string(),
+                MAX_PATH_LENGTH,
+            )
+            .into());
+        }
+        if self.interval == 0 || self.interval > MAX_METRICS_INTERVAL {
+            return Err(ErrorKind::IllegalValue(
+                "metrics interval".to_string(),
+                1,
+                true,
+                MAX_METRICS_INTERVAL,
+                true,
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+impl VmConfig {
+    /// Add the config of metrics to `VmConfig`.
+    pub fn add_metrics(&mut self, metrics_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("metrics");
+        cmd_parser.push("path").push("interval").push("format");
+        cmd_parser.parse(metrics_config)?;
+
+        let uri = match cmd_parser.get_value::<String>("path")? {
+            Some(uri) => uri,
+            None => return Err(ErrorKind::FieldIsMissing("path", "metrics").into()),
+        };
+        let target = match parse_uri(&uri).chain_err(|| "Failed to parse metrics path")? {
+            (UnixPath::File, path) => MetricsTarget::File(path),
+            (UnixPath::Unix, path) => MetricsTarget::Unix(path),
+            _ => bail!("Only file and unix socket are supported for metrics path"),
+        };
+        let interval = cmd_parser
+            .get_value::<u64>("interval")?
+            .unwrap_or(DEFAULT_METRICS_INTERVAL);
+        let format = match cmd_parser.get_value::<String>("format")? {
+            Some(format) => format.parse::<MetricsFormat>().map_err(|_| {
+                ErrorKind::InvalidParam(format.clone(), "metrics format".to_string())
+            })?,
+            None => MetricsFormat::default(),
+        };
+
+        let metrics = MetricsConfig {
+            target,
+            interval,
+            format,
+        };
+        metrics.check()?;
+        self.metrics = Some(metrics);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_metrics() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_metrics("path=unix:/tmp/metrics.sock,interval=5,format=json")
+            .is_ok());
+        let metrics = vm_config.metrics.as_ref().unwrap();
+        assert_eq!(
+            metrics.target,
+            MetricsTarget::Unix("/tmp/metrics.sock".to_string())
+        );
+        assert_eq!(metrics.interval, 5);
+        assert_eq!(metrics.format, MetricsFormat::Json);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_metrics("path=file:/tmp/vm.prom").is_ok());
+        let metrics = vm_config.metrics.as_ref().unwrap();
+        assert_eq!(
+            metrics.target,
+            MetricsTarget::File("/tmp/vm.prom".to_string())
+        );
+        assert_eq!(metrics.interval, DEFAULT_METRICS_INTERVAL);
+        assert_eq!(metrics.format, MetricsFormat::Prometheus);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_metrics("interval=5").is_err());
+        assert!(vm_config.add_metrics("path=/tmp/vm.prom").is_err());
+        assert!(vm_config
+            .add_metrics("path=tcp:127.0.0.1:9090,interval=5")
+            .is_err());
+        assert!(vm_config
+            .add_metrics("path=file:/tmp/vm.prom,interval=0")
+            .is_err());
+        assert!(vm_config
+            .add_metrics("path=file:/tmp/vm.prom,format=xml")
+            .is_err());
+    }
+}
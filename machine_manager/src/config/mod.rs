@@ -82,26 +82,37 @@ pub use boot_source::*;
 pub use chardev::*;
 pub use devices::*;
 pub use drive::*;
+pub use fs::*;
 pub use iothread::*;
 pub use machine_config::*;
+pub use metrics::*;
 pub use network::*;
+pub use numa::*;
 pub use pci::*;
 pub use rng::*;
+pub use scsi::*;
 pub use vfio::*;
+pub use virtio_mem::*;
 
 mod balloon;
 mod boot_source;
 mod chardev;
 mod devices;
 mod drive;
+mod fs;
 mod iothread;
 mod machine_config;
+mod metrics;
 mod network;
+mod numa;
 mod pci;
 mod rng;
+mod scsi;
 mod vfio;
+mod virtio_mem;
 
 use std::any::Any;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::str::FromStr;
 
@@ -117,6 +128,7 @@ pub const FAST_UNPLUG_OFF: &str = "0";
 #[derive(Debug, Clone)]
 pub enum ObjConfig {
     Rng(RngObjConfig),
+    Zone(MemZoneConfig),
 }
 
 fn parse_rng_obj(object_args: &str) -> Result<RngObjConfig> {
@@ -156,6 +168,8 @@ pub struct VmConfig {
     pub pflashs: Option<Vec<PFlashConfig>>,
     pub dev_name: HashMap<String, u8>,
     pub global_config: HashMap<String, String>,
+    pub numa_nodes: Vec<(String, String)>,
+    pub metrics: Option<MetricsConfig>,
 }
 
 impl VmConfig {
@@ -177,6 +191,26 @@ impl VmConfig {
             bail!("kernel file is required for microvm machine type, which is not provided");
         }
 
+        if !self.numa_nodes.is_empty() && self.machine_config.mach_type == MachineType::MicroVm {
+            bail!("Numa is not supported for microvm machine type");
+        }
+        if let Some(mem_backend) = &self.machine_config.mem_config.mem_backend {
+            match self.object.get(mem_backend) {
+                Some(ObjConfig::Zone(zone)) => {
+                    if zone.size != self.machine_config.mem_config.mem_size {
+                        bail!(
+                            "Size of memory backend {} is not equal to memory size",
+                            mem_backend
+                        );
+                    }
+                }
+                _ => bail!("Memory backend {} not found", mem_backend),
+            }
+            if self.machine_config.mem_config.mem_path.is_some() || !self.numa_nodes.is_empty() {
+                bail!("Memory backend can't be used with mem-path or numa");
+            }
+        }
+
         if self.boot_source.initrd.is_none() && self.drives.is_empty() {
             bail!("Before Vm start, set a initrd or drive_file as rootfs");
         }
@@ -242,6 +276,18 @@ impl VmConfig {
                     bail!("Object: {:?} has been added");
                 }
             }
+            "memory-backend-ram" | "memory-backend-file" | "memory-backend-memfd" => {
+                let zone_config = parse_mem_zone(object_args)?;
+                let id = zone_config.id.clone();
+                match self.object.entry(id) {
+                    Entry::Vacant(entry) => {
+                        entry.insert(ObjConfig::Zone(zone_config));
+                    }
+                    Entry::Occupied(entry) => {
+                        bail!("Object: {:?} has been added", entry.key());
+                    }
+                }
+            }
             _ => {
                 bail!("Unknow object type: {:?}", &device_type);
             }
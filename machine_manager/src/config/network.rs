@@ -14,33 +14,55 @@ extern crate serde;
 extern crate serde_json;
 
 use serde::{Deserialize, Serialize};
+use util::throttle::{BucketType, ThrottleConfig};
 
 use super::{
     errors::{ErrorKind, Result},
     pci_args_check,
 };
-use crate::config::{CmdParser, ConfigCheck, ExBool, VmConfig, MAX_STRING_LENGTH};
+use crate::config::{
+    check_bucket_limits, parse_bucket_options, push_bucket_options, ChardevType, CmdParser,
+    ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH, MAX_STRING_LENGTH,
+};
 use crate::qmp::{qmp_schema, QmpChannel};
 
 const MAC_ADDRESS_LENGTH: usize = 17;
+/// The maximum number of queue pairs of virtio-net device.
+pub const MAX_QUEUE_PAIRS: u16 = 16;
+/// The maximum of packets per second of rate limiting.
+const MAX_PPS: u64 = 100_000_000;
+/// Options of rate limiting of netdev. The read buckets limit received packets,
+/// and the write buckets limit transmitted packets.
+const RATE_LIMIT_NAMES: [(BucketType, &str); 4] = [
+    (BucketType::BpsRead, "rx-bps"),
+    (BucketType::IopsRead, "rx-pps"),
+    (BucketType::BpsWrite, "tx-bps"),
+    (BucketType::IopsWrite, "tx-pps"),
+];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetDevcfg {
     pub id: String,
-    pub tap_fd: Option<i32>,
+    pub tap_fds: Option<Vec<i32>>,
     pub vhost_type: Option<String>,
     pub vhost_fd: Option<i32>,
     pub ifname: String,
+    pub queues: u16,
+    pub chardev: Option<String>,
+    pub rate_limit: ThrottleConfig,
 }
 
 impl Default for NetDevcfg {
     fn default() -> Self {
         NetDevcfg {
             id: "".to_string(),
-            tap_fd: None,
+            tap_fds: None,
             vhost_type: None,
             vhost_fd: None,
             ifname: "".to_string(),
+            queues: 1,
+            chardev: None,
+            rate_limit: ThrottleConfig::default(),
         }
     }
 }
@@ -58,12 +80,23 @@ impl ConfigCheck for NetDevcfg {
         }
 
         if let Some(vhost_type) = self.vhost_type.as_ref() {
-            if vhost_type != "vhost-kernel" {
+            if vhost_type != "vhost-kernel" && vhost_type != "vhost-user" {
                 return Err(ErrorKind::UnknownVhostType.into());
             }
         }
 
-        Ok(())
+        if let Some(chardev) = self.chardev.as_ref() {
+            if chardev.len() > MAX_STRING_LENGTH {
+                return Err(ErrorKind::StringLengthTooLong(
+                    "chardev id".to_string(),
+                    MAX_STRING_LENGTH,
+                )
+                .into());
+            }
+        }
+
+        check_rate_limit(&self.rate_limit, self.vhost_type.as_ref())?;
+        check_queues(self.queues, self.tap_fds.as_ref())
     }
 }
 
@@ -75,10 +108,17 @@ pub struct NetworkInterfaceConfig {
     pub id: String,
     pub host_dev_name: String,
     pub mac: Option<String>,
-    pub tap_fd: Option<i32>,
+    pub tap_fds: Option<Vec<i32>>,
     pub vhost_type: Option<String>,
     pub vhost_fd: Option<i32>,
     pub iothread: Option<String>,
+    /// The iothreads the queue pairs are assigned to in turn, the first one is
+    /// `iothread`. None if all the queues are handled in `iothread`.
+    pub queue_iothreads: Option<Vec<String>>,
+    pub queues: u16,
+    pub mq: bool,
+    pub socket_path: Option<String>,
+    pub rate_limit: ThrottleConfig,
 }
 
 impl NetworkInterfaceConfig {
@@ -93,10 +133,15 @@ impl Default for NetworkInterfaceConfig {
             id: "".to_string(),
             host_dev_name: "".to_string(),
             mac: None,
-            tap_fd: None,
+            tap_fds: None,
             vhost_type: None,
             vhost_fd: None,
             iothread: None,
+            queue_iothreads: None,
+            queues: 1,
+            mq: false,
+            socket_path: None,
+            rate_limit: ThrottleConfig::default(),
         }
     }
 }
@@ -120,9 +165,22 @@ impl ConfigCheck for NetworkInterfaceConfig {
         }
 
         if let Some(vhost_type) = self.vhost_type.as_ref() {
-            if vhost_type != "vhost-kernel" {
+            if vhost_type != "vhost-kernel" && vhost_type != "vhost-user" {
                 return Err(ErrorKind::UnknownVhostType.into());
             }
+            if vhost_type == "vhost-user" && self.socket_path.is_none() {
+                bail!("Socket path is missing for vhost-user net device");
+            }
+        }
+
+        if let Some(socket_path) = self.socket_path.as_ref() {
+            if socket_path.len() > MAX_PATH_LENGTH {
+                return Err(ErrorKind::StringLengthTooLong(
+                    "socket path".to_string(),
+                    MAX_PATH_LENGTH,
+                )
+                .into());
+            }
         }
 
         if self.iothread.is_some() && self.iothread.as_ref().unwrap().len() > MAX_STRING_LENGTH {
@@ -132,11 +190,86 @@ impl ConfigCheck for NetworkInterfaceConfig {
             )
             .into());
         }
+        if let Some(iothreads) = self.queue_iothreads.as_ref() {
+            if iothreads.iter().any(|name| name.len() > MAX_STRING_LENGTH) {
+                return Err(ErrorKind::StringLengthTooLong(
+                    "iothread name".to_string(),
+                    MAX_STRING_LENGTH,
+                )
+                .into());
+            }
+            if iothreads.len() > self.queues as usize {
+                bail!(
+                    "{} iothreads are more than {} queue pairs of net device",
+                    iothreads.len(),
+                    self.queues
+                );
+            }
+        }
+
+        check_rate_limit(&self.rate_limit, self.vhost_type.as_ref())?;
+        check_queues(self.queues, self.tap_fds.as_ref())?;
+        if self.queues > 1 && !self.mq {
+            bail!(
+                "mq must be on for the net device with {} queue pairs",
+                self.queues
+            );
+        }
+        if let Some(vhost_type) = self.vhost_type.as_ref() {
+            if self.mq {
+                bail!("Multiqueue is not supported by {} net device", vhost_type);
+            }
+        }
 
         Ok(())
     }
 }
 
+/// Check the number of queue pairs, and it should be equal to the number of tap fds if given.
+fn check_queues(queues: u16, tap_fds: Option<&Vec<i32>>) -> Result<()> {
+    if !(1..=MAX_QUEUE_PAIRS).contains(&queues) {
+        return Err(ErrorKind::IllegalValue(
+            "number of queue pairs".to_string(),
+            1,
+            true,
+            MAX_QUEUE_PAIRS as u64,
+            true,
+        )
+        .into());
+    }
+    if let Some(fds) = tap_fds {
+        if fds.len() != queues as usize {
+            bail!(
+                "The number of tap fds {} is not equal to queue pairs {}",
+                fds.len(),
+                queues
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the limits of rate limiting, which is not supported by vhost net devices
+/// as their packets are not handled by StratoVirt.
+///
+/// # Arguments
+///
+/// * `rate_limit` - The limits of rate limiting.
+/// * `vhost_type` - The vhost type of the net device.
+pub fn check_rate_limit(rate_limit: &ThrottleConfig, vhost_type: Option<&String>) -> Result<()> {
+    check_bucket_limits(rate_limit, &RATE_LIMIT_NAMES, "net device", MAX_PPS)?;
+    if let Some(vhost_type) = vhost_type {
+        if rate_limit.enabled() {
+            bail!(
+                "Rate limiting is not supported by {} net device",
+                vhost_type
+            );
+        }
+    }
+    Ok(())
+}
+
 pub fn parse_netdev(cmd_parser: CmdParser) -> Result<NetDevcfg> {
     let mut net = NetDevcfg::default();
     let netdev_type = if let Some(netdev_type) = cmd_parser.get_value::<String>("")? {
@@ -144,7 +277,7 @@ pub fn parse_netdev(cmd_parser: CmdParser) -> Result<NetDevcfg> {
     } else {
         "".to_string()
     };
-    if netdev_type.ne("tap") {
+    if netdev_type.ne("tap") && netdev_type.ne("vhost-user") {
         bail!("Unsupported netdev type: {:?}", &netdev_type);
     }
     if let Some(net_id) = cmd_parser.get_value::<String>("id")? {
@@ -152,6 +285,19 @@ pub fn parse_netdev(cmd_parser: CmdParser) -> Result<NetDevcfg> {
     } else {
         return Err(ErrorKind::FieldIsMissing("id", "netdev").into());
     }
+    net.rate_limit = parse_bucket_options(&cmd_parser, &RATE_LIMIT_NAMES)?;
+    if netdev_type.eq("vhost-user") {
+        if let Some(chardev) = cmd_parser.get_value::<String>("chardev")? {
+            net.chardev = Some(chardev);
+        } else {
+            return Err(ErrorKind::FieldIsMissing("chardev", "vhost-user netdev").into());
+        }
+        net.vhost_type = Some(String::from("vhost-user"));
+        return Ok(net);
+    }
+    if cmd_parser.get_value::<String>("chardev")?.is_some() {
+        bail!("Argument \'chardev\' is only needed for vhost-user netdev");
+    }
     if let Some(ifname) = cmd_parser.get_value::<String>("ifname")? {
         net.ifname = ifname;
     }
@@ -161,12 +307,28 @@ pub fn parse_netdev(cmd_parser: CmdParser) -> Result<NetDevcfg> {
             net.vhost_type = Some(String::from("vhost-kernel"));
         }
     }
-    net.tap_fd = cmd_parser.get_value::<i32>("fd")?;
+    if let Some(fds) = cmd_parser.get_value::<String>("fds")? {
+        let mut tap_fds = Vec::new();
+        for fd in fds.split(':') {
+            tap_fds.push(
+                fd.parse::<i32>().map_err(|_| {
+                    ErrorKind::ConvertValueFailed("fds".to_string(), fd.to_string())
+                })?,
+            );
+        }
+        net.tap_fds = Some(tap_fds);
+    } else if let Some(fd) = cmd_parser.get_value::<i32>("fd")? {
+        net.tap_fds = Some(vec![fd]);
+    }
+    net.queues = match cmd_parser.get_value::<u16>("queues")? {
+        Some(queues) => queues,
+        None => net.tap_fds.as_ref().map_or(1, |fds| fds.len() as u16),
+    };
     net.vhost_fd = cmd_parser.get_value::<i32>("vhostfd")?;
     if net.vhost_fd.is_some() && net.vhost_type.is_none() {
         bail!("Argument \'vhostfd\' is not needed for virtio-net device");
     }
-    if net.tap_fd.is_none() && net.ifname.eq("") {
+    if net.tap_fds.is_none() && net.ifname.is_empty() {
         bail!("Tap device is missing, use \'ifname\' or \'fd\' to configure a tap device");
     }
 
@@ -183,7 +345,8 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
         .push("addr")
         .push("multifunction")
         .push("mac")
-        .push("iothread");
+        .push("iothread")
+        .push("mq");
 
     cmd_parser.parse(net_config)?;
     pci_args_check(&cmd_parser)?;
@@ -199,15 +362,25 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
     } else {
         "".to_string()
     };
-    netdevinterfacecfg.iothread = cmd_parser.get_value::<String>("iothread")?;
+    let (iothread, queue_iothreads) = split_iothreads(cmd_parser.get_value::<String>("iothread")?);
+    netdevinterfacecfg.iothread = iothread;
+    netdevinterfacecfg.queue_iothreads = queue_iothreads;
     netdevinterfacecfg.mac = cmd_parser.get_value::<String>("mac")?;
+    if let Some(mq) = cmd_parser.get_value::<ExBool>("mq")? {
+        netdevinterfacecfg.mq = mq.into();
+    }
 
     if let Some(netcfg) = &vm_config.netdevs.remove(&netdev) {
         netdevinterfacecfg.id = netid;
         netdevinterfacecfg.host_dev_name = netcfg.ifname.clone();
-        netdevinterfacecfg.tap_fd = netcfg.tap_fd;
+        netdevinterfacecfg.tap_fds = netcfg.tap_fds.clone();
         netdevinterfacecfg.vhost_fd = netcfg.vhost_fd;
         netdevinterfacecfg.vhost_type = netcfg.vhost_type.clone();
+        netdevinterfacecfg.queues = netcfg.queues;
+        netdevinterfacecfg.rate_limit = netcfg.rate_limit;
+        if let Some(chardev) = &netcfg.chardev {
+            netdevinterfacecfg.socket_path = Some(get_chardev_socket_path(vm_config, chardev)?);
+        }
     } else {
         bail!("Netdev: {:?} not found for net device", &netdev);
     }
@@ -216,38 +389,102 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
     Ok(netdevinterfacecfg)
 }
 
+/// Split the iothreads of net device separated by ':', e.g. `iothread1:iothread2`. Returns
+/// the first iothread, and all the iothreads if there are more than one.
+pub fn split_iothreads(iothread: Option<String>) -> (Option<String>, Option<Vec<String>>) {
+    let iothreads: Vec<String> = match iothread {
+        Some(iothread) => iothread.split(':').map(String::from).collect(),
+        None => return (None, None),
+    };
+    if iothreads.len() == 1 {
+        return (iothreads.into_iter().next(), None);
+    }
+    (Some(iothreads[0].clone()), Some(iothreads))
+}
+
+/// Get the socket path of the chardev used by vhost-user device. The chardev must be a
+/// client-mode socket, and the guest memory must be shared with the vhost-user slave.
+///
+/// # Arguments
+///
+/// * `vm_config` - VM configuration.
+/// * `chardev` - Id of the chardev.
+pub fn get_chardev_socket_path(vm_config: &mut VmConfig, chardev: &str) -> Result<String> {
+    if !vm_config.machine_config.mem_config.mem_share {
+        bail!("Guest memory must be shared for vhost-user device, use \'mem-share=on\'");
+    }
+    if let Some(char_dev) = vm_config.chardev.remove(chardev) {
+        if let ChardevType::Socket {
+            path,
+            server: false,
+            ..
+        } = char_dev.backend
+        {
+            Ok(path)
+        } else {
+            bail!(
+                "Chardev {:?} of vhost-user device must be a client-mode socket",
+                chardev
+            );
+        }
+    } else {
+        bail!("Chardev {:?} not found or is in use", chardev);
+    }
+}
+
 pub fn get_netdev_config(args: Box<qmp_schema::NetDevAddArgument>) -> Result<NetDevcfg> {
+    let rate_limit = get_netdev_rate_limit(&args)?;
     let mut config = NetDevcfg {
         id: args.id,
-        tap_fd: None,
+        tap_fds: None,
         vhost_type: None,
         vhost_fd: None,
         ifname: String::new(),
+        queues: 1,
+        chardev: None,
+        rate_limit,
     };
 
+    if args.net_type.as_deref() == Some("vhost-user") {
+        if args.chardev.is_none() {
+            return Err(ErrorKind::FieldIsMissing("chardev", "vhost-user netdev").into());
+        }
+        config.chardev = args.chardev;
+        config.vhost_type = Some(String::from("vhost-user"));
+        return Ok(config);
+    }
+
     if let Some(fds) = args.fds {
-        let netdev_fd = if fds.contains(':') {
-            let col: Vec<_> = fds.split(':').collect();
-            String::from(col[col.len() - 1])
-        } else {
-            String::from(&fds)
-        };
-        if let Some(fd_num) = QmpChannel::get_fd(&netdev_fd) {
-            config.tap_fd = Some(fd_num);
-        } else {
-            // try to convert string to RawFd
-            let fd_num = match netdev_fd.parse::<i32>() {
-                Ok(fd) => fd,
-                _ => {
-                    bail!("Failed to parse fd: {}", netdev_fd);
-                }
-            };
-            config.tap_fd = Some(fd_num);
+        let mut tap_fds = Vec::new();
+        for netdev_fd in fds.split(':') {
+            if let Some(fd_num) = QmpChannel::get_fd(netdev_fd) {
+                tap_fds.push(fd_num);
+            } else {
+                // try to convert string to RawFd
+                let fd_num = match netdev_fd.parse::<i32>() {
+                    Ok(fd) => fd,
+                    _ => {
+                        bail!("Failed to parse fd: {}", netdev_fd);
+                    }
+                };
+                tap_fds.push(fd_num);
+            }
         }
+        config.queues = tap_fds.len() as u16;
+        config.tap_fds = Some(tap_fds);
     } else if let Some(if_name) = args.if_name {
         config.ifname = if_name;
     }
 
+    if let Some(queues) = args.queues {
+        config.queues = match queues.parse::<u16>() {
+            Ok(queues) => queues,
+            Err(_) => {
+                bail!("Failed to parse queues: {}", queues);
+            }
+        };
+    }
+
     if let Some(vhost) = args.vhost {
         match vhost.parse::<ExBool>() {
             Ok(vhost) => {
@@ -271,13 +508,64 @@ pub fn get_netdev_config(args: Box<qmp_schema::NetDevAddArgument>) -> Result<Net
     if config.vhost_fd.is_some() && config.vhost_type.is_none() {
         bail!("Argument \'vhostfd\' is not needed for virtio-net device");
     }
-    if config.tap_fd.is_none() && config.ifname.eq("") {
+    if config.tap_fds.is_none() && config.ifname.is_empty() {
         bail!("Tap device is missing, use \'ifname\' or \'fd\' to configure a tap device");
     }
 
     Ok(config)
 }
 
+/// Build the limits of rate limiting from the arguments of QMP commands.
+///
+/// # Arguments
+///
+/// * `limits` - The average, maximum and maximum length of buckets, in order of
+///   rx bps, rx pps, tx bps and tx pps.
+fn build_rate_limit(limits: [(Option<u64>, Option<u64>, Option<u64>); 4]) -> ThrottleConfig {
+    let bucket_types = [
+        BucketType::BpsRead,
+        BucketType::IopsRead,
+        BucketType::BpsWrite,
+        BucketType::IopsWrite,
+    ];
+    let mut rate_limit = ThrottleConfig::default();
+    for (bucket_type, (avg, max, max_length)) in bucket_types.iter().zip(limits.iter()) {
+        let bucket = rate_limit.bucket_mut(*bucket_type);
+        bucket.avg = avg.unwrap_or(0);
+        bucket.max = max.unwrap_or(0);
+        bucket.max_length = max_length.unwrap_or(0);
+    }
+    rate_limit
+}
+
+/// Get the limits of rate limiting from the arguments of `netdev_add`,
+/// the limits are checked.
+pub fn get_netdev_rate_limit(args: &qmp_schema::NetDevAddArgument) -> Result<ThrottleConfig> {
+    let rate_limit = build_rate_limit([
+        (args.rx_bps, args.rx_bps_max, args.rx_bps_max_length),
+        (args.rx_pps, args.rx_pps_max, args.rx_pps_max_length),
+        (args.tx_bps, args.tx_bps_max, args.tx_bps_max_length),
+        (args.tx_pps, args.tx_pps_max, args.tx_pps_max_length),
+    ]);
+    check_rate_limit(&rate_limit, None)?;
+    Ok(rate_limit)
+}
+
+/// Get the limits of rate limiting from the arguments of `netdev_set_rate_limit`,
+/// the limits are checked.
+pub fn get_net_rate_limit_config(
+    args: &qmp_schema::NetRateLimitArgument,
+) -> Result<ThrottleConfig> {
+    let rate_limit = build_rate_limit([
+        (Some(args.rx_bps), args.rx_bps_max, args.rx_bps_max_length),
+        (Some(args.rx_pps), args.rx_pps_max, args.rx_pps_max_length),
+        (Some(args.tx_bps), args.tx_bps_max, args.tx_bps_max_length),
+        (Some(args.tx_pps), args.tx_pps_max, args.tx_pps_max_length),
+    ]);
+    check_rate_limit(&rate_limit, None)?;
+    Ok(rate_limit)
+}
+
 impl VmConfig {
     pub fn add_netdev(&mut self, netdev_config: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("netdev");
@@ -285,9 +573,13 @@ impl VmConfig {
             .push("")
             .push("id")
             .push("fd")
+            .push("fds")
+            .push("queues")
             .push("vhost")
             .push("ifname")
-            .push("vhostfd");
+            .push("vhostfd")
+            .push("chardev");
+        push_bucket_options(&mut cmd_parser, &RATE_LIMIT_NAMES);
 
         cmd_parser.parse(netdev_config)?;
         let drive_cfg = parse_netdev(cmd_parser)?;
@@ -349,6 +641,35 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_network_queue_iothreads() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,queues=4")
+            .is_ok());
+        let net_cfg = parse_net(
+            &mut vm_config,
+            "virtio-net-pci,id=net0,netdev=eth0,bus=pcie.0,addr=0x2.0x0,mq=on,iothread=iothread0:iothread1",
+        )
+        .unwrap();
+        assert_eq!(net_cfg.iothread, Some("iothread0".to_string()));
+        assert_eq!(
+            net_cfg.queue_iothreads,
+            Some(vec!["iothread0".to_string(), "iothread1".to_string()])
+        );
+
+        // No more iothreads than queue pairs.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,queues=2")
+            .is_ok());
+        assert!(parse_net(
+            &mut vm_config,
+            "virtio-net-pci,id=net0,netdev=eth0,bus=pcie.0,addr=0x2.0x0,mq=on,iothread=a:b:c",
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_network_config_cmdline_parser() {
         let mut vm_config = VmConfig::default();
@@ -362,8 +683,9 @@ mod tests {
         assert_eq!(network_configs.id, "net0");
         assert_eq!(network_configs.host_dev_name, "tap0");
         assert_eq!(network_configs.iothread, Some("iothread0".to_string()));
+        assert!(network_configs.queue_iothreads.is_none());
         assert!(network_configs.mac.is_none());
-        assert!(network_configs.tap_fd.is_none());
+        assert!(network_configs.tap_fds.is_none());
         assert!(network_configs.vhost_type.is_none());
         assert!(network_configs.vhost_fd.is_none());
 
@@ -380,7 +702,7 @@ mod tests {
         assert_eq!(network_configs.id, "net1");
         assert_eq!(network_configs.host_dev_name, "tap1");
         assert_eq!(network_configs.mac, Some(String::from("12:34:56:78:9A:BC")));
-        assert!(network_configs.tap_fd.is_none());
+        assert!(network_configs.tap_fds.is_none());
         assert_eq!(
             network_configs.vhost_type,
             Some(String::from("vhost-kernel"))
@@ -394,7 +716,7 @@ mod tests {
         let network_configs = net_cfg_res.unwrap();
         assert_eq!(network_configs.id, "net1");
         assert_eq!(network_configs.host_dev_name, "");
-        assert_eq!(network_configs.tap_fd, Some(35));
+        assert_eq!(network_configs.tap_fds, Some(vec![35]));
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config
@@ -412,6 +734,182 @@ mod tests {
         assert!(net_cfg_res.is_err());
     }
 
+    #[test]
+    fn test_network_multiqueue_config() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,queues=4")
+            .is_ok());
+        let net_cfg_res = parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=eth0,mq=on");
+        assert!(net_cfg_res.is_ok());
+        let network_configs = net_cfg_res.unwrap();
+        assert_eq!(network_configs.queues, 4);
+        assert!(network_configs.mq);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,fds=35:36").is_ok());
+        let net_cfg_res = parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=eth0,mq=on");
+        assert!(net_cfg_res.is_ok());
+        let network_configs = net_cfg_res.unwrap();
+        assert_eq!(network_configs.queues, 2);
+        assert_eq!(network_configs.tap_fds, Some(vec![35, 36]));
+
+        // Multiple queues without mq.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,queues=4")
+            .is_ok());
+        assert!(parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=eth0").is_err());
+
+        // The number of fds doesn't match queues.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,fds=35:36,queues=4")
+            .is_ok());
+        assert!(parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=eth0,mq=on").is_err());
+
+        // Queues overflow.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,queues=17")
+            .is_ok());
+        assert!(parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=eth0,mq=on").is_err());
+
+        // Multiqueue is not supported by vhost-kernel net.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,vhost=on")
+            .is_ok());
+        assert!(parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=eth0,mq=on").is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,fds=35:a").is_err());
+    }
+
+    #[test]
+    fn test_vhost_user_network_config() {
+        let mut vm_config = VmConfig::default();
+        vm_config.machine_config.mem_config.mem_share = true;
+        assert!(vm_config
+            .add_chardev("socket,id=char0,path=/path/to/vhost-user.sock")
+            .is_ok());
+        assert!(vm_config
+            .add_netdev("vhost-user,id=netdevid,chardev=char0")
+            .is_ok());
+        let net_cfg_res = parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=netdevid");
+        assert!(net_cfg_res.is_ok());
+        let network_configs = net_cfg_res.unwrap();
+        assert_eq!(network_configs.vhost_type, Some(String::from("vhost-user")));
+        assert_eq!(
+            network_configs.socket_path,
+            Some(String::from("/path/to/vhost-user.sock"))
+        );
+        assert!(network_configs.tap_fds.is_none());
+        // The chardev is in use.
+        assert!(!vm_config.chardev.contains_key("char0"));
+
+        // Chardev is missing.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("vhost-user,id=netdevid").is_err());
+        assert!(vm_config
+            .add_netdev("tap,id=netdevid,ifname=tap0,chardev=char0")
+            .is_err());
+
+        // Chardev not found.
+        let mut vm_config = VmConfig::default();
+        vm_config.machine_config.mem_config.mem_share = true;
+        assert!(vm_config
+            .add_netdev("vhost-user,id=netdevid,chardev=char0")
+            .is_ok());
+        assert!(parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=netdevid").is_err());
+
+        // Server-mode socket can't be used.
+        let mut vm_config = VmConfig::default();
+        vm_config.machine_config.mem_config.mem_share = true;
+        assert!(vm_config
+            .add_chardev("socket,id=char0,path=/path/to/vhost-user.sock,server,nowait")
+            .is_ok());
+        assert!(vm_config
+            .add_netdev("vhost-user,id=netdevid,chardev=char0")
+            .is_ok());
+        assert!(parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=netdevid").is_err());
+
+        // Guest memory is not shared.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_chardev("socket,id=char0,path=/path/to/vhost-user.sock")
+            .is_ok());
+        assert!(vm_config
+            .add_netdev("vhost-user,id=netdevid,chardev=char0")
+            .is_ok());
+        assert!(parse_net(&mut vm_config, "virtio-net-pci,id=net0,netdev=netdevid").is_err());
+
+        // Multiqueue is not supported by vhost-user net.
+        let mut vm_config = VmConfig::default();
+        vm_config.machine_config.mem_config.mem_share = true;
+        assert!(vm_config
+            .add_chardev("socket,id=char0,path=/path/to/vhost-user.sock")
+            .is_ok());
+        assert!(vm_config
+            .add_netdev("vhost-user,id=netdevid,chardev=char0")
+            .is_ok());
+        assert!(parse_net(
+            &mut vm_config,
+            "virtio-net-pci,id=net0,netdev=netdevid,mq=on"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_network_rate_limit_config() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev(
+                "tap,id=eth0,ifname=tap0,rx-bps=1048576,tx-pps=1000,\
+                tx-pps-max=2000,tx-pps-max-length=5"
+            )
+            .is_ok());
+        let net_cfg = parse_net(&mut vm_config, "virtio-net-device,id=net0,netdev=eth0").unwrap();
+        let rate_limit = net_cfg.rate_limit;
+        assert_eq!(rate_limit.bucket(BucketType::BpsRead).avg, 1048576);
+        assert_eq!(rate_limit.bucket(BucketType::IopsRead).avg, 0);
+        assert_eq!(rate_limit.bucket(BucketType::BpsWrite).avg, 0);
+        let tx_pps = rate_limit.bucket(BucketType::IopsWrite);
+        assert_eq!((tx_pps.avg, tx_pps.max, tx_pps.max_length), (1000, 2000, 5));
+
+        // Invalid limits.
+        for netdev in [
+            "rx-pps-max=100",
+            "rx-bps=100,rx-bps-max=10",
+            "tx-bps=100,tx-bps-max-length=10",
+            "tx-pps=100000001",
+            "vhost=on,tx-bps=100",
+        ]
+        .iter()
+        {
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config
+                .add_netdev(&format!("tap,id=eth0,ifname=tap0,{}", netdev))
+                .is_ok());
+            assert!(parse_net(&mut vm_config, "virtio-net-device,id=net0,netdev=eth0").is_err());
+        }
+
+        // Rate limits from netdev_add.
+        let mut netdev_add = create_netdev_add(
+            String::from("netdev"),
+            Some(String::from("tap0")),
+            None,
+            None,
+            None,
+        );
+        netdev_add.tx_bps = Some(4096);
+        netdev_add.tx_bps_max = Some(8192);
+        let net_cfg = get_netdev_config(netdev_add).unwrap();
+        let tx_bps = net_cfg.rate_limit.bucket(BucketType::BpsWrite);
+        assert_eq!((tx_bps.avg, tx_bps.max), (4096, 8192));
+        assert!(net_cfg.check().is_ok());
+    }
+
     #[test]
     fn test_pci_network_config_cmdline_parser() {
         let mut vm_config = VmConfig::default();
@@ -427,7 +925,7 @@ mod tests {
         assert_eq!(network_configs.id, "net1");
         assert_eq!(network_configs.host_dev_name, "tap1");
         assert_eq!(network_configs.mac, Some(String::from("12:34:56:78:9A:BC")));
-        assert!(network_configs.tap_fd.is_none());
+        assert!(network_configs.tap_fds.is_none());
         assert_eq!(
             network_configs.vhost_type,
             Some(String::from("vhost-kernel"))
@@ -478,6 +976,8 @@ mod tests {
         assert!(netdev_conf.check().is_ok());
         netdev_conf.vhost_type = Some(String::from("vhost-kernel"));
         assert!(netdev_conf.check().is_ok());
+        netdev_conf.vhost_type = Some(String::from("vhost-user"));
+        assert!(netdev_conf.check().is_ok());
         netdev_conf.vhost_type = Some(String::from("vhost-"));
         assert!(netdev_conf.check().is_err());
     }
@@ -545,6 +1045,8 @@ mod tests {
             downscript: None,
             script: None,
             queues: None,
+            chardev: None,
+            ..Default::default()
         })
     }
 
@@ -628,5 +1130,28 @@ mod tests {
         let net_cfg = net_cfg.unwrap();
         assert_eq!(net_cfg.vhost_type.unwrap(), "vhost-kernel");
         assert_eq!(net_cfg.vhost_fd.unwrap(), 12);
+        let mut netdev_add = create_netdev_add(String::from("netdev"), None, None, None, None);
+        netdev_add.net_type = Some(String::from("vhost-user"));
+        assert!(get_netdev_config(netdev_add.clone()).is_err());
+        netdev_add.chardev = Some(String::from("char0"));
+        let net_cfg = get_netdev_config(netdev_add).unwrap();
+        assert_eq!(net_cfg.vhost_type.unwrap(), "vhost-user");
+        assert_eq!(net_cfg.chardev.unwrap(), "char0");
+
+        // Limits of rate limiting are checked.
+        let mut netdev_add = create_netdev_add(
+            String::from("netdev"),
+            Some(String::from("tap0")),
+            None,
+            None,
+            None,
+        );
+        netdev_add.rx_bps = Some(1000);
+        netdev_add.rx_bps_max = Some(500);
+        assert!(get_netdev_config(netdev_add.clone()).is_err());
+        netdev_add.rx_bps_max = Some(2000);
+        let net_cfg = get_netdev_config(netdev_add).unwrap();
+        assert_eq!(net_cfg.rate_limit.bucket(BucketType::BpsRead).avg, 1000);
+        assert_eq!(net_cfg.rate_limit.bucket(BucketType::BpsRead).max, 2000);
     }
 }
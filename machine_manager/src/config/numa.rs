@@ -0,0 +1,431 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::BTreeMap;
+
+use super::errors::{ErrorKind, Result};
+use crate::config::{CmdParser, MemZoneConfig, ObjConfig, VmConfig};
+
+/// The maximum number of guest numa nodes.
+pub const MAX_NODES: u32 = 128;
+/// Distance from a node to itself.
+pub const LOCAL_DISTANCE: u8 = 10;
+/// Distance between two nodes if not specified.
+pub const DEFAULT_DISTANCE: u8 = 20;
+/// Distance 255 means the nodes are unreachable.
+const MAX_DISTANCE: u8 = 254;
+
+/// Config of a guest numa node, completed with its memory backend and distances.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NumaNode {
+    /// Vcpus in this node.
+    pub cpus: Vec<u8>,
+    /// Distances to all the nodes, indexed by node id.
+    pub distances: BTreeMap<u32, u8>,
+    /// Memory size of this node.
+    pub size: u64,
+    /// Memory backend of this node.
+    pub mem_zone: MemZoneConfig,
+}
+
+/// Guest numa nodes indexed by node id.
+pub type NumaNodes = BTreeMap<u32, NumaNode>;
+
+/// Config of `-numa node`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NumaConfig {
+    pub numa_id: u32,
+    pub cpus: Vec<u8>,
+    pub mem_dev: String,
+}
+
+/// Config of `-numa dist`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NumaDistance {
+    pub source: u32,
+    pub destination: u32,
+    pub distance: u8,
+}
+
+/// Parse the list of ids, such as `0-3:5:7-8`.
+///
+/// # Arguments
+///
+/// * `list` - Ids or ranges of ids separated by ':'.
+pub fn parse_id_list(list: &str) -> Result<Vec<u32>> {
+    let mut ids = Vec::new();
+    for item in list.split(':') {
+        let (start, end) = match item.find('-') {
+            Some(pos) => (&item[..pos], &item[pos + 1..]),
+            None => (item, item),
+        };
+        let start = start
+            .parse::<u32>()
+            .map_err(|_| ErrorKind::ConvertValueFailed(list.to_string(), "u32".to_string()))?;
+        let end = end
+            .parse::<u32>()
+            .map_err(|_| ErrorKind::ConvertValueFailed(list.to_string(), "u32".to_string()))?;
+        if start > end {
+            bail!("Invalid range {} in {}", item, list);
+        }
+        for id in start..=end {
+            if ids.contains(&id) {
+                bail!("Id {} is repeated in {}", id, list);
+            }
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+
+    Ok(ids)
+}
+
+/// Parse the config of `-numa node`.
+///
+/// # Arguments
+///
+/// * `numa_config` - The args of numa node.
+pub fn parse_numa_mem(numa_config: &str) -> Result<NumaConfig> {
+    let mut cmd_parser = CmdParser::new("numa");
+    cmd_parser
+        .push("")
+        .push("nodeid")
+        .push("cpus")
+        .push("memdev");
+    cmd_parser.parse(numa_config)?;
+
+    let mut config = NumaConfig::default();
+    if let Some(numa_id) = cmd_parser.get_value::<u32>("nodeid")? {
+        if numa_id >= MAX_NODES {
+            return Err(ErrorKind::IllegalValue(
+                "nodeid".to_string(),
+                0,
+                true,
+                u64::from(MAX_NODES),
+                false,
+            )
+            .into());
+        }
+        config.numa_id = numa_id;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("nodeid", "numa").into());
+    }
+    if let Some(cpus) = cmd_parser.get_value::<String>("cpus")? {
+        for cpu in parse_id_list(&cpus)? {
+            if cpu > u32::from(u8::MAX) {
+                bail!("Invalid cpu {} of numa node {}", cpu, config.numa_id);
+            }
+            config.cpus.push(cpu as u8);
+        }
+    } else {
+        return Err(ErrorKind::FieldIsMissing("cpus", "numa").into());
+    }
+    if let Some(mem_dev) = cmd_parser.get_value::<String>("memdev")? {
+        config.mem_dev = mem_dev;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("memdev", "numa").into());
+    }
+
+    Ok(config)
+}
+
+/// Parse the config of `-numa dist`.
+///
+/// # Arguments
+///
+/// * `numa_dist` - The args of numa distance.
+pub fn parse_numa_distance(numa_dist: &str) -> Result<NumaDistance> {
+    let mut cmd_parser = CmdParser::new("numa");
+    cmd_parser.push("").push("src").push("dst").push("val");
+    cmd_parser.parse(numa_dist)?;
+
+    let mut dist = NumaDistance::default();
+    if let Some(src) = cmd_parser.get_value::<u32>("src")? {
+        dist.source = src;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("src", "numa").into());
+    }
+    if let Some(dst) = cmd_parser.get_value::<u32>("dst")? {
+        dist.destination = dst;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("dst", "numa").into());
+    }
+    if let Some(val) = cmd_parser.get_value::<u8>("val")? {
+        dist.distance = val;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("val", "numa").into());
+    }
+
+    if dist.source >= MAX_NODES || dist.destination >= MAX_NODES {
+        bail!(
+            "Invalid numa distance from node {} to node {}",
+            dist.source,
+            dist.destination
+        );
+    }
+    if dist.source == dist.destination && dist.distance != LOCAL_DISTANCE {
+        bail!(
+            "Local distance of node {} must be {}",
+            dist.source,
+            LOCAL_DISTANCE
+        );
+    }
+    if dist.source != dist.destination
+        && (dist.distance <= LOCAL_DISTANCE || dist.distance > MAX_DISTANCE)
+    {
+        return Err(ErrorKind::IllegalValue(
+            "numa distance".to_string(),
+            u64::from(LOCAL_DISTANCE),
+            false,
+            u64::from(MAX_DISTANCE),
+            true,
+        )
+        .into());
+    }
+
+    Ok(dist)
+}
+
+impl VmConfig {
+    /// Add argument `numa` to `VmConfig`, the numa nodes are completed after
+    /// all the memory backends are added.
+    ///
+    /// # Arguments
+    ///
+    /// * `numa_config` - The args of numa.
+    pub fn add_numa(&mut self, numa_config: &str) -> Result<()> {
+        let mut cmd_params = CmdParser::new("numa");
+        cmd_params.push("");
+        cmd_params.get_parameters(numa_config)?;
+
+        match cmd_params.get_value::<String>("")? {
+            Some(numa_type) if numa_type == "node" || numa_type == "dist" => {
+                self.numa_nodes.push((numa_type, numa_config.to_string()));
+            }
+            Some(numa_type) => bail!("Unsupported numa type: {}", numa_type),
+            None => bail!("Numa type not specified"),
+        }
+
+        Ok(())
+    }
+}
+
+/// Complete the guest numa nodes from `-numa` configs and memory backends.
+/// Return `None` if numa is not configured.
+///
+/// # Arguments
+///
+/// * `vm_config` - Vm config with `-numa` configs.
+pub fn complete_numa_nodes(vm_config: &VmConfig) -> Result<Option<NumaNodes>> {
+    if vm_config.numa_nodes.is_empty() {
+        return Ok(None);
+    }
+
+    let max_cpus = vm_config.machine_config.max_cpus;
+    let mut numa_nodes = NumaNodes::new();
+    let mut distances = Vec::new();
+    let mut mem_devs = Vec::new();
+    for (numa_type, numa_config) in vm_config.numa_nodes.iter() {
+        if numa_type == "dist" {
+            distances.push(parse_numa_distance(numa_config)?);
+            continue;
+        }
+
+        let config = parse_numa_mem(numa_config)?;
+        if numa_nodes.contains_key(&config.numa_id) {
+            return Err(
+                ErrorKind::IdRepeat("numa node".to_string(), config.numa_id.to_string()).into(),
+            );
+        }
+        for cpu in config.cpus.iter() {
+            if *cpu >= max_cpus {
+                bail!("Cpu {} of numa node {} does not exist", cpu, config.numa_id);
+            }
+            if numa_nodes.values().any(|node| node.cpus.contains(cpu)) {
+                bail!("Cpu {} is assigned to more than one numa node", cpu);
+            }
+        }
+        let zone = match vm_config.object.get(&config.mem_dev) {
+            Some(ObjConfig::Zone(zone)) => zone,
+            _ => bail!(
+                "Memory backend {} of numa node {} not found",
+                config.mem_dev,
+                config.numa_id
+            ),
+        };
+        if mem_devs.contains(&config.mem_dev) {
+            bail!("Memory backend {} is used more than once", config.mem_dev);
+        }
+        mem_devs.push(config.mem_dev.clone());
+
+        numa_nodes.insert(
+            config.numa_id,
+            NumaNode {
+                cpus: config.cpus,
+                distances: BTreeMap::new(),
+                size: zone.size,
+                mem_zone: zone.clone(),
+            },
+        );
+    }
+
+    if numa_nodes.is_empty() {
+        bail!("Numa distance is set without numa node");
+    }
+    // Node ids are used as the index of distance matrix, so they must be continuous.
+    if let Some((id, _)) = numa_nodes.iter().last() {
+        if *id as usize + 1 != numa_nodes.len() {
+            bail!("Numa node ids must be continuous from 0");
+        }
+    }
+    let mem_size: u64 = numa_nodes.values().map(|node| node.size).sum();
+    if mem_size != vm_config.machine_config.mem_config.mem_size {
+        bail!(
+            "Total memory size of numa nodes 0x{:x} is not equal to memory size 0x{:x}",
+            mem_size,
+            vm_config.machine_config.mem_config.mem_size
+        );
+    }
+
+    for dist in distances.iter() {
+        if !numa_nodes.contains_key(&dist.source) || !numa_nodes.contains_key(&dist.destination) {
+            bail!(
+                "Numa node of distance from {} to {} does not exist",
+                dist.source,
+                dist.destination
+            );
+        }
+        numa_nodes
+            .get_mut(&dist.source)
+            .unwrap()
+            .distances
+            .insert(dist.destination, dist.distance);
+    }
+
+    // The distance is symmetrical if the opposite direction is not set.
+    let node_ids: Vec<u32> = numa_nodes.keys().cloned().collect();
+    for src in node_ids.iter() {
+        for dst in node_ids.iter() {
+            let distance = if src == dst {
+                LOCAL_DISTANCE
+            } else if let Some(distance) = numa_nodes[dst].distances.get(src) {
+                *distance
+            } else {
+                DEFAULT_DISTANCE
+            };
+            numa_nodes
+                .get_mut(src)
+                .unwrap()
+                .distances
+                .entry(*dst)
+                .or_insert(distance);
+        }
+    }
+
+    Ok(Some(numa_nodes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HostMemPolicy;
+
+    #[test]
+    fn test_parse_id_list() {
+        assert_eq!(parse_id_list("3").unwrap(), vec![3]);
+        assert_eq!(parse_id_list("0-2:5").unwrap(), vec![0, 1, 2, 5]);
+        assert!(parse_id_list("2-1").is_err());
+        assert!(parse_id_list("0-2:1").is_err());
+        assert!(parse_id_list("a").is_err());
+    }
+
+    #[test]
+    fn test_parse_numa_mem() {
+        let config = parse_numa_mem("node,nodeid=1,cpus=0-1:4,memdev=mem1").unwrap();
+        assert_eq!(config.numa_id, 1);
+        assert_eq!(config.cpus, vec![0, 1, 4]);
+        assert_eq!(config.mem_dev, "mem1");
+
+        assert!(parse_numa_mem("node,cpus=0,memdev=mem0").is_err());
+        assert!(parse_numa_mem("node,nodeid=0,memdev=mem0").is_err());
+        assert!(parse_numa_mem("node,nodeid=0,cpus=0").is_err());
+        assert!(parse_numa_mem("node,nodeid=128,cpus=0,memdev=mem0").is_err());
+        assert!(parse_numa_mem("node,nodeid=0,cpus=0-256,memdev=mem0").is_err());
+    }
+
+    #[test]
+    fn test_parse_numa_distance() {
+        let dist = parse_numa_distance("dist,src=0,dst=1,val=21").unwrap();
+        assert_eq!(dist.source, 0);
+        assert_eq!(dist.destination, 1);
+        assert_eq!(dist.distance, 21);
+
+        assert!(parse_numa_distance("dist,src=0,dst=0,val=10").is_ok());
+        assert!(parse_numa_distance("dist,src=0,dst=0,val=20").is_err());
+        assert!(parse_numa_distance("dist,src=0,dst=1,val=10").is_err());
+        assert!(parse_numa_distance("dist,src=0,dst=1,val=255").is_err());
+        assert!(parse_numa_distance("dist,src=0,dst=1").is_err());
+    }
+
+    #[test]
+    fn test_complete_numa_nodes() {
+        let mut vm_config = VmConfig::default();
+        assert!(complete_numa_nodes(&vm_config).unwrap().is_none());
+
+        assert!(vm_config.add_cpu("4").is_ok());
+        assert!(vm_config.add_memory("2G").is_ok());
+        assert!(vm_config
+            .add_object("memory-backend-ram,id=mem0,size=1G")
+            .is_ok());
+        assert!(vm_config
+            .add_object("memory-backend-ram,id=mem1,size=1G,host-nodes=1,policy=bind")
+            .is_ok());
+        assert!(vm_config
+            .add_numa("node,nodeid=0,cpus=0-1,memdev=mem0")
+            .is_ok());
+        assert!(vm_config.add_numa("dist,src=0,dst=1,val=30").is_ok());
+        assert!(vm_config
+            .add_numa("node,nodeid=1,cpus=2-3,memdev=mem1")
+            .is_ok());
+        assert!(vm_config.add_numa("cpu,node-id=0").is_err());
+
+        let numa_nodes = complete_numa_nodes(&vm_config).unwrap().unwrap();
+        assert_eq!(numa_nodes.len(), 2);
+        assert_eq!(numa_nodes[&0].cpus, vec![0, 1]);
+        assert_eq!(numa_nodes[&0].size, 1 << 30);
+        assert_eq!(numa_nodes[&1].mem_zone.host_numa_nodes, Some(vec![1]));
+        assert_eq!(numa_nodes[&1].mem_zone.policy, HostMemPolicy::Bind);
+        assert_eq!(numa_nodes[&0].distances[&0], LOCAL_DISTANCE);
+        assert_eq!(numa_nodes[&0].distances[&1], 30);
+        assert_eq!(numa_nodes[&1].distances[&0], 30);
+
+        // Memory size of nodes mismatches.
+        let mut config = vm_config.clone();
+        assert!(config.add_memory("4G").is_ok());
+        assert!(complete_numa_nodes(&config).is_err());
+
+        // Cpu is assigned twice.
+        let mut config = vm_config.clone();
+        config.numa_nodes[2].1 = "node,nodeid=1,cpus=1-3,memdev=mem1".to_string();
+        assert!(complete_numa_nodes(&config).is_err());
+
+        // Node ids are not continuous.
+        let mut config = vm_config.clone();
+        config.numa_nodes[2].1 = "node,nodeid=2,cpus=2-3,memdev=mem1".to_string();
+        assert!(complete_numa_nodes(&config).is_err());
+
+        // Memory backend not found.
+        let mut config = vm_config;
+        config.numa_nodes[2].1 = "node,nodeid=1,cpus=2-3,memdev=mem2".to_string();
+        assert!(complete_numa_nodes(&config).is_err());
+    }
+}
@@ -0,0 +1,384 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use serde::{Deserialize, Serialize};
+use util::aio::AioEngine;
+
+use super::errors::{ErrorKind, Result};
+use super::pci_args_check;
+use crate::config::{
+    CmdParser, ConfigCheck, DiskFormat, DriveConfig, VmConfig, MAX_PATH_LENGTH, MAX_STRING_LENGTH,
+};
+
+/// The maximum number of request queues of virtio-scsi controller.
+pub const MAX_SCSI_QUEUES: u16 = 16;
+/// The maximum channel number of scsi device, only channel 0 is supported by virtio-scsi.
+pub const MAX_SCSI_CHANNEL: u8 = 0;
+/// The maximum target number of scsi device.
+pub const MAX_SCSI_TARGET: u8 = 255;
+/// The maximum lun number of scsi device, refer to the flat space addressing of SAM.
+pub const MAX_SCSI_LUN: u16 = 16383;
+const MAX_SERIAL_NUM: usize = 20;
+
+/// Config struct for `virtio-scsi-pci`.
+/// Contains virtio-scsi controller's attr.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScsiCntlrConfig {
+    pub id: String,
+    pub iothread: Option<String>,
+    /// Number of request queues.
+    pub num_queues: u16,
+}
+
+impl Default for ScsiCntlrConfig {
+    fn default() -> Self {
+        ScsiCntlrConfig {
+            id: "".to_string(),
+            iothread: None,
+            num_queues: 1,
+        }
+    }
+}
+
+impl ConfigCheck for ScsiCntlrConfig {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "virtio-scsi controller id".to_string(),
+                MAX_STRING_LENGTH,
+            )
+            .into());
+        }
+
+        if self.iothread.is_some() && self.iothread.as_ref().unwrap().len() > MAX_STRING_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "iothread name".to_string(),
+                MAX_STRING_LENGTH,
+            )
+            .into());
+        }
+
+        if !(1..=MAX_SCSI_QUEUES).contains(&self.num_queues) {
+            return Err(ErrorKind::IllegalValue(
+                "num-queues of virtio-scsi controller".to_string(),
+                1,
+                true,
+                MAX_SCSI_QUEUES as u64,
+                true,
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_scsi_controller(cntlr_config: &str) -> Result<ScsiCntlrConfig> {
+    let mut cmd_parser = CmdParser::new("virtio-scsi");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("iothread")
+        .push("num-queues");
+
+    cmd_parser.parse(cntlr_config)?;
+
+    pci_args_check(&cmd_parser)?;
+
+    let mut cntlr_cfg = ScsiCntlrConfig::default();
+    if let Some(id) = cmd_parser.get_value::<String>("id")? {
+        cntlr_cfg.id = id;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("id", "virtio-scsi").into());
+    }
+
+    cntlr_cfg.iothread = cmd_parser.get_value::<String>("iothread")?;
+
+    if let Some(num_queues) = cmd_parser.get_value::<u16>("num-queues")? {
+        cntlr_cfg.num_queues = num_queues;
+    }
+
+    cntlr_cfg.check()?;
+    Ok(cntlr_cfg)
+}
+
+/// Type of scsi device.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScsiDevType {
+    /// Direct access block device, `scsi-hd`.
+    Disk,
+    /// CD/DVD device, `scsi-cd`.
+    CdRom,
+}
+
+impl Default for ScsiDevType {
+    fn default() -> Self {
+        ScsiDevType::Disk
+    }
+}
+
+/// Config struct for `scsi-hd` and `scsi-cd`.
+/// Contains scsi device's attr.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScsiDevConfig {
+    pub id: String,
+    pub dev_type: ScsiDevType,
+    /// Id of the virtio-scsi controller to which the device is attached.
+    pub cntlr: String,
+    pub channel: u8,
+    pub target: u8,
+    pub lun: u16,
+    pub path_on_host: String,
+    pub read_only: bool,
+    pub direct: bool,
+    pub serial_num: Option<String>,
+    pub discard: bool,
+    pub aio: AioEngine,
+}
+
+impl Default for ScsiDevConfig {
+    fn default() -> Self {
+        ScsiDevConfig {
+            id: "".to_string(),
+            dev_type: ScsiDevType::Disk,
+            cntlr: "".to_string(),
+            channel: 0,
+            target: 0,
+            lun: 0,
+            path_on_host: "".to_string(),
+            read_only: false,
+            direct: true,
+            serial_num: None,
+            discard: false,
+            aio: AioEngine::Native,
+        }
+    }
+}
+
+impl ConfigCheck for ScsiDevConfig {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "scsi device id".to_string(),
+                MAX_STRING_LENGTH,
+            )
+            .into());
+        }
+
+        if self.path_on_host.len() > MAX_PATH_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "scsi device path".to_string(),
+                MAX_PATH_LENGTH,
+            )
+            .into());
+        }
+
+        if self.serial_num.is_some() && self.serial_num.as_ref().unwrap().len() > MAX_SERIAL_NUM {
+            return Err(ErrorKind::StringLengthTooLong(
+                "scsi serial number".to_string(),
+                MAX_SERIAL_NUM,
+            )
+            .into());
+        }
+
+        if self.channel > MAX_SCSI_CHANNEL {
+            return Err(ErrorKind::IllegalValue(
+                "channel of scsi device".to_string(),
+                0,
+                true,
+                MAX_SCSI_CHANNEL as u64,
+                true,
+            )
+            .into());
+        }
+
+        if self.lun > MAX_SCSI_LUN {
+            return Err(ErrorKind::IllegalValue(
+                "lun of scsi device".to_string(),
+                0,
+                true,
+                MAX_SCSI_LUN as u64,
+                true,
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the id of virtio-scsi controller from the bus name of scsi device, such as `scsi0.0`.
+pub fn get_scsi_cntlr_id(bus: &str) -> Result<String> {
+    match bus.rfind('.') {
+        Some(pos) if pos > 0 && &bus[pos + 1..] == "0" => Ok(bus[..pos].to_string()),
+        _ => Err(ErrorKind::ConvertValueFailed("bus".to_string(), bus.to_string()).into()),
+    }
+}
+
+/// Fill the backend of scsi device with the drive config.
+///
+/// # Arguments
+///
+/// * `dev_cfg` - Config of scsi device.
+/// * `drive` - Config of the drive used by the scsi device.
+pub fn set_scsi_drive(dev_cfg: &mut ScsiDevConfig, drive: &DriveConfig) -> Result<()> {
+    if drive.format != DiskFormat::Raw {
+        bail!("Only raw image is supported for scsi device");
+    }
+    dev_cfg.path_on_host = drive.path_on_host.clone();
+    // Media of CD-ROM is always read-only.
+    dev_cfg.read_only = drive.read_only || dev_cfg.dev_type == ScsiDevType::CdRom;
+    dev_cfg.direct = drive.direct;
+    dev_cfg.discard = drive.discard;
+    dev_cfg.aio = drive.aio;
+    Ok(())
+}
+
+pub fn parse_scsi_device(vm_config: &mut VmConfig, dev_config: &str) -> Result<ScsiDevConfig> {
+    let mut cmd_parser = CmdParser::new("scsi-device");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("channel")
+        .push("scsi-id")
+        .push("lun")
+        .push("drive")
+        .push("serial");
+
+    cmd_parser.parse(dev_config)?;
+
+    let mut dev_cfg = ScsiDevConfig::default();
+    if let Some(dev_type) = cmd_parser.get_value::<String>("")? {
+        dev_cfg.dev_type = match dev_type.as_str() {
+            "scsi-cd" => ScsiDevType::CdRom,
+            _ => ScsiDevType::Disk,
+        };
+    }
+
+    if let Some(id) = cmd_parser.get_value::<String>("id")? {
+        dev_cfg.id = id;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("id", "scsi-device").into());
+    }
+
+    if let Some(bus) = cmd_parser.get_value::<String>("bus")? {
+        dev_cfg.cntlr = get_scsi_cntlr_id(&bus)?;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("bus", "scsi-device").into());
+    }
+
+    dev_cfg.channel = cmd_parser.get_value::<u8>("channel")?.unwrap_or(0);
+    dev_cfg.target = cmd_parser.get_value::<u8>("scsi-id")?.unwrap_or(0);
+    dev_cfg.lun = cmd_parser.get_value::<u16>("lun")?.unwrap_or(0);
+    dev_cfg.serial_num = cmd_parser.get_value::<String>("serial")?;
+    dev_cfg.check()?;
+
+    if let Some(drive) = cmd_parser.get_value::<String>("drive")? {
+        if let Some(drive_arg) = &vm_config.drives.remove(&drive) {
+            set_scsi_drive(&mut dev_cfg, drive_arg)?;
+        } else {
+            bail!("No drive configured matched for scsi device");
+        }
+    } else {
+        return Err(ErrorKind::FieldIsMissing("drive", "scsi-device").into());
+    }
+
+    Ok(dev_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scsi_controller_cmdline_parser() {
+        let cntlr_cfg =
+            parse_scsi_controller("virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3.0x0,num-queues=4")
+                .unwrap();
+        assert_eq!(cntlr_cfg.id, "scsi0");
+        assert_eq!(cntlr_cfg.num_queues, 4);
+        assert!(cntlr_cfg.iothread.is_none());
+
+        let cntlr_cfg =
+            parse_scsi_controller("virtio-scsi-pci,id=scsi1,bus=pcie.0,addr=0x4,iothread=io1")
+                .unwrap();
+        assert_eq!(cntlr_cfg.num_queues, 1);
+        assert_eq!(cntlr_cfg.iothread, Some("io1".to_string()));
+
+        assert!(parse_scsi_controller("virtio-scsi-pci,bus=pcie.0,addr=0x3").is_err());
+        assert!(parse_scsi_controller(
+            "virtio-scsi-pci,id=scsi0,bus=pcie.0,addr=0x3,num-queues=17"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_scsi_device_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=drive0,file=/path/to/disk,direct=off,discard=unmap")
+            .is_ok());
+        let dev_cfg = parse_scsi_device(
+            &mut vm_config,
+            "scsi-hd,id=disk0,bus=scsi0.0,scsi-id=1,lun=2,drive=drive0,serial=111",
+        )
+        .unwrap();
+        assert_eq!(dev_cfg.id, "disk0");
+        assert_eq!(dev_cfg.dev_type, ScsiDevType::Disk);
+        assert_eq!(dev_cfg.cntlr, "scsi0");
+        assert_eq!(dev_cfg.channel, 0);
+        assert_eq!(dev_cfg.target, 1);
+        assert_eq!(dev_cfg.lun, 2);
+        assert_eq!(dev_cfg.path_on_host, "/path/to/disk");
+        assert!(!dev_cfg.read_only);
+        assert!(dev_cfg.discard);
+        assert_eq!(dev_cfg.serial_num, Some("111".to_string()));
+        // The drive can only be used once.
+        assert!(
+            parse_scsi_device(&mut vm_config, "scsi-hd,id=disk1,bus=scsi0.0,drive=drive0").is_err()
+        );
+
+        // CD-ROM is always read-only.
+        assert!(vm_config
+            .add_drive("id=drive1,file=/path/to/cdrom,direct=off")
+            .is_ok());
+        let dev_cfg =
+            parse_scsi_device(&mut vm_config, "scsi-cd,id=cd0,bus=scsi0.0,drive=drive1").unwrap();
+        assert_eq!(dev_cfg.dev_type, ScsiDevType::CdRom);
+        assert!(dev_cfg.read_only);
+
+        // Invalid bus, channel and lun.
+        assert!(vm_config
+            .add_drive("id=drive2,file=/path/to/disk,direct=off")
+            .is_ok());
+        assert!(
+            parse_scsi_device(&mut vm_config, "scsi-hd,id=disk2,bus=scsi0,drive=drive2").is_err()
+        );
+        assert!(parse_scsi_device(
+            &mut vm_config,
+            "scsi-hd,id=disk2,bus=scsi0.0,channel=1,drive=drive2"
+        )
+        .is_err());
+        assert!(parse_scsi_device(
+            &mut vm_config,
+            "scsi-hd,id=disk2,bus=scsi0.0,lun=16384,drive=drive2"
+        )
+        .is_err());
+        assert!(parse_scsi_device(&mut vm_config, "scsi-hd,id=disk2,bus=scsi0.0").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::errors::{ErrorKind, Result};
+use super::{memory_unit_conversion, pci_args_check, MemZoneConfig, ObjConfig};
+use crate::config::{CmdParser, ConfigCheck, VmConfig, MAX_STRING_LENGTH};
+
+/// Default block size of virtio-mem device, which equals to the THP size of host.
+const DEFAULT_BLOCK_SIZE: u64 = 2 * 1024 * 1024;
+/// Minimum block size of virtio-mem device.
+const MIN_BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// Config structure for virtio-mem.
+#[derive(Debug, Clone, Default)]
+pub struct VirtioMemConfig {
+    pub id: String,
+    /// Memory backend which provides the hotpluggable memory region.
+    pub mem_zone: MemZoneConfig,
+    /// The size of memory the guest is requested to plug.
+    pub requested_size: u64,
+    /// Memory is plugged and unplugged at the granularity of `block_size`.
+    pub block_size: u64,
+    /// Guest numa node which the memory belongs to.
+    pub node: Option<u32>,
+}
+
+impl ConfigCheck for VirtioMemConfig {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(ErrorKind::StringLengthTooLong(
+                "virtio-mem id".to_string(),
+                MAX_STRING_LENGTH,
+            )
+            .into());
+        }
+        if self.block_size < MIN_BLOCK_SIZE || !self.block_size.is_power_of_two() {
+            bail!(
+                "Block size of virtio-mem should be a power of 2 and not less than 0x{:x}",
+                MIN_BLOCK_SIZE
+            );
+        }
+        if let Some(page_size) = self.mem_zone.hugetlb_size {
+            if self.block_size < page_size {
+                bail!(
+                    "Block size of virtio-mem is smaller than the huge page size of {}",
+                    self.mem_zone.id
+                );
+            }
+        }
+        if self.mem_zone.size % self.block_size != 0 {
+            bail!(
+                "Size of {} is not aligned to the block size of virtio-mem",
+                self.mem_zone.id
+            );
+        }
+        if self.requested_size % self.block_size != 0 {
+            bail!("Requested size of virtio-mem is not aligned to the block size");
+        }
+        if self.requested_size > self.mem_zone.size {
+            return Err(ErrorKind::IllegalValue(
+                "Requested size of virtio-mem".to_string(),
+                0,
+                true,
+                self.mem_zone.size,
+                true,
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_virtio_mem(vm_config: &mut VmConfig, mem_config: &str) -> Result<VirtioMemConfig> {
+    let mut cmd_parser = CmdParser::new("virtio-mem");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("memdev")
+        .push("requested-size")
+        .push("block-size")
+        .push("node");
+
+    cmd_parser.parse(mem_config)?;
+    pci_args_check(&cmd_parser)?;
+    let mut config = VirtioMemConfig {
+        block_size: DEFAULT_BLOCK_SIZE,
+        ..Default::default()
+    };
+    if let Some(id) = cmd_parser.get_value::<String>("id")? {
+        config.id = id;
+    } else {
+        return Err(ErrorKind::FieldIsMissing("id", "virtio-mem").into());
+    }
+    if let Some(size) = cmd_parser.get_value::<String>("requested-size")? {
+        config.requested_size = memory_unit_conversion(&size)?;
+    }
+    if let Some(size) = cmd_parser.get_value::<String>("block-size")? {
+        config.block_size = memory_unit_conversion(&size)?;
+    }
+    config.node = cmd_parser.get_value::<u32>("node")?;
+
+    let mem_dev = if let Some(mem_dev) = cmd_parser.get_value::<String>("memdev")? {
+        mem_dev
+    } else {
+        return Err(ErrorKind::FieldIsMissing("memdev", "virtio-mem").into());
+    };
+    if vm_config.machine_config.mem_config.mem_backend.as_ref() == Some(&mem_dev) {
+        bail!("Memory backend {} is already used as guest memory", mem_dev);
+    }
+    match vm_config.object.remove(&mem_dev) {
+        Some(ObjConfig::Zone(zone)) => config.mem_zone = zone,
+        Some(_) => bail!("Object {} is not a memory backend", mem_dev),
+        None => bail!("Memory backend {} for virtio-mem not found", mem_dev),
+    }
+
+    config.check()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::get_pci_bdf;
+
+    use super::*;
+
+    #[test]
+    fn test_virtio_mem_config_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("memory-backend-ram,id=mem1,size=4G")
+            .is_ok());
+        let mem_cfg = "virtio-mem-pci,id=vmem0,memdev=mem1,requested-size=1G,bus=pcie.0,addr=0x5";
+        let config = parse_virtio_mem(&mut vm_config, mem_cfg).unwrap();
+        assert_eq!(config.id, "vmem0");
+        assert_eq!(config.mem_zone.id, "mem1");
+        assert_eq!(config.mem_zone.size, 4 * 1024 * 1024 * 1024);
+        assert_eq!(config.requested_size, 1024 * 1024 * 1024);
+        assert_eq!(config.block_size, DEFAULT_BLOCK_SIZE);
+        assert!(config.node.is_none());
+        // The memory backend is owned by the virtio-mem device.
+        assert!(!vm_config.object.contains_key("mem1"));
+
+        let pci = get_pci_bdf(mem_cfg).unwrap();
+        assert_eq!(pci.bus, "pcie.0".to_string());
+        assert_eq!(pci.addr, (5, 0));
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("memory-backend-memfd,id=mem1,size=4G")
+            .is_ok());
+        let mem_cfg = "virtio-mem-pci,id=vmem0,memdev=mem1,block-size=128M,node=1";
+        let config = parse_virtio_mem(&mut vm_config, mem_cfg).unwrap();
+        assert_eq!(config.requested_size, 0);
+        assert_eq!(config.block_size, 128 * 1024 * 1024);
+        assert_eq!(config.node, Some(1));
+    }
+
+    #[test]
+    fn test_virtio_mem_config_check() {
+        let mem_cfgs = [
+            // Id is missing.
+            "virtio-mem-pci,memdev=mem1",
+            // Memory backend is missing.
+            "virtio-mem-pci,id=vmem0",
+            // Memory backend not found.
+            "virtio-mem-pci,id=vmem0,memdev=mem2",
+            // Block size is not a power of 2.
+            "virtio-mem-pci,id=vmem0,memdev=mem1,block-size=3M",
+            // Block size is larger than the region.
+            "virtio-mem-pci,id=vmem0,memdev=mem1,block-size=8G",
+            // Requested size is not aligned to block size.
+            "virtio-mem-pci,id=vmem0,memdev=mem1,requested-size=1M",
+            // Requested size is larger than the region.
+            "virtio-mem-pci,id=vmem0,memdev=mem1,requested-size=8G",
+        ];
+        for mem_cfg in mem_cfgs.iter() {
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config
+                .add_object("memory-backend-ram,id=mem1,size=4G")
+                .is_ok());
+            assert!(parse_virtio_mem(&mut vm_config, mem_cfg).is_err());
+        }
+
+        // Memory backend of guest memory can't be used by virtio-mem.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("memory-backend-ram,id=mem1,size=4G")
+            .is_ok());
+        vm_config.machine_config.mem_config.mem_backend = Some("mem1".to_string());
+        assert!(parse_virtio_mem(&mut vm_config, "virtio-mem-pci,id=vmem0,memdev=mem1").is_err());
+    }
+}
@@ -14,13 +14,16 @@ extern crate util;
 
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::{process, thread};
 
 use crate::machine::IOTHREADS;
 use crate::qmp::qmp_schema::IothreadInfo;
 
 use super::config::IothreadConfig;
-use util::loop_context::{EventLoopContext, EventLoopManager, EventNotifier};
+use util::loop_context::{
+    EventLoopContext, EventLoopManager, EventLoopStats, EventNotifier, LoopPauser,
+};
 
 /// This struct used to manage all events occur during VM lifetime.
 /// # Notes
@@ -104,6 +107,64 @@ impl EventLoop {
         panic!("Global Event Loop have not been initialized.");
     }
 
+    /// Return the statistics of main loop, named `main`, and all io-thread loops.
+    pub fn loop_stats() -> Vec<(String, Arc<EventLoopStats>)> {
+        unsafe {
+            if let Some(event_loop) = (*std::ptr::addr_of!(GLOBAL_EVENT_LOOP)).as_ref() {
+                let mut stats = vec![("main".to_string(), event_loop.main_loop.stats())];
+                let mut ids: Vec<&String> = event_loop.io_threads.keys().collect();
+                ids.sort();
+                for id in ids {
+                    stats.push((id.clone(), event_loop.io_threads[id].stats()));
+                }
+                return stats;
+            }
+        }
+
+        Vec::new()
+    }
+
+    fn loop_pausers() -> Vec<Arc<LoopPauser>> {
+        unsafe {
+            if let Some(event_loop) = (*std::ptr::addr_of!(GLOBAL_EVENT_LOOP)).as_ref() {
+                let mut pausers = vec![event_loop.main_loop.pauser()];
+                pausers.extend(event_loop.io_threads.values().map(|ctx| ctx.pauser()));
+                return pausers;
+            }
+        }
+
+        Vec::new()
+    }
+
+    /// Park main loop and all io-thread loops between two iterations, so that
+    /// no event handlers run until `resume_loops` is called. It must not be
+    /// called in the loop threads.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The max time to wait for each loop to park.
+    pub fn pause_loops(timeout: Duration) -> util::errors::Result<()> {
+        let pausers = Self::loop_pausers();
+        for pauser in pausers.iter() {
+            pauser.request();
+        }
+        for pauser in pausers.iter() {
+            if !pauser.wait_parked(timeout) {
+                Self::resume_loops();
+                bail!("Timeout to pause event loops");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resume all loops parked by `pause_loops`.
+    pub fn resume_loops() {
+        for pauser in Self::loop_pausers() {
+            pauser.resume();
+        }
+    }
+
     /// Set a `manager` to event loop
     ///
     /// # Arguments
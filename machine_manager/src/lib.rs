@@ -31,6 +31,7 @@ pub mod cmdline;
 pub mod config;
 pub mod event_loop;
 pub mod machine;
+pub mod metrics;
 pub mod qmp;
 pub mod signal_handler;
 pub mod socket;
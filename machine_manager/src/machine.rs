@@ -18,10 +18,13 @@ use std::sync::Mutex;
 use once_cell::sync::Lazy;
 use strum::VariantNames;
 
+use crate::errors::Result;
+use crate::metrics::Metric;
 use crate::qmp::qmp_schema::{
-    BlockDevAddArgument, ChardevInfo, Cmd, CmdLine, DeviceAddArgument, DeviceProps, Events, GicCap,
-    IothreadInfo, KvmInfo, MachineInfo, MigrateCapabilities, NetDevAddArgument, PropList,
-    QmpCommand, QmpEvent, Target, TypeLists,
+    BlockDevAddArgument, BlockDeviceInfo, BlockInfo, BlockIoThrottleArgument, BlockStats,
+    ChardevInfo, Cmd, CmdLine, DataFormat, DeviceAddArgument, DeviceProps, Events, GicCap,
+    IothreadInfo, KvmInfo, MachineInfo, MigrateCapabilities, NetDevAddArgument,
+    NetRateLimitArgument, PropList, QmpCommand, QmpErrorClass, QmpEvent, Target, TypeLists,
 };
 use crate::qmp::{Response, Version};
 
@@ -163,11 +166,20 @@ pub trait DeviceInterface {
     /// Delete a block device.
     fn blockdev_del(&self, node_name: String) -> Response;
 
+    /// Change the I/O throttle limits of a block device.
+    fn block_set_io_throttle(&self, args: Box<BlockIoThrottleArgument>) -> Response;
+
     /// Create a new network device.
     fn netdev_add(&mut self, args: Box<NetDevAddArgument>) -> Response;
 
     fn netdev_del(&mut self, id: String) -> Response;
 
+    /// Change the rate limits of a net device.
+    fn netdev_set_rate_limit(&self, args: Box<NetRateLimitArgument>) -> Response;
+
+    /// Query the packet statistics of net devices, and reset them if `reset` is true.
+    fn query_netdev_stats(&self, id: Option<String>, reset: Option<bool>) -> Response;
+
     /// Receive a file descriptor via SCM rights and assign it a name.
     fn getfd(&self, fd_name: String, if_fd: Option<RawFd>) -> Response;
 
@@ -177,6 +189,15 @@ pub trait DeviceInterface {
     /// Set balloon's size.
     fn balloon(&self, size: u64) -> Response;
 
+    /// Set the link status of a net device.
+    fn set_link(&self, name: String, up: bool) -> Response;
+
+    /// Read at most `size` bytes from a ringbuf-type chardev.
+    fn ringbuf_read(&self, device: String, size: u64, format: Option<DataFormat>) -> Response;
+
+    /// Write data to a ringbuf-type chardev.
+    fn ringbuf_write(&self, device: String, data: String, format: Option<DataFormat>) -> Response;
+
     /// Query the version of StratoVirt.
     fn query_version(&self) -> Response {
         let version = Version::new(1, 0, 5);
@@ -252,7 +273,7 @@ pub trait DeviceInterface {
         let machine_info = MachineInfo {
             hotplug: false,
             name: "q35".to_string(),
-            numa_mem_support: false,
+            numa_mem_support: true,
             cpu_max: 255,
             deprecated: false,
         };
@@ -262,7 +283,7 @@ pub trait DeviceInterface {
         let machine_info = MachineInfo {
             hotplug: false,
             name: "virt".to_string(),
-            numa_mem_support: false,
+            numa_mem_support: true,
             cpu_max: 255,
             deprecated: false,
         };
@@ -283,6 +304,7 @@ pub trait DeviceInterface {
             ("pci-bridge", "base-pci-bridge"),
             ("virtio-blk-pci-transitional", "virtio-blk-pci-base"),
             ("memory-backend-file", "memory-backend"),
+            ("memory-backend-ram", "memory-backend"),
             ("virtio-rng-device", "virtio-device"),
             ("rng-random", "rng-backend"),
             ("vfio-pci", "pci-device"),
@@ -311,6 +333,11 @@ pub trait DeviceInterface {
                 prop_type: "bool".to_string(),
             };
             vec_props.push(prop);
+            let prop = DeviceProps {
+                name: "free-page-reporting".to_string(),
+                prop_type: "bool".to_string(),
+            };
+            vec_props.push(prop);
         }
         Response::create_response(serde_json::to_value(&vec_props).unwrap(), None)
     }
@@ -369,18 +396,26 @@ pub trait DeviceInterface {
         Response::create_response(serde_json::to_value(&vec_cmd).unwrap(), None)
     }
 
+    /// Set the property of an object.
+    fn qom_set(&self, _path: String, property: String, _value: u64) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError(format!("Property {} is not supported", property)),
+            None,
+        )
+    }
+
     fn query_block(&self) -> Response {
-        let vec_cmd: Vec<ChardevInfo> = Vec::new();
+        let vec_cmd: Vec<BlockInfo> = Vec::new();
         Response::create_response(serde_json::to_value(&vec_cmd).unwrap(), None)
     }
 
     fn query_named_block_nodes(&self) -> Response {
-        let vec_cmd: Vec<ChardevInfo> = Vec::new();
+        let vec_cmd: Vec<BlockDeviceInfo> = Vec::new();
         Response::create_response(serde_json::to_value(&vec_cmd).unwrap(), None)
     }
 
     fn query_blockstats(&self) -> Response {
-        let vec_cmd: Vec<ChardevInfo> = Vec::new();
+        let vec_cmd: Vec<BlockStats> = Vec::new();
         Response::create_response(serde_json::to_value(&vec_cmd).unwrap(), None)
     }
 
@@ -416,11 +451,53 @@ pub trait MigrateInterface {
     }
 }
 
+/// Human monitor api
+///
+/// # Notes
+///
+/// Dumps of the VM internals used by the human monitor (HMP), which have
+/// no counterpart in QMP.
+pub trait HumanMonitorInterface {
+    /// Dump the net devices and their backends.
+    fn hmp_info_network(&self) -> String;
+
+    /// Dump the devices on PCI buses.
+    fn hmp_info_pci(&self) -> String;
+
+    /// Dump the region trees of the address spaces.
+    fn hmp_info_mtree(&self) -> String;
+
+    /// Dump the devices of each bus.
+    fn hmp_info_qtree(&self) -> String;
+
+    /// Dump the registers of the vCPU with index `cpu_index`.
+    fn hmp_info_registers(&self, cpu_index: usize) -> Result<String>;
+
+    /// Read `len` bytes of guest memory from `addr`, which is a guest physical
+    /// address if `cpu_index` is None, otherwise a guest virtual address translated
+    /// by the MMU of that vCPU.
+    fn hmp_read_memory(&self, addr: u64, len: u64, cpu_index: Option<usize>) -> Result<Vec<u8>>;
+}
+
+/// Metrics api
+///
+/// # Notes
+///
+/// Metrics of the vCPUs and devices dumped by the metrics exporter, the metrics
+/// of the VMM process are collected by the exporter itself.
+pub trait MetricsInterface {
+    /// Collect the metrics of the machine.
+    fn collect_metrics(&self) -> Vec<Metric>;
+}
+
 /// Machine interface which is exposed to inner hypervisor.
 pub trait MachineInterface: MachineLifecycle + MachineAddressInterface {}
 
 /// Machine interface which is exposed to outer hypervisor.
-pub trait MachineExternalInterface: MachineLifecycle + DeviceInterface + MigrateInterface {}
+pub trait MachineExternalInterface:
+    MachineLifecycle + DeviceInterface + MigrateInterface + HumanMonitorInterface
+{
+}
 
 pub static PTY_PATH: Lazy<Mutex<Vec<PathInfo>>> = Lazy::new(|| Mutex::new(Vec::new()));
 pub static IOTHREADS: Lazy<Mutex<Vec<IothreadInfo>>> = Lazy::new(|| Mutex::new(Vec::new()));
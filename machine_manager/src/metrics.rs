@@ -0,0 +1,493 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Metrics of the VMM, which are dumped periodically as a snapshot in
+//! Prometheus text format or JSON, to a file or the clients of a unix socket.
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+use util::loop_context::{EventNotifier, NotifierCallback, NotifierOperation};
+use util::unix::{host_page_size, limit_permission};
+use vmm_sys_util::epoll::EventSet;
+
+use crate::config::{MetricsConfig, MetricsFormat, MetricsTarget};
+use crate::errors::{Result, ResultExt};
+use crate::event_loop::EventLoop;
+use crate::machine::MetricsInterface;
+use crate::temp_cleaner::TempCleaner;
+
+/// Prefix of the names of all the metrics.
+const METRIC_PREFIX: &str = "stratovirt_";
+
+/// Type of a metric, refer to the Prometheus data model.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MetricType {
+    /// Value only goes up, and is reset on restart.
+    Counter,
+    /// Value may go up and down.
+    Gauge,
+}
+
+impl MetricType {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+        }
+    }
+}
+
+/// A sample of metric, which is distinguished from the other samples of the
+/// same metric by labels.
+#[derive(Debug, Clone)]
+pub struct MetricSample {
+    pub labels: Vec<(String, String)>,
+    pub value: u64,
+}
+
+/// A metric with all its samples.
+#[derive(Debug, Clone)]
+pub struct Metric {
+    /// Name of metric without prefix.
+    pub name: String,
+    pub help: String,
+    pub metric_type: MetricType,
+    pub samples: Vec<MetricSample>,
+}
+
+impl Metric {
+    pub fn counter(name: &str, help: &str) -> Self {
+        Metric::new(name, help, MetricType::Counter)
+    }
+
+    pub fn gauge(name: &str, help: &str) -> Self {
+        Metric::new(name, help, MetricType::Gauge)
+    }
+
+    fn new(name: &str, help: &str, metric_type: MetricType) -> Self {
+        Metric {
+            name: name.to_string(),
+            help: help.to_string(),
+            metric_type,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Add a sample with `labels`, which are pairs of label name and value.
+    pub fn add_sample(&mut self, labels: &[(&str, &str)], value: u64) {
+        self.samples.push(MetricSample {
+            labels: labels
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            value,
+        });
+    }
+}
+
+/// Render metrics in Prometheus text exposition format.
+pub fn render_prometheus(metrics: &[Metric]) -> String {
+    let mut text = String::new();
+    for metric in metrics {
+        let name = format!("{}{}", METRIC_PREFIX, metric.name);
+        text.push_str(&format!("# HELP {} {}\n", name, metric.help));
+        text.push_str(&format!(
+            "# TYPE {} {}\n",
+            name,
+            metric.metric_type.as_str()
+        ));
+        for sample in metric.samples.iter() {
+            text.push_str(&name);
+            if !sample.labels.is_empty() {
+                let labels: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+                    .collect();
+                text.push_str(&format!("{{{}}}", labels.join(",")));
+            }
+            text.push_str(&format!(" {}\n", sample.value));
+        }
+    }
+    text
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Render metrics in JSON, with the time of the snapshot in seconds since epoch.
+pub fn render_json(metrics: &[Metric], timestamp: u64) -> String {
+    let metrics: Vec<Value> = metrics
+        .iter()
+        .map(|metric| {
+            let samples: Vec<Value> = metric
+                .samples
+                .iter()
+                .map(|sample| {
+                    let labels: Map<String, Value> = sample
+                        .labels
+                        .iter()
+                        .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+                        .collect();
+                    json!({ "labels": labels, "value": sample.value })
+                })
+                .collect();
+            json!({
+                "name": format!("{}{}", METRIC_PREFIX, metric.name),
+                "help": metric.help,
+                "type": metric.metric_type.as_str(),
+                "samples": samples,
+            })
+        })
+        .collect();
+    let snapshot = json!({ "timestamp": timestamp, "metrics": metrics });
+    format!("{}\n", snapshot)
+}
+
+/// Metrics of the VMM process, which do not depend on the machine.
+fn process_metrics() -> Vec<Metric> {
+    let mut iterations = Metric::counter(
+        "event_loop_iterations_total",
+        "Number of iterations of the event loop.",
+    );
+    let mut latency = Metric::counter(
+        "event_loop_latency_ns_total",
+        "Total time spent handling events and timers in the event loop, in nanoseconds.",
+    );
+    let mut max_latency = Metric::gauge(
+        "event_loop_max_latency_ns",
+        "Maximum time spent in an iteration of the event loop, in nanoseconds.",
+    );
+    for (name, stats) in EventLoop::loop_stats() {
+        let labels = [("loop", name.as_str())];
+        iterations.add_sample(&labels, stats.iterations());
+        latency.add_sample(&labels, stats.total_latency_ns());
+        max_latency.add_sample(&labels, stats.max_latency_ns());
+    }
+
+    let mut metrics = vec![iterations, latency, max_latency];
+    match read_rss() {
+        Ok(rss) => {
+            let mut rss_metric =
+                Metric::gauge("memory_rss_bytes", "Resident set size of the VMM process.");
+            rss_metric.add_sample(&[], rss);
+            metrics.push(rss_metric);
+        }
+        Err(e) => error!("Failed to read RSS of process: {}", e),
+    }
+    metrics
+}
+
+/// Read the resident set size of the process in bytes.
+fn read_rss() -> Result<u64> {
+    let statm = fs::read_to_string("/proc/self/statm")?;
+    let pages = match statm.split_whitespace().nth(1) {
+        Some(pages) => pages
+            .parse::<u64>()
+            .chain_err(|| format!("Invalid /proc/self/statm: {}", statm))?,
+        None => bail!("Invalid /proc/self/statm: {}", statm),
+    };
+    Ok(pages * host_page_size())
+}
+
+/// The exporter takes a snapshot of metrics periodically in the main loop.
+struct MetricsExporter {
+    machine: Arc<Mutex<dyn MetricsInterface + Send + Sync>>,
+    config: MetricsConfig,
+    /// The latest snapshot, sent to each client of the unix socket.
+    snapshot: String,
+}
+
+impl MetricsExporter {
+    fn update_snapshot(&mut self) {
+        let mut metrics = self.machine.lock().unwrap().collect_metrics();
+        metrics.append(&mut process_metrics());
+        self.snapshot = match self.config.format {
+            MetricsFormat::Prometheus => render_prometheus(&metrics),
+            MetricsFormat::Json => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|t| t.as_secs())
+                    .unwrap_or(0);
+                render_json(&metrics, timestamp)
+            }
+        };
+
+        if let MetricsTarget::File(path) = &self.config.target {
+            if let Err(e) = write_snapshot_file(path, &self.snapshot) {
+                error!("Failed to dump metrics to {}: {}", path, e);
+            }
+        }
+    }
+}
+
+/// Replace the file with the snapshot, so that readers never see a partial one.
+fn write_snapshot_file(path: &str, snapshot: &str) -> Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, snapshot)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn schedule_update(exporter: Arc<Mutex<MetricsExporter>>) {
+    let interval = exporter.lock().unwrap().config.interval;
+    let cloned_exporter = exporter.clone();
+    let update = Box::new(move || {
+        cloned_exporter.lock().unwrap().update_snapshot();
+        schedule_update(cloned_exporter.clone());
+    });
+    if let Some(ctx) = EventLoop::get_ctx(None) {
+        ctx.delay_call(update, interval * 1_000_000_000);
+    }
+}
+
+/// Client of the metrics socket, the snapshot is sent in pieces if the client
+/// does not read it all at once.
+struct MetricsClient {
+    stream: UnixStream,
+    snapshot: String,
+    /// Length of the snapshot which has been sent.
+    offset: usize,
+}
+
+impl MetricsClient {
+    /// Send the rest of the snapshot, returns whether all of it has been sent.
+    fn send(&mut self) -> std::io::Result<bool> {
+        let data = self.snapshot.as_bytes();
+        while self.offset < data.len() {
+            match self.stream.write(&data[self.offset..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(len) => self.offset += len,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Wait for the client to be writable, and send the rest of the snapshot. The
+/// client is closed once the snapshot is sent completely.
+#[allow(clippy::arc_with_non_send_sync)]
+fn client_notifier(client: MetricsClient) -> EventNotifier {
+    let stream_fd = client.stream.as_raw_fd();
+    let client = Mutex::new(client);
+    let handler: Box<NotifierCallback> = Box::new(move |event, fd: RawFd| {
+        let finished = if event & (EventSet::HANG_UP | EventSet::ERROR) != EventSet::empty() {
+            warn!("Client of metrics socket is disconnected before metrics are sent");
+            true
+        } else {
+            client.lock().unwrap().send().unwrap_or_else(|e| {
+                warn!("Failed to send metrics to client: {}", e);
+                true
+            })
+        };
+        if !finished {
+            return None;
+        }
+        Some(vec![EventNotifier::new(
+            NotifierOperation::Delete,
+            fd,
+            None,
+            EventSet::OUT,
+            Vec::new(),
+        )])
+    });
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        stream_fd,
+        None,
+        EventSet::OUT | EventSet::HANG_UP | EventSet::ERROR,
+        vec![Arc::new(Mutex::new(handler))],
+    )
+}
+
+#[allow(clippy::arc_with_non_send_sync)]
+fn listener_notifier(
+    exporter: Arc<Mutex<MetricsExporter>>,
+    listener: UnixListener,
+) -> EventNotifier {
+    let listener_fd = listener.as_raw_fd();
+    let handler: Box<NotifierCallback> = Box::new(move |_, _: RawFd| {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("Failed to accept client of metrics socket: {}", e);
+                return None;
+            }
+        };
+        // A client which does not read must not block the main loop.
+        if let Err(e) = stream.set_nonblocking(true) {
+            error!("Failed to set client of metrics socket nonblocking: {}", e);
+            return None;
+        }
+        let mut client = MetricsClient {
+            stream,
+            snapshot: exporter.lock().unwrap().snapshot.clone(),
+            offset: 0,
+        };
+        match client.send() {
+            Ok(true) => None,
+            Ok(false) => Some(vec![client_notifier(client)]),
+            Err(e) => {
+                warn!("Failed to send metrics to client: {}", e);
+                None
+            }
+        }
+    });
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        listener_fd,
+        None,
+        EventSet::IN,
+        vec![Arc::new(Mutex::new(handler))],
+    )
+}
+
+/// Start to dump the metrics periodically in the main loop, the first snapshot
+/// is taken at once.
+///
+/// # Arguments
+///
+/// * `config` - Where and how often to dump the metrics.
+/// * `machine` - The machine whose metrics are collected.
+pub fn start_metrics_exporter(
+    config: &MetricsConfig,
+    machine: Arc<Mutex<dyn MetricsInterface + Send + Sync>>,
+) -> Result<()> {
+    let listener = match &config.target {
+        MetricsTarget::Unix(path) => {
+            let listener = UnixListener::bind(path)
+                .chain_err(|| format!("Failed to bind metrics socket {}", path))?;
+            TempCleaner::add_path(path.clone());
+            limit_permission(path)
+                .chain_err(|| format!("Failed to limit permission for metrics socket {}", path))?;
+            listener
+                .set_nonblocking(true)
+                .chain_err(|| "Failed to set metrics socket nonblocking")?;
+            Some(listener)
+        }
+        MetricsTarget::File(_) => None,
+    };
+
+    let exporter = Arc::new(Mutex::new(MetricsExporter {
+        machine,
+        config: config.clone(),
+        snapshot: String::new(),
+    }));
+    exporter.lock().unwrap().update_snapshot();
+    if let Some(listener) = listener {
+        EventLoop::update_event(vec![listener_notifier(exporter.clone(), listener)], None)
+            .chain_err(|| "Failed to register metrics socket")?;
+    }
+    schedule_update(exporter);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_metrics() -> Vec<Metric> {
+        let mut exits = Metric::counter("vcpu_exits_total", "Number of vCPU exits.");
+        exits.add_sample(&[("cpu", "0"), ("reason", "mmio_write")], 10);
+        exits.add_sample(&[("cpu", "1"), ("reason", "mmio_write")], 5);
+        let mut rss = Metric::gauge("memory_rss_bytes", "Resident set size.");
+        rss.add_sample(&[], 4096);
+        vec![exits, rss]
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let text = render_prometheus(&test_metrics());
+        assert_eq!(
+            text,
+            "# HELP stratovirt_vcpu_exits_total Number of vCPU exits.\n\
+             # TYPE stratovirt_vcpu_exits_total counter\n\
+             stratovirt_vcpu_exits_total{cpu=\"0\",reason=\"mmio_write\"} 10\n\
+             stratovirt_vcpu_exits_total{cpu=\"1\",reason=\"mmio_write\"} 5\n\
+             # HELP stratovirt_memory_rss_bytes Resident set size.\n\
+             # TYPE stratovirt_memory_rss_bytes gauge\n\
+             stratovirt_memory_rss_bytes 4096\n"
+        );
+
+        let mut metric = Metric::gauge("test", "Test.");
+        metric.add_sample(&[("device", "a\"b\\c\nd")], 1);
+        assert!(render_prometheus(&[metric]).contains("{device=\"a\\\"b\\\\c\\nd\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_json() {
+        let text = render_json(&test_metrics(), 100);
+        let value: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["timestamp"], 100);
+        let metrics = value["metrics"].as_array().unwrap();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0]["name"], "stratovirt_vcpu_exits_total");
+        assert_eq!(metrics[0]["type"], "counter");
+        assert_eq!(metrics[0]["samples"][1]["labels"]["cpu"], "1");
+        assert_eq!(metrics[0]["samples"][1]["value"], 5);
+        assert_eq!(metrics[1]["type"], "gauge");
+        assert!(metrics[1]["samples"][0]["labels"]
+            .as_object()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_read_rss() {
+        assert!(read_rss().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_metrics_client_partial_send() {
+        use std::io::Read;
+
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        // The snapshot is larger than the buffer of socket.
+        let len = 1 << 22;
+        let mut client = MetricsClient {
+            stream,
+            snapshot: "x".repeat(len),
+            offset: 0,
+        };
+        assert!(!client.send().unwrap());
+        assert!(client.offset > 0 && client.offset < len);
+
+        // The rest is sent as the client reads.
+        let mut received = 0;
+        let mut buf = vec![0_u8; 0x10000];
+        loop {
+            let finished = client.send().unwrap();
+            while received < client.offset {
+                received += peer.read(&mut buf).unwrap();
+            }
+            if finished {
+                break;
+            }
+        }
+        assert_eq!(received, len);
+    }
+}
@@ -0,0 +1,409 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Proxy of guest agent commands.
+//!
+//! Guest agent commands received by QMP, such as `guest-ping`, are forwarded
+//! to the guest agent through the virtio-serial port named
+//! `org.qemu.guest_agent.0`. Before each command, a `guest-sync-delimited`
+//! handshake is made to drop the stale data in the channel.
+//!
+//! QMP is served by the main loop, which also serves the devices the guest
+//! agent may depend on, so the proxy never blocks. The response of guest
+//! agent command is sent to QMP client when guest agent replies, or when
+//! the command times out.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::qmp_schema::{self as schema, QmpCommand};
+use super::{QmpChannel, Response};
+use crate::event_loop::EventLoop;
+
+/// Name of the virtio-serial port which guest agent listens on.
+pub const GUEST_AGENT_PORT_NAME: &str = "org.qemu.guest_agent.0";
+/// Delimiter which flushes the parser of guest agent, and leads the response
+/// of `guest-sync-delimited`.
+const SYNC_DELIMITER: u8 = 0xff;
+/// Timeout of sync handshake in nanoseconds.
+const SYNC_TIMEOUT_NS: u64 = 3_000_000_000;
+/// Timeout of guest agent command in nanoseconds.
+const COMMAND_TIMEOUT_NS: u64 = 30_000_000_000;
+/// Maximum length of the buffer for guest agent response.
+const MAX_RESPONSE_LEN: usize = 1024 * 1024;
+
+static GUEST_AGENT: Lazy<Mutex<GuestAgent>> = Lazy::new(|| Mutex::new(GuestAgent::default()));
+
+/// Channel to the guest agent, which is implemented by the guest agent port.
+pub trait GuestAgentTransport: Send {
+    /// Whether the guest agent port is opened by guest.
+    fn is_connected(&self) -> bool;
+
+    /// Send data to guest agent.
+    fn send(&mut self, data: &[u8]);
+}
+
+/// Stage of the guest agent command in flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// Waiting for the response of `guest-sync-delimited` with the id.
+    Sync(u64),
+    /// Waiting for the response of the command.
+    Exec,
+}
+
+/// Guest agent command received by QMP.
+struct Request {
+    /// Name of the command, such as `guest-ping`.
+    command: String,
+    arguments: Value,
+    /// Id of the QMP command.
+    id: Option<String>,
+    /// Whether guest agent replies the command, `guest-shutdown` doesn't reply on success.
+    has_reply: bool,
+}
+
+#[derive(Default)]
+struct GuestAgent {
+    transport: Option<Arc<Mutex<dyn GuestAgentTransport>>>,
+    /// Commands waiting for guest agent, the first one is in flight.
+    requests: VecDeque<Request>,
+    stage: Option<Stage>,
+    /// Data received from guest agent, which is not a complete response yet.
+    recv_buf: Vec<u8>,
+    /// Serial number of the latest message sent to guest agent, it's also the id of
+    /// `guest-sync-delimited`. A timer is expired if the serial number has changed.
+    seq: u64,
+}
+
+impl GuestAgent {
+    fn is_connected(&self) -> bool {
+        match &self.transport {
+            Some(transport) => transport.lock().unwrap().is_connected(),
+            None => false,
+        }
+    }
+
+    fn send(&mut self, msg: &[u8], timeout: u64) {
+        self.seq = self.seq.wrapping_add(1);
+        if let Some(transport) = &self.transport {
+            transport.lock().unwrap().send(msg);
+        }
+
+        let seq = self.seq;
+        if let Some(ctx) = EventLoop::get_ctx(None) {
+            ctx.delay_call(Box::new(move || handle_timeout(seq)), timeout);
+        }
+    }
+
+    /// Start the sync handshake of the first command.
+    fn start_request(&mut self) {
+        if self.requests.is_empty() {
+            return;
+        }
+        if !self.is_connected() {
+            self.finish_request(not_connected_response(self.requests[0].id.clone()));
+            return;
+        }
+
+        self.recv_buf.clear();
+        let sync_id = self.seq.wrapping_add(1);
+        self.stage = Some(Stage::Sync(sync_id));
+        let sync = serde_json::json!({
+            "execute": "guest-sync-delimited",
+            "arguments": { "id": sync_id },
+        });
+        let mut msg = vec![SYNC_DELIMITER];
+        msg.extend_from_slice(sync.to_string().as_bytes());
+        msg.push(b'\n');
+        self.send(&msg, SYNC_TIMEOUT_NS);
+    }
+
+    /// Send the first command after the sync handshake is done.
+    fn exec_request(&mut self) {
+        let request = &self.requests[0];
+        let mut cmd = serde_json::json!({ "execute": request.command });
+        if let Some(args) = request.arguments.as_object() {
+            if !args.is_empty() {
+                cmd["arguments"] = request.arguments.clone();
+            }
+        }
+        let mut msg = cmd.to_string().into_bytes();
+        msg.push(b'\n');
+        let has_reply = request.has_reply;
+
+        self.stage = Some(Stage::Exec);
+        self.send(&msg, COMMAND_TIMEOUT_NS);
+        if !has_reply {
+            let mut response = Response::create_empty_response();
+            response.change_id(self.requests[0].id.clone());
+            self.finish_request(response);
+        }
+    }
+
+    /// Send the response of the first command to QMP client, and start the next one.
+    fn finish_request(&mut self, response: Response) {
+        self.requests.pop_front();
+        self.stage = None;
+        self.recv_buf.clear();
+        QmpChannel::send_response(&response);
+        self.start_request();
+    }
+
+    fn receive(&mut self, data: &[u8]) {
+        let stage = if let Some(stage) = self.stage {
+            stage
+        } else {
+            debug!("Drop data from guest agent as no command is in flight");
+            return;
+        };
+        self.recv_buf.extend_from_slice(data);
+
+        if let Stage::Sync(sync_id) = stage {
+            // Data before the delimiter belongs to the stale commands.
+            if let Some(pos) = self.recv_buf.iter().rposition(|b| *b == SYNC_DELIMITER) {
+                self.recv_buf.drain(..pos);
+            } else {
+                self.recv_buf.clear();
+                return;
+            }
+            let end = match self.recv_buf.iter().position(|b| *b == b'\n') {
+                Some(end) => end,
+                None => return,
+            };
+            let line: Vec<u8> = self.recv_buf.drain(..=end).collect();
+            match serde_json::from_slice::<Value>(&line[1..]) {
+                Ok(resp) if resp.get("return") == Some(&Value::from(sync_id)) => {
+                    self.exec_request();
+                }
+                Ok(resp) => warn!("Unexpected sync response from guest agent: {}", resp),
+                Err(e) => warn!("Invalid sync response from guest agent: {}", e),
+            }
+            return;
+        }
+
+        while let Some(resp) = self.next_response() {
+            let id = self.requests[0].id.clone();
+            if let Some(ret) = resp.get("return") {
+                self.finish_request(Response::create_response(ret.clone(), id));
+                return;
+            }
+            if let Some(err) = resp.get("error") {
+                let desc = err
+                    .get("desc")
+                    .and_then(|desc| desc.as_str())
+                    .unwrap_or("Unknown error of guest agent")
+                    .to_string();
+                self.finish_request(Response::create_error_response(
+                    schema::QmpErrorClass::GenericError(desc),
+                    id,
+                ));
+                return;
+            }
+            warn!("Unexpected response from guest agent: {}", resp);
+        }
+
+        if self.recv_buf.len() > MAX_RESPONSE_LEN {
+            let id = self.requests[0].id.clone();
+            self.finish_request(Response::create_error_response(
+                schema::QmpErrorClass::GenericError(
+                    "Response of guest agent is too long".to_string(),
+                ),
+                id,
+            ));
+        }
+    }
+
+    /// Take the next complete line from the received data, and parse it as json.
+    fn next_response(&mut self) -> Option<Value> {
+        loop {
+            let pos = self.recv_buf.iter().position(|b| *b == b'\n')?;
+            let line: Vec<u8> = self.recv_buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Value>(line) {
+                Ok(resp) => return Some(resp),
+                Err(e) => warn!("Invalid response from guest agent: {}", e),
+            }
+        }
+    }
+}
+
+fn not_connected_response(id: Option<String>) -> Response {
+    Response::create_error_response(
+        schema::QmpErrorClass::GenericError("Guest agent is not connected".to_string()),
+        id,
+    )
+}
+
+fn handle_timeout(seq: u64) {
+    let mut agent = GUEST_AGENT.lock().unwrap();
+    if agent.seq != seq || agent.stage.is_none() {
+        return;
+    }
+    let request = &agent.requests[0];
+    let desc = format!("Guest agent command {} timed out", request.command);
+    let id = request.id.clone();
+    agent.finish_request(Response::create_error_response(
+        schema::QmpErrorClass::GenericError(desc),
+        id,
+    ));
+}
+
+/// Bind the guest agent port, which is called when the port is activated.
+pub fn bind_transport(transport: Arc<Mutex<dyn GuestAgentTransport>>) {
+    GUEST_AGENT.lock().unwrap().transport = Some(transport);
+}
+
+/// Unbind the guest agent port, all the commands waiting for guest agent fail.
+pub fn unbind_transport() {
+    let mut agent = GUEST_AGENT.lock().unwrap();
+    agent.transport = None;
+    agent.stage = None;
+    agent.recv_buf.clear();
+    while let Some(request) = agent.requests.pop_front() {
+        QmpChannel::send_response(&not_connected_response(request.id));
+    }
+}
+
+/// Handle the data sent by guest agent.
+pub fn receive(data: &[u8]) {
+    GUEST_AGENT.lock().unwrap().receive(data);
+}
+
+fn to_value<T: Serialize>(arguments: &T) -> Value {
+    serde_json::to_value(arguments).unwrap_or(Value::Null)
+}
+
+/// Get the name, arguments and id of guest agent command, return `None` if
+/// it's not a guest agent command.
+pub fn parse_command(qmp_command: &QmpCommand) -> Option<(&'static str, Value, Option<String>)> {
+    let (name, arguments, id) = match qmp_command {
+        QmpCommand::guest_ping { arguments, id } => ("guest-ping", to_value(arguments), id),
+        QmpCommand::guest_fsfreeze_freeze { arguments, id } => {
+            ("guest-fsfreeze-freeze", to_value(arguments), id)
+        }
+        QmpCommand::guest_fsfreeze_thaw { arguments, id } => {
+            ("guest-fsfreeze-thaw", to_value(arguments), id)
+        }
+        QmpCommand::guest_shutdown { arguments, id } => ("guest-shutdown", to_value(arguments), id),
+        QmpCommand::guest_exec { arguments, id } => ("guest-exec", to_value(arguments), id),
+        QmpCommand::guest_exec_status { arguments, id } => {
+            ("guest-exec-status", to_value(arguments), id)
+        }
+        QmpCommand::guest_network_get_interfaces { arguments, id } => {
+            ("guest-network-get-interfaces", to_value(arguments), id)
+        }
+        _ => return None,
+    };
+    Some((name, arguments, id.clone()))
+}
+
+/// Forward the command to guest agent.
+///
+/// # Arguments
+///
+/// * `command` - Name of the guest agent command.
+/// * `arguments` - Arguments of the command.
+/// * `id` - Id of the QMP command.
+///
+/// # Notes
+///
+/// Return the response immediately if the command can't be forwarded, otherwise
+/// return `None`, and the response is sent to QMP client later.
+pub fn execute(command: &str, arguments: Value, id: Option<String>) -> Option<Response> {
+    let mut agent = GUEST_AGENT.lock().unwrap();
+    if !agent.is_connected() {
+        return Some(not_connected_response(id));
+    }
+
+    agent.requests.push_back(Request {
+        command: command.to_string(),
+        arguments,
+        id,
+        has_reply: command != "guest-shutdown",
+    });
+    if agent.requests.len() == 1 {
+        agent.start_request();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestTransport {
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl GuestAgentTransport for TestTransport {
+        fn is_connected(&self) -> bool {
+            true
+        }
+
+        fn send(&mut self, data: &[u8]) {
+            self.sent.push(data.to_vec());
+        }
+    }
+
+    #[test]
+    fn test_guest_agent_proxy() {
+        EventLoop::object_init(&None).unwrap();
+        QmpChannel::object_init();
+
+        assert!(execute("guest-ping", Value::Null, None).is_some());
+
+        let transport = Arc::new(Mutex::new(TestTransport::default()));
+        bind_transport(transport.clone());
+        assert!(execute("guest-ping", serde_json::json!({}), Some("1".to_string())).is_none());
+        assert!(execute("guest-fsfreeze-freeze", serde_json::json!({}), None).is_none());
+        assert_eq!(GUEST_AGENT.lock().unwrap().requests.len(), 2);
+
+        // Sync handshake is made before the command.
+        let sync = transport.lock().unwrap().sent[0].clone();
+        assert_eq!(sync[0], SYNC_DELIMITER);
+        let sync: Value = serde_json::from_slice(&sync[1..]).unwrap();
+        assert_eq!(sync["execute"], "guest-sync-delimited");
+        let sync_id = sync["arguments"]["id"].as_u64().unwrap();
+
+        // Stale data and mismatched sync response are dropped.
+        receive(b"{\"return\": {}}\n");
+        receive(&[SYNC_DELIMITER]);
+        receive(format!("{{\"return\": {}}}\n", sync_id + 10).as_bytes());
+        assert_eq!(transport.lock().unwrap().sent.len(), 1);
+        receive(&[SYNC_DELIMITER]);
+        receive(format!("{{\"return\": {}}}\n", sync_id).as_bytes());
+        let cmd = transport.lock().unwrap().sent[1].clone();
+        assert_eq!(cmd, b"{\"execute\":\"guest-ping\"}\n".to_vec());
+
+        // The next command is started after the response is received.
+        receive(b"{\"return\": {}}");
+        assert_eq!(GUEST_AGENT.lock().unwrap().requests.len(), 2);
+        receive(b"\n");
+        assert_eq!(GUEST_AGENT.lock().unwrap().requests.len(), 1);
+        assert_eq!(transport.lock().unwrap().sent.len(), 3);
+
+        // Commands fail if guest agent is disconnected.
+        unbind_transport();
+        assert!(GUEST_AGENT.lock().unwrap().requests.is_empty());
+        assert!(execute("guest-ping", Value::Null, None).is_some());
+    }
+}
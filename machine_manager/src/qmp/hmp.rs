@@ -0,0 +1,467 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Human monitor (HMP).
+//!
+//! A line based text monitor on top of the machine external apis. It is
+//! reachable by QMP command `human-monitor-command`, or by a monitor created
+//! with `-mon mode=readline`. Queries which exist in QMP are formatted from
+//! their QMP response, others are served by `HumanMonitorInterface`.
+
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+
+use super::qmp_schema as schema;
+use super::Response;
+use crate::errors::{Result, ResultExt};
+use crate::machine::MachineExternalInterface;
+use crate::socket::SocketHandler;
+
+/// Prompt of the readline monitor.
+pub const HMP_PROMPT: &str = "(stratovirt) ";
+/// Banner sent when a client connects to the readline monitor.
+pub const HMP_BANNER: &str = "StratoVirt monitor - type 'help' for more information";
+/// Number of bytes dumped in each line by `x` and `xp`.
+const BYTES_PER_LINE: u64 = 16;
+/// Maximum number of bytes dumped by one `x` or `xp` command.
+const MAX_DUMP_SIZE: u64 = 64 * 1024;
+
+const HMP_HELP: &str = "\
+cont -- resume emulation
+cpu index -- set the default CPU
+help|? -- show the help
+info status -- show the VM status
+info version -- show the version of StratoVirt
+info cpus -- show infos for each CPU
+info registers -- show the cpu registers
+info block -- show the block devices
+info network -- show the network state
+info chardev -- show the character devices
+info pci -- show PCI info
+info mtree -- show memory tree
+info qtree -- show device tree
+stop -- stop emulation
+x /fmt addr -- virtual memory dump starting at 'addr'
+xp /fmt addr -- physical memory dump starting at 'addr'
+  fmt is /count format size: format is 'x'(hex), 'd'(signed decimal),
+  'u'(unsigned decimal), 'o'(octal) or 'c'(char), size is 'b'(8 bits),
+  'h'(16 bits), 'w'(32 bits) or 'g'(64 bits)
+";
+
+/// Output format of memory dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DumpFormat {
+    Hex,
+    Signed,
+    Unsigned,
+    Octal,
+    Char,
+}
+
+/// The `/fmt` argument of `x` and `xp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DumpSpec {
+    /// Number of units to dump.
+    count: u64,
+    format: DumpFormat,
+    /// Size of each unit in bytes.
+    unit: u64,
+}
+
+impl Default for DumpSpec {
+    fn default() -> Self {
+        DumpSpec {
+            count: 1,
+            format: DumpFormat::Hex,
+            unit: 4,
+        }
+    }
+}
+
+/// Execute a command line of human monitor, and return the output with
+/// "\r\n" line endings.
+///
+/// # Arguments
+///
+/// * `controller` - The machine which executes the command.
+/// * `command_line` - The command line to execute.
+/// * `cpu_index` - The current vCPU of the monitor, which is changed by `cpu`.
+pub fn handle_hmp_command(
+    controller: &Arc<Mutex<dyn MachineExternalInterface>>,
+    command_line: &str,
+    cpu_index: &mut usize,
+) -> String {
+    let output = match execute(controller, command_line, cpu_index) {
+        Ok(output) => output,
+        Err(e) => {
+            let mut msg = format!("Error: {}\n", e);
+            for cause in e.iter().skip(1) {
+                msg.push_str(&format!("Caused by: {}\n", cause));
+            }
+            msg
+        }
+    };
+    output.replace('\n', "\r\n")
+}
+
+/// Serve the input of readline monitor, each line received is executed as a
+/// command, then the output and prompt are sent back.
+///
+/// # Arguments
+///
+/// * `stream_fd` - The input stream file description.
+/// * `controller` - The machine which executes the commands.
+/// * `cpu_index` - The current vCPU of the monitor session.
+pub fn handle_hmp(
+    stream_fd: RawFd,
+    controller: &Arc<Mutex<dyn MachineExternalInterface>>,
+    cpu_index: &mut usize,
+) -> Result<()> {
+    let mut hmp_service = SocketHandler::new(stream_fd);
+    let mut output = String::new();
+    for line in hmp_service.decode_text()? {
+        info!("HMP: <-- {:?}", line);
+        output.push_str(&handle_hmp_command(controller, &line, cpu_index));
+    }
+    output.push_str(HMP_PROMPT);
+    hmp_service
+        .send_text(&output)
+        .chain_err(|| "Failed to send message to human monitor client.")?;
+    Ok(())
+}
+
+/// QMP command `human-monitor-command`.
+pub(crate) fn human_monitor_command(
+    controller: &Arc<Mutex<dyn MachineExternalInterface>>,
+    arguments: schema::human_monitor_command,
+) -> Response {
+    let mut cpu_index = arguments.cpu_index.unwrap_or(0) as usize;
+    let output = handle_hmp_command(controller, &arguments.command_line, &mut cpu_index);
+    Response::create_response(Value::String(output), None)
+}
+
+fn execute(
+    controller: &Arc<Mutex<dyn MachineExternalInterface>>,
+    command_line: &str,
+    cpu_index: &mut usize,
+) -> Result<String> {
+    let mut args = command_line.split_whitespace();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(String::new()),
+    };
+    let args: Vec<&str> = args.collect();
+
+    match command {
+        "help" | "?" => Ok(HMP_HELP.to_string()),
+        "info" => match args.first() {
+            Some(item) => info(controller, item, *cpu_index),
+            None => bail!("'info' requires an item, see 'help'"),
+        },
+        "cpu" => {
+            let index = match args.first() {
+                Some(index) => parse_number(index)? as usize,
+                None => bail!("'cpu' requires the index of CPU"),
+            };
+            let cpus = response_value(controller.lock().unwrap().query_cpus())?;
+            let exists = cpus
+                .as_array()
+                .into_iter()
+                .flatten()
+                .any(|cpu| cpu["CPU"].as_u64() == Some(index as u64));
+            if !exists {
+                bail!("invalid CPU index {}", index);
+            }
+            *cpu_index = index;
+            Ok(String::new())
+        }
+        "stop" => {
+            if !controller.lock().unwrap().pause() {
+                bail!("Failed to stop the VM");
+            }
+            Ok(String::new())
+        }
+        "cont" | "c" => {
+            if !controller.lock().unwrap().resume() {
+                bail!("Failed to resume the VM");
+            }
+            Ok(String::new())
+        }
+        "x" | "xp" => {
+            let (spec, addr) = parse_dump_args(&args)?;
+            let len = match spec.count.checked_mul(spec.unit) {
+                Some(len) if len <= MAX_DUMP_SIZE => len,
+                _ => bail!("Dump size is limited to {} bytes", MAX_DUMP_SIZE),
+            };
+            let cpu = if command == "x" {
+                Some(*cpu_index)
+            } else {
+                None
+            };
+            let data = controller.lock().unwrap().hmp_read_memory(addr, len, cpu)?;
+            Ok(format_memory(addr, &data, &spec))
+        }
+        _ => bail!("unknown command: '{}'", command),
+    }
+}
+
+fn info(
+    controller: &Arc<Mutex<dyn MachineExternalInterface>>,
+    item: &str,
+    cpu_index: usize,
+) -> Result<String> {
+    let locked_controller = controller.lock().unwrap();
+    match item {
+        "status" => {
+            let status = response_value(locked_controller.query_status())?;
+            Ok(format!(
+                "VM status: {}\n",
+                status["status"].as_str().unwrap_or("unknown")
+            ))
+        }
+        "version" => {
+            let version = response_value(locked_controller.query_version())?;
+            let number = &version["qemu"];
+            Ok(format!(
+                "{}.{}.{}\n",
+                number["major"], number["minor"], number["micro"]
+            ))
+        }
+        "cpus" => {
+            let cpus = response_value(locked_controller.query_cpus())?;
+            let mut output = String::new();
+            for cpu in cpus.as_array().into_iter().flatten() {
+                let index = cpu["CPU"].as_u64().unwrap_or(0);
+                let current = if index == cpu_index as u64 { '*' } else { ' ' };
+                output.push_str(&format!(
+                    "{} CPU #{}: thread_id={}\n",
+                    current, index, cpu["thread_id"]
+                ));
+            }
+            Ok(output)
+        }
+        "block" => {
+            let blocks = response_value(locked_controller.query_block())?;
+            let mut output = String::new();
+            for block in blocks.as_array().into_iter().flatten() {
+                let device = block["device"].as_str().unwrap_or("");
+                match block.get("inserted") {
+                    Some(inserted) => output.push_str(&format!(
+                        "{}: {} ({}{})\n",
+                        device,
+                        inserted["file"].as_str().unwrap_or(""),
+                        inserted["drv"].as_str().unwrap_or("raw"),
+                        if inserted["ro"].as_bool().unwrap_or(false) {
+                            ", read-only"
+                        } else {
+                            ""
+                        }
+                    )),
+                    None => output.push_str(&format!("{}: [not inserted]\n", device)),
+                }
+            }
+            Ok(output)
+        }
+        "chardev" => {
+            let chardevs = response_value(locked_controller.query_chardev())?;
+            let mut output = String::new();
+            for chardev in chardevs.as_array().into_iter().flatten() {
+                output.push_str(&format!(
+                    "{}: filename={}\n",
+                    chardev["label"].as_str().unwrap_or(""),
+                    chardev["filename"].as_str().unwrap_or("")
+                ));
+            }
+            Ok(output)
+        }
+        "network" => Ok(locked_controller.hmp_info_network()),
+        "pci" => Ok(locked_controller.hmp_info_pci()),
+        "mtree" => Ok(locked_controller.hmp_info_mtree()),
+        "qtree" => Ok(locked_controller.hmp_info_qtree()),
+        "registers" => locked_controller.hmp_info_registers(cpu_index),
+        _ => bail!("unknown info item: '{}'", item),
+    }
+}
+
+/// Get the `return` value of QMP response, or the error description.
+fn response_value(response: Response) -> Result<Value> {
+    if let Some(error) = response.error {
+        bail!("{}", error.desc);
+    }
+    Ok(response.return_.unwrap_or(Value::Null))
+}
+
+/// Parse a decimal, or hexadecimal number with prefix "0x".
+fn parse_number(arg: &str) -> Result<u64> {
+    let number = if let Some(hex) = arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else {
+        arg.parse::<u64>()
+    };
+    match number {
+        Ok(number) => Ok(number),
+        Err(_) => bail!("invalid number '{}'", arg),
+    }
+}
+
+/// Parse the arguments of `x` and `xp`: `[/fmt] addr`.
+fn parse_dump_args(args: &[&str]) -> Result<(DumpSpec, u64)> {
+    let (spec, addr) = match args {
+        [addr] if !addr.starts_with('/') => (DumpSpec::default(), addr),
+        [fmt, addr] if fmt.starts_with('/') => (parse_dump_spec(&fmt[1..])?, addr),
+        _ => bail!("usage: x|xp /fmt addr"),
+    };
+    Ok((spec, parse_number(addr)?))
+}
+
+/// Parse `/fmt`, the count, format and unit size are all optional.
+fn parse_dump_spec(fmt: &str) -> Result<DumpSpec> {
+    let mut spec = DumpSpec::default();
+    let digits = fmt.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 {
+        spec.count = parse_number(&fmt[..digits])?;
+    }
+    let mut unit_set = false;
+    for c in fmt[digits..].chars() {
+        match c {
+            'x' => spec.format = DumpFormat::Hex,
+            'd' => spec.format = DumpFormat::Signed,
+            'u' => spec.format = DumpFormat::Unsigned,
+            'o' => spec.format = DumpFormat::Octal,
+            'c' => spec.format = DumpFormat::Char,
+            'b' => spec.unit = 1,
+            'h' => spec.unit = 2,
+            'w' => spec.unit = 4,
+            'g' => spec.unit = 8,
+            'i' => bail!("format 'i' is not supported"),
+            _ => bail!("invalid format '{}'", c),
+        }
+        unit_set |= "bhwg".contains(c);
+    }
+    if spec.format == DumpFormat::Char && !unit_set {
+        spec.unit = 1;
+    }
+    if spec.count == 0 {
+        bail!("count of units should be greater than 0");
+    }
+    Ok(spec)
+}
+
+/// Format the memory dump in lines of `BYTES_PER_LINE` bytes, each line
+/// starts with the address of the first unit.
+fn format_memory(addr: u64, data: &[u8], spec: &DumpSpec) -> String {
+    let mut output = String::new();
+    for (i, chunk) in data.chunks(BYTES_PER_LINE as usize).enumerate() {
+        output.push_str(&format!("{:016x}:", addr + i as u64 * BYTES_PER_LINE));
+        for unit in chunk.chunks(spec.unit as usize) {
+            let mut bytes = [0_u8; 8];
+            bytes[..unit.len()].copy_from_slice(unit);
+            let value = u64::from_le_bytes(bytes);
+            let bits = spec.unit * 8;
+            let item = match spec.format {
+                DumpFormat::Hex => format!("0x{:0width$x}", value, width = unit.len() * 2),
+                DumpFormat::Unsigned => format!("{}", value),
+                DumpFormat::Octal => format!("0{:o}", value),
+                DumpFormat::Signed => {
+                    let shift = 64 - bits;
+                    format!("{}", ((value << shift) as i64) >> shift)
+                }
+                DumpFormat::Char => unit
+                    .iter()
+                    .map(|b| {
+                        if b.is_ascii_graphic() || *b == b' ' {
+                            format!("'{}'", *b as char)
+                        } else {
+                            format!("'\\x{:02x}'", b)
+                        }
+                    })
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            };
+            output.push(' ');
+            output.push_str(&item);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dump_args() {
+        let (spec, addr) = parse_dump_args(&["0x1000"]).unwrap();
+        assert_eq!(spec, DumpSpec::default());
+        assert_eq!(addr, 0x1000);
+
+        let (spec, addr) = parse_dump_args(&["/8xg", "4096"]).unwrap();
+        assert_eq!(spec.count, 8);
+        assert_eq!(spec.format, DumpFormat::Hex);
+        assert_eq!(spec.unit, 8);
+        assert_eq!(addr, 4096);
+
+        let (spec, _) = parse_dump_args(&["/16c", "0"]).unwrap();
+        assert_eq!(spec.format, DumpFormat::Char);
+        assert_eq!(spec.unit, 1);
+
+        let (spec, _) = parse_dump_args(&["/du", "0"]).unwrap();
+        assert_eq!(spec.count, 1);
+        assert_eq!(spec.format, DumpFormat::Unsigned);
+        assert_eq!(spec.unit, 4);
+
+        assert!(parse_dump_args(&[]).is_err());
+        assert!(parse_dump_args(&["/4i", "0"]).is_err());
+        assert!(parse_dump_args(&["/4z", "0"]).is_err());
+        assert!(parse_dump_args(&["/0x", "0"]).is_err());
+        assert!(parse_dump_args(&["/4x", "0xzz"]).is_err());
+        assert!(parse_dump_args(&["/4x"]).is_err());
+    }
+
+    #[test]
+    fn test_format_memory() {
+        let data: Vec<u8> = (0..20).collect();
+        let spec = DumpSpec {
+            count: 5,
+            format: DumpFormat::Hex,
+            unit: 4,
+        };
+        assert_eq!(
+            format_memory(0x1000, &data, &spec),
+            "0000000000001000: 0x03020100 0x07060504 0x0b0a0908 0x0f0e0d0c\n\
+             0000000000001010: 0x13121110\n"
+        );
+
+        let spec = DumpSpec {
+            count: 2,
+            format: DumpFormat::Signed,
+            unit: 2,
+        };
+        assert_eq!(
+            format_memory(0, &[0xff, 0xff, 0x10, 0x00], &spec),
+            "0000000000000000: -1 16\n"
+        );
+
+        let spec = DumpSpec {
+            count: 3,
+            format: DumpFormat::Char,
+            unit: 1,
+        };
+        assert_eq!(
+            format_memory(0, b"a \n", &spec),
+            "0000000000000000: 'a' ' ' '\\x0a'\n"
+        );
+    }
+}
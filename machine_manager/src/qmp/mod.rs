@@ -28,6 +28,8 @@
 extern crate serde;
 extern crate serde_json;
 
+pub mod guest_agent;
+pub mod hmp;
 #[allow(non_upper_case_globals)]
 #[allow(non_camel_case_types)]
 #[allow(non_snake_case)]
@@ -381,8 +383,11 @@ pub fn handle_qmp(
             info!("QMP: <-- {:?}", buffer);
             let qmp_command: schema::QmpCommand = buffer.unwrap();
             let (return_msg, shutdown_flag) = qmp_command_exec(qmp_command, controller, if_fd);
-            info!("QMP: --> {:?}", return_msg);
-            qmp_service.send_str(&return_msg)?;
+            // The response of guest agent command is sent when guest agent replies.
+            if let Some(return_msg) = return_msg {
+                info!("QMP: --> {:?}", return_msg);
+                qmp_service.send_str(&return_msg)?;
+            }
 
             // handle shutdown command
             if shutdown_flag {
@@ -412,14 +417,22 @@ pub fn handle_qmp(
 
 /// Create a match , where `qmp_command` and its arguments matching by handle
 /// function, and exec this qmp command.
+/// The response is `None` if the command is forwarded to guest agent.
 fn qmp_command_exec(
     qmp_command: QmpCommand,
     controller: &Arc<Mutex<dyn MachineExternalInterface>>,
     if_fd: Option<RawFd>,
-) -> (String, bool) {
+) -> (Option<String>, bool) {
     let mut qmp_response = Response::create_empty_response();
     let mut shutdown_flag = false;
 
+    // Forward the guest agent command, which is replied asynchronously.
+    if let Some((command, arguments, id)) = guest_agent::parse_command(&qmp_command) {
+        let return_msg = guest_agent::execute(command, arguments, id)
+            .map(|resp| serde_json::to_string(&resp).unwrap() + "\r");
+        return (return_msg, false);
+    }
+
     // Use macro create match to cover most Qmp command
     let mut id = create_command_matches!(
         qmp_command.clone(); controller.lock().unwrap(); qmp_response;
@@ -456,10 +469,17 @@ fn qmp_command_exec(
         (blockdev_del, blockdev_del, node_name),
         (netdev_del, netdev_del, id),
         (balloon, balloon, value),
+        (set_link, set_link, name, up),
+        (query_netdev_stats, query_netdev_stats, id, reset),
+        (qom_set, qom_set, path, property, value),
+        (ringbuf_read, ringbuf_read, device, size, format),
+        (ringbuf_write, ringbuf_write, device, data, format),
         (migrate, migrate, uri);
         (device_add, device_add),
         (blockdev_add, blockdev_add),
-        (netdev_add, netdev_add)
+        (netdev_add, netdev_add),
+        (block_set_io_throttle, block_set_io_throttle),
+        (netdev_set_rate_limit, netdev_set_rate_limit)
     );
 
     // Handle the Qmp command which macro can't cover
@@ -474,6 +494,10 @@ fn qmp_command_exec(
                 qmp_response = controller.lock().unwrap().getfd(arguments.fd_name, if_fd);
                 id
             }
+            QmpCommand::human_monitor_command { arguments, id } => {
+                qmp_response = hmp::human_monitor_command(controller, arguments);
+                id
+            }
             _ => None,
         }
     }
@@ -481,7 +505,7 @@ fn qmp_command_exec(
     // Change response id with input qmp message
     qmp_response.change_id(id);
     (
-        serde_json::to_string(&qmp_response).unwrap() + "\r",
+        Some(serde_json::to_string(&qmp_response).unwrap() + "\r"),
         shutdown_flag,
     )
 }
@@ -567,6 +591,25 @@ impl QmpChannel {
         }
     }
 
+    /// Send a `Response` to client, which is used for the command replied asynchronously.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The `Response` sent to client.
+    #[allow(clippy::unused_io_amount)]
+    pub fn send_response(response: &Response) {
+        if Self::is_connected() {
+            let resp_str = serde_json::to_string(response).unwrap();
+            let mut writer_unlocked = Self::inner().event_writer.write().unwrap();
+            let writer = writer_unlocked.as_mut().unwrap();
+            writer.flush().unwrap();
+            writer.write(resp_str.as_bytes()).unwrap();
+            writer.write(b"\r").unwrap();
+            writer.write(b"\n").unwrap();
+            info!("QMP: --> {:?}", resp_str);
+        }
+    }
+
     fn inner() -> &'static std::sync::Arc<QmpChannel> {
         unsafe {
             match &QMP_CHANNEL {
@@ -159,6 +159,12 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "set_link")]
+    set_link {
+        arguments: set_link,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "migrate")]
     migrate {
         arguments: migrate,
@@ -303,6 +309,54 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "qom-set")]
+    #[strum(serialize = "qom-set")]
+    qom_set {
+        arguments: qom_set,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "ringbuf-read")]
+    #[strum(serialize = "ringbuf-read")]
+    ringbuf_read {
+        arguments: ringbuf_read,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "ringbuf-write")]
+    #[strum(serialize = "ringbuf-write")]
+    ringbuf_write {
+        arguments: ringbuf_write,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "human-monitor-command")]
+    #[strum(serialize = "human-monitor-command")]
+    human_monitor_command {
+        arguments: human_monitor_command,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "block_set_io_throttle")]
+    block_set_io_throttle {
+        arguments: Box<block_set_io_throttle>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "netdev_set_rate_limit")]
+    netdev_set_rate_limit {
+        arguments: Box<netdev_set_rate_limit>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-netdev-stats")]
+    #[strum(serialize = "query-netdev-stats")]
+    query_netdev_stats {
+        #[serde(default)]
+        arguments: query_netdev_stats,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "query-block")]
     #[strum(serialize = "query-block")]
     query_block {
@@ -343,6 +397,60 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "guest-ping")]
+    #[strum(serialize = "guest-ping")]
+    guest_ping {
+        #[serde(default)]
+        arguments: guest_ping,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-fsfreeze-freeze")]
+    #[strum(serialize = "guest-fsfreeze-freeze")]
+    guest_fsfreeze_freeze {
+        #[serde(default)]
+        arguments: guest_fsfreeze_freeze,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-fsfreeze-thaw")]
+    #[strum(serialize = "guest-fsfreeze-thaw")]
+    guest_fsfreeze_thaw {
+        #[serde(default)]
+        arguments: guest_fsfreeze_thaw,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-shutdown")]
+    #[strum(serialize = "guest-shutdown")]
+    guest_shutdown {
+        #[serde(default)]
+        arguments: guest_shutdown,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-exec")]
+    #[strum(serialize = "guest-exec")]
+    guest_exec {
+        arguments: guest_exec,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-exec-status")]
+    #[strum(serialize = "guest-exec-status")]
+    guest_exec_status {
+        arguments: guest_exec_status,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-network-get-interfaces")]
+    #[strum(serialize = "guest-network-get-interfaces")]
+    guest_network_get_interfaces {
+        #[serde(default)]
+        arguments: guest_network_get_interfaces,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
 }
 
 /// qmp_capabilities
@@ -464,6 +572,10 @@ pub struct device_add {
     pub addr: Option<String>,
     #[serde(rename = "lun")]
     pub lun: Option<usize>,
+    #[serde(rename = "channel")]
+    pub channel: Option<u8>,
+    #[serde(rename = "scsi-id")]
+    pub scsi_id: Option<u8>,
     #[serde(rename = "drive")]
     pub drive: Option<String>,
     #[serde(rename = "romfile")]
@@ -487,6 +599,15 @@ pub struct device_add {
     pub iothread: Option<String>,
     pub multifunction: Option<bool>,
     pub host: Option<String>,
+    pub chardev: Option<String>,
+    #[serde(rename = "num-queues")]
+    pub num_queues: Option<u16>,
+    #[serde(rename = "socket-id")]
+    pub socket_id: Option<u8>,
+    #[serde(rename = "core-id")]
+    pub core_id: Option<u8>,
+    #[serde(rename = "thread-id")]
+    pub thread_id: Option<u8>,
 }
 
 pub type DeviceAddArgument = device_add;
@@ -522,6 +643,12 @@ pub struct CacheOptions {
 /// * `file` - the backend file information.
 /// * `cache` - if use direct io.
 /// * `read_only` - if readonly.
+/// * `driver` - the format of the block image, `raw` or `qcow2`, default is `raw`.
+/// * `discard` - `unmap` to enable discard, `ignore` to disable it, default is `ignore`.
+/// * `detect_zeroes` - detect all-zero writes, `off`, `on` or `unmap`, default is `off`.
+/// * `aio` - the aio engine, `native`, `io_uring`, `threads` or `off`, default is `native` if use
+///   direct io, otherwise `threads`.
+/// * `throttling.*` - the limits of I/O throttling, same as the `throttling.*` options of `-drive`.
 ///
 /// Additional arguments depend on the type.
 ///
@@ -548,10 +675,51 @@ pub struct blockdev_add {
     pub driver: Option<String>,
     pub backing: Option<String>,
     pub discard: Option<String>,
+    #[serde(rename = "detect-zeroes")]
+    pub detect_zeroes: Option<String>,
+    pub aio: Option<String>,
     pub id: Option<String>,
     pub options: Option<String>,
+    #[serde(rename = "throttling.bps-total")]
+    pub bps_total: Option<u64>,
+    #[serde(rename = "throttling.bps-total-max")]
+    pub bps_total_max: Option<u64>,
+    #[serde(rename = "throttling.bps-total-max-length")]
+    pub bps_total_max_length: Option<u64>,
+    #[serde(rename = "throttling.bps-read")]
+    pub bps_read: Option<u64>,
+    #[serde(rename = "throttling.bps-read-max")]
+    pub bps_read_max: Option<u64>,
+    #[serde(rename = "throttling.bps-read-max-length")]
+    pub bps_read_max_length: Option<u64>,
+    #[serde(rename = "throttling.bps-write")]
+    pub bps_write: Option<u64>,
+    #[serde(rename = "throttling.bps-write-max")]
+    pub bps_write_max: Option<u64>,
+    #[serde(rename = "throttling.bps-write-max-length")]
+    pub bps_write_max_length: Option<u64>,
     #[serde(rename = "throttling.iops-total")]
-    pub iops: Option<u64>,
+    pub iops_total: Option<u64>,
+    #[serde(rename = "throttling.iops-total-max")]
+    pub iops_total_max: Option<u64>,
+    #[serde(rename = "throttling.iops-total-max-length")]
+    pub iops_total_max_length: Option<u64>,
+    #[serde(rename = "throttling.iops-read")]
+    pub iops_read: Option<u64>,
+    #[serde(rename = "throttling.iops-read-max")]
+    pub iops_read_max: Option<u64>,
+    #[serde(rename = "throttling.iops-read-max-length")]
+    pub iops_read_max_length: Option<u64>,
+    #[serde(rename = "throttling.iops-write")]
+    pub iops_write: Option<u64>,
+    #[serde(rename = "throttling.iops-write-max")]
+    pub iops_write_max: Option<u64>,
+    #[serde(rename = "throttling.iops-write-max-length")]
+    pub iops_write_max_length: Option<u64>,
+    #[serde(rename = "throttling.iops-size")]
+    pub iops_size: Option<u64>,
+    #[serde(rename = "throttling.group")]
+    pub throttle_group: Option<String>,
 }
 
 pub type BlockDevAddArgument = blockdev_add;
@@ -570,7 +738,15 @@ impl Command for blockdev_add {
 ///
 /// * `id` - the device's ID, must be unique.
 /// * `ifname` - the backend tap dev name.
-/// * `fds` - the file fd opened by upper level.
+/// * `fds` - the file fd opened by upper level, the fds of all queue pairs are separated
+///   by `:` for multiqueue tap device.
+/// * `queues` - the number of queue pairs.
+/// * `type` - the type of netdev, `tap` or `vhost-user`.
+/// * `chardev` - the client-mode socket chardev connected with vhost-user backend.
+/// * `rx-bps`, `rx-pps` - Bytes and packets per second received by the guest.
+/// * `tx-bps`, `tx-pps` - Bytes and packets per second transmitted by the guest.
+/// * `*-max` - The limits during a burst.
+/// * `*-max-length` - Seconds that a burst can last.
 ///
 /// Additional arguments depend on the type.
 ///
@@ -597,6 +773,31 @@ pub struct netdev_add {
     pub downscript: Option<String>,
     pub script: Option<String>,
     pub queues: Option<String>,
+    pub chardev: Option<String>,
+    #[serde(rename = "rx-bps")]
+    pub rx_bps: Option<u64>,
+    #[serde(rename = "rx-bps-max")]
+    pub rx_bps_max: Option<u64>,
+    #[serde(rename = "rx-bps-max-length")]
+    pub rx_bps_max_length: Option<u64>,
+    #[serde(rename = "rx-pps")]
+    pub rx_pps: Option<u64>,
+    #[serde(rename = "rx-pps-max")]
+    pub rx_pps_max: Option<u64>,
+    #[serde(rename = "rx-pps-max-length")]
+    pub rx_pps_max_length: Option<u64>,
+    #[serde(rename = "tx-bps")]
+    pub tx_bps: Option<u64>,
+    #[serde(rename = "tx-bps-max")]
+    pub tx_bps_max: Option<u64>,
+    #[serde(rename = "tx-bps-max-length")]
+    pub tx_bps_max_length: Option<u64>,
+    #[serde(rename = "tx-pps")]
+    pub tx_pps: Option<u64>,
+    #[serde(rename = "tx-pps-max")]
+    pub tx_pps_max: Option<u64>,
+    #[serde(rename = "tx-pps-max-length")]
+    pub tx_pps_max_length: Option<u64>,
 }
 
 pub type NetDevAddArgument = netdev_add;
@@ -1077,6 +1278,33 @@ pub struct DeviceDeleted {
     pub path: String,
 }
 
+/// MemoryDeviceSizeChange
+///
+/// Emitted whenever the size of memory plugged by the guest through a
+/// virtio-mem device changes.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "MEMORY_DEVICE_SIZE_CHANGE",
+///      "data": { "id": "vmem0", "size": 1073741824,
+///                "qom-path": "/machine/peripheral/vmem0" },
+///      "timestamp": { "seconds": 1588168529, "microseconds": 201316 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryDeviceSizeChange {
+    /// Device name.
+    #[serde(rename = "id", default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The new size of plugged memory.
+    #[serde(rename = "size")]
+    pub size: u64,
+    /// Device path.
+    #[serde(rename = "qom-path")]
+    pub qom_path: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, EnumIter, EnumVariantNames, EnumString)]
 #[serde(tag = "event")]
 pub enum QmpEvent {
@@ -1109,6 +1337,11 @@ pub enum QmpEvent {
         data: BalloonInfo,
         timestamp: TimeStamp,
     },
+    #[serde(rename = "MEMORY_DEVICE_SIZE_CHANGE")]
+    MemoryDeviceSizeChange {
+        data: MemoryDeviceSizeChange,
+        timestamp: TimeStamp,
+    },
 }
 
 /// query-balloon:
@@ -1117,13 +1350,18 @@ pub enum QmpEvent {
 ///
 /// # Returns
 ///
-/// `BalloonInfo` includs the actual size of memory
+/// `BalloonInfo` includs the actual size of memory, and the memory
+/// statistics of guest if statistics queue of balloon is enabled.
 ///
 /// # Example
 ///
 /// ```text
 /// -> { "execute": "query-balloon" }
-/// <- {"return":{"actual":8589934592}}
+/// <- {"return":{"actual":8589934592,"guest-stats":{"last-update":1650853405,
+///     "stat-swap-in":0,"stat-swap-out":0,"stat-major-faults":178,
+///     "stat-minor-faults":67935,"stat-free-memory":7826743296,
+///     "stat-total-memory":8324165632,"stat-available-memory":7948251136,
+///     "stat-disk-caches":223854592,"stat-htlb-pgalloc":0,"stat-htlb-pgfail":0}}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct query_balloon {}
@@ -1137,6 +1375,44 @@ impl Command for query_balloon {
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct BalloonInfo {
     pub actual: u64,
+    #[serde(
+        rename = "guest-stats",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub guest_stats: Option<BalloonStats>,
+}
+
+/// Memory statistics reported by guest through balloon device, the
+/// statistics not supported by guest are omitted.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BalloonStats {
+    /// Time in seconds since the Epoch when the statistics are updated.
+    #[serde(rename = "last-update")]
+    pub last_update: u64,
+    #[serde(rename = "stat-swap-in", skip_serializing_if = "Option::is_none")]
+    pub swap_in: Option<u64>,
+    #[serde(rename = "stat-swap-out", skip_serializing_if = "Option::is_none")]
+    pub swap_out: Option<u64>,
+    #[serde(rename = "stat-major-faults", skip_serializing_if = "Option::is_none")]
+    pub major_faults: Option<u64>,
+    #[serde(rename = "stat-minor-faults", skip_serializing_if = "Option::is_none")]
+    pub minor_faults: Option<u64>,
+    #[serde(rename = "stat-free-memory", skip_serializing_if = "Option::is_none")]
+    pub free_memory: Option<u64>,
+    #[serde(rename = "stat-total-memory", skip_serializing_if = "Option::is_none")]
+    pub total_memory: Option<u64>,
+    #[serde(
+        rename = "stat-available-memory",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub available_memory: Option<u64>,
+    #[serde(rename = "stat-disk-caches", skip_serializing_if = "Option::is_none")]
+    pub disk_caches: Option<u64>,
+    #[serde(rename = "stat-htlb-pgalloc", skip_serializing_if = "Option::is_none")]
+    pub hugetlb_allocations: Option<u64>,
+    #[serde(rename = "stat-htlb-pgfail", skip_serializing_if = "Option::is_none")]
+    pub hugetlb_failures: Option<u64>,
 }
 
 /// balloon:
@@ -1171,6 +1447,39 @@ impl Command for balloon {
     }
 }
 
+/// set_link:
+///
+/// Set the link status of a virtio net device.
+///
+/// # Arguments
+///
+/// * `name` - The id of the net device.
+/// * `up` - Link is up if true, and down if false.
+///
+/// # Notes
+///
+/// The driver is notified by config change interrupt, packets are dropped
+/// while the link is down.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "set_link", "arguments": { "name": "net-0", "up": false } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct set_link {
+    pub name: String,
+    pub up: bool,
+}
+
+impl Command for set_link {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
 /// version:
 ///
 /// Query version of StratoVirt.
@@ -1204,7 +1513,7 @@ impl Command for query_version {
 /// {"name":"cont"},{"name":"device_add"},{"name":"device_del"},{"name":"netdev_add"},
 /// {"name":"netdev_del"},{"name":"query-hotpluggable-cpus"},{"name":"query-cpus"},
 /// {"name":"query_status"},{"name":"getfd"},{"name":"blockdev_add"},
-/// {"name":"blockdev_del"},{"name":"balloon"},{"name":"query_balloon"},
+/// {"name":"blockdev_del"},{"name":"balloon"},{"name":"query_balloon"},{"name":"set_link"},
 /// {"name":"migrate"},{"name":"query_migrate"},{"name":"query_version"},
 /// {"name":"query_target"},{"name":"query_commands"}]}
 /// ```
@@ -1624,40 +1933,670 @@ impl Command for qom_get {
     }
 }
 
+/// qom-set:
+///
+/// Set the property of an object, only `requested-size` of virtio-mem device
+/// is supported now.
+///
+/// # Arguments
+///
+/// * `path` - The id or the qom path of the object.
+/// * `property` - The name of the property.
+/// * `value` - The new value of the property.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "qom-set",
+///      "arguments": { "path": "vmem0", "property": "requested-size", "value": 1073741824 } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct qom_set {
+    pub path: String,
+    pub property: String,
+    pub value: u64,
+}
+
+impl Command for qom_set {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// Encoding of the data transferred by QMP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataFormat {
+    /// Data is transferred as utf-8 string, invalid utf-8 sequences are replaced
+    /// with U+FFFD when reading.
+    Utf8,
+    /// Data is transferred as base64 string.
+    Base64,
+}
+
+/// ringbuf-read:
+///
+/// Read the output of guest from a ringbuf-type chardev, the data read is removed
+/// from the ring buffer.
+///
+/// # Arguments
+///
+/// * `device` - The id of the chardev.
+/// * `size` - The maximum number of bytes to read.
+/// * `format` - "utf8"(default) or "base64".
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "ringbuf-read",
+///      "arguments": { "device": "ringbuf0", "size": 1024, "format": "utf8" } }
+/// <- {"return":"[    0.000000] Linux version 5.10.0"}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ringbuf_read {
+    pub device: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<DataFormat>,
+}
+
+impl Command for ringbuf_read {
+    type Res = String;
+
+    fn back(self) -> String {
+        Default::default()
+    }
+}
+
+/// ringbuf-write:
+///
+/// Write data to a ringbuf-type chardev.
+///
+/// # Arguments
+///
+/// * `device` - The id of the chardev.
+/// * `data` - The data to write.
+/// * `format` - "utf8"(default) or "base64".
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "ringbuf-write",
+///      "arguments": { "device": "ringbuf0", "data": "abcdefgh", "format": "utf8" } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ringbuf_write {
+    pub device: String,
+    pub data: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<DataFormat>,
+}
+
+impl Command for ringbuf_write {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// human-monitor-command:
+///
+/// Execute a command of the human monitor (HMP) and return its output.
+///
+/// # Arguments
+///
+/// * `command-line` - The command line to execute.
+/// * `cpu-index` - The index of the vCPU used by the command as current vCPU,
+///   default to 0.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "human-monitor-command",
+///      "arguments": { "command-line": "info status" } }
+/// <- { "return": "VM status: running\r\n" }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct human_monitor_command {
+    #[serde(rename = "command-line")]
+    pub command_line: String,
+    #[serde(rename = "cpu-index", default, skip_serializing_if = "Option::is_none")]
+    pub cpu_index: Option<u64>,
+}
+
+impl Command for human_monitor_command {
+    type Res = String;
+
+    fn back(self) -> String {
+        Default::default()
+    }
+}
+
+/// block_set_io_throttle:
+///
+/// Change the I/O throttle limits of a block device on the fly.
+///
+/// # Arguments
+///
+/// * `device` - The id of the block device.
+/// * `id` - The id of the block device, alternative to `device`.
+/// * `bps`, `bps_rd`, `bps_wr` - Total, read and write bytes per second.
+/// * `iops`, `iops_rd`, `iops_wr` - Total, read and write operations per second.
+/// * `*_max` - The limits during a burst. (optional)
+/// * `*_max_length` - Seconds that a burst can last. (optional)
+/// * `iops_size` - Size of an operation in bytes. (optional)
+/// * `group` - The throttle group shared by the devices. (optional)
+///
+/// # Notes
+///
+/// A limit of 0 means unlimited, all limits 0 disables throttling.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "block_set_io_throttle",
+///      "arguments": { "id": "drive-0", "bps": 0, "bps_rd": 1048576, "bps_wr": 0,
+///                     "iops": 0, "iops_rd": 0, "iops_wr": 100 } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct block_set_io_throttle {
+    pub device: Option<String>,
+    pub id: Option<String>,
+    pub bps: u64,
+    pub bps_rd: u64,
+    pub bps_wr: u64,
+    pub iops: u64,
+    pub iops_rd: u64,
+    pub iops_wr: u64,
+    pub bps_max: Option<u64>,
+    pub bps_rd_max: Option<u64>,
+    pub bps_wr_max: Option<u64>,
+    pub iops_max: Option<u64>,
+    pub iops_rd_max: Option<u64>,
+    pub iops_wr_max: Option<u64>,
+    pub bps_max_length: Option<u64>,
+    pub bps_rd_max_length: Option<u64>,
+    pub bps_wr_max_length: Option<u64>,
+    pub iops_max_length: Option<u64>,
+    pub iops_rd_max_length: Option<u64>,
+    pub iops_wr_max_length: Option<u64>,
+    pub iops_size: Option<u64>,
+    pub group: Option<String>,
+}
+
+pub type BlockIoThrottleArgument = block_set_io_throttle;
+
+impl Command for block_set_io_throttle {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// netdev_set_rate_limit:
+///
+/// Change the rate limits of a virtio net device on the fly.
+///
+/// # Arguments
+///
+/// * `id` - The id of the net device.
+/// * `rx-bps`, `rx-pps` - Bytes and packets per second received by the guest.
+/// * `tx-bps`, `tx-pps` - Bytes and packets per second transmitted by the guest.
+/// * `*-max` - The limits during a burst. (optional)
+/// * `*-max-length` - Seconds that a burst can last. (optional)
+///
+/// # Notes
+///
+/// A limit of 0 means unlimited, all limits 0 disables rate limiting.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "netdev_set_rate_limit",
+///      "arguments": { "id": "net-0", "rx-bps": 10485760, "rx-pps": 0,
+///                     "tx-bps": 10485760, "tx-pps": 10000 } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct netdev_set_rate_limit {
+    pub id: String,
+    #[serde(rename = "rx-bps")]
+    pub rx_bps: u64,
+    #[serde(rename = "rx-pps")]
+    pub rx_pps: u64,
+    #[serde(rename = "tx-bps")]
+    pub tx_bps: u64,
+    #[serde(rename = "tx-pps")]
+    pub tx_pps: u64,
+    #[serde(rename = "rx-bps-max")]
+    pub rx_bps_max: Option<u64>,
+    #[serde(rename = "rx-pps-max")]
+    pub rx_pps_max: Option<u64>,
+    #[serde(rename = "tx-bps-max")]
+    pub tx_bps_max: Option<u64>,
+    #[serde(rename = "tx-pps-max")]
+    pub tx_pps_max: Option<u64>,
+    #[serde(rename = "rx-bps-max-length")]
+    pub rx_bps_max_length: Option<u64>,
+    #[serde(rename = "rx-pps-max-length")]
+    pub rx_pps_max_length: Option<u64>,
+    #[serde(rename = "tx-bps-max-length")]
+    pub tx_bps_max_length: Option<u64>,
+    #[serde(rename = "tx-pps-max-length")]
+    pub tx_pps_max_length: Option<u64>,
+}
+
+pub type NetRateLimitArgument = netdev_set_rate_limit;
+
+impl Command for netdev_set_rate_limit {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// query-netdev-stats:
+///
+/// Get the packet statistics of net devices, counted from the view of the guest.
+///
+/// # Arguments
+///
+/// * `id` - The id of the net device, all net devices are queried if it is not set. (optional)
+/// * `reset` - Reset the statistics after they are returned. (optional)
+///
+/// # Notes
+///
+/// The statistics of vhost-kernel net devices are read from the tap device, `rx-queue-full`
+/// and `rx-errors` are not counted for them. vhost-user net devices are not reported.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-netdev-stats", "arguments": { "id": "net-0" } }
+/// <- {"return":[{"id":"net-0","stats":{"rx-packets":1563,"rx-bytes":2168321,
+///      "rx-dropped":3,"rx-errors":0,"rx-queue-full":1,"tx-packets":1024,
+///      "tx-bytes":98211,"tx-dropped":0,"tx-errors":0}}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct query_netdev_stats {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset: Option<bool>,
+}
+
+impl Command for query_netdev_stats {
+    type Res = Vec<NetdevStatsInfo>;
+
+    fn back(self) -> Vec<NetdevStatsInfo> {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct NetdevStatsInfo {
+    pub id: String,
+    pub stats: NetdevStats,
+}
+
+/// Packet statistics of a net device.
+///
+/// * `rx-*` - Packets received by the guest.
+/// * `tx-*` - Packets transmitted by the guest.
+/// * `*-dropped` - Packets dropped because the link is down, or filtered by the receive filter.
+/// * `*-errors` - Failures of reading or writing the tap device.
+/// * `rx-queue-full` - Times that receiving stops as the guest provides no receive buffer.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct NetdevStats {
+    #[serde(rename = "rx-packets")]
+    pub rx_packets: u64,
+    #[serde(rename = "rx-bytes")]
+    pub rx_bytes: u64,
+    #[serde(rename = "rx-dropped")]
+    pub rx_dropped: u64,
+    #[serde(rename = "rx-errors")]
+    pub rx_errors: u64,
+    #[serde(rename = "rx-queue-full")]
+    pub rx_queue_full: u64,
+    #[serde(rename = "tx-packets")]
+    pub tx_packets: u64,
+    #[serde(rename = "tx-bytes")]
+    pub tx_bytes: u64,
+    #[serde(rename = "tx-dropped")]
+    pub tx_dropped: u64,
+    #[serde(rename = "tx-errors")]
+    pub tx_errors: u64,
+}
+
+/// guest-ping:
+///
+/// Ping the guest agent, which is forwarded to guest agent.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "guest-ping" }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct guest_ping {}
+
+impl Command for guest_ping {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// guest-fsfreeze-freeze:
+///
+/// Freeze all the freezable filesystems of guest, return the number of
+/// filesystems frozen.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "guest-fsfreeze-freeze" }
+/// <- { "return": 2 }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct guest_fsfreeze_freeze {}
+
+impl Command for guest_fsfreeze_freeze {
+    type Res = i64;
+
+    fn back(self) -> i64 {
+        Default::default()
+    }
+}
+
+/// guest-fsfreeze-thaw:
+///
+/// Thaw all the filesystems of guest frozen by `guest-fsfreeze-freeze`, return
+/// the number of filesystems thawed.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "guest-fsfreeze-thaw" }
+/// <- { "return": 2 }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct guest_fsfreeze_thaw {}
+
+impl Command for guest_fsfreeze_thaw {
+    type Res = i64;
+
+    fn back(self) -> i64 {
+        Default::default()
+    }
+}
+
+/// guest-shutdown:
+///
+/// Shutdown the guest by guest agent, which doesn't reply on success.
+///
+/// # Arguments
+///
+/// * `mode` - "halt", "powerdown"(default) or "reboot".
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "guest-shutdown", "arguments": { "mode": "reboot" } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct guest_shutdown {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+}
+
+impl Command for guest_shutdown {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// guest-exec:
+///
+/// Execute a command in guest, return the pid of the process.
+///
+/// # Arguments
+///
+/// * `path` - Path of the executable in guest.
+/// * `arg` - Arguments of the executable.
+/// * `env` - Environment variables of the process.
+/// * `input-data` - Data to be passed to stdin of the process, base64 encoded.
+/// * `capture-output` - Whether to capture the output of the process.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "guest-exec",
+///      "arguments": { "path": "/bin/ls", "arg": ["/"], "capture-output": true } }
+/// <- { "return": { "pid": 1024 } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct guest_exec {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arg: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<Vec<String>>,
+    #[serde(
+        rename = "input-data",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub input_data: Option<String>,
+    #[serde(
+        rename = "capture-output",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub capture_output: Option<bool>,
+}
+
+impl Command for guest_exec {
+    type Res = GuestExec;
+
+    fn back(self) -> GuestExec {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct GuestExec {
+    pub pid: i64,
+}
+
+/// guest-exec-status:
+///
+/// Get the status of the process started by `guest-exec`.
+///
+/// # Arguments
+///
+/// * `pid` - The pid returned by `guest-exec`.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "guest-exec-status", "arguments": { "pid": 1024 } }
+/// <- { "return": { "exited": true, "exitcode": 0, "out-data": "YmluCmJvb3QK" } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct guest_exec_status {
+    pub pid: i64,
+}
+
+impl Command for guest_exec_status {
+    type Res = Any;
+
+    fn back(self) -> Any {
+        Default::default()
+    }
+}
+
+/// guest-network-get-interfaces:
+///
+/// Get the network interfaces of guest.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "guest-network-get-interfaces" }
+/// <- { "return": [{ "name": "lo", "hardware-address": "00:00:00:00:00:00",
+///      "ip-addresses": [{ "ip-address-type": "ipv4", "ip-address": "127.0.0.1",
+///      "prefix": 8 }] }] }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct guest_network_get_interfaces {}
+
+impl Command for guest_network_get_interfaces {
+    type Res = Any;
+
+    fn back(self) -> Any {
+        Default::default()
+    }
+}
+
 /// Query blocks of StratoVirt.
 ///
 /// # Example
 ///
 /// ```text
 /// -> { "execute": "query-block" }
-/// <- {"return":[]}
+/// <- {"return":[{"device":"drive-0","qdev":"blk-0","type":"unknown","removable":false,
+///      "locked":false,"inserted":{"file":"/path/to/rootfs","node-name":"drive-0",
+///      "ro":false,"drv":"raw","encrypted":false,"detect_zeroes":"off","bps":0,
+///      "bps_rd":0,"bps_wr":0,"iops":0,"iops_rd":0,"iops_wr":0,"cache":{"writeback":true,
+///      "direct":true,"no-flush":false},"write_threshold":0}}]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct query_block {}
 
 impl Command for query_block {
-    type Res = Vec<Cmd>;
+    type Res = Vec<BlockInfo>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<BlockInfo> {
         Default::default()
     }
 }
 
+/// Information of a block device.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockInfo {
+    /// The id of the drive.
+    pub device: String,
+    /// The id of the device which the drive is attached to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qdev: Option<String>,
+    #[serde(rename = "type")]
+    pub block_type: String,
+    pub removable: bool,
+    pub locked: bool,
+    /// The image of the drive, absent if no image is inserted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inserted: Option<BlockDeviceInfo>,
+}
+
+/// Information of the image of a block device.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockDeviceInfo {
+    pub file: String,
+    #[serde(rename = "node-name")]
+    pub node_name: String,
+    pub ro: bool,
+    pub drv: String,
+    pub encrypted: bool,
+    pub detect_zeroes: String,
+    pub bps: u64,
+    pub bps_rd: u64,
+    pub bps_wr: u64,
+    pub iops: u64,
+    pub iops_rd: u64,
+    pub iops_wr: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bps_max: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bps_rd_max: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bps_wr_max: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iops_max: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iops_rd_max: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iops_wr_max: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bps_max_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bps_rd_max_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bps_wr_max_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iops_max_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iops_rd_max_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iops_wr_max_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iops_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    pub cache: BlockdevCacheInfo,
+    pub write_threshold: u64,
+}
+
+/// Cache mode of a block device.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockdevCacheInfo {
+    pub writeback: bool,
+    pub direct: bool,
+    #[serde(rename = "no-flush")]
+    pub no_flush: bool,
+}
+
 /// Query named block node.
 ///
 /// # Example
 ///
 /// ```text
 /// -> { "execute": "query-named-block-nodes" }
-/// <- {"return":[]}
+/// <- {"return":[{"file":"/path/to/rootfs","node-name":"drive-0","ro":false,"drv":"raw",
+///      "encrypted":false,"detect_zeroes":"off","bps":0,"bps_rd":0,"bps_wr":0,"iops":0,
+///      "iops_rd":0,"iops_wr":0,"cache":{"writeback":true,"direct":true,"no-flush":false},
+///      "write_threshold":0}]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct query_named_block_nodes {}
 
 impl Command for query_named_block_nodes {
-    type Res = Vec<Cmd>;
+    type Res = Vec<BlockDeviceInfo>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<BlockDeviceInfo> {
         Default::default()
     }
 }
@@ -1668,19 +2607,80 @@ impl Command for query_named_block_nodes {
 ///
 /// ```text
 /// -> { "execute": "query-blockstats" }
-/// <- {"return":[]}
+/// <- {"return":[{"device":"drive-0","qdev":"blk-0","node-name":"drive-0",
+///      "stats":{"rd_bytes":1048576,"wr_bytes":0,"unmap_bytes":0,"rd_operations":256,
+///      "wr_operations":0,"flush_operations":0,"unmap_operations":0,
+///      "rd_total_time_ns":25600000,"wr_total_time_ns":0,"flush_total_time_ns":0,
+///      "unmap_total_time_ns":0,"rd_merged":0,"wr_merged":0,"unmap_merged":0,
+///      "idle_time_ns":1000000,"failed_rd_operations":0,"failed_wr_operations":0,
+///      "failed_flush_operations":0,"failed_unmap_operations":0,
+///      "rd_latency_histogram":{"boundaries":[10000,100000,1000000,10000000,100000000,
+///      1000000000],"bins":[0,0,256,0,0,0,0]}, ...}}]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct query_blockstats {}
 
 impl Command for query_blockstats {
-    type Res = Vec<Cmd>;
+    type Res = Vec<BlockStats>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<BlockStats> {
         Default::default()
     }
 }
 
+/// Statistics of a block device.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockStats {
+    /// The id of the drive.
+    pub device: String,
+    /// The id of the device which the drive is attached to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qdev: Option<String>,
+    #[serde(rename = "node-name", skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+    pub stats: BlockDeviceStats,
+}
+
+/// I/O statistics of a block device. The operations are counted after the
+/// adjacent requests are merged, and the total time is the sum of the latency
+/// of the operations.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockDeviceStats {
+    pub rd_bytes: u64,
+    pub wr_bytes: u64,
+    pub unmap_bytes: u64,
+    pub rd_operations: u64,
+    pub wr_operations: u64,
+    pub flush_operations: u64,
+    pub unmap_operations: u64,
+    pub rd_total_time_ns: u64,
+    pub wr_total_time_ns: u64,
+    pub flush_total_time_ns: u64,
+    pub unmap_total_time_ns: u64,
+    pub rd_merged: u64,
+    pub wr_merged: u64,
+    pub unmap_merged: u64,
+    /// Nanoseconds since the last operation completed, absent if no operation has completed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_time_ns: Option<u64>,
+    pub failed_rd_operations: u64,
+    pub failed_wr_operations: u64,
+    pub failed_flush_operations: u64,
+    pub failed_unmap_operations: u64,
+    pub rd_latency_histogram: BlockLatencyHistogramInfo,
+    pub wr_latency_histogram: BlockLatencyHistogramInfo,
+    pub flush_latency_histogram: BlockLatencyHistogramInfo,
+}
+
+/// Histogram of the latency of block operations. The bin `i` counts the operations
+/// with latency in `[boundaries[i - 1], boundaries[i])` nanoseconds, the first bin
+/// starts from 0 and the last bin has no upper bound.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockLatencyHistogramInfo {
+    pub boundaries: Vec<u64>,
+    pub bins: Vec<u64>,
+}
+
 /// Query capabilities of gic.
 ///
 /// # Example
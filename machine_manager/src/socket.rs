@@ -23,6 +23,7 @@ use vmm_sys_util::epoll::EventSet;
 use super::errors::Result;
 use crate::machine::MachineExternalInterface;
 use crate::{
+    qmp::hmp::{HMP_BANNER, HMP_PROMPT},
     qmp::qmp_schema::QmpEvent,
     qmp::{QmpChannel, QmpGreeting, Response},
 };
@@ -62,6 +63,10 @@ pub struct Socket {
     stream: RwLock<Option<SocketStream>>,
     /// Perform socket command
     performer: Option<Arc<Mutex<dyn MachineExternalInterface>>>,
+    /// Protocol served on the socket.
+    mode: MonitorMode,
+    /// Current vCPU of the human monitor session.
+    hmp_cpu_index: usize,
 }
 
 impl Socket {
@@ -80,9 +85,21 @@ impl Socket {
             listener,
             stream: RwLock::new(None),
             performer,
+            mode: MonitorMode::Control,
+            hmp_cpu_index: 0,
         }
     }
 
+    /// Set the protocol served on `Socket`, default to `MonitorMode::Control`.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The protocol served on `Socket`.
+    pub fn with_mode(mut self, mode: MonitorMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
     /// Get listener's fd from `Socket`.
     pub fn get_listener_fd(&self) -> RawFd {
         self.listener.as_raw_fd()
@@ -97,8 +114,18 @@ impl Socket {
             }
         }
 
-        QmpChannel::bind_writer(SocketRWHandler::new(self.get_stream_fd()));
-        self.send_response(true);
+        match self.mode {
+            MonitorMode::Control => {
+                QmpChannel::bind_writer(SocketRWHandler::new(self.get_stream_fd()));
+                self.send_response(true);
+            }
+            MonitorMode::Readline => {
+                let banner = format!("{}\r\n{}", HMP_BANNER, HMP_PROMPT);
+                if let Err(e) = self.get_socket_handler().send_text(&banner) {
+                    error!("Failed to send banner of human monitor: {}", e);
+                }
+            }
+        }
     }
 
     /// Accept a new incoming connection unix stream from unix listener.
@@ -191,24 +218,35 @@ impl Socket {
         let handler: Box<dyn Fn(EventSet, RawFd) -> Option<Vec<EventNotifier>>> =
             Box::new(move |event, _| {
                 if event == EventSet::IN {
-                    let socket_mutexed = shared_socket.lock().unwrap();
+                    let mut socket_mutexed = shared_socket.lock().unwrap();
                     let stream_fd = socket_mutexed.get_stream_fd();
 
-                    let performer = &socket_mutexed.performer.as_ref().unwrap();
-                    if let Err(e) = crate::qmp::handle_qmp(
-                        stream_fd,
-                        &performer,
-                        &mut shared_leak_bucket.lock().unwrap(),
-                    ) {
+                    let performer = socket_mutexed.performer.clone().unwrap();
+                    let ret = match socket_mutexed.mode {
+                        MonitorMode::Control => crate::qmp::handle_qmp(
+                            stream_fd,
+                            &performer,
+                            &mut shared_leak_bucket.lock().unwrap(),
+                        ),
+                        MonitorMode::Readline => crate::qmp::hmp::handle_hmp(
+                            stream_fd,
+                            &performer,
+                            &mut socket_mutexed.hmp_cpu_index,
+                        ),
+                    };
+                    if let Err(e) = ret {
                         error!("{}", e);
                     }
                 }
                 if event & EventSet::HANG_UP == EventSet::HANG_UP {
-                    let socket_mutexed = shared_socket.lock().unwrap();
+                    let mut socket_mutexed = shared_socket.lock().unwrap();
                     let stream_fd = socket_mutexed.get_stream_fd();
                     let listener_fd = socket_mutexed.get_listener_fd();
 
-                    QmpChannel::unbind();
+                    if socket_mutexed.mode == MonitorMode::Control {
+                        QmpChannel::unbind();
+                    }
+                    socket_mutexed.hmp_cpu_index = 0;
 
                     Some(vec![
                         EventNotifier::new(
@@ -291,6 +329,15 @@ pub enum SocketType {
     Unix = 1,
 }
 
+/// Protocol served on api socket.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MonitorMode {
+    /// QMP, the json based machine protocol.
+    Control,
+    /// HMP, the line based human monitor.
+    Readline,
+}
+
 /// Wrapper over UnixSteam.
 #[derive(Debug)]
 struct SocketStream {
@@ -627,6 +674,15 @@ impl SocketHandler {
         }
     }
 
+    /// Receive the bytes from `socket_fd` as text lines, line endings are removed.
+    pub fn decode_text(&mut self) -> Result<Vec<String>> {
+        self.buffer.clear();
+        self.stream.clear();
+        self.stream.read_fd()?;
+        self.buffer = self.stream.get_buf_string()?;
+        Ok(self.buffer.lines().map(String::from).collect())
+    }
+
     /// Discard message from `socket_fd`.
     pub fn discard(&mut self) -> Result<()> {
         self.stream.read_fd()?;
@@ -656,6 +712,22 @@ impl SocketHandler {
             )),
         }
     }
+
+    /// Send String to `socket_fd` as it is, without appending a newline.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - The `String` send to `socket_fd`.
+    ///
+    /// # Errors
+    /// The socket file descriptor is broken.
+    pub fn send_text(&mut self, s: &str) -> std::io::Result<()> {
+        if s.is_empty() {
+            return Ok(());
+        }
+        self.stream.flush().unwrap();
+        self.stream.write_all(s.as_bytes())
+    }
 }
 
 #[cfg(test)]
@@ -23,6 +23,7 @@ extern crate migration_derive;
 mod device_state;
 mod header;
 mod manager;
+mod migration;
 mod snapshot;
 mod status;
 
@@ -94,6 +94,52 @@ pub trait MigrationHook: StateTransfer {
     fn resume(&mut self) -> Result<()> {
         Ok(())
     }
+
+    /// Start logging dirty pages of memory during live migration.
+    ///
+    /// # Notes
+    ///
+    /// Only memory instance needs to implement it.
+    fn start_dirty_log(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Stop logging dirty pages of memory.
+    fn stop_dirty_log(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get memory ranges which need to be sent in live migration, every range
+    /// is represented as (guest_address, length).
+    ///
+    /// # Arguments
+    ///
+    /// * `full` - Get all ram ranges if `true`, otherwise get dirty ranges.
+    fn get_dirty_ranges(&self, _full: bool) -> Result<Vec<(u64, u64)>> {
+        Ok(Vec::new())
+    }
+
+    /// Save memory data of a range to `Write` trait object.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Start guest address of the range.
+    /// * `len` - Length of the range.
+    /// * `writer` - The `Write` trait object to store data.
+    fn save_memory_range(&self, _addr: u64, _len: u64, _writer: &mut dyn Write) -> Result<()> {
+        Ok(())
+    }
+
+    /// Load memory data of a range from `Read` trait object.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Start guest address of the range.
+    /// * `len` - Length of the range.
+    /// * `reader` - The `Read` trait object to receive data.
+    fn load_memory_range(&self, _addr: u64, _len: u64, _reader: &mut dyn Read) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// The instance id to represent a single object in VM.
@@ -0,0 +1,277 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::io::{Read, Write};
+use std::mem::size_of;
+
+use util::byte_code::ByteCode;
+
+use crate::errors::{ErrorKind, Result, ResultExt};
+use crate::header::FileFormat;
+use crate::manager::{MigrationEntry, MigrationManager, MIGRATION_MANAGER};
+use crate::status::MigrationStatus;
+
+/// The max iterations of sending dirty memory before stopping the VM.
+const MAX_DIRTY_ITERATIONS: u32 = 30;
+/// Stop iterating when the dirty memory sent in one iteration is less than it (4 MiB).
+const DIRTY_MEMORY_THRESHOLD: u64 = 4 << 20;
+/// The reply from destination when it loads all migration data successfully.
+const MIGRATION_ACK: u8 = 1;
+
+/// The header of a memory block in migration stream, followed by `len` bytes
+/// memory data. A block with zero length marks the end of memory data.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct MemBlock {
+    /// Start guest address of the memory block.
+    addr: u64,
+    /// Length of the memory block.
+    len: u64,
+}
+
+impl ByteCode for MemBlock {}
+
+impl MigrationManager {
+    /// Do live migration for `VM` through the stream connected with destination.
+    ///
+    /// # Notes
+    ///
+    /// The migration stream consists of `MigrationHeader`, memory blocks, device
+    /// state descriptors and device states. All ram is sent first while VM keeps
+    /// running, then dirty memory is sent iteratively until it converges. At last
+    /// the VM is paused, the remaining dirty memory and device states are sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream connected with destination.
+    /// * `pause_vm` - Callback to pause VM and quiesce devices before sending
+    ///   the last dirty memory and device state.
+    /// * `resume_vm` - Callback called after migration if `pause_vm` has been
+    ///   called, its argument is `true` if migration failed and the VM should
+    ///   keep running on source.
+    pub fn send_migration<T, P, R>(stream: &mut T, pause_vm: P, resume_vm: R) -> Result<()>
+    where
+        T: Read + Write,
+        P: FnOnce() -> bool,
+        R: FnOnce(bool),
+    {
+        // Set status to `Active`
+        MigrationManager::set_status(MigrationStatus::Active)?;
+
+        let mut paused = false;
+        let ret = Self::do_send_migration(stream, pause_vm, &mut paused);
+        if paused {
+            resume_vm(ret.is_err());
+        }
+        ret?;
+
+        // Set status to `Completed`
+        MigrationManager::set_status(MigrationStatus::Completed)?;
+
+        Ok(())
+    }
+
+    fn do_send_migration<T, F>(stream: &mut T, pause_vm: F, paused: &mut bool) -> Result<()>
+    where
+        T: Read + Write,
+        F: FnOnce() -> bool,
+    {
+        Self::save_header(FileFormat::Device, stream)?;
+
+        let ret = Self::start_dirty_log()
+            .and_then(|_| Self::send_memory_iteratively(stream, pause_vm, paused));
+        // Dirty log may be started partly even if starting fails.
+        let stop_ret = Self::stop_dirty_log();
+        ret?;
+        stop_ret?;
+
+        // Device state is sent with its length, so that destination knows where it ends.
+        let mut state_buffer = Vec::new();
+        Self::save_descriptor_db(&mut state_buffer)?;
+        Self::save_device_state(&mut state_buffer)?;
+        stream
+            .write_all((state_buffer.len() as u64).as_bytes())
+            .chain_err(|| "Failed to send device state length")?;
+        stream
+            .write_all(&state_buffer)
+            .chain_err(|| "Failed to send device state")?;
+        stream.flush()?;
+
+        let mut ack = [0_u8; 1];
+        stream
+            .read_exact(&mut ack)
+            .chain_err(|| "Failed to receive reply from destination")?;
+        if ack[0] != MIGRATION_ACK {
+            bail!("Destination failed to load migration data");
+        }
+
+        Ok(())
+    }
+
+    /// Receive live migration for `VM` through the stream connected with source.
+    ///
+    /// # Notes
+    ///
+    /// The memory of `VM` must be created before calling this function, it will
+    /// be filled with the memory data from source.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The stream connected with source.
+    pub fn recv_migration<T>(stream: &mut T) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        // Set status to `Active`
+        MigrationManager::set_status(MigrationStatus::Active)?;
+
+        let header = Self::load_header(stream)?;
+        header.check_header()?;
+        if header.format != FileFormat::Device {
+            bail!("Invalid migration stream header");
+        }
+
+        Self::recv_memory(stream).chain_err(|| "Failed to receive vm memory")?;
+
+        let mut length = 0_u64;
+        stream
+            .read_exact(length.as_mut_bytes())
+            .chain_err(|| "Failed to receive device state length")?;
+        if (length as usize) < header.desc_len {
+            bail!("Invalid device state length {}", length);
+        }
+        let mut state_buffer = vec![0_u8; length as usize];
+        stream
+            .read_exact(&mut state_buffer)
+            .chain_err(|| "Failed to receive device state")?;
+
+        let mut state_reader = state_buffer.as_slice();
+        let snapshot_desc_db = Self::load_descriptor_db(&mut state_reader, header.desc_len)
+            .chain_err(|| "Failed to load device descriptor db")?;
+        Self::load_vmstate(snapshot_desc_db, &mut state_reader)
+            .chain_err(|| "Failed to load migration device state")?;
+        Self::resume()?;
+
+        stream
+            .write_all(&[MIGRATION_ACK])
+            .chain_err(|| "Failed to reply to source")?;
+
+        // Set status to `Completed`
+        MigrationManager::set_status(MigrationStatus::Completed)?;
+
+        Ok(())
+    }
+
+    /// Send all ram, then send dirty memory until it converges or iterations
+    /// exceed the limit. The last dirty memory is sent after VM paused.
+    fn send_memory_iteratively<T, F>(stream: &mut T, pause_vm: F, paused: &mut bool) -> Result<()>
+    where
+        T: Read + Write,
+        F: FnOnce() -> bool,
+    {
+        Self::send_memory(stream, true)?;
+        for _ in 0..MAX_DIRTY_ITERATIONS {
+            if Self::send_memory(stream, false)? < DIRTY_MEMORY_THRESHOLD {
+                break;
+            }
+        }
+
+        // VM may be paused partly even if pausing fails, so it must be resumed anyway.
+        *paused = true;
+        if !pause_vm() {
+            bail!("Failed to pause VM for migration");
+        }
+        Self::send_memory(stream, false)?;
+        stream
+            .write_all(MemBlock::default().as_bytes())
+            .chain_err(|| "Failed to send end of memory")?;
+
+        Ok(())
+    }
+
+    /// Send memory blocks to `Write` trait object, returns the length of memory sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The `Write` trait object.
+    /// * `full` - Send all ram if `true`, otherwise only send dirty memory.
+    fn send_memory(writer: &mut dyn Write, full: bool) -> Result<u64> {
+        let mut sent = 0_u64;
+        for (_, entry) in MIGRATION_MANAGER.entry.read().unwrap().iter() {
+            if let MigrationEntry::Memory(i) = entry {
+                for (addr, len) in i.get_dirty_ranges(full)? {
+                    writer
+                        .write_all(MemBlock { addr, len }.as_bytes())
+                        .chain_err(|| "Failed to send memory block header")?;
+                    i.save_memory_range(addr, len, writer)
+                        .chain_err(|| ErrorKind::SaveVmMemoryErr(format!("addr 0x{:X}", addr)))?;
+                    sent += len;
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Receive memory blocks from `Read` trait object until the end block.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The `Read` trait object.
+    fn recv_memory(reader: &mut dyn Read) -> Result<()> {
+        let entry = MIGRATION_MANAGER.entry.read().unwrap();
+        let memory = entry
+            .values()
+            .find_map(|e| match e {
+                MigrationEntry::Memory(i) => Some(i),
+                _ => None,
+            })
+            .chain_err(|| "No memory instance registered")?;
+
+        let mut block_bytes = [0_u8; size_of::<MemBlock>()];
+        loop {
+            reader.read_exact(&mut block_bytes)?;
+            let block = *MemBlock::from_bytes(&block_bytes)
+                .ok_or(ErrorKind::FromBytesError("MEMORY_BLOCK"))?;
+            if block.len == 0 {
+                break;
+            }
+            memory
+                .load_memory_range(block.addr, block.len, reader)
+                .chain_err(|| ErrorKind::RestoreVmMemoryErr)?;
+        }
+
+        Ok(())
+    }
+
+    /// Start logging dirty pages for all memory instances.
+    fn start_dirty_log() -> Result<()> {
+        for (_, entry) in MIGRATION_MANAGER.entry.read().unwrap().iter() {
+            if let MigrationEntry::Memory(i) = entry {
+                i.start_dirty_log()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stop logging dirty pages for all memory instances.
+    fn stop_dirty_log() -> Result<()> {
+        for (_, entry) in MIGRATION_MANAGER.entry.read().unwrap().iter() {
+            if let MigrationEntry::Memory(i) = entry {
+                i.stop_dirty_log()?;
+            }
+        }
+
+        Ok(())
+    }
+}
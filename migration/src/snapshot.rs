@@ -147,7 +147,7 @@ impl MigrationManager {
     ///
     /// * `file_format` - confirm snapshot file format.
     /// * `writer` - The `Write` trait object to write header message.
-    fn save_header(file_format: FileFormat, writer: &mut dyn Write) -> Result<()> {
+    pub(crate) fn save_header(file_format: FileFormat, writer: &mut dyn Write) -> Result<()> {
         let mut header = MigrationHeader::default();
         header.format = file_format;
         header.desc_len = match file_format {
@@ -159,7 +159,7 @@ impl MigrationManager {
 
         input_slice[0..size_of::<MigrationHeader>()].copy_from_slice(header_bytes);
         writer
-            .write(&input_slice)
+            .write_all(&input_slice)
             .chain_err(|| "Failed to save migration header")?;
 
         Ok(())
@@ -170,7 +170,7 @@ impl MigrationManager {
     /// # Arguments
     ///
     /// * `reader` - The `Read` trait object.
-    fn load_header(reader: &mut dyn Read) -> Result<MigrationHeader> {
+    pub(crate) fn load_header(reader: &mut dyn Read) -> Result<MigrationHeader> {
         let mut header_bytes = [0u8; size_of::<MigrationHeader>()];
         reader.read_exact(&mut header_bytes)?;
 
@@ -220,7 +220,7 @@ impl MigrationManager {
     /// # Arguments
     ///
     /// * `writer` - The `Write` trait object.
-    fn save_device_state(writer: &mut dyn Write) -> Result<()> {
+    pub(crate) fn save_device_state(writer: &mut dyn Write) -> Result<()> {
         for (device_id, entry) in MIGRATION_MANAGER.entry.read().unwrap().iter() {
             match entry {
                 MigrationEntry::Safe(i) => i.pre_save(*device_id, writer)?,
@@ -238,7 +238,7 @@ impl MigrationManager {
     ///
     /// * `snap_desc_db` - The snapshot descriptor hashmap read from snapshot file.
     /// * `reader` - The `Read` trait object.
-    fn load_vmstate(
+    pub(crate) fn load_vmstate(
         snap_desc_db: HashMap<u64, DeviceStateDesc>,
         reader: &mut dyn Read,
     ) -> Result<()> {
@@ -287,7 +287,7 @@ impl MigrationManager {
 
     /// Resume recovered device.
     /// This function will be called after restore device state.
-    fn resume() -> Result<()> {
+    pub(crate) fn resume() -> Result<()> {
         for (_, entry) in MIGRATION_MANAGER.entry.read().unwrap().iter() {
             if let MigrationEntry::Mutex(i) = entry {
                 i.lock().unwrap().resume()?
@@ -0,0 +1,222 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Live migration from source to destination in one process, the migration
+//! manager is global so that this test lives in its own test binary.
+
+#[macro_use]
+extern crate migration_derive;
+
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use kvm_ioctls::Kvm;
+use migration::errors::Result;
+use migration::{
+    DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, MigrationStatus, StateTransfer,
+};
+use util::byte_code::ByteCode;
+
+const MEMORY_SIZE: usize = 0x10_000;
+
+/// Guest memory of source and destination, `dirty` records the ranges written
+/// while dirty log is started.
+#[derive(Default)]
+struct TestMemory {
+    src: Mutex<Vec<u8>>,
+    dst: Mutex<Vec<u8>>,
+    dirty: Mutex<Vec<(u64, u64)>>,
+}
+
+impl TestMemory {
+    fn write_src(&self, addr: u64, data: &[u8]) {
+        let start = addr as usize;
+        self.src.lock().unwrap()[start..start + data.len()].copy_from_slice(data);
+        self.dirty.lock().unwrap().push((addr, data.len() as u64));
+    }
+}
+
+impl StateTransfer for TestMemory {
+    fn get_state_vec(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        !0
+    }
+}
+
+impl MigrationHook for TestMemory {
+    fn get_dirty_ranges(&self, full: bool) -> Result<Vec<(u64, u64)>> {
+        let mut dirty = self.dirty.lock().unwrap();
+        if full {
+            dirty.clear();
+            return Ok(vec![(0, MEMORY_SIZE as u64)]);
+        }
+        Ok(dirty.drain(..).collect())
+    }
+
+    fn save_memory_range(&self, addr: u64, len: u64, writer: &mut dyn Write) -> Result<()> {
+        let start = addr as usize;
+        writer.write_all(&self.src.lock().unwrap()[start..start + len as usize])?;
+        Ok(())
+    }
+
+    fn load_memory_range(&self, addr: u64, len: u64, reader: &mut dyn Read) -> Result<()> {
+        let start = addr as usize;
+        reader.read_exact(&mut self.dst.lock().unwrap()[start..start + len as usize])?;
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+struct TestDeviceState {
+    status: u32,
+    config: u64,
+}
+
+#[derive(Default)]
+struct TestDevice {
+    state: TestDeviceState,
+}
+
+impl StateTransfer for TestDevice {
+    fn get_state_vec(&self) -> Result<Vec<u8>> {
+        Ok(self.state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> Result<()> {
+        self.state = *TestDeviceState::from_bytes(state).unwrap();
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&TestDeviceState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for TestDevice {}
+
+/// Stream of source: records the data sent and replies with `ack`.
+struct SourceStream {
+    sent: Vec<u8>,
+    ack: u8,
+}
+
+impl Read for SourceStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        buf.fill(self.ack);
+        Ok(buf.len())
+    }
+}
+
+impl Write for SourceStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.sent.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Stream of destination: receives the data sent by source and records the reply.
+struct DestinationStream<'a> {
+    received: &'a [u8],
+    reply: Vec<u8>,
+}
+
+impl Read for DestinationStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.received.read(buf)
+    }
+}
+
+impl Write for DestinationStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.reply.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_live_migration() {
+    if Kvm::new().is_err() {
+        return;
+    }
+
+    let memory = Arc::new(TestMemory {
+        src: Mutex::new((0..MEMORY_SIZE).map(|i| i as u8).collect()),
+        dst: Mutex::new(vec![0_u8; MEMORY_SIZE]),
+        ..Default::default()
+    });
+    let device = Arc::new(Mutex::new(TestDevice::default()));
+    MigrationManager::register_memory_instance(memory.clone());
+    MigrationManager::register_device_instance_mutex(TestDeviceState::descriptor(), device.clone());
+
+    // Destination fails to load migration data, VM keeps running on source.
+    MigrationManager::set_status(MigrationStatus::Setup).unwrap();
+    let mut stream = SourceStream {
+        sent: Vec::new(),
+        ack: 0,
+    };
+    let mut resumed = None;
+    assert!(MigrationManager::send_migration(
+        &mut stream,
+        || true,
+        |failed| resumed = Some(failed)
+    )
+    .is_err());
+    assert_eq!(resumed, Some(true));
+    MigrationManager::set_status(MigrationStatus::Failed).unwrap();
+
+    // Memory and device state changed while pausing VM must be sent as well.
+    MigrationManager::set_status(MigrationStatus::Setup).unwrap();
+    let mut stream = SourceStream {
+        sent: Vec::new(),
+        ack: 1,
+    };
+    let pause_vm = || {
+        memory.write_src(0x8ff0, &[0x55; 0x20]);
+        device.lock().unwrap().state = TestDeviceState {
+            status: 0xf,
+            config: 0x1234_5678,
+        };
+        true
+    };
+    let mut resumed = None;
+    MigrationManager::send_migration(&mut stream, pause_vm, |failed| resumed = Some(failed))
+        .unwrap();
+    assert_eq!(resumed, Some(false));
+    assert_eq!(
+        MigrationManager::migration_get_status(),
+        MigrationStatus::Completed
+    );
+
+    device.lock().unwrap().state = TestDeviceState::default();
+    let mut stream = DestinationStream {
+        received: &stream.sent,
+        reply: Vec::new(),
+    };
+    MigrationManager::recv_migration(&mut stream).unwrap();
+    assert_eq!(stream.reply, vec![1]);
+    assert!(stream.received.is_empty());
+    assert_eq!(*memory.dst.lock().unwrap(), *memory.src.lock().unwrap());
+    let state = device.lock().unwrap().state;
+    assert_eq!(state.status, 0xf);
+    assert_eq!(state.config, 0x1234_5678);
+}
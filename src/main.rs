@@ -15,8 +15,11 @@ extern crate error_chain;
 #[macro_use]
 extern crate log;
 
-use std::io::Write;
+use std::io::{Read, Write};
+use std::net::TcpListener;
 use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
 use std::sync::{Arc, Mutex};
 
 use machine::{LightMachine, MachineOps, StdMachine};
@@ -25,14 +28,17 @@ use machine_manager::{
     config::MachineType,
     config::VmConfig,
     event_loop::EventLoop,
+    metrics::start_metrics_exporter,
     qmp::QmpChannel,
     signal_handler::{exit_with_code, register_kill_signal, VM_EXIT_GENE_ERR},
     socket::Socket,
     temp_cleaner::TempCleaner,
 };
-use util::loop_context::EventNotifierHelper;
+use migration::{MigrationManager, MigrationStatus};
+use util::loop_context::{EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation};
 use util::unix::{parse_uri, UnixPath};
 use util::{arg_parser, daemonize::daemonize, logger, set_termi_canon_mode};
+use vmm_sys_util::epoll::EventSet;
 
 error_chain! {
     links {
@@ -136,18 +142,30 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
     register_kill_signal();
 
     let listeners = check_api_channel(&cmd_args, vm_config)?;
+    let incoming = match cmd_args.value_of("incoming") {
+        Some(uri) => Some(parse_uri(&uri)?),
+        None => None,
+    };
+    // Memory and boot source will be restored from snapshot file directly, while
+    // live migration fills the memory of a normally realized VM.
+    let is_snapshot = matches!(incoming, Some((UnixPath::File, _)));
     let mut sockets = Vec::new();
     let vm: Arc<Mutex<dyn MachineOps + Send + Sync>> = match vm_config.machine_config.mach_type {
         MachineType::MicroVm => {
             let vm = Arc::new(Mutex::new(
                 LightMachine::new(&vm_config).chain_err(|| "Failed to init MicroVM")?,
             ));
-            MachineOps::realize(&vm, vm_config, cmd_args.is_present("incoming"))
+            MachineOps::realize(&vm, vm_config, is_snapshot)
                 .chain_err(|| "Failed to realize micro VM.")?;
             EventLoop::set_manager(vm.clone(), None);
+            if let Some(metrics) = &vm_config.metrics {
+                start_metrics_exporter(metrics, vm.clone())
+                    .chain_err(|| "Failed to start metrics exporter")?;
+            }
 
-            for listener in listeners {
-                sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
+            for (listener, mode) in listeners {
+                sockets
+                    .push(Socket::from_unix_listener(listener, Some(vm.clone())).with_mode(mode));
             }
             vm
         }
@@ -155,12 +173,17 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             let vm = Arc::new(Mutex::new(
                 StdMachine::new(&vm_config).chain_err(|| "Failed to init StandardVM")?,
             ));
-            MachineOps::realize(&vm, vm_config, cmd_args.is_present("incoming"))
+            MachineOps::realize(&vm, vm_config, is_snapshot)
                 .chain_err(|| "Failed to realize standard VM.")?;
             EventLoop::set_manager(vm.clone(), None);
+            if let Some(metrics) = &vm_config.metrics {
+                start_metrics_exporter(metrics, vm.clone())
+                    .chain_err(|| "Failed to start metrics exporter")?;
+            }
 
-            for listener in listeners {
-                sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
+            for (listener, mode) in listeners {
+                sockets
+                    .push(Socket::from_unix_listener(listener, Some(vm.clone())).with_mode(mode));
             }
             vm
         }
@@ -169,20 +192,20 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
                 StdMachine::new(&vm_config).chain_err(|| "Failed to init NoneVM")?,
             ));
             EventLoop::set_manager(vm.clone(), None);
-            for listener in listeners {
-                sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
+            if let Some(metrics) = &vm_config.metrics {
+                start_metrics_exporter(metrics, vm.clone())
+                    .chain_err(|| "Failed to start metrics exporter")?;
+            }
+            for (listener, mode) in listeners {
+                sockets
+                    .push(Socket::from_unix_listener(listener, Some(vm.clone())).with_mode(mode));
             }
             vm
         }
     };
 
-    if let Some(uri) = cmd_args.value_of("incoming") {
-        if let (UnixPath::File, path) = parse_uri(&uri)? {
-            migration::MigrationManager::restore_snapshot(&path)
-                .chain_err(|| "Failed to start with incoming migration.")?;
-        } else {
-            bail!("Unsupported incoming unix path type.")
-        }
+    if let Some((UnixPath::File, path)) = &incoming {
+        MigrationManager::restore_snapshot(path).chain_err(|| "Failed to restore snapshot.")?;
     }
 
     for socket in sockets {
@@ -193,19 +216,145 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
         .chain_err(|| "Failed to add api event to MainLoop")?;
     }
 
-    vm.lock()
-        .unwrap()
-        .run(cmd_args.is_present("freeze_cpu"))
-        .chain_err(|| "Failed to start VM.")?;
-
+    let freeze_cpu = cmd_args.is_present("freeze_cpu");
+    let seccomp = !cmd_args.is_present("disable-seccomp");
     let balloon_switch_on = vm_config.dev_name.get("balloon").is_some();
-    if !cmd_args.is_present("disable-seccomp") {
-        vm.lock()
-            .unwrap()
+    match incoming {
+        Some((path_type, path)) if path_type != UnixPath::File => {
+            // VM is started, and seccomp rules are registered, after the memory
+            // and device state from source are loaded.
+            start_incoming_migration(path_type, &path, move || {
+                start_vm(&vm, freeze_cpu, seccomp, balloon_switch_on)
+            })
+            .chain_err(|| "Failed to start with incoming migration.")?;
+        }
+        _ => start_vm(&vm, freeze_cpu, seccomp, balloon_switch_on)?,
+    }
+
+    EventLoop::loop_run().chain_err(|| "MainLoop exits unexpectedly: error occurs")?;
+    Ok(())
+}
+
+/// Start VM, then register seccomp rules if `seccomp` is `true`. Seccomp rules
+/// are registered after vcpu threads start, as starting vcpus needs syscalls
+/// not allowed by the rules.
+fn start_vm(
+    vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>,
+    freeze_cpu: bool,
+    seccomp: bool,
+    balloon_switch_on: bool,
+) -> Result<()> {
+    let locked_vm = vm.lock().unwrap();
+    locked_vm
+        .run(freeze_cpu)
+        .chain_err(|| "Failed to start VM.")?;
+    if seccomp {
+        locked_vm
             .register_seccomp(balloon_switch_on)
             .chain_err(|| "Failed to register seccomp rules.")?;
     }
+    Ok(())
+}
+
+/// The listener waiting for the source of live migration to connect.
+enum IncomingListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+trait IncomingStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> IncomingStream for T {}
+
+impl IncomingListener {
+    fn accept(&self) -> std::io::Result<Box<dyn IncomingStream>> {
+        let stream: Box<dyn IncomingStream> = match self {
+            IncomingListener::Tcp(listener) => Box::new(listener.accept()?.0),
+            IncomingListener::Unix(listener) => Box::new(listener.accept()?.0),
+        };
+        Ok(stream)
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            IncomingListener::Tcp(listener) => listener.as_raw_fd(),
+            IncomingListener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+/// Wait for the source of live migration to connect in main loop. Then the
+/// migration data is received and VM is started in the thread `migration`,
+/// so that QMP keeps working while waiting for and receiving migration data.
+///
+/// # Arguments
+///
+/// * `path_type` - The type of incoming uri, only `Tcp` and `Unix` are supported.
+/// * `path` - The address to listen on.
+/// * `start_vm` - Callback to start VM after migration data is loaded.
+fn start_incoming_migration<F>(path_type: UnixPath, path: &str, start_vm: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    let listener = match path_type {
+        UnixPath::Tcp => IncomingListener::Tcp(
+            TcpListener::bind(path).chain_err(|| format!("Failed to bind {}", path))?,
+        ),
+        UnixPath::Unix => {
+            let listener =
+                UnixListener::bind(path).chain_err(|| format!("Failed to bind {}", path))?;
+            TempCleaner::add_path(path.to_string());
+            IncomingListener::Unix(listener)
+        }
+        _ => bail!("Unsupported incoming unix path type."),
+    };
+
+    let listener_fd = listener.as_raw_fd();
+    let start_vm = Mutex::new(Some(start_vm));
+    let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+        let mut stream = match listener.accept() {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Failed to accept migration connection: {}", e);
+                return None;
+            }
+        };
+        let start_vm = start_vm.lock().unwrap().take()?;
+        let spawned = std::thread::Builder::new()
+            .name("migration".to_string())
+            .spawn(move || {
+                let ret = MigrationManager::recv_migration(&mut stream)
+                    .chain_err(|| "Failed to receive live migration.")
+                    .and_then(|_| start_vm());
+                if let Err(ref e) = ret {
+                    error!("{}", error_chain::ChainedError::display_chain(e));
+                    let _ = MigrationManager::set_status(MigrationStatus::Failed)
+                        .map_err(|e| error!("{}", e));
+                }
+            });
+        if let Err(e) = spawned {
+            error!("Failed to create migration thread: {}", e);
+        }
+
+        // Only one source is accepted.
+        Some(vec![EventNotifier::new(
+            NotifierOperation::Delete,
+            fd,
+            None,
+            EventSet::IN,
+            Vec::new(),
+        )])
+    });
+    #[allow(clippy::arc_with_non_send_sync)]
+    let notifier = EventNotifier::new(
+        NotifierOperation::AddShared,
+        listener_fd,
+        None,
+        EventSet::IN,
+        vec![Arc::new(Mutex::new(handler))],
+    );
+    EventLoop::update_event(vec![notifier], None)
+        .chain_err(|| "Failed to add incoming migration event to MainLoop")?;
 
-    EventLoop::loop_run().chain_err(|| "MainLoop exits unexpectedly: error occurs")?;
     Ok(())
 }
@@ -10,7 +10,10 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use super::Result;
+use std::os::unix::io::RawFd;
+
+use super::{AioCb, AioContext, AioEvent, Result};
+use crate::link_list::Node;
 use kvm_bindings::__IncompleteArrayField;
 
 pub const IOCB_FLAG_RESFD: u32 = 1;
@@ -42,7 +45,7 @@ pub struct IoCb {
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum IoCmd {
     Pread = 0,
     Pwrite = 1,
@@ -51,6 +54,10 @@ pub enum IoCmd {
     Noop = 6,
     Preadv = 7,
     Pwritev = 8,
+    /// Commands below are not submitted to kernel aio, only processed synchronously.
+    Discard = 9,
+    WriteZeroes = 10,
+    WriteZeroesUnmap = 11,
 }
 
 #[repr(C)]
@@ -74,6 +81,10 @@ pub struct EventResult {
 pub struct LibaioContext {
     pub ctx: *mut IoContext,
     pub max_size: i32,
+    /// The eventfd notified when io requests complete.
+    resfd: RawFd,
+    /// The completion events got from aio ring.
+    events: Vec<AioEvent>,
 }
 
 #[repr(C)]
@@ -93,7 +104,7 @@ pub struct AioRing {
 }
 
 impl LibaioContext {
-    pub fn new(max_size: i32) -> Result<Self> {
+    pub fn new(max_size: i32, resfd: RawFd) -> Result<Self> {
         let mut ctx = std::ptr::null_mut();
 
         let ret = unsafe { libc::syscall(libc::SYS_io_setup, max_size, &mut ctx) };
@@ -101,33 +112,72 @@ impl LibaioContext {
             bail!("Failed to setup aio context, return {}.", ret);
         }
 
-        Ok(LibaioContext { ctx, max_size })
+        Ok(LibaioContext {
+            ctx,
+            max_size,
+            resfd,
+            events: Vec::new(),
+        })
     }
+}
 
-    pub fn submit(&self, nr: i64, iocbp: &mut Vec<*mut IoCb>) -> Result<()> {
-        let ret = unsafe { libc::syscall(libc::SYS_io_submit, self.ctx, nr, iocbp.as_ptr()) };
+impl<T: Clone> AioContext<T> for LibaioContext {
+    fn submit(&mut self, nodes: &[*mut Node<AioCb<T>>]) -> Result<usize> {
+        let mut iocbs = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            // Safe because the node stays alive until its request is completed.
+            let cb = unsafe { &mut (**node).value };
+            let iocb = IoCb {
+                aio_lio_opcode: cb.opcode as u16,
+                aio_fildes: cb.file_fd as u32,
+                aio_buf: cb.iovec.as_ptr() as u64,
+                aio_nbytes: cb.iovec.len() as u64,
+                aio_offset: cb.offset as u64,
+                aio_flags: IOCB_FLAG_RESFD,
+                aio_resfd: self.resfd as u32,
+                data: *node as u64,
+                ..Default::default()
+            };
+            let iocb = Box::into_raw(Box::new(iocb));
+            cb.iocb = std::ptr::NonNull::new(iocb);
+            iocbs.push(iocb);
+        }
+
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_io_submit,
+                self.ctx,
+                iocbs.len() as i64,
+                iocbs.as_ptr(),
+            )
+        };
         if ret < 0 {
             bail!("Failed to submit aio, return {}.", ret);
         }
 
-        Ok(())
+        Ok(ret as usize)
     }
 
-    #[allow(clippy::zero_ptr)]
-    pub fn get_events(&self) -> (&[IoEvent], u32, u32) {
+    fn get_events(&mut self) -> &[AioEvent] {
         let ring = self.ctx as *mut AioRing;
         let head = unsafe { (*ring).head };
         let tail = unsafe { (*ring).tail };
         let ring_nr = unsafe { (*ring).nr };
-        let nr = if tail >= head {
-            tail - head
-        } else {
-            ring_nr - head
-        };
-        unsafe { (*ring).head = (head + nr) % ring_nr };
-
         let io_events: &[IoEvent] = unsafe { (*ring).io_events.as_slice(ring_nr as usize) };
 
-        (io_events, head, head + nr)
+        self.events.clear();
+        let mut index = head;
+        while index != tail {
+            let evt = &io_events[index as usize];
+            self.events.push(AioEvent {
+                user_data: evt.data,
+                status: evt.res2,
+                res: evt.res,
+            });
+            index = (index + 1) % ring_nr;
+        }
+        unsafe { (*ring).head = tail };
+
+        &self.events
     }
 }
@@ -12,30 +12,121 @@
 
 mod libaio;
 mod raw;
+mod threads;
+mod uring;
 
 use std::clone::Clone;
 use std::marker::{Send, Sync};
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use vmm_sys_util::eventfd::EventFd;
 
 use super::errors::Result;
 use super::link_list::{List, Node};
 pub use libaio::*;
 pub use raw::*;
+pub use threads::*;
+pub use uring::*;
 
 type CbList<T> = List<AioCb<T>>;
 type CbNode<T> = Node<AioCb<T>>;
 
 pub type AioCompleteFunc<T> = Box<dyn Fn(&AioCb<T>, i64) + Sync + Send>;
 
+/// Count of worker threads of the `threads` engine for each `Aio`.
+const AIO_WORKER_THREADS: usize = 4;
+
+/// Count of io requests submitted to async engines of all `Aio`s and not completed.
+static INFLIGHT_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Get the count of io requests submitted to async engines and not completed.
+pub fn inflight_requests() -> u64 {
+    INFLIGHT_REQUESTS.load(Ordering::SeqCst)
+}
+
+/// The engine to process io requests asynchronously.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AioEngine {
+    /// No async engine, io requests are processed synchronously.
+    Off,
+    /// Linux native aio, which requires the file opened with `O_DIRECT`.
+    Native,
+    /// Linux io_uring.
+    IoUring,
+    /// Thread pool, io requests are processed synchronously by worker threads.
+    Threads,
+}
+
+impl FromStr for AioEngine {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(AioEngine::Off),
+            "native" => Ok(AioEngine::Native),
+            "io_uring" => Ok(AioEngine::IoUring),
+            "threads" => Ok(AioEngine::Threads),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AioEngine {
+    /// Whether the io command can be submitted to this engine.
+    fn is_supported(self, opcode: IoCmd) -> bool {
+        match self {
+            AioEngine::Off => false,
+            AioEngine::Native => matches!(opcode, IoCmd::Preadv | IoCmd::Pwritev),
+            AioEngine::IoUring => matches!(
+                opcode,
+                IoCmd::Preadv | IoCmd::Pwritev | IoCmd::Fdsync | IoCmd::Discard
+            ),
+            AioEngine::Threads => matches!(
+                opcode,
+                IoCmd::Preadv
+                    | IoCmd::Pwritev
+                    | IoCmd::Fdsync
+                    | IoCmd::Discard
+                    | IoCmd::WriteZeroes
+                    | IoCmd::WriteZeroesUnmap
+            ),
+        }
+    }
+}
+
+/// The completion event of io request.
+#[derive(Default, Clone, Copy)]
+pub struct AioEvent {
+    /// Pointer to the node of the completed request.
+    pub user_data: u64,
+    /// Non-zero if the engine fails to process the request.
+    pub status: i64,
+    /// Result of the request, negative errno on failure.
+    pub res: i64,
+}
+
+/// The context of async io engine.
+pub trait AioContext<T: Clone> {
+    /// Submit the io requests held by the nodes, the nodes must stay alive
+    /// until their requests are completed. Returns the count of the submitted
+    /// requests, which are the first ones of `nodes`.
+    fn submit(&mut self, nodes: &[*mut Node<AioCb<T>>]) -> Result<usize>;
+    /// Get the completion events of io requests submitted before.
+    fn get_events(&mut self) -> &[AioEvent];
+}
+
 pub struct AioCb<T: Clone> {
     pub last_aio: bool,
     pub file_fd: RawFd,
     pub opcode: IoCmd,
     pub iovec: Vec<Iovec>,
     pub offset: usize,
+    /// Length of the range for discard and write zeroes commands.
+    pub nbytes: u64,
     pub process: bool,
     pub iocb: Option<std::ptr::NonNull<IoCb>>,
     pub iocompletecb: T,
@@ -49,6 +140,7 @@ impl<T: Clone> AioCb<T> {
             opcode: IoCmd::Noop,
             iovec: Vec::new(),
             offset: 0,
+            nbytes: 0,
             process: false,
             iocb: None,
             iocompletecb: cb,
@@ -57,7 +149,8 @@ impl<T: Clone> AioCb<T> {
 }
 
 pub struct Aio<T: Clone + 'static> {
-    pub ctx: Arc<LibaioContext>,
+    ctx: Option<Box<dyn AioContext<T>>>,
+    engine: AioEngine,
     pub fd: EventFd,
     pub aio_in_queue: CbList<T>,
     pub aio_in_flight: CbList<T>,
@@ -66,12 +159,14 @@ pub struct Aio<T: Clone + 'static> {
 }
 
 impl<T: Clone + 'static> Aio<T> {
-    pub fn new(func: Arc<AioCompleteFunc<T>>) -> Result<Self> {
+    pub fn new(func: Arc<AioCompleteFunc<T>>, engine: AioEngine) -> Result<Self> {
         let max_events = 128;
+        let fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
 
         Ok(Aio {
-            ctx: Arc::new(LibaioContext::new(max_events as i32)?),
-            fd: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            ctx: Self::new_context(engine, max_events, &fd)?,
+            engine,
+            fd,
             aio_in_queue: List::new(),
             aio_in_flight: List::new(),
             max_events,
@@ -79,24 +174,73 @@ impl<T: Clone + 'static> Aio<T> {
         })
     }
 
+    fn new_context(
+        engine: AioEngine,
+        max_events: usize,
+        fd: &EventFd,
+    ) -> Result<Option<Box<dyn AioContext<T>>>> {
+        let ctx: Option<Box<dyn AioContext<T>>> = match engine {
+            AioEngine::Off => None,
+            AioEngine::Native => Some(Box::new(LibaioContext::new(
+                max_events as i32,
+                fd.as_raw_fd(),
+            )?)),
+            AioEngine::IoUring => Some(Box::new(IoUringContext::new(
+                max_events as u32,
+                fd.as_raw_fd(),
+            )?)),
+            AioEngine::Threads => Some(Box::new(ThreadsContext::new(AIO_WORKER_THREADS, fd)?)),
+        };
+
+        Ok(ctx)
+    }
+
+    /// Get the engine used to process io requests.
+    pub fn engine(&self) -> AioEngine {
+        self.engine
+    }
+
+    /// Change the engine used to process io requests, it fails if there are io
+    /// requests not completed.
+    pub fn set_engine(&mut self, engine: AioEngine) -> Result<()> {
+        if engine == self.engine {
+            return Ok(());
+        }
+        if self.aio_in_queue.len != 0 || self.aio_in_flight.len != 0 {
+            bail!("Failed to change aio engine, there are requests in flight");
+        }
+
+        self.ctx = Self::new_context(engine, self.max_events, &self.fd)?;
+        self.engine = engine;
+        Ok(())
+    }
+
     pub fn handle(&mut self) -> Result<bool> {
-        let (evts, start, end) = self.ctx.get_events();
+        let ctx = match self.ctx.as_mut() {
+            Some(ctx) => ctx,
+            None => return Ok(false),
+        };
         let mut done = false;
-        for e in start..end {
-            if evts[e as usize].res2 == 0 {
-                unsafe {
-                    done = true;
-                    let node = evts[e as usize].data as *mut CbNode<T>;
-
-                    (self.complete_func)(&(*node).value, evts[e as usize].res);
-                    self.aio_in_flight.unlink(&(*node));
-
-                    // free mem
-                    if let Some(i) = (*node).value.iocb {
-                        libc::free((*node).value.iovec.as_ptr() as *mut libc::c_void);
-                        libc::free(i.as_ptr() as *mut libc::c_void);
-                    };
-                    libc::free(node as *mut libc::c_void);
+        for evt in ctx.get_events() {
+            // Safe because the node is allocated in `rw_aio` and is only freed here.
+            unsafe {
+                done = true;
+                let node = Box::from_raw(evt.user_data as *mut CbNode<T>);
+                let mut res = if evt.status == 0 { evt.res } else { -1 };
+                // Discard is only a hint, it succeeds if the file does not support it.
+                if matches!(node.value.opcode, IoCmd::Discard)
+                    && res == -i64::from(libc::EOPNOTSUPP)
+                {
+                    res = 0;
+                }
+
+                (self.complete_func)(&node.value, res);
+                self.aio_in_flight.unlink(&node);
+                INFLIGHT_REQUESTS.fetch_sub(1, Ordering::SeqCst);
+
+                // free mem
+                if let Some(iocb) = node.value.iocb {
+                    drop(Box::from_raw(iocb.as_ptr()));
                 }
             }
         }
@@ -105,27 +249,51 @@ impl<T: Clone + 'static> Aio<T> {
 
     fn process_list(&mut self) -> Result<()> {
         if self.aio_in_queue.len > 0 && self.aio_in_flight.len < self.max_events {
-            let mut iocbs = Vec::new();
+            let mut nodes = Vec::new();
 
             for _ in self.aio_in_flight.len..self.max_events {
                 match self.aio_in_queue.pop_tail() {
-                    Some(node) => {
-                        iocbs.push(node.value.iocb.unwrap().as_ptr());
+                    Some(mut node) => {
+                        nodes.push(&mut *node as *mut CbNode<T>);
                         self.aio_in_flight.add_head(node);
                     }
                     None => break,
                 }
             }
 
-            if !iocbs.is_empty() {
-                return self.ctx.submit(iocbs.len() as i64, &mut iocbs);
+            if !nodes.is_empty() {
+                if let Some(ctx) = self.ctx.as_mut() {
+                    let submitted = ctx.submit(&nodes).unwrap_or_else(|e| {
+                        error!("{}", error_chain::ChainedError::display_chain(&e));
+                        0
+                    });
+                    // The requests failed to be submitted are completed with error.
+                    for node in nodes[submitted..].iter() {
+                        // Safe because the node is allocated in `rw_aio` and is not submitted.
+                        unsafe {
+                            let node = Box::from_raw(*node);
+                            (self.complete_func)(&node.value, -i64::from(libc::EIO));
+                            self.aio_in_flight.unlink(&node);
+                            INFLIGHT_REQUESTS.fetch_sub(1, Ordering::SeqCst);
+                            if let Some(iocb) = node.value.iocb {
+                                drop(Box::from_raw(iocb.as_ptr()));
+                            }
+                        }
+                    }
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Submit the io request to the async engine, the request is processed
+    /// synchronously if it is not supported by the engine.
     pub fn rw_aio(&mut self, cb: AioCb<T>, sector_size: u64) -> Result<()> {
+        if !self.engine.is_supported(cb.opcode) {
+            return self.rw_sync(cb);
+        }
+
         let mut misaligned = false;
         for iov in cb.iovec.iter() {
             if iov.iov_base % sector_size != 0 || iov.iov_len % sector_size != 0 {
@@ -138,27 +306,9 @@ impl<T: Clone + 'static> Aio<T> {
         }
 
         let last_aio = cb.last_aio;
-        let opcode = cb.opcode;
-        let file_fd = cb.file_fd;
-        let iovec = (&*cb.iovec).as_ptr() as u64;
-        let sg_size = cb.iovec.len();
-        let offset = cb.offset;
-
-        let mut node = Box::new(Node::new(cb));
-        let iocb = IoCb {
-            aio_lio_opcode: opcode as u16,
-            aio_fildes: file_fd as u32,
-            aio_buf: iovec,
-            aio_nbytes: sg_size as u64,
-            aio_offset: offset as u64,
-            aio_flags: IOCB_FLAG_RESFD,
-            aio_resfd: self.fd.as_raw_fd() as u32,
-            data: (&mut (*node) as *mut CbNode<T>) as u64,
-            ..Default::default()
-        };
-        node.value.iocb = std::ptr::NonNull::new(Box::into_raw(Box::new(iocb)));
-
+        let node = Box::new(Node::new(cb));
         self.aio_in_queue.add_head(node);
+        INFLIGHT_REQUESTS.fetch_add(1, Ordering::SeqCst);
         if last_aio || self.aio_in_queue.len + self.aio_in_flight.len >= self.max_events {
             return self.process_list();
         }
@@ -167,6 +317,11 @@ impl<T: Clone + 'static> Aio<T> {
     }
 
     pub fn rw_sync(&mut self, cb: AioCb<T>) -> Result<()> {
+        // Submit the requests queued before, as no request follows the last one.
+        if cb.last_aio {
+            self.process_list()?;
+        }
+
         let ret = match cb.opcode {
             IoCmd::Preadv => {
                 let mut r = 0;
@@ -187,6 +342,10 @@ impl<T: Clone + 'static> Aio<T> {
                 r
             }
             IoCmd::Fdsync => raw_datasync(cb.file_fd)?,
+            IoCmd::Discard => raw_discard(cb.file_fd, cb.offset, cb.nbytes),
+            IoCmd::WriteZeroes => raw_write_zeroes(cb.file_fd, cb.offset, cb.nbytes, false),
+            IoCmd::WriteZeroesUnmap => raw_write_zeroes(cb.file_fd, cb.offset, cb.nbytes, true),
+            IoCmd::Noop => 0,
             _ => -1,
         };
         (self.complete_func)(&cb, ret);
@@ -195,6 +354,10 @@ impl<T: Clone + 'static> Aio<T> {
     }
 
     fn handle_misaligned_aio(&mut self, cb: AioCb<T>) -> Result<()> {
+        if cb.last_aio {
+            self.process_list()?;
+        }
+
         // Safe because we only get the host page size.
         let host_page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
         let mut ret = 0_i64;
@@ -256,3 +419,11 @@ impl<T: Clone + 'static> Aio<T> {
         Ok(())
     }
 }
+
+impl<T: Clone + 'static> Drop for Aio<T> {
+    fn drop(&mut self) {
+        // The requests not completed are abandoned with the engine.
+        let abandoned = (self.aio_in_queue.len + self.aio_in_flight.len) as u64;
+        INFLIGHT_REQUESTS.fetch_sub(abandoned, Ordering::SeqCst);
+    }
+}
@@ -11,9 +11,12 @@
 // See the Mulan PSL v2 for more details.
 
 use super::Result;
-use libc::{c_void, fdatasync, pread, pwrite};
+use libc::{c_void, fallocate, fdatasync, pread, pwrite};
 use std::os::unix::io::RawFd;
 
+/// Max length of the zero buffer written when fallocate is not supported.
+const MAX_ZERO_BUF_LEN: u64 = 1 << 20;
+
 pub fn raw_read(fd: RawFd, buf: u64, size: usize, offset: usize) -> Result<i64> {
     let ret = unsafe { pread(fd, buf as *mut c_void, size, offset as i64) as i64 };
     if ret < 0 {
@@ -40,3 +43,140 @@ pub fn raw_datasync(fd: RawFd) -> Result<i64> {
 
     Ok(ret)
 }
+
+/// Punch hole in the range of file to discard the data, returns negative errno
+/// on failure. Discard is only a hint, so it succeeds if the file does not support it.
+pub fn raw_discard(fd: RawFd, offset: usize, size: u64) -> i64 {
+    let ret = do_fallocate(
+        fd,
+        libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+        offset,
+        size,
+    );
+    if ret == -i64::from(libc::EOPNOTSUPP) {
+        return 0;
+    }
+    if ret < 0 {
+        error!("Failed to discard for {}, return {}.", fd, ret);
+    }
+
+    ret
+}
+
+/// Make the range of file read as zeros, returns negative errno on failure.
+///
+/// # Arguments
+///
+/// * `fd` - The file descriptor.
+/// * `offset` - Start offset of the range.
+/// * `size` - Length of the range.
+/// * `unmap` - Whether to free the space of the range.
+pub fn raw_write_zeroes(fd: RawFd, offset: usize, size: u64, unmap: bool) -> i64 {
+    let mut ret = -i64::from(libc::EOPNOTSUPP);
+    if unmap {
+        ret = do_fallocate(
+            fd,
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset,
+            size,
+        );
+    }
+    if ret == -i64::from(libc::EOPNOTSUPP) {
+        ret = do_fallocate(
+            fd,
+            libc::FALLOC_FL_ZERO_RANGE | libc::FALLOC_FL_KEEP_SIZE,
+            offset,
+            size,
+        );
+    }
+    if ret == -i64::from(libc::EOPNOTSUPP) {
+        ret = write_zero_buf(fd, offset, size);
+    }
+    if ret < 0 {
+        error!("Failed to write zeroes for {}, return {}.", fd, ret);
+    }
+
+    ret
+}
+
+fn do_fallocate(fd: RawFd, mode: i32, offset: usize, size: u64) -> i64 {
+    // Safe because fallocate only changes the file, and the return value is checked.
+    let ret = unsafe { fallocate(fd, mode, offset as i64, size as i64) };
+    if ret < 0 {
+        return -i64::from(
+            std::io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EIO),
+        );
+    }
+
+    0
+}
+
+fn write_zero_buf(fd: RawFd, offset: usize, size: u64) -> i64 {
+    let buf_len = std::cmp::min(size, MAX_ZERO_BUF_LEN) as usize;
+    // Safe because we only get the host page size.
+    let host_page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    // Buffer is aligned to host page size, as the file may be opened with O_DIRECT.
+    // Safe because the allocated memory is checked, initialized and freed below.
+    let buf = unsafe { libc::memalign(host_page_size, buf_len) };
+    if buf.is_null() {
+        return -i64::from(libc::ENOMEM);
+    }
+    unsafe { libc::memset(buf, 0, buf_len) };
+
+    let mut ret = 0;
+    let mut done = 0_u64;
+    while done < size {
+        let len = std::cmp::min(size - done, buf_len as u64) as usize;
+        match raw_write(fd, buf as u64, len, offset + done as usize) {
+            Ok(n) if n > 0 => done += n as u64,
+            _ => {
+                ret = -i64::from(libc::EIO);
+                break;
+            }
+        }
+    }
+    // Safe because the memory is allocated above and not used anymore.
+    unsafe { libc::free(buf) };
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+
+    #[test]
+    fn test_write_zeroes_discard() {
+        let path = "/tmp/test_raw_write_zeroes.img";
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        file.write_all_at(&[0xff_u8; 8192], 0).unwrap();
+
+        let mut buf = [0_u8; 8192];
+        assert_eq!(raw_write_zeroes(file.as_raw_fd(), 512, 1024, false), 0);
+        file.read_exact_at(&mut buf, 0).unwrap();
+        assert!(buf[..512].iter().all(|b| *b == 0xff));
+        assert!(buf[512..1536].iter().all(|b| *b == 0));
+        assert!(buf[1536..].iter().all(|b| *b == 0xff));
+
+        assert_eq!(raw_write_zeroes(file.as_raw_fd(), 4096, 4096, true), 0);
+        assert_eq!(raw_discard(file.as_raw_fd(), 0, 512), 0);
+        file.read_exact_at(&mut buf, 0).unwrap();
+        assert!(buf[1536..4096].iter().all(|b| *b == 0xff));
+        assert!(buf[4096..].iter().all(|b| *b == 0));
+        assert_eq!(file.metadata().unwrap().len(), 8192);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::os::unix::io::RawFd;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{Builder, JoinHandle};
+
+use vmm_sys_util::eventfd::EventFd;
+
+use super::{raw_discard, raw_write_zeroes, AioCb, AioContext, AioEvent, IoCmd, Iovec, Result};
+use crate::errors::ResultExt;
+use crate::link_list::Node;
+
+/// The io request handed to worker threads, it holds a copy of the request
+/// fields as the node is only accessed by the thread owning the `Aio`.
+struct ThreadsRequest {
+    user_data: u64,
+    fd: RawFd,
+    opcode: IoCmd,
+    iovec: Vec<Iovec>,
+    offset: usize,
+    nbytes: u64,
+}
+
+/// Get the negative errno of the last failed syscall.
+fn last_errno() -> i64 {
+    -i64::from(
+        std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO),
+    )
+}
+
+impl ThreadsRequest {
+    /// Process the request, returns the count of transferred bytes for read
+    /// and write, or negative errno on failure.
+    fn process(&self) -> i64 {
+        match self.opcode {
+            IoCmd::Preadv | IoCmd::Pwritev => {
+                let mut done = 0_i64;
+                let mut off = self.offset;
+                for iov in self.iovec.iter() {
+                    // Safe because the buffer of iovec stays alive until the
+                    // request is completed, and the result is checked.
+                    let ret = unsafe {
+                        if let IoCmd::Preadv = self.opcode {
+                            libc::pread(
+                                self.fd,
+                                iov.iov_base as *mut libc::c_void,
+                                iov.iov_len as usize,
+                                off as i64,
+                            )
+                        } else {
+                            libc::pwrite(
+                                self.fd,
+                                iov.iov_base as *const libc::c_void,
+                                iov.iov_len as usize,
+                                off as i64,
+                            )
+                        }
+                    };
+                    if ret < 0 {
+                        return last_errno();
+                    }
+                    done += ret as i64;
+                    // Stop at the end of file, as the later buffers can't be transferred.
+                    if ret as u64 != iov.iov_len {
+                        break;
+                    }
+                    off += iov.iov_len as usize;
+                }
+                done
+            }
+            IoCmd::Fdsync => {
+                // Safe because fdatasync only syncs the file, and the result is checked.
+                if unsafe { libc::fdatasync(self.fd) } < 0 {
+                    return last_errno();
+                }
+                0
+            }
+            IoCmd::Discard => raw_discard(self.fd, self.offset, self.nbytes),
+            IoCmd::WriteZeroes => raw_write_zeroes(self.fd, self.offset, self.nbytes, false),
+            IoCmd::WriteZeroesUnmap => raw_write_zeroes(self.fd, self.offset, self.nbytes, true),
+            _ => -i64::from(libc::EINVAL),
+        }
+    }
+}
+
+/// Context of the thread pool engine, io requests are processed by worker
+/// threads with blocking syscalls, and the completion of io requests is
+/// notified through the eventfd.
+pub struct ThreadsContext {
+    /// Send requests to worker threads, the workers exit when it's dropped.
+    sender: Option<Sender<ThreadsRequest>>,
+    workers: Vec<JoinHandle<()>>,
+    /// The completion events pushed by worker threads.
+    completed: Arc<Mutex<Vec<AioEvent>>>,
+    /// The completion events got by `get_events`.
+    events: Vec<AioEvent>,
+}
+
+impl ThreadsContext {
+    /// Create the context with `threads` worker threads.
+    ///
+    /// # Arguments
+    ///
+    /// * `threads` - Count of worker threads.
+    /// * `eventfd` - The eventfd notified when io requests complete.
+    pub fn new(threads: usize, eventfd: &EventFd) -> Result<Self> {
+        let (sender, receiver) = channel::<ThreadsRequest>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let completed = Arc::new(Mutex::new(Vec::new()));
+
+        let mut workers = Vec::with_capacity(threads);
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            let completed = completed.clone();
+            let eventfd = eventfd
+                .try_clone()
+                .chain_err(|| "Failed to clone eventfd for aio worker")?;
+            let worker = Builder::new()
+                .name(format!("aio-worker-{}", i))
+                .spawn(move || Self::worker_loop(&receiver, &completed, &eventfd))
+                .chain_err(|| "Failed to create aio worker thread")?;
+            workers.push(worker);
+        }
+
+        Ok(ThreadsContext {
+            sender: Some(sender),
+            workers,
+            completed,
+            events: Vec::new(),
+        })
+    }
+
+    fn worker_loop(
+        receiver: &Mutex<Receiver<ThreadsRequest>>,
+        completed: &Mutex<Vec<AioEvent>>,
+        eventfd: &EventFd,
+    ) {
+        loop {
+            // The lock is released once a request is received, so that other
+            // workers can receive the following requests.
+            let req = match receiver.lock().unwrap().recv() {
+                Ok(req) => req,
+                Err(_) => break,
+            };
+            let res = req.process();
+            completed.lock().unwrap().push(AioEvent {
+                user_data: req.user_data,
+                status: 0,
+                res,
+            });
+            if let Err(e) = eventfd.write(1) {
+                error!("Failed to notify the completion of aio request: {}", e);
+            }
+        }
+    }
+}
+
+impl<T: Clone> AioContext<T> for ThreadsContext {
+    fn submit(&mut self, nodes: &[*mut Node<AioCb<T>>]) -> Result<usize> {
+        let sender = match self.sender.as_ref() {
+            Some(sender) => sender,
+            None => bail!("No aio worker thread to process requests"),
+        };
+
+        for (i, node) in nodes.iter().enumerate() {
+            // Safe because the node stays alive until its request is completed.
+            let cb = unsafe { &(**node).value };
+            let req = ThreadsRequest {
+                user_data: *node as u64,
+                fd: cb.file_fd,
+                opcode: cb.opcode,
+                iovec: cb.iovec.clone(),
+                offset: cb.offset,
+                nbytes: cb.nbytes,
+            };
+            if sender.send(req).is_err() {
+                error!("Failed to send aio request to worker threads");
+                return Ok(i);
+            }
+        }
+
+        Ok(nodes.len())
+    }
+
+    fn get_events(&mut self) -> &[AioEvent] {
+        self.events.clear();
+        self.events.append(&mut self.completed.lock().unwrap());
+
+        &self.events
+    }
+}
+
+impl Drop for ThreadsContext {
+    fn drop(&mut self) {
+        // Wait for the requests in flight, so that no buffer is accessed after drop.
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("Failed to join aio worker thread");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+
+    #[test]
+    fn test_threads_rw() {
+        let evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut ctx = ThreadsContext::new(2, &evt).unwrap();
+
+        let path = format!("/tmp/test_threads_rw_{}.img", std::process::id());
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+
+        let data = vec![0x5a_u8; 4096];
+        let mut cb = AioCb::new(());
+        cb.file_fd = file.as_raw_fd();
+        cb.opcode = IoCmd::Pwritev;
+        cb.offset = 4096;
+        cb.iovec = vec![Iovec {
+            iov_base: data.as_ptr() as u64,
+            iov_len: data.len() as u64,
+        }];
+        let mut node = Box::new(Node::new(cb));
+        let node_ptr = &mut *node as *mut Node<AioCb<()>>;
+        assert_eq!(ctx.submit(&[node_ptr]).unwrap(), 1);
+
+        // Wait for the completion notified by the worker thread.
+        let mut notified = false;
+        for _ in 0..1000 {
+            if evt.read().is_ok() {
+                notified = true;
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert!(notified);
+
+        let events = AioContext::<()>::get_events(&mut ctx);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].user_data, node_ptr as u64);
+        assert_eq!(events[0].res, 4096);
+
+        let mut buf = vec![0_u8; 4096];
+        file.read_exact_at(&mut buf, 4096).unwrap();
+        assert_eq!(buf, data);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
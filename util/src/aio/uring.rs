@@ -0,0 +1,443 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::{AioCb, AioContext, AioEvent, IoCmd, Result};
+use crate::link_list::Node;
+
+/// See: https://elixir.bootlin.com/linux/v5.10/source/include/uapi/linux/io_uring.h
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+const IORING_OFF_SQES: i64 = 0x1000_0000;
+const IORING_REGISTER_EVENTFD: u32 = 4;
+const IORING_FSYNC_DATASYNC: u32 = 1 << 0;
+const IORING_OP_READV: u8 = 1;
+const IORING_OP_WRITEV: u8 = 2;
+const IORING_OP_FSYNC: u8 = 3;
+const IORING_OP_FALLOCATE: u8 = 17;
+
+#[repr(C)]
+#[derive(Default)]
+struct IoSqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct IoCqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct IoUringParams {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: IoSqringOffsets,
+    cq_off: IoCqringOffsets,
+}
+
+/// Submission queue entry.
+#[repr(C)]
+#[derive(Default)]
+struct IoUringSqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    pad: [u64; 2],
+}
+
+/// Completion queue entry.
+#[repr(C)]
+#[derive(Default)]
+struct IoUringCqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// The ring memory shared with kernel.
+struct RingMmap {
+    addr: *mut u8,
+    size: usize,
+}
+
+impl RingMmap {
+    fn new(fd: RawFd, size: usize, offset: i64) -> Result<Self> {
+        // Safe because the result is checked, and the mapping is unmapped on drop.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            bail!(
+                "Failed to mmap io_uring ring: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        Ok(RingMmap {
+            addr: addr as *mut u8,
+            size,
+        })
+    }
+
+    /// Get the pointer at `offset` of the ring.
+    fn ptr<T>(&self, offset: u32) -> *mut T {
+        // Safe because the offset is got from kernel and is within the ring.
+        unsafe { self.addr.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for RingMmap {
+    fn drop(&mut self) {
+        // Safe because the memory is mapped in `new` and is not used anymore.
+        unsafe { libc::munmap(self.addr as *mut libc::c_void, self.size) };
+    }
+}
+
+/// Context of Linux io_uring, the completion of io requests is notified
+/// through the registered eventfd.
+pub struct IoUringContext {
+    /// The io_uring file.
+    file: File,
+    /// The rings are only accessed through the pointers below, and are held
+    /// to be unmapped on drop.
+    _sq_ring: RingMmap,
+    _cq_ring: RingMmap,
+    sqes: RingMmap,
+    sq_entries: u32,
+    sq_mask: u32,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_array: *mut u32,
+    cq_mask: u32,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cqes: *const IoUringCqe,
+    /// The completion events got from completion queue.
+    events: Vec<AioEvent>,
+}
+
+impl IoUringContext {
+    /// Create io_uring with `entries` submission queue entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - Count of submission queue entries.
+    /// * `eventfd` - The eventfd notified when io requests complete.
+    pub fn new(entries: u32, eventfd: RawFd) -> Result<Self> {
+        let mut params = IoUringParams::default();
+        // Safe because params is valid and the result is checked.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut IoUringParams,
+            )
+        };
+        if fd < 0 {
+            bail!(
+                "Failed to setup io_uring: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        // Safe because fd is created above and only owned by the file.
+        let file = unsafe { File::from_raw_fd(fd as RawFd) };
+
+        let sq_ring_size =
+            params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let sq_ring = RingMmap::new(file.as_raw_fd(), sq_ring_size, IORING_OFF_SQ_RING)?;
+        let cq_ring_size =
+            params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<IoUringCqe>();
+        let cq_ring = RingMmap::new(file.as_raw_fd(), cq_ring_size, IORING_OFF_CQ_RING)?;
+        let sqes_size = params.sq_entries as usize * size_of::<IoUringSqe>();
+        let sqes = RingMmap::new(file.as_raw_fd(), sqes_size, IORING_OFF_SQES)?;
+
+        // Safe because the eventfd is valid and the result is checked.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_register,
+                file.as_raw_fd(),
+                IORING_REGISTER_EVENTFD,
+                &eventfd as *const RawFd,
+                1,
+            )
+        };
+        if ret < 0 {
+            bail!(
+                "Failed to register eventfd for io_uring: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        // Safe because the ring mask is within the ring.
+        let sq_mask = unsafe { *sq_ring.ptr::<u32>(params.sq_off.ring_mask) };
+        let cq_mask = unsafe { *cq_ring.ptr::<u32>(params.cq_off.ring_mask) };
+        Ok(IoUringContext {
+            sq_entries: params.sq_entries,
+            sq_mask,
+            sq_head: sq_ring.ptr(params.sq_off.head),
+            sq_tail: sq_ring.ptr(params.sq_off.tail),
+            sq_array: sq_ring.ptr(params.sq_off.array),
+            cq_mask,
+            cq_head: cq_ring.ptr(params.cq_off.head),
+            cq_tail: cq_ring.ptr(params.cq_off.tail),
+            cqes: cq_ring.ptr(params.cq_off.cqes),
+            file,
+            _sq_ring: sq_ring,
+            _cq_ring: cq_ring,
+            sqes,
+            events: Vec::new(),
+        })
+    }
+
+    fn fill_sqe<T: Clone>(sqe: &mut IoUringSqe, cb: &AioCb<T>) -> Result<()> {
+        *sqe = IoUringSqe {
+            fd: cb.file_fd,
+            off: cb.offset as u64,
+            ..Default::default()
+        };
+        match cb.opcode {
+            IoCmd::Preadv | IoCmd::Pwritev => {
+                sqe.opcode = if let IoCmd::Preadv = cb.opcode {
+                    IORING_OP_READV
+                } else {
+                    IORING_OP_WRITEV
+                };
+                sqe.addr = cb.iovec.as_ptr() as u64;
+                sqe.len = cb.iovec.len() as u32;
+            }
+            IoCmd::Fdsync => {
+                sqe.opcode = IORING_OP_FSYNC;
+                sqe.rw_flags = IORING_FSYNC_DATASYNC;
+            }
+            IoCmd::Discard => {
+                // For fallocate, the length is passed in addr and the mode in len.
+                sqe.opcode = IORING_OP_FALLOCATE;
+                sqe.addr = cb.nbytes;
+                sqe.len = (libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE) as u32;
+            }
+            _ => bail!("Unsupported io command for io_uring"),
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Clone> AioContext<T> for IoUringContext {
+    fn submit(&mut self, nodes: &[*mut Node<AioCb<T>>]) -> Result<usize> {
+        // Safe because the ring pointers are within the mapped rings. Only this
+        // thread writes the tail of submission queue.
+        let tail = unsafe { (*self.sq_tail).load(Ordering::Relaxed) };
+        let head = unsafe { (*self.sq_head).load(Ordering::Acquire) };
+        if nodes.len() as u32 > self.sq_entries - tail.wrapping_sub(head) {
+            bail!("No enough io_uring entries for {} requests", nodes.len());
+        }
+
+        let sqes = self.sqes.ptr::<IoUringSqe>(0);
+        for (i, node) in nodes.iter().enumerate() {
+            let index = tail.wrapping_add(i as u32) & self.sq_mask;
+            // Safe because the index is masked, and the node stays alive until
+            // its request is completed.
+            unsafe {
+                let sqe = &mut *sqes.add(index as usize);
+                Self::fill_sqe(sqe, &(**node).value)?;
+                sqe.user_data = *node as u64;
+                *self.sq_array.add(index as usize) = index;
+            }
+        }
+        unsafe { (*self.sq_tail).store(tail.wrapping_add(nodes.len() as u32), Ordering::Release) };
+
+        // The kernel may consume part of the entries, submit the rest again.
+        let mut submitted = 0;
+        while submitted < nodes.len() {
+            // Safe because the fd is valid and the result is checked.
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.file.as_raw_fd(),
+                    (nodes.len() - submitted) as u32,
+                    0_u32,
+                    0_u32,
+                    std::ptr::null::<libc::sigset_t>(),
+                    0_usize,
+                )
+            };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("Failed to submit io_uring requests: {}", err);
+                break;
+            }
+            if ret == 0 {
+                break;
+            }
+            submitted += ret as usize;
+        }
+        if submitted < nodes.len() {
+            // Remove the entries not consumed from submission queue. It's safe as the
+            // kernel only reads the queue in `io_uring_enter` without SQPOLL.
+            unsafe {
+                (*self.sq_tail).store(tail.wrapping_add(submitted as u32), Ordering::Release)
+            };
+        }
+
+        Ok(submitted)
+    }
+
+    fn get_events(&mut self) -> &[AioEvent] {
+        self.events.clear();
+        // Safe because the ring pointers are within the mapped rings. Only this
+        // thread writes the head of completion queue.
+        let mut head = unsafe { (*self.cq_head).load(Ordering::Relaxed) };
+        let tail = unsafe { (*self.cq_tail).load(Ordering::Acquire) };
+        while head != tail {
+            let cqe = unsafe { &*self.cqes.add((head & self.cq_mask) as usize) };
+            self.events.push(AioEvent {
+                user_data: cqe.user_data,
+                status: 0,
+                res: i64::from(cqe.res),
+            });
+            head = head.wrapping_add(1);
+        }
+        unsafe { (*self.cq_head).store(head, Ordering::Release) };
+
+        &self.events
+    }
+}
+
+impl IoUringContext {
+    /// Wait until at least `min_complete` requests complete.
+    #[cfg(test)]
+    fn wait(&self, min_complete: u32) -> Result<()> {
+        const IORING_ENTER_GETEVENTS: u32 = 1 << 0;
+        // Safe because the fd is valid and the result is checked.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.file.as_raw_fd(),
+                0_u32,
+                min_complete,
+                IORING_ENTER_GETEVENTS,
+                std::ptr::null::<libc::sigset_t>(),
+                0_usize,
+            )
+        };
+        if ret < 0 {
+            bail!(
+                "Failed to wait io_uring requests: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::FileExt;
+
+    use vmm_sys_util::eventfd::EventFd;
+
+    use super::super::Iovec;
+    use super::*;
+
+    #[test]
+    fn test_io_uring_rw() {
+        let evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let mut ctx = match IoUringContext::new(16, evt.as_raw_fd()) {
+            Ok(ctx) => ctx,
+            // io_uring may be not supported by the host kernel.
+            Err(_) => return,
+        };
+
+        let path = "/tmp/test_io_uring_rw.img";
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+
+        let data = vec![0x5a_u8; 4096];
+        let mut cb = AioCb::new(());
+        cb.file_fd = file.as_raw_fd();
+        cb.opcode = IoCmd::Pwritev;
+        cb.offset = 4096;
+        cb.iovec = vec![Iovec {
+            iov_base: data.as_ptr() as u64,
+            iov_len: data.len() as u64,
+        }];
+        let mut node = Box::new(Node::new(cb));
+        let node_ptr = &mut *node as *mut Node<AioCb<()>>;
+        assert_eq!(ctx.submit(&[node_ptr]).unwrap(), 1);
+        ctx.wait(1).unwrap();
+        assert!(evt.read().unwrap() >= 1);
+
+        let events = AioContext::<()>::get_events(&mut ctx);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].user_data, node_ptr as u64);
+        assert_eq!(events[0].res, 4096);
+
+        let mut buf = vec![0_u8; 4096];
+        file.read_exact_at(&mut buf, 4096).unwrap();
+        assert_eq!(buf, data);
+        std::fs::remove_file(path).unwrap();
+    }
+}
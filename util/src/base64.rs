@@ -0,0 +1,112 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Standard base64 encoding with padding, as defined in RFC 4648.
+
+use crate::errors::Result;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const PAD: u8 = b'=';
+
+/// Encode `data` to base64 string.
+pub fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len() / 3 * 4 + 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let indexes = [
+            bytes[0] >> 2,
+            (bytes[0] & 0x3) << 4 | bytes[1] >> 4,
+            (bytes[1] & 0xf) << 2 | bytes[2] >> 6,
+            bytes[2] & 0x3f,
+        ];
+        for (i, index) in indexes.iter().enumerate() {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[*index as usize] as char);
+            } else {
+                encoded.push(PAD as char);
+            }
+        }
+    }
+    encoded
+}
+
+fn decode_char(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Decode base64 string `data`. Whitespaces are ignored.
+pub fn decode(data: &str) -> Result<Vec<u8>> {
+    let input: Vec<u8> = data.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    if input.len() & 0x3 != 0 {
+        bail!("Invalid length {} of base64 data", input.len());
+    }
+
+    let mut decoded = Vec::with_capacity(input.len() / 4 * 3);
+    let chunk_num = input.len() / 4;
+    for (i, chunk) in input.chunks(4).enumerate() {
+        let pad_num = chunk.iter().rev().take_while(|c| **c == PAD).count();
+        if pad_num > 2 || (pad_num > 0 && i != chunk_num - 1) {
+            bail!("Invalid padding of base64 data");
+        }
+        let mut value: u32 = 0;
+        for c in &chunk[..4 - pad_num] {
+            match decode_char(*c) {
+                Some(bits) => value = value << 6 | u32::from(bits),
+                None => bail!("Invalid character {:?} in base64 data", *c as char),
+            }
+        }
+        value <<= 6 * pad_num as u32;
+        let bytes = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+        decoded.extend_from_slice(&bytes[..3 - pad_num]);
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        let cases: [(&[u8], &str); 7] = [
+            (b"", ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+        ];
+        for (data, encoded) in cases.iter() {
+            assert_eq!(encode(data), *encoded);
+            assert_eq!(decode(encoded).unwrap(), data.to_vec());
+        }
+        assert_eq!(encode(&[0xff, 0xfe, 0x00]), "//4A");
+        assert_eq!(decode("Zm9v\nYmFy").unwrap(), b"foobar".to_vec());
+
+        assert!(decode("Zm9").is_err());
+        assert!(decode("Zm9*").is_err());
+        assert!(decode("Z===").is_err());
+        assert!(decode("Zg==Zm9v").is_err());
+    }
+}
@@ -20,6 +20,7 @@ extern crate log;
 
 pub mod aio;
 pub mod arg_parser;
+pub mod base64;
 pub mod bitmap;
 pub mod byte_code;
 pub mod checksum;
@@ -33,6 +34,7 @@ pub mod num_ops;
 pub mod reader;
 pub mod seccomp;
 pub mod tap;
+pub mod throttle;
 pub mod unix;
 #[macro_use]
 pub mod logger;
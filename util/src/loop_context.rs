@@ -13,12 +13,14 @@
 extern crate vmm_sys_util;
 
 use std::collections::BTreeMap;
-use std::os::unix::io::RawFd;
-use std::sync::{Arc, Mutex, RwLock};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
 use libc::{c_void, read};
 use vmm_sys_util::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
+use vmm_sys_util::eventfd::EventFd;
 
 use crate::errors::{ErrorKind, Result, ResultExt};
 
@@ -128,11 +130,109 @@ impl Timer {
     pub fn new(func: Box<dyn Fn()>, nsec: u64) -> Self {
         Timer {
             func,
-            expire_time: Instant::now() + Duration::new(0, nsec as u32),
+            expire_time: Instant::now() + Duration::from_nanos(nsec),
         }
     }
 }
 
+/// Statistics of the iterations of `EventLoopContext`, the latency of an
+/// iteration is the time spent handling events and timers after epoll wakes up.
+#[derive(Default)]
+pub struct EventLoopStats {
+    /// Number of iterations.
+    iterations: AtomicU64,
+    /// Total latency of iterations in nanoseconds.
+    total_latency_ns: AtomicU64,
+    /// Maximum latency of an iteration in nanoseconds.
+    max_latency_ns: AtomicU64,
+}
+
+impl EventLoopStats {
+    fn account(&self, latency: Duration) {
+        let latency_ns = latency.as_nanos() as u64;
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+        self.total_latency_ns
+            .fetch_add(latency_ns, Ordering::Relaxed);
+        self.max_latency_ns.fetch_max(latency_ns, Ordering::Relaxed);
+    }
+
+    pub fn iterations(&self) -> u64 {
+        self.iterations.load(Ordering::Relaxed)
+    }
+
+    pub fn total_latency_ns(&self) -> u64 {
+        self.total_latency_ns.load(Ordering::Relaxed)
+    }
+
+    pub fn max_latency_ns(&self) -> u64 {
+        self.max_latency_ns.load(Ordering::Relaxed)
+    }
+}
+
+/// Parks the thread running an `EventLoopContext` between two iterations on
+/// request, so that no event handlers run until it is resumed.
+pub struct LoopPauser {
+    /// Fast path to check whether parking is requested.
+    requested: AtomicBool,
+    /// Whether the loop thread is parked.
+    parked: Mutex<bool>,
+    cond: Condvar,
+    /// Eventfd to wake up the loop thread from `epoll_wait`.
+    kick: EventFd,
+}
+
+impl LoopPauser {
+    fn new() -> Self {
+        LoopPauser {
+            requested: AtomicBool::new(false),
+            parked: Mutex::new(false),
+            cond: Condvar::new(),
+            kick: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        }
+    }
+
+    /// Request the loop thread to park, it returns without waiting.
+    pub fn request(&self) {
+        let _parked = self.parked.lock().unwrap();
+        self.requested.store(true, Ordering::SeqCst);
+        if let Err(e) = self.kick.write(1) {
+            error!("Failed to kick event loop: {}", e);
+        }
+    }
+
+    /// Wait until the loop thread is parked, returns `false` on timeout.
+    pub fn wait_parked(&self, timeout: Duration) -> bool {
+        let parked = self.parked.lock().unwrap();
+        let (parked, _) = self
+            .cond
+            .wait_timeout_while(parked, timeout, |parked| {
+                !*parked && self.requested.load(Ordering::SeqCst)
+            })
+            .unwrap();
+        *parked
+    }
+
+    /// Let the loop thread continue running.
+    pub fn resume(&self) {
+        let _parked = self.parked.lock().unwrap();
+        self.requested.store(false, Ordering::SeqCst);
+        self.cond.notify_all();
+    }
+
+    fn park_if_requested(&self) {
+        if !self.requested.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut parked = self.parked.lock().unwrap();
+        *parked = true;
+        self.cond.notify_all();
+        while self.requested.load(Ordering::SeqCst) {
+            parked = self.cond.wait(parked).unwrap();
+        }
+        *parked = false;
+    }
+}
+
 /// Epoll Loop Context
 #[allow(clippy::vec_box)]
 pub struct EventLoopContext {
@@ -148,6 +248,10 @@ pub struct EventLoopContext {
     ready_events: Vec<EpollEvent>,
     /// Timer list
     timers: Vec<Timer>,
+    /// Statistics of loop iterations.
+    stats: Arc<EventLoopStats>,
+    /// Parks the loop thread on request.
+    pauser: Arc<LoopPauser>,
 }
 
 unsafe impl Sync for EventLoopContext {}
@@ -156,14 +260,41 @@ unsafe impl Send for EventLoopContext {}
 impl EventLoopContext {
     /// Constructs a new `EventLoopContext`.
     pub fn new() -> Self {
-        EventLoopContext {
+        let mut ctx = EventLoopContext {
             epoll: Epoll::new().unwrap(),
             manager: None,
             events: Arc::new(RwLock::new(BTreeMap::new())),
             gc: Arc::new(RwLock::new(Vec::new())),
             ready_events: vec![EpollEvent::default(); READY_EVENT_MAX],
             timers: Vec::new(),
-        }
+            stats: Arc::new(EventLoopStats::default()),
+            pauser: Arc::new(LoopPauser::new()),
+        };
+
+        let handler: Box<NotifierCallback> = Box::new(|_, fd: RawFd| {
+            read_fd(fd);
+            None
+        });
+        #[allow(clippy::arc_with_non_send_sync)]
+        let kick = EventNotifier::new(
+            NotifierOperation::AddShared,
+            ctx.pauser.kick.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![Arc::new(Mutex::new(handler))],
+        );
+        ctx.add_event(kick).unwrap();
+        ctx
+    }
+
+    /// Get the statistics of loop iterations.
+    pub fn stats(&self) -> Arc<EventLoopStats> {
+        self.stats.clone()
+    }
+
+    /// Get the pauser to park the loop thread.
+    pub fn pauser(&self) -> Arc<LoopPauser> {
+        self.pauser.clone()
     }
 
     pub fn set_manager(&mut self, manager: Arc<Mutex<dyn EventLoopManager>>) {
@@ -335,6 +466,7 @@ impl EventLoopContext {
 
     /// Executes `epoll.wait()` to wait for events, and call the responding callbacks.
     pub fn run(&mut self) -> Result<bool> {
+        self.pauser.park_if_requested();
         if let Some(manager) = &self.manager {
             if manager.lock().unwrap().loop_should_exit() {
                 manager.lock().unwrap().loop_cleanup()?;
@@ -346,6 +478,7 @@ impl EventLoopContext {
     }
 
     pub fn iothread_run(&mut self) -> Result<bool> {
+        self.pauser.park_if_requested();
         if let Some(manager) = &self.manager {
             if manager.lock().unwrap().loop_should_exit() {
                 manager.lock().unwrap().loop_cleanup()?;
@@ -425,10 +558,13 @@ impl EventLoopContext {
             }
 
             expired_nr += 1;
-            (timer.func)();
         }
 
-        self.timers.drain(0..expired_nr);
+        // Take the expired timers out first, because their functions may add new timers.
+        let expired_timers: Vec<Timer> = self.timers.drain(0..expired_nr).collect();
+        for timer in expired_timers {
+            (timer.func)();
+        }
     }
 
     fn epoll_wait_manager(&mut self, time_out: i32) -> Result<bool> {
@@ -440,6 +576,7 @@ impl EventLoopContext {
             Err(e) if e.raw_os_error() == Some(libc::EINTR) => 0,
             Err(e) => return Err(ErrorKind::EpollWait(e).into()),
         };
+        let start = Instant::now();
 
         for i in 0..ev_count {
             // It`s safe because elements in self.events_map never get released in other functions
@@ -449,8 +586,8 @@ impl EventLoopContext {
             };
             if let EventStatus::Alive = event.status {
                 let mut notifiers = Vec::new();
-                for i in 0..event.handlers.len() {
-                    let handle = event.handlers[i].lock().unwrap();
+                for j in 0..event.handlers.len() {
+                    let handle = event.handlers[j].lock().unwrap();
                     match handle(self.ready_events[i].event_set(), event.raw_fd) {
                         None => {}
                         Some(mut notifier) => {
@@ -464,6 +601,7 @@ impl EventLoopContext {
 
         self.run_timers();
         self.clear_gc();
+        self.stats.account(start.elapsed());
         Ok(true)
     }
 }
@@ -732,6 +870,46 @@ mod test {
         assert!(mainloop.update_events(vec![event1]).is_ok());
     }
 
+    #[test]
+    fn loop_stats_test() {
+        let mut mainloop = EventLoopContext::new();
+        let fd = EventFd::new(EFD_NONBLOCK).unwrap();
+        let event = EventNotifier::new(
+            NotifierOperation::AddShared,
+            fd.as_raw_fd(),
+            None,
+            EventSet::OUT,
+            Vec::new(),
+        );
+        mainloop.update_events(vec![event]).unwrap();
+        let stats = mainloop.stats();
+        assert_eq!(stats.iterations(), 0);
+
+        mainloop.run().unwrap();
+        mainloop.run().unwrap();
+        assert_eq!(stats.iterations(), 2);
+        assert!(stats.max_latency_ns() <= stats.total_latency_ns());
+    }
+
+    #[test]
+    fn loop_pause_test() {
+        let mut mainloop = EventLoopContext::new();
+        let pauser = mainloop.pauser();
+        let stats = mainloop.stats();
+
+        pauser.request();
+        let handle = std::thread::spawn(move || {
+            mainloop.run().unwrap();
+        });
+        assert!(pauser.wait_parked(Duration::from_secs(5)));
+        assert_eq!(stats.iterations(), 0);
+
+        // The loop is woken up by the kick eventfd after resumed.
+        pauser.resume();
+        handle.join().unwrap();
+        assert_eq!(stats.iterations(), 1);
+    }
+
     #[test]
     fn fd_released_test() {
         let mut mainloop = EventLoopContext::new();
@@ -258,6 +258,19 @@ fn bpf_jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
     SockFilter { code, jt, jf, k }
 }
 
+/// Create a bpf_filter to compare the argument loaded, which goes on if the
+/// argument is matched, otherwise skips `skip` bpf_filters.
+fn constraint_jump(cmp: SeccompCmpOpt, args_value: u32, skip: u8) -> SockFilter {
+    match cmp {
+        SeccompCmpOpt::Eq => bpf_jump(BPF_JMP + BPF_JEQ + BPF_K, args_value, 0, skip),
+        SeccompCmpOpt::Ne => bpf_jump(BPF_JMP + BPF_JEQ + BPF_K, args_value, skip, 0),
+        SeccompCmpOpt::Ge => bpf_jump(BPF_JMP + BPF_JGE + BPF_K, args_value, 0, skip),
+        SeccompCmpOpt::Gt => bpf_jump(BPF_JMP + BPF_JGT + BPF_K, args_value, 0, skip),
+        SeccompCmpOpt::Le => bpf_jump(BPF_JMP + BPF_JGE + BPF_K, args_value, skip, 0),
+        SeccompCmpOpt::Lt => bpf_jump(BPF_JMP + BPF_JGT + BPF_K, args_value, skip, 0),
+    }
+}
+
 /// Validate the syscall's arch is correct.
 fn validate_architecture() -> Vec<SockFilter> {
     vec![
@@ -320,14 +333,7 @@ impl BpfRule {
         let args_filter = bpf_stmt(BPF_LD + BPF_W + BPF_ABS, SeccompData::args(args_num));
 
         // Create a bpf_filter to limit args in syscall.
-        let constraint_filter = match cmp {
-            SeccompCmpOpt::Eq => bpf_jump(BPF_JMP + BPF_JEQ + BPF_K, args_value, 0, 1),
-            SeccompCmpOpt::Ne => bpf_jump(BPF_JMP + BPF_JEQ + BPF_K, args_value, 1, 0),
-            SeccompCmpOpt::Ge => bpf_jump(BPF_JMP + BPF_JGE + BPF_K, args_value, 0, 1),
-            SeccompCmpOpt::Gt => bpf_jump(BPF_JMP + BPF_JGT + BPF_K, args_value, 0, 1),
-            SeccompCmpOpt::Le => bpf_jump(BPF_JMP + BPF_JGE + BPF_K, args_value, 1, 0),
-            SeccompCmpOpt::Lt => bpf_jump(BPF_JMP + BPF_JGT + BPF_K, args_value, 1, 0),
-        };
+        let constraint_filter = constraint_jump(cmp, args_value, 1);
 
         self.append(&mut vec![
             args_filter,
@@ -337,6 +343,34 @@ impl BpfRule {
         self
     }
 
+    /// Allow a syscall if all the arguments limitations are met. Unlike
+    /// `add_constraint`, which allows the syscall if any of the limitations
+    /// is met, the limitations here are combined together.
+    ///
+    /// # Arguments
+    /// * `constraints` - The list of compare operator, index number and value
+    ///   of the arguments, same as `add_constraint`.
+    pub fn add_constraints(mut self, constraints: &[(SeccompCmpOpt, u32, u32)]) -> BpfRule {
+        if self.inner_rules.is_empty() {
+            self.tail_rule = bpf_stmt(BPF_LD + BPF_W + BPF_ABS, SeccompData::nr());
+        }
+
+        let mut bpf_filters = Vec::new();
+        for (index, (cmp, args_num, args_value)) in constraints.iter().enumerate() {
+            // Skip the rest filters of the group if the limitation is not met.
+            let skip = ((constraints.len() - index - 1) * 2 + 1) as u8;
+            bpf_filters.push(bpf_stmt(
+                BPF_LD + BPF_W + BPF_ABS,
+                SeccompData::args(*args_num),
+            ));
+            bpf_filters.push(constraint_jump(*cmp, *args_value, skip));
+        }
+        bpf_filters.push(bpf_stmt(BPF_RET + BPF_K, SECCOMP_RET_ALLOW));
+
+        self.append(&mut bpf_filters);
+        self
+    }
+
     /// Change `BpfRules` to a list of `SockFilter`. It will be used when
     /// seccomp taking effect.
     fn as_vec(&mut self) -> Vec<SockFilter> {
@@ -585,4 +619,58 @@ mod tests {
 
         assert_eq!(seccomp_filter.sock_filters, bpf_vec);
     }
+
+    #[test]
+    fn test_enable_syscall_constraints() {
+        // a list of bpf_filter to allow `read` of `1024` bytes from fd 3.
+        let bpf_vec = vec![
+            SockFilter {
+                code: 0x15,
+                jt: 0,
+                jf: 6,
+                k: libc::SYS_read as u32,
+            },
+            SockFilter {
+                code: 0x20,
+                jt: 0,
+                jf: 0,
+                k: 0x10,
+            },
+            SockFilter {
+                code: 0x15,
+                jt: 0,
+                jf: 3,
+                k: 3,
+            },
+            SockFilter {
+                code: 0x20,
+                jt: 0,
+                jf: 0,
+                k: 0x20,
+            },
+            SockFilter {
+                code: 0x15,
+                jt: 0,
+                jf: 1,
+                k: 1024,
+            },
+            SockFilter {
+                code: 0x06,
+                jt: 0,
+                jf: 0,
+                k: 0x7fff_0000,
+            },
+            SockFilter {
+                code: 0x20,
+                jt: 0,
+                jf: 0,
+                k: 0,
+            },
+        ];
+
+        let mut rule = BpfRule::new(libc::SYS_read)
+            .add_constraints(&[(SeccompCmpOpt::Eq, 0, 3), (SeccompCmpOpt::Eq, 2, 1024)]);
+
+        assert_eq!(rule.as_vec(), bpf_vec);
+    }
 }
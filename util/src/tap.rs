@@ -25,13 +25,20 @@ pub const TUN_F_UFO: u32 = 16;
 pub const TUN_F_VIRTIO: u32 = TUN_F_CSUM | TUN_F_TSO4 | TUN_F_TSO6 | TUN_F_UFO;
 
 const IFF_TAP: u16 = 0x02;
+const IFF_MULTI_QUEUE: u16 = 0x100;
+const IFF_ATTACH_QUEUE: u16 = 0x200;
+const IFF_DETACH_QUEUE: u16 = 0x400;
 const IFF_NO_PI: u16 = 0x1000;
 const IFF_VNET_HDR: u16 = 0x4000;
 const TUNTAP_PATH: &str = "/dev/net/tun";
+/// Size of `struct ifreq` in kernel.
+const IFREQ_SIZE: usize = 40;
 
 ioctl_iow_nr!(TUNSETIFF, 84, 202, ::std::os::raw::c_int);
+ioctl_ior_nr!(TUNGETIFF, 84, 210, ::std::os::raw::c_uint);
 ioctl_iow_nr!(TUNSETOFFLOAD, 84, 208, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETVNETHDRSZ, 84, 216, ::std::os::raw::c_int);
+ioctl_iow_nr!(TUNSETQUEUE, 84, 217, ::std::os::raw::c_int);
 
 #[repr(C)]
 pub struct IfReq {
@@ -44,7 +51,14 @@ pub struct Tap {
 }
 
 impl Tap {
-    pub fn new(name: Option<&str>, fd: Option<RawFd>) -> Result<Self> {
+    /// Open tap device with name or fd.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the tap device on host.
+    /// * `fd` - Fd of the tap device opened.
+    /// * `multi_queue` - Open the tap device with `IFF_MULTI_QUEUE`, each open creates a queue.
+    pub fn new(name: Option<&str>, fd: Option<RawFd>, multi_queue: bool) -> Result<Self> {
         let file;
 
         if let Some(name) = name {
@@ -56,9 +70,13 @@ impl Tap {
             let (left, _) = ifr_name.split_at_mut(name.len());
             left.copy_from_slice(name.as_bytes());
 
+            let mut ifr_flags = IFF_TAP | IFF_NO_PI | IFF_VNET_HDR;
+            if multi_queue {
+                ifr_flags |= IFF_MULTI_QUEUE;
+            }
             let mut if_req = IfReq {
                 ifr_name,
-                ifr_flags: IFF_TAP | IFF_NO_PI | IFF_VNET_HDR,
+                ifr_flags,
             };
 
             let file_ = OpenOptions::new()
@@ -68,7 +86,14 @@ impl Tap {
                 .open(TUNTAP_PATH)
                 .chain_err(|| format!("Open {} failed.", TUNTAP_PATH))?;
 
-            unsafe { ioctl_with_mut_ref(&file_, TUNSETIFF(), &mut if_req) };
+            let ret = unsafe { ioctl_with_mut_ref(&file_, TUNSETIFF(), &mut if_req) };
+            if ret < 0 {
+                return Err(format!(
+                    "Failed to set tap ifr flags, error is {}",
+                    std::io::Error::last_os_error()
+                )
+                .into());
+            }
 
             file = file_;
         } else if let Some(fd) = fd {
@@ -101,6 +126,46 @@ impl Tap {
         Ok(())
     }
 
+    /// Attach or detach the queue of multiqueue tap device.
+    pub fn set_queue(&self, enable: bool) -> Result<()> {
+        let mut if_req = IfReq {
+            ifr_name: [0_u8; 16],
+            ifr_flags: if enable {
+                IFF_ATTACH_QUEUE
+            } else {
+                IFF_DETACH_QUEUE
+            },
+        };
+        let ret = unsafe { ioctl_with_mut_ref(&self.file, TUNSETQUEUE(), &mut if_req) };
+        if ret < 0 {
+            return Err(format!(
+                "ioctl TUNSETQUEUE failed, error is {}",
+                std::io::Error::last_os_error()
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Get the name of the tap device on host.
+    pub fn get_ifname(&self) -> Result<String> {
+        // Kernel writes the whole `struct ifreq`.
+        let mut if_req = [0_u8; IFREQ_SIZE];
+        let ret = unsafe { ioctl_with_mut_ref(&self.file, TUNGETIFF(), &mut if_req) };
+        if ret < 0 {
+            return Err(format!(
+                "ioctl TUNGETIFF failed, error is {}",
+                std::io::Error::last_os_error()
+            )
+            .into());
+        }
+
+        let name = &if_req[..16];
+        let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        Ok(String::from_utf8_lossy(&name[..len]).to_string())
+    }
+
     pub fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
         self.file.read(buf)
     }
@@ -0,0 +1,395 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! I/O throttling with leaky buckets, which limits both bandwidth and operations
+//! per second, separately for read and write, and allows bursts.
+//!
+//! Each bucket leaks at the average rate `avg`. A request is allowed as long as
+//! the level of the bucket is within its size, so up to `max * max_length` units
+//! can be consumed at once after the bucket has been idle. While bursting, the
+//! rate is also limited to `max` by a second bucket.
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::loop_context::EventLoopContext;
+
+/// Nanoseconds per second.
+const NANOS_PER_SEC: f64 = 1_000_000_000.0;
+/// The bucket size is the amount of one tenth of a second if burst is not configured.
+const BUCKET_SIZE_DIVISOR: f64 = 10.0;
+/// Number of bucket types.
+pub const BUCKETS_COUNT: usize = 6;
+
+/// Types of the throttle buckets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BucketType {
+    BpsTotal = 0,
+    BpsRead,
+    BpsWrite,
+    IopsTotal,
+    IopsRead,
+    IopsWrite,
+}
+
+impl BucketType {
+    /// All bucket types, in the order of their index.
+    pub const ALL: [BucketType; BUCKETS_COUNT] = [
+        BucketType::BpsTotal,
+        BucketType::BpsRead,
+        BucketType::BpsWrite,
+        BucketType::IopsTotal,
+        BucketType::IopsRead,
+        BucketType::IopsWrite,
+    ];
+
+    /// Whether the bucket counts bytes rather than operations.
+    pub fn is_bps(self) -> bool {
+        matches!(
+            self,
+            BucketType::BpsTotal | BucketType::BpsRead | BucketType::BpsWrite
+        )
+    }
+
+    /// Whether the bucket accounts the requests of the direction.
+    fn accounts(self, is_write: bool) -> bool {
+        match self {
+            BucketType::BpsTotal | BucketType::IopsTotal => true,
+            BucketType::BpsRead | BucketType::IopsRead => !is_write,
+            BucketType::BpsWrite | BucketType::IopsWrite => is_write,
+        }
+    }
+}
+
+/// Limits of one throttle bucket, `0` means not limited.
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct BucketConfig {
+    /// Average units per second.
+    pub avg: u64,
+    /// Units per second allowed during a burst.
+    pub max: u64,
+    /// Seconds that a burst at `max` can last, `0` is the same as `1`.
+    pub max_length: u64,
+}
+
+impl BucketConfig {
+    fn burst_length(&self) -> u64 {
+        std::cmp::max(self.max_length, 1)
+    }
+}
+
+/// Limits of I/O throttling.
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ThrottleConfig {
+    /// Limits of each bucket, indexed by `BucketType`.
+    pub buckets: [BucketConfig; BUCKETS_COUNT],
+    /// Requests larger than this size are accounted as several operations, `0`
+    /// means each request is one operation.
+    pub iops_size: u64,
+}
+
+impl ThrottleConfig {
+    /// Get the limits of the bucket.
+    pub fn bucket(&self, bucket_type: BucketType) -> &BucketConfig {
+        &self.buckets[bucket_type as usize]
+    }
+
+    /// Get the mutable limits of the bucket.
+    pub fn bucket_mut(&mut self, bucket_type: BucketType) -> &mut BucketConfig {
+        &mut self.buckets[bucket_type as usize]
+    }
+
+    /// Whether any limit is configured.
+    pub fn enabled(&self) -> bool {
+        self.buckets.iter().any(|bucket| bucket.avg != 0)
+    }
+}
+
+/// State of a leaky bucket.
+#[derive(Default, Copy, Clone)]
+struct LeakyBucket {
+    config: BucketConfig,
+    /// Units in the bucket.
+    level: f64,
+    /// Units in the burst bucket, which limits the rate to `max` while bursting.
+    burst_level: f64,
+}
+
+impl LeakyBucket {
+    fn leak(&mut self, nanos: f64) {
+        let leak = self.config.avg as f64 * nanos / NANOS_PER_SEC;
+        self.level = (self.level - leak).max(0.0);
+        if self.config.burst_length() > 1 {
+            let leak = self.config.max as f64 * nanos / NANOS_PER_SEC;
+            self.burst_level = (self.burst_level - leak).max(0.0);
+        }
+    }
+
+    /// Nanoseconds to wait until the bucket accepts more requests.
+    fn compute_wait(&self) -> u64 {
+        let avg = self.config.avg as f64;
+        if avg == 0.0 {
+            return 0;
+        }
+        let max = self.config.max as f64;
+        let (bucket_size, burst_bucket_size) = if max == 0.0 {
+            (avg / BUCKET_SIZE_DIVISOR, 0.0)
+        } else {
+            (
+                max * self.config.burst_length() as f64,
+                max / BUCKET_SIZE_DIVISOR,
+            )
+        };
+
+        let extra = self.level - bucket_size;
+        if extra > 0.0 {
+            return (extra / avg * NANOS_PER_SEC) as u64;
+        }
+        if self.config.burst_length() > 1 {
+            let extra = self.burst_level - burst_bucket_size;
+            if extra > 0.0 {
+                return (extra / max * NANOS_PER_SEC) as u64;
+            }
+        }
+        0
+    }
+
+    fn account(&mut self, units: f64) {
+        self.level += units;
+        if self.config.burst_length() > 1 {
+            self.burst_level += units;
+        }
+    }
+}
+
+/// Throttle state, which may be shared by several devices to limit them as a group.
+pub struct Throttle {
+    /// Limits of the throttle.
+    config: ThrottleConfig,
+    /// State of each bucket, indexed by `BucketType`.
+    buckets: [LeakyBucket; BUCKETS_COUNT],
+    /// Time of the last leak.
+    prev_time: Instant,
+}
+
+impl Throttle {
+    /// Construct function
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Limits of the throttle.
+    pub fn new(config: ThrottleConfig) -> Self {
+        let mut throttle = Throttle {
+            config,
+            buckets: [LeakyBucket::default(); BUCKETS_COUNT],
+            prev_time: Instant::now(),
+        };
+        throttle.set_config(config);
+        throttle
+    }
+
+    /// Get the limits of the throttle.
+    pub fn config(&self) -> ThrottleConfig {
+        self.config
+    }
+
+    /// Update the limits, the buckets are emptied.
+    pub fn set_config(&mut self, config: ThrottleConfig) {
+        self.config = config;
+        for (bucket, bucket_config) in self.buckets.iter_mut().zip(config.buckets.iter()) {
+            *bucket = LeakyBucket {
+                config: *bucket_config,
+                ..Default::default()
+            };
+        }
+        self.prev_time = Instant::now();
+    }
+
+    /// Nanoseconds to wait before the next request of the direction is allowed,
+    /// `0` if it is allowed now.
+    ///
+    /// # Arguments
+    ///
+    /// * `is_write` - Whether the request is a write request.
+    pub fn compute_wait(&mut self, is_write: bool) -> u64 {
+        let now = Instant::now();
+        let nanos = (now - self.prev_time).as_nanos() as f64;
+        self.prev_time = now;
+
+        let mut wait = 0;
+        for (bucket, bucket_type) in self.buckets.iter_mut().zip(BucketType::ALL.iter()) {
+            bucket.leak(nanos);
+            if bucket_type.accounts(is_write) {
+                wait = std::cmp::max(wait, bucket.compute_wait());
+            }
+        }
+        wait
+    }
+
+    /// Account a request to the buckets.
+    ///
+    /// # Arguments
+    ///
+    /// * `is_write` - Whether the request is a write request.
+    /// * `bytes` - Size of the request.
+    pub fn account(&mut self, is_write: bool, bytes: u64) {
+        let ops = if self.config.iops_size != 0 && bytes > self.config.iops_size {
+            bytes as f64 / self.config.iops_size as f64
+        } else {
+            1.0
+        };
+        for (bucket, bucket_type) in self.buckets.iter_mut().zip(BucketType::ALL.iter()) {
+            if bucket_type.accounts(is_write) {
+                bucket.account(if bucket_type.is_bps() {
+                    bytes as f64
+                } else {
+                    ops
+                });
+            }
+        }
+    }
+}
+
+/// Timer of a throttled device, which wakes up the device when the throttle
+/// allows more requests.
+pub struct ThrottleTimer {
+    /// Indicate whether the timer started.
+    timer_started: bool,
+    /// When throttle is ready for allowing more IO operation, the internal callback will write
+    /// this FD. This FD should be listened by IO thread.
+    timer_wakeup: EventFd,
+}
+
+impl Default for ThrottleTimer {
+    fn default() -> Self {
+        ThrottleTimer {
+            timer_started: false,
+            timer_wakeup: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        }
+    }
+}
+
+impl ThrottleTimer {
+    /// Return true if the request must wait, and caller must return directly instead
+    /// of launching IO. The timer is started to wake up the caller later.
+    ///
+    /// # Arguments
+    ///
+    /// * `throttle` - The throttle of the device.
+    /// * `loop_context` - used for delay function call.
+    /// * `is_write` - Whether the request is a write request.
+    pub fn throttled(
+        &mut self,
+        throttle: &mut Throttle,
+        loop_context: &mut EventLoopContext,
+        is_write: bool,
+    ) -> bool {
+        if self.timer_started {
+            return true;
+        }
+
+        let wait = throttle.compute_wait(is_write);
+        if wait == 0 {
+            return false;
+        }
+
+        let wakeup_clone = self.timer_wakeup.try_clone().unwrap();
+        let func = Box::new(move || {
+            wakeup_clone
+                .write(1)
+                .unwrap_or_else(|e| error!("Throttle send event to device failed {}", e));
+        });
+        loop_context.delay_call(func, wait);
+        self.timer_started = true;
+
+        true
+    }
+
+    /// Clear the timer state.
+    pub fn clear_timer(&mut self) {
+        self.timer_started = false;
+    }
+
+    /// Get raw fd of wakeup event.
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.timer_wakeup.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bucket_type: BucketType, avg: u64, max: u64, max_length: u64) -> ThrottleConfig {
+        let mut config = ThrottleConfig::default();
+        *config.bucket_mut(bucket_type) = BucketConfig {
+            avg,
+            max,
+            max_length,
+        };
+        config
+    }
+
+    #[test]
+    fn test_throttle_bps() {
+        let mut throttle = Throttle::new(config(BucketType::BpsRead, 1000, 0, 0));
+        assert_eq!(throttle.compute_wait(false), 0);
+        // The bucket size is 100 bytes.
+        throttle.account(false, 100);
+        assert_eq!(throttle.compute_wait(false), 0);
+        throttle.account(false, 400);
+        let wait = throttle.compute_wait(false);
+        assert!(wait > 390_000_000 && wait <= 400_000_000);
+        // Write requests are not limited.
+        assert_eq!(throttle.compute_wait(true), 0);
+    }
+
+    #[test]
+    fn test_throttle_iops_size() {
+        let mut config = config(BucketType::IopsTotal, 10, 0, 0);
+        config.iops_size = 4096;
+        let mut throttle = Throttle::new(config);
+        // One operation fills the bucket.
+        throttle.account(true, 4096);
+        assert_eq!(throttle.compute_wait(false), 0);
+        // A request of 4 times of iops_size is accounted as 4 operations.
+        throttle.account(true, 4 * 4096);
+        let wait = throttle.compute_wait(false);
+        assert!(wait > 390_000_000 && wait <= 400_000_000);
+    }
+
+    #[test]
+    fn test_throttle_burst() {
+        let mut throttle = Throttle::new(config(BucketType::IopsWrite, 10, 100, 2));
+        // Bursting is limited to 10 operations per 0.1 second.
+        for _ in 0..10 {
+            throttle.account(true, 512);
+        }
+        assert_eq!(throttle.compute_wait(true), 0);
+        throttle.account(true, 512);
+        assert!(throttle.compute_wait(true) > 0);
+
+        // The bucket size is 200 operations.
+        let mut throttle = Throttle::new(config(BucketType::IopsWrite, 10, 100, 2));
+        throttle.buckets[BucketType::IopsWrite as usize].level = 200.0;
+        assert_eq!(throttle.compute_wait(true), 0);
+        throttle.buckets[BucketType::IopsWrite as usize].level = 201.0;
+        assert!(throttle.compute_wait(true) > 0);
+
+        // Updating the limits empties the buckets.
+        throttle.set_config(config(BucketType::IopsWrite, 10, 100, 2));
+        assert_eq!(throttle.compute_wait(true), 0);
+    }
+}
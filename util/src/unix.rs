@@ -64,17 +64,16 @@ impl From<&str> for UnixPath {
 ///
 /// # Notions
 ///
-/// Unix uri is the string as `file:/xxx/xxx` or `unix:/xxx/xxx` or `tcp:xxx.xxx.xxx`.
+/// Unix uri is the string as `file:/xxx/xxx` or `unix:/xxx/xxx` or `tcp:xxx.xxx.xxx.xxx:port`.
+/// For `tcp` uri, the returned path is the socket address `xxx.xxx.xxx.xxx:port`.
 pub fn parse_uri(uri: &str) -> Result<(UnixPath, String)> {
     let parse_vec: Vec<&str> = uri.split(':').collect();
-    if parse_vec.len() == 2 {
-        match UnixPath::from(parse_vec[0]) {
-            UnixPath::File => Ok((UnixPath::File, String::from(parse_vec[1]))),
-            UnixPath::Unix => Ok((UnixPath::Unix, String::from(parse_vec[1]))),
-            _ => bail!("Unsupported unix path type."),
-        }
-    } else {
-        bail!("Invalid unix uri: {}", uri)
+    match (UnixPath::from(parse_vec[0]), parse_vec.len()) {
+        (UnixPath::File, 2) => Ok((UnixPath::File, String::from(parse_vec[1]))),
+        (UnixPath::Unix, 2) => Ok((UnixPath::Unix, String::from(parse_vec[1]))),
+        (UnixPath::Tcp, 3) => Ok((UnixPath::Tcp, format!("{}:{}", parse_vec[1], parse_vec[2]))),
+        (UnixPath::Unknown, 2) => bail!("Unsupported unix path type."),
+        _ => bail!("Invalid unix uri: {}", uri),
     }
 }
 
@@ -151,6 +150,48 @@ fn set_memory_undumpable(host_addr: *mut libc::c_void, size: u64) {
     }
 }
 
+/// Set the numa memory policy of host memory range.
+///
+/// # Arguments
+///
+/// * `host_addr` - The start host address of memory range, aligned with page size.
+/// * `len` - Length of memory range.
+/// * `mode` - Memory policy mode, such as `MPOL_BIND`.
+/// * `node_mask` - Bitmask of host numa nodes.
+/// * `max_node` - The number of bits in `node_mask`.
+/// * `flags` - Memory policy flags, such as `MPOL_MF_MOVE`.
+///
+/// # Errors
+///
+/// * Failed to do mbind.
+pub fn mbind(
+    host_addr: u64,
+    len: u64,
+    mode: u32,
+    node_mask: &[u64],
+    max_node: u64,
+    flags: u32,
+) -> Result<()> {
+    // Safe because the memory range is mapped, node_mask is valid for max_node bits
+    // and the return value is checked.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            host_addr as *mut libc::c_void,
+            len as libc::c_ulong,
+            mode as libc::c_int,
+            node_mask.as_ptr(),
+            max_node as libc::c_ulong,
+            flags as libc::c_uint,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error()).chain_err(|| "Mbind failed.");
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::{parse_uri, UnixPath};
@@ -169,5 +210,17 @@ mod tests {
 
         let test_uri_03 = "tcp:127.0.0.1";
         assert!(parse_uri(test_uri_03).is_err());
+
+        let test_uri_04 = "tcp:127.0.0.1:4446";
+        assert_eq!(
+            parse_uri(test_uri_04).unwrap(),
+            (UnixPath::Tcp, String::from("127.0.0.1:4446"))
+        );
+
+        let test_uri_05 = "unix:/tmp/test_socket";
+        assert_eq!(
+            parse_uri(test_uri_05).unwrap(),
+            (UnixPath::Unix, String::from("/tmp/test_socket"))
+        );
     }
 }
@@ -306,6 +306,14 @@ impl Listener for VfioContainer {
         }
         Ok(())
     }
+
+    fn set_dirty_log(&self, enable: bool) -> address_space::errors::Result<()> {
+        // The device writes guest memory by DMA without logging dirty pages.
+        if enable {
+            bail!("Dirty page logging is not supported by vfio device");
+        }
+        Ok(())
+    }
 }
 
 /// Vfio group is a member of IOMMU group, which contains a set of devices isolated from all
@@ -16,7 +16,7 @@ use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 use std::{
     cmp::{self, Reverse},
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use address_space::{
@@ -24,7 +24,10 @@ use address_space::{
 };
 use error_chain::ChainedError;
 use machine_manager::{
-    config::BalloonConfig, event_loop::EventLoop, qmp::qmp_schema::BalloonInfo, qmp::QmpChannel,
+    config::BalloonConfig,
+    event_loop::EventLoop,
+    qmp::qmp_schema::{BalloonInfo, BalloonStats},
+    qmp::QmpChannel,
 };
 use util::{
     bitmap::Bitmap,
@@ -39,11 +42,13 @@ use util::{
 use vmm_sys_util::{epoll::EventSet, eventfd::EventFd, timerfd::TimerFd};
 
 use super::{
-    errors::*, Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
-    VIRTIO_F_VERSION_1, VIRTIO_TYPE_BALLOON,
+    errors::*, virtio_has_feature, Element, Queue, VirtioDevice, VirtioInterrupt,
+    VirtioInterruptType, VIRTIO_F_VERSION_1, VIRTIO_TYPE_BALLOON,
 };
 
+const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1;
 const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2;
+const VIRTIO_BALLOON_F_REPORTING: u32 = 5;
 const VIRTIO_BALLOON_PFN_SHIFT: u32 = 12;
 const QUEUE_SIZE_BALLOON: u16 = 256;
 const QUEUE_NUM_BALLOON: usize = 2;
@@ -52,6 +57,18 @@ const BALLOON_INFLATE_EVENT: bool = true;
 const BALLOON_DEFLATE_EVENT: bool = false;
 const BITS_OF_TYPE_U64: u64 = 64;
 
+/// Tags of memory statistics reported by guest.
+const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
+const VIRTIO_BALLOON_S_SWAP_OUT: u16 = 1;
+const VIRTIO_BALLOON_S_MAJFLT: u16 = 2;
+const VIRTIO_BALLOON_S_MINFLT: u16 = 3;
+const VIRTIO_BALLOON_S_MEMFREE: u16 = 4;
+const VIRTIO_BALLOON_S_MEMTOT: u16 = 5;
+const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
+const VIRTIO_BALLOON_S_CACHES: u16 = 7;
+const VIRTIO_BALLOON_S_HTLB_PGALLOC: u16 = 8;
+const VIRTIO_BALLOON_S_HTLB_PGFAIL: u16 = 9;
+
 static mut BALLOON_DEV: Option<Arc<Mutex<Balloon>>> = None;
 
 /// IO vector, used to find memory segments.
@@ -72,8 +89,19 @@ struct VirtioBalloonConfig {
     pub actual: u32,
 }
 
+/// Memory statistic reported by guest through statistics queue.
+#[repr(C, packed)]
+#[derive(Copy, Clone, Default)]
+struct VirtioBalloonStat {
+    /// Tag of the statistic, `VIRTIO_BALLOON_S_*`.
+    tag: u16,
+    /// Value of the statistic.
+    val: u64,
+}
+
 impl ByteCode for Iovec {}
 impl ByteCode for VirtioBalloonConfig {}
+impl ByteCode for VirtioBalloonStat {}
 
 /// Bitmap for balloon. It is used if the host page size is bigger than 4k.
 struct BalloonedPageBitmap {
@@ -212,6 +240,7 @@ impl Request {
         for iov in self.iovec.iter() {
             let mut offset = 0;
             let mut hvaset = Vec::new();
+            let mut gpaset = Vec::new();
             while let Some(pfn) = iov_to_buf::<u32>(address_space, iov, offset) {
                 offset += std::mem::size_of::<u32>() as u64;
                 let gpa: GuestAddress = GuestAddress((pfn as u64) << VIRTIO_BALLOON_PFN_SHIFT);
@@ -223,6 +252,7 @@ impl Request {
                     }
                 };
                 hvaset.push(hva);
+                gpaset.push(gpa);
             }
             hvaset.sort_by_key(|&b| Reverse(b));
             let host_page_size = host_page_size();
@@ -292,6 +322,12 @@ impl Request {
                     }
                 }
             }
+            // Released pages read as zero, they are sent again in live migration.
+            if req_type {
+                for gpa in gpaset {
+                    address_space.mark_dirty(gpa, BALLOON_PAGE_SIZE);
+                }
+            }
         }
     }
 }
@@ -393,6 +429,36 @@ impl BlnMemInfo {
         }
     }
 
+    /// Release the host memory of guest range [`addr`, `addr` + `len`), the
+    /// parts which are not aligned with host page size are skipped.
+    fn release_mem_range(&self, addr: GuestAddress, len: u64) {
+        let all_regions = self.regions.lock().unwrap();
+        let host_page_size = host_page_size();
+        let mut start = addr.raw_value();
+        let end = start.saturating_add(len);
+        while start < end {
+            let region = match all_regions.iter().find(|reg| {
+                start >= reg.guest_phys_addr && start < reg.guest_phys_addr + reg.memory_size
+            }) {
+                Some(reg) => reg,
+                None => {
+                    error!("Can not get host address, gpa: {}", start);
+                    return;
+                }
+            };
+            let size = cmp::min(end, region.guest_phys_addr + region.memory_size) - start;
+            let hva = region.userspace_addr + start - region.guest_phys_addr;
+            if hva % host_page_size == 0 && size % host_page_size == 0 {
+                memory_advise(
+                    hva as *const libc::c_void as *mut _,
+                    size as usize,
+                    libc::MADV_DONTNEED,
+                );
+            }
+            start += size;
+        }
+    }
+
     /// Get Ram size of AddressSpace.
     fn get_ram_size(&self) -> u64 {
         let mut size = 0_u64;
@@ -458,6 +524,21 @@ struct BalloonIoHandler {
     event_timer: Arc<Mutex<TimerFd>>,
     /// Actual balloon size
     balloon_actual: Arc<AtomicU32>,
+    /// Statistics queue.
+    stats_queue: Option<Arc<Mutex<Queue>>>,
+    /// Statistics EventFd.
+    stats_evt: Option<EventFd>,
+    /// Timer to request memory statistics from guest.
+    stats_timer: Option<TimerFd>,
+    /// Descriptor index of the statistics buffer, which is returned to guest
+    /// when requesting new statistics.
+    stats_desc_index: Option<u16>,
+    /// The latest memory statistics reported by guest.
+    guest_stats: Arc<Mutex<Option<BalloonStats>>>,
+    /// Free page reporting queue.
+    report_queue: Option<Arc<Mutex<Queue>>>,
+    /// Free page reporting EventFd.
+    report_evt: Option<EventFd>,
 }
 
 impl BalloonIoHandler {
@@ -504,12 +585,119 @@ impl BalloonIoHandler {
         Ok(())
     }
 
+    /// Process the memory statistics reported by guest. The buffer is held
+    /// until host requests new statistics.
+    fn process_stats_queue(&mut self) -> Result<()> {
+        let queue = match &self.stats_queue {
+            Some(queue) => queue,
+            None => return Ok(()),
+        };
+        let mut locked_queue = queue.lock().unwrap();
+        let mut need_interrupt = false;
+        while let Ok(elem) = locked_queue
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            let mut stats = BalloonStats {
+                last_update: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs()),
+                ..Default::default()
+            };
+            for elem_iov in elem.out_iovec.iter() {
+                let iov = Iovec {
+                    iov_base: elem_iov.addr,
+                    iov_len: elem_iov.len as u64,
+                };
+                let mut offset = 0;
+                while let Some(stat) =
+                    iov_to_buf::<VirtioBalloonStat>(&self.mem_space, &iov, offset)
+                {
+                    offset += size_of::<VirtioBalloonStat>() as u64;
+                    update_balloon_stats(&mut stats, stat.tag, stat.val);
+                }
+            }
+            *self.guest_stats.lock().unwrap() = Some(stats);
+            // The buffer held before can not be used any more, return it to guest.
+            if let Some(index) = self.stats_desc_index.replace(elem.index) {
+                locked_queue
+                    .vring
+                    .add_used(&self.mem_space, index, 0)
+                    .chain_err(|| "Failed to add balloon statistics buffer into used queue")?;
+                need_interrupt = true;
+            }
+        }
+
+        if need_interrupt {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue))
+                .chain_err(|| ErrorKind::InterruptTrigger("balloon", VirtioInterruptType::Vring))?;
+        }
+        Ok(())
+    }
+
+    /// Request guest to update the memory statistics by returning the
+    /// statistics buffer.
+    fn request_stats(&mut self) -> Result<()> {
+        let queue = match &self.stats_queue {
+            Some(queue) => queue,
+            None => return Ok(()),
+        };
+        let index = match self.stats_desc_index.take() {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+        let mut locked_queue = queue.lock().unwrap();
+        locked_queue
+            .vring
+            .add_used(&self.mem_space, index, 0)
+            .chain_err(|| "Failed to add balloon statistics buffer into used queue")?;
+        (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue))
+            .chain_err(|| ErrorKind::InterruptTrigger("balloon", VirtioInterruptType::Vring))?;
+        Ok(())
+    }
+
+    /// Release the free pages reported by guest.
+    fn process_report_queue(&mut self) -> Result<()> {
+        let queue = match &self.report_queue {
+            Some(queue) => queue,
+            None => return Ok(()),
+        };
+        let mut locked_queue = queue.lock().unwrap();
+        let mut need_interrupt = false;
+        while let Ok(elem) = locked_queue
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            let mem_info = self.mem_info.lock().unwrap();
+            if !mem_info.has_huge_page() {
+                for elem_iov in elem.in_iovec.iter() {
+                    mem_info.release_mem_range(elem_iov.addr, elem_iov.len as u64);
+                    self.mem_space
+                        .mark_dirty(elem_iov.addr, elem_iov.len as u64);
+                }
+            }
+            drop(mem_info);
+            locked_queue
+                .vring
+                .add_used(&self.mem_space, elem.index, 0)
+                .chain_err(|| "Failed to add free page report into used queue")?;
+            need_interrupt = true;
+        }
+
+        if need_interrupt {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue))
+                .chain_err(|| ErrorKind::InterruptTrigger("balloon", VirtioInterruptType::Vring))?;
+        }
+        Ok(())
+    }
+
     /// Send balloon changed event.
     fn send_balloon_changed_event(&self) {
         let ram_size = self.mem_info.lock().unwrap().get_ram_size();
         let balloon_size = self.get_balloon_memory_size();
         let msg = BalloonInfo {
             actual: ram_size - balloon_size,
+            guest_stats: None,
         };
         event!(BalloonChanged; msg);
     }
@@ -520,7 +708,7 @@ impl BalloonIoHandler {
     }
 
     fn deactivate_evt_handler(&self) -> Vec<EventNotifier> {
-        let notifiers = vec![
+        let mut notifiers = vec![
             EventNotifier::new(
                 NotifierOperation::Delete,
                 self.deactivate_evt,
@@ -550,11 +738,46 @@ impl BalloonIoHandler {
                 Vec::new(),
             ),
         ];
+        let optional_fds = [
+            self.stats_evt.as_ref().map(|evt| evt.as_raw_fd()),
+            self.stats_timer.as_ref().map(|timer| timer.as_raw_fd()),
+            self.report_evt.as_ref().map(|evt| evt.as_raw_fd()),
+        ];
+        for fd in optional_fds.iter().flatten() {
+            notifiers.push(EventNotifier::new(
+                NotifierOperation::Delete,
+                *fd,
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ));
+        }
 
         notifiers
     }
 }
 
+/// Update the memory statistic of `tag` with `val`.
+fn update_balloon_stats(stats: &mut BalloonStats, tag: u16, val: u64) {
+    let stat = match tag {
+        VIRTIO_BALLOON_S_SWAP_IN => &mut stats.swap_in,
+        VIRTIO_BALLOON_S_SWAP_OUT => &mut stats.swap_out,
+        VIRTIO_BALLOON_S_MAJFLT => &mut stats.major_faults,
+        VIRTIO_BALLOON_S_MINFLT => &mut stats.minor_faults,
+        VIRTIO_BALLOON_S_MEMFREE => &mut stats.free_memory,
+        VIRTIO_BALLOON_S_MEMTOT => &mut stats.total_memory,
+        VIRTIO_BALLOON_S_AVAIL => &mut stats.available_memory,
+        VIRTIO_BALLOON_S_CACHES => &mut stats.disk_caches,
+        VIRTIO_BALLOON_S_HTLB_PGALLOC => &mut stats.hugetlb_allocations,
+        VIRTIO_BALLOON_S_HTLB_PGFAIL => &mut stats.hugetlb_failures,
+        _ => {
+            warn!("Unknown balloon statistic tag: {}", tag);
+            return;
+        }
+    };
+    *stat = Some(val);
+}
+
 /// Create a new EventNotifier.
 ///
 /// # Arguments
@@ -644,6 +867,48 @@ impl EventNotifierHelper for BalloonIoHandler {
             handler,
         ));
 
+        // register event notifier for statistics event.
+        if let Some(stats_evt) = &locked_balloon_io.stats_evt {
+            let cloned_balloon_io = balloon_io.clone();
+            let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+                read_fd(fd);
+                if let Err(e) = cloned_balloon_io.lock().unwrap().process_stats_queue() {
+                    error!("Failed to get balloon statistics: {}", e.display_chain());
+                };
+                None
+            });
+            notifiers.push(build_event_notifier(stats_evt.as_raw_fd(), handler));
+        }
+
+        // register event notifier for statistics polling timer.
+        if let Some(stats_timer) = &locked_balloon_io.stats_timer {
+            let cloned_balloon_io = balloon_io.clone();
+            let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+                read_fd(fd);
+                if let Err(e) = cloned_balloon_io.lock().unwrap().request_stats() {
+                    error!(
+                        "Failed to request balloon statistics: {}",
+                        e.display_chain()
+                    );
+                };
+                None
+            });
+            notifiers.push(build_event_notifier(stats_timer.as_raw_fd(), handler));
+        }
+
+        // register event notifier for free page reporting event.
+        if let Some(report_evt) = &locked_balloon_io.report_evt {
+            let cloned_balloon_io = balloon_io.clone();
+            let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+                read_fd(fd);
+                if let Err(e) = cloned_balloon_io.lock().unwrap().process_report_queue() {
+                    error!("Failed to release reported pages: {}", e.display_chain());
+                };
+                None
+            });
+            notifiers.push(build_event_notifier(report_evt.as_raw_fd(), handler));
+        }
+
         notifiers
     }
 }
@@ -668,6 +933,10 @@ pub struct Balloon {
     event_timer: Arc<Mutex<TimerFd>>,
     /// EventFd for device deactivate.
     deactivate_evt: EventFd,
+    /// Interval in seconds to request memory statistics from guest.
+    stats_polling_interval: u32,
+    /// The latest memory statistics reported by guest.
+    guest_stats: Arc<Mutex<Option<BalloonStats>>>,
 }
 
 impl Balloon {
@@ -681,6 +950,12 @@ impl Balloon {
         if bln_cfg.deflate_on_oom {
             device_features |= 1u64 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
         }
+        if bln_cfg.stats_polling_interval > 0 {
+            device_features |= 1u64 << VIRTIO_BALLOON_F_STATS_VQ;
+        }
+        if bln_cfg.free_page_reporting {
+            device_features |= 1u64 << VIRTIO_BALLOON_F_REPORTING;
+        }
 
         Balloon {
             device_features,
@@ -692,6 +967,8 @@ impl Balloon {
             mem_space,
             event_timer: Arc::new(Mutex::new(TimerFd::new().unwrap())),
             deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            stats_polling_interval: bln_cfg.stats_polling_interval,
+            guest_stats: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -737,6 +1014,7 @@ impl Balloon {
         })?;
         let msg = BalloonInfo {
             actual: self.get_guest_memory_size(),
+            guest_stats: None,
         };
         event!(BalloonChanged; msg);
         Ok(())
@@ -751,6 +1029,34 @@ impl Balloon {
     pub fn get_guest_memory_size(&self) -> u64 {
         self.mem_info.lock().unwrap().get_ram_size() - self.get_balloon_memory_size()
     }
+
+    /// Get the latest memory statistics reported by guest.
+    pub fn get_guest_stats(&self) -> Option<BalloonStats> {
+        self.guest_stats.lock().unwrap().clone()
+    }
+
+    /// Get the indexes of statistics queue and free page reporting queue. The
+    /// optional queues follow the inflate and deflate queues, and an absent
+    /// queue does not take an index.
+    ///
+    /// # Arguments
+    ///
+    /// * `features` - The features which decide the queues in use.
+    fn queue_layout(features: u64) -> (Option<usize>, Option<usize>) {
+        let mut queue_index = QUEUE_NUM_BALLOON;
+        let stats_index = if virtio_has_feature(features, VIRTIO_BALLOON_F_STATS_VQ) {
+            queue_index += 1;
+            Some(queue_index - 1)
+        } else {
+            None
+        };
+        let report_index = if virtio_has_feature(features, VIRTIO_BALLOON_F_REPORTING) {
+            Some(queue_index)
+        } else {
+            None
+        };
+        (stats_index, report_index)
+    }
 }
 
 impl VirtioDevice for Balloon {
@@ -770,7 +1076,14 @@ impl VirtioDevice for Balloon {
 
     /// Get the number of balloon-device queues.
     fn queue_num(&self) -> usize {
-        QUEUE_NUM_BALLOON
+        // Before features negotiation, all the queues offered by device are counted.
+        let features = if self.driver_features == 0 {
+            self.device_features
+        } else {
+            self.driver_features
+        };
+        let (stats_index, report_index) = Self::queue_layout(features);
+        QUEUE_NUM_BALLOON + stats_index.map_or(0, |_| 1) + report_index.map_or(0, |_| 1)
     }
 
     /// Get the zise of balloon queue.
@@ -870,14 +1183,34 @@ impl VirtioDevice for Balloon {
         queues: &[Arc<Mutex<Queue>>],
         mut queue_evts: Vec<EventFd>,
     ) -> Result<()> {
-        if queues.len() != QUEUE_NUM_BALLOON {
-            return Err(ErrorKind::IncorrectQueueNum(QUEUE_NUM_BALLOON, queues.len()).into());
+        let queue_num = self.queue_num();
+        if queues.len() != queue_num {
+            return Err(ErrorKind::IncorrectQueueNum(queue_num, queues.len()).into());
         }
 
         let inf_queue = queues[0].clone();
         let inf_queue_evt = queue_evts.remove(0);
         let def_queue = queues[1].clone();
         let def_queue_evt = queue_evts.remove(0);
+        let (stats_index, report_index) = Self::queue_layout(self.driver_features);
+        let (mut stats_queue, mut stats_evt, mut stats_timer) = (None, None, None);
+        if let Some(index) = stats_index {
+            stats_queue = Some(queues[index].clone());
+            stats_evt = Some(queue_evts.remove(0));
+
+            let interval = Duration::from_secs(u64::from(self.stats_polling_interval));
+            let mut timer = TimerFd::new()
+                .chain_err(|| "Failed to create timer for balloon statistics polling")?;
+            timer
+                .reset(interval, Some(interval))
+                .chain_err(|| "Failed to set timer for balloon statistics polling")?;
+            stats_timer = Some(timer);
+        }
+        let (mut report_queue, mut report_evt) = (None, None);
+        if let Some(index) = report_index {
+            report_queue = Some(queues[index].clone());
+            report_evt = Some(queue_evts.remove(0));
+        }
 
         self.interrupt_cb = Some(interrupt_cb.clone());
         let handler = BalloonIoHandler {
@@ -892,6 +1225,13 @@ impl VirtioDevice for Balloon {
             mem_info: self.mem_info.clone(),
             event_timer: self.event_timer.clone(),
             balloon_actual: self.actual.clone(),
+            stats_queue,
+            stats_evt,
+            stats_timer,
+            stats_desc_index: None,
+            guest_stats: self.guest_stats.clone(),
+            report_queue,
+            report_evt,
         };
 
         EventLoop::update_event(
@@ -949,6 +1289,15 @@ pub fn qmp_query_balloon() -> Option<u64> {
     None
 }
 
+pub fn qmp_query_balloon_stats() -> Option<BalloonStats> {
+    // Safe, because there is no confliction when writing global variable BALLOON_DEV, in other words,
+    // this function will not be called simultaneously.
+    if let Some(dev) = unsafe { (*std::ptr::addr_of!(BALLOON_DEV)).as_ref() } {
+        return dev.lock().unwrap().get_guest_stats();
+    }
+    None
+}
+
 /// Create a syscall bpf rule for device `Balloon`.
 pub fn balloon_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
     syscall_allow_list.extend(vec![
@@ -1013,6 +1362,7 @@ mod tests {
         let bln_cfg = BalloonConfig {
             id: "bln".to_string(),
             deflate_on_oom: true,
+            ..Default::default()
         };
 
         let mem_space = address_space_init();
@@ -1054,6 +1404,7 @@ mod tests {
         let bln_cfg = BalloonConfig {
             id: "bln".to_string(),
             deflate_on_oom: true,
+            ..Default::default()
         };
 
         let mem_space = address_space_init();
@@ -1072,6 +1423,7 @@ mod tests {
         let bln_cfg = BalloonConfig {
             id: "bln".to_string(),
             deflate_on_oom: true,
+            ..Default::default()
         };
 
         let mem_space = address_space_init();
@@ -1089,6 +1441,7 @@ mod tests {
         let bln_cfg = BalloonConfig {
             id: "bln".to_string(),
             deflate_on_oom: true,
+            ..Default::default()
         };
         let mut bln = Balloon::new(&bln_cfg, mem_space.clone());
         bln.realize().unwrap();
@@ -1166,6 +1519,13 @@ mod tests {
             mem_info: bln.mem_info.clone(),
             event_timer: bln.event_timer.clone(),
             balloon_actual: bln.actual.clone(),
+            stats_queue: None,
+            stats_evt: None,
+            stats_timer: None,
+            stats_desc_index: None,
+            guest_stats: bln.guest_stats.clone(),
+            report_queue: None,
+            report_evt: None,
         };
 
         let balloon = Arc::new(Mutex::new(bln));
@@ -1230,6 +1590,171 @@ mod tests {
         assert!(handler.process_balloon_queue(BALLOON_DEFLATE_EVENT).is_ok());
     }
 
+    fn create_queue(mem_space: &Arc<AddressSpace>, base: u64) -> (QueueConfig, Queue) {
+        let mut queue_config = QueueConfig::new(QUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(base);
+        queue_config.addr_cache.desc_table_host =
+            mem_space.get_host_address(queue_config.desc_table).unwrap();
+        queue_config.avail_ring = GuestAddress(base + 0x1000);
+        queue_config.addr_cache.avail_ring_host =
+            mem_space.get_host_address(queue_config.avail_ring).unwrap();
+        queue_config.used_ring = GuestAddress(base + 0x2000);
+        queue_config.addr_cache.used_ring_host =
+            mem_space.get_host_address(queue_config.used_ring).unwrap();
+        queue_config.ready = true;
+        queue_config.size = QUEUE_SIZE;
+        (queue_config, Queue::new(queue_config, 1).unwrap())
+    }
+
+    #[test]
+    fn test_balloon_stats_and_report() {
+        const VIRTQ_DESC_F_WRITE: u16 = 0x02;
+        let mem_space = address_space_init();
+        let bln_cfg = BalloonConfig {
+            id: "bln".to_string(),
+            free_page_reporting: true,
+            stats_polling_interval: 1,
+            ..Default::default()
+        };
+        let mut bln = Balloon::new(&bln_cfg, mem_space.clone());
+        bln.realize().unwrap();
+        assert_eq!(bln.queue_num(), 4);
+        let feature = (1u64 << VIRTIO_F_VERSION_1)
+            | (1u64 << VIRTIO_BALLOON_F_STATS_VQ)
+            | (1u64 << VIRTIO_BALLOON_F_REPORTING);
+        assert_eq!(bln.device_features, feature);
+        // Queues not negotiated by driver do not take an index.
+        bln.driver_features = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_BALLOON_F_REPORTING);
+        assert_eq!(bln.queue_num(), 3);
+        assert_eq!(Balloon::queue_layout(bln.driver_features), (None, Some(2)));
+        assert_eq!(Balloon::queue_layout(feature), (Some(2), Some(3)));
+        bln.driver_features = 0;
+
+        let interrupt_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let cb = Arc::new(Box::new(
+            move |_int_type: &VirtioInterruptType, _queue: Option<&Queue>| {
+                interrupt_evt.write(1).chain_err(|| ErrorKind::EventFdWrite)
+            },
+        ) as VirtioInterrupt);
+        let (stats_config, stats_queue) = create_queue(&mem_space, 0x10000);
+        let (report_config, report_queue) = create_queue(&mem_space, 0x20000);
+        let mut handler = BalloonIoHandler {
+            driver_features: bln.device_features,
+            mem_space: mem_space.clone(),
+            inf_queue: Arc::new(Mutex::new(create_queue(&mem_space, 0x30000).1)),
+            inf_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            def_queue: Arc::new(Mutex::new(create_queue(&mem_space, 0x40000).1)),
+            def_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            deactivate_evt: bln.deactivate_evt.as_raw_fd(),
+            interrupt_cb: cb,
+            mem_info: bln.mem_info.clone(),
+            event_timer: bln.event_timer.clone(),
+            balloon_actual: bln.actual.clone(),
+            stats_queue: Some(Arc::new(Mutex::new(stats_queue))),
+            stats_evt: None,
+            stats_timer: None,
+            stats_desc_index: None,
+            guest_stats: bln.guest_stats.clone(),
+            report_queue: Some(Arc::new(Mutex::new(report_queue))),
+            report_evt: None,
+        };
+
+        // Guest reports memory statistics.
+        let stats = [
+            VirtioBalloonStat {
+                tag: VIRTIO_BALLOON_S_MEMFREE,
+                val: 0x1000,
+            },
+            VirtioBalloonStat {
+                tag: VIRTIO_BALLOON_S_AVAIL,
+                val: 0x2000,
+            },
+        ];
+        for (i, stat) in stats.iter().enumerate() {
+            mem_space
+                .write_object::<VirtioBalloonStat>(
+                    stat,
+                    GuestAddress(0x50000 + (i * size_of::<VirtioBalloonStat>()) as u64),
+                )
+                .unwrap();
+        }
+        let desc = SplitVringDesc {
+            addr: GuestAddress(0x50000),
+            len: (stats.len() * size_of::<VirtioBalloonStat>()) as u32,
+            flags: 0,
+            next: 0,
+        };
+        mem_space
+            .write_object::<SplitVringDesc>(&desc, stats_config.desc_table)
+            .unwrap();
+        mem_space
+            .write_object::<u16>(&0, GuestAddress(stats_config.avail_ring.0 + 4))
+            .unwrap();
+        mem_space
+            .write_object::<u16>(&1, GuestAddress(stats_config.avail_ring.0 + 2))
+            .unwrap();
+        assert!(handler.process_stats_queue().is_ok());
+        assert_eq!(handler.stats_desc_index, Some(0));
+
+        // A new statistics buffer replaces the held one, which is returned to guest.
+        mem_space
+            .write_object::<SplitVringDesc>(
+                &desc,
+                GuestAddress(stats_config.desc_table.0 + size_of::<SplitVringDesc>() as u64),
+            )
+            .unwrap();
+        mem_space
+            .write_object::<u16>(&1, GuestAddress(stats_config.avail_ring.0 + 6))
+            .unwrap();
+        mem_space
+            .write_object::<u16>(&2, GuestAddress(stats_config.avail_ring.0 + 2))
+            .unwrap();
+        assert!(handler.process_stats_queue().is_ok());
+        assert_eq!(handler.stats_desc_index, Some(1));
+        let used_idx = mem_space
+            .read_object::<u16>(GuestAddress(stats_config.used_ring.0 + 2))
+            .unwrap();
+        assert_eq!(used_idx, 1);
+        let guest_stats = bln.get_guest_stats().unwrap();
+        assert_eq!(guest_stats.free_memory, Some(0x1000));
+        assert_eq!(guest_stats.available_memory, Some(0x2000));
+        assert!(guest_stats.swap_in.is_none());
+
+        // Host requests new statistics by returning the buffer.
+        assert!(handler.request_stats().is_ok());
+        assert!(handler.stats_desc_index.is_none());
+        let used_idx = mem_space
+            .read_object::<u16>(GuestAddress(stats_config.used_ring.0 + 2))
+            .unwrap();
+        assert_eq!(used_idx, 2);
+
+        // Guest reports free pages, which are zero after released.
+        let page_size = host_page_size();
+        let page_addr = GuestAddress(MEMORY_SIZE - page_size);
+        mem_space.write_object::<u64>(&0xff, page_addr).unwrap();
+        let desc = SplitVringDesc {
+            addr: page_addr,
+            len: page_size as u32,
+            flags: VIRTQ_DESC_F_WRITE,
+            next: 0,
+        };
+        mem_space
+            .write_object::<SplitVringDesc>(&desc, report_config.desc_table)
+            .unwrap();
+        mem_space
+            .write_object::<u16>(&0, GuestAddress(report_config.avail_ring.0 + 4))
+            .unwrap();
+        mem_space
+            .write_object::<u16>(&1, GuestAddress(report_config.avail_ring.0 + 2))
+            .unwrap();
+        assert!(handler.process_report_queue().is_ok());
+        assert_eq!(mem_space.read_object::<u64>(page_addr).unwrap(), 0);
+        let used_idx = mem_space
+            .read_object::<u16>(GuestAddress(report_config.used_ring.0 + 2))
+            .unwrap();
+        assert_eq!(used_idx, 1);
+    }
+
     #[test]
     fn test_balloon_activate() {
         let mem_space = address_space_init();
@@ -1262,6 +1787,7 @@ mod tests {
         let bln_cfg = BalloonConfig {
             id: "bln".to_string(),
             deflate_on_oom: true,
+            ..Default::default()
         };
         let mut bln = Balloon::new(&bln_cfg, mem_space.clone());
         assert!(bln
@@ -11,37 +11,50 @@
 // See the Mulan PSL v2 for more details.
 
 use std::cmp;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs::{File, OpenOptions};
 use std::io::{Seek, SeekFrom, Write};
 use std::mem::size_of;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
 
 use address_space::{AddressSpace, GuestAddress};
 use error_chain::ChainedError;
 use machine_manager::{
-    config::{BlkDevConfig, ConfigCheck},
+    config::{BlkDevConfig, ConfigCheck, DiskFormat, WriteZeroesState},
     event_loop::EventLoop,
+    qmp::qmp_schema::{
+        BlockDeviceInfo, BlockDeviceStats, BlockInfo, BlockLatencyHistogramInfo, BlockStats,
+        BlockdevCacheInfo,
+    },
 };
 use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
-use util::aio::{Aio, AioCb, AioCompleteFunc, IoCmd, Iovec};
+use once_cell::sync::Lazy;
+use util::aio::{Aio, AioCb, AioCompleteFunc, AioEngine, IoCmd, Iovec};
 use util::byte_code::ByteCode;
-use util::leak_bucket::LeakBucket;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
 use util::num_ops::{read_u32, write_u32};
+use util::throttle::{BucketType, Throttle, ThrottleConfig, ThrottleTimer};
 use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 use super::errors::{ErrorKind, Result, ResultExt};
+use super::qcow2::Qcow2Driver;
 use super::{
-    Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VIRTIO_BLK_F_FLUSH,
-    VIRTIO_BLK_F_RO, VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_SIZE_MAX, VIRTIO_BLK_ID_BYTES,
-    VIRTIO_BLK_S_OK, VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
-    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
+    Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioNotifyStats,
+    VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_RO, VIRTIO_BLK_F_SEG_MAX,
+    VIRTIO_BLK_F_SIZE_MAX, VIRTIO_BLK_F_WRITE_ZEROES, VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR,
+    VIRTIO_BLK_S_OK, VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID,
+    VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_WRITE_ZEROES,
+    VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
+    VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
 };
 
 /// Number of virtqueues.
@@ -54,8 +67,42 @@ const SECTOR_SHIFT: u8 = 9;
 const SECTOR_SIZE: u64 = (0x01_u64) << SECTOR_SHIFT;
 /// Size of the dummy block device.
 const DUMMY_IMG_SIZE: u64 = 0;
-
-type SenderConfig = (Option<Arc<File>>, u64, Option<String>, bool);
+/// Max number of sectors in one discard or write zeroes segment.
+const MAX_DISCARD_WRITE_ZEROES_SECTORS: u32 = (i32::MAX as u32) >> SECTOR_SHIFT;
+/// Max number of segments in one discard or write zeroes request.
+const MAX_DISCARD_WRITE_ZEROES_SEG: u32 = 1;
+
+/// Block devices whose I/O throttle can be changed by `block_set_io_throttle`.
+static BLOCK_DEVICES: Lazy<Mutex<Vec<Weak<Mutex<Block>>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// Throttles shared by the block devices in the same throttle group.
+static THROTTLE_GROUPS: Lazy<Mutex<HashMap<String, Weak<Mutex<Throttle>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Upper bounds in nanoseconds of the bins of latency histograms, the last bin
+/// has no upper bound.
+const LATENCY_HISTOGRAM_BOUNDARIES: [u64; 6] = [
+    10_000,
+    100_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+];
+
+/// The throttle of a block device, `None` if I/O is not limited.
+type SharedThrottle = Arc<Mutex<Option<Arc<Mutex<Throttle>>>>>;
+/// The I/O accounting of a block device, shared by the device and its IO handler.
+type SharedIoStats = Arc<Mutex<BlockIoStats>>;
+
+type SenderConfig = (
+    Option<Arc<File>>,
+    u64,
+    Option<String>,
+    AioEngine,
+    Option<Arc<Mutex<Qcow2Driver>>>,
+    bool,
+    WriteZeroesState,
+);
 
 fn get_serial_num_config(serial_num: &str) -> Vec<u8> {
     let mut id_bytes = vec![0; VIRTIO_BLK_ID_BYTES as usize];
@@ -86,7 +133,12 @@ struct RequestOutHeader {
 impl RequestOutHeader {
     fn is_valid(&self) -> bool {
         match self.request_type {
-            VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT | VIRTIO_BLK_T_FLUSH | VIRTIO_BLK_T_GET_ID => true,
+            VIRTIO_BLK_T_IN
+            | VIRTIO_BLK_T_OUT
+            | VIRTIO_BLK_T_FLUSH
+            | VIRTIO_BLK_T_GET_ID
+            | VIRTIO_BLK_T_DISCARD
+            | VIRTIO_BLK_T_WRITE_ZEROES => true,
             _ => {
                 error!(
                     "request type {} is not supported for block",
@@ -100,6 +152,171 @@ impl RequestOutHeader {
 
 impl ByteCode for RequestOutHeader {}
 
+/// The segment of discard and write zeroes request.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct DiscardWriteZeroesSeg {
+    /// The start sector.
+    sector: u64,
+    /// The number of sectors.
+    num_sectors: u32,
+    /// Only `VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP` is valid for write zeroes request.
+    flags: u32,
+}
+
+impl ByteCode for DiscardWriteZeroesSeg {}
+
+/// Check whether all data in iovec is zero.
+fn iovec_is_zero(iovec: &[Iovec]) -> bool {
+    iovec.iter().all(|iov| {
+        // Safe because the iovec is mapped from guest memory.
+        let data =
+            unsafe { std::slice::from_raw_parts(iov.iov_base as *const u8, iov.iov_len as usize) };
+        data.iter().all(|b| *b == 0)
+    })
+}
+
+/// State shared by the aio requests split from one block request.
+struct SplitRequest {
+    /// Count of the aio requests not completed.
+    pending: AtomicU32,
+    /// Whether any of the aio requests failed.
+    failed: AtomicBool,
+}
+
+/// Types of the accounted block requests.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum BlockAcctType {
+    Read = 0,
+    Write,
+    Flush,
+    Unmap,
+}
+
+/// Number of types of the accounted block requests.
+const BLOCK_ACCT_TYPES: usize = 4;
+
+/// Accounting of one type of block requests.
+#[derive(Default, Copy, Clone)]
+struct BlockAcctStats {
+    bytes: u64,
+    operations: u64,
+    failed_operations: u64,
+    /// Requests merged into adjacent requests, which are not counted as operations.
+    merged: u64,
+    total_time_ns: u64,
+    /// Latency histogram, the bins are divided by `LATENCY_HISTOGRAM_BOUNDARIES`.
+    latency_bins: [u64; LATENCY_HISTOGRAM_BOUNDARIES.len() + 1],
+}
+
+impl BlockAcctStats {
+    fn latency_histogram(&self) -> BlockLatencyHistogramInfo {
+        BlockLatencyHistogramInfo {
+            boundaries: LATENCY_HISTOGRAM_BOUNDARIES.to_vec(),
+            bins: self.latency_bins.to_vec(),
+        }
+    }
+}
+
+/// I/O accounting of a block device.
+#[derive(Default)]
+struct BlockIoStats {
+    /// Accounting of each type of requests, indexed by `BlockAcctType`.
+    stats: [BlockAcctStats; BLOCK_ACCT_TYPES],
+    /// Time when the last operation completed.
+    last_done: Option<Instant>,
+}
+
+impl BlockIoStats {
+    fn stats(&self, acct_type: BlockAcctType) -> &BlockAcctStats {
+        &self.stats[acct_type as usize]
+    }
+
+    /// Account a completed operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `acct_type` - Type of the operation.
+    /// * `bytes` - Size of the operation.
+    /// * `start` - Time when the operation was submitted.
+    /// * `failed` - Whether the operation failed.
+    fn account_done(&mut self, acct_type: BlockAcctType, bytes: u64, start: Instant, failed: bool) {
+        let now = Instant::now();
+        let latency = (now - start).as_nanos() as u64;
+        let stats = &mut self.stats[acct_type as usize];
+        if failed {
+            stats.failed_operations += 1;
+        } else {
+            stats.bytes += bytes;
+            stats.operations += 1;
+            stats.total_time_ns += latency;
+            let bin = LATENCY_HISTOGRAM_BOUNDARIES
+                .iter()
+                .position(|boundary| latency < *boundary)
+                .unwrap_or(LATENCY_HISTOGRAM_BOUNDARIES.len());
+            stats.latency_bins[bin] += 1;
+        }
+        self.last_done = Some(now);
+    }
+
+    /// Account a request which is merged into the adjacent request.
+    fn account_merged(&mut self, acct_type: BlockAcctType) {
+        self.stats[acct_type as usize].merged += 1;
+    }
+
+    fn device_stats(&self) -> BlockDeviceStats {
+        let rd = self.stats(BlockAcctType::Read);
+        let wr = self.stats(BlockAcctType::Write);
+        let flush = self.stats(BlockAcctType::Flush);
+        let unmap = self.stats(BlockAcctType::Unmap);
+        BlockDeviceStats {
+            rd_bytes: rd.bytes,
+            wr_bytes: wr.bytes,
+            unmap_bytes: unmap.bytes,
+            rd_operations: rd.operations,
+            wr_operations: wr.operations,
+            flush_operations: flush.operations,
+            unmap_operations: unmap.operations,
+            rd_total_time_ns: rd.total_time_ns,
+            wr_total_time_ns: wr.total_time_ns,
+            flush_total_time_ns: flush.total_time_ns,
+            unmap_total_time_ns: unmap.total_time_ns,
+            rd_merged: rd.merged,
+            wr_merged: wr.merged,
+            unmap_merged: unmap.merged,
+            idle_time_ns: self
+                .last_done
+                .map(|last_done| last_done.elapsed().as_nanos() as u64),
+            failed_rd_operations: rd.failed_operations,
+            failed_wr_operations: wr.failed_operations,
+            failed_flush_operations: flush.failed_operations,
+            failed_unmap_operations: unmap.failed_operations,
+            rd_latency_histogram: rd.latency_histogram(),
+            wr_latency_histogram: wr.latency_histogram(),
+            flush_latency_histogram: flush.latency_histogram(),
+        }
+    }
+}
+
+/// Accounting information of a block request in flight.
+#[derive(Clone)]
+struct BlockAcctCookie {
+    stats: SharedIoStats,
+    acct_type: BlockAcctType,
+    bytes: u64,
+    /// Time when the request was submitted.
+    start: Instant,
+}
+
+impl BlockAcctCookie {
+    fn done(&self, failed: bool) {
+        self.stats
+            .lock()
+            .unwrap()
+            .account_done(self.acct_type, self.bytes, self.start, failed);
+    }
+}
+
 #[derive(Clone)]
 pub struct AioCompleteCb {
     queue: Arc<Mutex<Queue>>,
@@ -109,6 +326,10 @@ pub struct AioCompleteCb {
     req_status_addr: GuestAddress,
     interrupt_cb: Option<Arc<VirtioInterrupt>>,
     driver_features: u64,
+    /// Set if the block request is split into several aio requests.
+    split: Option<Arc<SplitRequest>>,
+    /// Set if the block request is accounted.
+    acct: Option<BlockAcctCookie>,
 }
 
 impl AioCompleteCb {
@@ -129,6 +350,8 @@ impl AioCompleteCb {
             req_status_addr,
             interrupt_cb,
             driver_features,
+            split: None,
+            acct: None,
         }
     }
 }
@@ -140,6 +363,8 @@ struct Request {
     iovec: Vec<Iovec>,
     data_len: u64,
     in_header: GuestAddress,
+    /// Segment of discard or write zeroes request.
+    segment: Option<DiscardWriteZeroesSeg>,
 }
 
 impl Request {
@@ -186,6 +411,7 @@ impl Request {
             iovec: Vec::with_capacity(elem.desc_num as usize),
             data_len: 0,
             in_header: in_iov_elem.addr,
+            segment: None,
         };
 
         match out_header.request_type {
@@ -219,6 +445,22 @@ impl Request {
                     }
                 }
             }
+            VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => {
+                let seg_size = size_of::<DiscardWriteZeroesSeg>() as u32;
+                let seg_elem = elem.out_iovec.get(1).filter(|iov| iov.len == seg_size);
+                if elem.out_iovec.len() != 2 || seg_elem.is_none() {
+                    bail!(
+                        "Invalid segment for block request: type {} out {}",
+                        out_header.request_type,
+                        elem.out_iovec.len()
+                    );
+                }
+                let seg_addr = seg_elem.unwrap().addr;
+                let segment = mem_space
+                    .read_object::<DiscardWriteZeroesSeg>(seg_addr)
+                    .chain_err(|| ErrorKind::ReadObjectErr("the block's segment", seg_addr.0))?;
+                request.segment = Some(segment);
+            }
             _ => (),
         }
 
@@ -231,9 +473,11 @@ impl Request {
         &self,
         aio: &mut Box<Aio<AioCompleteCb>>,
         disk: &File,
+        qcow2: Option<&Arc<Mutex<Qcow2Driver>>>,
         disk_sectors: u64,
         serial_num: &Option<String>,
-        direct: bool,
+        discard: bool,
+        write_zeroes: WriteZeroesState,
         last_aio: bool,
         iocompletecb: AioCompleteCb,
     ) -> Result<u32> {
@@ -256,6 +500,7 @@ impl Request {
             opcode: IoCmd::Noop,
             iovec: Vec::new(),
             offset: (self.out_header.sector << SECTOR_SHIFT) as usize,
+            nbytes: 0,
             process: true,
             iocb: None,
             iocompletecb,
@@ -270,37 +515,90 @@ impl Request {
         }
 
         match self.out_header.request_type {
+            VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT if qcow2.is_some() => {
+                aiocb.opcode = if self.out_header.request_type == VIRTIO_BLK_T_IN {
+                    IoCmd::Preadv
+                } else {
+                    IoCmd::Pwritev
+                };
+                Self::execute_qcow2(aio, qcow2.unwrap(), aiocb)
+                    .chain_err(|| "Failed to process block request for qcow2 image")?;
+            }
             VIRTIO_BLK_T_IN => {
                 aiocb.opcode = IoCmd::Preadv;
-                if direct {
-                    (*aio).as_mut().rw_aio(aiocb, SECTOR_SIZE).chain_err(|| {
-                        "Failed to process block request for reading asynchronously"
-                    })?;
+                (*aio)
+                    .as_mut()
+                    .rw_aio(aiocb, SECTOR_SIZE)
+                    .chain_err(|| "Failed to process block request for reading")?;
+            }
+            VIRTIO_BLK_T_OUT
+                if write_zeroes != WriteZeroesState::Off && iovec_is_zero(&self.iovec) =>
+            {
+                aiocb.opcode = if write_zeroes == WriteZeroesState::Unmap && discard {
+                    IoCmd::WriteZeroesUnmap
                 } else {
-                    (*aio).as_mut().rw_sync(aiocb).chain_err(|| {
-                        "Failed to process block request for reading synchronously"
-                    })?;
-                }
+                    IoCmd::WriteZeroes
+                };
+                aiocb.nbytes = self.data_len;
+                aiocb.iovec.clear();
+                (*aio)
+                    .as_mut()
+                    .rw_aio(aiocb, SECTOR_SIZE)
+                    .chain_err(|| "Failed to process block request for writing zero data")?;
             }
             VIRTIO_BLK_T_OUT => {
                 aiocb.opcode = IoCmd::Pwritev;
-                if direct {
-                    (*aio).as_mut().rw_aio(aiocb, SECTOR_SIZE).chain_err(|| {
-                        "Failed to process block request for writing asynchronously"
-                    })?;
-                } else {
-                    (*aio).as_mut().rw_sync(aiocb).chain_err(|| {
-                        "Failed to process block request for writing synchronously"
-                    })?;
-                }
+                (*aio)
+                    .as_mut()
+                    .rw_aio(aiocb, SECTOR_SIZE)
+                    .chain_err(|| "Failed to process block request for writing")?;
             }
             VIRTIO_BLK_T_FLUSH => {
                 aiocb.opcode = IoCmd::Fdsync;
                 (*aio)
                     .as_mut()
-                    .rw_sync(aiocb)
+                    .rw_aio(aiocb, SECTOR_SIZE)
                     .chain_err(|| "Failed to process block request for flushing")?;
             }
+            VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => {
+                if qcow2.is_some() {
+                    bail!("Discard and write zeroes are not supported for qcow2 image");
+                }
+                let segment = self.segment.unwrap_or_default();
+                if segment.num_sectors > MAX_DISCARD_WRITE_ZEROES_SECTORS {
+                    bail!("Too many sectors {} in segment", segment.num_sectors);
+                }
+                segment
+                    .sector
+                    .checked_add(u64::from(segment.num_sectors))
+                    .filter(|off| off <= &disk_sectors)
+                    .chain_err(|| {
+                        format!(
+                            "segment sector {} invalid, disk sector {}",
+                            segment.sector, disk_sectors
+                        )
+                    })?;
+
+                aiocb.offset = (segment.sector << SECTOR_SHIFT) as usize;
+                aiocb.nbytes = u64::from(segment.num_sectors) << SECTOR_SHIFT;
+                aiocb.iovec.clear();
+                aiocb.opcode = if self.out_header.request_type == VIRTIO_BLK_T_DISCARD {
+                    // Discard is only a hint, just ignore it if it is disabled.
+                    if discard {
+                        IoCmd::Discard
+                    } else {
+                        IoCmd::Noop
+                    }
+                } else if discard && segment.flags & VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0 {
+                    IoCmd::WriteZeroesUnmap
+                } else {
+                    IoCmd::WriteZeroes
+                };
+                (*aio)
+                    .as_mut()
+                    .rw_aio(aiocb, SECTOR_SIZE)
+                    .chain_err(|| "Failed to process block request for discard or write zeroes")?;
+            }
             VIRTIO_BLK_T_GET_ID => {
                 if let Some(serial) = serial_num {
                     let serial_vec = get_serial_num_config(&serial);
@@ -315,6 +613,10 @@ impl Request {
                         }
                         write_buf_mem(&serial_vec, iov.iov_base)
                             .chain_err(|| "Failed to write buf for virtio block id")?;
+                        aiocb
+                            .iocompletecb
+                            .mem_space
+                            .mark_dirty_host(iov.iov_base, serial_vec.len() as u64);
                     }
                 }
 
@@ -328,9 +630,101 @@ impl Request {
         Ok(0)
     }
 
+    /// Submit the request of qcow2 image. Guest data is mapped to host offsets of the
+    /// image, then the request is split into aio requests by the mapped ranges, and it
+    /// is completed after all of them are done.
+    #[allow(clippy::borrowed_box)]
+    fn execute_qcow2(
+        aio: &mut Box<Aio<AioCompleteCb>>,
+        qcow2: &Arc<Mutex<Qcow2Driver>>,
+        mut aiocb: AioCb<AioCompleteCb>,
+    ) -> Result<()> {
+        let offset = aiocb.offset as u64;
+        let mapped = match aiocb.opcode {
+            IoCmd::Preadv => {
+                let mapped = qcow2.lock().unwrap().map_read(offset, &aiocb.iovec)?;
+                // Unallocated clusters are filled synchronously while mapping.
+                for iov in aiocb.iovec.iter() {
+                    aiocb
+                        .iocompletecb
+                        .mem_space
+                        .mark_dirty_host(iov.iov_base, iov.iov_len);
+                }
+                mapped
+            }
+            _ => qcow2.lock().unwrap().map_write(offset, &aiocb.iovec)?,
+        };
+        if mapped.is_empty() {
+            // All data has been handled synchronously, complete the request directly.
+            aiocb.opcode = IoCmd::Noop;
+            aiocb.iovec.clear();
+            return (*aio)
+                .as_mut()
+                .rw_sync(aiocb)
+                .chain_err(|| "Failed to complete qcow2 request");
+        }
+
+        let count = mapped.len();
+        let mut iocompletecb = aiocb.iocompletecb.clone();
+        if count > 1 {
+            iocompletecb.split = Some(Arc::new(SplitRequest {
+                pending: AtomicU32::new(count as u32),
+                failed: AtomicBool::new(false),
+            }));
+        }
+        let split = iocompletecb.split.clone();
+        for (index, (host_offset, iovec)) in mapped.into_iter().enumerate() {
+            let cb = AioCb {
+                last_aio: aiocb.last_aio && index == count - 1,
+                file_fd: aiocb.file_fd,
+                opcode: aiocb.opcode,
+                iovec,
+                offset: host_offset as usize,
+                nbytes: 0,
+                process: true,
+                iocb: None,
+                iocompletecb: iocompletecb.clone(),
+            };
+            if let Err(e) = (*aio).as_mut().rw_aio(cb, SECTOR_SIZE) {
+                // The request is completed by the last completion of the submitted aio
+                // requests, or by the caller if none of them is pending.
+                if let Some(split) = split.as_ref() {
+                    let unsubmitted = (count - index) as u32;
+                    split.failed.store(true, Ordering::SeqCst);
+                    if split.pending.fetch_sub(unsubmitted, Ordering::SeqCst) != unsubmitted {
+                        error!(
+                            "Failed to process qcow2 request, {}",
+                            error_chain::ChainedError::display_chain(&e)
+                        );
+                        return Ok(());
+                    }
+                }
+                return Err(e).chain_err(|| "Failed to process qcow2 request");
+            }
+        }
+
+        Ok(())
+    }
+
     fn get_req_sector_num(&self) -> u64 {
         self.data_len / SECTOR_SIZE
     }
+
+    /// Get the accounting type and size of the request, `None` if it is not accounted.
+    fn acct_info(&self) -> Option<(BlockAcctType, u64)> {
+        let segment_bytes = || {
+            self.segment
+                .map_or(0, |segment| u64::from(segment.num_sectors) << SECTOR_SHIFT)
+        };
+        match self.out_header.request_type {
+            VIRTIO_BLK_T_IN => Some((BlockAcctType::Read, self.data_len)),
+            VIRTIO_BLK_T_OUT => Some((BlockAcctType::Write, self.data_len)),
+            VIRTIO_BLK_T_FLUSH => Some((BlockAcctType::Flush, 0)),
+            VIRTIO_BLK_T_DISCARD => Some((BlockAcctType::Unmap, segment_bytes())),
+            VIRTIO_BLK_T_WRITE_ZEROES => Some((BlockAcctType::Write, segment_bytes())),
+            _ => None,
+        }
+    }
 }
 
 /// Control block of Block IO.
@@ -343,12 +737,16 @@ struct BlockIoHandler {
     mem_space: Arc<AddressSpace>,
     /// The image file opened by the block device.
     disk_image: Option<Arc<File>>,
+    /// The qcow2 driver if the disk image is qcow2 format.
+    qcow2: Option<Arc<Mutex<Qcow2Driver>>>,
     /// The number of sectors of the disk image.
     disk_sectors: u64,
     /// Serial number of the block device.
     serial_num: Option<String>,
-    /// if use direct access io.
-    direct: bool,
+    /// Whether discard is enabled.
+    discard: bool,
+    /// How to handle the write requests with all-zero data.
+    write_zeroes: WriteZeroesState,
     /// Aio context.
     aio: Option<Box<Aio<AioCompleteCb>>>,
     /// Bit mask of features negotiated by the backend and the frontend.
@@ -363,8 +761,14 @@ struct BlockIoHandler {
     interrupt_cb: Arc<VirtioInterrupt>,
     /// thread name of io handler
     iothread: Option<String>,
-    /// Using the leak bucket to implement IO limits
-    leak_bucket: Option<LeakBucket>,
+    /// The throttle to implement IO limits, shared with the device.
+    throttle: SharedThrottle,
+    /// Timer to wake up the handler when the throttle allows more requests.
+    throttle_timer: ThrottleTimer,
+    /// The I/O accounting, shared with the device.
+    io_stats: SharedIoStats,
+    /// Counters of notifications of the transport.
+    notify_stats: Arc<VirtioNotifyStats>,
 }
 
 impl BlockIoHandler {
@@ -386,6 +790,9 @@ impl BlockIoHandler {
                         continue_merge = false;
                         merge_req_queue.push(req.clone());
                     } else {
+                        if let Some((acct_type, _)) = req.acct_info() {
+                            self.io_stats.lock().unwrap().account_merged(acct_type);
+                        }
                         for iov in req.iovec.iter() {
                             let iovec = Iovec {
                                 iov_base: iov.iov_base,
@@ -414,28 +821,20 @@ impl BlockIoHandler {
         let mut queue = self.queue.lock().unwrap();
 
         while let Ok(elem) = queue.vring.pop_avail(&self.mem_space, self.driver_features) {
-            // limit io operations if iops is configured
-            if let Some(lb) = self.leak_bucket.as_mut() {
-                if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
-                    if lb.throttled(ctx, 1_u64) {
+            match Request::new(&self.mem_space, &elem) {
+                Ok(req) => {
+                    // limit io operations if throttling is configured
+                    if Self::throttled(
+                        &self.throttle,
+                        &mut self.throttle_timer,
+                        self.iothread.as_ref(),
+                        &req,
+                    )? {
                         queue.vring.push_back();
                         break;
                     }
-                } else {
-                    bail!(
-                        "IOThread {:?} of Block is not found in cmdline.",
-                        self.iothread,
-                    );
-                };
-            }
-
-            match Request::new(&self.mem_space, &elem) {
-                Ok(req) => {
-                    match req.out_header.request_type {
-                        VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT => {
-                            last_aio_req_index = req_index;
-                        }
-                        _ => {}
+                    if req.out_header.request_type != VIRTIO_BLK_T_GET_ID {
+                        last_aio_req_index = req_index;
                     }
                     req_queue.push(req);
                     req_index += 1;
@@ -472,7 +871,7 @@ impl BlockIoHandler {
                         _ => 0u32,
                     };
 
-                    let aiocompletecb = AioCompleteCb::new(
+                    let mut aiocompletecb = AioCompleteCb::new(
                         self.queue.clone(),
                         self.mem_space.clone(),
                         req.desc_index,
@@ -481,13 +880,24 @@ impl BlockIoHandler {
                         Some(self.interrupt_cb.clone()),
                         self.driver_features,
                     );
+                    let io_stats = &self.io_stats;
+                    aiocompletecb.acct =
+                        req.acct_info().map(|(acct_type, bytes)| BlockAcctCookie {
+                            stats: io_stats.clone(),
+                            acct_type,
+                            bytes,
+                            start: Instant::now(),
+                        });
+                    let acct = aiocompletecb.acct.clone();
 
                     match req.execute(
                         aio,
                         disk_img,
+                        self.qcow2.as_ref(),
                         self.disk_sectors,
                         &self.serial_num,
-                        self.direct,
+                        self.discard,
+                        self.write_zeroes,
                         last_aio_req_index == req_index,
                         aiocompletecb,
                     ) {
@@ -515,10 +925,32 @@ impl BlockIoHandler {
                             }
                         }
                         Err(ref e) => {
+                            if let Some(acct) = acct {
+                                acct.done(true);
+                            }
                             error!(
                                 "Failed to execute block request, {}",
                                 error_chain::ChainedError::display_chain(e)
                             );
+                            // The request is not submitted, complete it with IO error.
+                            self.mem_space
+                                .write_object(&VIRTIO_BLK_S_IOERR, req.in_header)
+                                .chain_err(|| {
+                                    "Failed to write result for the failed block request"
+                                })?;
+                            let mut locked_queue = self.queue.lock().unwrap();
+                            locked_queue
+                                .vring
+                                .add_used(&self.mem_space, req.desc_index, 0)
+                                .chain_err(|| {
+                                    "Failed to add the failed block request to used ring"
+                                })?;
+                            if locked_queue
+                                .vring
+                                .should_notify(&self.mem_space, self.driver_features)
+                            {
+                                need_interrupt = true;
+                            }
                         }
                     }
                     req_index += 1;
@@ -549,15 +981,70 @@ impl BlockIoHandler {
         Ok(done)
     }
 
-    fn build_aio(&self) -> Result<Box<Aio<AioCompleteCb>>> {
+    /// Return true if the request must wait for the throttle, otherwise the request
+    /// is accounted to the throttle.
+    fn throttled(
+        throttle: &SharedThrottle,
+        throttle_timer: &mut ThrottleTimer,
+        iothread: Option<&String>,
+        req: &Request,
+    ) -> Result<bool> {
+        let is_write = match req.out_header.request_type {
+            VIRTIO_BLK_T_IN => false,
+            VIRTIO_BLK_T_OUT => true,
+            _ => return Ok(false),
+        };
+        let throttle = match throttle.lock().unwrap().as_ref() {
+            Some(throttle) => throttle.clone(),
+            None => return Ok(false),
+        };
+        let ctx = match EventLoop::get_ctx(iothread) {
+            Some(ctx) => ctx,
+            None => bail!("IOThread {:?} of Block is not found in cmdline.", iothread),
+        };
+
+        let mut locked_throttle = throttle.lock().unwrap();
+        if throttle_timer.throttled(&mut locked_throttle, ctx, is_write) {
+            return Ok(true);
+        }
+        locked_throttle.account(is_write, req.data_len);
+        Ok(false)
+    }
+
+    fn build_aio(&self, engine: AioEngine) -> Result<Box<Aio<AioCompleteCb>>> {
         let complete_func = Arc::new(Box::new(move |aiocb: &AioCb<AioCompleteCb>, ret: i64| {
+            let complete_cb = &aiocb.iocompletecb;
+            if let IoCmd::Preadv = aiocb.opcode {
+                // Data is read into guest memory through host address.
+                for iov in aiocb.iovec.iter() {
+                    complete_cb
+                        .mem_space
+                        .mark_dirty_host(iov.iov_base, iov.iov_len);
+                }
+            }
+            let mut ret = ret;
+            if let Some(split) = complete_cb.split.as_ref() {
+                if ret < 0 {
+                    split.failed.store(true, Ordering::SeqCst);
+                }
+                if split.pending.fetch_sub(1, Ordering::SeqCst) != 1 {
+                    return;
+                }
+                if split.failed.load(Ordering::SeqCst) {
+                    ret = -1;
+                }
+            }
+
+            if let Some(acct) = complete_cb.acct.as_ref() {
+                acct.done(ret < 0);
+            }
+
             let status = if ret < 0 {
                 ret
             } else {
                 i64::from(VIRTIO_BLK_S_OK)
             };
 
-            let complete_cb = &aiocb.iocompletecb;
             if let Err(ref e) = complete_cb
                 .mem_space
                 .write_object(&status, complete_cb.req_status_addr)
@@ -600,22 +1087,34 @@ impl BlockIoHandler {
             }
         }) as AioCompleteFunc<AioCompleteCb>);
 
-        Ok(Box::new(Aio::new(complete_func)?))
+        Ok(Box::new(Aio::new(complete_func, engine)?))
     }
 
     fn update_evt_handler(&mut self) {
         match self.receiver.recv() {
-            Ok((image, disk_sectors, serial_num, direct)) => {
+            Ok((image, disk_sectors, serial_num, aio_engine, qcow2, discard, write_zeroes)) => {
                 self.disk_sectors = disk_sectors;
                 self.disk_image = image;
                 self.serial_num = serial_num;
-                self.direct = direct;
+                if let Some(aio) = self.aio.as_mut() {
+                    if let Err(ref e) = aio.set_engine(aio_engine) {
+                        error!(
+                            "Failed to update aio engine of block {}",
+                            error_chain::ChainedError::display_chain(e)
+                        );
+                    }
+                }
+                self.qcow2 = qcow2;
+                self.discard = discard;
+                self.write_zeroes = write_zeroes;
             }
             Err(_) => {
                 self.disk_sectors = 0;
                 self.disk_image = None;
+                self.qcow2 = None;
                 self.serial_num = None;
-                self.direct = true;
+                self.discard = false;
+                self.write_zeroes = WriteZeroesState::Off;
             }
         };
 
@@ -650,16 +1149,14 @@ impl BlockIoHandler {
                 EventSet::IN,
                 Vec::new(),
             ),
-        ];
-        if let Some(lb) = self.leak_bucket.as_ref() {
-            notifiers.push(EventNotifier::new(
+            EventNotifier::new(
                 NotifierOperation::Delete,
-                lb.as_raw_fd(),
+                self.throttle_timer.as_raw_fd(),
                 None,
                 EventSet::IN,
                 Vec::new(),
-            ));
-        }
+            ),
+        ];
         if let Some(aio) = &self.aio {
             notifiers.push(EventNotifier::new(
                 NotifierOperation::Delete,
@@ -708,8 +1205,9 @@ impl EventNotifierHelper for BlockIoHandler {
 
         // Register event notifier for queue_evt.
         let h_clone = handler.clone();
+        let notify_stats = handler_raw.notify_stats.clone();
         let h: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            notify_stats.account_queue_notifies(read_fd(fd));
 
             if let Err(ref e) = h_clone.lock().unwrap().process_queue() {
                 error!(
@@ -749,26 +1247,25 @@ impl EventNotifierHelper for BlockIoHandler {
 
         notifiers.push(e);
 
-        // Register timer event notifier for IO limits
-        if let Some(lb) = handler_raw.leak_bucket.as_ref() {
-            let h_clone = handler.clone();
-            let h: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
-                read_fd(fd);
-
-                if let Some(lb) = h_clone.lock().unwrap().leak_bucket.as_mut() {
-                    lb.clear_timer();
-                }
+        // Register timer event notifier for IO limits, the limits may be set on the fly.
+        let h_clone = handler.clone();
+        let h: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
 
-                if let Err(ref e) = h_clone.lock().unwrap().process_queue() {
-                    error!(
-                        "Failed to handle block IO {}",
-                        error_chain::ChainedError::display_chain(e)
-                    );
-                }
-                None
-            });
-            notifiers.push(build_event_notifier(lb.as_raw_fd(), h));
-        }
+            let mut locked_handler = h_clone.lock().unwrap();
+            locked_handler.throttle_timer.clear_timer();
+            if let Err(ref e) = locked_handler.process_queue() {
+                error!(
+                    "Failed to handle block IO {}",
+                    error_chain::ChainedError::display_chain(e)
+                );
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(
+            handler_raw.throttle_timer.as_raw_fd(),
+            h,
+        ));
 
         // Register event notifier for aio.
         if let Some(ref aio) = handler_raw.aio {
@@ -822,14 +1319,16 @@ impl EventNotifierHelper for BlockIoHandler {
 /// State of block device.
 #[repr(C)]
 #[derive(Clone, Copy, Desc, ByteCode)]
-#[desc_version(compat_version = "0.1.0")]
+#[desc_version(current_version = "2.2.0", compat_version = "0.1.0")]
 pub struct BlockState {
     /// Bitmask of features supported by the backend.
     device_features: u64,
     /// Bit mask of features negotiated byu the backend and the frontend.
     driver_features: u64,
-    /// Config space of the block device.
-    config_space: [u8; 16],
+    /// Config space of the block device. It is 16 bytes before version 2.2.0,
+    /// the fields after them are zero when restored from the old versions, as
+    /// the features using them are not offered.
+    config_space: [u8; 60],
 }
 
 /// Block device structure.
@@ -838,6 +1337,8 @@ pub struct Block {
     blk_cfg: BlkDevConfig,
     /// Image file opened.
     disk_image: Option<Arc<File>>,
+    /// The qcow2 driver if the image file is qcow2 format.
+    qcow2: Option<Arc<Mutex<Qcow2Driver>>>,
     /// Number of sectors of the image file.
     disk_sectors: u64,
     /// Status of block device.
@@ -850,6 +1351,12 @@ pub struct Block {
     update_evt: EventFd,
     /// Eventfd for device deactivate.
     deactivate_evt: EventFd,
+    /// The throttle to limit IO, shared with the IO handler.
+    throttle: SharedThrottle,
+    /// The I/O accounting, shared with the IO handler.
+    io_stats: SharedIoStats,
+    /// Counters of notifications of the transport.
+    notify_stats: Arc<VirtioNotifyStats>,
 }
 
 impl Default for Block {
@@ -857,12 +1364,16 @@ impl Default for Block {
         Block {
             blk_cfg: Default::default(),
             disk_image: None,
+            qcow2: None,
             disk_sectors: 0,
             state: BlockState::default(),
             interrupt_cb: None,
             sender: None,
             update_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
             deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            throttle: Arc::new(Mutex::new(None)),
+            io_stats: Arc::new(Mutex::new(BlockIoStats::default())),
+            notify_stats: Arc::new(VirtioNotifyStats::default()),
         }
     }
 }
@@ -872,13 +1383,97 @@ impl Block {
         Self {
             blk_cfg,
             disk_image: None,
+            qcow2: None,
             disk_sectors: 0,
             state: BlockState::default(),
             interrupt_cb: None,
             sender: None,
             update_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
             deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            throttle: Arc::new(Mutex::new(None)),
+            io_stats: Arc::new(Mutex::new(BlockIoStats::default())),
+            notify_stats: Arc::new(VirtioNotifyStats::default()),
+        }
+    }
+
+    /// Set the limits of IO throttling, which take effect immediately.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The limits, IO is not limited if no limit is set.
+    /// * `group` - The throttle group, the devices in the same group share the limits.
+    fn set_io_throttle(&mut self, config: ThrottleConfig, group: Option<String>) {
+        *self.throttle.lock().unwrap() = get_throttle(config, group.as_ref());
+        self.blk_cfg.throttle = config;
+        self.blk_cfg.throttle_group = group;
+    }
+
+    /// Get the name of the block backend, which is the node name if it is set.
+    fn backend_name(&self) -> &str {
+        if self.blk_cfg.node_name.is_empty() {
+            &self.blk_cfg.id
+        } else {
+            &self.blk_cfg.node_name
+        }
+    }
+
+    /// Get the information of the image, `None` if no image is inserted.
+    fn block_device_info(&self) -> Option<BlockDeviceInfo> {
+        if self.blk_cfg.path_on_host.is_empty() {
+            return None;
         }
+
+        let throttle = &self.blk_cfg.throttle;
+        let avg = |bucket_type| throttle.bucket(bucket_type).avg;
+        let max = |bucket_type| Some(throttle.bucket(bucket_type).max).filter(|max| *max != 0);
+        let max_length = |bucket_type| {
+            Some(throttle.bucket(bucket_type).max_length).filter(|length| *length != 0)
+        };
+        Some(BlockDeviceInfo {
+            file: self.blk_cfg.path_on_host.clone(),
+            node_name: self.backend_name().to_string(),
+            ro: self.blk_cfg.read_only,
+            drv: match self.blk_cfg.format {
+                DiskFormat::Raw => "raw",
+                DiskFormat::Qcow2 => "qcow2",
+            }
+            .to_string(),
+            encrypted: false,
+            detect_zeroes: match self.blk_cfg.write_zeroes {
+                WriteZeroesState::Off => "off",
+                WriteZeroesState::On => "on",
+                WriteZeroesState::Unmap => "unmap",
+            }
+            .to_string(),
+            bps: avg(BucketType::BpsTotal),
+            bps_rd: avg(BucketType::BpsRead),
+            bps_wr: avg(BucketType::BpsWrite),
+            iops: avg(BucketType::IopsTotal),
+            iops_rd: avg(BucketType::IopsRead),
+            iops_wr: avg(BucketType::IopsWrite),
+            bps_max: max(BucketType::BpsTotal),
+            bps_rd_max: max(BucketType::BpsRead),
+            bps_wr_max: max(BucketType::BpsWrite),
+            iops_max: max(BucketType::IopsTotal),
+            iops_rd_max: max(BucketType::IopsRead),
+            iops_wr_max: max(BucketType::IopsWrite),
+            bps_max_length: max_length(BucketType::BpsTotal),
+            bps_rd_max_length: max_length(BucketType::BpsRead),
+            bps_wr_max_length: max_length(BucketType::BpsWrite),
+            iops_max_length: max_length(BucketType::IopsTotal),
+            iops_rd_max_length: max_length(BucketType::IopsRead),
+            iops_wr_max_length: max_length(BucketType::IopsWrite),
+            iops_size: Some(throttle.iops_size).filter(|size| *size != 0),
+            group: self.blk_cfg.throttle_group.clone(),
+            // Writes are completed once they reach the host page cache or the
+            // disk, and flush requests are always passed to the image.
+            cache: BlockdevCacheInfo {
+                writeback: true,
+                direct: self.blk_cfg.direct,
+                no_flush: false,
+            },
+            write_threshold: 0,
+        })
     }
 
     fn build_device_config_space(&mut self) {
@@ -892,6 +1487,27 @@ impl Block {
         for i in 0..4 {
             self.state.config_space[12 + i] = (126 >> (8 * i)) as u8;
         }
+
+        // max_discard_sectors, max_discard_seg, discard_sector_alignment: 32bits
+        let discard_limits = [
+            MAX_DISCARD_WRITE_ZEROES_SECTORS,
+            MAX_DISCARD_WRITE_ZEROES_SEG,
+            1,
+        ];
+        // max_write_zeroes_sectors, max_write_zeroes_seg: 32bits
+        let write_zeroes_limits = [
+            MAX_DISCARD_WRITE_ZEROES_SECTORS,
+            MAX_DISCARD_WRITE_ZEROES_SEG,
+        ];
+        for (i, limit) in discard_limits
+            .iter()
+            .chain(write_zeroes_limits.iter())
+            .enumerate()
+        {
+            self.state.config_space[36 + 4 * i..40 + 4 * i].copy_from_slice(&limit.to_le_bytes());
+        }
+        // write_zeroes_may_unmap: 8bits
+        self.state.config_space[56] = self.blk_cfg.discard as u8;
     }
 }
 
@@ -916,8 +1532,16 @@ impl VirtioDevice for Block {
         self.state.device_features |= 1_u64 << VIRTIO_BLK_F_SIZE_MAX;
         self.state.device_features |= 1_u64 << VIRTIO_BLK_F_SEG_MAX;
         self.state.device_features |= 1_u64 << VIRTIO_F_RING_EVENT_IDX;
+        // Discard and write zeroes are only supported for raw image.
+        if !self.blk_cfg.read_only && self.blk_cfg.format == DiskFormat::Raw {
+            if self.blk_cfg.discard {
+                self.state.device_features |= 1_u64 << VIRTIO_BLK_F_DISCARD;
+            }
+            self.state.device_features |= 1_u64 << VIRTIO_BLK_F_WRITE_ZEROES;
+        }
 
         self.build_device_config_space();
+        self.set_io_throttle(self.blk_cfg.throttle, self.blk_cfg.throttle_group.clone());
 
         let mut disk_size = DUMMY_IMG_SIZE;
 
@@ -953,9 +1577,23 @@ impl VirtioDevice for Block {
                 .seek(SeekFrom::End(0))
                 .chain_err(|| "Failed to seek the end for block")? as u64;
 
+            self.qcow2 = None;
+            if self.blk_cfg.format == DiskFormat::Qcow2 {
+                let qcow2 = Qcow2Driver::open(
+                    Path::new(&self.blk_cfg.path_on_host),
+                    self.blk_cfg.read_only,
+                )
+                .chain_err(|| {
+                    format!("Failed to open qcow2 image {}", self.blk_cfg.path_on_host)
+                })?;
+                disk_size = qcow2.virtual_size();
+                self.qcow2 = Some(Arc::new(Mutex::new(qcow2)));
+            }
+
             self.disk_image = Some(Arc::new(file));
         } else {
             self.disk_image = None;
+            self.qcow2 = None;
         }
 
         self.disk_sectors = disk_size >> SECTOR_SHIFT;
@@ -1047,8 +1685,10 @@ impl VirtioDevice for Block {
             queue_evt: queue_evts.remove(0),
             mem_space,
             disk_image: self.disk_image.clone(),
+            qcow2: self.qcow2.clone(),
             disk_sectors: self.disk_sectors,
-            direct: self.blk_cfg.direct,
+            discard: self.blk_cfg.discard,
+            write_zeroes: self.blk_cfg.write_zeroes,
             serial_num: self.blk_cfg.serial_num.clone(),
             aio: None,
             driver_features: self.state.driver_features,
@@ -1057,10 +1697,13 @@ impl VirtioDevice for Block {
             deactivate_evt: self.deactivate_evt.as_raw_fd(),
             interrupt_cb,
             iothread: self.blk_cfg.iothread.clone(),
-            leak_bucket: self.blk_cfg.iops.map(LeakBucket::new),
+            throttle: self.throttle.clone(),
+            throttle_timer: ThrottleTimer::default(),
+            io_stats: self.io_stats.clone(),
+            notify_stats: self.notify_stats.clone(),
         };
 
-        handler.aio = Some(handler.build_aio()?);
+        handler.aio = Some(handler.build_aio(self.blk_cfg.aio)?);
 
         EventLoop::update_event(
             EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler))),
@@ -1076,6 +1719,10 @@ impl VirtioDevice for Block {
             .chain_err(|| ErrorKind::EventFdWrite)
     }
 
+    fn set_notify_stats(&mut self, stats: Arc<VirtioNotifyStats>) {
+        self.notify_stats = stats;
+    }
+
     fn update_config(&mut self, dev_config: Option<Arc<dyn ConfigCheck>>) -> Result<()> {
         if let Some(conf) = dev_config {
             self.blk_cfg = conf
@@ -1086,6 +1733,8 @@ impl VirtioDevice for Block {
         } else {
             self.blk_cfg = Default::default();
         }
+        // The statistics are of the replaced image.
+        *self.io_stats.lock().unwrap() = BlockIoStats::default();
 
         self.realize()?;
 
@@ -1095,7 +1744,10 @@ impl VirtioDevice for Block {
                     self.disk_image.take(),
                     self.disk_sectors,
                     self.blk_cfg.serial_num.clone(),
-                    self.blk_cfg.direct,
+                    self.blk_cfg.aio,
+                    self.qcow2.take(),
+                    self.blk_cfg.discard,
+                    self.blk_cfg.write_zeroes,
                 ))
                 .chain_err(|| ErrorKind::ChannelSend("image fd".to_string()))?;
 
@@ -1113,6 +1765,114 @@ impl VirtioDevice for Block {
     }
 }
 
+/// Get the throttle with the limits, the throttle of the group is shared and its
+/// limits are updated.
+fn get_throttle(config: ThrottleConfig, group: Option<&String>) -> Option<Arc<Mutex<Throttle>>> {
+    if !config.enabled() {
+        return None;
+    }
+    let name = match group {
+        Some(name) => name,
+        None => return Some(Arc::new(Mutex::new(Throttle::new(config)))),
+    };
+
+    let mut groups = THROTTLE_GROUPS.lock().unwrap();
+    // Drop the groups which have no device.
+    groups.retain(|_, throttle| throttle.strong_count() > 0);
+    if let Some(throttle) = groups.get(name).and_then(|throttle| throttle.upgrade()) {
+        throttle.lock().unwrap().set_config(config);
+        return Some(throttle);
+    }
+    let throttle = Arc::new(Mutex::new(Throttle::new(config)));
+    groups.insert(name.clone(), Arc::downgrade(&throttle));
+    Some(throttle)
+}
+
+/// Register the block device, so that its IO throttle can be changed by
+/// `block_set_io_throttle`.
+pub fn register_block_device(block: &Arc<Mutex<Block>>) {
+    BLOCK_DEVICES.lock().unwrap().push(Arc::downgrade(block));
+}
+
+/// Set the IO throttle of the block device with the id `name`.
+///
+/// # Arguments
+///
+/// * `name` - The id of the block device.
+/// * `config` - The limits, IO is not limited if no limit is set.
+/// * `group` - The throttle group, the devices in the same group share the limits.
+pub fn qmp_block_set_io_throttle(
+    name: &str,
+    config: ThrottleConfig,
+    group: Option<String>,
+) -> Result<()> {
+    let mut block_devices = BLOCK_DEVICES.lock().unwrap();
+    // Drop the devices which have been removed.
+    block_devices.retain(|block| block.strong_count() > 0);
+    for block in block_devices.iter().filter_map(|block| block.upgrade()) {
+        let mut locked_block = block.lock().unwrap();
+        if !name.is_empty()
+            && (locked_block.blk_cfg.id == name || locked_block.blk_cfg.node_name == name)
+        {
+            locked_block.set_io_throttle(config, group);
+            return Ok(());
+        }
+    }
+
+    bail!("Block device {} not found", name);
+}
+
+/// Call `f` with each registered block device which has an id.
+fn for_each_block_device<F: FnMut(&Block)>(mut f: F) {
+    let mut block_devices = BLOCK_DEVICES.lock().unwrap();
+    // Drop the devices which have been removed.
+    block_devices.retain(|block| block.strong_count() > 0);
+    for block in block_devices.iter().filter_map(|block| block.upgrade()) {
+        let locked_block = block.lock().unwrap();
+        if !locked_block.blk_cfg.id.is_empty() {
+            f(&locked_block);
+        }
+    }
+}
+
+/// Get the information of the block devices for `query-block`.
+pub fn qmp_query_block() -> Vec<BlockInfo> {
+    let mut infos = Vec::new();
+    for_each_block_device(|block| {
+        infos.push(BlockInfo {
+            device: block.backend_name().to_string(),
+            qdev: Some(block.blk_cfg.id.clone()),
+            block_type: "unknown".to_string(),
+            removable: false,
+            locked: false,
+            inserted: block.block_device_info(),
+        })
+    });
+    infos
+}
+
+/// Get the information of the images for `query-named-block-nodes`.
+pub fn qmp_query_named_block_nodes() -> Vec<BlockDeviceInfo> {
+    let mut infos = Vec::new();
+    for_each_block_device(|block| infos.extend(block.block_device_info()));
+    infos
+}
+
+/// Get the I/O statistics of the block devices for `query-blockstats`.
+pub fn qmp_query_blockstats() -> Vec<BlockStats> {
+    let mut stats = Vec::new();
+    for_each_block_device(|block| {
+        stats.push(BlockStats {
+            device: block.backend_name().to_string(),
+            qdev: Some(block.blk_cfg.id.clone()),
+            node_name: Some(block.backend_name().to_string())
+                .filter(|_| !block.blk_cfg.path_on_host.is_empty()),
+            stats: block.io_stats.lock().unwrap().device_stats(),
+        })
+    });
+    stats
+}
+
 // Send and Sync is not auto-implemented for `Sender` type.
 // Implementing them is safe because `Sender` field of Block won't change in migration
 // workflow.
@@ -1149,9 +1909,10 @@ mod tests {
     use machine_manager::config::IothreadConfig;
     use std::sync::atomic::{AtomicU32, Ordering};
     use std::{thread, time::Duration};
+    use util::throttle::{BucketConfig, BucketType};
     use vmm_sys_util::tempfile::TempFile;
 
-    const CONFIG_SPACE_SIZE: usize = 16;
+    const CONFIG_SPACE_SIZE: usize = 60;
     const VIRTQ_DESC_F_NEXT: u16 = 0x01;
     const VIRTQ_DESC_F_WRITE: u16 = 0x02;
     const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
@@ -1190,7 +1951,7 @@ mod tests {
         assert_eq!(block.disk_sectors, 0);
         assert_eq!(block.state.device_features, 0);
         assert_eq!(block.state.driver_features, 0);
-        assert_eq!(block.state.config_space.len(), 16);
+        assert_eq!(block.state.config_space.len(), CONFIG_SPACE_SIZE);
         assert!(block.disk_image.is_none());
         assert!(block.interrupt_cb.is_none());
         assert!(block.sender.is_none());
@@ -1207,6 +1968,22 @@ mod tests {
         assert_eq!(block.queue_size(), QUEUE_SIZE_BLK);
     }
 
+    // Verify the block state of old version with 16 bytes config space can be restored.
+    #[test]
+    fn test_block_state_compat() {
+        // The config space of old version is 16 bytes.
+        let state = pad_compat_state(
+            &BlockState::descriptor(),
+            &[("config_space", Some(16))],
+            &[0xff_u8; 32],
+        );
+        let state = BlockState::from_bytes(&state).unwrap();
+        assert_eq!(state.device_features, !0);
+        assert_eq!(state.driver_features, !0);
+        assert_eq!(state.config_space[..16], [0xff_u8; 16]);
+        assert_eq!(state.config_space[16..], [0_u8; 44]);
+    }
+
     // Test `write_config` and `read_config`. The main contests include: compare expect data and
     // read date are same; Input invalid offset or date length, it will failed.
     #[test]
@@ -1224,7 +2001,7 @@ mod tests {
         assert!(block
             .write_config(CONFIG_SPACE_SIZE as u64 + 1, &expect_config_space)
             .is_err());
-        let errlen_config_space = [0u8; 17];
+        let errlen_config_space = [0u8; CONFIG_SPACE_SIZE + 1];
         assert!(block.write_config(0, &errlen_config_space).is_err());
         // Invalid read
         read_config_space = expect_config_space;
@@ -1281,6 +2058,111 @@ mod tests {
         block.state.driver_features = 0;
     }
 
+    // Test the features and config space of discard and write zeroes.
+    #[test]
+    fn test_discard_write_zeroes_config() {
+        let mut block = Block::default();
+        let file = TempFile::new().unwrap();
+        block.blk_cfg.path_on_host = file.as_path().to_str().unwrap().to_string();
+        block.blk_cfg.direct = false;
+        assert!(block.realize().is_ok());
+        assert_eq!(
+            block.state.device_features & (1_u64 << VIRTIO_BLK_F_DISCARD),
+            0
+        );
+        assert_ne!(
+            block.state.device_features & (1_u64 << VIRTIO_BLK_F_WRITE_ZEROES),
+            0
+        );
+        assert_eq!(block.state.config_space[56], 0);
+
+        block.blk_cfg.discard = true;
+        assert!(block.realize().is_ok());
+        assert_ne!(
+            block.state.device_features & (1_u64 << VIRTIO_BLK_F_DISCARD),
+            0
+        );
+        let mut max_discard_seg = [0_u8; 4];
+        block.read_config(40, &mut max_discard_seg).unwrap();
+        assert_eq!(
+            u32::from_le_bytes(max_discard_seg),
+            MAX_DISCARD_WRITE_ZEROES_SEG
+        );
+        assert_eq!(block.state.config_space[56], 1);
+
+        // Discard and write zeroes are not supported for read-only device.
+        block.blk_cfg.read_only = true;
+        assert!(block.realize().is_ok());
+        assert_eq!(
+            block.state.device_features & (1_u64 << VIRTIO_BLK_F_DISCARD),
+            0
+        );
+        assert_eq!(
+            block.state.device_features & (1_u64 << VIRTIO_BLK_F_WRITE_ZEROES),
+            0
+        );
+    }
+
+    // Test the I/O accounting and the reported image information.
+    #[test]
+    fn test_block_io_stats() {
+        let mut stats = BlockIoStats::default();
+        let start = Instant::now() - Duration::from_millis(2);
+        stats.account_done(BlockAcctType::Read, 4096, start, false);
+        stats.account_done(BlockAcctType::Read, 512, start, true);
+        stats.account_done(BlockAcctType::Flush, 0, Instant::now(), false);
+        stats.account_merged(BlockAcctType::Write);
+
+        let device_stats = stats.device_stats();
+        assert_eq!(device_stats.rd_bytes, 4096);
+        assert_eq!(device_stats.rd_operations, 1);
+        assert_eq!(device_stats.failed_rd_operations, 1);
+        assert!(device_stats.rd_total_time_ns >= 2_000_000);
+        // 2ms is in the bin of [1ms, 10ms).
+        assert_eq!(
+            device_stats.rd_latency_histogram.bins,
+            [0, 0, 0, 1, 0, 0, 0]
+        );
+        assert_eq!(device_stats.flush_operations, 1);
+        assert_eq!(device_stats.wr_operations, 0);
+        assert_eq!(device_stats.wr_merged, 1);
+        assert!(device_stats.idle_time_ns.is_some());
+
+        let mut block = Block::default();
+        assert!(block.block_device_info().is_none());
+        block.blk_cfg.id = "drive-0".to_string();
+        block.blk_cfg.path_on_host = "/path/to/image".to_string();
+        block.blk_cfg.read_only = true;
+        block.set_io_throttle(
+            ThrottleConfig {
+                buckets: [
+                    BucketConfig::default(),
+                    BucketConfig {
+                        avg: 1000,
+                        max: 2000,
+                        max_length: 0,
+                    },
+                    BucketConfig::default(),
+                    BucketConfig::default(),
+                    BucketConfig::default(),
+                    BucketConfig::default(),
+                ],
+                iops_size: 0,
+            },
+            None,
+        );
+        let info = block.block_device_info().unwrap();
+        assert_eq!(info.node_name, "drive-0");
+        assert_eq!(info.file, "/path/to/image");
+        assert!(info.ro);
+        assert_eq!(info.drv, "raw");
+        assert_eq!(info.bps_rd, 1000);
+        assert_eq!(info.bps_rd_max, Some(2000));
+        assert_eq!(info.bps_rd_max_length, None);
+        assert_eq!(info.bps_wr, 0);
+        assert_eq!(info.bps_wr_max, None);
+    }
+
     // Test `get_serial_num_config`. The function will output the shorter length between 20
     // with serial_num length.
     #[test]
@@ -1321,7 +2203,7 @@ mod tests {
 
         // config iothread and iops
         block.blk_cfg.iothread = Some(thread_name);
-        block.blk_cfg.iops = Some(100);
+        block.blk_cfg.throttle.bucket_mut(BucketType::IopsTotal).avg = 100;
 
         let mem_space = address_space_init();
         let interrupt_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
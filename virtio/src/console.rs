@@ -10,19 +10,24 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::VecDeque;
 use std::io::Write;
+use std::mem::size_of;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
 use std::{cmp, usize};
 
 use address_space::AddressSpace;
 use devices::legacy::{Chardev, InputReceiver};
 use error_chain::ChainedError;
 use machine_manager::{
-    config::{ChardevType, VirtioConsole},
+    config::{VirtioSerialInfo, VirtioSerialPort},
     event_loop::EventLoop,
+    qmp::guest_agent::{self, GuestAgentTransport},
 };
 use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use once_cell::sync::Lazy;
 use util::byte_code::ByteCode;
 use util::loop_context::{read_fd, EventNotifier, EventNotifierHelper, NotifierOperation};
 use util::num_ops::{read_u32, write_u32};
@@ -31,20 +36,37 @@ use vmm_sys_util::eventfd::EventFd;
 
 use super::errors::{ErrorKind, Result, ResultExt};
 use super::{
-    Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VIRTIO_CONSOLE_F_SIZE,
-    VIRTIO_F_VERSION_1, VIRTIO_TYPE_CONSOLE,
+    virtio_has_feature, Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    VIRTIO_CONSOLE_F_MULTIPORT, VIRTIO_CONSOLE_F_SIZE, VIRTIO_F_VERSION_1, VIRTIO_TYPE_CONSOLE,
 };
 
-/// Number of virtqueues.
-const QUEUE_NUM_CONSOLE: usize = 2;
+/// Number of virtqueues of each port, including receiveq and transmitq.
+const QUEUE_NUM_PER_PORT: usize = 2;
 /// Size of virtqueue.
 const QUEUE_SIZE_CONSOLE: u16 = 256;
+/// Index of control receiveq, control transmitq is the next one.
+const CTRL_QUEUE_INDEX: usize = 2;
 
 const BUFF_SIZE: usize = 4096;
 
+/// Control events, refer to Virtio Spec.
+const VIRTIO_CONSOLE_DEVICE_READY: u16 = 0;
+const VIRTIO_CONSOLE_DEVICE_ADD: u16 = 1;
+const VIRTIO_CONSOLE_DEVICE_REMOVE: u16 = 2;
+const VIRTIO_CONSOLE_PORT_READY: u16 = 3;
+const VIRTIO_CONSOLE_CONSOLE_PORT: u16 = 4;
+const VIRTIO_CONSOLE_RESIZE: u16 = 5;
+const VIRTIO_CONSOLE_PORT_OPEN: u16 = 6;
+const VIRTIO_CONSOLE_PORT_NAME: u16 = 7;
+
+/// The only virtio-serial device of the VM, which ports are attached to.
+static VIRTIO_SERIAL: Lazy<Mutex<Option<Weak<Mutex<Console>>>>> = Lazy::new(|| Mutex::new(None));
+
 #[derive(Copy, Clone, Debug, Default)]
 #[repr(C)]
 struct VirtioConsoleConfig {
+    cols: u16,
+    rows: u16,
     max_nr_ports: u32,
     emerg_wr: u32,
 }
@@ -53,28 +75,75 @@ impl ByteCode for VirtioConsoleConfig {}
 
 impl VirtioConsoleConfig {
     /// Create configuration of virtio-console devices.
-    pub fn new() -> Self {
+    pub fn new(max_nr_ports: u32) -> Self {
         VirtioConsoleConfig {
-            max_nr_ports: 1_u32,
+            cols: 0_u16,
+            rows: 0_u16,
+            max_nr_ports,
             emerg_wr: 0_u32,
         }
     }
 }
 
+/// Message transferred on control queues.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct VirtioConsoleControl {
+    /// Port number.
+    id: u32,
+    /// The kind of control event.
+    event: u16,
+    /// Extra information for the event.
+    value: u16,
+}
+
+impl ByteCode for VirtioConsoleControl {}
+
+/// Get the index of receiveq and transmitq of the port.
+fn port_queue_index(nr: u32) -> (usize, usize) {
+    let rx = if nr == 0 {
+        0
+    } else {
+        (nr as usize + 1) * QUEUE_NUM_PER_PORT
+    };
+    (rx, rx + 1)
+}
+
+/// Port of virtio-serial device, such as virtconsole and virtserialport.
+#[derive(Clone)]
+pub struct SerialPort {
+    /// Id of the port.
+    pub id: String,
+    /// Port number.
+    pub nr: u32,
+    /// Name of the port reported to guest.
+    pub name: Option<String>,
+    /// Whether the port is a console port.
+    pub is_console: bool,
+    /// Character device for redirection, the guest agent port without chardev
+    /// is connected to the guest agent proxy.
+    chardev: Option<Arc<Mutex<Chardev>>>,
+    /// Whether the port is opened by guest.
+    guest_connected: Arc<AtomicBool>,
+}
+
 struct ConsoleHandler {
     input_queue: Arc<Mutex<Queue>>,
     output_queue: Arc<Mutex<Queue>>,
     output_queue_evt: EventFd,
-    deactivate_evt: RawFd,
     mem_space: Arc<AddressSpace>,
     interrupt_cb: Arc<VirtioInterrupt>,
     driver_features: u64,
-    chardev: Arc<Mutex<Chardev>>,
+    port: SerialPort,
 }
 
 impl InputReceiver for ConsoleHandler {
     #[allow(clippy::useless_asref)]
     fn input_handle(&mut self, buffer: &[u8]) {
+        // Data is discarded if the port has not been opened by guest.
+        if !self.port.is_console && !self.port.guest_connected.load(Ordering::Acquire) {
+            return;
+        }
         let mut queue_lock = self.input_queue.lock().unwrap();
 
         let count = buffer.len();
@@ -145,50 +214,43 @@ impl InputReceiver for ConsoleHandler {
     }
 }
 
+impl GuestAgentTransport for ConsoleHandler {
+    fn is_connected(&self) -> bool {
+        self.port.guest_connected.load(Ordering::Acquire)
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        self.input_handle(data);
+    }
+}
+
 impl ConsoleHandler {
-    fn output_handle(&mut self) {
+    /// Handle the data from guest, return the data for guest agent proxy, which
+    /// should be delivered after the handler is unlocked.
+    fn output_handle(&mut self) -> Vec<u8> {
         let mut queue_lock = self.output_queue.lock().unwrap();
         let mut buffer = [0_u8; 4096];
+        let mut agent_data = Vec::new();
 
         while let Ok(elem) = queue_lock
             .vring
             .pop_avail(&self.mem_space, self.driver_features)
         {
-            let mut read_count = 0_usize;
-            for elem_iov in elem.out_iovec.iter() {
-                let allow_read_count = cmp::min(read_count + elem_iov.len as usize, buffer.len());
-                let mut slice = &mut buffer[read_count..allow_read_count];
-
-                let read_result = self.mem_space.read(
-                    &mut slice,
-                    elem_iov.addr,
-                    (allow_read_count - read_count) as u64,
-                );
-                match read_result {
-                    Ok(_) => {
-                        read_count = allow_read_count;
+            let read_count = read_elem_buffer(&self.mem_space, &elem, &mut buffer);
+            if let Some(chardev) = &self.port.chardev {
+                if let Some(output) = &mut chardev.lock().unwrap().output {
+                    let mut locked_output = output.lock().unwrap();
+                    if let Err(e) = locked_output.write_all(&buffer[..read_count]) {
+                        error!("Failed to write to console output: {}", e);
                     }
-                    Err(ref e) => {
-                        error!(
-                            "Failed to read buffer for output console: addr: {:X}, len: {} {}",
-                            elem_iov.addr.0,
-                            allow_read_count - read_count,
-                            e.display_chain()
-                        );
-                        break;
+                    if let Err(e) = locked_output.flush() {
+                        error!("Failed to flush console output: {}", e);
                     }
-                };
-            }
-            if let Some(output) = &mut self.chardev.lock().unwrap().output {
-                let mut locked_output = output.lock().unwrap();
-                if let Err(e) = locked_output.write_all(&buffer[..read_count as usize]) {
-                    error!("Failed to write to console output: {}", e);
-                }
-                if let Err(e) = locked_output.flush() {
-                    error!("Failed to flush console output: {}", e);
+                } else {
+                    debug!("Failed to get output fd");
                 }
             } else {
-                debug!("Failed to get output fd");
+                agent_data.extend_from_slice(&buffer[..read_count]);
             }
 
             if let Err(ref e) = queue_lock.vring.add_used(&self.mem_space, elem.index, 0) {
@@ -201,88 +263,302 @@ impl ConsoleHandler {
                 break;
             }
         }
+        agent_data
     }
 
     fn deactivate_evt_handler(&self) -> Vec<EventNotifier> {
-        let locked_chardev = self.chardev.lock().unwrap();
-        let mut notifiers = vec![
+        let mut notifiers = vec![EventNotifier::new(
+            NotifierOperation::Delete,
+            self.output_queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            Vec::new(),
+        )];
+        let mut locked_chardev = if let Some(chardev) = &self.port.chardev {
+            chardev.lock().unwrap()
+        } else {
+            guest_agent::unbind_transport();
+            return notifiers;
+        };
+        notifiers.append(&mut locked_chardev.remove_notifiers());
+        notifiers
+    }
+}
+
+impl EventNotifierHelper for ConsoleHandler {
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn internal_notifiers(console_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_cls = console_handler.clone();
+        let handler = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let agent_data = cloned_cls.lock().unwrap().output_handle();
+            if !agent_data.is_empty() {
+                guest_agent::receive(&agent_data);
+            }
+            None as Option<Vec<EventNotifier>>
+        });
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            console_handler.lock().unwrap().output_queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![Arc::new(Mutex::new(handler))],
+        )]
+    }
+}
+
+/// Read the data of out iovecs of the element into the buffer, return the length read.
+fn read_elem_buffer(mem_space: &Arc<AddressSpace>, elem: &Element, buffer: &mut [u8]) -> usize {
+    let mut read_count = 0_usize;
+    for elem_iov in elem.out_iovec.iter() {
+        let allow_read_count = cmp::min(read_count + elem_iov.len as usize, buffer.len());
+        let mut slice = &mut buffer[read_count..allow_read_count];
+
+        let read_result = mem_space.read(
+            &mut slice,
+            elem_iov.addr,
+            (allow_read_count - read_count) as u64,
+        );
+        match read_result {
+            Ok(_) => {
+                read_count = allow_read_count;
+            }
+            Err(ref e) => {
+                error!(
+                    "Failed to read buffer for output console: addr: {:X}, len: {} {}",
+                    elem_iov.addr.0,
+                    allow_read_count - read_count,
+                    e.display_chain()
+                );
+                break;
+            }
+        };
+    }
+    read_count
+}
+
+/// Handler of control queues, which is used to negotiate ports with guest.
+struct ControlHandler {
+    ctrl_rx_queue: Arc<Mutex<Queue>>,
+    ctrl_rx_queue_evt: EventFd,
+    ctrl_tx_queue: Arc<Mutex<Queue>>,
+    ctrl_tx_queue_evt: EventFd,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    ports: Vec<SerialPort>,
+    /// Control messages waiting for available buffers of control receiveq.
+    pending_msgs: VecDeque<Vec<u8>>,
+}
+
+impl ControlHandler {
+    fn ctrl_tx_handle(&mut self) -> Result<()> {
+        let mut msgs = Vec::new();
+        {
+            let mut queue_lock = self.ctrl_tx_queue.lock().unwrap();
+            let mut buffer = [0_u8; size_of::<VirtioConsoleControl>()];
+            while let Ok(elem) = queue_lock
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+            {
+                let read_count = read_elem_buffer(&self.mem_space, &elem, &mut buffer);
+                if read_count == buffer.len() {
+                    // Safe to unwrap, because the length of buffer is checked.
+                    msgs.push(*VirtioConsoleControl::from_bytes(&buffer).unwrap());
+                } else {
+                    error!(
+                        "Invalid control message of virtio-serial, len {}",
+                        read_count
+                    );
+                }
+                queue_lock
+                    .vring
+                    .add_used(&self.mem_space, elem.index, 0)
+                    .chain_err(|| "Failed to add used ring for control transmitq")?;
+            }
+            if msgs.is_empty() {
+                return Ok(());
+            }
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock))
+                .chain_err(|| ErrorKind::InterruptTrigger("console", VirtioInterruptType::Vring))?;
+        }
+
+        for msg in msgs {
+            self.handle_control_message(msg);
+        }
+        self.flush_pending_msgs()
+    }
+
+    fn handle_control_message(&mut self, msg: VirtioConsoleControl) {
+        match msg.event {
+            VIRTIO_CONSOLE_DEVICE_READY => {
+                if msg.value == 0 {
+                    error!("Guest failed to initialize virtio-serial device");
+                    return;
+                }
+                let nrs: Vec<u32> = self.ports.iter().map(|port| port.nr).collect();
+                for nr in nrs {
+                    self.send_control_message(nr, VIRTIO_CONSOLE_DEVICE_ADD, 1, &[]);
+                }
+            }
+            VIRTIO_CONSOLE_PORT_READY => {
+                let port = if let Some(port) = self.ports.iter().find(|port| port.nr == msg.id) {
+                    port.clone()
+                } else {
+                    warn!("Port {} of virtio-serial is not found", msg.id);
+                    return;
+                };
+                if msg.value == 0 {
+                    error!("Guest failed to add port {} of virtio-serial", msg.id);
+                    return;
+                }
+                if port.is_console {
+                    self.send_control_message(port.nr, VIRTIO_CONSOLE_CONSOLE_PORT, 1, &[]);
+                }
+                if let Some(name) = &port.name {
+                    self.send_control_message(
+                        port.nr,
+                        VIRTIO_CONSOLE_PORT_NAME,
+                        1,
+                        name.as_bytes(),
+                    );
+                }
+                // Host side of the port is always connected.
+                self.send_control_message(port.nr, VIRTIO_CONSOLE_PORT_OPEN, 1, &[]);
+            }
+            VIRTIO_CONSOLE_PORT_OPEN => {
+                if let Some(port) = self.ports.iter().find(|port| port.nr == msg.id) {
+                    port.guest_connected
+                        .store(msg.value != 0, Ordering::Release);
+                }
+            }
+            VIRTIO_CONSOLE_DEVICE_ADD
+            | VIRTIO_CONSOLE_DEVICE_REMOVE
+            | VIRTIO_CONSOLE_CONSOLE_PORT
+            | VIRTIO_CONSOLE_RESIZE
+            | VIRTIO_CONSOLE_PORT_NAME => {
+                warn!(
+                    "Unexpected control event {} from guest for virtio-serial",
+                    msg.event
+                );
+            }
+            _ => {
+                warn!("Unknown control event {} for virtio-serial", msg.event);
+            }
+        }
+    }
+
+    fn send_control_message(&mut self, id: u32, event: u16, value: u16, extra: &[u8]) {
+        let msg = VirtioConsoleControl { id, event, value };
+        let mut buffer = msg.as_bytes().to_vec();
+        buffer.extend_from_slice(extra);
+        self.pending_msgs.push_back(buffer);
+    }
+
+    #[allow(clippy::useless_asref)]
+    fn flush_pending_msgs(&mut self) -> Result<()> {
+        if self.pending_msgs.is_empty() {
+            return Ok(());
+        }
+
+        let mut queue_lock = self.ctrl_rx_queue.lock().unwrap();
+        let mut need_interrupt = false;
+        while let Some(msg) = self.pending_msgs.front() {
+            let elem = match queue_lock
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+            {
+                Ok(elem) => elem,
+                Err(_) => break,
+            };
+            let mut write_count = 0_usize;
+            for elem_iov in elem.in_iovec.iter() {
+                let allow_write_count = cmp::min(write_count + elem_iov.len as usize, msg.len());
+                let source_slice = &msg[write_count..allow_write_count];
+                self.mem_space
+                    .write(
+                        &mut source_slice.as_ref(),
+                        elem_iov.addr,
+                        source_slice.len() as u64,
+                    )
+                    .chain_err(|| "Failed to write control message of virtio-serial")?;
+                write_count = allow_write_count;
+                if write_count >= msg.len() {
+                    break;
+                }
+            }
+            queue_lock
+                .vring
+                .add_used(&self.mem_space, elem.index, write_count as u32)
+                .chain_err(|| "Failed to add used ring for control receiveq")?;
+            need_interrupt = true;
+            self.pending_msgs.pop_front();
+        }
+
+        if need_interrupt {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock))
+                .chain_err(|| ErrorKind::InterruptTrigger("console", VirtioInterruptType::Vring))?;
+        }
+        Ok(())
+    }
+
+    fn deactivate_evt_handler(&self) -> Vec<EventNotifier> {
+        vec![
             EventNotifier::new(
                 NotifierOperation::Delete,
-                self.deactivate_evt,
+                self.ctrl_rx_queue_evt.as_raw_fd(),
                 None,
                 EventSet::IN,
                 Vec::new(),
             ),
             EventNotifier::new(
                 NotifierOperation::Delete,
-                self.output_queue_evt.as_raw_fd(),
+                self.ctrl_tx_queue_evt.as_raw_fd(),
                 None,
                 EventSet::IN,
                 Vec::new(),
             ),
-        ];
-        match &locked_chardev.backend {
-            ChardevType::Stdio | ChardevType::Pty => {
-                if let Some(input) = locked_chardev.input.clone() {
-                    notifiers.push(EventNotifier::new(
-                        NotifierOperation::Delete,
-                        input.lock().unwrap().as_raw_fd(),
-                        None,
-                        EventSet::IN,
-                        Vec::new(),
-                    ));
-                }
-            }
-            ChardevType::Socket(_) => {
-                let listener_fd = locked_chardev.listener.as_ref().unwrap().as_raw_fd();
-                notifiers.push(EventNotifier::new(
-                    NotifierOperation::Delete,
-                    listener_fd,
-                    None,
-                    EventSet::IN,
-                    Vec::new(),
-                ));
-                if let Some(stream_fd) = locked_chardev.stream_fd {
-                    notifiers.push(EventNotifier::new(
-                        NotifierOperation::Delete,
-                        stream_fd,
-                        None,
-                        EventSet::IN | EventSet::HANG_UP,
-                        Vec::new(),
-                    ));
-                }
-            }
-            _ => (),
-        }
-        notifiers
+        ]
     }
 }
 
-impl EventNotifierHelper for ConsoleHandler {
-    fn internal_notifiers(console_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+impl EventNotifierHelper for ControlHandler {
+    fn internal_notifiers(ctrl_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut notifiers = Vec::new();
-        let cloned_cls = console_handler.clone();
+        let cloned_ctrl = ctrl_handler.clone();
         let handler = Box::new(move |_, fd: RawFd| {
             read_fd(fd);
-            cloned_cls.lock().unwrap().output_handle();
+            if let Err(ref e) = cloned_ctrl.lock().unwrap().ctrl_tx_handle() {
+                error!(
+                    "Failed to handle control transmitq of virtio-serial: {}",
+                    e.display_chain()
+                );
+            }
             None as Option<Vec<EventNotifier>>
         });
         notifiers.push(EventNotifier::new(
             NotifierOperation::AddShared,
-            console_handler.lock().unwrap().output_queue_evt.as_raw_fd(),
+            ctrl_handler.lock().unwrap().ctrl_tx_queue_evt.as_raw_fd(),
             None,
             EventSet::IN,
             vec![Arc::new(Mutex::new(handler))],
         ));
 
-        let cloned_cls = console_handler.clone();
+        // Guest adds buffers to control receiveq, try to send the pending messages.
+        let cloned_ctrl = ctrl_handler.clone();
         let handler = Box::new(move |_, fd: RawFd| {
             read_fd(fd);
-            Some(cloned_cls.lock().unwrap().deactivate_evt_handler())
+            if let Err(ref e) = cloned_ctrl.lock().unwrap().flush_pending_msgs() {
+                error!(
+                    "Failed to handle control receiveq of virtio-serial: {}",
+                    e.display_chain()
+                );
+            }
+            None as Option<Vec<EventNotifier>>
         });
         notifiers.push(EventNotifier::new(
             NotifierOperation::AddShared,
-            console_handler.lock().unwrap().deactivate_evt,
+            ctrl_handler.lock().unwrap().ctrl_rx_queue_evt.as_raw_fd(),
             None,
             EventSet::IN,
             vec![Arc::new(Mutex::new(handler))],
@@ -295,7 +571,7 @@ impl EventNotifierHelper for ConsoleHandler {
 /// Status of console device.
 #[repr(C)]
 #[derive(Copy, Clone, Desc, ByteCode)]
-#[desc_version(compat_version = "0.1.0")]
+#[desc_version(current_version = "2.2.0", compat_version = "0.1.0")]
 pub struct VirtioConsoleState {
     /// Bit mask of features supported by the backend.
     device_features: u64,
@@ -303,46 +579,131 @@ pub struct VirtioConsoleState {
     driver_features: u64,
     /// Virtio Console config space.
     config_space: VirtioConsoleConfig,
+    /// Bit mask of port numbers of the ports opened by guest.
+    guest_connected: u32,
 }
 
-/// Virtio console device structure.
+/// Virtio serial device structure, which provides console ports and generic ports.
 pub struct Console {
     /// Status of console device.
     state: VirtioConsoleState,
     /// EventFd for device deactivate.
     deactivate_evt: EventFd,
-    /// Character device for redirection.
-    chardev: Arc<Mutex<Chardev>>,
+    /// Ports attached to the device.
+    ports: Vec<SerialPort>,
 }
 
 impl Console {
-    /// Create a virtio-console device.
+    /// Create a virtio-serial device.
     ///
     /// # Arguments
     ///
-    /// * `console_cfg` - Device configuration set by user.
-    pub fn new(console_cfg: VirtioConsole) -> Self {
+    /// * `serial_cfg` - Device configuration set by user.
+    pub fn new(serial_cfg: VirtioSerialInfo) -> Self {
         Console {
             state: VirtioConsoleState {
                 device_features: 0_u64,
                 driver_features: 0_u64,
-                config_space: VirtioConsoleConfig::new(),
+                config_space: VirtioConsoleConfig::new(serial_cfg.max_ports),
+                guest_connected: 0_u32,
             },
             deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
-            chardev: Arc::new(Mutex::new(Chardev::new(console_cfg.chardev))),
+            ports: Vec::new(),
+        }
+    }
+
+    /// Add a port to the device, the chardev of the port is realized at the same time.
+    ///
+    /// # Arguments
+    ///
+    /// * `port_cfg` - Port configuration set by user.
+    pub fn add_port(&mut self, port_cfg: &VirtioSerialPort) -> Result<()> {
+        let max_nr_ports = self.state.config_space.max_nr_ports;
+        let nr = match port_cfg.nr {
+            Some(0) if !port_cfg.is_console => {
+                bail!("Port number 0 of virtio-serial is reserved for console")
+            }
+            Some(nr) => nr,
+            None => {
+                // Port 0 is preferred by console for compatibility with guest without multiport.
+                let start = if port_cfg.is_console { 0 } else { 1 };
+                (start..max_nr_ports)
+                    .find(|nr| !self.ports.iter().any(|port| port.nr == *nr))
+                    .chain_err(|| format!("No free port of virtio-serial for {}", port_cfg.id))?
+            }
+        };
+        if nr >= max_nr_ports {
+            bail!(
+                "Port number {} of {} exceeds max_ports {} of virtio-serial",
+                nr,
+                port_cfg.id,
+                max_nr_ports
+            );
         }
+        for port in self.ports.iter() {
+            if port.nr == nr {
+                bail!("Port number {} of virtio-serial is already in use", nr);
+            }
+            if port.id == port_cfg.id {
+                bail!("Port id {} of virtio-serial is already in use", port.id);
+            }
+            if let Some(name) = &port.name {
+                if port_cfg.name.as_ref() == Some(name) {
+                    bail!("Port name {} of virtio-serial is already in use", name);
+                }
+            }
+        }
+
+        let chardev = if let Some(chardev_cfg) = &port_cfg.chardev {
+            let mut chardev = Chardev::new(chardev_cfg.clone());
+            chardev
+                .realize()
+                .chain_err(|| "Failed to realize chardev")?;
+            Some(Arc::new(Mutex::new(chardev)))
+        } else {
+            None
+        };
+        self.ports.push(SerialPort {
+            id: port_cfg.id.clone(),
+            nr,
+            name: port_cfg.name.clone(),
+            is_console: port_cfg.is_console,
+            chardev,
+            guest_connected: Arc::new(AtomicBool::new(false)),
+        });
+        Ok(())
     }
 }
 
+/// Register the virtio-serial device, so that ports can be attached to it.
+pub fn register_virtio_serial(dev: &Arc<Mutex<Console>>) {
+    *VIRTIO_SERIAL.lock().unwrap() = Some(Arc::downgrade(dev));
+}
+
+/// Attach the port to the virtio-serial device.
+///
+/// # Arguments
+///
+/// * `port_cfg` - Configuration of the port.
+pub fn virtio_serial_attach_port(port_cfg: &VirtioSerialPort) -> Result<()> {
+    let dev = VIRTIO_SERIAL
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|dev| dev.upgrade());
+    if let Some(dev) = dev {
+        return dev.lock().unwrap().add_port(port_cfg);
+    }
+
+    bail!("No virtio-serial device configured for {}", port_cfg.id);
+}
+
 impl VirtioDevice for Console {
     /// Realize virtio console device.
     fn realize(&mut self) -> Result<()> {
-        self.state.device_features = 1_u64 << VIRTIO_F_VERSION_1 | 1_u64 << VIRTIO_CONSOLE_F_SIZE;
-        self.chardev
-            .lock()
-            .unwrap()
-            .realize()
-            .chain_err(|| "Failed to realize chardev")?;
+        self.state.device_features = 1_u64 << VIRTIO_F_VERSION_1
+            | 1_u64 << VIRTIO_CONSOLE_F_SIZE
+            | 1_u64 << VIRTIO_CONSOLE_F_MULTIPORT;
         Ok(())
     }
 
@@ -351,9 +712,9 @@ impl VirtioDevice for Console {
         VIRTIO_TYPE_CONSOLE
     }
 
-    /// Get the count of virtio device queues.
+    /// Get the count of virtio device queues, including queues of control and all ports.
     fn queue_num(&self) -> usize {
-        QUEUE_NUM_CONSOLE
+        (self.state.config_space.max_nr_ports as usize + 1) * QUEUE_NUM_PER_PORT
     }
 
     /// Get the queue size of virtio device.
@@ -404,33 +765,110 @@ impl VirtioDevice for Console {
         mem_space: Arc<AddressSpace>,
         interrupt_cb: Arc<VirtioInterrupt>,
         queues: &[Arc<Mutex<Queue>>],
-        mut queue_evts: Vec<EventFd>,
+        queue_evts: Vec<EventFd>,
     ) -> Result<()> {
-        queue_evts.remove(0); // input_queue_evt never used
-
-        let handler = ConsoleHandler {
-            input_queue: queues[0].clone(),
-            output_queue: queues[1].clone(),
-            output_queue_evt: queue_evts.remove(0),
-            mem_space,
-            interrupt_cb,
-            driver_features: self.state.driver_features,
-            deactivate_evt: self.deactivate_evt.as_raw_fd(),
-            chardev: self.chardev.clone(),
+        let queue_num = self.queue_num();
+        if queues.len() != queue_num {
+            return Err(ErrorKind::IncorrectQueueNum(queue_num, queues.len()).into());
+        }
+        let driver_features = self.state.driver_features;
+        let multiport = virtio_has_feature(driver_features, VIRTIO_CONSOLE_F_MULTIPORT as u32);
+
+        let mut port_handlers = Vec::new();
+        for port in self.ports.iter() {
+            // Only port 0 is available if guest doesn't support multiport.
+            if !multiport && port.nr != 0 {
+                continue;
+            }
+            let (rx, tx) = port_queue_index(port.nr);
+            let handler = ConsoleHandler {
+                input_queue: queues[rx].clone(),
+                output_queue: queues[tx].clone(),
+                output_queue_evt: queue_evts[tx]
+                    .try_clone()
+                    .chain_err(|| "Failed to clone queue event of virtio-serial")?,
+                mem_space: mem_space.clone(),
+                interrupt_cb: interrupt_cb.clone(),
+                driver_features,
+                port: port.clone(),
+            };
+
+            let dev = Arc::new(Mutex::new(handler));
+            EventLoop::update_event(EventNotifierHelper::internal_notifiers(dev.clone()), None)?;
+            if let Some(chardev) = &port.chardev {
+                chardev.lock().unwrap().set_input_callback(&dev);
+                EventLoop::update_event(
+                    EventNotifierHelper::internal_notifiers(chardev.clone()),
+                    None,
+                )?;
+            } else {
+                guest_agent::bind_transport(dev.clone());
+            }
+            port_handlers.push(dev);
+        }
+
+        let ctrl_handler = if multiport {
+            let handler = ControlHandler {
+                ctrl_rx_queue: queues[CTRL_QUEUE_INDEX].clone(),
+                ctrl_rx_queue_evt: queue_evts[CTRL_QUEUE_INDEX]
+                    .try_clone()
+                    .chain_err(|| "Failed to clone queue event of virtio-serial")?,
+                ctrl_tx_queue: queues[CTRL_QUEUE_INDEX + 1].clone(),
+                ctrl_tx_queue_evt: queue_evts[CTRL_QUEUE_INDEX + 1]
+                    .try_clone()
+                    .chain_err(|| "Failed to clone queue event of virtio-serial")?,
+                mem_space,
+                interrupt_cb,
+                driver_features,
+                ports: self.ports.clone(),
+                pending_msgs: VecDeque::new(),
+            };
+            let ctrl = Arc::new(Mutex::new(handler));
+            EventLoop::update_event(EventNotifierHelper::internal_notifiers(ctrl.clone()), None)?;
+            Some(ctrl)
+        } else {
+            None
         };
 
-        let dev = Arc::new(Mutex::new(handler));
-        EventLoop::update_event(EventNotifierHelper::internal_notifiers(dev.clone()), None)?;
-        let locked_dev = dev.lock().unwrap();
-        locked_dev.chardev.lock().unwrap().set_input_callback(&dev);
+        // All handlers are removed together when the device is deactivated.
+        let deactivate_fd = self.deactivate_evt.as_raw_fd();
+        let handler = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut notifiers = vec![EventNotifier::new(
+                NotifierOperation::Delete,
+                fd,
+                None,
+                EventSet::IN,
+                Vec::new(),
+            )];
+            for port_handler in port_handlers.iter() {
+                notifiers.append(&mut port_handler.lock().unwrap().deactivate_evt_handler());
+            }
+            if let Some(ctrl) = &ctrl_handler {
+                notifiers.append(&mut ctrl.lock().unwrap().deactivate_evt_handler());
+            }
+            Some(notifiers)
+        });
+        #[allow(clippy::arc_with_non_send_sync)]
         EventLoop::update_event(
-            EventNotifierHelper::internal_notifiers(locked_dev.chardev.clone()),
+            vec![EventNotifier::new(
+                NotifierOperation::AddShared,
+                deactivate_fd,
+                None,
+                EventSet::IN,
+                vec![Arc::new(Mutex::new(handler))],
+            )],
             None,
         )?;
         Ok(())
     }
 
     fn deactivate(&mut self) -> Result<()> {
+        // Ports are closed by guest when the device is reset. The state is kept
+        // across activation so that it survives migration.
+        for port in self.ports.iter() {
+            port.guest_connected.store(false, Ordering::Release);
+        }
         self.deactivate_evt
             .write(1)
             .chain_err(|| ErrorKind::EventFdWrite)
@@ -439,12 +877,24 @@ impl VirtioDevice for Console {
 
 impl StateTransfer for Console {
     fn get_state_vec(&self) -> migration::errors::Result<Vec<u8>> {
-        Ok(self.state.as_bytes().to_vec())
+        let mut state = self.state;
+        state.guest_connected = self
+            .ports
+            .iter()
+            .filter(|port| port.guest_connected.load(Ordering::Acquire))
+            .fold(0, |mask, port| mask | (1 << port.nr));
+        Ok(state.as_bytes().to_vec())
     }
 
     fn set_state_mut(&mut self, state: &[u8]) -> migration::errors::Result<()> {
         self.state = *VirtioConsoleState::from_bytes(state)
             .ok_or(migration::errors::ErrorKind::FromBytesError("CONSOLE"))?;
+        for port in self.ports.iter() {
+            port.guest_connected.store(
+                self.state.guest_connected & (1 << port.nr) != 0,
+                Ordering::Release,
+            );
+        }
 
         Ok(())
     }
@@ -470,19 +920,37 @@ mod tests {
 
     use machine_manager::config::{ChardevConfig, ChardevType};
 
+    fn serial_info(max_ports: u32) -> VirtioSerialInfo {
+        VirtioSerialInfo {
+            id: "serial".to_string(),
+            pci_bdf: None,
+            multifunction: false,
+            max_ports,
+        }
+    }
+
+    fn port_cfg(
+        id: &str,
+        nr: Option<u32>,
+        name: Option<&str>,
+        is_console: bool,
+    ) -> VirtioSerialPort {
+        VirtioSerialPort {
+            id: id.to_string(),
+            chardev: Some(ChardevConfig {
+                id: format!("chardev_{}", id),
+                backend: ChardevType::File(format!("/tmp/virtio_serial_test_{}", id)),
+                logfile: None,
+            }),
+            nr,
+            name: name.map(|name| name.to_string()),
+            is_console,
+        }
+    }
+
     #[test]
     fn test_set_driver_features() {
-        let chardev_cfg = ChardevConfig {
-            id: "chardev".to_string(),
-            backend: ChardevType::Stdio,
-        };
-        let mut console = Console::new(VirtioConsole {
-            id: "console".to_string(),
-            chardev: chardev_cfg.clone(),
-        });
-        let mut chardev = Chardev::new(chardev_cfg);
-        chardev.output = Some(Arc::new(Mutex::new(std::io::stdout())));
-        console.chardev = Arc::new(Mutex::new(chardev));
+        let mut console = Console::new(serial_info(1));
 
         //If the device feature is 0, all driver features are not supported.
         console.state.device_features = 0;
@@ -537,17 +1005,7 @@ mod tests {
 
     #[test]
     fn test_read_config() {
-        let chardev_cfg = ChardevConfig {
-            id: "chardev".to_string(),
-            backend: ChardevType::Stdio,
-        };
-        let mut console = Console::new(VirtioConsole {
-            id: "console".to_string(),
-            chardev: chardev_cfg.clone(),
-        });
-        let mut chardev = Chardev::new(chardev_cfg);
-        chardev.output = Some(Arc::new(Mutex::new(std::io::stdout())));
-        console.chardev = Arc::new(Mutex::new(chardev));
+        let console = Console::new(serial_info(1));
 
         //The offset of configuration that needs to be read exceeds the maximum
         let offset = size_of::<VirtioConsoleConfig>() as u64;
@@ -557,14 +1015,96 @@ mod tests {
         //Check the configuration that needs to be read
         let offset = 0_u64;
         let mut read_data: Vec<u8> = vec![0; 8];
-        let expect_data: Vec<u8> = vec![1, 0, 0, 0, 0, 0, 0, 0];
+        let expect_data: Vec<u8> = vec![0, 0, 0, 0, 1, 0, 0, 0];
         assert_eq!(console.read_config(offset, &mut read_data).is_ok(), true);
         assert_eq!(read_data, expect_data);
 
-        let offset = 0_u64;
+        let offset = 4_u64;
         let mut read_data: Vec<u8> = vec![0; 1];
         let expect_data: Vec<u8> = vec![1];
         assert_eq!(console.read_config(offset, &mut read_data).is_ok(), true);
         assert_eq!(read_data, expect_data);
     }
+
+    #[test]
+    fn test_add_port() {
+        let mut console = Console::new(serial_info(3));
+        console.realize().unwrap();
+        assert_eq!(console.queue_num(), 8);
+        assert_eq!(port_queue_index(0), (0, 1));
+        assert_eq!(port_queue_index(1), (4, 5));
+        assert_eq!(port_queue_index(2), (6, 7));
+
+        // Port number of generic port is allocated from 1.
+        let cfg = port_cfg("port0", None, Some("org.qemu.guest_agent.0"), false);
+        assert!(console.add_port(&cfg).is_ok());
+        assert_eq!(console.ports[0].nr, 1);
+        // Console prefers port 0.
+        assert!(console
+            .add_port(&port_cfg("console0", None, None, true))
+            .is_ok());
+        assert_eq!(console.ports[1].nr, 0);
+        // Port number 0 is reserved for console.
+        assert!(console
+            .add_port(&port_cfg("port1", Some(0), None, false))
+            .is_err());
+        // Port number exceeds max_ports.
+        assert!(console
+            .add_port(&port_cfg("port1", Some(3), None, false))
+            .is_err());
+        // Port name is in use.
+        let cfg = port_cfg("port1", None, Some("org.qemu.guest_agent.0"), false);
+        assert!(console.add_port(&cfg).is_err());
+        assert!(console
+            .add_port(&port_cfg("port1", None, None, false))
+            .is_ok());
+        assert_eq!(console.ports[2].nr, 2);
+        // No free port.
+        assert!(console
+            .add_port(&port_cfg("port2", None, None, false))
+            .is_err());
+
+        for id in ["port0", "console0", "port1"].iter() {
+            let _ = std::fs::remove_file(format!("/tmp/virtio_serial_test_{}", id));
+        }
+    }
+
+    #[test]
+    fn test_console_state_compat() {
+        let mut console = Console::new(serial_info(3));
+        console.realize().unwrap();
+        assert!(console
+            .add_port(&port_cfg("console0", None, None, true))
+            .is_ok());
+        assert!(console
+            .add_port(&port_cfg("port0", None, None, false))
+            .is_ok());
+
+        // Ports opened by guest are migrated.
+        console.ports[1]
+            .guest_connected
+            .store(true, Ordering::Release);
+        let state = console.get_state_vec().unwrap();
+        console.ports[1]
+            .guest_connected
+            .store(false, Ordering::Release);
+        console.set_state_mut(&state).unwrap();
+        assert!(!console.ports[0].guest_connected.load(Ordering::Acquire));
+        assert!(console.ports[1].guest_connected.load(Ordering::Acquire));
+
+        // State of old version has no ports opened by guest.
+        let old_len = 2 * size_of::<u64>() + size_of::<VirtioConsoleConfig>();
+        let state = pad_compat_state(
+            &VirtioConsoleState::descriptor(),
+            &[("guest_connected", None)],
+            &state[..old_len],
+        );
+        console.set_state_mut(&state).unwrap();
+        assert_eq!(console.state.config_space.max_nr_ports, 3);
+        assert!(!console.ports[1].guest_connected.load(Ordering::Acquire));
+
+        for id in ["console0", "port0"].iter() {
+            let _ = std::fs::remove_file(format!("/tmp/virtio_serial_test_{}", id));
+        }
+    }
 }
@@ -108,22 +108,38 @@ pub mod errors {
 mod balloon;
 mod block;
 mod console;
+mod mem;
 mod net;
+mod notify_stats;
+mod qcow2;
 mod queue;
 mod rng;
+mod scsi;
 mod vhost;
 mod virtio_mmio;
 #[allow(dead_code)]
 mod virtio_pci;
 
 pub use balloon::*;
-pub use block::{Block, BlockState};
-pub use console::{Console, VirtioConsoleState};
+pub use block::{
+    qmp_block_set_io_throttle, qmp_query_block, qmp_query_blockstats, qmp_query_named_block_nodes,
+    register_block_device, Block, BlockState,
+};
+pub use console::{register_virtio_serial, virtio_serial_attach_port, Console, VirtioConsoleState};
 pub use errors::*;
+pub use mem::{
+    qmp_set_requested_size, register_virtio_mem_device, virtio_mem_end_address, VirtioMem,
+};
 pub use net::*;
+pub use notify_stats::{query_notify_stats, VirtioNotifyStats};
 pub use queue::*;
 pub use rng::{Rng, RngState};
+pub use scsi::{
+    register_scsi_cntlr, scsi_attach_device, scsi_detach_device, scsi_device_existed, ScsiCntlr,
+    ScsiCntlrState,
+};
 pub use vhost::kernel as VhostKern;
+pub use vhost::user as VhostUser;
 pub use virtio_mmio::{VirtioMmioDevice, VirtioMmioState};
 pub use virtio_pci::VirtioPciDevice;
 
@@ -144,8 +160,10 @@ pub const VIRTIO_TYPE_BLOCK: u32 = 2;
 pub const VIRTIO_TYPE_CONSOLE: u32 = 3;
 pub const VIRTIO_TYPE_RNG: u32 = 4;
 pub const VIRTIO_TYPE_BALLOON: u32 = 5;
+pub const VIRTIO_TYPE_SCSI: u32 = 8;
 pub const VIRTIO_TYPE_VSOCK: u32 = 19;
-pub const _VIRTIO_TYPE_FS: u32 = 26;
+pub const VIRTIO_TYPE_MEM: u32 = 24;
+pub const VIRTIO_TYPE_FS: u32 = 26;
 
 // The Status of Virtio Device.
 const CONFIG_STATUS_ACKNOWLEDGE: u32 = 0x01;
@@ -182,16 +200,48 @@ pub const VIRTIO_NET_F_GUEST_UFO: u32 = 10;
 pub const VIRTIO_NET_F_HOST_TSO4: u32 = 11;
 /// Device can receive UFO.
 pub const VIRTIO_NET_F_HOST_UFO: u32 = 14;
+/// Driver can merge receive buffers.
+pub const VIRTIO_NET_F_MRG_RXBUF: u32 = 15;
+/// Configuration status field is available.
+pub const VIRTIO_NET_F_STATUS: u32 = 16;
+/// Control channel is available.
+pub const VIRTIO_NET_F_CTRL_VQ: u32 = 17;
+/// Control channel RX mode support.
+pub const VIRTIO_NET_F_CTRL_RX: u32 = 18;
+/// Control channel VLAN filtering.
+pub const VIRTIO_NET_F_CTRL_VLAN: u32 = 19;
+/// Extra RX mode control support.
+pub const VIRTIO_NET_F_CTRL_RX_EXTRA: u32 = 20;
+/// Device supports multiqueue with automatic receive steering.
+pub const VIRTIO_NET_F_MQ: u32 = 22;
+/// Set MAC address through control channel.
+pub const VIRTIO_NET_F_CTRL_MAC_ADDR: u32 = 23;
 /// Configuration cols and rows are valid.
 pub const VIRTIO_CONSOLE_F_SIZE: u64 = 0;
+/// There are multiple ports and control virtqueues.
+pub const VIRTIO_CONSOLE_F_MULTIPORT: u64 = 1;
 /// Maximum size of any single segment is in size_max.
 pub const VIRTIO_BLK_F_SIZE_MAX: u32 = 1;
 /// Maximum number of segments in a request is in seg_max.
 pub const VIRTIO_BLK_F_SEG_MAX: u32 = 2;
 /// Device is read-only.
 pub const VIRTIO_BLK_F_RO: u32 = 5;
+/// Block size of disk is in blk_size.
+pub const VIRTIO_BLK_F_BLK_SIZE: u32 = 6;
 /// Cache flush command support.
 pub const VIRTIO_BLK_F_FLUSH: u32 = 9;
+/// Device exports information on optimal I/O alignment.
+pub const VIRTIO_BLK_F_TOPOLOGY: u32 = 10;
+/// Device can toggle its cache between writeback and writethrough modes.
+pub const VIRTIO_BLK_F_CONFIG_WCE: u32 = 11;
+/// Device supports multiqueue.
+pub const VIRTIO_BLK_F_MQ: u32 = 12;
+/// Device can support discard command.
+pub const VIRTIO_BLK_F_DISCARD: u32 = 13;
+/// Device can support write zeroes command.
+pub const VIRTIO_BLK_F_WRITE_ZEROES: u32 = 14;
+/// Device reports hotplug and hot-unplug events.
+pub const VIRTIO_SCSI_F_HOTPLUG: u32 = 1;
 
 /// The IO type of virtio block, refer to Virtio Spec.
 /// Read.
@@ -202,10 +252,58 @@ pub const VIRTIO_BLK_T_OUT: u32 = 1;
 pub const VIRTIO_BLK_T_FLUSH: u32 = 4;
 /// Device id
 pub const VIRTIO_BLK_T_GET_ID: u32 = 8;
+/// Discard.
+pub const VIRTIO_BLK_T_DISCARD: u32 = 11;
+/// Write zeroes.
+pub const VIRTIO_BLK_T_WRITE_ZEROES: u32 = 13;
+/// Flag of write zeroes segment: the device may deallocate the range.
+pub const VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP: u32 = 1;
 /// Device id length
 pub const VIRTIO_BLK_ID_BYTES: u32 = 20;
 /// Success
 pub const VIRTIO_BLK_S_OK: u32 = 0;
+/// IO error.
+pub const VIRTIO_BLK_S_IOERR: u32 = 1;
+
+/// The ack of virtio net control command, refer to Virtio Spec.
+/// Success.
+pub const VIRTIO_NET_OK: u8 = 0;
+/// Failure.
+pub const VIRTIO_NET_ERR: u8 = 1;
+/// The class of virtio net control command: packet receive filtering.
+pub const VIRTIO_NET_CTRL_RX: u8 = 0;
+/// Set promiscuous mode.
+pub const VIRTIO_NET_CTRL_RX_PROMISC: u8 = 0;
+/// Set all-multicast receive mode.
+pub const VIRTIO_NET_CTRL_RX_ALLMULTI: u8 = 1;
+/// Set all-unicast receive mode.
+pub const VIRTIO_NET_CTRL_RX_ALLUNI: u8 = 2;
+/// Suppress multicast receive.
+pub const VIRTIO_NET_CTRL_RX_NOMULTI: u8 = 3;
+/// Suppress unicast receive.
+pub const VIRTIO_NET_CTRL_RX_NOUNI: u8 = 4;
+/// Suppress broadcast receive.
+pub const VIRTIO_NET_CTRL_RX_NOBCAST: u8 = 5;
+/// The class of virtio net control command: mac address filtering.
+pub const VIRTIO_NET_CTRL_MAC: u8 = 1;
+/// Set the unicast and multicast mac filter table.
+pub const VIRTIO_NET_CTRL_MAC_TABLE_SET: u8 = 0;
+/// Set the default mac address.
+pub const VIRTIO_NET_CTRL_MAC_ADDR_SET: u8 = 1;
+/// The class of virtio net control command: vlan filtering.
+pub const VIRTIO_NET_CTRL_VLAN: u8 = 2;
+/// Add a vlan id to the vlan filter table.
+pub const VIRTIO_NET_CTRL_VLAN_ADD: u8 = 0;
+/// Delete a vlan id from the vlan filter table.
+pub const VIRTIO_NET_CTRL_VLAN_DEL: u8 = 1;
+/// The class of virtio net control command: multiqueue.
+pub const VIRTIO_NET_CTRL_MQ: u8 = 4;
+/// Set the number of virtqueue pairs used.
+pub const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+/// The minimum number of virtqueue pairs.
+pub const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN: u16 = 1;
+/// The status of virtio net link: link is up.
+pub const VIRTIO_NET_S_LINK_UP: u16 = 1;
 
 /// Interrupt status: Used Buffer Notification
 pub const VIRTIO_MMIO_INT_VRING: u32 = 0x01;
@@ -310,4 +408,56 @@ pub trait VirtioDevice: Send {
     fn update_config(&mut self, _dev_config: Option<Arc<dyn ConfigCheck>>) -> Result<()> {
         bail!("Unsupported to update configuration")
     }
+
+    /// Set the counters of notifications for the device to count queue notifies,
+    /// only the devices handling queue events in StratoVirt need it.
+    ///
+    /// # Arguments
+    ///
+    /// * `_stats` - The counters of the transport of this device.
+    fn set_notify_stats(&mut self, _stats: Arc<VirtioNotifyStats>) {}
+}
+
+/// Pad the state saved by the compatible version of device to the current
+/// version, as it is restored from an old snapshot.
+///
+/// # Arguments
+///
+/// * `current_desc` - Descriptor of the current version of device state.
+/// * `old_fields` - Fields which differ in the compatible version, with their
+///   old sizes, `None` if the field does not exist.
+/// * `old_state` - State saved by the compatible version.
+#[cfg(test)]
+pub(crate) fn pad_compat_state(
+    current_desc: &migration::DeviceStateDesc,
+    old_fields: &[(&str, Option<u32>)],
+    old_state: &[u8],
+) -> Vec<u8> {
+    let mut old_desc = current_desc.clone();
+    old_desc.current_version = current_desc.compat_version;
+    old_desc.size = old_state.len() as u32;
+    old_desc.fields.clear();
+    let mut shrink = 0_u32;
+    for field in current_desc.fields.iter() {
+        let size = match old_fields.iter().find(|(name, _)| *name == field.var_name) {
+            Some((_, Some(size))) => *size,
+            Some((_, None)) => {
+                shrink += field.size;
+                continue;
+            }
+            None => field.size,
+        };
+        old_desc.fields.push(migration::FieldDesc {
+            offset: field.offset - shrink,
+            size,
+            ..field.clone()
+        });
+        shrink += field.size - size;
+    }
+
+    assert!(current_desc.current_version > old_desc.current_version);
+    let mut state = old_state.to_vec();
+    current_desc.add_padding(&old_desc, &mut state).unwrap();
+    assert_eq!(state.len(), current_desc.size as usize);
+    state
 }
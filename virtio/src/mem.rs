@@ -0,0 +1,838 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::io::Write;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex, Weak};
+
+use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
+use error_chain::ChainedError;
+use machine_manager::{
+    config::VirtioMemConfig, event_loop::EventLoop, qmp::qmp_schema::MemoryDeviceSizeChange,
+    qmp::QmpChannel,
+};
+use once_cell::sync::Lazy;
+use util::bitmap::Bitmap;
+use util::byte_code::ByteCode;
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+use util::num_ops::{read_u32, write_u32};
+use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
+
+use super::errors::{ErrorKind, Result, ResultExt};
+use super::{
+    Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VIRTIO_F_VERSION_1,
+    VIRTIO_TYPE_MEM,
+};
+
+/// The field `node_id` of config space is an ACPI PXM.
+const VIRTIO_MEM_F_ACPI_PXM: u32 = 0;
+const QUEUE_NUM_MEM: usize = 1;
+const QUEUE_SIZE_MEM: u16 = 128;
+
+/// Request types of guest request queue, refer to Virtio Spec.
+const VIRTIO_MEM_REQ_PLUG: u16 = 0;
+const VIRTIO_MEM_REQ_UNPLUG: u16 = 1;
+const VIRTIO_MEM_REQ_UNPLUG_ALL: u16 = 2;
+const VIRTIO_MEM_REQ_STATE: u16 = 3;
+/// Response types of guest request queue.
+const VIRTIO_MEM_RESP_ACK: u16 = 0;
+const VIRTIO_MEM_RESP_NACK: u16 = 1;
+const VIRTIO_MEM_RESP_ERROR: u16 = 3;
+/// States of memory blocks answered to `VIRTIO_MEM_REQ_STATE`.
+const VIRTIO_MEM_STATE_PLUGGED: u16 = 0;
+const VIRTIO_MEM_STATE_UNPLUGGED: u16 = 1;
+const VIRTIO_MEM_STATE_MIXED: u16 = 2;
+
+static VIRTIO_MEM_DEVICES: Lazy<Mutex<Vec<Weak<Mutex<VirtioMem>>>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Configuration space of virtio-mem device, refer to Virtio Spec.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VirtioMemConfigSpace {
+    /// Memory is plugged and unplugged at the granularity of `block_size`.
+    block_size: u64,
+    /// Guest numa node which the memory belongs to.
+    node_id: u16,
+    padding: [u8; 6],
+    /// Start guest physical address of the memory region.
+    addr: u64,
+    /// Size of the memory region.
+    region_size: u64,
+    /// Size of the region which can be plugged by the guest.
+    usable_region_size: u64,
+    /// Size of memory currently plugged by the guest.
+    plugged_size: u64,
+    /// Size of memory the guest is requested to plug.
+    requested_size: u64,
+}
+
+impl ByteCode for VirtioMemConfigSpace {}
+
+/// Request of guest request queue.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioMemReq {
+    req_type: u16,
+    padding: [u16; 3],
+    addr: u64,
+    nb_blocks: u16,
+    padding_1: [u16; 3],
+}
+
+impl ByteCode for VirtioMemReq {}
+
+/// Response of guest request queue.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct VirtioMemResp {
+    resp_type: u16,
+    padding: [u16; 3],
+    state: u16,
+}
+
+impl ByteCode for VirtioMemResp {}
+
+impl VirtioMemResp {
+    fn new(resp_type: u16) -> Self {
+        VirtioMemResp {
+            resp_type,
+            ..Default::default()
+        }
+    }
+}
+
+/// Memory blocks of the hotpluggable region, shared by device and its handler.
+struct MemBlocks {
+    /// Config space presented to the guest.
+    config: VirtioMemConfigSpace,
+    /// Bitmap of plugged blocks.
+    plugged: Bitmap<u64>,
+    /// Host memory mapping of the region.
+    mem_mapping: Arc<HostMemMapping>,
+    /// The memory is mapped as shared.
+    share: bool,
+}
+
+impl MemBlocks {
+    fn new(mem_cfg: &VirtioMemConfig, mem_mapping: Arc<HostMemMapping>, share: bool) -> Self {
+        let region_size = mem_mapping.size();
+        let nr_blocks = region_size / mem_cfg.block_size;
+        MemBlocks {
+            config: VirtioMemConfigSpace {
+                block_size: mem_cfg.block_size,
+                node_id: mem_cfg.node.unwrap_or(0) as u16,
+                addr: mem_mapping.start_address().raw_value(),
+                region_size,
+                usable_region_size: region_size,
+                requested_size: mem_cfg.requested_size,
+                ..Default::default()
+            },
+            plugged: Bitmap::<u64>::new(nr_blocks as usize / 64 + 1),
+            mem_mapping,
+            share,
+        }
+    }
+
+    /// Check that the blocks requested by guest locate in the usable region.
+    fn is_valid_range(&self, addr: u64, nb_blocks: u16) -> bool {
+        let size = nb_blocks as u64 * self.config.block_size;
+        let usable_end = self.config.addr + self.config.usable_region_size;
+        nb_blocks != 0
+            && addr % self.config.block_size == 0
+            && addr >= self.config.addr
+            && addr
+                .checked_add(size)
+                .map_or(false, |end| end <= usable_end)
+    }
+
+    fn block_index(&self, addr: u64) -> usize {
+        ((addr - self.config.addr) / self.config.block_size) as usize
+    }
+
+    fn is_plugged(&self, index: usize) -> bool {
+        self.plugged.contain(index).unwrap_or(false)
+    }
+
+    fn range_state(&self, addr: u64, nb_blocks: u16) -> u16 {
+        let first = self.block_index(addr);
+        let plugged = (first..first + nb_blocks as usize)
+            .filter(|index| self.is_plugged(*index))
+            .count();
+        if plugged == nb_blocks as usize {
+            VIRTIO_MEM_STATE_PLUGGED
+        } else if plugged == 0 {
+            VIRTIO_MEM_STATE_UNPLUGGED
+        } else {
+            VIRTIO_MEM_STATE_MIXED
+        }
+    }
+
+    /// Release the host memory which backs the range of region.
+    fn discard(&self, offset: u64, size: u64) -> Result<()> {
+        let ret = match self.mem_mapping.file_backend() {
+            // Pages of shared file mapping stay in the file, punch hole to free them.
+            Some(fb) if self.share => unsafe {
+                libc::fallocate(
+                    fb.file.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    (fb.offset + offset) as libc::off_t,
+                    size as libc::off_t,
+                )
+            },
+            // Safe, because the memory to be freed belongs to the region of device.
+            _ => unsafe {
+                libc::madvise(
+                    (self.mem_mapping.host_address() + offset) as *mut libc::c_void,
+                    size as libc::size_t,
+                    libc::MADV_DONTNEED,
+                )
+            },
+        };
+        if ret != 0 {
+            bail!(
+                "Failed to discard memory of virtio-mem, offset 0x{:x}, size 0x{:x}: {}",
+                offset,
+                size,
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+
+    fn change_range_state(&mut self, addr: u64, nb_blocks: u16, plug: bool) -> Result<()> {
+        let size = nb_blocks as u64 * self.config.block_size;
+        if !plug {
+            self.discard(addr - self.config.addr, size)?;
+        }
+        let first = self.block_index(addr);
+        for index in first..first + nb_blocks as usize {
+            if plug {
+                self.plugged.set(index)?;
+            } else {
+                self.plugged.clear(index)?;
+            }
+        }
+        if plug {
+            self.config.plugged_size += size;
+        } else {
+            self.config.plugged_size -= size;
+        }
+        Ok(())
+    }
+
+    fn unplug_all(&mut self) -> Result<()> {
+        if self.config.plugged_size == 0 {
+            return Ok(());
+        }
+        self.discard(0, self.config.region_size)?;
+        let nr_blocks = self.config.region_size / self.config.block_size;
+        self.plugged = Bitmap::<u64>::new(nr_blocks as usize / 64 + 1);
+        self.config.plugged_size = 0;
+        Ok(())
+    }
+
+    fn handle_plug_request(&mut self, req: &VirtioMemReq, plug: bool) -> VirtioMemResp {
+        if !self.is_valid_range(req.addr, req.nb_blocks) {
+            return VirtioMemResp::new(VIRTIO_MEM_RESP_ERROR);
+        }
+        let size = req.nb_blocks as u64 * self.config.block_size;
+        if plug && self.config.plugged_size + size > self.config.requested_size {
+            return VirtioMemResp::new(VIRTIO_MEM_RESP_NACK);
+        }
+        let expected = if plug {
+            VIRTIO_MEM_STATE_UNPLUGGED
+        } else {
+            VIRTIO_MEM_STATE_PLUGGED
+        };
+        if self.range_state(req.addr, req.nb_blocks) != expected {
+            return VirtioMemResp::new(VIRTIO_MEM_RESP_ERROR);
+        }
+        match self.change_range_state(req.addr, req.nb_blocks, plug) {
+            Ok(()) => VirtioMemResp::new(VIRTIO_MEM_RESP_ACK),
+            Err(e) => {
+                error!("{}", e.display_chain());
+                VirtioMemResp::new(VIRTIO_MEM_RESP_ERROR)
+            }
+        }
+    }
+
+    fn handle_request(&mut self, req: &VirtioMemReq) -> VirtioMemResp {
+        match req.req_type {
+            VIRTIO_MEM_REQ_PLUG => self.handle_plug_request(req, true),
+            VIRTIO_MEM_REQ_UNPLUG => self.handle_plug_request(req, false),
+            VIRTIO_MEM_REQ_UNPLUG_ALL => match self.unplug_all() {
+                Ok(()) => VirtioMemResp::new(VIRTIO_MEM_RESP_ACK),
+                Err(e) => {
+                    error!("{}", e.display_chain());
+                    VirtioMemResp::new(VIRTIO_MEM_RESP_ERROR)
+                }
+            },
+            VIRTIO_MEM_REQ_STATE => {
+                if !self.is_valid_range(req.addr, req.nb_blocks) {
+                    return VirtioMemResp::new(VIRTIO_MEM_RESP_ERROR);
+                }
+                VirtioMemResp {
+                    resp_type: VIRTIO_MEM_RESP_ACK,
+                    state: self.range_state(req.addr, req.nb_blocks),
+                    ..Default::default()
+                }
+            }
+            _ => VirtioMemResp::new(VIRTIO_MEM_RESP_ERROR),
+        }
+    }
+}
+
+struct VirtioMemHandler {
+    /// Id of the device.
+    id: String,
+    queue: Arc<Mutex<Queue>>,
+    queue_evt: EventFd,
+    deactivate_evt: RawFd,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    mem_space: Arc<AddressSpace>,
+    blocks: Arc<Mutex<MemBlocks>>,
+}
+
+impl VirtioMemHandler {
+    fn parse_request(&self, elem: &Element) -> Result<VirtioMemReq> {
+        let out_iov = match elem.out_iovec.first() {
+            Some(iov) if iov.len as usize >= size_of::<VirtioMemReq>() => iov,
+            _ => bail!("Invalid request of virtio-mem"),
+        };
+        match elem.in_iovec.first() {
+            Some(iov) if iov.len as usize >= size_of::<VirtioMemResp>() => {}
+            _ => bail!("Invalid response buffer of virtio-mem"),
+        }
+        self.mem_space
+            .read_object::<VirtioMemReq>(out_iov.addr)
+            .chain_err(|| "Failed to read request of virtio-mem")
+    }
+
+    fn process_queue(&mut self) -> Result<()> {
+        let mut queue_lock = self.queue.lock().unwrap();
+        let mut need_interrupt = false;
+        let old_size = self.blocks.lock().unwrap().config.plugged_size;
+
+        while let Ok(elem) = queue_lock
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            let resp = match self.parse_request(&elem) {
+                Ok(req) => {
+                    let resp = self.blocks.lock().unwrap().handle_request(&req);
+                    if resp.resp_type == VIRTIO_MEM_RESP_ACK {
+                        self.mark_discarded(&req);
+                    }
+                    resp
+                }
+                Err(ref e) => {
+                    error!("Failed to parse request, {}", e.display_chain());
+                    VirtioMemResp::new(VIRTIO_MEM_RESP_ERROR)
+                }
+            };
+            // The request is completed without response if the response buffer is invalid.
+            let mut used_len = 0;
+            if let Some(iov) = elem
+                .in_iovec
+                .first()
+                .filter(|iov| iov.len as usize >= size_of::<VirtioMemResp>())
+            {
+                self.mem_space
+                    .write_object(&resp, iov.addr)
+                    .chain_err(|| "Failed to write response of virtio-mem")?;
+                used_len = size_of::<VirtioMemResp>() as u32;
+            }
+            queue_lock
+                .vring
+                .add_used(&self.mem_space, elem.index, used_len)
+                .chain_err(|| format!("Failed to add used ring, index: {}", elem.index))?;
+            need_interrupt = true;
+        }
+
+        if need_interrupt {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock))
+                .chain_err(|| ErrorKind::InterruptTrigger("mem", VirtioInterruptType::Vring))?;
+        }
+
+        let new_size = self.blocks.lock().unwrap().config.plugged_size;
+        if new_size != old_size {
+            self.send_size_change_event(new_size);
+        }
+        Ok(())
+    }
+
+    /// Discarded memory reads as zero, mark it dirty to be sent again in live migration.
+    fn mark_discarded(&self, req: &VirtioMemReq) {
+        let config = self.blocks.lock().unwrap().config;
+        match req.req_type {
+            VIRTIO_MEM_REQ_UNPLUG => self.mem_space.mark_dirty(
+                GuestAddress(req.addr),
+                u64::from(req.nb_blocks) * config.block_size,
+            ),
+            VIRTIO_MEM_REQ_UNPLUG_ALL => self
+                .mem_space
+                .mark_dirty(GuestAddress(config.addr), config.region_size),
+            _ => {}
+        }
+    }
+
+    /// Send memory device size change event.
+    fn send_size_change_event(&self, size: u64) {
+        let msg = MemoryDeviceSizeChange {
+            id: Some(self.id.clone()),
+            size,
+            qom_path: format!("/machine/peripheral/{}", self.id),
+        };
+        event!(MemoryDeviceSizeChange; msg);
+    }
+
+    fn deactivate_evt_handler(&self) -> Vec<EventNotifier> {
+        vec![
+            EventNotifier::new(
+                NotifierOperation::Delete,
+                self.deactivate_evt,
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ),
+            EventNotifier::new(
+                NotifierOperation::Delete,
+                self.queue_evt.as_raw_fd(),
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ),
+        ]
+    }
+}
+
+impl EventNotifierHelper for VirtioMemHandler {
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn internal_notifiers(mem_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let mut notifiers = Vec::new();
+
+        // Register event notifier for queue_evt
+        let mem_handler_clone = mem_handler.clone();
+        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+
+            if let Err(ref e) = mem_handler_clone.lock().unwrap().process_queue() {
+                error!(
+                    "Failed to process queue for virtio mem, err: {}",
+                    e.display_chain(),
+                );
+            }
+
+            None
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            mem_handler.lock().unwrap().queue_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![Arc::new(Mutex::new(handler))],
+        ));
+
+        // Register event notifier for deactivate_evt
+        let mem_handler_clone = mem_handler.clone();
+        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            Some(mem_handler_clone.lock().unwrap().deactivate_evt_handler())
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            mem_handler.lock().unwrap().deactivate_evt,
+            None,
+            EventSet::IN,
+            vec![Arc::new(Mutex::new(handler))],
+        ));
+
+        notifiers
+    }
+}
+
+/// Virtio-mem device structure, which provides a hotpluggable memory region
+/// to the guest.
+pub struct VirtioMem {
+    /// Configuration of virtio-mem device.
+    mem_cfg: VirtioMemConfig,
+    /// Bitmask of features supported by the backend.
+    device_features: u64,
+    /// Bitmask of features negotiated by the backend and the frontend.
+    driver_features: u64,
+    /// Memory blocks of the hotpluggable region.
+    blocks: Arc<Mutex<MemBlocks>>,
+    /// Region of the hotpluggable memory, which is added into address space
+    /// when the device is activated firstly.
+    region: Option<Region>,
+    /// The interrupt call back function.
+    interrupt_cb: Option<Arc<VirtioInterrupt>>,
+    /// Eventfd for device deactivate.
+    deactivate_evt: EventFd,
+}
+
+impl VirtioMem {
+    /// Create a virtio-mem device.
+    ///
+    /// # Arguments
+    ///
+    /// * `mem_cfg` - Configuration of virtio-mem device.
+    /// * `mem_mapping` - Host memory mapping of the hotpluggable region.
+    /// * `share` - The memory is mapped as shared.
+    pub fn new(mem_cfg: VirtioMemConfig, mem_mapping: Arc<HostMemMapping>, share: bool) -> Self {
+        let blocks = MemBlocks::new(&mem_cfg, mem_mapping, share);
+        VirtioMem {
+            mem_cfg,
+            device_features: 0,
+            driver_features: 0,
+            blocks: Arc::new(Mutex::new(blocks)),
+            region: None,
+            interrupt_cb: None,
+            deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        }
+    }
+
+    /// Set the size of memory the guest is requested to plug, the driver is
+    /// notified by config change interrupt.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The requested size.
+    pub fn set_requested_size(&mut self, size: u64) -> Result<()> {
+        let mut blocks = self.blocks.lock().unwrap();
+        if size % blocks.config.block_size != 0 {
+            bail!(
+                "Requested size 0x{:x} is not aligned to block size 0x{:x}",
+                size,
+                blocks.config.block_size
+            );
+        }
+        if size > blocks.config.region_size {
+            bail!(
+                "Requested size 0x{:x} exceeds region size 0x{:x}",
+                size,
+                blocks.config.region_size
+            );
+        }
+        blocks.config.requested_size = size;
+        drop(blocks);
+
+        if let Some(interrupt_cb) = &self.interrupt_cb {
+            interrupt_cb(&VirtioInterruptType::Config, None)
+                .chain_err(|| ErrorKind::InterruptTrigger("mem", VirtioInterruptType::Config))?;
+        }
+        Ok(())
+    }
+}
+
+impl VirtioDevice for VirtioMem {
+    /// Realize virtio mem device.
+    fn realize(&mut self) -> Result<()> {
+        self.device_features = 1 << VIRTIO_F_VERSION_1 as u64;
+        if self.mem_cfg.node.is_some() {
+            self.device_features |= 1 << VIRTIO_MEM_F_ACPI_PXM as u64;
+        }
+        Ok(())
+    }
+
+    /// Get the virtio device type, refer to Virtio Spec.
+    fn device_type(&self) -> u32 {
+        VIRTIO_TYPE_MEM
+    }
+
+    /// Get the count of virtio device queues.
+    fn queue_num(&self) -> usize {
+        QUEUE_NUM_MEM
+    }
+
+    /// Get the queue size of virtio device.
+    fn queue_size(&self) -> u16 {
+        QUEUE_SIZE_MEM
+    }
+
+    /// Get device features from host.
+    fn get_device_features(&self, features_select: u32) -> u32 {
+        read_u32(self.device_features, features_select)
+    }
+
+    /// Set driver features by guest.
+    fn set_driver_features(&mut self, page: u32, value: u32) {
+        let mut v = write_u32(value, page);
+        let unrequested_features = v & !self.device_features;
+        if unrequested_features != 0 {
+            warn!("Received acknowledge request with unknown feature: {:x}", v);
+            v &= !unrequested_features;
+        }
+        self.driver_features |= v;
+    }
+
+    /// Read data of config from guest.
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) -> Result<()> {
+        let config_space = self.blocks.lock().unwrap().config;
+        let config_slice = config_space.as_bytes();
+        let config_len = config_slice.len() as u64;
+        if offset >= config_len {
+            return Err(ErrorKind::DevConfigOverflow(offset, config_len).into());
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            data.write_all(&config_slice[offset as usize..cmp::min(end, config_len) as usize])?;
+        }
+        Ok(())
+    }
+
+    /// Write data to config from guest.
+    fn write_config(&mut self, offset: u64, _data: &[u8]) -> Result<()> {
+        bail!(
+            "Writing device config space for virtio mem is not supported, offset: {}",
+            offset
+        );
+    }
+
+    /// Activate the virtio device, this function is called by vcpu thread when frontend
+    /// virtio driver is ready and write `DRIVER_OK` to backend.
+    fn activate(
+        &mut self,
+        mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queues: &[Arc<Mutex<Queue>>],
+        mut queue_evts: Vec<EventFd>,
+    ) -> Result<()> {
+        // The region is invisible to boot loader, so that it won't be reported as boot memory.
+        if self.region.is_none() {
+            let mem_mapping = self.blocks.lock().unwrap().mem_mapping.clone();
+            let addr = mem_mapping.start_address().raw_value();
+            let region = Region::init_ram_region(mem_mapping);
+            mem_space
+                .root()
+                .add_subregion(region.clone(), addr)
+                .chain_err(|| "Failed to add region of virtio mem")?;
+            self.region = Some(region);
+        }
+
+        self.interrupt_cb = Some(interrupt_cb.clone());
+        let handler = VirtioMemHandler {
+            id: self.mem_cfg.id.clone(),
+            queue: queues[0].clone(),
+            queue_evt: queue_evts.remove(0),
+            deactivate_evt: self.deactivate_evt.as_raw_fd(),
+            interrupt_cb,
+            driver_features: self.driver_features,
+            mem_space,
+            blocks: self.blocks.clone(),
+        };
+
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler))),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        self.deactivate_evt
+            .write(1)
+            .chain_err(|| ErrorKind::EventFdWrite)
+    }
+
+    /// All the memory is unplugged when the vm is reset.
+    fn reset(&mut self) -> Result<()> {
+        self.blocks.lock().unwrap().unplug_all()
+    }
+}
+
+/// Register the virtio-mem device, so that its requested size can be changed by `qom-set`.
+pub fn register_virtio_mem_device(mem: &Arc<Mutex<VirtioMem>>) {
+    VIRTIO_MEM_DEVICES.lock().unwrap().push(Arc::downgrade(mem));
+}
+
+/// Get the end address of the regions occupied by virtio-mem devices.
+pub fn virtio_mem_end_address() -> Option<GuestAddress> {
+    let mem_devices = VIRTIO_MEM_DEVICES.lock().unwrap();
+    mem_devices
+        .iter()
+        .filter_map(|mem| mem.upgrade())
+        .map(|mem| {
+            let blocks = mem.lock().unwrap().blocks.clone();
+            let locked_blocks = blocks.lock().unwrap();
+            GuestAddress(locked_blocks.config.addr + locked_blocks.config.region_size)
+        })
+        .max()
+}
+
+/// Set the requested size of the virtio-mem device with the id `name`.
+///
+/// # Arguments
+///
+/// * `name` - The id of the virtio-mem device.
+/// * `size` - The requested size.
+pub fn qmp_set_requested_size(name: &str, size: u64) -> Result<()> {
+    let mut mem_devices = VIRTIO_MEM_DEVICES.lock().unwrap();
+    // Drop the devices which have been removed.
+    mem_devices.retain(|mem| mem.strong_count() > 0);
+    for mem in mem_devices.iter().filter_map(|mem| mem.upgrade()) {
+        let mut locked_mem = mem.lock().unwrap();
+        if locked_mem.mem_cfg.id == name {
+            return locked_mem.set_requested_size(size);
+        }
+    }
+
+    bail!("Virtio mem device {} not found", name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: u64 = 0x20_0000;
+    const REGION_ADDR: u64 = 0x1_0000_0000;
+    const REGION_SIZE: u64 = 0x100_0000;
+
+    fn create_virtio_mem(requested_size: u64) -> VirtioMem {
+        let mem_cfg = VirtioMemConfig {
+            id: "vmem0".to_string(),
+            requested_size,
+            block_size: BLOCK_SIZE,
+            ..Default::default()
+        };
+        let mem_mapping = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(REGION_ADDR),
+                None,
+                REGION_SIZE,
+                None,
+                false,
+                false,
+                false,
+            )
+            .unwrap(),
+        );
+        VirtioMem::new(mem_cfg, mem_mapping, false)
+    }
+
+    fn mem_req(req_type: u16, addr: u64, nb_blocks: u16) -> VirtioMemReq {
+        VirtioMemReq {
+            req_type,
+            addr,
+            nb_blocks,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_virtio_mem_init() {
+        let mut mem = create_virtio_mem(0);
+        assert_eq!(size_of::<VirtioMemConfigSpace>(), 56);
+        assert_eq!(size_of::<VirtioMemReq>(), 24);
+        assert_eq!(size_of::<VirtioMemResp>(), 10);
+
+        assert!(mem.realize().is_ok());
+        assert_eq!(mem.device_type(), VIRTIO_TYPE_MEM);
+        assert_eq!(mem.queue_num(), QUEUE_NUM_MEM);
+        assert_eq!(mem.queue_size(), QUEUE_SIZE_MEM);
+        assert_eq!(mem.get_device_features(1), 1);
+        assert_eq!(mem.get_device_features(0), 0);
+
+        let mut data = [0_u8; 8];
+        assert!(mem.read_config(0, &mut data).is_ok());
+        assert_eq!(u64::from_le_bytes(data), BLOCK_SIZE);
+        assert!(mem.read_config(16, &mut data).is_ok());
+        assert_eq!(u64::from_le_bytes(data), REGION_ADDR);
+        assert!(mem.read_config(24, &mut data).is_ok());
+        assert_eq!(u64::from_le_bytes(data), REGION_SIZE);
+        assert!(mem.read_config(56, &mut data).is_err());
+        assert!(mem.write_config(48, &data).is_err());
+    }
+
+    #[test]
+    fn test_virtio_mem_requests() {
+        let mem = create_virtio_mem(2 * BLOCK_SIZE);
+        let mut blocks = mem.blocks.lock().unwrap();
+
+        // Plug more memory than requested.
+        let resp = blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_PLUG, REGION_ADDR, 3));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_NACK);
+        // Invalid ranges: no block, unaligned address, out of the region.
+        for (addr, nb_blocks) in [
+            (REGION_ADDR, 0),
+            (REGION_ADDR + 0x1000, 1),
+            (REGION_ADDR - BLOCK_SIZE, 1),
+            (REGION_ADDR + REGION_SIZE - BLOCK_SIZE, 2),
+        ] {
+            let resp = blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_PLUG, addr, nb_blocks));
+            assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        }
+
+        let resp = blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_PLUG, REGION_ADDR, 2));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(blocks.config.plugged_size, 2 * BLOCK_SIZE);
+        let resp = blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_STATE, REGION_ADDR, 2));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_PLUGGED);
+        let resp = blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_STATE, REGION_ADDR, 3));
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_MIXED);
+
+        // Unplug blocks which are not all plugged.
+        let resp = blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_UNPLUG, REGION_ADDR, 3));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ERROR);
+        let resp =
+            blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_UNPLUG, REGION_ADDR + BLOCK_SIZE, 1));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(blocks.config.plugged_size, BLOCK_SIZE);
+        let resp =
+            blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_STATE, REGION_ADDR + BLOCK_SIZE, 1));
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_UNPLUGGED);
+
+        let resp = blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_UNPLUG_ALL, 0, 0));
+        assert_eq!(resp.resp_type, VIRTIO_MEM_RESP_ACK);
+        assert_eq!(blocks.config.plugged_size, 0);
+        let resp = blocks.handle_request(&mem_req(VIRTIO_MEM_REQ_STATE, REGION_ADDR, 8));
+        assert_eq!(resp.state, VIRTIO_MEM_STATE_UNPLUGGED);
+    }
+
+    #[test]
+    fn test_virtio_mem_requested_size() {
+        let mut mem = create_virtio_mem(0);
+        assert!(mem.set_requested_size(BLOCK_SIZE + 0x1000).is_err());
+        assert!(mem.set_requested_size(REGION_SIZE + BLOCK_SIZE).is_err());
+        assert!(mem.set_requested_size(REGION_SIZE).is_ok());
+        assert_eq!(
+            mem.blocks.lock().unwrap().config.requested_size,
+            REGION_SIZE
+        );
+
+        let mem = Arc::new(Mutex::new(mem));
+        register_virtio_mem_device(&mem);
+        assert!(qmp_set_requested_size("vmem0", BLOCK_SIZE).is_ok());
+        assert_eq!(
+            mem.lock()
+                .unwrap()
+                .blocks
+                .lock()
+                .unwrap()
+                .config
+                .requested_size,
+            BLOCK_SIZE
+        );
+        assert!(qmp_set_requested_size("vmem1", BLOCK_SIZE).is_err());
+        assert_eq!(
+            virtio_mem_end_address(),
+            Some(GuestAddress(REGION_ADDR + REGION_SIZE))
+        );
+    }
+}
@@ -11,37 +11,65 @@
 // See the Mulan PSL v2 for more details.
 
 use std::io::Write;
+use std::mem::size_of;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 use std::{cmp, mem};
 
 use address_space::AddressSpace;
 use machine_manager::{
     config::{ConfigCheck, NetworkInterfaceConfig},
     event_loop::EventLoop,
+    qmp::qmp_schema::{NetdevStats, NetdevStatsInfo},
 };
 use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use once_cell::sync::Lazy;
 use util::byte_code::ByteCode;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
 use util::num_ops::{read_u32, write_u32};
 use util::tap::{Tap, TUN_F_VIRTIO};
+use util::throttle::{Throttle, ThrottleConfig, ThrottleTimer};
 use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 use super::errors::{ErrorKind, Result, ResultExt};
 use super::{
-    Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioNetHdr,
-    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM,
-    VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO,
-    VIRTIO_NET_F_MAC, VIRTIO_TYPE_NET,
+    virtio_has_feature, Element, Queue, VhostKern, VirtioDevice, VirtioInterrupt,
+    VirtioInterruptType, VirtioNetHdr, VirtioNotifyStats, VIRTIO_F_RING_EVENT_IDX,
+    VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET,
+    VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
+    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI,
+    VIRTIO_NET_CTRL_RX_ALLUNI, VIRTIO_NET_CTRL_RX_NOBCAST, VIRTIO_NET_CTRL_RX_NOMULTI,
+    VIRTIO_NET_CTRL_RX_NOUNI, VIRTIO_NET_CTRL_RX_PROMISC, VIRTIO_NET_CTRL_VLAN,
+    VIRTIO_NET_CTRL_VLAN_ADD, VIRTIO_NET_CTRL_VLAN_DEL, VIRTIO_NET_ERR, VIRTIO_NET_F_CSUM,
+    VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_RX, VIRTIO_NET_F_CTRL_RX_EXTRA,
+    VIRTIO_NET_F_CTRL_VLAN, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4,
+    VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC,
+    VIRTIO_NET_F_MQ, VIRTIO_NET_F_STATUS, VIRTIO_NET_OK, VIRTIO_NET_S_LINK_UP, VIRTIO_TYPE_NET,
 };
 
-/// Number of virtqueues.
-const QUEUE_NUM_NET: usize = 2;
 /// Size of each virtqueue.
 const QUEUE_SIZE_NET: u16 = 256;
+/// Length of mac address.
+const MAC_ADDR_LEN: usize = 6;
+/// The maximum number of mac addresses in the mac filter table.
+const CTRL_MAC_TABLE_LEN: usize = 64;
+/// The number of vlan ids.
+const CTRL_VLAN_ID_NUM: u16 = 4096;
+/// Length of ethernet header.
+const ETH_HLEN: usize = 14;
+/// Length of 802.1Q vlan tag.
+const VLAN_TAG_LEN: usize = 4;
+/// Ethernet type of 802.1Q vlan tagged frame.
+const ETH_P_8021Q: u16 = 0x8100;
+/// Mask of vlan id in vlan tag.
+const VLAN_VID_MASK: u16 = 0x0fff;
+
+/// Net devices whose link status can be changed by `set_link`.
+static NET_DEVICES: Lazy<Mutex<Vec<Weak<Mutex<Net>>>>> = Lazy::new(|| Mutex::new(Vec::new()));
 
 type SenderConfig = Option<Tap>;
 
@@ -66,31 +94,524 @@ pub struct VirtioNetConfig {
 
 impl ByteCode for VirtioNetConfig {}
 
+/// The header of the request in control virtqueue.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct CtrlHdr {
+    /// The class of the command.
+    class: u8,
+    /// The command in the class.
+    cmd: u8,
+}
+
+impl ByteCode for CtrlHdr {}
+
+/// The receive filter configured by the driver through control virtqueue.
+#[repr(C)]
+#[derive(Copy, Clone, ByteCode)]
+struct CtrlInfo {
+    /// Bit mask of receive modes enabled, indexed by `VIRTIO_NET_CTRL_RX_*` commands.
+    rx_mode: u32,
+    /// Mac address used by the driver.
+    mac: [u8; 6],
+    /// Unicast addresses overflow the mac filter table, receive all unicast packets.
+    uni_overflow: u8,
+    /// Multicast addresses overflow the mac filter table, receive all multicast packets.
+    multi_overflow: u8,
+    /// The number of mac addresses in the mac filter table.
+    mac_table_len: u32,
+    /// Index of the first multicast address in the mac filter table.
+    first_multi: u32,
+    /// Mac filter table of `CTRL_MAC_TABLE_LEN` entries, unicast addresses are
+    /// followed by multicast addresses.
+    mac_table: [u8; 384],
+    /// Bitmap of the vlan ids allowed to receive.
+    vlan_map: [u32; 128],
+}
+
+impl CtrlInfo {
+    fn new(mac: [u8; MAC_ADDR_LEN]) -> Self {
+        let mut ctrl_info = CtrlInfo::default();
+        ctrl_info.reset(mac);
+        ctrl_info
+    }
+
+    /// Reset the receive filter, all packets are received in promiscuous mode by default.
+    fn reset(&mut self, mac: [u8; MAC_ADDR_LEN]) {
+        *self = CtrlInfo::default();
+        self.rx_mode = 1 << VIRTIO_NET_CTRL_RX_PROMISC;
+        self.mac = mac;
+    }
+
+    fn rx_mode_enabled(&self, cmd: u8) -> bool {
+        self.rx_mode & (1 << cmd) != 0
+    }
+
+    fn set_rx_mode(&mut self, cmd: u8, enable: bool) {
+        if enable {
+            self.rx_mode |= 1 << cmd;
+        } else {
+            self.rx_mode &= !(1 << cmd);
+        }
+    }
+
+    /// Set the mac filter table with the unicast table and the multicast table in `data`,
+    /// each of them is a le32 number of entries followed by the mac addresses.
+    fn set_mac_table(&mut self, data: &[u8]) -> Result<()> {
+        let (uni_macs, data) = parse_mac_table(data)?;
+        let (multi_macs, _) = parse_mac_table(data)?;
+        let max_len = CTRL_MAC_TABLE_LEN * MAC_ADDR_LEN;
+
+        let mut mac_table = Vec::with_capacity(max_len);
+        let uni_overflow = uni_macs.len() > max_len;
+        if !uni_overflow {
+            mac_table.extend_from_slice(uni_macs);
+        }
+        let first_multi = mac_table.len() / MAC_ADDR_LEN;
+        let multi_overflow = mac_table.len() + multi_macs.len() > max_len;
+        if !multi_overflow {
+            mac_table.extend_from_slice(multi_macs);
+        }
+
+        self.mac_table[..mac_table.len()].copy_from_slice(&mac_table);
+        self.mac_table_len = (mac_table.len() / MAC_ADDR_LEN) as u32;
+        self.first_multi = first_multi as u32;
+        self.uni_overflow = uni_overflow as u8;
+        self.multi_overflow = multi_overflow as u8;
+
+        Ok(())
+    }
+
+    fn mac_table_contains(&self, start: usize, end: usize, mac: &[u8]) -> bool {
+        self.mac_table[start * MAC_ADDR_LEN..end * MAC_ADDR_LEN]
+            .chunks(MAC_ADDR_LEN)
+            .any(|entry| entry == mac)
+    }
+
+    fn set_vlan(&mut self, vid: u16, enable: bool) {
+        let (index, bit) = ((vid >> 5) as usize, vid & 0x1f);
+        if enable {
+            self.vlan_map[index] |= 1 << bit;
+        } else {
+            self.vlan_map[index] &= !(1 << bit);
+        }
+    }
+
+    fn vlan_enabled(&self, vid: u16) -> bool {
+        self.vlan_map[(vid >> 5) as usize] & (1 << (vid & 0x1f)) != 0
+    }
+
+    /// Check whether the ethernet frame `buf` passes the receive filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `driver_features` - Features negotiated, filters are only applied when the
+    ///   related control features are negotiated.
+    /// * `buf` - The beginning of the ethernet frame, including the vlan tag if any.
+    fn filter_packet(&self, driver_features: u64, buf: &[u8]) -> bool {
+        if buf.len() < ETH_HLEN {
+            return true;
+        }
+        let rx_filter = virtio_has_feature(driver_features, VIRTIO_NET_F_CTRL_RX);
+        if rx_filter && self.rx_mode_enabled(VIRTIO_NET_CTRL_RX_PROMISC) {
+            return true;
+        }
+
+        let eth_type = u16::from_be_bytes([buf[12], buf[13]]);
+        if virtio_has_feature(driver_features, VIRTIO_NET_F_CTRL_VLAN)
+            && eth_type == ETH_P_8021Q
+            && buf.len() >= ETH_HLEN + VLAN_TAG_LEN
+        {
+            let vid = u16::from_be_bytes([buf[14], buf[15]]) & VLAN_VID_MASK;
+            if !self.vlan_enabled(vid) {
+                return false;
+            }
+        }
+        if !rx_filter {
+            return true;
+        }
+
+        let dst = &buf[..MAC_ADDR_LEN];
+        let first_multi = self.first_multi as usize;
+        if dst[0] & 0x01 != 0 {
+            if dst.iter().all(|b| *b == 0xff) {
+                !self.rx_mode_enabled(VIRTIO_NET_CTRL_RX_NOBCAST)
+            } else if self.rx_mode_enabled(VIRTIO_NET_CTRL_RX_NOMULTI) {
+                false
+            } else if self.rx_mode_enabled(VIRTIO_NET_CTRL_RX_ALLMULTI) || self.multi_overflow != 0
+            {
+                true
+            } else {
+                self.mac_table_contains(first_multi, self.mac_table_len as usize, dst)
+            }
+        } else if self.rx_mode_enabled(VIRTIO_NET_CTRL_RX_NOUNI) {
+            false
+        } else if self.rx_mode_enabled(VIRTIO_NET_CTRL_RX_ALLUNI) || self.uni_overflow != 0 {
+            true
+        } else {
+            dst == self.mac || self.mac_table_contains(0, first_multi, dst)
+        }
+    }
+}
+
+/// Parse the mac table in `data`, which is a le32 number of entries followed by
+/// the mac addresses. Return the mac addresses and the remaining data.
+fn parse_mac_table(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    if data.len() < size_of::<u32>() {
+        bail!("Invalid length {} of mac table", data.len());
+    }
+    let (entries, data) = data.split_at(size_of::<u32>());
+    let entries = u32::from_le_bytes([entries[0], entries[1], entries[2], entries[3]]) as usize;
+    let len = entries * MAC_ADDR_LEN;
+    if data.len() < len {
+        bail!("Invalid number {} of mac table entries", entries);
+    }
+
+    Ok(data.split_at(len))
+}
+
+struct CtrlVirtio {
+    queue: Arc<Mutex<Queue>>,
+    queue_evt: EventFd,
+}
+
+impl CtrlVirtio {
+    fn new(queue: Arc<Mutex<Queue>>, queue_evt: EventFd) -> Self {
+        CtrlVirtio { queue, queue_evt }
+    }
+}
+
+/// Handler of the control virtqueue.
+struct NetCtrlHandler {
+    ctrl: CtrlVirtio,
+    /// Queues of the multiqueue tap device, which are attached or detached with the
+    /// number of queue pairs in use.
+    taps: Vec<Tap>,
+    /// The number of queue pairs in use.
+    queue_pairs: u16,
+    /// The number of queue pairs of the device.
+    max_queue_pairs: u16,
+    /// The receive filter shared with the io handlers.
+    ctrl_info: Arc<Mutex<CtrlInfo>>,
+    /// Virtio net configurations shared with the device.
+    config_space: Arc<Mutex<VirtioNetConfig>>,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    deactivate_evt: RawFd,
+    /// Counters of notifications of the transport.
+    notify_stats: Arc<VirtioNotifyStats>,
+}
+
+impl NetCtrlHandler {
+    fn handle_ctrl(&mut self) -> Result<()> {
+        let queue = self.ctrl.queue.clone();
+        let mut locked_queue = queue.lock().unwrap();
+        let mut need_irq = false;
+
+        while let Ok(elem) = locked_queue
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            let ack = match self.handle_ctrl_request(&elem) {
+                Ok(()) => VIRTIO_NET_OK,
+                Err(ref e) => {
+                    error!(
+                        "Failed to handle net control request, {}",
+                        error_chain::ChainedError::display_chain(e)
+                    );
+                    VIRTIO_NET_ERR
+                }
+            };
+            let status = match elem.in_iovec.last() {
+                Some(iov) if iov.len as usize >= size_of::<u8>() => iov.addr,
+                _ => bail!("Invalid status of net control request"),
+            };
+            self.mem_space
+                .write_object::<u8>(&ack, status)
+                .chain_err(|| "Failed to write the ack of net control request")?;
+
+            locked_queue
+                .vring
+                .add_used(&self.mem_space, elem.index, size_of::<u8>() as u32)
+                .chain_err(|| {
+                    format!(
+                        "Failed to add used ring for net control queue, index: {}",
+                        elem.index
+                    )
+                })?;
+            need_irq = true;
+        }
+
+        if need_irq {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue))
+                .chain_err(|| ErrorKind::InterruptTrigger("net", VirtioInterruptType::Vring))?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_ctrl_request(&mut self, elem: &Element) -> Result<()> {
+        let mut data = Vec::new();
+        for iov in elem.out_iovec.iter() {
+            self.mem_space
+                .read(&mut data, iov.addr, iov.len as u64)
+                .chain_err(|| "Failed to read net control request")?;
+        }
+        let hdr_len = size_of::<CtrlHdr>();
+        if data.len() < hdr_len {
+            bail!("Invalid length {} of net control request", data.len());
+        }
+        let hdr = *CtrlHdr::from_bytes(&data[..hdr_len]).unwrap();
+
+        let data = &data[hdr_len..];
+        match hdr.class {
+            VIRTIO_NET_CTRL_RX => self.handle_rx_mode(hdr.cmd, data),
+            VIRTIO_NET_CTRL_MAC => self.handle_mac(hdr.cmd, data),
+            VIRTIO_NET_CTRL_VLAN => self.handle_vlan(hdr.cmd, data),
+            VIRTIO_NET_CTRL_MQ => self.handle_mq(hdr.cmd, data),
+            _ => bail!("Unsupported net control class {}", hdr.class),
+        }
+    }
+
+    fn handle_rx_mode(&mut self, cmd: u8, data: &[u8]) -> Result<()> {
+        if cmd > VIRTIO_NET_CTRL_RX_NOBCAST {
+            bail!("Unsupported net rx mode command {}", cmd);
+        }
+        if data.is_empty() {
+            bail!("Invalid length {} of rx mode", data.len());
+        }
+
+        self.ctrl_info
+            .lock()
+            .unwrap()
+            .set_rx_mode(cmd, data[0] != 0);
+        Ok(())
+    }
+
+    fn handle_mac(&mut self, cmd: u8, data: &[u8]) -> Result<()> {
+        let mut ctrl_info = self.ctrl_info.lock().unwrap();
+        match cmd {
+            VIRTIO_NET_CTRL_MAC_TABLE_SET => ctrl_info.set_mac_table(data),
+            VIRTIO_NET_CTRL_MAC_ADDR_SET => {
+                if data.len() < MAC_ADDR_LEN {
+                    bail!("Invalid length {} of mac address", data.len());
+                }
+                ctrl_info.mac.copy_from_slice(&data[..MAC_ADDR_LEN]);
+                self.config_space.lock().unwrap().mac = ctrl_info.mac;
+                Ok(())
+            }
+            _ => bail!("Unsupported net mac command {}", cmd),
+        }
+    }
+
+    fn handle_vlan(&mut self, cmd: u8, data: &[u8]) -> Result<()> {
+        if data.len() < size_of::<u16>() {
+            bail!("Invalid length {} of vlan id", data.len());
+        }
+        let vid = u16::from_le_bytes([data[0], data[1]]);
+        if vid >= CTRL_VLAN_ID_NUM {
+            bail!("Invalid vlan id {}", vid);
+        }
+
+        let mut ctrl_info = self.ctrl_info.lock().unwrap();
+        match cmd {
+            VIRTIO_NET_CTRL_VLAN_ADD => ctrl_info.set_vlan(vid, true),
+            VIRTIO_NET_CTRL_VLAN_DEL => ctrl_info.set_vlan(vid, false),
+            _ => bail!("Unsupported net vlan command {}", cmd),
+        }
+        Ok(())
+    }
+
+    fn handle_mq(&mut self, cmd: u8, data: &[u8]) -> Result<()> {
+        if cmd != VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET {
+            bail!("Unsupported net multiqueue command {}", cmd);
+        }
+        if data.len() < size_of::<u16>() {
+            bail!("Invalid length {} of virtqueue pairs", data.len());
+        }
+        let queue_pairs = u16::from_le_bytes([data[0], data[1]]);
+        if !(VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN..=self.max_queue_pairs).contains(&queue_pairs) {
+            bail!(
+                "Invalid number of virtqueue pairs {}, the maximum is {}",
+                queue_pairs,
+                self.max_queue_pairs
+            );
+        }
+
+        self.set_queue_pairs(queue_pairs)
+    }
+
+    fn set_queue_pairs(&mut self, queue_pairs: u16) -> Result<()> {
+        set_tap_queues(&self.taps, queue_pairs, self.queue_pairs)?;
+        self.queue_pairs = queue_pairs;
+
+        Ok(())
+    }
+
+    fn deactivate_evt_handler(&mut self) -> Vec<EventNotifier> {
+        // Attach all the tap queues for the next activation.
+        if let Err(ref e) = self.set_queue_pairs(self.taps.len() as u16) {
+            error!(
+                "Failed to reset queues of tap, {}",
+                error_chain::ChainedError::display_chain(e)
+            );
+        }
+
+        vec![
+            build_event_notifier(
+                self.deactivate_evt,
+                None,
+                NotifierOperation::Delete,
+                EventSet::IN,
+            ),
+            build_event_notifier(
+                self.ctrl.queue_evt.as_raw_fd(),
+                None,
+                NotifierOperation::Delete,
+                EventSet::IN,
+            ),
+        ]
+    }
+}
+
+impl EventNotifierHelper for NetCtrlHandler {
+    fn internal_notifiers(net_ctrl: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let locked_net_ctrl = net_ctrl.lock().unwrap();
+        let mut notifiers = Vec::new();
+
+        // Register event notifier for deactivate_evt.
+        let cloned_net_ctrl = net_ctrl.clone();
+        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            Some(cloned_net_ctrl.lock().unwrap().deactivate_evt_handler())
+        });
+        notifiers.push(build_event_notifier(
+            locked_net_ctrl.deactivate_evt,
+            Some(handler),
+            NotifierOperation::AddShared,
+            EventSet::IN,
+        ));
+
+        // Register event notifier for ctrl.
+        let cloned_net_ctrl = net_ctrl.clone();
+        let notify_stats = locked_net_ctrl.notify_stats.clone();
+        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            notify_stats.account_queue_notifies(read_fd(fd));
+            if let Err(ref e) = cloned_net_ctrl.lock().unwrap().handle_ctrl() {
+                error!(
+                    "Failed to handle ctrl queue for net, {}",
+                    error_chain::ChainedError::display_chain(e)
+                );
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(
+            locked_net_ctrl.ctrl.queue_evt.as_raw_fd(),
+            Some(handler),
+            NotifierOperation::AddShared,
+            EventSet::IN,
+        ));
+
+        notifiers
+    }
+}
+
+/// The rate limit of a net device, `None` if packets are not limited.
+type SharedRateLimit = Arc<Mutex<Option<Throttle>>>;
+
+/// Packet statistics of a net device, shared by the IO handlers of all queue pairs.
+#[derive(Default)]
+struct NetIoStats {
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    rx_dropped: AtomicU64,
+    rx_errors: AtomicU64,
+    rx_queue_full: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    tx_dropped: AtomicU64,
+    tx_errors: AtomicU64,
+}
+
+impl NetIoStats {
+    fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account a packet received or transmitted by the guest.
+    ///
+    /// # Arguments
+    ///
+    /// * `packets` - The packets counter of the direction.
+    /// * `bytes` - The bytes counter of the direction.
+    /// * `len` - Length of the packet including the virtio net header.
+    fn account_packet(packets: &AtomicU64, bytes: &AtomicU64, len: u64) {
+        packets.fetch_add(1, Ordering::Relaxed);
+        let hdr_len = size_of::<VirtioNetHdr>() as u64;
+        bytes.fetch_add(len.saturating_sub(hdr_len), Ordering::Relaxed);
+    }
+
+    /// Get the statistics, and reset them to 0 if `reset` is true.
+    fn stats(&self, reset: bool) -> NetdevStats {
+        let read = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+        NetdevStats {
+            rx_packets: read(&self.rx_packets),
+            rx_bytes: read(&self.rx_bytes),
+            rx_dropped: read(&self.rx_dropped),
+            rx_errors: read(&self.rx_errors),
+            rx_queue_full: read(&self.rx_queue_full),
+            tx_packets: read(&self.tx_packets),
+            tx_bytes: read(&self.tx_bytes),
+            tx_dropped: read(&self.tx_dropped),
+            tx_errors: read(&self.tx_errors),
+        }
+    }
+}
+
 struct TxVirtio {
     queue: Arc<Mutex<Queue>>,
     queue_evt: EventFd,
+    /// Timer to wake up the handler when the rate limit allows more packets.
+    rate_limit_timer: ThrottleTimer,
 }
 
 impl TxVirtio {
     fn new(queue: Arc<Mutex<Queue>>, queue_evt: EventFd) -> Self {
-        TxVirtio { queue, queue_evt }
+        TxVirtio {
+            queue,
+            queue_evt,
+            rate_limit_timer: ThrottleTimer::default(),
+        }
     }
 }
 
 struct RxVirtio {
     queue_full: bool,
+    /// Receiving is paused until the rate limit allows more packets.
+    rate_limited: bool,
     need_irqs: bool,
     queue: Arc<Mutex<Queue>>,
     queue_evt: EventFd,
+    /// Timer to wake up the handler when the rate limit allows more packets.
+    rate_limit_timer: ThrottleTimer,
 }
 
 impl RxVirtio {
     fn new(queue: Arc<Mutex<Queue>>, queue_evt: EventFd) -> Self {
         RxVirtio {
             queue_full: false,
+            rate_limited: false,
             need_irqs: false,
             queue,
             queue_evt,
+            rate_limit_timer: ThrottleTimer::default(),
         }
     }
 }
@@ -103,6 +624,18 @@ struct NetIoHandler {
     mem_space: Arc<AddressSpace>,
     interrupt_cb: Arc<VirtioInterrupt>,
     driver_features: u64,
+    /// The receive filter configured through control virtqueue.
+    ctrl_info: Arc<Mutex<CtrlInfo>>,
+    /// Packets are dropped when the link is down.
+    link_up: Arc<AtomicBool>,
+    /// The rate limit of the device, shared by all queue pairs.
+    rate_limit: SharedRateLimit,
+    /// The packet statistics of the device, shared by all queue pairs.
+    stats: Arc<NetIoStats>,
+    /// Counters of notifications of the transport.
+    notify_stats: Arc<VirtioNotifyStats>,
+    /// The iothread which the handler runs in.
+    iothread: Option<String>,
     receiver: Receiver<SenderConfig>,
     update_evt: RawFd,
     deactivate_evt: RawFd,
@@ -114,9 +647,21 @@ impl NetIoHandler {
         let mut queue = self.rx.queue.lock().unwrap();
         while let Some(tap) = self.tap.as_mut() {
             if queue.vring.avail_ring_len(&self.mem_space)? == 0 {
+                if !self.rx.queue_full {
+                    NetIoStats::inc(&self.stats.rx_queue_full);
+                }
                 self.rx.queue_full = true;
                 break;
             }
+            if Self::rate_limited(
+                &self.rate_limit,
+                &mut self.rx.rate_limit_timer,
+                self.iothread.as_ref(),
+                false,
+            )? {
+                self.rx.rate_limited = true;
+                break;
+            }
             let elem = queue
                 .vring
                 .pop_avail(&self.mem_space, self.driver_features)
@@ -149,8 +694,26 @@ impl NetIoHandler {
                 if e.kind() == std::io::ErrorKind::WouldBlock {
                     break;
                 }
+                NetIoStats::inc(&self.stats.rx_errors);
                 bail!("Failed to call readv for net handle_rx: {}", e);
             }
+            let mut left = write_count as u64;
+            for elem_iov in elem.in_iovec.iter() {
+                let len = cmp::min(left, u64::from(elem_iov.len));
+                self.mem_space.mark_dirty(elem_iov.addr, len);
+                left -= len;
+            }
+            if let Some(rate_limit) = self.rate_limit.lock().unwrap().as_mut() {
+                rate_limit.account(false, write_count as u64);
+            }
+            if !self.link_up.load(Ordering::Acquire)
+                || !self.filter_packet(&elem, write_count as usize)?
+            {
+                // Drop the packet and reuse the buffer.
+                queue.vring.push_back();
+                NetIoStats::inc(&self.stats.rx_dropped);
+                continue;
+            }
 
             queue
                 .vring
@@ -161,6 +724,11 @@ impl NetIoHandler {
                         elem.index, write_count
                     )
                 })?;
+            NetIoStats::account_packet(
+                &self.stats.rx_packets,
+                &self.stats.rx_bytes,
+                write_count as u64,
+            );
             self.rx.need_irqs = true;
         }
 
@@ -173,11 +741,56 @@ impl NetIoHandler {
         Ok(())
     }
 
+    /// Check whether the packet received in `elem` passes the receive filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `elem` - The element which the packet is received in.
+    /// * `len` - Length of the packet including the virtio net header.
+    fn filter_packet(&self, elem: &Element, len: usize) -> Result<bool> {
+        if !virtio_has_feature(self.driver_features, VIRTIO_NET_F_CTRL_RX)
+            && !virtio_has_feature(self.driver_features, VIRTIO_NET_F_CTRL_VLAN)
+        {
+            return Ok(true);
+        }
+
+        let hdr_len = size_of::<VirtioNetHdr>();
+        let len = cmp::min(len, hdr_len + ETH_HLEN + VLAN_TAG_LEN);
+        let mut buf = Vec::with_capacity(len);
+        for iov in elem.in_iovec.iter() {
+            if buf.len() >= len {
+                break;
+            }
+            let count = cmp::min(len - buf.len(), iov.len as usize);
+            self.mem_space
+                .read(&mut buf, iov.addr, count as u64)
+                .chain_err(|| "Failed to read the header of net rx packet")?;
+        }
+        if buf.len() < hdr_len {
+            return Ok(true);
+        }
+
+        Ok(self
+            .ctrl_info
+            .lock()
+            .unwrap()
+            .filter_packet(self.driver_features, &buf[hdr_len..]))
+    }
+
     fn handle_tx(&mut self) -> Result<()> {
         let mut queue = self.tx.queue.lock().unwrap();
         let mut need_irq = false;
 
         while let Ok(elem) = queue.vring.pop_avail(&self.mem_space, self.driver_features) {
+            if Self::rate_limited(
+                &self.rate_limit,
+                &mut self.tx.rate_limit_timer,
+                self.iothread.as_ref(),
+                true,
+            )? {
+                queue.vring.push_back();
+                break;
+            }
             let mut iovecs = Vec::new();
             for elem_iov in elem.out_iovec.iter() {
                 let host_addr = queue
@@ -194,8 +807,10 @@ impl NetIoHandler {
                 }
             }
             let mut read_len = 0;
+            let mut sent = false;
             if let Some(tap) = self.tap.as_mut() {
-                if !iovecs.is_empty() {
+                // Packets are dropped when the link is down.
+                if !iovecs.is_empty() && self.link_up.load(Ordering::Acquire) {
                     read_len = unsafe {
                         libc::writev(
                             tap.as_raw_fd() as libc::c_int,
@@ -203,12 +818,23 @@ impl NetIoHandler {
                             iovecs.len() as libc::c_int,
                         )
                     };
+                    sent = true;
                 }
             };
             if read_len < 0 {
                 let e = std::io::Error::last_os_error();
+                NetIoStats::inc(&self.stats.tx_errors);
                 bail!("Failed to call writev for net handle_tx: {}", e);
             }
+            let len = elem.out_iovec.iter().map(|iov| iov.len as u64).sum();
+            if let Some(rate_limit) = self.rate_limit.lock().unwrap().as_mut() {
+                rate_limit.account(true, len);
+            }
+            if sent {
+                NetIoStats::account_packet(&self.stats.tx_packets, &self.stats.tx_bytes, len);
+            } else {
+                NetIoStats::inc(&self.stats.tx_dropped);
+            }
 
             queue
                 .vring
@@ -226,6 +852,25 @@ impl NetIoHandler {
         Ok(())
     }
 
+    /// Return true if the packets of the direction must wait for the rate limit.
+    fn rate_limited(
+        rate_limit: &SharedRateLimit,
+        rate_limit_timer: &mut ThrottleTimer,
+        iothread: Option<&String>,
+        is_tx: bool,
+    ) -> Result<bool> {
+        let mut locked_rate_limit = rate_limit.lock().unwrap();
+        let rate_limit = match locked_rate_limit.as_mut() {
+            Some(rate_limit) => rate_limit,
+            None => return Ok(false),
+        };
+        let ctx = match EventLoop::get_ctx(iothread) {
+            Some(ctx) => ctx,
+            None => bail!("IOThread {:?} of Net is not found in cmdline.", iothread),
+        };
+        Ok(rate_limit_timer.throttled(rate_limit, ctx, is_tx))
+    }
+
     fn update_evt_handler(net_io: &Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut locked_net_io = net_io.lock().unwrap();
         locked_net_io.tap = match locked_net_io.receiver.recv() {
@@ -260,6 +905,18 @@ impl NetIoHandler {
                 NotifierOperation::Delete,
                 EventSet::IN,
             ),
+            build_event_notifier(
+                locked_net_io.rx.rate_limit_timer.as_raw_fd(),
+                None,
+                NotifierOperation::Delete,
+                EventSet::IN,
+            ),
+            build_event_notifier(
+                locked_net_io.tx.rate_limit_timer.as_raw_fd(),
+                None,
+                NotifierOperation::Delete,
+                EventSet::IN,
+            ),
         ];
         if old_tap_fd != -1 {
             notifiers.push(build_event_notifier(
@@ -305,6 +962,20 @@ impl NetIoHandler {
                 EventSet::IN,
                 Vec::new(),
             ),
+            EventNotifier::new(
+                NotifierOperation::Delete,
+                self.rx.rate_limit_timer.as_raw_fd(),
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ),
+            EventNotifier::new(
+                NotifierOperation::Delete,
+                self.tx.rate_limit_timer.as_raw_fd(),
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ),
         ];
         if self.tap_fd != -1 {
             notifiers.push(EventNotifier::new(
@@ -367,9 +1038,10 @@ impl EventNotifierHelper for NetIoHandler {
 
         // Register event notifier for rx.
         let cloned_net_io = net_io.clone();
+        let notify_stats = locked_net_io.notify_stats.clone();
         let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
             let mut locked_net_io = cloned_net_io.lock().unwrap();
-            read_fd(fd);
+            notify_stats.account_queue_notifies(read_fd(fd));
             if let Some(tap) = locked_net_io.tap.as_ref() {
                 if !locked_net_io.is_listening {
                     let notifier = vec![EventNotifier::new(
@@ -395,8 +1067,9 @@ impl EventNotifierHelper for NetIoHandler {
 
         // Register event notifier for tx.
         let cloned_net_io = net_io.clone();
+        let notify_stats = locked_net_io.notify_stats.clone();
         let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            notify_stats.account_queue_notifies(read_fd(fd));
             if let Err(ref e) = cloned_net_io.lock().unwrap().handle_tx() {
                 error!(
                     "Failed to handle tx(tx event) for net, {}",
@@ -413,6 +1086,60 @@ impl EventNotifierHelper for NetIoHandler {
             EventSet::IN,
         ));
 
+        // Register timer event notifier for rx rate limit, which resumes listening
+        // to the tap. The limits may be set on the fly.
+        let cloned_net_io = net_io.clone();
+        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut locked_net_io = cloned_net_io.lock().unwrap();
+            locked_net_io.rx.rate_limit_timer.clear_timer();
+            if !locked_net_io.rx.rate_limited {
+                return None;
+            }
+            locked_net_io.rx.rate_limited = false;
+            if let Some(tap) = locked_net_io.tap.as_ref() {
+                if !locked_net_io.is_listening {
+                    let notifier = vec![EventNotifier::new(
+                        NotifierOperation::Resume,
+                        tap.as_raw_fd(),
+                        None,
+                        EventSet::IN,
+                        Vec::new(),
+                    )];
+                    locked_net_io.is_listening = true;
+                    return Some(notifier);
+                }
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(
+            locked_net_io.rx.rate_limit_timer.as_raw_fd(),
+            Some(handler),
+            NotifierOperation::AddShared,
+            EventSet::IN,
+        ));
+
+        // Register timer event notifier for tx rate limit.
+        let cloned_net_io = net_io.clone();
+        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut locked_net_io = cloned_net_io.lock().unwrap();
+            locked_net_io.tx.rate_limit_timer.clear_timer();
+            if let Err(ref e) = locked_net_io.handle_tx() {
+                error!(
+                    "Failed to handle tx(rate limit timer) for net, {}",
+                    error_chain::ChainedError::display_chain(e)
+                );
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(
+            locked_net_io.tx.rate_limit_timer.as_raw_fd(),
+            Some(handler),
+            NotifierOperation::AddShared,
+            EventSet::IN,
+        ));
+
         // Register event notifier for tap.
         let cloned_net_io = net_io.clone();
         if let Some(tap) = locked_net_io.tap.as_ref() {
@@ -426,7 +1153,9 @@ impl EventNotifierHelper for NetIoHandler {
                 }
 
                 if let Some(tap) = locked_net_io.tap.as_ref() {
-                    if locked_net_io.rx.queue_full {
+                    // Stop listening to the tap until the guest fills the queue,
+                    // or the rate limit allows more packets.
+                    if locked_net_io.rx.queue_full || locked_net_io.rx.rate_limited {
                         let notifier = vec![EventNotifier::new(
                             NotifierOperation::Park,
                             tap.as_raw_fd(),
@@ -457,7 +1186,7 @@ impl EventNotifierHelper for NetIoHandler {
 /// Status of net device.
 #[repr(C)]
 #[derive(Copy, Clone, Desc, ByteCode)]
-#[desc_version(compat_version = "0.1.0")]
+#[desc_version(current_version = "2.2.0", compat_version = "0.1.0")]
 pub struct VirtioNetState {
     /// Bit mask of features supported by the backend.
     device_features: u64,
@@ -465,48 +1194,224 @@ pub struct VirtioNetState {
     driver_features: u64,
     /// Virtio net configurations.
     config_space: VirtioNetConfig,
+    /// The receive filter configured through control virtqueue.
+    ctrl_info: CtrlInfo,
 }
 
 /// Network device structure.
 pub struct Net {
     /// Configuration of the network device.
     net_cfg: NetworkInterfaceConfig,
-    /// Tap device opened.
-    tap: Option<Tap>,
+    /// Tap device opened, one queue of tap for each queue pair.
+    taps: Option<Vec<Tap>>,
     /// The status of net device.
     state: VirtioNetState,
-    /// The send half of Rust's channel to send tap information.
-    sender: Option<Sender<SenderConfig>>,
-    /// Eventfd for config space update.
-    update_evt: EventFd,
-    /// Eventfd for device deactivate.
-    deactivate_evt: EventFd,
+    /// The send half of Rust's channel to send tap information, one for each queue pair.
+    senders: Option<Vec<Sender<SenderConfig>>>,
+    /// Eventfd for config space update, one for each queue pair.
+    update_evts: Vec<EventFd>,
+    /// Eventfd for device deactivate, one for each queue pair and control queue.
+    deactivate_evts: Vec<EventFd>,
+    /// Virtio net configurations, the mac address may be set by the control handler.
+    config_space: Arc<Mutex<VirtioNetConfig>>,
+    /// The receive filter configured through control virtqueue.
+    ctrl_info: Arc<Mutex<CtrlInfo>>,
+    /// The link status of the device.
+    link_up: Arc<AtomicBool>,
+    /// The rate limit of the device, shared with the IO handlers.
+    rate_limit: SharedRateLimit,
+    /// The packet statistics of the device, shared with the IO handlers.
+    stats: Arc<NetIoStats>,
+    /// The interrupt callback to notify the driver of link status change.
+    interrupt_cb: Option<Arc<VirtioInterrupt>>,
+    /// Counters of notifications of the transport, shared with the handlers.
+    notify_stats: Arc<VirtioNotifyStats>,
 }
 
 impl Default for Net {
     fn default() -> Self {
-        Self {
-            net_cfg: Default::default(),
-            tap: None,
-            state: VirtioNetState::default(),
-            sender: None,
-            update_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
-            deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
-        }
+        Net::new(Default::default())
     }
 }
 
 impl Net {
     pub fn new(net_cfg: NetworkInterfaceConfig) -> Self {
+        let queue_pairs = if net_cfg.mq {
+            net_cfg.queues as usize
+        } else {
+            1
+        };
+
+        let rate_limit = get_rate_limit(net_cfg.rate_limit);
         Self {
             net_cfg,
-            tap: None,
+            taps: None,
             state: VirtioNetState::default(),
-            sender: None,
-            update_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
-            deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            senders: None,
+            update_evts: create_event_fds(queue_pairs),
+            // One more deactivate eventfd for control queue.
+            deactivate_evts: create_event_fds(queue_pairs + 1),
+            config_space: Arc::new(Mutex::new(VirtioNetConfig::default())),
+            ctrl_info: Arc::new(Mutex::new(CtrlInfo::new([0; MAC_ADDR_LEN]))),
+            link_up: Arc::new(AtomicBool::new(true)),
+            rate_limit: Arc::new(Mutex::new(rate_limit)),
+            stats: Arc::new(NetIoStats::default()),
+            interrupt_cb: None,
+            notify_stats: Arc::new(VirtioNotifyStats::default()),
         }
     }
+
+    /// Set the rate limits of the device, which take effect immediately.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The limits, packets are not limited if no limit is set.
+    pub fn set_rate_limit(&mut self, config: ThrottleConfig) {
+        *self.rate_limit.lock().unwrap() = get_rate_limit(config);
+        self.net_cfg.rate_limit = config;
+    }
+
+    /// Set the link status of the device, and notify the driver by config interrupt.
+    ///
+    /// # Arguments
+    ///
+    /// * `up` - Whether the link is up.
+    pub fn set_link(&mut self, up: bool) -> Result<()> {
+        self.link_up.store(up, Ordering::Release);
+        let mut config_space = self.config_space.lock().unwrap();
+        if up {
+            config_space.status |= VIRTIO_NET_S_LINK_UP;
+        } else {
+            config_space.status &= !VIRTIO_NET_S_LINK_UP;
+        }
+        drop(config_space);
+
+        if let Some(interrupt_cb) = &self.interrupt_cb {
+            interrupt_cb(&VirtioInterruptType::Config, None)
+                .chain_err(|| ErrorKind::InterruptTrigger("net", VirtioInterruptType::Config))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Register the net device, so that its link status can be changed by `set_link`.
+pub fn register_net_device(net: &Arc<Mutex<Net>>) {
+    NET_DEVICES.lock().unwrap().push(Arc::downgrade(net));
+}
+
+/// Set the link status of the net device with the id `name`.
+///
+/// # Arguments
+///
+/// * `name` - The id of the net device.
+/// * `up` - Whether the link is up.
+pub fn qmp_set_link(name: &str, up: bool) -> Result<()> {
+    let mut net_devices = NET_DEVICES.lock().unwrap();
+    // Drop the devices which have been removed.
+    net_devices.retain(|net| net.strong_count() > 0);
+    for net in net_devices.iter().filter_map(|net| net.upgrade()) {
+        let mut locked_net = net.lock().unwrap();
+        if locked_net.net_cfg.id == name {
+            return locked_net.set_link(up);
+        }
+    }
+
+    bail!("Net device {} not found", name);
+}
+
+/// Set the rate limits of the net device with the id `name`.
+///
+/// # Arguments
+///
+/// * `name` - The id of the net device.
+/// * `config` - The limits, packets are not limited if no limit is set.
+pub fn qmp_net_set_rate_limit(name: &str, config: ThrottleConfig) -> Result<()> {
+    let mut net_devices = NET_DEVICES.lock().unwrap();
+    net_devices.retain(|net| net.strong_count() > 0);
+    for net in net_devices.iter().filter_map(|net| net.upgrade()) {
+        let mut locked_net = net.lock().unwrap();
+        if locked_net.net_cfg.id == name {
+            locked_net.set_rate_limit(config);
+            return Ok(());
+        }
+    }
+
+    bail!("Net device {} not found", name);
+}
+
+/// Get the packet statistics of the net devices for `query-netdev-stats`.
+///
+/// # Arguments
+///
+/// * `name` - The id of the net device, all net devices are queried if it is `None`.
+/// * `reset` - Reset the statistics after they are read.
+pub fn qmp_query_netdev_stats(name: Option<&str>, reset: bool) -> Result<Vec<NetdevStatsInfo>> {
+    let mut infos = Vec::new();
+    let mut net_devices = NET_DEVICES.lock().unwrap();
+    net_devices.retain(|net| net.strong_count() > 0);
+    for net in net_devices.iter().filter_map(|net| net.upgrade()) {
+        let locked_net = net.lock().unwrap();
+        if let Some(name) = name {
+            if locked_net.net_cfg.id != name {
+                continue;
+            }
+        }
+        infos.push(NetdevStatsInfo {
+            id: locked_net.net_cfg.id.clone(),
+            stats: locked_net.stats.stats(reset),
+        });
+    }
+    drop(net_devices);
+    infos.extend(VhostKern::query_net_stats(name, reset));
+
+    if let Some(name) = name {
+        if infos.is_empty() {
+            bail!("Net device {} not found", name);
+        }
+    }
+    Ok(infos)
+}
+
+/// Describe the registered net devices for human monitor, one device per line.
+pub fn hmp_info_network() -> String {
+    let mut net_devices = NET_DEVICES.lock().unwrap();
+    net_devices.retain(|net| net.strong_count() > 0);
+    let mut info = String::new();
+    for net in net_devices.iter().filter_map(|net| net.upgrade()) {
+        let locked_net = net.lock().unwrap();
+        let mac = locked_net.config_space.lock().unwrap().mac;
+        info.push_str(&format!(
+            "{}: ifname={}, macaddr={}, queues={}, link={}\n",
+            locked_net.net_cfg.id,
+            locked_net.net_cfg.host_dev_name,
+            mac.iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<String>>()
+                .join(":"),
+            locked_net.net_cfg.queues,
+            if locked_net.link_up.load(Ordering::Acquire) {
+                "up"
+            } else {
+                "down"
+            }
+        ));
+    }
+    info
+}
+
+fn get_rate_limit(config: ThrottleConfig) -> Option<Throttle> {
+    if config.enabled() {
+        Some(Throttle::new(config))
+    } else {
+        None
+    }
+}
+
+fn create_event_fds(num: usize) -> Vec<EventFd> {
+    (0..num)
+        .map(|_| EventFd::new(libc::EFD_NONBLOCK).unwrap())
+        .collect()
 }
 
 /// Set Mac address configured into the virtio configuration, and return features mask with
@@ -536,33 +1441,104 @@ pub fn build_device_config_space(device_config: &mut VirtioNetConfig, mac: &str)
 ///
 /// # Arguments
 ///
-/// * `net_fd` - Fd of tap device opened.
+/// * `net_fds` - Fds of tap device opened, one for each queue pair.
 /// * `host_dev_name` - Path of tap device on host.
-pub fn create_tap(net_fd: Option<i32>, host_dev_name: Option<&str>) -> Result<Option<Tap>> {
-    if net_fd.is_none() && host_dev_name.is_none() {
+/// * `queue_pairs` - The number of queue pairs, tap is opened with multiqueue if more than one.
+pub fn create_tap(
+    net_fds: Option<&[i32]>,
+    host_dev_name: Option<&str>,
+    queue_pairs: u16,
+) -> Result<Option<Vec<Tap>>> {
+    if net_fds.is_none() && host_dev_name.is_none() {
         return Ok(None);
     }
-    if net_fd.is_some() && host_dev_name.is_some() {
+    if net_fds.is_some() && host_dev_name.is_some() {
         error!("Create tap: fd and file_path exist meanwhile (use fd by default)");
     }
+    if let Some(fds) = net_fds {
+        if fds.len() != queue_pairs as usize {
+            bail!(
+                "The number of tap fds {} is not equal to queue pairs {}",
+                fds.len(),
+                queue_pairs
+            );
+        }
+    }
 
-    let tap = if let Some(fd) = net_fd {
-        Tap::new(None, Some(fd)).chain_err(|| "Failed to create tap")?
-    } else {
-        // `unwrap()` won't fail because the arguments have been checked
-        let dev_name = host_dev_name.unwrap();
-        Tap::new(Some(dev_name), None)
-            .chain_err(|| format!("Failed to create tap with name {}", dev_name))?
-    };
+    let multi_queue = queue_pairs > 1;
+    let mut taps = Vec::with_capacity(queue_pairs as usize);
+    for index in 0..queue_pairs as usize {
+        let tap = if let Some(fds) = net_fds {
+            Tap::new(None, Some(fds[index]), multi_queue).chain_err(|| "Failed to create tap")?
+        } else {
+            // `unwrap()` won't fail because the arguments have been checked
+            let dev_name = host_dev_name.unwrap();
+            Tap::new(Some(dev_name), None, multi_queue)
+                .chain_err(|| format!("Failed to create tap with name {}", dev_name))?
+        };
+
+        tap.set_offload(TUN_F_VIRTIO)
+            .chain_err(|| "Failed to set tap offload")?;
 
-    tap.set_offload(TUN_F_VIRTIO)
-        .chain_err(|| "Failed to set tap offload")?;
+        let vnet_hdr_size = mem::size_of::<VirtioNetHdr>() as u32;
+        tap.set_hdr_size(vnet_hdr_size)
+            .chain_err(|| "Failed to set tap hdr size")?;
 
-    let vnet_hdr_size = mem::size_of::<VirtioNetHdr>() as u32;
-    tap.set_hdr_size(vnet_hdr_size)
-        .chain_err(|| "Failed to set tap hdr size")?;
+        taps.push(tap);
+    }
 
-    Ok(Some(tap))
+    Ok(Some(taps))
+}
+
+/// Attach the tap queues in use and detach the others, so that the packets
+/// are only received by the queue pairs in use.
+///
+/// # Arguments
+///
+/// * `taps` - Queues of the multiqueue tap device.
+/// * `queue_pairs` - The number of queue pairs in use.
+/// * `old_queue_pairs` - The number of queue pairs attached before.
+fn set_tap_queues(taps: &[Tap], queue_pairs: u16, old_queue_pairs: u16) -> Result<()> {
+    if taps.len() > 1 {
+        for (index, tap) in taps.iter().enumerate() {
+            let enable = index < queue_pairs as usize;
+            if enable != (index < old_queue_pairs as usize) {
+                tap.set_queue(enable)
+                    .chain_err(|| format!("Failed to set queue {} of tap", index))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl Net {
+    /// Get the number of queue pairs and the index of control queue of the
+    /// queue layout with the features negotiated.
+    fn queue_layout(&self) -> (usize, Option<usize>) {
+        let driver_features = self.state.driver_features;
+        let queue_pairs = if virtio_has_feature(driver_features, VIRTIO_NET_F_MQ) {
+            self.config_space.lock().unwrap().max_virtqueue_pairs as usize
+        } else {
+            1
+        };
+        // The control queue follows the last queue pair.
+        let ctrl_index = if virtio_has_feature(driver_features, VIRTIO_NET_F_CTRL_VQ) {
+            Some(queue_pairs * 2)
+        } else {
+            None
+        };
+        (queue_pairs, ctrl_index)
+    }
+
+    /// Get the iothread which the handler of queue pair `index` runs in, the
+    /// queue pairs are assigned to the configured iothreads in turn.
+    fn queue_iothread(&self, index: usize) -> Option<&String> {
+        match self.net_cfg.queue_iothreads.as_ref() {
+            Some(iothreads) if !iothreads.is_empty() => Some(&iothreads[index % iothreads.len()]),
+            _ => self.net_cfg.iothread.as_ref(),
+        }
+    }
 }
 
 impl VirtioDevice for Net {
@@ -577,6 +1553,14 @@ impl VirtioDevice for Net {
                 self.net_cfg.iothread,
             );
         }
+        for iothread in self.net_cfg.queue_iothreads.iter().flatten() {
+            if EventLoop::get_ctx(Some(iothread)).is_none() {
+                bail!(
+                    "IOThread {:?} of Net is not configured in params.",
+                    iothread
+                );
+            }
+        }
 
         self.state.device_features = 1 << VIRTIO_F_VERSION_1
             | 1 << VIRTIO_NET_F_CSUM
@@ -585,36 +1569,60 @@ impl VirtioDevice for Net {
             | 1 << VIRTIO_NET_F_GUEST_UFO
             | 1 << VIRTIO_NET_F_HOST_TSO4
             | 1 << VIRTIO_NET_F_HOST_UFO
-            | 1 << VIRTIO_F_RING_EVENT_IDX;
+            | 1 << VIRTIO_F_RING_EVENT_IDX
+            | 1 << VIRTIO_NET_F_STATUS
+            | 1 << VIRTIO_NET_F_CTRL_VQ
+            | 1 << VIRTIO_NET_F_CTRL_RX
+            | 1 << VIRTIO_NET_F_CTRL_VLAN
+            | 1 << VIRTIO_NET_F_CTRL_RX_EXTRA
+            | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR;
+
+        let queue_pairs = self.net_cfg.queues;
+        let mut config_space = self.config_space.lock().unwrap();
+        if self.net_cfg.mq {
+            self.state.device_features |= 1 << VIRTIO_NET_F_MQ;
+            config_space.max_virtqueue_pairs = queue_pairs;
+        }
+        config_space.status = if self.link_up.load(Ordering::Acquire) {
+            VIRTIO_NET_S_LINK_UP
+        } else {
+            0
+        };
 
         if let Some(mac) = &self.net_cfg.mac {
-            self.state.device_features |=
-                build_device_config_space(&mut self.state.config_space, mac);
+            self.state.device_features |= build_device_config_space(&mut config_space, mac);
         }
+        drop(config_space);
 
         if !self.net_cfg.host_dev_name.is_empty() {
-            self.tap = None;
-            self.tap = create_tap(None, Some(&self.net_cfg.host_dev_name))
+            self.taps = None;
+            self.taps = create_tap(None, Some(&self.net_cfg.host_dev_name), queue_pairs)
                 .chain_err(|| "Failed to open tap with file path")?;
-        } else if let Some(fd) = self.net_cfg.tap_fd {
+        } else if let Some(fds) = self.net_cfg.tap_fds.as_ref() {
             let mut need_create = true;
-            if let Some(tap) = &self.tap {
-                if fd == tap.as_raw_fd() {
+            if let Some(taps) = &self.taps {
+                if taps
+                    .iter()
+                    .map(|tap| tap.as_raw_fd())
+                    .eq(fds.iter().cloned())
+                {
                     need_create = false;
                 }
             }
 
             if need_create {
-                self.tap = create_tap(Some(fd), None).chain_err(|| "Failed to open tap")?;
+                self.taps =
+                    create_tap(Some(fds), None, queue_pairs).chain_err(|| "Failed to open tap")?;
             }
         } else {
-            self.tap = None;
+            self.taps = None;
         }
 
+        let mut config_space = self.config_space.lock().unwrap();
         if let Some(mac) = &self.net_cfg.mac {
-            self.state.device_features |=
-                build_device_config_space(&mut self.state.config_space, mac);
+            self.state.device_features |= build_device_config_space(&mut config_space, mac);
         }
+        self.ctrl_info.lock().unwrap().mac = config_space.mac;
 
         Ok(())
     }
@@ -628,9 +1636,21 @@ impl VirtioDevice for Net {
         VIRTIO_TYPE_NET
     }
 
-    /// Get the count of virtio device queues.
+    /// Get the count of virtio device queues. All the queues are counted before the
+    /// features are negotiated, then only the queues used with the negotiated features.
     fn queue_num(&self) -> usize {
-        QUEUE_NUM_NET
+        if self.state.driver_features == 0 {
+            let queue_pairs = if self.net_cfg.mq {
+                self.net_cfg.queues as usize
+            } else {
+                1
+            };
+            // One more queue for control virtqueue.
+            return queue_pairs * 2 + 1;
+        }
+
+        let (queue_pairs, ctrl_index) = self.queue_layout();
+        queue_pairs * 2 + ctrl_index.map_or(0, |_| 1)
     }
 
     /// Get the queue size of virtio device.
@@ -656,7 +1676,8 @@ impl VirtioDevice for Net {
 
     /// Read data of config from guest.
     fn read_config(&self, offset: u64, mut data: &mut [u8]) -> Result<()> {
-        let config_slice = self.state.config_space.as_bytes();
+        let config_space = *self.config_space.lock().unwrap();
+        let config_slice = config_space.as_bytes();
         let config_len = config_slice.len() as u64;
         if offset >= config_len {
             return Err(ErrorKind::DevConfigOverflow(offset, config_len).into());
@@ -670,13 +1691,17 @@ impl VirtioDevice for Net {
     /// Write data to config from guest.
     fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
         let data_len = data.len();
-        let config_slice = self.state.config_space.as_mut_bytes();
+        let mut config_space = self.config_space.lock().unwrap();
+        let config_slice = config_space.as_mut_bytes();
         let config_len = config_slice.len();
         if offset as usize + data_len > config_len {
             return Err(ErrorKind::DevConfigOverflow(offset, config_len as u64).into());
         }
 
         config_slice[(offset as usize)..(offset as usize + data_len)].copy_from_slice(data);
+        if (offset as usize) < MAC_ADDR_LEN {
+            self.ctrl_info.lock().unwrap().mac = config_space.mac;
+        }
 
         Ok(())
     }
@@ -690,37 +1715,95 @@ impl VirtioDevice for Net {
         queues: &[Arc<Mutex<Queue>>],
         mut queue_evts: Vec<EventFd>,
     ) -> Result<()> {
-        let rx_queue = queues[0].clone();
-        let rx_queue_evt = queue_evts.remove(0);
-        let tx_queue = queues[1].clone();
-        let tx_queue_evt = queue_evts.remove(0);
-
-        let (sender, receiver) = channel();
-        self.sender = Some(sender);
-
-        let mut handler = NetIoHandler {
-            rx: RxVirtio::new(rx_queue, rx_queue_evt),
-            tx: TxVirtio::new(tx_queue, tx_queue_evt),
-            tap: self.tap.as_ref().map(|t| Tap {
-                file: t.file.try_clone().unwrap(),
-            }),
-            tap_fd: -1,
+        let driver_features = self.state.driver_features;
+        let (queue_pairs, ctrl_index) = self.queue_layout();
+        if queues.len() < queue_pairs * 2 + ctrl_index.map_or(0, |_| 1) {
+            bail!("Invalid number of queues {} for net", queues.len());
+        }
+
+        let mut senders = Vec::with_capacity(queue_pairs);
+        for index in 0..queue_pairs {
+            let rx_queue = queues[index * 2].clone();
+            let rx_queue_evt = queue_evts.remove(0);
+            let tx_queue = queues[index * 2 + 1].clone();
+            let tx_queue_evt = queue_evts.remove(0);
+
+            let (sender, receiver) = channel();
+            senders.push(sender);
+
+            let mut handler = NetIoHandler {
+                rx: RxVirtio::new(rx_queue, rx_queue_evt),
+                tx: TxVirtio::new(tx_queue, tx_queue_evt),
+                tap: self.taps.as_ref().map(|taps| Tap {
+                    file: taps[index].file.try_clone().unwrap(),
+                }),
+                tap_fd: -1,
+                mem_space: mem_space.clone(),
+                interrupt_cb: interrupt_cb.clone(),
+                driver_features,
+                ctrl_info: self.ctrl_info.clone(),
+                link_up: self.link_up.clone(),
+                rate_limit: self.rate_limit.clone(),
+                stats: self.stats.clone(),
+                notify_stats: self.notify_stats.clone(),
+                iothread: self.queue_iothread(index).cloned(),
+                receiver,
+                update_evt: self.update_evts[index].as_raw_fd(),
+                deactivate_evt: self.deactivate_evts[index].as_raw_fd(),
+                is_listening: true,
+            };
+            if let Some(tap) = &handler.tap {
+                handler.tap_fd = tap.as_raw_fd();
+            }
+
+            EventLoop::update_event(
+                EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler))),
+                self.queue_iothread(index),
+            )?;
+        }
+        self.senders = Some(senders);
+
+        let ctrl_index = match ctrl_index {
+            Some(index) => index,
+            None => {
+                if let Some(taps) = self.taps.as_ref() {
+                    set_tap_queues(taps, queue_pairs as u16, taps.len() as u16)?;
+                }
+                self.interrupt_cb = Some(interrupt_cb);
+                return Ok(());
+            }
+        };
+        let ctrl_queue = queues[ctrl_index].clone();
+        let ctrl_queue_evt = queue_evts.remove(0);
+        let taps = self.taps.as_ref().map_or(Vec::new(), |taps| {
+            taps.iter()
+                .map(|tap| Tap {
+                    file: tap.file.try_clone().unwrap(),
+                })
+                .collect()
+        });
+        let tap_queues = taps.len() as u16;
+        let mut ctrl_handler = NetCtrlHandler {
+            ctrl: CtrlVirtio::new(ctrl_queue, ctrl_queue_evt),
+            taps,
+            queue_pairs: tap_queues,
+            max_queue_pairs: queue_pairs as u16,
+            ctrl_info: self.ctrl_info.clone(),
+            config_space: self.config_space.clone(),
             mem_space,
-            interrupt_cb,
-            driver_features: self.state.driver_features,
-            receiver,
-            update_evt: self.update_evt.as_raw_fd(),
-            deactivate_evt: self.deactivate_evt.as_raw_fd(),
-            is_listening: true,
+            interrupt_cb: interrupt_cb.clone(),
+            driver_features,
+            deactivate_evt: self.deactivate_evts[self.deactivate_evts.len() - 1].as_raw_fd(),
+            notify_stats: self.notify_stats.clone(),
         };
-        if let Some(tap) = &handler.tap {
-            handler.tap_fd = tap.as_raw_fd();
-        }
+        // Only the first queue pair is used until the driver sets the number of queue pairs.
+        ctrl_handler.set_queue_pairs(1)?;
 
         EventLoop::update_event(
-            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler))),
+            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(ctrl_handler))),
             self.net_cfg.iothread.as_ref(),
         )?;
+        self.interrupt_cb = Some(interrupt_cb);
 
         Ok(())
     }
@@ -735,26 +1818,53 @@ impl VirtioDevice for Net {
         } else {
             self.net_cfg = Default::default();
         }
+        *self.rate_limit.lock().unwrap() = get_rate_limit(self.net_cfg.rate_limit);
 
         self.realize()?;
 
-        if let Some(sender) = &self.sender {
-            sender
-                .send(self.tap.take())
-                .chain_err(|| ErrorKind::ChannelSend("tap fd".to_string()))?;
+        if let Some(senders) = &self.senders {
+            let mut taps = self.taps.take().unwrap_or_default().into_iter();
+            for (index, sender) in senders.iter().enumerate() {
+                sender
+                    .send(taps.next())
+                    .chain_err(|| ErrorKind::ChannelSend("tap fd".to_string()))?;
+
+                self.update_evts[index]
+                    .write(1)
+                    .chain_err(|| ErrorKind::EventFdWrite)?;
+            }
+        }
+
+        Ok(())
+    }
 
-            self.update_evt
+    fn deactivate(&mut self) -> Result<()> {
+        // Only the handlers of the queues in use are activated.
+        let (queue_pairs, ctrl_index) = self.queue_layout();
+        for deactivate_evt in self.deactivate_evts.iter().take(queue_pairs) {
+            deactivate_evt
+                .write(1)
+                .chain_err(|| ErrorKind::EventFdWrite)?;
+        }
+        if ctrl_index.is_some() {
+            self.deactivate_evts[self.deactivate_evts.len() - 1]
                 .write(1)
                 .chain_err(|| ErrorKind::EventFdWrite)?;
+        } else if let Some(taps) = self.taps.as_ref() {
+            // Attach all the tap queues for the next activation.
+            set_tap_queues(taps, taps.len() as u16, queue_pairs as u16)?;
         }
+        self.interrupt_cb = None;
+        self.ctrl_info
+            .lock()
+            .unwrap()
+            .reset(self.config_space.lock().unwrap().mac);
 
         Ok(())
     }
 
-    fn deactivate(&mut self) -> Result<()> {
-        self.deactivate_evt
-            .write(1)
-            .chain_err(|| ErrorKind::EventFdWrite)
+    fn set_notify_stats(&mut self, stats: Arc<VirtioNotifyStats>) {
+        self.notify_stats = stats;
     }
 }
 
@@ -765,13 +1875,31 @@ unsafe impl Sync for Net {}
 
 impl StateTransfer for Net {
     fn get_state_vec(&self) -> migration::errors::Result<Vec<u8>> {
-        Ok(self.state.as_bytes().to_vec())
+        let mut state = self.state;
+        state.config_space = *self.config_space.lock().unwrap();
+        state.ctrl_info = *self.ctrl_info.lock().unwrap();
+        Ok(state.as_bytes().to_vec())
     }
 
     fn set_state_mut(&mut self, state: &[u8]) -> migration::errors::Result<()> {
         self.state = *VirtioNetState::from_bytes(state)
             .ok_or(migration::errors::ErrorKind::FromBytesError("NET"))?;
 
+        // The receive filter and link status are not saved by the device without
+        // control virtqueue, `ctrl_info` is zero-filled in the state of old version.
+        if !virtio_has_feature(self.state.device_features, VIRTIO_NET_F_CTRL_VQ) {
+            self.state.ctrl_info.reset(self.state.config_space.mac);
+        }
+        if !virtio_has_feature(self.state.device_features, VIRTIO_NET_F_STATUS) {
+            self.state.config_space.status = VIRTIO_NET_S_LINK_UP;
+        }
+        *self.config_space.lock().unwrap() = self.state.config_space;
+        *self.ctrl_info.lock().unwrap() = self.state.ctrl_info;
+        self.link_up.store(
+            self.state.config_space.status & VIRTIO_NET_S_LINK_UP != 0,
+            Ordering::Release,
+        );
+
         Ok(())
     }
 
@@ -791,6 +1919,8 @@ mod tests {
     pub use super::super::*;
     pub use super::*;
 
+    use address_space::Region;
+
     #[test]
     fn test_net_init() {
         // test net new method
@@ -798,17 +1928,18 @@ mod tests {
         assert_eq!(net.state.device_features, 0);
         assert_eq!(net.state.driver_features, 0);
 
-        assert_eq!(net.tap.is_none(), true);
-        assert_eq!(net.sender.is_none(), true);
+        assert!(net.taps.is_none());
+        assert!(net.senders.is_none());
         assert_eq!(net.net_cfg.mac.is_none(), true);
-        assert_eq!(net.net_cfg.tap_fd.is_none(), true);
+        assert!(net.net_cfg.tap_fds.is_none());
         assert_eq!(net.net_cfg.vhost_type.is_none(), true);
         assert_eq!(net.net_cfg.vhost_fd.is_none(), true);
 
         // test net realize method
         net.realize().unwrap();
         assert_eq!(net.device_type(), 1);
-        assert_eq!(net.queue_num(), 2);
+        // One queue pair and one control queue.
+        assert_eq!(net.queue_num(), 3);
         assert_eq!(net.queue_size(), 256);
 
         // test read_config and write_config method
@@ -824,7 +1955,8 @@ mod tests {
         net.write_config(0x00, &origin_data).unwrap();
 
         // test boundary condition of offset and data parameters
-        let device_config = net.state.config_space.as_bytes();
+        let device_config = *net.config_space.lock().unwrap();
+        let device_config = device_config.as_bytes();
         let len = device_config.len() as u64;
 
         let mut data: Vec<u8> = vec![0; 10];
@@ -849,4 +1981,301 @@ mod tests {
         let mut data: Vec<u8> = vec![0; len as usize];
         assert_eq!(net.write_config(offset, &mut data).is_ok(), true);
     }
+
+    #[test]
+    fn test_net_state_compat() {
+        let mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        let config_space = VirtioNetConfig {
+            mac,
+            max_virtqueue_pairs: 1,
+            ..Default::default()
+        };
+        let mut state = Vec::new();
+        state.extend_from_slice(&(1_u64 << VIRTIO_NET_F_MAC).to_le_bytes());
+        state.extend_from_slice(&(1_u64 << VIRTIO_NET_F_MAC).to_le_bytes());
+        state.extend_from_slice(config_space.as_bytes());
+        let state = pad_compat_state(
+            &VirtioNetState::descriptor(),
+            &[("ctrl_info", None)],
+            &state,
+        );
+
+        let mut net = Net::default();
+        net.set_state_mut(&state).unwrap();
+        assert_eq!(net.queue_layout(), (1, None));
+        let ctrl_info = *net.ctrl_info.lock().unwrap();
+        assert_eq!(ctrl_info.mac, mac);
+        assert!(ctrl_info.rx_mode_enabled(VIRTIO_NET_CTRL_RX_PROMISC));
+        assert_eq!(net.config_space.lock().unwrap().mac, mac);
+        assert!(net.link_up.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn test_net_queue_iothreads() {
+        let iothread = |name: &str| Some(name.to_string());
+
+        // All the queue pairs run in the same iothread by default.
+        let net = Net::new(NetworkInterfaceConfig {
+            iothread: iothread("iothread0"),
+            queues: 4,
+            mq: true,
+            ..Default::default()
+        });
+        for index in 0..4 {
+            assert_eq!(net.queue_iothread(index), iothread("iothread0").as_ref());
+        }
+
+        // The queue pairs are assigned to the iothreads in turn.
+        let net = Net::new(NetworkInterfaceConfig {
+            iothread: iothread("iothread0"),
+            queue_iothreads: Some(vec!["iothread0".to_string(), "iothread1".to_string()]),
+            queues: 4,
+            mq: true,
+            ..Default::default()
+        });
+        assert_eq!(net.queue_iothread(0), iothread("iothread0").as_ref());
+        assert_eq!(net.queue_iothread(1), iothread("iothread1").as_ref());
+        assert_eq!(net.queue_iothread(2), iothread("iothread0").as_ref());
+        assert_eq!(net.queue_iothread(3), iothread("iothread1").as_ref());
+    }
+
+    #[test]
+    fn test_net_multiqueue() {
+        let net_cfg = NetworkInterfaceConfig {
+            queues: 4,
+            mq: true,
+            ..Default::default()
+        };
+        let mut net = Net::new(net_cfg);
+        assert_eq!(net.update_evts.len(), 4);
+        assert_eq!(net.deactivate_evts.len(), 5);
+
+        net.realize().unwrap();
+        // Four queue pairs and one control queue.
+        assert_eq!(net.queue_num(), 9);
+        assert!(virtio_has_feature(
+            net.state.device_features,
+            VIRTIO_NET_F_CTRL_VQ
+        ));
+        assert!(virtio_has_feature(
+            net.state.device_features,
+            VIRTIO_NET_F_MQ
+        ));
+        let max_virtqueue_pairs = net.config_space.lock().unwrap().max_virtqueue_pairs;
+        assert_eq!(max_virtqueue_pairs, 4);
+
+        // The queues used depend on the features negotiated.
+        net.state.driver_features = 1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_NET_F_CTRL_VQ;
+        assert_eq!(net.queue_num(), 3);
+        assert_eq!(net.queue_layout(), (1, Some(2)));
+        net.state.driver_features |= 1 << VIRTIO_NET_F_MQ;
+        assert_eq!(net.queue_num(), 9);
+        assert_eq!(net.queue_layout(), (4, Some(8)));
+        // No control queue for the driver or snapshot without it.
+        net.state.driver_features = 1 << VIRTIO_F_VERSION_1;
+        assert_eq!(net.queue_num(), 2);
+        assert_eq!(net.queue_layout(), (1, None));
+
+        // Multiqueue is not offered by default.
+        let mut net = Net::default();
+        net.realize().unwrap();
+        assert_eq!(net.queue_num(), 3);
+        assert_eq!(net.deactivate_evts.len(), 2);
+        assert!(!virtio_has_feature(
+            net.state.device_features,
+            VIRTIO_NET_F_MQ
+        ));
+    }
+
+    fn build_frame(dst: [u8; MAC_ADDR_LEN], vid: Option<u16>) -> Vec<u8> {
+        let mut frame = dst.to_vec();
+        frame.extend_from_slice(&[0x52, 0x54, 0x00, 0x12, 0x34, 0x57]);
+        if let Some(vid) = vid {
+            frame.extend_from_slice(&ETH_P_8021Q.to_be_bytes());
+            frame.extend_from_slice(&vid.to_be_bytes());
+        }
+        frame.extend_from_slice(&0x0800_u16.to_be_bytes());
+        frame
+    }
+
+    fn build_mac_table(
+        uni_macs: &[[u8; MAC_ADDR_LEN]],
+        multi_macs: &[[u8; MAC_ADDR_LEN]],
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        for macs in [uni_macs, multi_macs].iter() {
+            data.extend_from_slice(&(macs.len() as u32).to_le_bytes());
+            for mac in macs.iter() {
+                data.extend_from_slice(mac);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_net_rx_filter() {
+        let mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+        let other_mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x58];
+        let multi_mac = [0x01, 0x00, 0x5e, 0x00, 0x00, 0x01];
+        let bcast_mac = [0xff; MAC_ADDR_LEN];
+        let features = 1 << VIRTIO_NET_F_CTRL_RX | 1 << VIRTIO_NET_F_CTRL_VLAN;
+        let mut ctrl_info = CtrlInfo::new(mac);
+
+        // All packets are received in promiscuous mode.
+        assert!(ctrl_info.filter_packet(features, &build_frame(other_mac, None)));
+        assert!(ctrl_info.filter_packet(features, &build_frame(other_mac, Some(100))));
+
+        // Only the packets to its own mac address and broadcast are received by default.
+        ctrl_info.set_rx_mode(VIRTIO_NET_CTRL_RX_PROMISC, false);
+        assert!(ctrl_info.filter_packet(features, &build_frame(mac, None)));
+        assert!(ctrl_info.filter_packet(features, &build_frame(bcast_mac, None)));
+        assert!(!ctrl_info.filter_packet(features, &build_frame(other_mac, None)));
+        assert!(!ctrl_info.filter_packet(features, &build_frame(multi_mac, None)));
+        // Filters are not applied if the features are not negotiated.
+        assert!(ctrl_info.filter_packet(0, &build_frame(other_mac, Some(100))));
+
+        // Mac filter table.
+        let data = build_mac_table(&[other_mac], &[multi_mac]);
+        ctrl_info.set_mac_table(&data).unwrap();
+        assert!(ctrl_info.filter_packet(features, &build_frame(other_mac, None)));
+        assert!(ctrl_info.filter_packet(features, &build_frame(multi_mac, None)));
+        assert!(ctrl_info.set_mac_table(&data[..data.len() - 1]).is_err());
+        let uni_macs = vec![other_mac; CTRL_MAC_TABLE_LEN + 1];
+        ctrl_info
+            .set_mac_table(&build_mac_table(&uni_macs, &[]))
+            .unwrap();
+        assert_eq!(ctrl_info.uni_overflow, 1);
+        assert_eq!(ctrl_info.mac_table_len, 0);
+        assert!(ctrl_info.filter_packet(features, &build_frame([0x02; 6], None)));
+        assert!(!ctrl_info.filter_packet(features, &build_frame(multi_mac, None)));
+
+        // Rx modes.
+        ctrl_info.set_rx_mode(VIRTIO_NET_CTRL_RX_ALLMULTI, true);
+        assert!(ctrl_info.filter_packet(features, &build_frame(multi_mac, None)));
+        ctrl_info.set_rx_mode(VIRTIO_NET_CTRL_RX_NOMULTI, true);
+        assert!(!ctrl_info.filter_packet(features, &build_frame(multi_mac, None)));
+        ctrl_info.set_rx_mode(VIRTIO_NET_CTRL_RX_NOBCAST, true);
+        assert!(!ctrl_info.filter_packet(features, &build_frame(bcast_mac, None)));
+        ctrl_info.set_rx_mode(VIRTIO_NET_CTRL_RX_NOUNI, true);
+        assert!(!ctrl_info.filter_packet(features, &build_frame(mac, None)));
+
+        // Vlan filter.
+        ctrl_info.reset(mac);
+        ctrl_info.set_rx_mode(VIRTIO_NET_CTRL_RX_PROMISC, false);
+        assert!(!ctrl_info.filter_packet(features, &build_frame(mac, Some(100))));
+        ctrl_info.set_vlan(100, true);
+        assert!(ctrl_info.filter_packet(features, &build_frame(mac, Some(100))));
+        assert!(!ctrl_info.filter_packet(features, &build_frame(mac, Some(101))));
+        ctrl_info.set_vlan(100, false);
+        assert!(!ctrl_info.filter_packet(features, &build_frame(mac, Some(100))));
+    }
+
+    #[test]
+    fn test_net_link_status() {
+        let net_cfg = NetworkInterfaceConfig {
+            id: "net-0".to_string(),
+            mac: Some("52:54:00:12:34:56".to_string()),
+            ..Default::default()
+        };
+        let net = Arc::new(Mutex::new(Net::new(net_cfg)));
+        register_net_device(&net);
+
+        let mut locked_net = net.lock().unwrap();
+        locked_net.realize().unwrap();
+        for feature in [
+            VIRTIO_NET_F_STATUS,
+            VIRTIO_NET_F_CTRL_VQ,
+            VIRTIO_NET_F_CTRL_RX,
+            VIRTIO_NET_F_CTRL_VLAN,
+            VIRTIO_NET_F_CTRL_MAC_ADDR,
+        ]
+        .iter()
+        {
+            assert!(virtio_has_feature(
+                locked_net.state.device_features,
+                *feature
+            ));
+        }
+        let status = locked_net.config_space.lock().unwrap().status;
+        assert_eq!(status, VIRTIO_NET_S_LINK_UP);
+
+        // Mac address changed by the driver is visible in config space.
+        let new_mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x57];
+        let queue = Queue::new(QueueConfig::new(QUEUE_SIZE_NET), QUEUE_TYPE_SPLIT_VRING).unwrap();
+        let mut ctrl_handler = NetCtrlHandler {
+            ctrl: CtrlVirtio::new(
+                Arc::new(Mutex::new(queue)),
+                EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            ),
+            taps: Vec::new(),
+            queue_pairs: 1,
+            max_queue_pairs: 1,
+            ctrl_info: locked_net.ctrl_info.clone(),
+            config_space: locked_net.config_space.clone(),
+            mem_space: AddressSpace::new(Region::init_container_region(1 << 36)).unwrap(),
+            interrupt_cb: Arc::new(
+                Box::new(|_: &VirtioInterruptType, _: Option<&Queue>| Ok(())) as VirtioInterrupt,
+            ),
+            driver_features: 0,
+            deactivate_evt: -1,
+            notify_stats: Arc::new(VirtioNotifyStats::default()),
+        };
+        ctrl_handler
+            .handle_mac(VIRTIO_NET_CTRL_MAC_ADDR_SET, &new_mac)
+            .unwrap();
+        let mut mac = [0_u8; MAC_ADDR_LEN];
+        locked_net.read_config(0, &mut mac).unwrap();
+        assert_eq!(mac, new_mac);
+        assert_eq!(locked_net.ctrl_info.lock().unwrap().mac, new_mac);
+        let state = locked_net.get_state_vec().unwrap();
+        let state = VirtioNetState::from_bytes(&state).unwrap();
+        assert_eq!(state.config_space.mac, new_mac);
+        drop(locked_net);
+
+        qmp_set_link("net-0", false).unwrap();
+        let status = net.lock().unwrap().config_space.lock().unwrap().status;
+        assert_eq!(status, 0);
+        assert!(!net.lock().unwrap().link_up.load(Ordering::Acquire));
+        qmp_set_link("net-0", true).unwrap();
+        let status = net.lock().unwrap().config_space.lock().unwrap().status;
+        assert_eq!(status, VIRTIO_NET_S_LINK_UP);
+        assert!(qmp_set_link("net-1", true).is_err());
+    }
+
+    #[test]
+    fn test_net_stats() {
+        let net_cfg = NetworkInterfaceConfig {
+            id: "net-stats".to_string(),
+            ..Default::default()
+        };
+        let net = Arc::new(Mutex::new(Net::new(net_cfg)));
+        register_net_device(&net);
+
+        let hdr_len = size_of::<VirtioNetHdr>() as u64;
+        let stats = net.lock().unwrap().stats.clone();
+        NetIoStats::account_packet(&stats.rx_packets, &stats.rx_bytes, hdr_len + 60);
+        NetIoStats::account_packet(&stats.rx_packets, &stats.rx_bytes, hdr_len + 1514);
+        NetIoStats::account_packet(&stats.tx_packets, &stats.tx_bytes, hdr_len + 98);
+        NetIoStats::inc(&stats.rx_dropped);
+        NetIoStats::inc(&stats.rx_queue_full);
+
+        let infos = qmp_query_netdev_stats(Some("net-stats"), false).unwrap();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].id, "net-stats");
+        assert_eq!(infos[0].stats.rx_packets, 2);
+        assert_eq!(infos[0].stats.rx_bytes, 1574);
+        assert_eq!(infos[0].stats.rx_dropped, 1);
+        assert_eq!(infos[0].stats.rx_queue_full, 1);
+        assert_eq!(infos[0].stats.tx_packets, 1);
+        assert_eq!(infos[0].stats.tx_bytes, 98);
+        assert_eq!(infos[0].stats.tx_dropped, 0);
+
+        // The statistics are returned before they are reset.
+        let infos = qmp_query_netdev_stats(Some("net-stats"), true).unwrap();
+        assert_eq!(infos[0].stats.rx_packets, 2);
+        let infos = qmp_query_netdev_stats(Some("net-stats"), false).unwrap();
+        assert_eq!(infos[0].stats.rx_packets, 0);
+        assert_eq!(infos[0].stats.tx_bytes, 0);
+        assert!(qmp_query_netdev_stats(Some("net-stats-1"), false).is_err());
+    }
 }
@@ -0,0 +1,72 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use once_cell::sync::Lazy;
+
+/// Notify stats of all the virtio transports, keyed by the names of transports.
+static NOTIFY_STATS: Lazy<Mutex<HashMap<String, Weak<VirtioNotifyStats>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Counters of the notifications between guest and a virtio device. Interrupts
+/// are counted by the transport, while queue notifies are counted by the device
+/// which handles the queue events in StratoVirt.
+#[derive(Default)]
+pub struct VirtioNotifyStats {
+    /// Count of queue notifies from guest.
+    queue_notifies: AtomicU64,
+    /// Count of interrupts to guest.
+    interrupts: AtomicU64,
+}
+
+impl VirtioNotifyStats {
+    /// Account the value read from a queue eventfd, which is the number of
+    /// notifies since the last read.
+    pub fn account_queue_notifies(&self, count: u64) {
+        self.queue_notifies.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn account_interrupt(&self) {
+        self.interrupts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn queue_notifies(&self) -> u64 {
+        self.queue_notifies.load(Ordering::Relaxed)
+    }
+
+    pub fn interrupts(&self) -> u64 {
+        self.interrupts.load(Ordering::Relaxed)
+    }
+}
+
+/// Register the notify stats of a virtio transport named `name`.
+pub(crate) fn register_notify_stats(name: String, stats: &Arc<VirtioNotifyStats>) {
+    NOTIFY_STATS
+        .lock()
+        .unwrap()
+        .insert(name, Arc::downgrade(stats));
+}
+
+/// Get the notify stats of all the virtio transports alive, sorted by name.
+pub fn query_notify_stats() -> Vec<(String, Arc<VirtioNotifyStats>)> {
+    let mut all_stats = NOTIFY_STATS.lock().unwrap();
+    all_stats.retain(|_, stats| stats.strong_count() > 0);
+    let mut ret: Vec<(String, Arc<VirtioNotifyStats>)> = all_stats
+        .iter()
+        .filter_map(|(name, stats)| stats.upgrade().map(|stats| (name.clone(), stats)))
+        .collect();
+    ret.sort_by(|a, b| a.0.cmp(&b.0));
+    ret
+}
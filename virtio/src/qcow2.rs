@@ -0,0 +1,929 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use byteorder::{BigEndian, ByteOrder};
+use util::aio::Iovec;
+
+use super::errors::{Result, ResultExt};
+
+/// Magic number of qcow2 image: "QFI\xfb".
+const QCOW_MAGIC: u32 = 0x5146_49fb;
+/// Length of the header of qcow2 image with version 2.
+const QCOW_V2_HEADER_LEN: u32 = 72;
+/// Minimal length of the header of qcow2 image with version 3.
+const QCOW_V3_HEADER_LEN: u32 = 104;
+const MIN_CLUSTER_BITS: u32 = 9;
+const MAX_CLUSTER_BITS: u32 = 21;
+/// Only 16 bits refcount is supported.
+const REFCOUNT_ORDER: u32 = 4;
+/// Bit of incompatible features: the refcounts may be inconsistent.
+const INCOMPAT_DIRTY: u64 = 1 << 0;
+/// Header extension type of backing file format name.
+const EXT_BACKING_FORMAT: u32 = 0xe279_2aca;
+/// Offset mask of L1 and L2 table entries.
+const ENTRY_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+/// Flag of L1 and L2 table entries: the refcount of the cluster is exactly one.
+const QCOW_OFLAG_COPIED: u64 = 1 << 63;
+/// Flag of L2 table entries: the cluster is compressed.
+const QCOW_OFLAG_COMPRESSED: u64 = 1 << 62;
+/// Flag of L2 table entries: the cluster reads as all zeros.
+const QCOW_OFLAG_ZERO: u64 = 1 << 0;
+/// Max count of cached L2 tables and refcount blocks.
+const MAX_CACHED_TABLES: usize = 32;
+
+/// Header of qcow2 image, all fields are stored in big-endian.
+#[derive(Default, Debug, Clone)]
+struct QcowHeader {
+    magic: u32,
+    version: u32,
+    backing_file_offset: u64,
+    backing_file_size: u32,
+    cluster_bits: u32,
+    size: u64,
+    crypt_method: u32,
+    l1_size: u32,
+    l1_table_offset: u64,
+    refcount_table_offset: u64,
+    refcount_table_clusters: u32,
+    nb_snapshots: u32,
+    incompatible_features: u64,
+    refcount_order: u32,
+    header_length: u32,
+}
+
+impl QcowHeader {
+    fn from_buf(buf: &[u8]) -> Result<Self> {
+        if buf.len() < QCOW_V2_HEADER_LEN as usize {
+            bail!("Invalid qcow2 header length {}", buf.len());
+        }
+        let mut header = QcowHeader {
+            magic: BigEndian::read_u32(&buf[0..4]),
+            version: BigEndian::read_u32(&buf[4..8]),
+            backing_file_offset: BigEndian::read_u64(&buf[8..16]),
+            backing_file_size: BigEndian::read_u32(&buf[16..20]),
+            cluster_bits: BigEndian::read_u32(&buf[20..24]),
+            size: BigEndian::read_u64(&buf[24..32]),
+            crypt_method: BigEndian::read_u32(&buf[32..36]),
+            l1_size: BigEndian::read_u32(&buf[36..40]),
+            l1_table_offset: BigEndian::read_u64(&buf[40..48]),
+            refcount_table_offset: BigEndian::read_u64(&buf[48..56]),
+            refcount_table_clusters: BigEndian::read_u32(&buf[56..60]),
+            nb_snapshots: BigEndian::read_u32(&buf[60..64]),
+            refcount_order: REFCOUNT_ORDER,
+            header_length: QCOW_V2_HEADER_LEN,
+            ..Default::default()
+        };
+        if header.version >= 3 {
+            if buf.len() < QCOW_V3_HEADER_LEN as usize {
+                bail!("Invalid qcow2 v3 header length {}", buf.len());
+            }
+            header.incompatible_features = BigEndian::read_u64(&buf[72..80]);
+            header.refcount_order = BigEndian::read_u32(&buf[96..100]);
+            header.header_length = BigEndian::read_u32(&buf[100..104]);
+        }
+
+        Ok(header)
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.magic != QCOW_MAGIC {
+            bail!("Invalid qcow2 magic 0x{:x}", self.magic);
+        }
+        if self.version != 2 && self.version != 3 {
+            bail!("Unsupported qcow2 version {}", self.version);
+        }
+        if self.cluster_bits < MIN_CLUSTER_BITS || self.cluster_bits > MAX_CLUSTER_BITS {
+            bail!("Invalid qcow2 cluster bits {}", self.cluster_bits);
+        }
+        if self.crypt_method != 0 {
+            bail!("Encrypted qcow2 image is not supported");
+        }
+        if self.incompatible_features & INCOMPAT_DIRTY != 0 {
+            bail!("Qcow2 image is dirty, please repair it with 'qemu-img check -r all'");
+        }
+        if self.incompatible_features & !INCOMPAT_DIRTY != 0 {
+            bail!(
+                "Unsupported qcow2 incompatible features 0x{:x}",
+                self.incompatible_features
+            );
+        }
+        if self.refcount_order != REFCOUNT_ORDER {
+            bail!("Unsupported qcow2 refcount order {}", self.refcount_order);
+        }
+        if self.header_length < QCOW_V2_HEADER_LEN {
+            bail!("Invalid qcow2 header length {}", self.header_length);
+        }
+
+        Ok(())
+    }
+}
+
+/// How a guest cluster is stored in the image.
+#[derive(Debug, PartialEq)]
+enum ClusterType {
+    /// Not allocated, reads from backing file or zeros.
+    Unallocated,
+    /// Reads as zeros, may have a preallocated host cluster.
+    Zero(u64),
+    /// Stored at host offset, the flag shows whether it can be written in place.
+    Normal(u64, bool),
+    /// Compressed cluster.
+    Compressed,
+}
+
+/// The backing image of a qcow2 image, only read from.
+enum BackingImage {
+    Raw(File, u64),
+    Qcow2(Box<Qcow2Driver>),
+}
+
+impl BackingImage {
+    fn open(path: &Path, format: Option<&str>) -> Result<Self> {
+        let file = File::open(path)
+            .chain_err(|| format!("Failed to open backing file {}", path.display()))?;
+        let format = match format {
+            Some(format) => format.to_string(),
+            None => {
+                let mut magic = [0_u8; 4];
+                match file.read_exact_at(&mut magic, 0) {
+                    Ok(()) if BigEndian::read_u32(&magic) == QCOW_MAGIC => "qcow2".to_string(),
+                    _ => "raw".to_string(),
+                }
+            }
+        };
+
+        match format.as_str() {
+            "raw" => {
+                let size = file
+                    .metadata()
+                    .chain_err(|| "Failed to get size of backing file")?
+                    .len();
+                Ok(BackingImage::Raw(file, size))
+            }
+            "qcow2" => Ok(BackingImage::Qcow2(Box::new(Qcow2Driver::open(
+                path, true,
+            )?))),
+            _ => bail!("Unsupported backing file format {}", format),
+        }
+    }
+
+    /// Read data from backing image, the data beyond its size reads as zeros.
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let size = match self {
+            BackingImage::Raw(_, size) => *size,
+            BackingImage::Qcow2(driver) => driver.virtual_size(),
+        };
+        buf.iter_mut().for_each(|b| *b = 0);
+        if offset >= size {
+            return Ok(());
+        }
+
+        let len = cmp::min(size - offset, buf.len() as u64) as usize;
+        match self {
+            BackingImage::Raw(file, _) => file
+                .read_exact_at(&mut buf[..len], offset)
+                .chain_err(|| "Failed to read backing file"),
+            BackingImage::Qcow2(driver) => driver.read(offset, &mut buf[..len]),
+        }
+    }
+}
+
+/// Driver of qcow2 image.
+///
+/// # Notes
+///
+/// Metadata of the image is accessed synchronously through its own file descriptor,
+/// and the guest data of allocated clusters is accessed by the caller with the host
+/// offsets returned from `map_read` and `map_write`. Clusters which are not allocated
+/// are handled synchronously, including reading from backing file, zeroing and
+/// allocating new clusters.
+pub struct Qcow2Driver {
+    /// Image file for metadata and synchronous data access.
+    file: File,
+    header: QcowHeader,
+    cluster_size: u64,
+    /// Count of entries in one L2 table.
+    l2_entries: u64,
+    /// Count of entries in one refcount block.
+    refcount_block_entries: u64,
+    l1_table: Vec<u64>,
+    refcount_table: Vec<u64>,
+    /// Cached L2 tables, indexed by their host offsets.
+    l2_cache: HashMap<u64, Vec<u64>>,
+    /// Cached refcount blocks, indexed by their host offsets.
+    refcount_cache: HashMap<u64, Vec<u16>>,
+    /// Host offset where the next new cluster is allocated.
+    free_cluster_offset: u64,
+    backing: Option<BackingImage>,
+    read_only: bool,
+}
+
+impl Qcow2Driver {
+    /// Open qcow2 image.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the image file.
+    /// * `read_only` - Whether the image is opened read-only.
+    pub fn open(path: &Path, read_only: bool) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .open(path)
+            .chain_err(|| format!("Failed to open qcow2 image {}", path.display()))?;
+
+        let mut buf = vec![0_u8; QCOW_V3_HEADER_LEN as usize];
+        file.read_exact_at(&mut buf[..QCOW_V2_HEADER_LEN as usize], 0)
+            .chain_err(|| "Failed to read qcow2 header")?;
+        if BigEndian::read_u32(&buf[4..8]) >= 3 {
+            file.read_exact_at(&mut buf[QCOW_V2_HEADER_LEN as usize..], 72)
+                .chain_err(|| "Failed to read qcow2 v3 header")?;
+        }
+        let header = QcowHeader::from_buf(&buf)?;
+        header.check()?;
+        if header.nb_snapshots != 0 && !read_only {
+            bail!("Qcow2 image with internal snapshots can only be opened read-only");
+        }
+
+        let cluster_size = 1_u64 << header.cluster_bits;
+        let l2_entries = cluster_size / 8;
+        let refcount_block_entries = cluster_size * 8 / (1 << header.refcount_order);
+        if header.size > (header.l1_size as u64) * l2_entries * cluster_size {
+            bail!("Qcow2 L1 table is too small for image size {}", header.size);
+        }
+
+        let l1_table = read_table(&file, header.l1_table_offset, header.l1_size as u64)
+            .chain_err(|| "Failed to read qcow2 L1 table")?;
+        let refcount_table = read_table(
+            &file,
+            header.refcount_table_offset,
+            header.refcount_table_clusters as u64 * cluster_size / 8,
+        )
+        .chain_err(|| "Failed to read qcow2 refcount table")?;
+
+        let file_size = file
+            .metadata()
+            .chain_err(|| "Failed to get size of qcow2 image")?
+            .len();
+        let free_cluster_offset = (file_size + cluster_size - 1) & !(cluster_size - 1);
+
+        let mut driver = Qcow2Driver {
+            file,
+            header,
+            cluster_size,
+            l2_entries,
+            refcount_block_entries,
+            l1_table,
+            refcount_table,
+            l2_cache: HashMap::new(),
+            refcount_cache: HashMap::new(),
+            free_cluster_offset,
+            backing: None,
+            read_only,
+        };
+        driver.open_backing(path)?;
+
+        Ok(driver)
+    }
+
+    /// Virtual size of the image in bytes.
+    pub fn virtual_size(&self) -> u64 {
+        self.header.size
+    }
+
+    /// Map guest data of read request to host offsets of the image.
+    /// The data which is not allocated in image is filled synchronously.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - Guest offset of the request.
+    /// * `iovec` - Buffers of the request.
+    ///
+    /// Returns the host offsets and buffers which need to be read from the image.
+    pub fn map_read(&mut self, offset: u64, iovec: &[Iovec]) -> Result<Vec<(u64, Vec<Iovec>)>> {
+        let mut mapped = Vec::new();
+        let total = iovec_len(iovec);
+        let mut done = 0_u64;
+        while done < total {
+            let guest_offset = offset + done;
+            let in_cluster = guest_offset & (self.cluster_size - 1);
+            let len = cmp::min(self.cluster_size - in_cluster, total - done);
+            let iov = iovec_slice(iovec, done, len);
+
+            match self.get_cluster(guest_offset)? {
+                ClusterType::Normal(host, _) => push_mapped(&mut mapped, host + in_cluster, iov),
+                ClusterType::Zero(_) => iovec_fill_zero(&iov),
+                ClusterType::Unallocated => {
+                    let mut buf = vec![0_u8; len as usize];
+                    if let Some(backing) = self.backing.as_mut() {
+                        backing.read(guest_offset, &mut buf)?;
+                    }
+                    iovec_write_from(&iov, &buf);
+                }
+                ClusterType::Compressed => bail!("Compressed qcow2 cluster is not supported"),
+            }
+            done += len;
+        }
+
+        Ok(mapped)
+    }
+
+    /// Map guest data of write request to host offsets of the image.
+    /// The data which needs allocating new clusters is written synchronously.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - Guest offset of the request.
+    /// * `iovec` - Buffers of the request.
+    ///
+    /// Returns the host offsets and buffers which need to be written to the image.
+    pub fn map_write(&mut self, offset: u64, iovec: &[Iovec]) -> Result<Vec<(u64, Vec<Iovec>)>> {
+        if self.read_only {
+            bail!("Failed to write read-only qcow2 image");
+        }
+
+        let mut mapped = Vec::new();
+        let total = iovec_len(iovec);
+        let mut done = 0_u64;
+        while done < total {
+            let guest_offset = offset + done;
+            let in_cluster = guest_offset & (self.cluster_size - 1);
+            let len = cmp::min(self.cluster_size - in_cluster, total - done);
+            let iov = iovec_slice(iovec, done, len);
+
+            match self.get_cluster(guest_offset)? {
+                ClusterType::Normal(host, true) => push_mapped(&mut mapped, host + in_cluster, iov),
+                cluster => {
+                    let mut buf = self.read_cluster_base(guest_offset, &cluster)?;
+                    iovec_read_into(
+                        &iov,
+                        &mut buf[in_cluster as usize..(in_cluster + len) as usize],
+                    );
+                    let host = match cluster {
+                        ClusterType::Zero(host) if host != 0 => host,
+                        _ => self.alloc_cluster()?,
+                    };
+                    self.file
+                        .write_all_at(&buf, host)
+                        .chain_err(|| "Failed to write qcow2 data cluster")?;
+                    self.set_l2_entry(guest_offset, host | QCOW_OFLAG_COPIED)?;
+                    // The shared cluster is replaced by the new one, drop its reference
+                    // after the L2 entry is updated so that a crash leaks it at most.
+                    if let ClusterType::Normal(old_host, false) = cluster {
+                        self.update_refcount(old_host, false)?;
+                    }
+                }
+            }
+            done += len;
+        }
+
+        Ok(mapped)
+    }
+
+    /// Read guest data from image synchronously.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - Guest offset of the data.
+    /// * `buf` - Buffer to store the data.
+    pub fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let iovec = [Iovec {
+            iov_base: buf.as_mut_ptr() as u64,
+            iov_len: buf.len() as u64,
+        }];
+        for (host, iov) in self.map_read(offset, &iovec)? {
+            let mut data = vec![0_u8; iovec_len(&iov) as usize];
+            self.file
+                .read_exact_at(&mut data, host)
+                .chain_err(|| "Failed to read qcow2 data cluster")?;
+            iovec_write_from(&iov, &data);
+        }
+
+        Ok(())
+    }
+
+    fn open_backing(&mut self, image_path: &Path) -> Result<()> {
+        if self.header.backing_file_offset == 0 || self.header.backing_file_size == 0 {
+            return Ok(());
+        }
+
+        let mut name = vec![0_u8; self.header.backing_file_size as usize];
+        self.file
+            .read_exact_at(&mut name, self.header.backing_file_offset)
+            .chain_err(|| "Failed to read qcow2 backing file name")?;
+        let name = String::from_utf8(name).chain_err(|| "Invalid qcow2 backing file name")?;
+        let mut path = PathBuf::from(&name);
+        if path.is_relative() {
+            if let Some(dir) = image_path.parent() {
+                path = dir.join(path);
+            }
+        }
+
+        let format = self.read_backing_format()?;
+        self.backing = Some(BackingImage::open(&path, format.as_deref())?);
+
+        Ok(())
+    }
+
+    /// Read backing file format from header extensions.
+    fn read_backing_format(&self) -> Result<Option<String>> {
+        let mut offset = self.header.header_length as u64;
+        loop {
+            let mut ext = [0_u8; 8];
+            self.file
+                .read_exact_at(&mut ext, offset)
+                .chain_err(|| "Failed to read qcow2 header extension")?;
+            let ext_type = BigEndian::read_u32(&ext[0..4]);
+            let ext_len = BigEndian::read_u32(&ext[4..8]) as u64;
+            if ext_type == 0 {
+                return Ok(None);
+            }
+            if offset + 8 + ext_len > self.cluster_size {
+                bail!("Invalid qcow2 header extension length {}", ext_len);
+            }
+            if ext_type == EXT_BACKING_FORMAT {
+                let mut format = vec![0_u8; ext_len as usize];
+                self.file
+                    .read_exact_at(&mut format, offset + 8)
+                    .chain_err(|| "Failed to read qcow2 backing format")?;
+                return Ok(Some(
+                    String::from_utf8(format).chain_err(|| "Invalid qcow2 backing format")?,
+                ));
+            }
+            offset += 8 + ((ext_len + 7) & !7);
+        }
+    }
+
+    fn get_cluster(&mut self, guest_offset: u64) -> Result<ClusterType> {
+        let l1_index = (guest_offset / self.cluster_size / self.l2_entries) as usize;
+        let l1_entry = *self
+            .l1_table
+            .get(l1_index)
+            .chain_err(|| format!("Guest offset 0x{:x} is out of qcow2 L1 table", guest_offset))?;
+        let l2_offset = l1_entry & ENTRY_OFFSET_MASK;
+        if l2_offset == 0 {
+            return Ok(ClusterType::Unallocated);
+        }
+
+        let l2_index = ((guest_offset / self.cluster_size) % self.l2_entries) as usize;
+        let l2_entry = self.load_l2_table(l2_offset)?[l2_index];
+        if l2_entry & QCOW_OFLAG_COMPRESSED != 0 {
+            return Ok(ClusterType::Compressed);
+        }
+        let host = l2_entry & ENTRY_OFFSET_MASK;
+        if self.header.version >= 3 && l2_entry & QCOW_OFLAG_ZERO != 0 {
+            if l2_entry & QCOW_OFLAG_COPIED != 0 {
+                return Ok(ClusterType::Zero(host));
+            }
+            return Ok(ClusterType::Zero(0));
+        }
+        if host == 0 {
+            return Ok(ClusterType::Unallocated);
+        }
+
+        Ok(ClusterType::Normal(
+            host,
+            l2_entry & QCOW_OFLAG_COPIED != 0 && l1_entry & QCOW_OFLAG_COPIED != 0,
+        ))
+    }
+
+    /// Get the data of the cluster before it is written partially.
+    fn read_cluster_base(&mut self, guest_offset: u64, cluster: &ClusterType) -> Result<Vec<u8>> {
+        let cluster_start = guest_offset & !(self.cluster_size - 1);
+        let mut buf = vec![0_u8; self.cluster_size as usize];
+        match cluster {
+            ClusterType::Unallocated => {
+                if let Some(backing) = self.backing.as_mut() {
+                    backing.read(cluster_start, &mut buf)?;
+                }
+            }
+            ClusterType::Normal(host, _) => {
+                self.file
+                    .read_exact_at(&mut buf, *host)
+                    .chain_err(|| "Failed to read qcow2 data cluster")?;
+            }
+            ClusterType::Zero(_) => {}
+            ClusterType::Compressed => bail!("Compressed qcow2 cluster is not supported"),
+        }
+
+        Ok(buf)
+    }
+
+    fn load_l2_table(&mut self, l2_offset: u64) -> Result<&mut Vec<u64>> {
+        if !self.l2_cache.contains_key(&l2_offset) {
+            let table = read_table(&self.file, l2_offset, self.l2_entries)
+                .chain_err(|| format!("Failed to read qcow2 L2 table at 0x{:x}", l2_offset))?;
+            if self.l2_cache.len() >= MAX_CACHED_TABLES {
+                self.l2_cache.clear();
+            }
+            self.l2_cache.insert(l2_offset, table);
+        }
+
+        Ok(self.l2_cache.get_mut(&l2_offset).unwrap())
+    }
+
+    fn set_l2_entry(&mut self, guest_offset: u64, entry: u64) -> Result<()> {
+        let l1_index = (guest_offset / self.cluster_size / self.l2_entries) as usize;
+        let mut l2_offset = self.l1_table[l1_index] & ENTRY_OFFSET_MASK;
+        if l2_offset == 0 {
+            l2_offset = self.alloc_cluster()?;
+            self.file
+                .write_all_at(&vec![0_u8; self.cluster_size as usize], l2_offset)
+                .chain_err(|| "Failed to write qcow2 L2 table")?;
+            self.l2_cache
+                .insert(l2_offset, vec![0_u64; self.l2_entries as usize]);
+            self.write_entry_u64(
+                self.header.l1_table_offset + l1_index as u64 * 8,
+                l2_offset | QCOW_OFLAG_COPIED,
+            )?;
+            self.l1_table[l1_index] = l2_offset | QCOW_OFLAG_COPIED;
+        }
+
+        let l2_index = (guest_offset / self.cluster_size) % self.l2_entries;
+        self.write_entry_u64(l2_offset + l2_index * 8, entry)?;
+        self.load_l2_table(l2_offset)?[l2_index as usize] = entry;
+
+        Ok(())
+    }
+
+    /// Allocate a new cluster at the end of image, returns its host offset.
+    fn alloc_cluster(&mut self) -> Result<u64> {
+        let offset = self.free_cluster_offset;
+        self.free_cluster_offset += self.cluster_size;
+        self.update_refcount(offset, true)?;
+
+        Ok(offset)
+    }
+
+    /// Increase or decrease the refcount of cluster at `offset` by one. The
+    /// cluster whose refcount drops to 0 is free, it is not reused as clusters
+    /// are always allocated at the end of image.
+    fn update_refcount(&mut self, offset: u64, increase: bool) -> Result<()> {
+        let cluster_index = offset / self.cluster_size;
+        let table_index = (cluster_index / self.refcount_block_entries) as usize;
+        if table_index >= self.refcount_table.len() {
+            bail!("Qcow2 refcount table is full");
+        }
+
+        let mut block_offset = self.refcount_table[table_index] & ENTRY_OFFSET_MASK;
+        if block_offset == 0 {
+            if !increase {
+                bail!("Refcount of qcow2 cluster 0x{:x} is already 0", offset);
+            }
+            block_offset = self.free_cluster_offset;
+            self.free_cluster_offset += self.cluster_size;
+            self.file
+                .write_all_at(&vec![0_u8; self.cluster_size as usize], block_offset)
+                .chain_err(|| "Failed to write qcow2 refcount block")?;
+            self.refcount_cache.insert(
+                block_offset,
+                vec![0_u16; self.refcount_block_entries as usize],
+            );
+            self.write_entry_u64(
+                self.header.refcount_table_offset + table_index as u64 * 8,
+                block_offset,
+            )?;
+            self.refcount_table[table_index] = block_offset;
+            // The new refcount block is a cluster in use too.
+            self.update_refcount(block_offset, true)?;
+        }
+
+        let block_index = (cluster_index % self.refcount_block_entries) as usize;
+        let refcount = self.load_refcount_block(block_offset)?[block_index];
+        let refcount = if increase {
+            refcount
+                .checked_add(1)
+                .chain_err(|| format!("Refcount of qcow2 cluster 0x{:x} overflows", offset))?
+        } else {
+            refcount
+                .checked_sub(1)
+                .chain_err(|| format!("Refcount of qcow2 cluster 0x{:x} is already 0", offset))?
+        };
+        let mut buf = [0_u8; 2];
+        BigEndian::write_u16(&mut buf, refcount);
+        self.file
+            .write_all_at(&buf, block_offset + block_index as u64 * 2)
+            .chain_err(|| "Failed to write qcow2 refcount")?;
+        self.load_refcount_block(block_offset)?[block_index] = refcount;
+
+        Ok(())
+    }
+
+    fn load_refcount_block(&mut self, block_offset: u64) -> Result<&mut Vec<u16>> {
+        if !self.refcount_cache.contains_key(&block_offset) {
+            let mut buf = vec![0_u8; self.cluster_size as usize];
+            self.file
+                .read_exact_at(&mut buf, block_offset)
+                .chain_err(|| {
+                    format!("Failed to read qcow2 refcount block 0x{:x}", block_offset)
+                })?;
+            let block = buf.chunks(2).map(BigEndian::read_u16).collect();
+            if self.refcount_cache.len() >= MAX_CACHED_TABLES {
+                self.refcount_cache.clear();
+            }
+            self.refcount_cache.insert(block_offset, block);
+        }
+
+        Ok(self.refcount_cache.get_mut(&block_offset).unwrap())
+    }
+
+    fn write_entry_u64(&self, offset: u64, entry: u64) -> Result<()> {
+        let mut buf = [0_u8; 8];
+        BigEndian::write_u64(&mut buf, entry);
+        self.file
+            .write_all_at(&buf, offset)
+            .chain_err(|| format!("Failed to write qcow2 table entry at 0x{:x}", offset))?;
+
+        Ok(())
+    }
+}
+
+/// Read a table of big-endian `u64` entries from image.
+fn read_table(file: &File, offset: u64, entries: u64) -> Result<Vec<u64>> {
+    let mut buf = vec![0_u8; entries as usize * 8];
+    file.read_exact_at(&mut buf, offset)?;
+
+    Ok(buf.chunks(8).map(BigEndian::read_u64).collect())
+}
+
+/// Add mapped buffers, merge them into the last one if host offsets are contiguous.
+fn push_mapped(mapped: &mut Vec<(u64, Vec<Iovec>)>, host: u64, mut iov: Vec<Iovec>) {
+    if let Some((last_host, last_iov)) = mapped.last_mut() {
+        if *last_host + iovec_len(last_iov) == host {
+            last_iov.append(&mut iov);
+            return;
+        }
+    }
+    mapped.push((host, iov));
+}
+
+fn iovec_len(iovec: &[Iovec]) -> u64 {
+    iovec.iter().map(|iov| iov.iov_len).sum()
+}
+
+/// Get the buffers of `len` bytes starting from `offset` in `iovec`.
+fn iovec_slice(iovec: &[Iovec], mut offset: u64, mut len: u64) -> Vec<Iovec> {
+    let mut slice = Vec::new();
+    for iov in iovec.iter() {
+        if len == 0 {
+            break;
+        }
+        if offset >= iov.iov_len {
+            offset -= iov.iov_len;
+            continue;
+        }
+        let size = cmp::min(iov.iov_len - offset, len);
+        slice.push(Iovec {
+            iov_base: iov.iov_base + offset,
+            iov_len: size,
+        });
+        offset = 0;
+        len -= size;
+    }
+    slice
+}
+
+fn iovec_fill_zero(iovec: &[Iovec]) {
+    for iov in iovec {
+        // Safe because the buffers are valid host memory of the request.
+        unsafe { std::ptr::write_bytes(iov.iov_base as *mut u8, 0, iov.iov_len as usize) };
+    }
+}
+
+/// Copy data in `buf` to the buffers of `iovec`.
+fn iovec_write_from(iovec: &[Iovec], buf: &[u8]) {
+    let mut pos = 0;
+    for iov in iovec {
+        let len = cmp::min(iov.iov_len as usize, buf.len() - pos);
+        // Safe because the buffers are valid host memory of the request.
+        unsafe { std::ptr::copy_nonoverlapping(buf[pos..].as_ptr(), iov.iov_base as *mut u8, len) };
+        pos += len;
+    }
+}
+
+/// Copy data in the buffers of `iovec` to `buf`.
+fn iovec_read_into(iovec: &[Iovec], buf: &mut [u8]) {
+    let mut pos = 0;
+    for iov in iovec {
+        let len = cmp::min(iov.iov_len as usize, buf.len() - pos);
+        // Safe because the buffers are valid host memory of the request.
+        unsafe {
+            std::ptr::copy_nonoverlapping(iov.iov_base as *const u8, buf[pos..].as_mut_ptr(), len)
+        };
+        pos += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLUSTER_BITS: u32 = 16;
+    const CLUSTER_SIZE: u64 = 1 << CLUSTER_BITS;
+    const IMAGE_SIZE: u64 = 16 << 20;
+
+    /// Create an empty qcow2 v3 image with layout: header, refcount table,
+    /// refcount block and L1 table in the first four clusters.
+    fn create_image(path: &Path, backing: Option<&str>) {
+        let mut header = vec![0_u8; CLUSTER_SIZE as usize];
+        BigEndian::write_u32(&mut header[0..4], QCOW_MAGIC);
+        BigEndian::write_u32(&mut header[4..8], 3);
+        BigEndian::write_u32(&mut header[20..24], CLUSTER_BITS);
+        BigEndian::write_u64(&mut header[24..32], IMAGE_SIZE);
+        BigEndian::write_u32(&mut header[36..40], 1);
+        BigEndian::write_u64(&mut header[40..48], 3 * CLUSTER_SIZE);
+        BigEndian::write_u64(&mut header[48..56], CLUSTER_SIZE);
+        BigEndian::write_u32(&mut header[56..60], 1);
+        BigEndian::write_u32(&mut header[96..100], REFCOUNT_ORDER);
+        BigEndian::write_u32(&mut header[100..104], QCOW_V3_HEADER_LEN);
+        if let Some(name) = backing {
+            let offset = QCOW_V3_HEADER_LEN as usize + 8;
+            BigEndian::write_u64(&mut header[8..16], offset as u64);
+            BigEndian::write_u32(&mut header[16..20], name.len() as u32);
+            header[offset..offset + name.len()].copy_from_slice(name.as_bytes());
+        }
+
+        let mut image = header;
+        let mut refcount_table = vec![0_u8; CLUSTER_SIZE as usize];
+        BigEndian::write_u64(&mut refcount_table[0..8], 2 * CLUSTER_SIZE);
+        image.append(&mut refcount_table);
+        let mut refcount_block = vec![0_u8; CLUSTER_SIZE as usize];
+        for i in 0..4 {
+            BigEndian::write_u16(&mut refcount_block[i * 2..i * 2 + 2], 1);
+        }
+        image.append(&mut refcount_block);
+        image.append(&mut vec![0_u8; CLUSTER_SIZE as usize]);
+        std::fs::write(path, image).unwrap();
+    }
+
+    fn write_image(driver: &mut Qcow2Driver, offset: u64, data: &[u8]) {
+        let iovec = [Iovec {
+            iov_base: data.as_ptr() as u64,
+            iov_len: data.len() as u64,
+        }];
+        for (host, iov) in driver.map_write(offset, &iovec).unwrap() {
+            let mut buf = vec![0_u8; iovec_len(&iov) as usize];
+            iovec_read_into(&iov, &mut buf);
+            driver.file.write_all_at(&buf, host).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_qcow2_read_write() {
+        let path = PathBuf::from(format!(
+            "/tmp/test_qcow2_read_write_{}.qcow2",
+            std::process::id()
+        ));
+        create_image(&path, None);
+
+        let mut driver = Qcow2Driver::open(&path, false).unwrap();
+        assert_eq!(driver.virtual_size(), IMAGE_SIZE);
+
+        // Unallocated clusters read as zeros.
+        let mut buf = vec![0xff_u8; 4096];
+        driver.read(0, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+
+        // Write data across the boundary of clusters.
+        let data: Vec<u8> = (0..8192).map(|i| (i % 251) as u8).collect();
+        let offset = CLUSTER_SIZE - 4096;
+        write_image(&mut driver, offset, &data);
+        let mut buf = vec![0_u8; 8192];
+        driver.read(offset, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        // Overwrite the allocated cluster in place.
+        write_image(&mut driver, offset, &data[..512]);
+        assert_eq!(driver.free_cluster_offset, 7 * CLUSTER_SIZE);
+
+        // Data and metadata persist after reopening.
+        drop(driver);
+        let mut driver = Qcow2Driver::open(&path, true).unwrap();
+        let mut buf = vec![0_u8; 8192];
+        driver.read(offset, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        let mut buf = vec![0xff_u8; 512];
+        driver.read(offset - 512, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+        for i in 0..7 {
+            assert_eq!(driver.load_refcount_block(2 * CLUSTER_SIZE).unwrap()[i], 1);
+        }
+        assert!(driver.map_write(0, &[]).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_qcow2_backing_file() {
+        let backing_name = format!("test_qcow2_backing_{}.raw", std::process::id());
+        let backing_path = PathBuf::from("/tmp").join(&backing_name);
+        let backing_data = vec![0x5a_u8; 2 * CLUSTER_SIZE as usize];
+        std::fs::write(&backing_path, &backing_data).unwrap();
+        let path = PathBuf::from(format!(
+            "/tmp/test_qcow2_backing_{}.qcow2",
+            std::process::id()
+        ));
+        create_image(&path, Some(&backing_name));
+
+        let mut driver = Qcow2Driver::open(&path, false).unwrap();
+        let mut buf = vec![0_u8; 1024];
+        driver.read(CLUSTER_SIZE, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0x5a));
+        // Reads beyond the backing file get zeros.
+        driver.read(2 * CLUSTER_SIZE, &mut buf).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+
+        // Partial write copies the rest of cluster from backing file.
+        write_image(&mut driver, 512, &[0xa5_u8; 512]);
+        let mut buf = vec![0_u8; 1536];
+        driver.read(0, &mut buf).unwrap();
+        assert!(buf[..512].iter().all(|b| *b == 0x5a));
+        assert!(buf[512..1024].iter().all(|b| *b == 0xa5));
+        assert!(buf[1024..].iter().all(|b| *b == 0x5a));
+        let backing = std::fs::read(&backing_path).unwrap();
+        assert_eq!(backing, backing_data);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&backing_path).unwrap();
+    }
+
+    #[test]
+    fn test_qcow2_shared_cluster_write() {
+        let path = PathBuf::from(format!(
+            "/tmp/test_qcow2_shared_cluster_{}.qcow2",
+            std::process::id()
+        ));
+        create_image(&path, None);
+
+        let mut driver = Qcow2Driver::open(&path, false).unwrap();
+        write_image(&mut driver, 0, &[0x5a_u8; 512]);
+        let old_host = match driver.get_cluster(0).unwrap() {
+            ClusterType::Normal(host, true) => host,
+            _ => panic!("Cluster is not allocated"),
+        };
+        // The cluster is shared, such as by an internal snapshot.
+        driver.update_refcount(old_host, true).unwrap();
+        driver.set_l2_entry(0, old_host).unwrap();
+
+        // Writing the shared cluster allocates a new one and drops the reference.
+        write_image(&mut driver, 512, &[0xa5_u8; 512]);
+        let new_host = match driver.get_cluster(0).unwrap() {
+            ClusterType::Normal(host, true) => host,
+            _ => panic!("Cluster is not copied"),
+        };
+        assert_ne!(new_host, old_host);
+        let old_index = (old_host / CLUSTER_SIZE) as usize;
+        let new_index = (new_host / CLUSTER_SIZE) as usize;
+        let block = driver.load_refcount_block(2 * CLUSTER_SIZE).unwrap();
+        assert_eq!(block[old_index], 1);
+        assert_eq!(block[new_index], 1);
+        let mut buf = vec![0_u8; 1024];
+        driver.read(0, &mut buf).unwrap();
+        assert!(buf[..512].iter().all(|b| *b == 0x5a));
+        assert!(buf[512..].iter().all(|b| *b == 0xa5));
+
+        // The cluster referenced only by the replaced entry is freed.
+        driver.set_l2_entry(0, new_host).unwrap();
+        write_image(&mut driver, 0, &[0x3c_u8; 512]);
+        let block = driver.load_refcount_block(2 * CLUSTER_SIZE).unwrap();
+        assert_eq!(block[new_index], 0);
+        assert!(driver.update_refcount(new_host, false).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_iovec_slice() {
+        let iovec = vec![
+            Iovec {
+                iov_base: 0x1000,
+                iov_len: 0x200,
+            },
+            Iovec {
+                iov_base: 0x4000,
+                iov_len: 0x400,
+            },
+        ];
+        let slice = iovec_slice(&iovec, 0x100, 0x200);
+        assert_eq!(slice.len(), 2);
+        assert_eq!((slice[0].iov_base, slice[0].iov_len), (0x1100, 0x100));
+        assert_eq!((slice[1].iov_base, slice[1].iov_len), (0x4000, 0x100));
+        let slice = iovec_slice(&iovec, 0x300, 0x300);
+        assert_eq!(slice.len(), 1);
+        assert_eq!((slice[0].iov_base, slice[0].iov_len), (0x4100, 0x300));
+    }
+}
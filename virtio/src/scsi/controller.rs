@@ -0,0 +1,1157 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex, Weak};
+
+use address_space::{AddressSpace, GuestAddress};
+use error_chain::ChainedError;
+use machine_manager::{
+    config::{ScsiCntlrConfig, ScsiDevConfig, MAX_SCSI_LUN, MAX_SCSI_TARGET},
+    event_loop::EventLoop,
+};
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use once_cell::sync::Lazy;
+use util::aio::{Aio, AioCb, AioCompleteFunc, AioEngine, IoCmd, Iovec};
+use util::byte_code::ByteCode;
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+use util::num_ops::{read_u32, write_u32};
+use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
+
+use super::super::errors::{ErrorKind, Result, ResultExt};
+use super::super::{
+    ElemIovec, Element, Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1,
+    VIRTIO_SCSI_F_HOTPLUG, VIRTIO_TYPE_SCSI,
+};
+use super::disk::{
+    inquiry_no_lun, report_luns, ScsiCmdResult, ScsiDevice, ScsiSense, INQUIRY, REPORT_LUNS,
+    SENSE_IO_ERROR, SENSE_LUN_NOT_SUPPORTED, UNMAP,
+};
+
+/// Number of the control queue and the event queue.
+const QUEUE_NUM_SCSI_CTRL_EVENT: usize = 2;
+/// Size of each virtqueue.
+const QUEUE_SIZE_SCSI: u16 = 256;
+/// Size of cdb in the request.
+const VIRTIO_SCSI_CDB_SIZE: usize = 32;
+/// Size of sense data in the response.
+const VIRTIO_SCSI_SENSE_SIZE: usize = 96;
+/// The maximum number of segments in one request.
+const SCSI_SEG_MAX: u32 = QUEUE_SIZE_SCSI as u32 - 2;
+/// The maximum number of sectors in one request.
+const SCSI_MAX_SECTORS: u32 = 0xffff;
+/// The maximum number of linked commands per lun.
+const SCSI_CMD_PER_LUN: u32 = 128;
+/// Used to check the alignment of io buffers.
+const SECTOR_SIZE: u64 = 512;
+
+/// Request types of the control queue.
+const VIRTIO_SCSI_T_TMF: u32 = 0;
+const VIRTIO_SCSI_T_AN_QUERY: u32 = 1;
+const VIRTIO_SCSI_T_AN_SUBSCRIBE: u32 = 2;
+
+/// Response codes.
+const VIRTIO_SCSI_S_OK: u8 = 0;
+const VIRTIO_SCSI_S_OVERRUN: u8 = 1;
+const VIRTIO_SCSI_S_BAD_TARGET: u8 = 3;
+const VIRTIO_SCSI_S_INCORRECT_LUN: u8 = 12;
+
+/// Events reported through the event queue.
+const VIRTIO_SCSI_T_TRANSPORT_RESET: u32 = 1;
+const VIRTIO_SCSI_T_EVENTS_MISSED: u32 = 0x8000_0000;
+const VIRTIO_SCSI_EVT_RESET_RESCAN: u32 = 1;
+const VIRTIO_SCSI_EVT_RESET_REMOVED: u32 = 2;
+
+/// SCSI status.
+const SCSI_STATUS_GOOD: u8 = 0x00;
+const SCSI_STATUS_CHECK_CONDITION: u8 = 0x02;
+
+/// The virtio-scsi controllers, used to attach scsi devices by the controller id.
+static SCSI_CNTLRS: Lazy<Mutex<Vec<Weak<Mutex<ScsiCntlr>>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// The scsi devices attached to the controller, indexed by target and lun.
+type ScsiBus = BTreeMap<(u8, u16), Arc<ScsiDevice>>;
+
+/// Header of the command request.
+#[derive(Clone, Copy)]
+struct VirtioScsiCmdReq {
+    lun: [u8; 8],
+    cdb: [u8; VIRTIO_SCSI_CDB_SIZE],
+}
+
+impl VirtioScsiCmdReq {
+    /// Length of the header: lun, tag, task_attr, prio, crn and cdb.
+    const LEN: usize = 8 + 8 + 3 + VIRTIO_SCSI_CDB_SIZE;
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let mut req = VirtioScsiCmdReq {
+            lun: [0; 8],
+            cdb: [0; VIRTIO_SCSI_CDB_SIZE],
+        };
+        req.lun.copy_from_slice(&bytes[..8]);
+        req.cdb.copy_from_slice(&bytes[19..Self::LEN]);
+        req
+    }
+}
+
+/// Response of the command request.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct VirtioScsiCmdResp {
+    sense_len: u32,
+    resid: u32,
+    status_qualifier: u16,
+    status: u8,
+    response: u8,
+    sense: [u8; VIRTIO_SCSI_SENSE_SIZE],
+}
+
+impl Default for VirtioScsiCmdResp {
+    fn default() -> Self {
+        VirtioScsiCmdResp {
+            sense_len: 0,
+            resid: 0,
+            status_qualifier: 0,
+            status: SCSI_STATUS_GOOD,
+            response: VIRTIO_SCSI_S_OK,
+            sense: [0; VIRTIO_SCSI_SENSE_SIZE],
+        }
+    }
+}
+
+impl ByteCode for VirtioScsiCmdResp {}
+
+impl VirtioScsiCmdResp {
+    fn with_response(response: u8) -> Self {
+        VirtioScsiCmdResp {
+            response,
+            ..Default::default()
+        }
+    }
+
+    fn with_sense(sense: ScsiSense) -> Self {
+        let mut resp = VirtioScsiCmdResp {
+            status: SCSI_STATUS_CHECK_CONDITION,
+            ..Default::default()
+        };
+        let sense = sense.to_bytes();
+        resp.sense_len = sense.len() as u32;
+        resp.sense[..sense.len()].copy_from_slice(&sense);
+        resp
+    }
+}
+
+/// Event reported through the event queue.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct VirtioScsiEvent {
+    event: u32,
+    lun: [u8; 8],
+    reason: u32,
+}
+
+impl ByteCode for VirtioScsiEvent {}
+
+/// Decode the target and lun from the lun field of virtio-scsi request.
+fn decode_lun(lun: &[u8; 8]) -> Option<(u8, u16)> {
+    if lun[0] != 1 {
+        return None;
+    }
+    Some((
+        lun[1],
+        (u16::from(lun[2]) << 8 | u16::from(lun[3])) & 0x3fff,
+    ))
+}
+
+/// Encode the target and lun into the lun field of virtio-scsi event.
+fn encode_lun(target: u8, lun: u16) -> [u8; 8] {
+    [1, target, 0x40 | (lun >> 8) as u8, lun as u8, 0, 0, 0, 0]
+}
+
+/// Read the data of the iovecs from guest memory.
+fn read_iovec(mem_space: &AddressSpace, iovec: &[ElemIovec]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for iov in iovec {
+        mem_space
+            .read(&mut data, iov.addr, u64::from(iov.len))
+            .chain_err(|| "Failed to read scsi request data")?;
+    }
+    Ok(data)
+}
+
+/// Write the data to the iovecs in guest memory, return the length written.
+fn write_iovec(mem_space: &AddressSpace, iovec: &[ElemIovec], data: &[u8]) -> Result<usize> {
+    let mut written = 0;
+    for iov in iovec {
+        if written == data.len() {
+            break;
+        }
+        let len = cmp::min(iov.len as usize, data.len() - written);
+        mem_space
+            .write(&mut &data[written..written + len], iov.addr, len as u64)
+            .chain_err(|| "Failed to write scsi response data")?;
+        written += len;
+    }
+    Ok(written)
+}
+
+fn iovec_len(iovec: &[ElemIovec]) -> u64 {
+    iovec.iter().map(|iov| u64::from(iov.len)).sum()
+}
+
+/// Map the guest buffers of `nbytes` to host iovecs.
+fn map_iovec(mem_space: &AddressSpace, iovec: &[ElemIovec], nbytes: u64) -> Result<Vec<Iovec>> {
+    let mut host_iovec = Vec::with_capacity(iovec.len());
+    let mut left = nbytes;
+    for iov in iovec {
+        if left == 0 {
+            break;
+        }
+        let len = cmp::min(u64::from(iov.len), left);
+        let hva = mem_space
+            .get_host_address(iov.addr)
+            .chain_err(|| ErrorKind::AddressOverflow("scsi buffer", iov.addr.0, len))?;
+        host_iovec.push(Iovec {
+            iov_base: hva,
+            iov_len: len,
+        });
+        left -= len;
+    }
+    Ok(host_iovec)
+}
+
+/// Context to complete the command request.
+#[derive(Clone)]
+pub struct ScsiCompleteCb {
+    queue: Arc<Mutex<Queue>>,
+    mem_space: Arc<AddressSpace>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    desc_index: u16,
+    resp_addr: GuestAddress,
+    /// Length of data transferred to the guest.
+    data_in_len: u32,
+    /// Residual length of the buffers for data transfer.
+    resid: u32,
+}
+
+impl ScsiCompleteCb {
+    fn complete(&self, resp: &mut VirtioScsiCmdResp) -> Result<()> {
+        let mut used_len = size_of::<VirtioScsiCmdResp>() as u32;
+        if resp.response == VIRTIO_SCSI_S_OK && resp.status == SCSI_STATUS_GOOD {
+            resp.resid = self.resid;
+            used_len += self.data_in_len;
+        }
+        self.mem_space
+            .write_object(resp, self.resp_addr)
+            .chain_err(|| "Failed to write the response of scsi request")?;
+
+        let mut queue_lock = self.queue.lock().unwrap();
+        queue_lock
+            .vring
+            .add_used(&self.mem_space, self.desc_index, used_len)
+            .chain_err(|| {
+                format!(
+                    "Failed to add used ring for scsi request, index {}",
+                    self.desc_index
+                )
+            })?;
+
+        if queue_lock
+            .vring
+            .should_notify(&self.mem_space, self.driver_features)
+        {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock))
+                .chain_err(|| ErrorKind::InterruptTrigger("scsi", VirtioInterruptType::Vring))?;
+        }
+        Ok(())
+    }
+}
+
+/// Handler of the request queue.
+struct ScsiCmdHandler {
+    queue: Arc<Mutex<Queue>>,
+    queue_evt: EventFd,
+    mem_space: Arc<AddressSpace>,
+    /// The scsi devices shared with the controller.
+    bus: Arc<Mutex<ScsiBus>>,
+    /// Aio contexts, one for each aio engine.
+    aios: Vec<Aio<ScsiCompleteCb>>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    deactivate_evt: RawFd,
+}
+
+impl ScsiCmdHandler {
+    fn build_aio(engine: AioEngine) -> Result<Aio<ScsiCompleteCb>> {
+        let complete_func = Arc::new(Box::new(move |aiocb: &AioCb<ScsiCompleteCb>, ret: i64| {
+            if aiocb.opcode == IoCmd::Preadv {
+                // Data is read into guest memory through host address.
+                for iov in aiocb.iovec.iter() {
+                    aiocb
+                        .iocompletecb
+                        .mem_space
+                        .mark_dirty_host(iov.iov_base, iov.iov_len);
+                }
+            }
+            let mut resp = if ret < 0 {
+                VirtioScsiCmdResp::with_sense(SENSE_IO_ERROR)
+            } else {
+                VirtioScsiCmdResp::default()
+            };
+            if let Err(ref e) = aiocb.iocompletecb.complete(&mut resp) {
+                error!(
+                    "Failed to complete scsi request(aio completion), {}",
+                    e.display_chain()
+                );
+            }
+        }) as AioCompleteFunc<ScsiCompleteCb>);
+
+        Ok(Aio::new(complete_func, engine)?)
+    }
+
+    fn process_queue(&mut self) -> Result<()> {
+        let mut elems = Vec::new();
+        let mut queue = self.queue.lock().unwrap();
+        while let Ok(elem) = queue.vring.pop_avail(&self.mem_space, self.driver_features) {
+            elems.push(elem);
+        }
+        drop(queue);
+
+        for elem in elems {
+            if let Err(ref e) = self.handle_request(&elem) {
+                error!("Failed to handle scsi request, {}", e.display_chain());
+                // Free the descriptor table entry if the request is invalid.
+                let mut queue = self.queue.lock().unwrap();
+                queue
+                    .vring
+                    .add_used(&self.mem_space, elem.index, 0)
+                    .chain_err(|| "Failed to add used ring")?;
+                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue)).chain_err(|| {
+                    ErrorKind::InterruptTrigger("scsi", VirtioInterruptType::Vring)
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_request(&mut self, elem: &Element) -> Result<()> {
+        let req_iov = match elem.out_iovec.first() {
+            Some(iov) if iov.len as usize >= VirtioScsiCmdReq::LEN => iov,
+            _ => bail!("Invalid header of scsi request"),
+        };
+        let resp_iov = match elem.in_iovec.first() {
+            Some(iov) if iov.len as usize >= size_of::<VirtioScsiCmdResp>() => iov,
+            _ => bail!("Invalid response of scsi request"),
+        };
+        let mut header = Vec::new();
+        self.mem_space
+            .read(&mut header, req_iov.addr, VirtioScsiCmdReq::LEN as u64)
+            .chain_err(|| ErrorKind::ReadObjectErr("the scsi request header", req_iov.addr.0))?;
+        let req = VirtioScsiCmdReq::from_bytes(&header);
+        let data_out = &elem.out_iovec[1..];
+        let data_in = &elem.in_iovec[1..];
+
+        let mut complete_cb = ScsiCompleteCb {
+            queue: self.queue.clone(),
+            mem_space: self.mem_space.clone(),
+            interrupt_cb: self.interrupt_cb.clone(),
+            driver_features: self.driver_features,
+            desc_index: elem.index,
+            resp_addr: resp_iov.addr,
+            data_in_len: 0,
+            resid: 0,
+        };
+
+        let (target, lun) = match decode_lun(&req.lun) {
+            Some(addr) => addr,
+            None => {
+                return complete_cb.complete(&mut VirtioScsiCmdResp::with_response(
+                    VIRTIO_SCSI_S_BAD_TARGET,
+                ))
+            }
+        };
+        let cdb = &req.cdb;
+        let (device, result) = {
+            let bus = self.bus.lock().unwrap();
+            let luns: Vec<u16> = bus
+                .range((target, 0)..=(target, u16::MAX))
+                .map(|(addr, _)| addr.1)
+                .collect();
+            if luns.is_empty() {
+                drop(bus);
+                return complete_cb.complete(&mut VirtioScsiCmdResp::with_response(
+                    VIRTIO_SCSI_S_BAD_TARGET,
+                ));
+            }
+            match bus.get(&(target, lun)) {
+                _ if cdb[0] == REPORT_LUNS => (None, report_luns(&luns, cdb)),
+                Some(device) => {
+                    let data = if cdb[0] == UNMAP {
+                        read_iovec(&self.mem_space, data_out)?
+                    } else {
+                        Vec::new()
+                    };
+                    (Some(device.clone()), device.execute(cdb, &data))
+                }
+                None if cdb[0] == INQUIRY => (None, inquiry_no_lun(cdb)),
+                None => (None, ScsiCmdResult::Failed(SENSE_LUN_NOT_SUPPORTED)),
+            }
+        };
+
+        match result {
+            ScsiCmdResult::Done(data) => {
+                let written = write_iovec(&self.mem_space, data_in, &data)?;
+                complete_cb.data_in_len = written as u32;
+                complete_cb.resid = (iovec_len(data_in) - written as u64) as u32;
+                complete_cb.complete(&mut VirtioScsiCmdResp::default())
+            }
+            ScsiCmdResult::Failed(sense) => {
+                complete_cb.complete(&mut VirtioScsiCmdResp::with_sense(sense))
+            }
+            ScsiCmdResult::Io {
+                opcode,
+                offset,
+                nbytes,
+            } => {
+                // Safe to unwrap, as io is only required by the device.
+                let device = device.unwrap();
+                let data_iovec = match opcode {
+                    IoCmd::Preadv => data_in,
+                    IoCmd::Pwritev => data_out,
+                    _ => &[],
+                };
+                let buf_len = iovec_len(data_iovec);
+                if buf_len < nbytes {
+                    return complete_cb
+                        .complete(&mut VirtioScsiCmdResp::with_response(VIRTIO_SCSI_S_OVERRUN));
+                }
+                if opcode == IoCmd::Preadv {
+                    complete_cb.data_in_len = nbytes as u32;
+                }
+                complete_cb.resid = (buf_len - nbytes) as u32;
+
+                let disk = match device.disk_image() {
+                    Some(disk) => disk,
+                    None => bail!("Image of scsi device {} is not opened", device.config.id),
+                };
+                let aiocb = AioCb {
+                    last_aio: true,
+                    file_fd: disk.as_raw_fd(),
+                    opcode,
+                    iovec: map_iovec(&self.mem_space, data_iovec, nbytes)?,
+                    offset: offset as usize,
+                    nbytes: 0,
+                    process: true,
+                    iocb: None,
+                    iocompletecb: complete_cb,
+                };
+                self.get_aio(device.config.aio)
+                    .rw_aio(aiocb, SECTOR_SIZE)
+                    .chain_err(|| "Failed to submit scsi io request")
+            }
+        }
+    }
+
+    /// Get the aio context of the engine, the requests are processed synchronously
+    /// if the engine is not available.
+    fn get_aio(&mut self, engine: AioEngine) -> &mut Aio<ScsiCompleteCb> {
+        let index = self
+            .aios
+            .iter()
+            .position(|aio| aio.engine() == engine)
+            .unwrap_or(0);
+        &mut self.aios[index]
+    }
+
+    fn deactivate_evt_handler(&mut self) -> Vec<EventNotifier> {
+        let mut notifiers = vec![
+            EventNotifier::new(
+                NotifierOperation::Delete,
+                self.deactivate_evt,
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ),
+            EventNotifier::new(
+                NotifierOperation::Delete,
+                self.queue_evt.as_raw_fd(),
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ),
+        ];
+        for aio in self
+            .aios
+            .iter()
+            .filter(|aio| aio.engine() != AioEngine::Off)
+        {
+            notifiers.push(EventNotifier::new(
+                NotifierOperation::Delete,
+                aio.fd.as_raw_fd(),
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ));
+        }
+
+        notifiers
+    }
+}
+
+#[allow(clippy::arc_with_non_send_sync)]
+fn build_event_notifier(fd: RawFd, handler: Box<NotifierCallback>) -> EventNotifier {
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        fd,
+        None,
+        EventSet::IN,
+        vec![Arc::new(Mutex::new(handler))],
+    )
+}
+
+impl EventNotifierHelper for ScsiCmdHandler {
+    fn internal_notifiers(handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let handler_raw = handler.lock().unwrap();
+        let mut notifiers = Vec::new();
+
+        // Register event notifier for deactivate_evt.
+        let h_clone = handler.clone();
+        let h: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            Some(h_clone.lock().unwrap().deactivate_evt_handler())
+        });
+        notifiers.push(build_event_notifier(handler_raw.deactivate_evt, h));
+
+        // Register event notifier for queue_evt.
+        let h_clone = handler.clone();
+        let h: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            if let Err(ref e) = h_clone.lock().unwrap().process_queue() {
+                error!("Failed to handle scsi request queue, {}", e.display_chain());
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(handler_raw.queue_evt.as_raw_fd(), h));
+
+        // Register event notifier for aio.
+        for (index, aio) in handler_raw.aios.iter().enumerate() {
+            if aio.engine() == AioEngine::Off {
+                continue;
+            }
+            let h_clone = handler.clone();
+            let h: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+                read_fd(fd);
+                if let Err(ref e) = h_clone.lock().unwrap().aios[index].handle() {
+                    error!("Failed to handle aio, {}", e.display_chain());
+                }
+                None
+            });
+            notifiers.push(build_event_notifier(aio.fd.as_raw_fd(), h));
+        }
+
+        notifiers
+    }
+}
+
+/// Handler of the control queue and the event queue.
+struct ScsiCtrlHandler {
+    ctrl_queue: Arc<Mutex<Queue>>,
+    ctrl_queue_evt: EventFd,
+    event_queue: Arc<Mutex<Queue>>,
+    event_queue_evt: EventFd,
+    mem_space: Arc<AddressSpace>,
+    /// The scsi devices shared with the controller.
+    bus: Arc<Mutex<ScsiBus>>,
+    /// Events not reported to the driver.
+    events: Arc<Mutex<VecDeque<VirtioScsiEvent>>>,
+    /// Eventfd to report the pending events.
+    event_evt: RawFd,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    driver_features: u64,
+    deactivate_evt: RawFd,
+}
+
+impl ScsiCtrlHandler {
+    fn handle_ctrl(&mut self) -> Result<()> {
+        let queue = self.ctrl_queue.clone();
+        let mut locked_queue = queue.lock().unwrap();
+        let mut need_irq = false;
+
+        while let Ok(elem) = locked_queue
+            .vring
+            .pop_avail(&self.mem_space, self.driver_features)
+        {
+            let used_len = match self.handle_ctrl_request(&elem) {
+                Ok(len) => len,
+                Err(ref e) => {
+                    error!(
+                        "Failed to handle scsi control request, {}",
+                        e.display_chain()
+                    );
+                    0
+                }
+            };
+            locked_queue
+                .vring
+                .add_used(&self.mem_space, elem.index, used_len)
+                .chain_err(|| {
+                    format!(
+                        "Failed to add used ring for scsi control queue, index: {}",
+                        elem.index
+                    )
+                })?;
+            need_irq = true;
+        }
+
+        if need_irq {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue))
+                .chain_err(|| ErrorKind::InterruptTrigger("scsi", VirtioInterruptType::Vring))?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle the task management function and asynchronous notification request,
+    /// return the length of the response.
+    fn handle_ctrl_request(&mut self, elem: &Element) -> Result<u32> {
+        let req = read_iovec(&self.mem_space, &elem.out_iovec)?;
+        if req.len() < size_of::<u32>() {
+            bail!("Invalid length {} of scsi control request", req.len());
+        }
+        let req_type = u32::from_le_bytes([req[0], req[1], req[2], req[3]]);
+
+        let (lun_offset, resp) = match req_type {
+            // The response of TMF is one byte.
+            VIRTIO_SCSI_T_TMF => (8, vec![VIRTIO_SCSI_S_OK]),
+            // The response of AN is event_actual of 4 bytes and one byte response, no
+            // event is supported.
+            VIRTIO_SCSI_T_AN_QUERY | VIRTIO_SCSI_T_AN_SUBSCRIBE => {
+                (4, vec![0, 0, 0, 0, VIRTIO_SCSI_S_OK])
+            }
+            _ => bail!("Unsupported scsi control request type {}", req_type),
+        };
+        if req.len() < lun_offset + 8 {
+            bail!("Invalid length {} of scsi control request", req.len());
+        }
+        let mut lun = [0_u8; 8];
+        lun.copy_from_slice(&req[lun_offset..lun_offset + 8]);
+
+        // As the requests are completed synchronously or submitted to the host, there is
+        // nothing to abort or reset.
+        let mut resp = resp;
+        let response = resp.last_mut().unwrap();
+        match decode_lun(&lun) {
+            Some((target, lun)) => {
+                let bus = self.bus.lock().unwrap();
+                if !bus.contains_key(&(target, lun)) {
+                    *response = if bus.range((target, 0)..=(target, u16::MAX)).count() == 0 {
+                        VIRTIO_SCSI_S_BAD_TARGET
+                    } else {
+                        VIRTIO_SCSI_S_INCORRECT_LUN
+                    };
+                }
+            }
+            None => *response = VIRTIO_SCSI_S_BAD_TARGET,
+        }
+        let written = write_iovec(&self.mem_space, &elem.in_iovec, &resp)?;
+        Ok(written as u32)
+    }
+
+    /// Report the pending events through the event queue.
+    fn handle_event(&mut self) -> Result<()> {
+        let mut events = self.events.lock().unwrap();
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let mut locked_queue = self.event_queue.lock().unwrap();
+        let mut need_irq = false;
+        while !events.is_empty() {
+            let elem = match locked_queue
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+            {
+                Ok(elem) => elem,
+                Err(_) => break,
+            };
+            let mut event = events.pop_front().unwrap();
+            // Tell the driver to rescan the bus if events are dropped.
+            if !events.is_empty() && locked_queue.vring.avail_ring_len(&self.mem_space)? == 0 {
+                event.event |= VIRTIO_SCSI_T_EVENTS_MISSED;
+                events.clear();
+            }
+            let written = write_iovec(&self.mem_space, &elem.in_iovec, event.as_bytes())?;
+            locked_queue
+                .vring
+                .add_used(&self.mem_space, elem.index, written as u32)
+                .chain_err(|| {
+                    format!(
+                        "Failed to add used ring for scsi event queue, index: {}",
+                        elem.index
+                    )
+                })?;
+            need_irq = true;
+        }
+
+        if need_irq {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&locked_queue))
+                .chain_err(|| ErrorKind::InterruptTrigger("scsi", VirtioInterruptType::Vring))?;
+        }
+
+        Ok(())
+    }
+
+    fn deactivate_evt_handler(&mut self) -> Vec<EventNotifier> {
+        [
+            self.deactivate_evt,
+            self.event_evt,
+            self.ctrl_queue_evt.as_raw_fd(),
+            self.event_queue_evt.as_raw_fd(),
+        ]
+        .iter()
+        .map(|fd| {
+            EventNotifier::new(
+                NotifierOperation::Delete,
+                *fd,
+                None,
+                EventSet::IN,
+                Vec::new(),
+            )
+        })
+        .collect()
+    }
+}
+
+impl EventNotifierHelper for ScsiCtrlHandler {
+    fn internal_notifiers(handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let handler_raw = handler.lock().unwrap();
+        let mut notifiers = Vec::new();
+
+        // Register event notifier for deactivate_evt.
+        let h_clone = handler.clone();
+        let h: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            Some(h_clone.lock().unwrap().deactivate_evt_handler())
+        });
+        notifiers.push(build_event_notifier(handler_raw.deactivate_evt, h));
+
+        // Register event notifier for ctrl_queue_evt.
+        let h_clone = handler.clone();
+        let h: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            if let Err(ref e) = h_clone.lock().unwrap().handle_ctrl() {
+                error!("Failed to handle scsi control queue, {}", e.display_chain());
+            }
+            None
+        });
+        notifiers.push(build_event_notifier(
+            handler_raw.ctrl_queue_evt.as_raw_fd(),
+            h,
+        ));
+
+        // Register event notifier for event_queue_evt and event_evt, the pending events
+        // are reported when the driver provides buffers or new events come.
+        for fd in [
+            handler_raw.event_queue_evt.as_raw_fd(),
+            handler_raw.event_evt,
+        ]
+        .iter()
+        {
+            let h_clone = handler.clone();
+            let h: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+                read_fd(fd);
+                if let Err(ref e) = h_clone.lock().unwrap().handle_event() {
+                    error!("Failed to handle scsi event queue, {}", e.display_chain());
+                }
+                None
+            });
+            notifiers.push(build_event_notifier(*fd, h));
+        }
+
+        notifiers
+    }
+}
+
+/// State of virtio-scsi controller.
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct ScsiCntlrState {
+    /// Bitmask of features supported by the backend.
+    device_features: u64,
+    /// Bit mask of features negotiated by the backend and the frontend.
+    driver_features: u64,
+    /// Config space of the virtio-scsi controller.
+    config_space: [u8; 36],
+}
+
+/// Virtio-scsi controller structure.
+pub struct ScsiCntlr {
+    /// Configuration of the virtio-scsi controller.
+    config: ScsiCntlrConfig,
+    /// Status of virtio-scsi controller.
+    state: ScsiCntlrState,
+    /// The scsi devices attached to the controller.
+    bus: Arc<Mutex<ScsiBus>>,
+    /// Events not reported to the driver.
+    events: Arc<Mutex<VecDeque<VirtioScsiEvent>>>,
+    /// Eventfd to report the pending events.
+    event_evt: EventFd,
+    /// Eventfd for device deactivate, one for each request queue and control queue.
+    deactivate_evts: Vec<EventFd>,
+}
+
+impl ScsiCntlr {
+    pub fn new(config: ScsiCntlrConfig) -> Self {
+        let deactivate_evts = (0..=config.num_queues)
+            .map(|_| EventFd::new(libc::EFD_NONBLOCK).unwrap())
+            .collect();
+        ScsiCntlr {
+            config,
+            state: ScsiCntlrState::default(),
+            bus: Arc::new(Mutex::new(BTreeMap::new())),
+            events: Arc::new(Mutex::new(VecDeque::new())),
+            event_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            deactivate_evts,
+        }
+    }
+
+    fn build_device_config_space(&mut self) {
+        let config = [
+            u32::from(self.config.num_queues),
+            SCSI_SEG_MAX,
+            SCSI_MAX_SECTORS,
+            SCSI_CMD_PER_LUN,
+            size_of::<VirtioScsiEvent>() as u32,
+            VIRTIO_SCSI_SENSE_SIZE as u32,
+            VIRTIO_SCSI_CDB_SIZE as u32,
+        ];
+        for (i, value) in config.iter().enumerate() {
+            self.state.config_space[4 * i..4 * i + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        // max_channel: 16bits, max_target: 16bits, max_lun: 32bits
+        self.state.config_space[28..30].copy_from_slice(&0_u16.to_le_bytes());
+        self.state.config_space[30..32].copy_from_slice(&u16::from(MAX_SCSI_TARGET).to_le_bytes());
+        self.state.config_space[32..36].copy_from_slice(&u32::from(MAX_SCSI_LUN).to_le_bytes());
+    }
+
+    /// Report the event to the driver, only if hotplug is negotiated.
+    fn report_event(&self, target: u8, lun: u16, reason: u32) -> Result<()> {
+        if self.state.driver_features & (1_u64 << VIRTIO_SCSI_F_HOTPLUG) == 0 {
+            return Ok(());
+        }
+        self.events.lock().unwrap().push_back(VirtioScsiEvent {
+            event: VIRTIO_SCSI_T_TRANSPORT_RESET,
+            lun: encode_lun(target, lun),
+            reason,
+        });
+        self.event_evt
+            .write(1)
+            .chain_err(|| ErrorKind::EventFdWrite)
+    }
+
+    /// Attach the scsi device to the controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `dev_cfg` - Configuration of the scsi device.
+    pub fn attach_device(&mut self, dev_cfg: &ScsiDevConfig) -> Result<()> {
+        let addr = (dev_cfg.target, dev_cfg.lun);
+        let mut bus = self.bus.lock().unwrap();
+        if bus.contains_key(&addr) {
+            bail!(
+                "Scsi device with target {} lun {} already exists on {}",
+                dev_cfg.target,
+                dev_cfg.lun,
+                self.config.id
+            );
+        }
+        let mut device = ScsiDevice::new(dev_cfg.clone());
+        device.realize()?;
+        bus.insert(addr, Arc::new(device));
+        drop(bus);
+
+        self.report_event(dev_cfg.target, dev_cfg.lun, VIRTIO_SCSI_EVT_RESET_RESCAN)
+    }
+
+    /// Detach the scsi device with the id, return whether the device is found.
+    pub fn detach_device(&mut self, id: &str) -> Result<bool> {
+        let mut bus = self.bus.lock().unwrap();
+        let addr = match bus.iter().find(|(_, dev)| dev.config.id == id) {
+            Some((addr, _)) => *addr,
+            None => return Ok(false),
+        };
+        bus.remove(&addr);
+        drop(bus);
+
+        self.report_event(addr.0, addr.1, VIRTIO_SCSI_EVT_RESET_REMOVED)?;
+        Ok(true)
+    }
+
+    fn has_device(&self, id: &str) -> bool {
+        self.bus
+            .lock()
+            .unwrap()
+            .values()
+            .any(|dev| dev.config.id == id)
+    }
+}
+
+impl VirtioDevice for ScsiCntlr {
+    /// Realize virtio-scsi controller.
+    fn realize(&mut self) -> Result<()> {
+        if self.config.iothread.is_some()
+            && EventLoop::get_ctx(self.config.iothread.as_ref()).is_none()
+        {
+            bail!(
+                "IOThread {:?} of virtio-scsi is not configured in params.",
+                self.config.iothread,
+            );
+        }
+
+        self.state.device_features = (1_u64 << VIRTIO_F_VERSION_1)
+            | (1_u64 << VIRTIO_F_RING_INDIRECT_DESC)
+            | (1_u64 << VIRTIO_F_RING_EVENT_IDX)
+            | (1_u64 << VIRTIO_SCSI_F_HOTPLUG);
+        self.build_device_config_space();
+
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Get the virtio device type, refer to Virtio Spec.
+    fn device_type(&self) -> u32 {
+        VIRTIO_TYPE_SCSI
+    }
+
+    /// Get the count of virtio device queues.
+    fn queue_num(&self) -> usize {
+        QUEUE_NUM_SCSI_CTRL_EVENT + self.config.num_queues as usize
+    }
+
+    /// Get the queue size of virtio device.
+    fn queue_size(&self) -> u16 {
+        QUEUE_SIZE_SCSI
+    }
+
+    /// Get device features from host.
+    fn get_device_features(&self, features_select: u32) -> u32 {
+        read_u32(self.state.device_features, features_select)
+    }
+
+    /// Set driver features by guest.
+    fn set_driver_features(&mut self, page: u32, value: u32) {
+        let mut v = write_u32(value, page);
+        let unrequested_features = v & !self.state.device_features;
+        if unrequested_features != 0 {
+            v &= !unrequested_features;
+        }
+        self.state.driver_features |= v;
+    }
+
+    /// Read data of config from guest.
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) -> Result<()> {
+        let config_len = self.state.config_space.len() as u64;
+        if offset >= config_len {
+            return Err(ErrorKind::DevConfigOverflow(offset, config_len).into());
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            data.write_all(
+                &self.state.config_space[offset as usize..cmp::min(end, config_len) as usize],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Write data to config from guest.
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let data_len = data.len();
+        let config_len = self.state.config_space.len();
+        if offset as usize + data_len > config_len {
+            return Err(ErrorKind::DevConfigOverflow(offset, config_len as u64).into());
+        }
+
+        // Only sense_size and cdb_size are writable, and they can't be changed.
+        let start = offset as usize;
+        if start < 20
+            || start + data_len > 28
+            || self.state.config_space[start..start + data_len] != *data
+        {
+            bail!(
+                "Unsupported to change virtio-scsi config, offset {} len {}",
+                offset,
+                data_len
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Activate the virtio device, this function is called by vcpu thread when frontend
+    /// virtio driver is ready and write `DRIVER_OK` to backend.
+    fn activate(
+        &mut self,
+        mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queues: &[Arc<Mutex<Queue>>],
+        mut queue_evts: Vec<EventFd>,
+    ) -> Result<()> {
+        let driver_features = self.state.driver_features;
+        let num_queues = self.config.num_queues as usize;
+        if queues.len() != QUEUE_NUM_SCSI_CTRL_EVENT + num_queues {
+            return Err(ErrorKind::IncorrectQueueNum(
+                QUEUE_NUM_SCSI_CTRL_EVENT + num_queues,
+                queues.len(),
+            )
+            .into());
+        }
+
+        let ctrl_handler = ScsiCtrlHandler {
+            ctrl_queue: queues[0].clone(),
+            ctrl_queue_evt: queue_evts.remove(0),
+            event_queue: queues[1].clone(),
+            event_queue_evt: queue_evts.remove(0),
+            mem_space: mem_space.clone(),
+            bus: self.bus.clone(),
+            events: self.events.clone(),
+            event_evt: self.event_evt.as_raw_fd(),
+            interrupt_cb: interrupt_cb.clone(),
+            driver_features,
+            deactivate_evt: self.deactivate_evts[num_queues].as_raw_fd(),
+        };
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(ctrl_handler))),
+            self.config.iothread.as_ref(),
+        )?;
+
+        for (index, queue) in queues[QUEUE_NUM_SCSI_CTRL_EVENT..].iter().enumerate() {
+            let mut aios = vec![ScsiCmdHandler::build_aio(AioEngine::Off)?];
+            for engine in [AioEngine::Native, AioEngine::IoUring, AioEngine::Threads].iter() {
+                match ScsiCmdHandler::build_aio(*engine) {
+                    Ok(aio) => aios.push(aio),
+                    Err(ref e) => warn!(
+                        "Failed to create aio context {:?} for scsi, {}",
+                        engine,
+                        e.display_chain()
+                    ),
+                }
+            }
+            let handler = ScsiCmdHandler {
+                queue: queue.clone(),
+                queue_evt: queue_evts.remove(0),
+                mem_space: mem_space.clone(),
+                bus: self.bus.clone(),
+                aios,
+                interrupt_cb: interrupt_cb.clone(),
+                driver_features,
+                deactivate_evt: self.deactivate_evts[index].as_raw_fd(),
+            };
+            #[allow(clippy::arc_with_non_send_sync)]
+            let handler = Arc::new(Mutex::new(handler));
+            EventLoop::update_event(
+                EventNotifierHelper::internal_notifiers(handler),
+                self.config.iothread.as_ref(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        for deactivate_evt in self.deactivate_evts.iter() {
+            deactivate_evt
+                .write(1)
+                .chain_err(|| ErrorKind::EventFdWrite)?;
+        }
+        self.events.lock().unwrap().clear();
+
+        Ok(())
+    }
+}
+
+impl StateTransfer for ScsiCntlr {
+    fn get_state_vec(&self) -> migration::errors::Result<Vec<u8>> {
+        Ok(self.state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::errors::Result<()> {
+        self.state = *ScsiCntlrState::from_bytes(state)
+            .ok_or(migration::errors::ErrorKind::FromBytesError("SCSI"))?;
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&ScsiCntlrState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for ScsiCntlr {}
+
+/// Register the virtio-scsi controller, so that scsi devices can be attached to it.
+pub fn register_scsi_cntlr(cntlr: &Arc<Mutex<ScsiCntlr>>) {
+    SCSI_CNTLRS.lock().unwrap().push(Arc::downgrade(cntlr));
+}
+
+fn scsi_cntlrs() -> Vec<Arc<Mutex<ScsiCntlr>>> {
+    let mut cntlrs = SCSI_CNTLRS.lock().unwrap();
+    // Drop the controllers which have been removed.
+    cntlrs.retain(|cntlr| cntlr.strong_count() > 0);
+    cntlrs.iter().filter_map(|cntlr| cntlr.upgrade()).collect()
+}
+
+/// Attach the scsi device to the virtio-scsi controller specified in the config.
+///
+/// # Arguments
+///
+/// * `dev_cfg` - Configuration of the scsi device.
+pub fn scsi_attach_device(dev_cfg: &ScsiDevConfig) -> Result<()> {
+    for cntlr in scsi_cntlrs() {
+        let mut locked_cntlr = cntlr.lock().unwrap();
+        if locked_cntlr.config.id == dev_cfg.cntlr {
+            return locked_cntlr.attach_device(dev_cfg);
+        }
+    }
+
+    bail!("Virtio-scsi controller {} not found", dev_cfg.cntlr);
+}
+
+/// Detach the scsi device with the id, return whether the device is found.
+pub fn scsi_detach_device(id: &str) -> Result<bool> {
+    for cntlr in scsi_cntlrs() {
+        if cntlr.lock().unwrap().detach_device(id)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Whether the scsi device with the id is attached to any controller.
+pub fn scsi_device_existed(id: &str) -> bool {
+    scsi_cntlrs()
+        .iter()
+        .any(|cntlr| cntlr.lock().unwrap().has_device(id))
+}
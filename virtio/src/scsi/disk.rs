@@ -0,0 +1,865 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+
+use machine_manager::config::{ScsiDevConfig, ScsiDevType};
+use util::aio::{raw_discard, IoCmd};
+
+use super::super::errors::{Result, ResultExt};
+
+/// SCSI operation codes, refer to SPC-4, SBC-3 and MMC-6.
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const READ_6: u8 = 0x08;
+const WRITE_6: u8 = 0x0a;
+pub const INQUIRY: u8 = 0x12;
+const MODE_SENSE: u8 = 0x1a;
+const START_STOP: u8 = 0x1b;
+const ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const VERIFY_10: u8 = 0x2f;
+const SYNCHRONIZE_CACHE: u8 = 0x35;
+pub const UNMAP: u8 = 0x42;
+const READ_TOC: u8 = 0x43;
+const GET_CONFIGURATION: u8 = 0x46;
+const GET_EVENT_STATUS_NOTIFICATION: u8 = 0x4a;
+const MODE_SENSE_10: u8 = 0x5a;
+const READ_16: u8 = 0x88;
+const WRITE_16: u8 = 0x8a;
+const VERIFY_16: u8 = 0x8f;
+const SYNCHRONIZE_CACHE_16: u8 = 0x91;
+const SERVICE_ACTION_IN_16: u8 = 0x9e;
+pub const REPORT_LUNS: u8 = 0xa0;
+const READ_12: u8 = 0xa8;
+const WRITE_12: u8 = 0xaa;
+const VERIFY_12: u8 = 0xaf;
+
+/// Service action of SERVICE ACTION IN(16).
+const SAI_READ_CAPACITY_16: u8 = 0x10;
+
+/// Peripheral device types.
+const TYPE_DISK: u8 = 0x00;
+const TYPE_ROM: u8 = 0x05;
+/// Peripheral qualifier and device type of the lun which is not present.
+pub const TYPE_NO_LUN: u8 = 0x7f;
+
+/// Mode pages.
+const MODE_PAGE_CACHING: u8 = 0x08;
+const MODE_PAGE_CAPABILITIES: u8 = 0x2a;
+const MODE_PAGE_ALL: u8 = 0x3f;
+
+/// Vital product data pages.
+const VPD_SUPPORTED_PAGES: u8 = 0x00;
+const VPD_SERIAL_NUMBER: u8 = 0x80;
+const VPD_DEVICE_ID: u8 = 0x83;
+const VPD_BLOCK_LIMITS: u8 = 0xb0;
+const VPD_LB_PROVISIONING: u8 = 0xb2;
+
+/// Length of the standard inquiry data.
+const INQUIRY_DATA_LEN: usize = 36;
+/// Length of the fixed format sense data.
+pub const SCSI_SENSE_LEN: usize = 18;
+const SCSI_VENDOR: &[u8; 8] = b"STRATO  ";
+const SCSI_DISK_PRODUCT: &[u8; 16] = b"STRATO HARDDISK ";
+const SCSI_CDROM_PRODUCT: &[u8; 16] = b"STRATO CD-ROM   ";
+const SCSI_REVISION: &[u8; 4] = b"2.1 ";
+
+/// Logical block size of disk.
+const DISK_BLOCK_SIZE: u32 = 512;
+/// Logical block size of CD-ROM.
+const CDROM_BLOCK_SIZE: u32 = 2048;
+/// The maximum number of logical blocks in one unmap block descriptor.
+const MAX_UNMAP_BLOCKS: u32 = (i32::MAX as u32) / DISK_BLOCK_SIZE;
+/// The maximum number of unmap block descriptors in one UNMAP command.
+const MAX_UNMAP_DESCRIPTORS: u32 = 255;
+/// MMC profile of CD-ROM.
+const MMC_PROFILE_CD_ROM: u16 = 0x0008;
+
+/// Sense key, additional sense code and additional sense code qualifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScsiSense {
+    pub key: u8,
+    pub asc: u8,
+    pub ascq: u8,
+}
+
+impl ScsiSense {
+    const fn new(key: u8, asc: u8, ascq: u8) -> Self {
+        ScsiSense { key, asc, ascq }
+    }
+
+    /// Build the fixed format sense data.
+    pub fn to_bytes(self) -> [u8; SCSI_SENSE_LEN] {
+        let mut sense = [0_u8; SCSI_SENSE_LEN];
+        // Current error, fixed format.
+        sense[0] = 0x70;
+        sense[2] = self.key;
+        // Additional sense length.
+        sense[7] = (SCSI_SENSE_LEN - 8) as u8;
+        sense[12] = self.asc;
+        sense[13] = self.ascq;
+        sense
+    }
+}
+
+/// Sense of no error.
+pub const SENSE_NO_SENSE: ScsiSense = ScsiSense::new(0x00, 0x00, 0x00);
+/// Sense of medium not present.
+pub const SENSE_NO_MEDIUM: ScsiSense = ScsiSense::new(0x02, 0x3a, 0x00);
+/// Sense of invalid command operation code.
+pub const SENSE_INVALID_OPCODE: ScsiSense = ScsiSense::new(0x05, 0x20, 0x00);
+/// Sense of logical block address out of range.
+pub const SENSE_LBA_OUT_OF_RANGE: ScsiSense = ScsiSense::new(0x05, 0x21, 0x00);
+/// Sense of invalid field in cdb.
+pub const SENSE_INVALID_FIELD: ScsiSense = ScsiSense::new(0x05, 0x24, 0x00);
+/// Sense of invalid field in parameter list.
+pub const SENSE_INVALID_PARAM: ScsiSense = ScsiSense::new(0x05, 0x26, 0x00);
+/// Sense of logical unit not supported.
+pub const SENSE_LUN_NOT_SUPPORTED: ScsiSense = ScsiSense::new(0x05, 0x25, 0x00);
+/// Sense of write protected.
+pub const SENSE_WRITE_PROTECTED: ScsiSense = ScsiSense::new(0x07, 0x27, 0x00);
+/// Sense of I/O error.
+pub const SENSE_IO_ERROR: ScsiSense = ScsiSense::new(0x0b, 0x00, 0x06);
+
+/// How the scsi command is completed.
+#[derive(Debug, PartialEq)]
+pub enum ScsiCmdResult {
+    /// The command is completed, with the data transferred to the guest.
+    Done(Vec<u8>),
+    /// The command fails with the sense.
+    Failed(ScsiSense),
+    /// The command is completed by the io on the image file.
+    Io {
+        opcode: IoCmd,
+        /// Offset of the image file in bytes.
+        offset: u64,
+        /// Length of the io in bytes.
+        nbytes: u64,
+    },
+}
+
+fn read_be16(buf: &[u8]) -> u16 {
+    u16::from_be_bytes([buf[0], buf[1]])
+}
+
+fn read_be32(buf: &[u8]) -> u32 {
+    u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+fn read_be64(buf: &[u8]) -> u64 {
+    let mut bytes = [0_u8; 8];
+    bytes.copy_from_slice(&buf[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Truncate the data to the allocation length of the command.
+fn truncate(mut data: Vec<u8>, alloc_len: usize) -> ScsiCmdResult {
+    data.truncate(alloc_len);
+    ScsiCmdResult::Done(data)
+}
+
+/// Encode the lun for REPORT LUNS, refer to the logical unit addressing of SAM.
+pub fn encode_lun(lun: u16) -> [u8; 8] {
+    let mut bytes = [0_u8; 8];
+    if lun < 256 {
+        // Peripheral device addressing.
+        bytes[1] = lun as u8;
+    } else {
+        // Flat space addressing.
+        bytes[0] = 0x40 | (lun >> 8) as u8;
+        bytes[1] = lun as u8;
+    }
+    bytes
+}
+
+/// Build the data of REPORT LUNS.
+///
+/// # Arguments
+///
+/// * `luns` - The luns present on the target.
+/// * `cdb` - The command descriptor block.
+pub fn report_luns(luns: &[u16], cdb: &[u8]) -> ScsiCmdResult {
+    let alloc_len = read_be32(&cdb[6..10]) as usize;
+    if alloc_len < 16 {
+        return ScsiCmdResult::Failed(SENSE_INVALID_FIELD);
+    }
+    let mut data = Vec::with_capacity(8 * (luns.len() + 1));
+    data.extend_from_slice(&((luns.len() * 8) as u32).to_be_bytes());
+    data.extend_from_slice(&[0; 4]);
+    for lun in luns {
+        data.extend_from_slice(&encode_lun(*lun));
+    }
+    truncate(data, alloc_len)
+}
+
+/// Build the standard inquiry data of the lun which is not present on the target.
+pub fn inquiry_no_lun(cdb: &[u8]) -> ScsiCmdResult {
+    if cdb[1] & 0x01 != 0 {
+        return ScsiCmdResult::Failed(SENSE_LUN_NOT_SUPPORTED);
+    }
+    let mut data = vec![0_u8; INQUIRY_DATA_LEN];
+    data[0] = TYPE_NO_LUN;
+    data[2] = 5;
+    data[3] = 2;
+    data[4] = (INQUIRY_DATA_LEN - 5) as u8;
+    truncate(data, read_be16(&cdb[3..5]) as usize)
+}
+
+/// Scsi device attached to virtio-scsi controller, `scsi-hd` or `scsi-cd`.
+pub struct ScsiDevice {
+    /// Configuration of the scsi device.
+    pub config: ScsiDevConfig,
+    /// Image file opened.
+    disk_image: Option<Arc<File>>,
+    /// Number of logical blocks of the image file.
+    num_blocks: u64,
+    /// Size of logical block in bytes.
+    block_size: u32,
+}
+
+impl ScsiDevice {
+    pub fn new(config: ScsiDevConfig) -> Self {
+        let block_size = match config.dev_type {
+            ScsiDevType::Disk => DISK_BLOCK_SIZE,
+            ScsiDevType::CdRom => CDROM_BLOCK_SIZE,
+        };
+        ScsiDevice {
+            config,
+            disk_image: None,
+            num_blocks: 0,
+            block_size,
+        }
+    }
+
+    /// Open the image file of the scsi device.
+    pub fn realize(&mut self) -> Result<()> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(!self.config.read_only);
+        if self.config.direct {
+            options.custom_flags(libc::O_DIRECT);
+        }
+        let mut file = options.open(&self.config.path_on_host).chain_err(|| {
+            format!(
+                "failed to open the file for scsi device {}",
+                self.config.path_on_host
+            )
+        })?;
+        let disk_size = file
+            .seek(SeekFrom::End(0))
+            .chain_err(|| "Failed to seek the end for scsi device")?;
+
+        self.num_blocks = disk_size / u64::from(self.block_size);
+        self.disk_image = Some(Arc::new(file));
+        Ok(())
+    }
+
+    /// Get the image file opened.
+    pub fn disk_image(&self) -> Option<&Arc<File>> {
+        self.disk_image.as_ref()
+    }
+
+    fn is_cdrom(&self) -> bool {
+        self.config.dev_type == ScsiDevType::CdRom
+    }
+
+    /// Whether the device supports UNMAP command.
+    fn can_unmap(&self) -> bool {
+        !self.is_cdrom() && !self.config.read_only && self.config.discard
+    }
+
+    /// Handle the scsi command.
+    ///
+    /// # Arguments
+    ///
+    /// * `cdb` - The command descriptor block.
+    /// * `data_out` - The data transferred from the guest.
+    pub fn execute(&self, cdb: &[u8], data_out: &[u8]) -> ScsiCmdResult {
+        match cdb[0] {
+            TEST_UNIT_READY | START_STOP | ALLOW_MEDIUM_REMOVAL | VERIFY_10 | VERIFY_12
+            | VERIFY_16 => ScsiCmdResult::Done(Vec::new()),
+            REQUEST_SENSE => truncate(SENSE_NO_SENSE.to_bytes().to_vec(), cdb[4] as usize),
+            INQUIRY => self.inquiry(cdb),
+            MODE_SENSE | MODE_SENSE_10 => self.mode_sense(cdb),
+            READ_CAPACITY_10 => self.read_capacity_10(),
+            SERVICE_ACTION_IN_16 if cdb[1] & 0x1f == SAI_READ_CAPACITY_16 => {
+                self.read_capacity_16(cdb)
+            }
+            READ_6 | READ_10 | READ_12 | READ_16 | WRITE_6 | WRITE_10 | WRITE_12 | WRITE_16 => {
+                self.read_write(cdb)
+            }
+            SYNCHRONIZE_CACHE | SYNCHRONIZE_CACHE_16 => ScsiCmdResult::Io {
+                opcode: IoCmd::Fdsync,
+                offset: 0,
+                nbytes: 0,
+            },
+            UNMAP if !self.is_cdrom() => self.unmap(data_out),
+            READ_TOC if self.is_cdrom() => self.read_toc(cdb),
+            GET_CONFIGURATION if self.is_cdrom() => self.get_configuration(cdb),
+            GET_EVENT_STATUS_NOTIFICATION if self.is_cdrom() => {
+                Self::get_event_status_notification(cdb)
+            }
+            _ => ScsiCmdResult::Failed(SENSE_INVALID_OPCODE),
+        }
+    }
+
+    fn inquiry(&self, cdb: &[u8]) -> ScsiCmdResult {
+        let alloc_len = read_be16(&cdb[3..5]) as usize;
+        let dev_type = if self.is_cdrom() { TYPE_ROM } else { TYPE_DISK };
+
+        if cdb[1] & 0x01 == 0 {
+            if cdb[2] != 0 {
+                return ScsiCmdResult::Failed(SENSE_INVALID_FIELD);
+            }
+            let mut data = vec![0_u8; INQUIRY_DATA_LEN];
+            data[0] = dev_type;
+            // CD-ROM is removable.
+            data[1] = if self.is_cdrom() { 0x80 } else { 0 };
+            // Compliance with SPC-3.
+            data[2] = 5;
+            // Response data format.
+            data[3] = 2;
+            data[4] = (INQUIRY_DATA_LEN - 5) as u8;
+            // Command queuing is supported.
+            data[7] = 0x02;
+            data[8..16].copy_from_slice(SCSI_VENDOR);
+            if self.is_cdrom() {
+                data[16..32].copy_from_slice(SCSI_CDROM_PRODUCT);
+            } else {
+                data[16..32].copy_from_slice(SCSI_DISK_PRODUCT);
+            }
+            data[32..36].copy_from_slice(SCSI_REVISION);
+            return truncate(data, alloc_len);
+        }
+
+        let page = cdb[2];
+        let mut data = vec![dev_type, page, 0, 0];
+        match page {
+            VPD_SUPPORTED_PAGES => {
+                data.push(VPD_SUPPORTED_PAGES);
+                if self.config.serial_num.is_some() {
+                    data.push(VPD_SERIAL_NUMBER);
+                }
+                data.push(VPD_DEVICE_ID);
+                if !self.is_cdrom() {
+                    data.push(VPD_BLOCK_LIMITS);
+                    data.push(VPD_LB_PROVISIONING);
+                }
+            }
+            VPD_SERIAL_NUMBER if self.config.serial_num.is_some() => {
+                data.extend_from_slice(self.config.serial_num.as_ref().unwrap().as_bytes());
+            }
+            VPD_DEVICE_ID => {
+                let id = self.config.id.as_bytes();
+                let id_len = cmp::min(id.len(), 255 - 8);
+                // Vendor specific identifier with ASCII code set.
+                data.extend_from_slice(&[0x02, 0x00, 0x00, id_len as u8]);
+                data.extend_from_slice(&id[..id_len]);
+            }
+            VPD_BLOCK_LIMITS if !self.is_cdrom() => {
+                data.resize(64, 0);
+                if self.can_unmap() {
+                    data[20..24].copy_from_slice(&MAX_UNMAP_BLOCKS.to_be_bytes());
+                    data[24..28].copy_from_slice(&MAX_UNMAP_DESCRIPTORS.to_be_bytes());
+                    // Unmap granularity is one logical block.
+                    data[28..32].copy_from_slice(&1_u32.to_be_bytes());
+                }
+            }
+            VPD_LB_PROVISIONING if !self.is_cdrom() => {
+                data.resize(8, 0);
+                if self.can_unmap() {
+                    // UNMAP command is supported.
+                    data[5] = 0x80;
+                }
+            }
+            _ => return ScsiCmdResult::Failed(SENSE_INVALID_FIELD),
+        }
+        let page_len = (data.len() - 4) as u16;
+        data[2..4].copy_from_slice(&page_len.to_be_bytes());
+        truncate(data, alloc_len)
+    }
+
+    fn mode_sense(&self, cdb: &[u8]) -> ScsiCmdResult {
+        let is_10 = cdb[0] == MODE_SENSE_10;
+        let dbd = cdb[1] & 0x08 != 0;
+        let page = cdb[2] & 0x3f;
+        let alloc_len = if is_10 {
+            read_be16(&cdb[7..9]) as usize
+        } else {
+            cdb[4] as usize
+        };
+
+        let mut pages = Vec::new();
+        if !self.is_cdrom() && (page == MODE_PAGE_CACHING || page == MODE_PAGE_ALL) {
+            let mut caching = vec![0_u8; 20];
+            caching[0] = MODE_PAGE_CACHING;
+            caching[1] = 18;
+            // Write cache is enabled.
+            caching[2] = 0x04;
+            pages.extend(caching);
+        }
+        if self.is_cdrom() && (page == MODE_PAGE_CAPABILITIES || page == MODE_PAGE_ALL) {
+            let mut capabilities = vec![0_u8; 20];
+            capabilities[0] = MODE_PAGE_CAPABILITIES;
+            capabilities[1] = 18;
+            // Read CD-R and CD-RW.
+            capabilities[2] = 0x03;
+            // Lock and eject are supported, tray loading mechanism.
+            capabilities[6] = 0x29;
+            pages.extend(capabilities);
+        }
+        if pages.is_empty() {
+            return ScsiCmdResult::Failed(SENSE_INVALID_FIELD);
+        }
+
+        let mut block_desc = Vec::new();
+        if !dbd {
+            let num_blocks = cmp::min(self.num_blocks, 0xff_ffff) as u32;
+            block_desc.extend_from_slice(&num_blocks.to_be_bytes());
+            block_desc.extend_from_slice(&self.block_size.to_be_bytes());
+            // Density code is 0, and the first byte of block length is reserved.
+            block_desc[0] = 0;
+            block_desc[4] = 0;
+        }
+        // Write protected.
+        let dev_specific = if self.config.read_only { 0x80 } else { 0 };
+
+        let mut data = if is_10 {
+            let len = (6 + block_desc.len() + pages.len()) as u16;
+            let mut header = len.to_be_bytes().to_vec();
+            header.extend_from_slice(&[0, dev_specific, 0, 0]);
+            header.extend_from_slice(&(block_desc.len() as u16).to_be_bytes());
+            header
+        } else {
+            let len = (3 + block_desc.len() + pages.len()) as u8;
+            vec![len, 0, dev_specific, block_desc.len() as u8]
+        };
+        data.extend(block_desc);
+        data.extend(pages);
+        truncate(data, alloc_len)
+    }
+
+    fn read_capacity_10(&self) -> ScsiCmdResult {
+        let last_lba = cmp::min(self.num_blocks.saturating_sub(1), u64::from(u32::MAX)) as u32;
+        let mut data = last_lba.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.block_size.to_be_bytes());
+        ScsiCmdResult::Done(data)
+    }
+
+    fn read_capacity_16(&self, cdb: &[u8]) -> ScsiCmdResult {
+        let alloc_len = read_be32(&cdb[10..14]) as usize;
+        let mut data = vec![0_u8; 32];
+        data[0..8].copy_from_slice(&self.num_blocks.saturating_sub(1).to_be_bytes());
+        data[8..12].copy_from_slice(&self.block_size.to_be_bytes());
+        if self.can_unmap() {
+            // Logical block provisioning management is enabled.
+            data[14] = 0x80;
+        }
+        truncate(data, alloc_len)
+    }
+
+    fn read_write(&self, cdb: &[u8]) -> ScsiCmdResult {
+        let (lba, num_blocks) = match cdb[0] {
+            READ_6 | WRITE_6 => {
+                let lba = (u64::from(cdb[1] & 0x1f) << 16) | u64::from(read_be16(&cdb[2..4]));
+                // Zero transfer length means 256 blocks.
+                let num_blocks = if cdb[4] == 0 { 256 } else { u32::from(cdb[4]) };
+                (lba, num_blocks)
+            }
+            READ_10 | WRITE_10 => (
+                u64::from(read_be32(&cdb[2..6])),
+                u32::from(read_be16(&cdb[7..9])),
+            ),
+            READ_12 | WRITE_12 => (u64::from(read_be32(&cdb[2..6])), read_be32(&cdb[6..10])),
+            _ => (read_be64(&cdb[2..10]), read_be32(&cdb[10..14])),
+        };
+        let is_write = matches!(cdb[0], WRITE_6 | WRITE_10 | WRITE_12 | WRITE_16);
+        if is_write && self.config.read_only {
+            return ScsiCmdResult::Failed(SENSE_WRITE_PROTECTED);
+        }
+        if lba
+            .checked_add(u64::from(num_blocks))
+            .filter(|end| *end <= self.num_blocks)
+            .is_none()
+        {
+            return ScsiCmdResult::Failed(SENSE_LBA_OUT_OF_RANGE);
+        }
+        if num_blocks == 0 {
+            return ScsiCmdResult::Done(Vec::new());
+        }
+
+        ScsiCmdResult::Io {
+            opcode: if is_write {
+                IoCmd::Pwritev
+            } else {
+                IoCmd::Preadv
+            },
+            offset: lba * u64::from(self.block_size),
+            nbytes: u64::from(num_blocks) * u64::from(self.block_size),
+        }
+    }
+
+    fn unmap(&self, data_out: &[u8]) -> ScsiCmdResult {
+        if self.config.read_only {
+            return ScsiCmdResult::Failed(SENSE_WRITE_PROTECTED);
+        }
+        if data_out.is_empty() {
+            return ScsiCmdResult::Done(Vec::new());
+        }
+        if data_out.len() < 8 {
+            return ScsiCmdResult::Failed(SENSE_INVALID_PARAM);
+        }
+        let desc_len = read_be16(&data_out[2..4]) as usize;
+        if desc_len % 16 != 0 || data_out.len() < 8 + desc_len {
+            return ScsiCmdResult::Failed(SENSE_INVALID_PARAM);
+        }
+        if desc_len / 16 > MAX_UNMAP_DESCRIPTORS as usize {
+            return ScsiCmdResult::Failed(SENSE_INVALID_FIELD);
+        }
+
+        let mut ranges = Vec::with_capacity(desc_len / 16);
+        for desc in data_out[8..8 + desc_len].chunks(16) {
+            let lba = read_be64(&desc[0..8]);
+            let num_blocks = read_be32(&desc[8..12]);
+            if num_blocks > MAX_UNMAP_BLOCKS {
+                return ScsiCmdResult::Failed(SENSE_INVALID_FIELD);
+            }
+            if lba
+                .checked_add(u64::from(num_blocks))
+                .filter(|end| *end <= self.num_blocks)
+                .is_none()
+            {
+                return ScsiCmdResult::Failed(SENSE_LBA_OUT_OF_RANGE);
+            }
+            ranges.push((lba, num_blocks));
+        }
+
+        // Unmap is only a hint, just ignore it if discard is disabled.
+        if !self.config.discard {
+            return ScsiCmdResult::Done(Vec::new());
+        }
+        let disk = match self.disk_image.as_ref() {
+            Some(disk) => disk,
+            None => return ScsiCmdResult::Failed(SENSE_NO_MEDIUM),
+        };
+        for (lba, num_blocks) in ranges {
+            let ret = raw_discard(
+                disk.as_raw_fd(),
+                (lba * u64::from(self.block_size)) as usize,
+                u64::from(num_blocks) * u64::from(self.block_size),
+            );
+            if ret < 0 && ret != -i64::from(libc::EOPNOTSUPP) {
+                return ScsiCmdResult::Failed(SENSE_IO_ERROR);
+            }
+        }
+        ScsiCmdResult::Done(Vec::new())
+    }
+
+    fn read_toc(&self, cdb: &[u8]) -> ScsiCmdResult {
+        let msf = cdb[1] & 0x02 != 0;
+        let format = cdb[2] & 0x0f;
+        let alloc_len = read_be16(&cdb[7..9]) as usize;
+
+        let encode_addr = |lba: u64| -> [u8; 4] {
+            if msf {
+                // The first 150 frames are the lead-in area.
+                let frames = lba + 150;
+                [
+                    0,
+                    (frames / (75 * 60)) as u8,
+                    ((frames / 75) % 60) as u8,
+                    (frames % 75) as u8,
+                ]
+            } else {
+                (lba as u32).to_be_bytes()
+            }
+        };
+
+        let mut data = match format {
+            // Formatted TOC with one data track and the lead-out track.
+            0 => {
+                let mut data = vec![0, 0, 1, 1];
+                data.extend_from_slice(&[0, 0x14, 1, 0]);
+                data.extend_from_slice(&encode_addr(0));
+                data.extend_from_slice(&[0, 0x14, 0xaa, 0]);
+                data.extend_from_slice(&encode_addr(self.num_blocks));
+                data
+            }
+            // Multi-session information.
+            1 => {
+                let mut data = vec![0, 0, 1, 1];
+                data.extend_from_slice(&[0, 0x14, 1, 0]);
+                data.extend_from_slice(&encode_addr(0));
+                data
+            }
+            _ => return ScsiCmdResult::Failed(SENSE_INVALID_FIELD),
+        };
+        let len = (data.len() - 2) as u16;
+        data[0..2].copy_from_slice(&len.to_be_bytes());
+        truncate(data, alloc_len)
+    }
+
+    fn get_configuration(&self, cdb: &[u8]) -> ScsiCmdResult {
+        let alloc_len = read_be16(&cdb[7..9]) as usize;
+        // Only the feature header is returned, with the current profile.
+        let mut data = vec![0_u8; 8];
+        data[0..4].copy_from_slice(&4_u32.to_be_bytes());
+        data[6..8].copy_from_slice(&MMC_PROFILE_CD_ROM.to_be_bytes());
+        truncate(data, alloc_len)
+    }
+
+    fn get_event_status_notification(cdb: &[u8]) -> ScsiCmdResult {
+        // Only polling is supported.
+        if cdb[1] & 0x01 == 0 {
+            return ScsiCmdResult::Failed(SENSE_INVALID_FIELD);
+        }
+        let alloc_len = read_be16(&cdb[7..9]) as usize;
+        // No event is available, and only media class is supported.
+        let data = vec![0, 2, 0x80, 0x10];
+        truncate(data, alloc_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vmm_sys_util::tempfile::TempFile;
+
+    fn create_device(dev_type: ScsiDevType, size: u64) -> (ScsiDevice, TempFile) {
+        let file = TempFile::new().unwrap();
+        file.as_file().set_len(size).unwrap();
+        let config = ScsiDevConfig {
+            id: "scsi0-0-0".to_string(),
+            dev_type,
+            path_on_host: file.as_path().to_str().unwrap().to_string(),
+            read_only: dev_type == ScsiDevType::CdRom,
+            direct: false,
+            discard: true,
+            ..Default::default()
+        };
+        let mut dev = ScsiDevice::new(config);
+        dev.realize().unwrap();
+        (dev, file)
+    }
+
+    #[test]
+    fn test_scsi_inquiry() {
+        let (disk, _file) = create_device(ScsiDevType::Disk, 1 << 20);
+        let cdb = [INQUIRY, 0, 0, 0, 0xff, 0];
+        match disk.execute(&cdb, &[]) {
+            ScsiCmdResult::Done(data) => {
+                assert_eq!(data.len(), INQUIRY_DATA_LEN);
+                assert_eq!(data[0], TYPE_DISK);
+                assert_eq!(&data[8..16], SCSI_VENDOR);
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        // Supported vpd pages.
+        let cdb = [INQUIRY, 1, VPD_SUPPORTED_PAGES, 0, 0xff, 0];
+        assert_eq!(
+            disk.execute(&cdb, &[]),
+            ScsiCmdResult::Done(vec![
+                TYPE_DISK,
+                VPD_SUPPORTED_PAGES,
+                0,
+                4,
+                VPD_SUPPORTED_PAGES,
+                VPD_DEVICE_ID,
+                VPD_BLOCK_LIMITS,
+                VPD_LB_PROVISIONING
+            ])
+        );
+        // Unmap is supported.
+        let cdb = [INQUIRY, 1, VPD_LB_PROVISIONING, 0, 0xff, 0];
+        match disk.execute(&cdb, &[]) {
+            ScsiCmdResult::Done(data) => assert_eq!(data[5], 0x80),
+            res => panic!("Unexpected result {:?}", res),
+        }
+        // Serial number page is not supported without serial.
+        let cdb = [INQUIRY, 1, VPD_SERIAL_NUMBER, 0, 0xff, 0];
+        assert_eq!(
+            disk.execute(&cdb, &[]),
+            ScsiCmdResult::Failed(SENSE_INVALID_FIELD)
+        );
+
+        // Allocation length truncates the data.
+        let (cdrom, _file) = create_device(ScsiDevType::CdRom, 1 << 20);
+        let cdb = [INQUIRY, 0, 0, 0, 2, 0];
+        assert_eq!(
+            cdrom.execute(&cdb, &[]),
+            ScsiCmdResult::Done(vec![TYPE_ROM, 0x80])
+        );
+    }
+
+    #[test]
+    fn test_scsi_read_capacity() {
+        let (disk, _file) = create_device(ScsiDevType::Disk, 1 << 20);
+        let cdb = [READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            disk.execute(&cdb, &[]),
+            ScsiCmdResult::Done(vec![0, 0, 0x07, 0xff, 0, 0, 0x02, 0])
+        );
+
+        let mut cdb = [0_u8; 16];
+        cdb[0] = SERVICE_ACTION_IN_16;
+        cdb[1] = SAI_READ_CAPACITY_16;
+        cdb[13] = 32;
+        match disk.execute(&cdb, &[]) {
+            ScsiCmdResult::Done(data) => {
+                assert_eq!(data.len(), 32);
+                assert_eq!(read_be64(&data[0..8]), 2047);
+                assert_eq!(read_be32(&data[8..12]), DISK_BLOCK_SIZE);
+                assert_eq!(data[14], 0x80);
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        let (cdrom, _file) = create_device(ScsiDevType::CdRom, 1 << 20);
+        let cdb = [READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            cdrom.execute(&cdb, &[]),
+            ScsiCmdResult::Done(vec![0, 0, 0x01, 0xff, 0, 0, 0x08, 0])
+        );
+    }
+
+    #[test]
+    fn test_scsi_read_write() {
+        let (disk, _file) = create_device(ScsiDevType::Disk, 1 << 20);
+        let cdb = [READ_10, 0, 0, 0, 0, 2, 0, 0, 8, 0];
+        assert_eq!(
+            disk.execute(&cdb, &[]),
+            ScsiCmdResult::Io {
+                opcode: IoCmd::Preadv,
+                offset: 1024,
+                nbytes: 4096
+            }
+        );
+        let mut cdb = [0_u8; 16];
+        cdb[0] = WRITE_16;
+        cdb[9] = 0xff;
+        cdb[13] = 1;
+        assert_eq!(
+            disk.execute(&cdb, &[]),
+            ScsiCmdResult::Io {
+                opcode: IoCmd::Pwritev,
+                offset: 0xff * 512,
+                nbytes: 512
+            }
+        );
+        // Out of range.
+        let cdb = [READ_10, 0, 0, 0, 0x07, 0xff, 0, 0, 2, 0];
+        assert_eq!(
+            disk.execute(&cdb, &[]),
+            ScsiCmdResult::Failed(SENSE_LBA_OUT_OF_RANGE)
+        );
+
+        // CD-ROM is read-only.
+        let (cdrom, _file) = create_device(ScsiDevType::CdRom, 1 << 20);
+        let cdb = [WRITE_10, 0, 0, 0, 0, 0, 0, 0, 1, 0];
+        assert_eq!(
+            cdrom.execute(&cdb, &[]),
+            ScsiCmdResult::Failed(SENSE_WRITE_PROTECTED)
+        );
+        let cdb = [READ_6, 0, 0, 1, 1, 0];
+        assert_eq!(
+            cdrom.execute(&cdb, &[]),
+            ScsiCmdResult::Io {
+                opcode: IoCmd::Preadv,
+                offset: 2048,
+                nbytes: 2048
+            }
+        );
+    }
+
+    #[test]
+    fn test_scsi_unmap() {
+        let (disk, _file) = create_device(ScsiDevType::Disk, 1 << 20);
+        let cdb = [UNMAP, 0, 0, 0, 0, 0, 0, 0, 24, 0];
+        let mut param = vec![0, 22, 0, 16, 0, 0, 0, 0];
+        param.extend_from_slice(&8_u64.to_be_bytes());
+        param.extend_from_slice(&8_u32.to_be_bytes());
+        param.extend_from_slice(&[0; 4]);
+        assert_eq!(disk.execute(&cdb, &param), ScsiCmdResult::Done(Vec::new()));
+
+        // Out of range.
+        param[8..16].copy_from_slice(&2047_u64.to_be_bytes());
+        assert_eq!(
+            disk.execute(&cdb, &param),
+            ScsiCmdResult::Failed(SENSE_LBA_OUT_OF_RANGE)
+        );
+        // Truncated parameter list.
+        assert_eq!(
+            disk.execute(&cdb, &param[..20]),
+            ScsiCmdResult::Failed(SENSE_INVALID_PARAM)
+        );
+    }
+
+    #[test]
+    fn test_scsi_report_luns() {
+        let mut cdb = [0_u8; 12];
+        cdb[0] = REPORT_LUNS;
+        cdb[9] = 0xff;
+        let mut expect = vec![0, 0, 0, 16, 0, 0, 0, 0];
+        expect.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+        expect.extend_from_slice(&[0x41, 0x2c, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(report_luns(&[0, 300], &cdb), ScsiCmdResult::Done(expect));
+
+        cdb[9] = 8;
+        assert_eq!(
+            report_luns(&[0], &cdb),
+            ScsiCmdResult::Failed(SENSE_INVALID_FIELD)
+        );
+    }
+
+    #[test]
+    fn test_scsi_mode_sense() {
+        let (disk, _file) = create_device(ScsiDevType::Disk, 1 << 20);
+        let cdb = [MODE_SENSE, 0x08, MODE_PAGE_CACHING, 0, 0xff, 0];
+        match disk.execute(&cdb, &[]) {
+            ScsiCmdResult::Done(data) => {
+                assert_eq!(data.len(), 24);
+                assert_eq!(data[0], 23);
+                assert_eq!(data[3], 0);
+                assert_eq!(data[4], MODE_PAGE_CACHING);
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        let (cdrom, _file) = create_device(ScsiDevType::CdRom, 1 << 20);
+        let cdb = [MODE_SENSE_10, 0, MODE_PAGE_ALL, 0, 0, 0, 0, 0, 0xff, 0];
+        match cdrom.execute(&cdb, &[]) {
+            ScsiCmdResult::Done(data) => {
+                assert_eq!(data.len(), 36);
+                // Write protected.
+                assert_eq!(data[3], 0x80);
+                assert_eq!(read_be16(&data[6..8]), 8);
+                assert_eq!(read_be32(&data[12..16]), CDROM_BLOCK_SIZE);
+                assert_eq!(data[16], MODE_PAGE_CAPABILITIES);
+            }
+            res => panic!("Unexpected result {:?}", res),
+        }
+        // Caching page is not supported by CD-ROM.
+        let cdb = [MODE_SENSE, 0, MODE_PAGE_CACHING, 0, 0xff, 0];
+        assert_eq!(
+            cdrom.execute(&cdb, &[]),
+            ScsiCmdResult::Failed(SENSE_INVALID_FIELD)
+        );
+    }
+}
@@ -13,7 +13,8 @@
 mod net;
 mod vsock;
 
-pub use net::Net;
+pub(crate) use net::query_net_stats;
+pub use net::{register_net_device, Net};
 pub use vsock::{Vsock, VsockState};
 
 use std::fs::{File, OpenOptions};
@@ -25,17 +26,12 @@ use address_space::{
     AddressSpace, FlatRange, GuestAddress, Listener, ListenerReqType, RegionIoEventFd, RegionType,
 };
 use util::byte_code::ByteCode;
-use util::loop_context::{
-    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
-};
-use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref};
 
 use super::super::errors::{ErrorKind, Result, ResultExt};
-use super::super::{QueueConfig, VirtioInterrupt, VirtioInterruptType};
-use super::{VhostNotify, VhostOps};
-use crate::error_chain::ChainedError;
+use super::super::QueueConfig;
+use super::VhostOps;
 
 /// Refer to VHOST_VIRTIO in
 /// https://github.com/torvalds/linux/blob/master/include/uapi/linux/vhost.h.
@@ -214,6 +210,14 @@ impl Listener for VhostMemInfo {
         }
         Ok(())
     }
+
+    fn set_dirty_log(&self, enable: bool) -> std::result::Result<(), address_space::errors::Error> {
+        // The vhost backend writes guest memory without logging dirty pages.
+        if enable {
+            bail!("Dirty page logging is not supported by vhost backend");
+        }
+        Ok(())
+    }
 }
 
 pub struct VhostBackend {
@@ -415,89 +419,3 @@ impl VhostOps for VhostBackend {
         Ok(())
     }
 }
-
-pub struct VhostIoHandler {
-    interrupt_cb: Arc<VirtioInterrupt>,
-    host_notifies: Vec<VhostNotify>,
-    deactivate_evt: RawFd,
-}
-
-impl VhostIoHandler {
-    fn deactivate_evt_handler(&mut self) -> Vec<EventNotifier> {
-        let mut notifiers = Vec::new();
-        for host_notify in self.host_notifies.iter() {
-            notifiers.push(EventNotifier::new(
-                NotifierOperation::Delete,
-                host_notify.notify_evt.as_raw_fd(),
-                None,
-                EventSet::IN,
-                Vec::new(),
-            ));
-        }
-
-        notifiers.push(EventNotifier::new(
-            NotifierOperation::Delete,
-            self.deactivate_evt,
-            None,
-            EventSet::IN,
-            Vec::new(),
-        ));
-
-        notifiers
-    }
-}
-
-impl EventNotifierHelper for VhostIoHandler {
-    fn internal_notifiers(vhost_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
-        let mut notifiers = Vec::new();
-        let vhost = vhost_handler.clone();
-
-        let handler: Box<dyn Fn(EventSet, RawFd) -> Option<Vec<EventNotifier>>> =
-            Box::new(move |_, fd: RawFd| {
-                read_fd(fd);
-
-                let locked_vhost_handler = vhost.lock().unwrap();
-
-                for host_notify in locked_vhost_handler.host_notifies.iter() {
-                    if let Err(e) = (locked_vhost_handler.interrupt_cb)(
-                        &VirtioInterruptType::Vring,
-                        Some(&host_notify.queue.lock().unwrap()),
-                    ) {
-                        error!(
-                            "Failed to trigger interrupt for vhost device, error is {}",
-                            e.display_chain()
-                        );
-                    }
-                }
-
-                None as Option<Vec<EventNotifier>>
-            });
-        let h = Arc::new(Mutex::new(handler));
-
-        for host_notify in vhost_handler.lock().unwrap().host_notifies.iter() {
-            notifiers.push(EventNotifier::new(
-                NotifierOperation::AddShared,
-                host_notify.notify_evt.as_raw_fd(),
-                None,
-                EventSet::IN,
-                vec![h.clone()],
-            ));
-        }
-
-        // Register event notifier for deactivate_evt.
-        let vhost = vhost_handler.clone();
-        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
-            read_fd(fd);
-            Some(vhost.lock().unwrap().deactivate_evt_handler())
-        });
-        notifiers.push(EventNotifier::new(
-            NotifierOperation::AddShared,
-            vhost_handler.lock().unwrap().deactivate_evt,
-            None,
-            EventSet::IN,
-            vec![Arc::new(Mutex::new(handler))],
-        ));
-
-        notifiers
-    }
-}
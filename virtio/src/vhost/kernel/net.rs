@@ -14,10 +14,15 @@ use std::cmp;
 use std::fs::File;
 use std::io::Write;
 use std::os::unix::io::AsRawFd;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 
 use address_space::AddressSpace;
-use machine_manager::{config::NetworkInterfaceConfig, event_loop::EventLoop};
+use machine_manager::{
+    config::NetworkInterfaceConfig,
+    event_loop::EventLoop,
+    qmp::qmp_schema::{NetdevStats, NetdevStatsInfo},
+};
+use once_cell::sync::Lazy;
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
 use util::num_ops::{read_u32, write_u32};
@@ -32,8 +37,8 @@ use super::super::super::{
     VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO,
     VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_TYPE_NET,
 };
-use super::super::{VhostNotify, VhostOps};
-use super::{VhostBackend, VhostIoHandler, VhostVringFile, VHOST_NET_SET_BACKEND};
+use super::super::{VhostIoHandler, VhostNotify, VhostOps};
+use super::{VhostBackend, VhostVringFile, VHOST_NET_SET_BACKEND};
 
 /// Number of virtqueues.
 const QUEUE_NUM_NET: usize = 2;
@@ -41,6 +46,12 @@ const QUEUE_NUM_NET: usize = 2;
 const QUEUE_SIZE_NET: u16 = 256;
 /// Feature for vhost-net to add virtio_net_hdr for RX, and strip for TX packets.
 const VHOST_NET_F_VIRTIO_NET_HDR: u32 = 27;
+/// Directory of the network interfaces on host, the packet statistics of tap
+/// device are in `<ifname>/statistics`.
+const SYS_CLASS_NET_PATH: &str = "/sys/class/net";
+
+/// Registered vhost-kernel net devices, whose packet statistics can be queried.
+static NET_DEVICES: Lazy<Mutex<Vec<Weak<Mutex<Net>>>>> = Lazy::new(|| Mutex::new(Vec::new()));
 
 trait VhostNetBackend {
     /// Attach virtio net ring to a raw socket, or tap device.
@@ -90,6 +101,10 @@ pub struct Net {
     mem_space: Arc<AddressSpace>,
     /// EventFd for device deactivate.
     deactivate_evt: EventFd,
+    /// Name of the tap device on host.
+    ifname: String,
+    /// Statistics of the tap device when the statistics are reset.
+    stats_base: NetdevStats,
 }
 
 impl Net {
@@ -104,8 +119,96 @@ impl Net {
             device_config: VirtioNetConfig::default(),
             mem_space: mem_space.clone(),
             deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            ifname: String::new(),
+            stats_base: NetdevStats::default(),
+        }
+    }
+
+    /// Read the packet statistics of the tap device, from the view of the guest.
+    fn tap_stats(&self) -> Result<NetdevStats> {
+        let read = |name: &str| -> Result<u64> {
+            let path = format!("{}/{}/statistics/{}", SYS_CLASS_NET_PATH, self.ifname, name);
+            let value =
+                std::fs::read_to_string(&path).chain_err(|| format!("Failed to read {}", path))?;
+            value
+                .trim()
+                .parse::<u64>()
+                .chain_err(|| format!("Invalid statistics {} in {}", value.trim(), path))
+        };
+
+        // Packets transmitted by tap device are received by the guest.
+        Ok(NetdevStats {
+            rx_packets: read("tx_packets")?,
+            rx_bytes: read("tx_bytes")?,
+            rx_dropped: read("tx_dropped")?,
+            tx_packets: read("rx_packets")?,
+            tx_bytes: read("rx_bytes")?,
+            tx_dropped: read("rx_dropped")?,
+            tx_errors: read("rx_errors")?,
+            ..Default::default()
+        })
+    }
+
+    /// Get the packet statistics since they are reset, and reset them if `reset` is true.
+    fn stats(&mut self, reset: bool) -> Result<NetdevStats> {
+        let current = self.tap_stats()?;
+        let base = &self.stats_base;
+        let stats = NetdevStats {
+            rx_packets: current.rx_packets.saturating_sub(base.rx_packets),
+            rx_bytes: current.rx_bytes.saturating_sub(base.rx_bytes),
+            rx_dropped: current.rx_dropped.saturating_sub(base.rx_dropped),
+            tx_packets: current.tx_packets.saturating_sub(base.tx_packets),
+            tx_bytes: current.tx_bytes.saturating_sub(base.tx_bytes),
+            tx_dropped: current.tx_dropped.saturating_sub(base.tx_dropped),
+            tx_errors: current.tx_errors.saturating_sub(base.tx_errors),
+            ..Default::default()
+        };
+        if reset {
+            self.stats_base = current;
+        }
+        Ok(stats)
+    }
+}
+
+/// Register the vhost-kernel net device, so that its packet statistics can be
+/// queried by `query-netdev-stats`.
+pub fn register_net_device(net: &Arc<Mutex<Net>>) {
+    NET_DEVICES.lock().unwrap().push(Arc::downgrade(net));
+}
+
+/// Get the packet statistics of the registered vhost-kernel net devices.
+///
+/// # Arguments
+///
+/// * `name` - The id of the net device, all net devices are queried if it is `None`.
+/// * `reset` - Reset the statistics after they are read.
+pub(crate) fn query_net_stats(name: Option<&str>, reset: bool) -> Vec<NetdevStatsInfo> {
+    let mut infos = Vec::new();
+    let mut net_devices = NET_DEVICES.lock().unwrap();
+    net_devices.retain(|net| net.strong_count() > 0);
+    for net in net_devices.iter().filter_map(|net| net.upgrade()) {
+        let mut locked_net = net.lock().unwrap();
+        if locked_net.ifname.is_empty() {
+            continue;
+        }
+        if let Some(name) = name {
+            if locked_net.net_cfg.id != name {
+                continue;
+            }
+        }
+        match locked_net.stats(reset) {
+            Ok(stats) => infos.push(NetdevStatsInfo {
+                id: locked_net.net_cfg.id.clone(),
+                stats,
+            }),
+            Err(ref e) => error!(
+                "Failed to get statistics of net device {}, {}",
+                locked_net.net_cfg.id,
+                error_chain::ChainedError::display_chain(e)
+            ),
         }
     }
+    infos
 }
 
 impl VirtioDevice for Net {
@@ -141,8 +244,17 @@ impl VirtioDevice for Net {
             _ => Some(self.net_cfg.host_dev_name.as_str()),
         };
 
-        self.tap = create_tap(self.net_cfg.tap_fd, host_dev_name)
+        let taps = create_tap(self.net_cfg.tap_fds.as_deref(), host_dev_name, 1)
             .chain_err(|| "Failed to create tap for vhost net")?;
+        self.tap = taps.map(|mut taps| taps.remove(0));
+        // The statistics are not reported if the name of tap is unknown.
+        self.ifname = self
+            .tap
+            .as_ref()
+            .and_then(|tap| tap.get_ifname().ok())
+            .unwrap_or_default();
+        // The statistics of tap device are counted since the device is realized.
+        self.stats_base = self.tap_stats().unwrap_or_default();
         self.backend = Some(backend);
         self.device_features = device_features;
         self.vhost_features = vhost_features;
@@ -379,9 +491,14 @@ mod tests {
             host_dev_name: "tap1".to_string(),
             mac: Some("1F:2C:3E:4A:5B:6D".to_string()),
             vhost_type: Some("vhost-kernel".to_string()),
-            tap_fd: Some(4),
+            tap_fds: Some(vec![4]),
             vhost_fd: Some(5),
             iothread: None,
+            queue_iothreads: None,
+            queues: 1,
+            mq: false,
+            socket_path: None,
+            rate_limit: Default::default(),
         };
         let conf = vec![net1];
         let confs = Some(conf);
@@ -397,9 +514,14 @@ mod tests {
             host_dev_name: "tap0".to_string(),
             mac: Some("1A:2B:3C:4D:5E:6F".to_string()),
             vhost_type: Some("vhost-kernel".to_string()),
-            tap_fd: None,
+            tap_fds: None,
             vhost_fd: None,
             iothread: None,
+            queue_iothreads: None,
+            queues: 1,
+            mq: false,
+            socket_path: None,
+            rate_limit: Default::default(),
         };
         let conf = vec![net1];
         let confs = Some(conf);
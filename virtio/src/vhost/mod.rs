@@ -11,13 +11,20 @@
 // See the Mulan PSL v2 for more details.
 
 pub mod kernel;
+pub mod user;
 
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::sync::{Arc, Mutex};
 
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
 
 use super::errors::Result;
-use super::{Queue, QueueConfig};
+use super::{Queue, QueueConfig, VirtioInterrupt, VirtioInterruptType};
+use crate::error_chain::ChainedError;
 
 /// Vhost vring call notify structure.
 pub struct VhostNotify {
@@ -90,3 +97,90 @@ pub trait VhostOps {
     /// * `fd` - EventFd that will be signaled from guest.
     fn set_vring_kick(&self, queue_idx: usize, fd: &EventFd) -> Result<()>;
 }
+
+pub struct VhostIoHandler {
+    interrupt_cb: Arc<VirtioInterrupt>,
+    host_notifies: Vec<VhostNotify>,
+    deactivate_evt: RawFd,
+}
+
+impl VhostIoHandler {
+    fn deactivate_evt_handler(&mut self) -> Vec<EventNotifier> {
+        let mut notifiers = Vec::new();
+        for host_notify in self.host_notifies.iter() {
+            notifiers.push(EventNotifier::new(
+                NotifierOperation::Delete,
+                host_notify.notify_evt.as_raw_fd(),
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ));
+        }
+
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::Delete,
+            self.deactivate_evt,
+            None,
+            EventSet::IN,
+            Vec::new(),
+        ));
+
+        notifiers
+    }
+}
+
+impl EventNotifierHelper for VhostIoHandler {
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn internal_notifiers(vhost_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let mut notifiers = Vec::new();
+        let vhost = vhost_handler.clone();
+
+        let handler: Box<dyn Fn(EventSet, RawFd) -> Option<Vec<EventNotifier>>> =
+            Box::new(move |_, fd: RawFd| {
+                read_fd(fd);
+
+                let locked_vhost_handler = vhost.lock().unwrap();
+
+                for host_notify in locked_vhost_handler.host_notifies.iter() {
+                    if let Err(e) = (locked_vhost_handler.interrupt_cb)(
+                        &VirtioInterruptType::Vring,
+                        Some(&host_notify.queue.lock().unwrap()),
+                    ) {
+                        error!(
+                            "Failed to trigger interrupt for vhost device, error is {}",
+                            e.display_chain()
+                        );
+                    }
+                }
+
+                None as Option<Vec<EventNotifier>>
+            });
+        let h = Arc::new(Mutex::new(handler));
+
+        for host_notify in vhost_handler.lock().unwrap().host_notifies.iter() {
+            notifiers.push(EventNotifier::new(
+                NotifierOperation::AddShared,
+                host_notify.notify_evt.as_raw_fd(),
+                None,
+                EventSet::IN,
+                vec![h.clone()],
+            ));
+        }
+
+        // Register event notifier for deactivate_evt.
+        let vhost = vhost_handler.clone();
+        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            Some(vhost.lock().unwrap().deactivate_evt_handler())
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            vhost_handler.lock().unwrap().deactivate_evt,
+            None,
+            EventSet::IN,
+            vec![Arc::new(Mutex::new(handler))],
+        ));
+
+        notifiers
+    }
+}
@@ -0,0 +1,708 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+
+use address_space::AddressSpace;
+use machine_manager::{config::VhostUserBlkDevConfig, event_loop::EventLoop};
+use util::loop_context::EventNotifierHelper;
+use util::num_ops::{read_u32, write_u32};
+use vmm_sys_util::eventfd::EventFd;
+
+use super::super::super::errors::{ErrorKind, Result, ResultExt};
+use super::super::super::{
+    Queue, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VIRTIO_BLK_F_BLK_SIZE,
+    VIRTIO_BLK_F_CONFIG_WCE, VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_MQ,
+    VIRTIO_BLK_F_RO, VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_SIZE_MAX, VIRTIO_BLK_F_TOPOLOGY,
+    VIRTIO_BLK_F_WRITE_ZEROES, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
+    VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
+};
+use super::super::{VhostIoHandler, VhostNotify, VhostOps};
+use super::client::ConfigChangeCallback;
+use super::message::{
+    VHOST_USER_F_PROTOCOL_FEATURES, VHOST_USER_PROTOCOL_F_CONFIG, VHOST_USER_PROTOCOL_F_MQ,
+    VHOST_USER_PROTOCOL_F_SLAVE_REQ,
+};
+use super::VhostUserClient;
+
+/// Size of each virtqueue.
+const QUEUE_SIZE_BLK: u16 = 256;
+/// Size of the config space of virtio block device.
+const VIRTIO_BLK_CONFIG_SIZE: usize = 60;
+/// Offset of `num_queues` in the config space of virtio block device.
+const VIRTIO_BLK_CONFIG_NUM_QUEUES: usize = 34;
+
+/// Block device structure, whose IO is handled by the vhost-user slave.
+pub struct Block {
+    /// Configuration of the vhost user block device.
+    blk_cfg: VhostUserBlkDevConfig,
+    /// Bit mask of features supported by the backend.
+    device_features: u64,
+    /// Bit mask of features negotiated by the backend and the frontend.
+    driver_features: u64,
+    /// Bit mask of features supported by the vhost-user slave.
+    vhost_features: u64,
+    /// Config space of the block device, which is got from the vhost-user slave when
+    /// the device is realized and when the slave notifies that it is changed.
+    config_space: Arc<Mutex<Vec<u8>>>,
+    /// System address space.
+    mem_space: Arc<AddressSpace>,
+    /// Vhost user client.
+    client: Option<Arc<Mutex<VhostUserClient>>>,
+    /// EventFd for device deactivate.
+    deactivate_evt: EventFd,
+    /// The interrupt callback to notify the driver of config change, which is set
+    /// when the device is activated.
+    interrupt_cb: Arc<Mutex<Option<Arc<VirtioInterrupt>>>>,
+}
+
+impl Block {
+    pub fn new(cfg: &VhostUserBlkDevConfig, mem_space: &Arc<AddressSpace>) -> Self {
+        Block {
+            blk_cfg: cfg.clone(),
+            device_features: 0_u64,
+            driver_features: 0_u64,
+            vhost_features: 0_u64,
+            config_space: Arc::new(Mutex::new(vec![0_u8; VIRTIO_BLK_CONFIG_SIZE])),
+            mem_space: mem_space.clone(),
+            client: None,
+            deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            interrupt_cb: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Get the config space from the vhost-user slave, and build the callback which
+    /// updates it when the slave notifies that it is changed.
+    fn init_config_space(&self, client: &mut VhostUserClient) -> Result<()> {
+        let device_features = self.device_features;
+        let num_queues = self.blk_cfg.num_queues;
+        *self.config_space.lock().unwrap() = get_config_space(client, device_features, num_queues)?;
+
+        let config_space = self.config_space.clone();
+        let interrupt_cb = self.interrupt_cb.clone();
+        let cb: ConfigChangeCallback = Box::new(move |client| {
+            *config_space.lock().unwrap() = get_config_space(client, device_features, num_queues)?;
+            if let Some(interrupt_cb) = interrupt_cb.lock().unwrap().as_ref() {
+                interrupt_cb(&VirtioInterruptType::Config, None).chain_err(|| {
+                    ErrorKind::InterruptTrigger("block", VirtioInterruptType::Config)
+                })?;
+            }
+            Ok(())
+        });
+        client.set_config_change_cb(cb);
+        Ok(())
+    }
+
+    /// Negotiate features with the vhost-user slave, and check whether the
+    /// configuration of the device is supported by it.
+    fn negotiate_features(&mut self, client: &mut VhostUserClient) -> Result<()> {
+        client
+            .set_owner()
+            .chain_err(|| "Failed to set owner for vhost user blk")?;
+        client
+            .negotiate_protocol_features(
+                1 << VHOST_USER_PROTOCOL_F_CONFIG
+                    | 1 << VHOST_USER_PROTOCOL_F_MQ
+                    | 1 << VHOST_USER_PROTOCOL_F_SLAVE_REQ,
+            )
+            .chain_err(|| "Failed to negotiate protocol features for vhost user blk")?;
+        if client.protocol_features & (1 << VHOST_USER_PROTOCOL_F_CONFIG) == 0 {
+            bail!("Config space access is not supported by the vhost user blk slave");
+        }
+        client
+            .set_slave_req_fd()
+            .chain_err(|| "Failed to set up slave channel for vhost user blk")?;
+        let vhost_features = client
+            .get_features()
+            .chain_err(|| "Failed to get features for vhost user blk")?;
+
+        let supported_features = 1 << VIRTIO_F_VERSION_1
+            | 1 << VIRTIO_F_RING_INDIRECT_DESC
+            | 1 << VIRTIO_F_RING_EVENT_IDX
+            | 1 << VIRTIO_BLK_F_SIZE_MAX
+            | 1 << VIRTIO_BLK_F_SEG_MAX
+            | 1 << VIRTIO_BLK_F_RO
+            | 1 << VIRTIO_BLK_F_BLK_SIZE
+            | 1 << VIRTIO_BLK_F_FLUSH
+            | 1 << VIRTIO_BLK_F_TOPOLOGY
+            | 1 << VIRTIO_BLK_F_CONFIG_WCE
+            | 1 << VIRTIO_BLK_F_DISCARD
+            | 1 << VIRTIO_BLK_F_WRITE_ZEROES;
+        self.device_features = vhost_features & supported_features;
+
+        let num_queues = self.blk_cfg.num_queues;
+        if num_queues > 1 {
+            if client.protocol_features & (1 << VHOST_USER_PROTOCOL_F_MQ) == 0
+                || vhost_features & (1 << VIRTIO_BLK_F_MQ) == 0
+            {
+                bail!("Multiqueue is not supported by the vhost user blk slave");
+            }
+            let max_queues = client
+                .get_queue_num()
+                .chain_err(|| "Failed to get queue num for vhost user blk")?;
+            if u64::from(num_queues) > max_queues {
+                bail!(
+                    "The vhost user blk slave supports at most {} queues, but {} are configured",
+                    max_queues,
+                    num_queues
+                );
+            }
+            self.device_features |= 1 << VIRTIO_BLK_F_MQ;
+        }
+        self.vhost_features = vhost_features;
+
+        Ok(())
+    }
+}
+
+impl VirtioDevice for Block {
+    /// Realize vhost user block device.
+    fn realize(&mut self) -> Result<()> {
+        let mut client = VhostUserClient::new(&self.mem_space, &self.blk_cfg.socket_path)
+            .chain_err(|| "Failed to create the client which communicates with the slave")?;
+        if let Err(e) = self.negotiate_features(&mut client).and_then(|_| {
+            self.init_config_space(&mut client)
+                .chain_err(|| "Failed to get config space for vhost user blk")
+        }) {
+            client.unregister_listener()?;
+            return Err(e);
+        }
+        self.client = Some(Arc::new(Mutex::new(client)));
+
+        let client = self.client.as_ref().unwrap().clone();
+        EventLoop::update_event(EventNotifierHelper::internal_notifiers(client), None)
+            .chain_err(|| "Failed to update event for vhost user blk")?;
+
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        if let Some(client) = self.client.take() {
+            let locked_client = client.lock().unwrap();
+            locked_client
+                .delete_event()
+                .chain_err(|| "Failed to delete vhost user blk event")?;
+            locked_client.unregister_listener()?;
+        }
+        Ok(())
+    }
+
+    /// Get the virtio device type, refer to Virtio Spec.
+    fn device_type(&self) -> u32 {
+        VIRTIO_TYPE_BLOCK
+    }
+
+    /// Get the count of virtio device queues.
+    fn queue_num(&self) -> usize {
+        self.blk_cfg.num_queues as usize
+    }
+
+    /// Get the queue size of virtio device.
+    fn queue_size(&self) -> u16 {
+        QUEUE_SIZE_BLK
+    }
+
+    /// Get device features from host.
+    fn get_device_features(&self, features_select: u32) -> u32 {
+        read_u32(self.device_features, features_select)
+    }
+
+    /// Set driver features by guest.
+    fn set_driver_features(&mut self, page: u32, value: u32) {
+        let mut features = write_u32(value, page);
+        let unsupported_features = features & !self.device_features;
+        if unsupported_features != 0 {
+            warn!(
+                "Received acknowledge request with unsupported feature for vhost user blk: 0x{:x}",
+                features
+            );
+            features &= !unsupported_features;
+        }
+        self.driver_features |= features;
+    }
+
+    /// Read data of config from guest.
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) -> Result<()> {
+        let config_space = self.config_space.lock().unwrap();
+        let config_len = config_space.len() as u64;
+        if offset >= config_len {
+            return Err(ErrorKind::DevConfigOverflow(offset, config_len).into());
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            data.write_all(&config_space[offset as usize..cmp::min(end, config_len) as usize])?;
+        }
+
+        Ok(())
+    }
+
+    /// Write data to config from guest.
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let data_len = data.len();
+        let mut config_space = self.config_space.lock().unwrap();
+        let config_len = config_space.len();
+        if offset as usize + data_len > config_len {
+            return Err(ErrorKind::DevConfigOverflow(offset, config_len as u64).into());
+        }
+
+        if let Some(client) = &self.client {
+            client
+                .lock()
+                .unwrap()
+                .set_config(offset as u32, data)
+                .chain_err(|| "Failed to set config space for vhost user blk")?;
+        }
+        config_space[(offset as usize)..(offset as usize + data_len)].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    /// Activate the virtio device, this function is called by vcpu thread when frontend
+    /// virtio driver is ready and write `DRIVER_OK` to backend.
+    fn activate(
+        &mut self,
+        _mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queues: &[Arc<Mutex<Queue>>],
+        queue_evts: Vec<EventFd>,
+    ) -> Result<()> {
+        let client = match &self.client {
+            None => return Err("Failed to get client for vhost user blk".into()),
+            Some(client_) => client_,
+        };
+
+        let mut host_notifies = Vec::new();
+        let mut call_evts = Vec::new();
+        for queue in queues.iter() {
+            let host_notify = VhostNotify {
+                notify_evt: EventFd::new(libc::EFD_NONBLOCK)
+                    .chain_err(|| ErrorKind::EventFdCreate)?,
+                queue: queue.clone(),
+            };
+            call_evts.push(
+                host_notify
+                    .notify_evt
+                    .try_clone()
+                    .chain_err(|| "Failed to clone eventfd for vhost user blk")?,
+            );
+            host_notifies.push(host_notify);
+        }
+
+        let mut locked_client = client.lock().unwrap();
+        locked_client.features =
+            self.driver_features | (self.vhost_features & 1 << VHOST_USER_F_PROTOCOL_FEATURES);
+        locked_client.set_queues(queues);
+        locked_client.set_queue_evts(&queue_evts)?;
+        locked_client.set_call_evts(&call_evts)?;
+        locked_client
+            .activate_vhost_vring()
+            .chain_err(|| "Failed to activate vrings for vhost user blk")?;
+        drop(locked_client);
+        *self.interrupt_cb.lock().unwrap() = Some(interrupt_cb.clone());
+
+        let handler = VhostIoHandler {
+            interrupt_cb,
+            host_notifies,
+            deactivate_evt: self.deactivate_evt.as_raw_fd(),
+        };
+
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler))),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        if let Some(client) = &self.client {
+            client
+                .lock()
+                .unwrap()
+                .reset_vhost_user()
+                .chain_err(|| "Failed to reset vhost user blk")?;
+        }
+        *self.interrupt_cb.lock().unwrap() = None;
+        self.deactivate_evt
+            .write(1)
+            .chain_err(|| ErrorKind::EventFdWrite)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.driver_features = 0_u64;
+
+        Ok(())
+    }
+}
+
+/// Get the config space from the vhost-user slave.
+fn get_config_space(
+    client: &VhostUserClient,
+    device_features: u64,
+    num_queues: u16,
+) -> Result<Vec<u8>> {
+    let mut config_space = client.get_config(0, VIRTIO_BLK_CONFIG_SIZE as u32)?;
+    // Only the configured queues are reported to the driver.
+    if device_features & (1 << VIRTIO_BLK_F_MQ) != 0 {
+        config_space[VIRTIO_BLK_CONFIG_NUM_QUEUES..VIRTIO_BLK_CONFIG_NUM_QUEUES + 2]
+            .copy_from_slice(&num_queues.to_le_bytes());
+    }
+    Ok(config_space)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::{Duration, Instant};
+
+    use address_space::{create_host_mmaps, GuestAddress, Region};
+    use machine_manager::config::MachineMemConfig;
+
+    use super::super::test_backend::{TestBlkBackend, TEST_BLK_ID, TEST_BLK_SECTORS};
+    use super::*;
+    use crate::{
+        QueueConfig, QUEUE_TYPE_SPLIT_VRING, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
+    };
+
+    const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
+    const VIRTQ_DESC_F_NEXT: u16 = 0x01;
+    const VIRTQ_DESC_F_WRITE: u16 = 0x02;
+
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36);
+        let sys_space = AddressSpace::new(root).unwrap();
+        let mem_config = MachineMemConfig {
+            mem_size: SYSTEM_SPACE_SIZE,
+            mem_share: true,
+            ..Default::default()
+        };
+        let host_mmap = create_host_mmaps(&[(0, SYSTEM_SPACE_SIZE)], &mem_config, 1)
+            .unwrap()
+            .remove(0);
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone()),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    /// Create a device connected with the slave, without registering events to the main loop.
+    fn connect_block(path: &str, num_queues: u16, sys_space: &Arc<AddressSpace>) -> Result<Block> {
+        let blk_cfg = VhostUserBlkDevConfig {
+            id: "blk0".to_string(),
+            socket_path: path.to_string(),
+            num_queues,
+        };
+        let mut block = Block::new(&blk_cfg, sys_space);
+        let mut client = VhostUserClient::new(sys_space, path)?;
+        block.negotiate_features(&mut client)?;
+        block.init_config_space(&mut client)?;
+        block.client = Some(Arc::new(Mutex::new(client)));
+        Ok(block)
+    }
+
+    #[test]
+    fn test_vhost_user_blk_config() {
+        let path = format!("/tmp/test_vhost_user_blk_cfg_{}.sock", std::process::id());
+        let features = 1 << VIRTIO_F_VERSION_1
+            | 1 << VIRTIO_BLK_F_FLUSH
+            | 1 << VIRTIO_BLK_F_BLK_SIZE
+            | 1 << VIRTIO_BLK_F_CONFIG_WCE
+            | 1 << VIRTIO_BLK_F_MQ;
+        let backend = TestBlkBackend::new(&path, features, 4);
+        let sys_space = address_space_init();
+
+        let mut block = connect_block(&path, 2, &sys_space).unwrap();
+        assert_eq!(block.queue_num(), 2);
+        assert_eq!(block.device_features, features);
+        assert_eq!(
+            backend.state.lock().unwrap().acked_protocol_features,
+            1 << VHOST_USER_PROTOCOL_F_CONFIG
+                | 1 << VHOST_USER_PROTOCOL_F_MQ
+                | 1 << VHOST_USER_PROTOCOL_F_SLAVE_REQ
+        );
+
+        // Capacity and block size are reported by the slave, and the number of queues
+        // is the configured one.
+        let mut capacity = [0_u8; 8];
+        block.read_config(0, &mut capacity).unwrap();
+        assert_eq!(u64::from_le_bytes(capacity), TEST_BLK_SECTORS);
+        let mut blk_size = [0_u8; 4];
+        block.read_config(20, &mut blk_size).unwrap();
+        assert_eq!(u32::from_le_bytes(blk_size), 512);
+        let mut num_queues = [0_u8; 2];
+        block.read_config(34, &mut num_queues).unwrap();
+        assert_eq!(u16::from_le_bytes(num_queues), 2);
+        assert!(block
+            .read_config(VIRTIO_BLK_CONFIG_SIZE as u64, &mut num_queues)
+            .is_err());
+
+        // The cached config space is used until the slave notifies that it is changed,
+        // e.g. the disk is resized, and then the driver is notified.
+        backend.state.lock().unwrap().config[0] = 32;
+        block.read_config(0, &mut capacity).unwrap();
+        assert_eq!(u64::from_le_bytes(capacity), TEST_BLK_SECTORS);
+        let config_irqs = Arc::new(AtomicU32::new(0));
+        let irqs = config_irqs.clone();
+        *block.interrupt_cb.lock().unwrap() = Some(Arc::new(Box::new(
+            move |int_type: &VirtioInterruptType, _: Option<&Queue>| {
+                if let VirtioInterruptType::Config = int_type {
+                    irqs.fetch_add(1, Ordering::SeqCst);
+                }
+                Ok(())
+            },
+        )));
+        backend.resize(64);
+        let client = block.client.as_ref().unwrap().clone();
+        client.lock().unwrap().handle_slave_msg().unwrap();
+        block.read_config(0, &mut capacity).unwrap();
+        assert_eq!(u64::from_le_bytes(capacity), 64);
+        assert_eq!(config_irqs.load(Ordering::SeqCst), 1);
+        *block.interrupt_cb.lock().unwrap() = None;
+
+        // Writeback is forwarded to the slave.
+        block.write_config(32, &[1]).unwrap();
+        assert_eq!(backend.state.lock().unwrap().config[32], 1);
+        assert!(block
+            .write_config(VIRTIO_BLK_CONFIG_SIZE as u64, &[1])
+            .is_err());
+
+        // The slave disconnects, the cached config space is used.
+        drop(backend);
+        block.read_config(0, &mut capacity).unwrap();
+        assert_eq!(u64::from_le_bytes(capacity), 64);
+    }
+
+    #[test]
+    fn test_vhost_user_blk_queues() {
+        let path = format!("/tmp/test_vhost_user_blk_mq_{}.sock", std::process::id());
+        let sys_space = address_space_init();
+
+        // Too many queues.
+        let features = 1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_BLK_F_MQ;
+        let backend = TestBlkBackend::new(&path, features, 2);
+        assert!(connect_block(&path, 4, &sys_space).is_err());
+        drop(backend);
+
+        // Multiqueue is not supported by the slave.
+        let backend = TestBlkBackend::new(&path, 1 << VIRTIO_F_VERSION_1, 2);
+        assert!(connect_block(&path, 2, &sys_space).is_err());
+        let block = connect_block(&path, 1, &sys_space).unwrap();
+        assert_eq!(block.device_features, 1 << VIRTIO_F_VERSION_1);
+        drop(backend);
+
+        // The slave is not listening.
+        assert!(connect_block(&path, 1, &sys_space).is_err());
+    }
+
+    #[test]
+    fn test_vhost_user_blk_features() {
+        let blk_cfg = VhostUserBlkDevConfig::default();
+        let sys_space = address_space_init();
+        let mut block = Block::new(&blk_cfg, &sys_space);
+        assert!(block.realize().is_err());
+        assert!(block
+            .activate(
+                sys_space.clone(),
+                Arc::new(Box::new(|_, _| Ok(()))),
+                &[],
+                Vec::new()
+            )
+            .is_err());
+
+        block.device_features = 1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_BLK_F_FLUSH;
+        block.set_driver_features(0, 0xffff_ffff);
+        assert_eq!(block.driver_features, 1 << VIRTIO_BLK_F_FLUSH);
+        block.set_driver_features(1, 0xffff_ffff);
+        assert_eq!(
+            block.driver_features,
+            1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_BLK_F_FLUSH
+        );
+        assert!(block.reset().is_ok());
+        assert_eq!(block.driver_features, 0);
+    }
+
+    fn write_desc(
+        sys_space: &Arc<AddressSpace>,
+        desc_table: u64,
+        index: u64,
+        desc: (u64, u32, u16, u16),
+    ) {
+        let base = desc_table + 16 * index;
+        sys_space
+            .write_object::<u64>(&desc.0, GuestAddress(base))
+            .unwrap();
+        sys_space
+            .write_object::<u32>(&desc.1, GuestAddress(base + 8))
+            .unwrap();
+        sys_space
+            .write_object::<u16>(&desc.2, GuestAddress(base + 12))
+            .unwrap();
+        sys_space
+            .write_object::<u16>(&desc.3, GuestAddress(base + 14))
+            .unwrap();
+    }
+
+    fn submit_request(
+        sys_space: &Arc<AddressSpace>,
+        queue_config: &QueueConfig,
+        kick_evt: &EventFd,
+        call_evt: &EventFd,
+        request: (u32, u64, u64, u16),
+    ) {
+        let (request_type, sector, data_addr, data_flags) = request;
+        let desc_table = queue_config.desc_table.raw_value();
+        sys_space
+            .write_object::<u32>(&request_type, GuestAddress(0x4000))
+            .unwrap();
+        sys_space
+            .write_object::<u64>(&sector, GuestAddress(0x4008))
+            .unwrap();
+        sys_space
+            .write_object::<u8>(&0xff, GuestAddress(0x6000))
+            .unwrap();
+        write_desc(sys_space, desc_table, 0, (0x4000, 16, VIRTQ_DESC_F_NEXT, 1));
+        write_desc(
+            sys_space,
+            desc_table,
+            1,
+            (data_addr, 512, VIRTQ_DESC_F_NEXT | data_flags, 2),
+        );
+        write_desc(sys_space, desc_table, 2, (0x6000, 1, VIRTQ_DESC_F_WRITE, 0));
+
+        let avail = queue_config.avail_ring;
+        let avail_idx = sys_space
+            .read_object::<u16>(avail.checked_add(2).unwrap())
+            .unwrap();
+        sys_space
+            .write_object::<u16>(
+                &0,
+                avail
+                    .checked_add(4 + 2 * u64::from(avail_idx % queue_config.size))
+                    .unwrap(),
+            )
+            .unwrap();
+        sys_space
+            .write_object::<u16>(&(avail_idx + 1), avail.checked_add(2).unwrap())
+            .unwrap();
+        kick_evt.write(1).unwrap();
+
+        let start = Instant::now();
+        while call_evt.read().is_err() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(
+            sys_space.read_object::<u8>(GuestAddress(0x6000)).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_vhost_user_blk_io() {
+        let path = format!("/tmp/test_vhost_user_blk_io_{}.sock", std::process::id());
+        let backend = TestBlkBackend::new(&path, 1 << VIRTIO_F_VERSION_1, 1);
+        let sys_space = address_space_init();
+        let block = connect_block(&path, 1, &sys_space).unwrap();
+
+        let mut queue_config = QueueConfig::new(16);
+        queue_config.desc_table = GuestAddress(0x1000);
+        queue_config.avail_ring = GuestAddress(0x2000);
+        queue_config.used_ring = GuestAddress(0x3000);
+        queue_config.ready = true;
+        let queue = Arc::new(Mutex::new(
+            Queue::new(queue_config, QUEUE_TYPE_SPLIT_VRING).unwrap(),
+        ));
+        let kick_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let call_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+
+        let mut locked_client = block.client.as_ref().unwrap().lock().unwrap();
+        locked_client.features = 1 << VIRTIO_F_VERSION_1 | 1 << VHOST_USER_F_PROTOCOL_FEATURES;
+        locked_client.set_queues(&[queue]);
+        locked_client
+            .set_queue_evts(&[kick_evt.try_clone().unwrap()])
+            .unwrap();
+        locked_client
+            .set_call_evts(&[call_evt.try_clone().unwrap()])
+            .unwrap();
+        locked_client.activate_vhost_vring().unwrap();
+        drop(locked_client);
+
+        // Write the second sector.
+        sys_space
+            .write(&mut [0xab_u8; 512].as_ref(), GuestAddress(0x5000), 512)
+            .unwrap();
+        submit_request(
+            &sys_space,
+            &queue_config,
+            &kick_evt,
+            &call_evt,
+            (VIRTIO_BLK_T_OUT, 1, 0x5000, 0),
+        );
+        assert!(backend.state.lock().unwrap().disk[512..1024]
+            .iter()
+            .all(|b| *b == 0xab));
+
+        // Read it back.
+        submit_request(
+            &sys_space,
+            &queue_config,
+            &kick_evt,
+            &call_evt,
+            (VIRTIO_BLK_T_IN, 1, 0x7000, VIRTQ_DESC_F_WRITE),
+        );
+        let mut data = [0_u8; 512];
+        sys_space
+            .read(&mut data.as_mut(), GuestAddress(0x7000), 512)
+            .unwrap();
+        assert!(data.iter().all(|b| *b == 0xab));
+        // The used length is the size of data and status.
+        assert_eq!(
+            sys_space
+                .read_object::<u32>(GuestAddress(0x3000 + 4 + 8 + 4))
+                .unwrap(),
+            513
+        );
+
+        // Get the serial number.
+        submit_request(
+            &sys_space,
+            &queue_config,
+            &kick_evt,
+            &call_evt,
+            (VIRTIO_BLK_T_GET_ID, 0, 0x7000, VIRTQ_DESC_F_WRITE),
+        );
+        let mut serial = vec![0_u8; TEST_BLK_ID.len()];
+        sys_space
+            .read(
+                &mut serial.as_mut_slice(),
+                GuestAddress(0x7000),
+                TEST_BLK_ID.len() as u64,
+            )
+            .unwrap();
+        assert_eq!(serial, TEST_BLK_ID);
+        assert_eq!(
+            sys_space.read_object::<u16>(GuestAddress(0x3002)).unwrap(),
+            3
+        );
+
+        let mut locked_client = block.client.as_ref().unwrap().lock().unwrap();
+        assert!(locked_client.reset_vhost_user().is_ok());
+    }
+}
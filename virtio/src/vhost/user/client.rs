@@ -0,0 +1,1122 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+
+use address_space::{
+    AddressSpace, FileBackend, FlatRange, GuestAddress, Listener, ListenerReqType, RegionIoEventFd,
+    RegionType,
+};
+use machine_manager::event_loop::EventLoop;
+use util::byte_code::ByteCode;
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use super::super::super::errors::{ErrorKind, Result, ResultExt};
+use super::super::super::{Queue, QueueConfig};
+use super::super::VhostOps;
+use super::message::{
+    VhostUserConfig, VhostUserMemHdr, VhostUserMemoryRegion, VhostUserMsgHdr, VhostUserMsgReq,
+    VhostUserU64, VhostUserVringAddr, VhostUserVringState, VHOST_USER_F_PROTOCOL_FEATURES,
+    VHOST_USER_MAX_REGIONS, VHOST_USER_NEED_REPLY_MASK, VHOST_USER_PROTOCOL_F_SLAVE_REQ,
+    VHOST_USER_SLAVE_CONFIG_CHANGE_MSG, VHOST_USER_VRING_IDX_MASK,
+};
+use super::sock::VhostUserSock;
+use crate::error_chain::ChainedError;
+
+/// Interval of retrying to connect to the vhost-user slave (1s).
+const RECONNECT_INTERVAL_NS: u64 = 1_000_000_000;
+
+/// Callback to handle the config change notified by the slave, the new config space
+/// can be got from the slave with the client.
+pub type ConfigChangeCallback = Box<dyn Fn(&VhostUserClient) -> Result<()> + Send + Sync>;
+
+#[derive(Clone)]
+struct RegionInfo {
+    region: VhostUserMemoryRegion,
+    file_back: FileBackend,
+}
+
+#[derive(Clone)]
+struct VhostUserMemInfo {
+    regions: Arc<Mutex<Vec<RegionInfo>>>,
+    enabled: bool,
+}
+
+impl VhostUserMemInfo {
+    fn new() -> Self {
+        VhostUserMemInfo {
+            regions: Arc::new(Mutex::new(Vec::new())),
+            enabled: false,
+        }
+    }
+
+    fn addr_to_host(&self, addr: GuestAddress) -> Option<u64> {
+        let addr = addr.raw_value();
+        for reg_info in self.regions.lock().unwrap().iter() {
+            let region = &reg_info.region;
+            if addr >= region.guest_phys_addr && addr < region.guest_phys_addr + region.memory_size
+            {
+                let offset = addr - region.guest_phys_addr;
+                return Some(region.userspace_addr + offset);
+            }
+        }
+        None
+    }
+
+    fn build_region_info(fr: &FlatRange) -> Result<RegionInfo> {
+        let file_back = fr.owner.get_file_backend().chain_err(|| {
+            "Guest memory of vhost-user device must be shared, use \'mem-share=on\'"
+        })?;
+        let region = VhostUserMemoryRegion {
+            guest_phys_addr: fr.addr_range.base.raw_value(),
+            memory_size: fr.addr_range.size,
+            userspace_addr: fr.owner.get_host_address().unwrap() + fr.offset_in_region,
+            mmap_offset: file_back.offset + fr.offset_in_region,
+        };
+        Ok(RegionInfo { region, file_back })
+    }
+
+    fn add_mem_range(&self, fr: &FlatRange) -> Result<()> {
+        let reg_info = Self::build_region_info(fr)?;
+        self.regions.lock().unwrap().push(reg_info);
+        Ok(())
+    }
+
+    fn delete_mem_range(&self, fr: &FlatRange) -> Result<()> {
+        let target = Self::build_region_info(fr)?.region;
+        let mut mem_regions = self.regions.lock().unwrap();
+        if let Some(index) = mem_regions.iter().position(|r| r.region == target) {
+            mem_regions.remove(index);
+        } else {
+            debug!("Vhost-user: deleting mem region failed: not matched");
+        }
+        Ok(())
+    }
+}
+
+impl Listener for VhostUserMemInfo {
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    fn handle_request(
+        &self,
+        range: Option<&FlatRange>,
+        _evtfd: Option<&RegionIoEventFd>,
+        req_type: ListenerReqType,
+    ) -> std::result::Result<(), address_space::errors::Error> {
+        match req_type {
+            ListenerReqType::AddRegion => {
+                let fr = range.unwrap();
+                if fr.owner.region_type() == RegionType::Ram {
+                    self.add_mem_range(fr)
+                        .map_err(|e| address_space::errors::Error::from(e.to_string()))?;
+                }
+            }
+            ListenerReqType::DeleteRegion => {
+                let fr = range.unwrap();
+                if fr.owner.region_type() == RegionType::Ram {
+                    self.delete_mem_range(fr)
+                        .map_err(|e| address_space::errors::Error::from(e.to_string()))?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn set_dirty_log(&self, enable: bool) -> std::result::Result<(), address_space::errors::Error> {
+        // The vhost-user backend writes guest memory without logging dirty pages.
+        if enable {
+            bail!("Dirty page logging is not supported by vhost-user backend");
+        }
+        Ok(())
+    }
+}
+
+/// Struct for communication with the vhost-user slave through unix domain socket.
+pub struct VhostUserClient {
+    /// The socket connected with the slave.
+    sock: VhostUserSock,
+    /// The slave channel, through which the slave sends requests to the master.
+    slave_sock: Option<VhostUserSock>,
+    /// Callback to handle the config change notified through the slave channel.
+    config_change_cb: Option<ConfigChangeCallback>,
+    /// Memory layout of the guest which is sent to the slave.
+    mem_info: VhostUserMemInfo,
+    /// The listener registered in `mem_space` to update `mem_info`.
+    mem_listener: Arc<Mutex<VhostUserMemInfo>>,
+    /// System address space.
+    mem_space: Arc<AddressSpace>,
+    /// EventFd used to retry connecting to the slave.
+    reconnect_evt: EventFd,
+    /// Virtio features acked by the driver, including vhost-user specific ones.
+    pub features: u64,
+    /// Vhost-user protocol features negotiated with the slave.
+    pub protocol_features: u64,
+    /// Virtio queues handled by the slave.
+    queues: Vec<Arc<Mutex<Queue>>>,
+    /// EventFds notified by the guest when available buffers are added.
+    queue_evts: Vec<EventFd>,
+    /// EventFds notified by the slave when buffers have been used.
+    call_evts: Vec<EventFd>,
+    /// Vring bases to set up the vrings from, which are zero for fresh vrings and
+    /// saved by `GET_VRING_BASE` when the vrings are stopped. They are unknown while
+    /// the vrings are running in the slave.
+    vring_bases: Vec<Option<u16>>,
+}
+
+impl VhostUserClient {
+    pub fn new(mem_space: &Arc<AddressSpace>, path: &str) -> Result<Self> {
+        let sock = VhostUserSock::connect(path)?;
+        let mem_info = VhostUserMemInfo::new();
+        let mem_listener = Arc::new(Mutex::new(mem_info.clone()));
+        mem_space
+            .register_listener(mem_listener.clone())
+            .chain_err(|| "Failed to register memory listener for vhost-user")?;
+
+        Ok(VhostUserClient {
+            sock,
+            slave_sock: None,
+            config_change_cb: None,
+            mem_info,
+            mem_listener,
+            mem_space: mem_space.clone(),
+            reconnect_evt: EventFd::new(libc::EFD_NONBLOCK)
+                .chain_err(|| ErrorKind::EventFdCreate)?,
+            features: 0,
+            protocol_features: 0,
+            queues: Vec::new(),
+            queue_evts: Vec::new(),
+            call_evts: Vec::new(),
+            vring_bases: Vec::new(),
+        })
+    }
+
+    /// Set the virtio queues handled by the slave.
+    pub fn set_queues(&mut self, queues: &[Arc<Mutex<Queue>>]) {
+        self.queues = queues.to_vec();
+        self.vring_bases = vec![Some(0); queues.len()];
+    }
+
+    /// Set the eventfds notified by the guest, which are passed to the slave as vring kicks.
+    pub fn set_queue_evts(&mut self, queue_evts: &[EventFd]) -> Result<()> {
+        self.queue_evts = clone_evts(queue_evts)?;
+        Ok(())
+    }
+
+    /// Set the eventfds notified by the slave, which are passed to the slave as vring calls.
+    pub fn set_call_evts(&mut self, call_evts: &[EventFd]) -> Result<()> {
+        self.call_evts = clone_evts(call_evts)?;
+        Ok(())
+    }
+
+    /// Negotiate the vhost-user protocol features with the slave.
+    ///
+    /// # Arguments
+    ///
+    /// * `supported` - Protocol features supported by the device.
+    pub fn negotiate_protocol_features(&mut self, supported: u64) -> Result<()> {
+        let features = self.get_features()?;
+        if features & (1 << VHOST_USER_F_PROTOCOL_FEATURES) == 0 {
+            self.protocol_features = 0;
+            return Ok(());
+        }
+        let protocol_features = self.get_protocol_features()? & supported;
+        self.set_protocol_features(protocol_features)?;
+        self.protocol_features = protocol_features;
+        Ok(())
+    }
+
+    /// Set the callback to handle the config change notified by the slave.
+    pub fn set_config_change_cb(&mut self, cb: ConfigChangeCallback) {
+        self.config_change_cb = Some(cb);
+    }
+
+    /// Set up the slave channel if it is supported by the slave, the protocol
+    /// features must have been negotiated.
+    pub fn set_slave_req_fd(&mut self) -> Result<()> {
+        if self.protocol_features & (1 << VHOST_USER_PROTOCOL_F_SLAVE_REQ) == 0 {
+            self.slave_sock = None;
+            return Ok(());
+        }
+
+        let (master, slave) =
+            UnixStream::pair().chain_err(|| "Failed to create vhost-user slave channel")?;
+        let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::SetSlaveReqFd, false, 0);
+        self.sock
+            .send_msg(&hdr, None::<&VhostUserU64>, &[], &[slave.as_raw_fd()])
+            .chain_err(|| "Failed to send msg for setting slave req fd")?;
+        self.slave_sock = Some(VhostUserSock::from_stream(&self.sock.path, master)?);
+        Ok(())
+    }
+
+    /// Handle a request sent by the slave through the slave channel.
+    pub fn handle_slave_msg(&self) -> Result<()> {
+        let slave_sock = match &self.slave_sock {
+            Some(sock) => sock,
+            None => return Ok(()),
+        };
+        let (hdr, _) = slave_sock.recv_msg()?;
+        let ret = match hdr.request {
+            VHOST_USER_SLAVE_CONFIG_CHANGE_MSG => match &self.config_change_cb {
+                Some(cb) => cb(self).chain_err(|| "Failed to handle config change"),
+                None => Ok(()),
+            },
+            _ => Err(format!("Unsupported request {} from vhost-user slave", hdr.request).into()),
+        };
+        if hdr.flags & VHOST_USER_NEED_REPLY_MASK != 0 {
+            // Zero means success, and non-zero means failure.
+            slave_sock.send_reply(&hdr, &VhostUserU64::new(ret.is_err() as u64))?;
+        }
+        ret
+    }
+
+    /// Send the features, memory table and vrings to the slave, and then enable the vrings.
+    /// The queues and eventfds must have been set.
+    pub fn activate_vhost_vring(&mut self) -> Result<()> {
+        if self.queues.len() != self.queue_evts.len() || self.queues.len() != self.call_evts.len() {
+            bail!(
+                "Mismatched vhost-user vrings: {} queues, {} kick evts, {} call evts",
+                self.queues.len(),
+                self.queue_evts.len(),
+                self.call_evts.len()
+            );
+        }
+
+        self.set_features(self.features)
+            .chain_err(|| "Failed to set features for vhost-user")?;
+        self.set_mem_table()
+            .chain_err(|| "Failed to set mem table for vhost-user")?;
+
+        for (queue_index, queue_mutex) in self.queues.iter().enumerate() {
+            let queue = queue_mutex.lock().unwrap();
+            let actual_size = queue.vring.actual_size();
+            let queue_config = queue.vring.get_queue_config();
+            drop(queue);
+
+            self.set_vring_num(queue_index, actual_size).chain_err(|| {
+                format!(
+                    "Failed to set vring num for vhost-user, index: {}, size: {}",
+                    queue_index, actual_size,
+                )
+            })?;
+            self.set_vring_addr(&queue_config, queue_index, 0)
+                .chain_err(|| {
+                    format!(
+                        "Failed to set vring addr for vhost-user, index: {}",
+                        queue_index,
+                    )
+                })?;
+            // The slave which is disconnected while the vring is running can't save the
+            // vring base, resume from the used index in this case.
+            let vring_base = match self.vring_bases[queue_index] {
+                Some(vring_base) => vring_base,
+                None => self.get_used_idx(&queue_config)?,
+            };
+            self.set_vring_base(queue_index, vring_base).chain_err(|| {
+                format!(
+                    "Failed to set vring base for vhost-user, index: {}",
+                    queue_index,
+                )
+            })?;
+            self.set_vring_call(queue_index, &self.call_evts[queue_index])
+                .chain_err(|| {
+                    format!(
+                        "Failed to set vring call for vhost-user, index: {}",
+                        queue_index,
+                    )
+                })?;
+            self.set_vring_kick(queue_index, &self.queue_evts[queue_index])
+                .chain_err(|| {
+                    format!(
+                        "Failed to set vring kick for vhost-user, index: {}",
+                        queue_index,
+                    )
+                })?;
+        }
+
+        if self.features & (1 << VHOST_USER_F_PROTOCOL_FEATURES) != 0 {
+            for queue_index in 0..self.queues.len() {
+                self.set_vring_enable(queue_index, true).chain_err(|| {
+                    format!(
+                        "Failed to enable vring for vhost-user, index: {}",
+                        queue_index,
+                    )
+                })?;
+            }
+        }
+        self.vring_bases = vec![None; self.queues.len()];
+
+        Ok(())
+    }
+
+    /// Stop the vrings in the slave, and save the vring bases got from the slave to
+    /// set up the vrings again.
+    pub fn stop_vhost_vring(&mut self) -> Result<()> {
+        for queue_index in 0..self.queues.len() {
+            if self.features & (1 << VHOST_USER_F_PROTOCOL_FEATURES) != 0 {
+                self.set_vring_enable(queue_index, false)?;
+            }
+            self.vring_bases[queue_index] = Some(self.get_vring_base(queue_index)?);
+        }
+        Ok(())
+    }
+
+    /// Stop the vrings in the slave, and forget the queues and eventfds.
+    pub fn reset_vhost_user(&mut self) -> Result<()> {
+        self.stop_vhost_vring()?;
+
+        self.queues.clear();
+        self.queue_evts.clear();
+        self.call_evts.clear();
+        self.vring_bases.clear();
+        Ok(())
+    }
+
+    /// Stop tracking the memory layout of the guest.
+    pub fn unregister_listener(&self) -> Result<()> {
+        self.mem_space
+            .unregister_listener(self.mem_listener.clone())
+            .chain_err(|| "Failed to unregister memory listener for vhost-user")?;
+        Ok(())
+    }
+
+    /// Remove the event notifiers of the client from the main loop.
+    pub fn delete_event(&self) -> Result<()> {
+        let mut notifiers = vec![
+            EventNotifier::new(
+                NotifierOperation::Delete,
+                self.sock.as_raw_fd(),
+                None,
+                EventSet::HANG_UP,
+                Vec::new(),
+            ),
+            EventNotifier::new(
+                NotifierOperation::Delete,
+                self.reconnect_evt.as_raw_fd(),
+                None,
+                EventSet::IN,
+                Vec::new(),
+            ),
+        ];
+        if let Some(slave_sock) = &self.slave_sock {
+            notifiers.push(EventNotifier::new(
+                NotifierOperation::Delete,
+                slave_sock.as_raw_fd(),
+                None,
+                EventSet::IN | EventSet::HANG_UP,
+                Vec::new(),
+            ));
+        }
+        EventLoop::update_event(notifiers, None)?;
+        Ok(())
+    }
+
+    /// Get vhost-user protocol features supported by the slave.
+    pub fn get_protocol_features(&self) -> Result<u64> {
+        let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::GetProtocolFeatures, false, 0);
+        self.sock
+            .send_msg(&hdr, None::<&VhostUserU64>, &[], &[])
+            .chain_err(|| "Failed to send msg for getting protocol features")?;
+        let features = self
+            .sock
+            .recv_reply::<VhostUserU64>(&hdr)
+            .chain_err(|| "Failed to get protocol features")?;
+        Ok(features.value)
+    }
+
+    /// Set vhost-user protocol features used by the master.
+    pub fn set_protocol_features(&self, features: u64) -> Result<()> {
+        self.send_u64(VhostUserMsgReq::SetProtocolFeatures, features)
+            .chain_err(|| "Failed to set protocol features")
+    }
+
+    /// Enable or disable the vring.
+    ///
+    /// # Arguments
+    /// * `queue_idx` - Index of the queue to modify.
+    /// * `status` - Enable the vring if true, or disable it.
+    pub fn set_vring_enable(&self, queue_idx: usize, status: bool) -> Result<()> {
+        let vring_state = VhostUserVringState::new(queue_idx as u32, status as u32);
+        self.send_body(VhostUserMsgReq::SetVringEnable, &vring_state)
+            .chain_err(|| "Failed to set vring enable")
+    }
+
+    /// Get the maximum number of queues supported by the slave.
+    pub fn get_queue_num(&self) -> Result<u64> {
+        let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::GetQueueNum, false, 0);
+        self.sock
+            .send_msg(&hdr, None::<&VhostUserU64>, &[], &[])
+            .chain_err(|| "Failed to send msg for getting queue num")?;
+        let queue_num = self
+            .sock
+            .recv_reply::<VhostUserU64>(&hdr)
+            .chain_err(|| "Failed to get queue num")?;
+        Ok(queue_num.value)
+    }
+
+    /// Get the device config space from the slave.
+    ///
+    /// # Arguments
+    /// * `offset` - Offset in the device config space.
+    /// * `size` - Size of the config space to get.
+    pub fn get_config(&self, offset: u32, size: u32) -> Result<Vec<u8>> {
+        let config = VhostUserConfig {
+            offset,
+            size,
+            flags: 0,
+        };
+        let payload = vec![0_u8; size as usize];
+        let hdr = VhostUserMsgHdr::new(
+            VhostUserMsgReq::GetConfig,
+            false,
+            (size_of::<VhostUserConfig>() + payload.len()) as u32,
+        );
+        self.sock
+            .send_msg(&hdr, Some(&config), &payload, &[])
+            .chain_err(|| "Failed to send msg for getting config")?;
+        let (reply, payload) = self
+            .sock
+            .recv_reply_with_payload::<VhostUserConfig>(&hdr, size as usize)
+            .chain_err(|| "Failed to get config")?;
+        if reply.offset != offset || reply.size != size {
+            bail!(
+                "Mismatched config from vhost-user slave, offset {} size {}, expected offset {} size {}",
+                reply.offset,
+                reply.size,
+                offset,
+                size
+            );
+        }
+        Ok(payload)
+    }
+
+    /// Set the device config space of the slave.
+    ///
+    /// # Arguments
+    /// * `offset` - Offset in the device config space.
+    /// * `data` - Data to write to the config space.
+    pub fn set_config(&self, offset: u32, data: &[u8]) -> Result<()> {
+        let config = VhostUserConfig {
+            offset,
+            size: data.len() as u32,
+            flags: 0,
+        };
+        let hdr = VhostUserMsgHdr::new(
+            VhostUserMsgReq::SetConfig,
+            false,
+            (size_of::<VhostUserConfig>() + data.len()) as u32,
+        );
+        self.sock
+            .send_msg(&hdr, Some(&config), data, &[])
+            .chain_err(|| "Failed to send msg for setting config")
+    }
+
+    fn send_body<D: ByteCode>(&self, request: VhostUserMsgReq, body: &D) -> Result<()> {
+        let hdr = VhostUserMsgHdr::new(request, false, size_of::<D>() as u32);
+        self.sock.send_msg(&hdr, Some(body), &[], &[])
+    }
+
+    fn send_u64(&self, request: VhostUserMsgReq, value: u64) -> Result<()> {
+        self.send_body(request, &VhostUserU64::new(value))
+    }
+
+    fn send_vring_fd(&self, request: VhostUserMsgReq, queue_idx: usize, fd: RawFd) -> Result<()> {
+        let value = VhostUserU64::new(queue_idx as u64 & VHOST_USER_VRING_IDX_MASK);
+        let hdr = VhostUserMsgHdr::new(request, false, size_of::<VhostUserU64>() as u32);
+        self.sock.send_msg(&hdr, Some(&value), &[], &[fd])
+    }
+
+    fn get_used_idx(&self, queue_config: &QueueConfig) -> Result<u16> {
+        // The used index is placed after the 16-bit flags of used ring.
+        let addr = queue_config
+            .used_ring
+            .checked_add(2)
+            .chain_err(|| "Used ring address overflows")?;
+        self.mem_space
+            .read_object::<u16>(addr)
+            .chain_err(|| ErrorKind::ReadObjectErr("used ring idx", addr.raw_value()))
+    }
+
+    /// Connect to the slave again, and restore the state of it.
+    fn reconnect(&mut self) -> Result<()> {
+        self.sock = VhostUserSock::connect(&self.sock.path)?;
+        self.set_owner()?;
+        let protocol_features = self.protocol_features;
+        self.negotiate_protocol_features(protocol_features)?;
+        self.set_slave_req_fd()?;
+        if !self.queues.is_empty() {
+            self.activate_vhost_vring()?;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn sock_notifier(client: Arc<Mutex<Self>>) -> EventNotifier {
+        let sock_fd = client.lock().unwrap().sock.as_raw_fd();
+        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            let locked_client = client.lock().unwrap();
+            warn!(
+                "Vhost-user slave {} disconnected, try to reconnect",
+                locked_client.sock.path
+            );
+            delay_reconnect(&locked_client.reconnect_evt);
+            Some(vec![EventNotifier::new(
+                NotifierOperation::Delete,
+                fd,
+                None,
+                EventSet::HANG_UP,
+                Vec::new(),
+            )])
+        });
+        EventNotifier::new(
+            NotifierOperation::AddShared,
+            sock_fd,
+            None,
+            EventSet::HANG_UP,
+            vec![Arc::new(Mutex::new(handler))],
+        )
+    }
+
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn slave_notifier(client: Arc<Mutex<Self>>) -> Option<EventNotifier> {
+        let slave_fd = client.lock().unwrap().slave_sock.as_ref()?.as_raw_fd();
+        let handler: Box<NotifierCallback> = Box::new(move |event, fd: RawFd| {
+            if event & EventSet::HANG_UP == EventSet::HANG_UP {
+                // The slave channel is set up again when the slave is reconnected.
+                return Some(vec![EventNotifier::new(
+                    NotifierOperation::Delete,
+                    fd,
+                    None,
+                    EventSet::IN | EventSet::HANG_UP,
+                    Vec::new(),
+                )]);
+            }
+            if let Err(e) = client.lock().unwrap().handle_slave_msg() {
+                error!(
+                    "Failed to handle vhost-user slave msg, error is {}",
+                    e.display_chain()
+                );
+            }
+            None
+        });
+        Some(EventNotifier::new(
+            NotifierOperation::AddShared,
+            slave_fd,
+            None,
+            EventSet::IN | EventSet::HANG_UP,
+            vec![Arc::new(Mutex::new(handler))],
+        ))
+    }
+}
+
+impl EventNotifierHelper for VhostUserClient {
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn internal_notifiers(client_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let mut notifiers = vec![VhostUserClient::sock_notifier(client_handler.clone())];
+        notifiers.extend(VhostUserClient::slave_notifier(client_handler.clone()));
+
+        let client = client_handler.clone();
+        let handler: Box<NotifierCallback> = Box::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let mut locked_client = client.lock().unwrap();
+            if let Err(e) = locked_client.reconnect() {
+                debug!(
+                    "Failed to reconnect vhost-user slave {}, error is {}",
+                    locked_client.sock.path,
+                    e.display_chain()
+                );
+                delay_reconnect(&locked_client.reconnect_evt);
+                return None;
+            }
+            info!("Vhost-user slave {} reconnected", locked_client.sock.path);
+            drop(locked_client);
+            let mut notifiers = vec![VhostUserClient::sock_notifier(client.clone())];
+            notifiers.extend(VhostUserClient::slave_notifier(client.clone()));
+            Some(notifiers)
+        });
+        notifiers.push(EventNotifier::new(
+            NotifierOperation::AddShared,
+            client_handler.lock().unwrap().reconnect_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![Arc::new(Mutex::new(handler))],
+        ));
+
+        notifiers
+    }
+}
+
+impl VhostOps for VhostUserClient {
+    fn set_owner(&self) -> Result<()> {
+        let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::SetOwner, false, 0);
+        self.sock
+            .send_msg(&hdr, None::<&VhostUserU64>, &[], &[])
+            .chain_err(|| "Failed to send msg for setting owner")
+    }
+
+    fn reset_owner(&self) -> Result<()> {
+        let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::ResetOwner, false, 0);
+        self.sock
+            .send_msg(&hdr, None::<&VhostUserU64>, &[], &[])
+            .chain_err(|| "Failed to send msg for resetting owner")
+    }
+
+    fn get_features(&self) -> Result<u64> {
+        let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::GetFeatures, false, 0);
+        self.sock
+            .send_msg(&hdr, None::<&VhostUserU64>, &[], &[])
+            .chain_err(|| "Failed to send msg for getting features")?;
+        let features = self
+            .sock
+            .recv_reply::<VhostUserU64>(&hdr)
+            .chain_err(|| "Failed to get features")?;
+        Ok(features.value)
+    }
+
+    fn set_features(&self, features: u64) -> Result<()> {
+        self.send_u64(VhostUserMsgReq::SetFeatures, features)
+            .chain_err(|| "Failed to send msg for setting features")
+    }
+
+    fn set_mem_table(&self) -> Result<()> {
+        let regions = self.mem_info.regions.lock().unwrap();
+        if regions.len() > VHOST_USER_MAX_REGIONS {
+            bail!(
+                "Too many memory regions {} for vhost-user, the maximum is {}",
+                regions.len(),
+                VHOST_USER_MAX_REGIONS
+            );
+        }
+
+        let mut payload = Vec::new();
+        let mut fds = Vec::new();
+        for reg_info in regions.iter() {
+            payload.extend_from_slice(reg_info.region.as_bytes());
+            fds.push(reg_info.file_back.file.as_raw_fd());
+        }
+        let mem_hdr = VhostUserMemHdr {
+            nregions: regions.len() as u32,
+            padding: 0,
+        };
+        let hdr = VhostUserMsgHdr::new(
+            VhostUserMsgReq::SetMemTable,
+            false,
+            (size_of::<VhostUserMemHdr>() + payload.len()) as u32,
+        );
+        self.sock
+            .send_msg(&hdr, Some(&mem_hdr), &payload, &fds)
+            .chain_err(|| "Failed to send msg for setting mem table")
+    }
+
+    fn set_vring_num(&self, queue_idx: usize, num: u16) -> Result<()> {
+        let vring_state = VhostUserVringState::new(queue_idx as u32, u32::from(num));
+        self.send_body(VhostUserMsgReq::SetVringNum, &vring_state)
+            .chain_err(|| "Failed to send msg for setting vring num")
+    }
+
+    fn set_vring_addr(&self, queue: &QueueConfig, index: usize, flags: u32) -> Result<()> {
+        let desc_user_addr = self
+            .mem_info
+            .addr_to_host(queue.desc_table)
+            .ok_or_else(|| {
+                ErrorKind::Msg(format!(
+                    "Failed to transform desc-table address {}",
+                    queue.desc_table.0
+                ))
+            })?;
+        let used_user_addr = self.mem_info.addr_to_host(queue.used_ring).ok_or_else(|| {
+            ErrorKind::Msg(format!(
+                "Failed to transform used ring address {}",
+                queue.used_ring.0
+            ))
+        })?;
+        let avail_user_addr = self
+            .mem_info
+            .addr_to_host(queue.avail_ring)
+            .ok_or_else(|| {
+                ErrorKind::Msg(format!(
+                    "Failed to transform avail ring address {}",
+                    queue.avail_ring.0
+                ))
+            })?;
+
+        let vring_addr = VhostUserVringAddr {
+            index: index as u32,
+            flags,
+            desc_user_addr,
+            used_user_addr,
+            avail_user_addr,
+            log_guest_addr: 0_u64,
+        };
+        self.send_body(VhostUserMsgReq::SetVringAddr, &vring_addr)
+            .chain_err(|| "Failed to send msg for setting vring addr")
+    }
+
+    fn set_vring_base(&self, queue_idx: usize, last_avail_idx: u16) -> Result<()> {
+        let vring_state = VhostUserVringState::new(queue_idx as u32, u32::from(last_avail_idx));
+        self.send_body(VhostUserMsgReq::SetVringBase, &vring_state)
+            .chain_err(|| "Failed to send msg for setting vring base")
+    }
+
+    fn get_vring_base(&self, queue_idx: usize) -> Result<u16> {
+        let vring_state = VhostUserVringState::new(queue_idx as u32, 0);
+        let hdr = VhostUserMsgHdr::new(
+            VhostUserMsgReq::GetVringBase,
+            false,
+            size_of::<VhostUserVringState>() as u32,
+        );
+        self.sock
+            .send_msg(&hdr, Some(&vring_state), &[], &[])
+            .chain_err(|| "Failed to send msg for getting vring base")?;
+        let vring_state = self
+            .sock
+            .recv_reply::<VhostUserVringState>(&hdr)
+            .chain_err(|| "Failed to get vring base")?;
+        Ok(vring_state.value as u16)
+    }
+
+    fn set_vring_call(&self, queue_idx: usize, fd: &EventFd) -> Result<()> {
+        self.send_vring_fd(VhostUserMsgReq::SetVringCall, queue_idx, fd.as_raw_fd())
+            .chain_err(|| "Failed to send msg for setting vring call")
+    }
+
+    fn set_vring_kick(&self, queue_idx: usize, fd: &EventFd) -> Result<()> {
+        self.send_vring_fd(VhostUserMsgReq::SetVringKick, queue_idx, fd.as_raw_fd())
+            .chain_err(|| "Failed to send msg for setting vring kick")
+    }
+}
+
+fn clone_evts(evts: &[EventFd]) -> Result<Vec<EventFd>> {
+    let mut cloned = Vec::new();
+    for evt in evts.iter() {
+        cloned.push(evt.try_clone().chain_err(|| "Failed to clone eventfd")?);
+    }
+    Ok(cloned)
+}
+
+/// Trigger `reconnect_evt` after the reconnect interval.
+fn delay_reconnect(reconnect_evt: &EventFd) {
+    let evt = match reconnect_evt.try_clone() {
+        Ok(evt) => evt,
+        Err(e) => {
+            error!("Failed to clone reconnect eventfd for vhost-user: {}", e);
+            return;
+        }
+    };
+    let func = Box::new(move || {
+        evt.write(1)
+            .unwrap_or_else(|e| error!("Failed to write reconnect eventfd for vhost-user: {}", e));
+    });
+    if let Some(ctx) = EventLoop::get_ctx(None) {
+        ctx.delay_call(func, RECONNECT_INTERVAL_NS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use address_space::{create_host_mmaps, HostMemMapping, Region};
+    use machine_manager::config::MachineMemConfig;
+
+    use super::super::message::{VHOST_USER_PROTOCOL_F_MQ, VHOST_USER_REPLY_MASK};
+    use super::super::test_backend::TestBlkBackend;
+    use super::*;
+    use crate::QUEUE_TYPE_SPLIT_VRING;
+
+    const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
+
+    fn address_space_init(mem_share: bool) -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36);
+        let sys_space = AddressSpace::new(root).unwrap();
+        let host_mmap = if mem_share {
+            let mem_config = MachineMemConfig {
+                mem_size: SYSTEM_SPACE_SIZE,
+                mem_share: true,
+                ..Default::default()
+            };
+            create_host_mmaps(&[(0, SYSTEM_SPACE_SIZE)], &mem_config, 1)
+                .unwrap()
+                .remove(0)
+        } else {
+            Arc::new(
+                HostMemMapping::new(
+                    GuestAddress(0),
+                    None,
+                    SYSTEM_SPACE_SIZE,
+                    None,
+                    false,
+                    false,
+                    false,
+                )
+                .unwrap(),
+            )
+        };
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone()),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    /// A fake slave which records the requests and answers the ones needing reply.
+    fn fake_slave(listener: UnixListener) -> thread::JoinHandle<Vec<(u32, Vec<u8>)>> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut requests = Vec::new();
+            loop {
+                let mut hdr = VhostUserMsgHdr::default();
+                if stream.read_exact(hdr.as_mut_bytes()).is_err() {
+                    break;
+                }
+                let mut body = vec![0_u8; hdr.size as usize];
+                stream.read_exact(&mut body).unwrap();
+
+                let reply_body = match hdr.get_request() {
+                    VhostUserMsgReq::GetFeatures => Some(
+                        (1_u64 << VHOST_USER_F_PROTOCOL_FEATURES)
+                            .as_bytes()
+                            .to_vec(),
+                    ),
+                    VhostUserMsgReq::GetProtocolFeatures => Some(0xff_u64.as_bytes().to_vec()),
+                    VhostUserMsgReq::GetVringBase => {
+                        let state = VhostUserVringState::from_bytes(&body).unwrap();
+                        Some(VhostUserVringState::new(state.index, 5).as_bytes().to_vec())
+                    }
+                    _ => None,
+                };
+                if let Some(reply_body) = reply_body {
+                    let mut reply = hdr;
+                    reply.flags |= VHOST_USER_REPLY_MASK;
+                    reply.size = reply_body.len() as u32;
+                    stream.write_all(reply.as_bytes()).unwrap();
+                    stream.write_all(&reply_body).unwrap();
+                }
+                requests.push((hdr.request, body));
+            }
+            requests
+        })
+    }
+
+    #[test]
+    fn test_vhost_user_client_mem_share() {
+        let path = format!("/tmp/test_vhost_user_client_{}.sock", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let slave = fake_slave(listener);
+
+        // Guest memory which is not shared can't be accessed by the slave.
+        let sys_space = address_space_init(false);
+        assert!(VhostUserClient::new(&sys_space, &path).is_err());
+        drop(slave);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_vhost_user_client_activate() {
+        let path = format!("/tmp/test_vhost_user_activate_{}.sock", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let slave = fake_slave(listener);
+
+        let sys_space = address_space_init(true);
+        let mut client = VhostUserClient::new(&sys_space, &path).unwrap();
+        assert_eq!(client.mem_info.regions.lock().unwrap().len(), 1);
+        assert!(client.set_owner().is_ok());
+        assert!(client
+            .negotiate_protocol_features(1 << VHOST_USER_PROTOCOL_F_MQ)
+            .is_ok());
+        assert_eq!(client.protocol_features, 1 << VHOST_USER_PROTOCOL_F_MQ);
+
+        let mut queue_config = QueueConfig::new(16);
+        queue_config.desc_table = GuestAddress(0);
+        queue_config.avail_ring = GuestAddress(0x100);
+        queue_config.used_ring = GuestAddress(0x200);
+        queue_config.ready = true;
+        let queue = Arc::new(Mutex::new(
+            Queue::new(queue_config, QUEUE_TYPE_SPLIT_VRING).unwrap(),
+        ));
+        sys_space
+            .write_object::<u16>(&3, GuestAddress(0x202))
+            .unwrap();
+        let kick_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let call_evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+
+        // Eventfds are not set.
+        client.set_queues(&[queue]);
+        assert!(client.activate_vhost_vring().is_err());
+
+        client.features = 1 << VHOST_USER_F_PROTOCOL_FEATURES;
+        client.set_queue_evts(&[kick_evt]).unwrap();
+        client.set_call_evts(&[call_evt]).unwrap();
+        assert!(client.activate_vhost_vring().is_ok());
+        assert!(client.reset_vhost_user().is_ok());
+        assert!(client.queues.is_empty());
+        drop(client);
+
+        let requests: Vec<u32> = slave.join().unwrap().iter().map(|r| r.0).collect();
+        let expected = vec![
+            VhostUserMsgReq::SetOwner,
+            VhostUserMsgReq::GetFeatures,
+            VhostUserMsgReq::GetProtocolFeatures,
+            VhostUserMsgReq::SetProtocolFeatures,
+            VhostUserMsgReq::SetFeatures,
+            VhostUserMsgReq::SetMemTable,
+            VhostUserMsgReq::SetVringNum,
+            VhostUserMsgReq::SetVringAddr,
+            VhostUserMsgReq::SetVringBase,
+            VhostUserMsgReq::SetVringCall,
+            VhostUserMsgReq::SetVringKick,
+            VhostUserMsgReq::SetVringEnable,
+            VhostUserMsgReq::SetVringEnable,
+            VhostUserMsgReq::GetVringBase,
+        ];
+        assert_eq!(
+            requests,
+            expected.iter().map(|r| *r as u32).collect::<Vec<u32>>()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_vhost_user_client_reconnect() {
+        let path = format!("/tmp/test_vhost_user_reconnect_{}.sock", std::process::id());
+        let backend = TestBlkBackend::new(&path, 0, 1);
+        let sys_space = address_space_init(true);
+        let mut client = VhostUserClient::new(&sys_space, &path).unwrap();
+        client.set_owner().unwrap();
+        client
+            .negotiate_protocol_features(1 << VHOST_USER_PROTOCOL_F_MQ)
+            .unwrap();
+
+        let mut queue_config = QueueConfig::new(16);
+        queue_config.avail_ring = GuestAddress(0x100);
+        queue_config.used_ring = GuestAddress(0x200);
+        queue_config.ready = true;
+        let queue = Arc::new(Mutex::new(
+            Queue::new(queue_config, QUEUE_TYPE_SPLIT_VRING).unwrap(),
+        ));
+        let evt = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        client.features = 1 << VHOST_USER_F_PROTOCOL_FEATURES;
+        client.set_queues(&[queue]);
+        client.set_queue_evts(&[evt.try_clone().unwrap()]).unwrap();
+        client.set_call_evts(&[evt]).unwrap();
+        client.activate_vhost_vring().unwrap();
+
+        // The slave is restarted while the vrings are running, and the vrings are
+        // restored from the used index.
+        sys_space
+            .write_object::<u16>(&3, GuestAddress(0x202))
+            .unwrap();
+        backend.disconnect();
+        assert!(client.reconnect().is_ok());
+        assert_eq!(client.get_vring_base(0).unwrap(), 3);
+
+        // The slave is restarted after the vrings are stopped, and the vrings are
+        // restored from the vring bases got from the slave.
+        client.set_vring_base(0, 7).unwrap();
+        client.stop_vhost_vring().unwrap();
+        assert_eq!(client.vring_bases, vec![Some(7)]);
+        backend.state.lock().unwrap().requests.clear();
+        backend.disconnect();
+        assert!(client.reconnect().is_ok());
+        assert_eq!(client.protocol_features, 1 << VHOST_USER_PROTOCOL_F_MQ);
+        assert_eq!(client.get_vring_base(0).unwrap(), 7);
+
+        let state = backend.state.lock().unwrap();
+        assert_eq!(state.connections, 3);
+        assert_eq!(state.acked_features, 1 << VHOST_USER_F_PROTOCOL_FEATURES);
+        assert_eq!(state.requests[0], VhostUserMsgReq::SetOwner);
+        assert!(state.requests.contains(&VhostUserMsgReq::SetMemTable));
+        assert!(state.requests.contains(&VhostUserMsgReq::SetVringEnable));
+    }
+
+    #[test]
+    fn test_vhost_user_client_timeout() {
+        let path = format!("/tmp/test_vhost_user_timeout_{}.sock", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        // The slave is hung, which never replies.
+        let _listener = UnixListener::bind(&path).unwrap();
+        let sock = VhostUserSock::connect(&path).unwrap();
+
+        let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::GetFeatures, false, 0);
+        sock.send_msg(&hdr, None::<&VhostUserU64>, &[], &[])
+            .unwrap();
+        assert!(sock.recv_reply::<VhostUserU64>(&hdr).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_vhost_user_client_messages() {
+        let path = format!("/tmp/test_vhost_user_msg_{}.sock", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let slave = fake_slave(listener);
+
+        let sys_space = address_space_init(true);
+        let client = VhostUserClient::new(&sys_space, &path).unwrap();
+        assert!(client.set_mem_table().is_ok());
+        let mut queue_config = QueueConfig::new(16);
+        queue_config.used_ring = GuestAddress(0x200);
+        assert!(client.set_vring_addr(&queue_config, 1, 0).is_ok());
+        queue_config.used_ring = GuestAddress(SYSTEM_SPACE_SIZE);
+        assert!(client.set_vring_addr(&queue_config, 1, 0).is_err());
+        assert_eq!(client.get_vring_base(1).unwrap(), 5);
+        drop(client);
+
+        let requests = slave.join().unwrap();
+        assert_eq!(requests.len(), 3);
+
+        let (request, body) = &requests[0];
+        assert_eq!(*request, VhostUserMsgReq::SetMemTable as u32);
+        let mem_hdr_len = size_of::<VhostUserMemHdr>();
+        let mem_hdr = VhostUserMemHdr::from_bytes(&body[..mem_hdr_len]).unwrap();
+        assert_eq!(mem_hdr.nregions, 1);
+        let region = VhostUserMemoryRegion::from_bytes(&body[mem_hdr_len..]).unwrap();
+        assert_eq!(region.guest_phys_addr, 0);
+        assert_eq!(region.memory_size, SYSTEM_SPACE_SIZE);
+        assert_eq!(region.mmap_offset, 0);
+
+        let (request, body) = &requests[1];
+        assert_eq!(*request, VhostUserMsgReq::SetVringAddr as u32);
+        let vring_addr = VhostUserVringAddr::from_bytes(body).unwrap();
+        assert_eq!(vring_addr.index, 1);
+        assert_eq!(vring_addr.used_user_addr, region.userspace_addr + 0x200);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,406 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+
+use address_space::AddressSpace;
+use machine_manager::{
+    config::{FsConfig, MAX_FS_TAG_LENGTH},
+    event_loop::EventLoop,
+};
+use util::byte_code::ByteCode;
+use util::loop_context::EventNotifierHelper;
+use util::num_ops::{read_u32, write_u32};
+use vmm_sys_util::eventfd::EventFd;
+
+use super::super::super::errors::{ErrorKind, Result, ResultExt};
+use super::super::super::{
+    Queue, VirtioDevice, VirtioInterrupt, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
+    VIRTIO_F_VERSION_1, VIRTIO_TYPE_FS,
+};
+use super::super::{VhostIoHandler, VhostNotify, VhostOps};
+use super::message::{VHOST_USER_F_PROTOCOL_FEATURES, VHOST_USER_PROTOCOL_F_MQ};
+use super::VhostUserClient;
+
+/// Size of each virtqueue.
+const QUEUE_SIZE_FS: u16 = 1024;
+/// Number of high priority queue, which is used for FUSE_INTERRUPT and FUSE_FORGET.
+const HIPRIO_QUEUE_NUM: usize = 1;
+
+/// Config space of virtio fs device, refer to Virtio Spec.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+struct VirtioFsConfig {
+    /// Mount tag of the file system, padded with NUL bytes.
+    tag: [u8; MAX_FS_TAG_LENGTH],
+    /// Number of request queues, the high priority queue is not included.
+    num_request_queues: u32,
+}
+
+impl Default for VirtioFsConfig {
+    fn default() -> Self {
+        VirtioFsConfig {
+            tag: [0_u8; MAX_FS_TAG_LENGTH],
+            num_request_queues: 0,
+        }
+    }
+}
+
+impl ByteCode for VirtioFsConfig {}
+
+/// File system device structure, whose requests are handled by the vhost-user slave,
+/// e.g. virtiofsd.
+pub struct Fs {
+    /// Configuration of the vhost user fs device.
+    fs_cfg: FsConfig,
+    /// Bit mask of features supported by the backend.
+    device_features: u64,
+    /// Bit mask of features negotiated by the backend and the frontend.
+    driver_features: u64,
+    /// Bit mask of features supported by the vhost-user slave.
+    vhost_features: u64,
+    /// Config space of the fs device.
+    config: VirtioFsConfig,
+    /// System address space.
+    mem_space: Arc<AddressSpace>,
+    /// Vhost user client.
+    client: Option<Arc<Mutex<VhostUserClient>>>,
+    /// EventFd for device deactivate.
+    deactivate_evt: EventFd,
+}
+
+impl Fs {
+    pub fn new(cfg: &FsConfig, mem_space: &Arc<AddressSpace>) -> Self {
+        let mut config = VirtioFsConfig {
+            num_request_queues: u32::from(cfg.num_request_queues),
+            ..Default::default()
+        };
+        let tag_len = cmp::min(cfg.tag.len(), MAX_FS_TAG_LENGTH);
+        config.tag[..tag_len].copy_from_slice(&cfg.tag.as_bytes()[..tag_len]);
+
+        Fs {
+            fs_cfg: cfg.clone(),
+            device_features: 0_u64,
+            driver_features: 0_u64,
+            vhost_features: 0_u64,
+            config,
+            mem_space: mem_space.clone(),
+            client: None,
+            deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        }
+    }
+
+    /// Negotiate features with the vhost-user slave, and check whether the
+    /// configuration of the device is supported by it.
+    fn negotiate_features(&mut self, client: &mut VhostUserClient) -> Result<()> {
+        client
+            .set_owner()
+            .chain_err(|| "Failed to set owner for vhost user fs")?;
+        client
+            .negotiate_protocol_features(1 << VHOST_USER_PROTOCOL_F_MQ)
+            .chain_err(|| "Failed to negotiate protocol features for vhost user fs")?;
+        let vhost_features = client
+            .get_features()
+            .chain_err(|| "Failed to get features for vhost user fs")?;
+
+        let supported_features = 1 << VIRTIO_F_VERSION_1
+            | 1 << VIRTIO_F_RING_INDIRECT_DESC
+            | 1 << VIRTIO_F_RING_EVENT_IDX;
+        self.device_features = vhost_features & supported_features;
+
+        // The slave which doesn't support MQ protocol feature can handle the requests
+        // of the high priority queue and one request queue.
+        let queue_num = self.queue_num() as u64;
+        let max_queues = if client.protocol_features & (1 << VHOST_USER_PROTOCOL_F_MQ) != 0 {
+            client
+                .get_queue_num()
+                .chain_err(|| "Failed to get queue num for vhost user fs")?
+        } else {
+            (HIPRIO_QUEUE_NUM + 1) as u64
+        };
+        if queue_num > max_queues {
+            bail!(
+                "The vhost user fs slave supports at most {} queues, but {} are configured",
+                max_queues,
+                queue_num
+            );
+        }
+        self.vhost_features = vhost_features;
+
+        Ok(())
+    }
+}
+
+impl VirtioDevice for Fs {
+    /// Realize vhost user fs device.
+    fn realize(&mut self) -> Result<()> {
+        let mut client = VhostUserClient::new(&self.mem_space, &self.fs_cfg.socket_path)
+            .chain_err(|| "Failed to create the client which communicates with the slave")?;
+        if let Err(e) = self.negotiate_features(&mut client) {
+            client.unregister_listener()?;
+            return Err(e);
+        }
+        let client = Arc::new(Mutex::new(client));
+        self.client = Some(client.clone());
+
+        EventLoop::update_event(EventNotifierHelper::internal_notifiers(client), None)
+            .chain_err(|| "Failed to update event for vhost user fs")?;
+
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        if let Some(client) = self.client.take() {
+            let locked_client = client.lock().unwrap();
+            locked_client
+                .delete_event()
+                .chain_err(|| "Failed to delete vhost user fs event")?;
+            locked_client.unregister_listener()?;
+        }
+        Ok(())
+    }
+
+    /// Get the virtio device type, refer to Virtio Spec.
+    fn device_type(&self) -> u32 {
+        VIRTIO_TYPE_FS
+    }
+
+    /// Get the count of virtio device queues.
+    fn queue_num(&self) -> usize {
+        HIPRIO_QUEUE_NUM + self.fs_cfg.num_request_queues as usize
+    }
+
+    /// Get the queue size of virtio device.
+    fn queue_size(&self) -> u16 {
+        QUEUE_SIZE_FS
+    }
+
+    /// Get device features from host.
+    fn get_device_features(&self, features_select: u32) -> u32 {
+        read_u32(self.device_features, features_select)
+    }
+
+    /// Set driver features by guest.
+    fn set_driver_features(&mut self, page: u32, value: u32) {
+        let mut features = write_u32(value, page);
+        let unsupported_features = features & !self.device_features;
+        if unsupported_features != 0 {
+            warn!(
+                "Received acknowledge request with unsupported feature for vhost user fs: 0x{:x}",
+                features
+            );
+            features &= !unsupported_features;
+        }
+        self.driver_features |= features;
+    }
+
+    /// Read data of config from guest.
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) -> Result<()> {
+        let config_slice = self.config.as_bytes();
+        let config_len = config_slice.len() as u64;
+        if offset >= config_len {
+            return Err(ErrorKind::DevConfigOverflow(offset, config_len).into());
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            data.write_all(&config_slice[offset as usize..cmp::min(end, config_len) as usize])?;
+        }
+
+        Ok(())
+    }
+
+    /// Write data to config from guest.
+    fn write_config(&mut self, offset: u64, _data: &[u8]) -> Result<()> {
+        // The config space of virtio fs device is read-only.
+        let config_len = self.config.as_bytes().len() as u64;
+        Err(ErrorKind::DevConfigOverflow(offset, config_len).into())
+    }
+
+    /// Activate the virtio device, this function is called by vcpu thread when frontend
+    /// virtio driver is ready and write `DRIVER_OK` to backend.
+    fn activate(
+        &mut self,
+        _mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queues: &[Arc<Mutex<Queue>>],
+        queue_evts: Vec<EventFd>,
+    ) -> Result<()> {
+        let client = match &self.client {
+            None => return Err("Failed to get client for vhost user fs".into()),
+            Some(client_) => client_,
+        };
+
+        let mut host_notifies = Vec::new();
+        let mut call_evts = Vec::new();
+        for queue in queues.iter() {
+            let host_notify = VhostNotify {
+                notify_evt: EventFd::new(libc::EFD_NONBLOCK)
+                    .chain_err(|| ErrorKind::EventFdCreate)?,
+                queue: queue.clone(),
+            };
+            call_evts.push(
+                host_notify
+                    .notify_evt
+                    .try_clone()
+                    .chain_err(|| "Failed to clone eventfd for vhost user fs")?,
+            );
+            host_notifies.push(host_notify);
+        }
+
+        let mut locked_client = client.lock().unwrap();
+        locked_client.features =
+            self.driver_features | (self.vhost_features & 1 << VHOST_USER_F_PROTOCOL_FEATURES);
+        locked_client.set_queues(queues);
+        locked_client.set_queue_evts(&queue_evts)?;
+        locked_client.set_call_evts(&call_evts)?;
+        locked_client
+            .activate_vhost_vring()
+            .chain_err(|| "Failed to activate vrings for vhost user fs")?;
+        drop(locked_client);
+
+        let handler = VhostIoHandler {
+            interrupt_cb,
+            host_notifies,
+            deactivate_evt: self.deactivate_evt.as_raw_fd(),
+        };
+
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler))),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        if let Some(client) = &self.client {
+            client
+                .lock()
+                .unwrap()
+                .reset_vhost_user()
+                .chain_err(|| "Failed to reset vhost user fs")?;
+        }
+        self.deactivate_evt
+            .write(1)
+            .chain_err(|| ErrorKind::EventFdWrite)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.driver_features = 0_u64;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use address_space::{create_host_mmaps, Region};
+    use machine_manager::config::MachineMemConfig;
+
+    use super::super::test_backend::TestBlkBackend;
+    use super::*;
+
+    const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
+
+    fn address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36);
+        let sys_space = AddressSpace::new(root).unwrap();
+        let mem_config = MachineMemConfig {
+            mem_size: SYSTEM_SPACE_SIZE,
+            mem_share: true,
+            ..Default::default()
+        };
+        let host_mmap = create_host_mmaps(&[(0, SYSTEM_SPACE_SIZE)], &mem_config, 1)
+            .unwrap()
+            .remove(0);
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone()),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    fn fs_config(path: &str, num_request_queues: u16) -> FsConfig {
+        FsConfig {
+            id: "fs0".to_string(),
+            tag: "myfs".to_string(),
+            socket_path: path.to_string(),
+            num_request_queues,
+        }
+    }
+
+    #[test]
+    fn test_vhost_user_fs_config() {
+        let sys_space = address_space_init();
+        let mut fs = Fs::new(&fs_config("", 2), &sys_space);
+        assert_eq!(fs.device_type(), VIRTIO_TYPE_FS);
+        assert_eq!(fs.queue_num(), 3);
+        assert_eq!(fs.queue_size(), QUEUE_SIZE_FS);
+
+        let mut tag = [0xff_u8; MAX_FS_TAG_LENGTH];
+        fs.read_config(0, &mut tag).unwrap();
+        assert_eq!(&tag[..4], b"myfs");
+        assert!(tag[4..].iter().all(|b| *b == 0));
+        let mut num_queues = [0_u8; 4];
+        fs.read_config(MAX_FS_TAG_LENGTH as u64, &mut num_queues)
+            .unwrap();
+        assert_eq!(u32::from_le_bytes(num_queues), 2);
+        assert!(fs
+            .read_config(MAX_FS_TAG_LENGTH as u64 + 4, &mut num_queues)
+            .is_err());
+        // The config space is read-only.
+        assert!(fs.write_config(0, &[0]).is_err());
+
+        fs.device_features = 1 << VIRTIO_F_VERSION_1;
+        fs.set_driver_features(0, 0xffff_ffff);
+        assert_eq!(fs.driver_features, 0);
+        fs.set_driver_features(1, 0xffff_ffff);
+        assert_eq!(fs.driver_features, 1 << VIRTIO_F_VERSION_1);
+        assert!(fs.reset().is_ok());
+        assert_eq!(fs.driver_features, 0);
+
+        // The slave is not listening.
+        assert!(fs.realize().is_err());
+    }
+
+    #[test]
+    fn test_vhost_user_fs_queues() {
+        let path = format!("/tmp/test_vhost_user_fs_{}.sock", std::process::id());
+        let sys_space = address_space_init();
+        let features = 1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_F_RING_EVENT_IDX | 1 << 40;
+
+        // The slave supports 4 queues, which are 1 high priority queue and 3 request queues.
+        let backend = TestBlkBackend::new(&path, features, 4);
+        let mut fs = Fs::new(&fs_config(&path, 3), &sys_space);
+        let mut client = VhostUserClient::new(&sys_space, &path).unwrap();
+        assert!(fs.negotiate_features(&mut client).is_ok());
+        assert_eq!(
+            fs.device_features,
+            1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_F_RING_EVENT_IDX
+        );
+        client.unregister_listener().unwrap();
+        drop(client);
+
+        let mut fs = Fs::new(&fs_config(&path, 4), &sys_space);
+        let mut client = VhostUserClient::new(&sys_space, &path).unwrap();
+        assert!(fs.negotiate_features(&mut client).is_err());
+        client.unregister_listener().unwrap();
+        drop(backend);
+    }
+}
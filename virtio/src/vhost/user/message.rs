@@ -0,0 +1,292 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use util::byte_code::ByteCode;
+
+/// The version of the vhost-user protocol.
+pub const VHOST_USER_VERSION: u32 = 0x1;
+/// Mask of the version bits in the flags of message header.
+pub const VHOST_USER_VERSION_MASK: u32 = 0x3;
+/// Mark the message as a reply from the slave.
+pub const VHOST_USER_REPLY_MASK: u32 = 0x1 << 2;
+/// Ask the slave to reply to the message.
+pub const VHOST_USER_NEED_REPLY_MASK: u32 = 0x1 << 3;
+/// The maximum number of memory regions in `VHOST_USER_SET_MEM_TABLE`, which is
+/// also the maximum number of fds passed in one message.
+pub const VHOST_USER_MAX_REGIONS: usize = 8;
+/// Feature bit of virtio device which indicates that vhost-user protocol features
+/// are supported.
+pub const VHOST_USER_F_PROTOCOL_FEATURES: u32 = 30;
+/// Flag of vring kick/call message, which indicates that no fd is passed.
+#[allow(dead_code)]
+pub const VHOST_USER_VRING_NOFD_MASK: u64 = 0x1 << 8;
+/// Mask of the vring index in vring kick/call message.
+pub const VHOST_USER_VRING_IDX_MASK: u64 = 0xff;
+
+/// Protocol feature: the slave supports multiple queues.
+pub const VHOST_USER_PROTOCOL_F_MQ: u32 = 0;
+/// Protocol feature: the slave supports `VHOST_USER_GET_CONFIG`/`VHOST_USER_SET_CONFIG`.
+pub const VHOST_USER_PROTOCOL_F_CONFIG: u32 = 9;
+/// Protocol feature: the slave sends requests to the master through the slave channel.
+pub const VHOST_USER_PROTOCOL_F_SLAVE_REQ: u32 = 10;
+
+/// Request sent by the slave through the slave channel, which notifies that the
+/// device config space is changed.
+pub const VHOST_USER_SLAVE_CONFIG_CHANGE_MSG: u32 = 2;
+
+/// Type of requests sent to the vhost-user slave, refer to
+/// https://qemu-project.gitlab.io/qemu/interop/vhost-user.html.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum VhostUserMsgReq {
+    None = 0,
+    GetFeatures = 1,
+    SetFeatures = 2,
+    SetOwner = 3,
+    ResetOwner = 4,
+    SetMemTable = 5,
+    SetLogBase = 6,
+    SetLogFd = 7,
+    SetVringNum = 8,
+    SetVringAddr = 9,
+    SetVringBase = 10,
+    GetVringBase = 11,
+    SetVringKick = 12,
+    SetVringCall = 13,
+    SetVringErr = 14,
+    GetProtocolFeatures = 15,
+    SetProtocolFeatures = 16,
+    GetQueueNum = 17,
+    SetVringEnable = 18,
+    SendRarp = 19,
+    NetSetMtu = 20,
+    SetSlaveReqFd = 21,
+    IotlbMsg = 22,
+    SetVringEndian = 23,
+    GetConfig = 24,
+    SetConfig = 25,
+    MaxCmd = 26,
+}
+
+impl From<u32> for VhostUserMsgReq {
+    fn from(t: u32) -> Self {
+        match t {
+            0 => VhostUserMsgReq::None,
+            1 => VhostUserMsgReq::GetFeatures,
+            2 => VhostUserMsgReq::SetFeatures,
+            3 => VhostUserMsgReq::SetOwner,
+            4 => VhostUserMsgReq::ResetOwner,
+            5 => VhostUserMsgReq::SetMemTable,
+            6 => VhostUserMsgReq::SetLogBase,
+            7 => VhostUserMsgReq::SetLogFd,
+            8 => VhostUserMsgReq::SetVringNum,
+            9 => VhostUserMsgReq::SetVringAddr,
+            10 => VhostUserMsgReq::SetVringBase,
+            11 => VhostUserMsgReq::GetVringBase,
+            12 => VhostUserMsgReq::SetVringKick,
+            13 => VhostUserMsgReq::SetVringCall,
+            14 => VhostUserMsgReq::SetVringErr,
+            15 => VhostUserMsgReq::GetProtocolFeatures,
+            16 => VhostUserMsgReq::SetProtocolFeatures,
+            17 => VhostUserMsgReq::GetQueueNum,
+            18 => VhostUserMsgReq::SetVringEnable,
+            19 => VhostUserMsgReq::SendRarp,
+            20 => VhostUserMsgReq::NetSetMtu,
+            21 => VhostUserMsgReq::SetSlaveReqFd,
+            22 => VhostUserMsgReq::IotlbMsg,
+            23 => VhostUserMsgReq::SetVringEndian,
+            24 => VhostUserMsgReq::GetConfig,
+            25 => VhostUserMsgReq::SetConfig,
+            _ => VhostUserMsgReq::MaxCmd,
+        }
+    }
+}
+
+/// The header of vhost-user message.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct VhostUserMsgHdr {
+    /// The request id for vhost-user message.
+    pub request: u32,
+    /// The flags for property setting.
+    pub flags: u32,
+    /// The total length of the payload following the header.
+    pub size: u32,
+}
+
+impl ByteCode for VhostUserMsgHdr {}
+
+impl VhostUserMsgHdr {
+    /// Create a new vhost-user message header.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The request id for vhost-user message.
+    /// * `need_reply` - Whether the slave should reply to the message.
+    /// * `size` - The length of the payload following the header.
+    pub fn new(request: VhostUserMsgReq, need_reply: bool, size: u32) -> Self {
+        let mut flags = VHOST_USER_VERSION;
+        if need_reply {
+            flags |= VHOST_USER_NEED_REPLY_MASK;
+        }
+        VhostUserMsgHdr {
+            request: request as u32,
+            flags,
+            size,
+        }
+    }
+
+    /// Get the request type of the message.
+    pub fn get_request(&self) -> VhostUserMsgReq {
+        VhostUserMsgReq::from(self.request)
+    }
+
+    /// Check whether the message is a valid reply.
+    pub fn is_reply(&self) -> bool {
+        self.flags & VHOST_USER_VERSION_MASK == VHOST_USER_VERSION
+            && self.flags & VHOST_USER_REPLY_MASK != 0
+    }
+}
+
+/// Payload of the message which only carries a 64-bit value.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VhostUserU64 {
+    pub value: u64,
+}
+
+impl ByteCode for VhostUserU64 {}
+
+impl VhostUserU64 {
+    pub fn new(value: u64) -> Self {
+        VhostUserU64 { value }
+    }
+}
+
+/// The header of the payload of `VHOST_USER_SET_MEM_TABLE`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VhostUserMemHdr {
+    /// Number of memory regions following the header.
+    pub nregions: u32,
+    /// Padding for alignment.
+    pub padding: u32,
+}
+
+impl ByteCode for VhostUserMemHdr {}
+
+/// Memory region information for `VHOST_USER_SET_MEM_TABLE`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct VhostUserMemoryRegion {
+    /// GPA.
+    pub guest_phys_addr: u64,
+    /// Size of the memory region.
+    pub memory_size: u64,
+    /// HVA.
+    pub userspace_addr: u64,
+    /// Offset where the region starts in the mapped file.
+    pub mmap_offset: u64,
+}
+
+impl ByteCode for VhostUserMemoryRegion {}
+
+/// Vring state for `VHOST_USER_SET_VRING_NUM`, `VHOST_USER_SET_VRING_BASE`,
+/// `VHOST_USER_GET_VRING_BASE` and `VHOST_USER_SET_VRING_ENABLE`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VhostUserVringState {
+    /// Vring index.
+    pub index: u32,
+    /// A common 32bit value to encapsulate vring state etc.
+    pub value: u32,
+}
+
+impl ByteCode for VhostUserVringState {}
+
+impl VhostUserVringState {
+    pub fn new(index: u32, value: u32) -> Self {
+        VhostUserVringState { index, value }
+    }
+}
+
+/// Vring address for `VHOST_USER_SET_VRING_ADDR`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VhostUserVringAddr {
+    /// Vring index.
+    pub index: u32,
+    /// Option flags.
+    pub flags: u32,
+    /// Base address of descriptor table.
+    pub desc_user_addr: u64,
+    /// Base address of used vring.
+    pub used_user_addr: u64,
+    /// Base address of available vring.
+    pub avail_user_addr: u64,
+    /// Address where to write logs.
+    pub log_guest_addr: u64,
+}
+
+impl ByteCode for VhostUserVringAddr {}
+
+/// The header of the payload of `VHOST_USER_GET_CONFIG`/`VHOST_USER_SET_CONFIG`,
+/// followed by `size` bytes of device config space.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct VhostUserConfig {
+    /// Offset in the device config space.
+    pub offset: u32,
+    /// Size of the config space to access.
+    pub size: u32,
+    /// Flags for the access.
+    pub flags: u32,
+}
+
+impl ByteCode for VhostUserConfig {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vhost_user_msg_hdr() {
+        let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::GetFeatures, false, 0);
+        assert_eq!(hdr.get_request(), VhostUserMsgReq::GetFeatures);
+        assert_eq!(hdr.flags, VHOST_USER_VERSION);
+        assert!(!hdr.is_reply());
+        assert_eq!(hdr.as_bytes().len(), 12);
+
+        let hdr = VhostUserMsgHdr::new(VhostUserMsgReq::SetMemTable, true, 8);
+        assert_eq!(hdr.flags, VHOST_USER_VERSION | VHOST_USER_NEED_REPLY_MASK);
+        assert_eq!(hdr.size, 8);
+
+        let mut reply = VhostUserMsgHdr::new(VhostUserMsgReq::GetFeatures, false, 8);
+        reply.flags |= VHOST_USER_REPLY_MASK;
+        assert!(reply.is_reply());
+        reply.flags = VHOST_USER_REPLY_MASK | 0x2;
+        assert!(!reply.is_reply());
+
+        assert_eq!(VhostUserMsgReq::from(25), VhostUserMsgReq::SetConfig);
+        assert_eq!(VhostUserMsgReq::from(100), VhostUserMsgReq::MaxCmd);
+    }
+
+    #[test]
+    fn test_vhost_user_payload_size() {
+        assert_eq!(std::mem::size_of::<VhostUserU64>(), 8);
+        assert_eq!(std::mem::size_of::<VhostUserMemHdr>(), 8);
+        assert_eq!(std::mem::size_of::<VhostUserMemoryRegion>(), 32);
+        assert_eq!(std::mem::size_of::<VhostUserVringState>(), 8);
+        assert_eq!(std::mem::size_of::<VhostUserVringAddr>(), 40);
+        assert_eq!(std::mem::size_of::<VhostUserConfig>(), 12);
+    }
+}
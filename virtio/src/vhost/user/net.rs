@@ -0,0 +1,370 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+
+use address_space::AddressSpace;
+use machine_manager::{config::NetworkInterfaceConfig, event_loop::EventLoop};
+use util::byte_code::ByteCode;
+use util::loop_context::EventNotifierHelper;
+use util::num_ops::{read_u32, write_u32};
+use vmm_sys_util::eventfd::EventFd;
+
+use super::super::super::errors::{ErrorKind, Result, ResultExt};
+use super::super::super::{
+    net::{build_device_config_space, VirtioNetConfig},
+    Queue, VirtioDevice, VirtioInterrupt, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
+    VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4,
+    VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MRG_RXBUF,
+    VIRTIO_TYPE_NET,
+};
+use super::super::{VhostIoHandler, VhostNotify, VhostOps};
+use super::message::VHOST_USER_F_PROTOCOL_FEATURES;
+use super::VhostUserClient;
+
+/// Number of virtqueues.
+const QUEUE_NUM_NET: usize = 2;
+/// Size of each virtqueue.
+const QUEUE_SIZE_NET: u16 = 256;
+
+/// Network device structure.
+pub struct Net {
+    /// Configuration of the vhost user network device.
+    net_cfg: NetworkInterfaceConfig,
+    /// Bit mask of features supported by the backend.
+    device_features: u64,
+    /// Bit mask of features negotiated by the backend and the frontend.
+    driver_features: u64,
+    /// Bit mask of features supported by the vhost-user slave.
+    vhost_features: u64,
+    /// Virtio net configurations.
+    device_config: VirtioNetConfig,
+    /// System address space.
+    mem_space: Arc<AddressSpace>,
+    /// Vhost user client.
+    client: Option<Arc<Mutex<VhostUserClient>>>,
+    /// EventFd for device deactivate.
+    deactivate_evt: EventFd,
+}
+
+impl Net {
+    pub fn new(cfg: &NetworkInterfaceConfig, mem_space: &Arc<AddressSpace>) -> Self {
+        Net {
+            net_cfg: cfg.clone(),
+            device_features: 0_u64,
+            driver_features: 0_u64,
+            vhost_features: 0_u64,
+            device_config: VirtioNetConfig::default(),
+            mem_space: mem_space.clone(),
+            client: None,
+            deactivate_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+        }
+    }
+
+    fn build_config_space(&mut self) {
+        self.device_config = VirtioNetConfig::default();
+        if let Some(mac) = &self.net_cfg.mac {
+            self.device_features |= build_device_config_space(&mut self.device_config, mac);
+        }
+    }
+}
+
+impl VirtioDevice for Net {
+    /// Realize vhost user network device.
+    fn realize(&mut self) -> Result<()> {
+        let socket_path = self
+            .net_cfg
+            .socket_path
+            .as_ref()
+            .chain_err(|| "Failed to get socket path for vhost user net")?;
+        let mut client = VhostUserClient::new(&self.mem_space, socket_path)
+            .chain_err(|| "Failed to create the client which communicates with the slave")?;
+        client
+            .set_owner()
+            .chain_err(|| "Failed to set owner for vhost user net")?;
+        client
+            .negotiate_protocol_features(0)
+            .chain_err(|| "Failed to negotiate protocol features for vhost user net")?;
+        let vhost_features = client
+            .get_features()
+            .chain_err(|| "Failed to get features for vhost user net")?;
+
+        let supported_features = 1 << VIRTIO_F_VERSION_1
+            | 1 << VIRTIO_F_RING_INDIRECT_DESC
+            | 1 << VIRTIO_F_RING_EVENT_IDX
+            | 1 << VIRTIO_NET_F_CSUM
+            | 1 << VIRTIO_NET_F_GUEST_CSUM
+            | 1 << VIRTIO_NET_F_GUEST_TSO4
+            | 1 << VIRTIO_NET_F_GUEST_UFO
+            | 1 << VIRTIO_NET_F_HOST_TSO4
+            | 1 << VIRTIO_NET_F_HOST_UFO
+            | 1 << VIRTIO_NET_F_MRG_RXBUF;
+        self.device_features = vhost_features & supported_features;
+        self.vhost_features = vhost_features;
+        self.build_config_space();
+
+        let client = Arc::new(Mutex::new(client));
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(client.clone()),
+            None,
+        )
+        .chain_err(|| "Failed to update event for vhost user net")?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        if let Some(client) = self.client.take() {
+            let locked_client = client.lock().unwrap();
+            locked_client
+                .delete_event()
+                .chain_err(|| "Failed to delete vhost user net event")?;
+            locked_client.unregister_listener()?;
+        }
+        Ok(())
+    }
+
+    /// Get the virtio device type, refer to Virtio Spec.
+    fn device_type(&self) -> u32 {
+        VIRTIO_TYPE_NET
+    }
+
+    /// Get the count of virtio device queues.
+    fn queue_num(&self) -> usize {
+        QUEUE_NUM_NET
+    }
+
+    /// Get the queue size of virtio device.
+    fn queue_size(&self) -> u16 {
+        QUEUE_SIZE_NET
+    }
+
+    /// Get device features from host.
+    fn get_device_features(&self, features_select: u32) -> u32 {
+        read_u32(self.device_features, features_select)
+    }
+
+    /// Set driver features by guest.
+    fn set_driver_features(&mut self, page: u32, value: u32) {
+        let mut features = write_u32(value, page);
+        let unsupported_features = features & !self.device_features;
+        if unsupported_features != 0 {
+            warn!(
+                "Received acknowledge request with unsupported feature for vhost user net: 0x{:x}",
+                features
+            );
+            features &= !unsupported_features;
+        }
+        self.driver_features |= features;
+    }
+
+    /// Read data of config from guest.
+    fn read_config(&self, offset: u64, mut data: &mut [u8]) -> Result<()> {
+        let config_slice = self.device_config.as_bytes();
+        let config_size = config_slice.len() as u64;
+        if offset >= config_size {
+            return Err(ErrorKind::DevConfigOverflow(offset, config_size).into());
+        }
+        if let Some(end) = offset.checked_add(data.len() as u64) {
+            data.write_all(&config_slice[offset as usize..cmp::min(end, config_size) as usize])?;
+        }
+
+        Ok(())
+    }
+
+    /// Write data to config from guest.
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        let data_len = data.len();
+        let config_slice = self.device_config.as_mut_bytes();
+        let config_len = config_slice.len();
+        if offset as usize + data_len > config_len {
+            return Err(ErrorKind::DevConfigOverflow(offset, config_len as u64).into());
+        }
+
+        config_slice[(offset as usize)..(offset as usize + data_len)].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    /// Activate the virtio device, this function is called by vcpu thread when frontend
+    /// virtio driver is ready and write `DRIVER_OK` to backend.
+    fn activate(
+        &mut self,
+        _mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queues: &[Arc<Mutex<Queue>>],
+        queue_evts: Vec<EventFd>,
+    ) -> Result<()> {
+        let client = match &self.client {
+            None => return Err("Failed to get client for vhost user net".into()),
+            Some(client_) => client_,
+        };
+
+        let mut host_notifies = Vec::new();
+        let mut call_evts = Vec::new();
+        for queue in queues.iter() {
+            let host_notify = VhostNotify {
+                notify_evt: EventFd::new(libc::EFD_NONBLOCK)
+                    .chain_err(|| ErrorKind::EventFdCreate)?,
+                queue: queue.clone(),
+            };
+            call_evts.push(
+                host_notify
+                    .notify_evt
+                    .try_clone()
+                    .chain_err(|| "Failed to clone eventfd for vhost user net")?,
+            );
+            host_notifies.push(host_notify);
+        }
+
+        let mut locked_client = client.lock().unwrap();
+        locked_client.features =
+            self.driver_features | (self.vhost_features & 1 << VHOST_USER_F_PROTOCOL_FEATURES);
+        locked_client.set_queues(queues);
+        locked_client.set_queue_evts(&queue_evts)?;
+        locked_client.set_call_evts(&call_evts)?;
+        locked_client
+            .activate_vhost_vring()
+            .chain_err(|| "Failed to activate vrings for vhost user net")?;
+        drop(locked_client);
+
+        let handler = VhostIoHandler {
+            interrupt_cb,
+            host_notifies,
+            deactivate_evt: self.deactivate_evt.as_raw_fd(),
+        };
+
+        EventLoop::update_event(
+            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler))),
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        if let Some(client) = &self.client {
+            client
+                .lock()
+                .unwrap()
+                .reset_vhost_user()
+                .chain_err(|| "Failed to reset vhost user net")?;
+        }
+        self.deactivate_evt
+            .write(1)
+            .chain_err(|| ErrorKind::EventFdWrite)?;
+
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.driver_features = 0_u64;
+        self.build_config_space();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use address_space::*;
+
+    const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
+
+    fn vhost_address_space_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 36);
+        let sys_space = AddressSpace::new(root).unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0),
+                None,
+                SYSTEM_SPACE_SIZE,
+                None,
+                false,
+                false,
+                false,
+            )
+            .unwrap(),
+        );
+        sys_space
+            .root()
+            .add_subregion(
+                Region::init_ram_region(host_mmap.clone()),
+                host_mmap.start_address().raw_value(),
+            )
+            .unwrap();
+        sys_space
+    }
+
+    #[test]
+    fn test_vhost_user_net_realize() {
+        let mut net_cfg = NetworkInterfaceConfig {
+            id: "net0".to_string(),
+            mac: Some("1A:2B:3C:4D:5E:6F".to_string()),
+            vhost_type: Some("vhost-user".to_string()),
+            ..Default::default()
+        };
+        let sys_space = vhost_address_space_init();
+
+        // The socket path is missing.
+        let mut net = Net::new(&net_cfg, &sys_space);
+        assert!(net.realize().is_err());
+
+        // The slave is not listening.
+        net_cfg.socket_path = Some("/path/to/not/exist/vhost-user.sock".to_string());
+        let mut net = Net::new(&net_cfg, &sys_space);
+        assert!(net.realize().is_err());
+        assert!(net
+            .activate(
+                sys_space.clone(),
+                Arc::new(Box::new(|_, _| Ok(()))),
+                &[],
+                Vec::new()
+            )
+            .is_err());
+
+        // The mac address is reported through config space after reset.
+        assert!(net.reset().is_ok());
+        assert_eq!(net.device_config.mac, [0x1A, 0x2B, 0x3C, 0x4D, 0x5E, 0x6F]);
+        let mut mac = [0_u8; 6];
+        assert!(net.read_config(0, &mut mac).is_ok());
+        assert_eq!(mac, [0x1A, 0x2B, 0x3C, 0x4D, 0x5E, 0x6F]);
+        let len = net.device_config.as_bytes().len() as u64;
+        assert!(net.read_config(len, &mut mac).is_err());
+        assert!(net.write_config(len - 1, &mac).is_err());
+    }
+
+    #[test]
+    fn test_vhost_user_net_features() {
+        let net_cfg = NetworkInterfaceConfig::default();
+        let sys_space = vhost_address_space_init();
+        let mut net = Net::new(&net_cfg, &sys_space);
+
+        net.device_features = 1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_NET_F_CSUM;
+        net.set_driver_features(0, 0xff);
+        assert_eq!(net.driver_features, 1 << VIRTIO_NET_F_CSUM);
+        net.set_driver_features(1, 0xff);
+        assert_eq!(
+            net.driver_features,
+            1 << VIRTIO_F_VERSION_1 | 1 << VIRTIO_NET_F_CSUM
+        );
+        assert_eq!(net.get_device_features(1), 1);
+
+        assert!(net.reset().is_ok());
+        assert_eq!(net.driver_features, 0);
+    }
+}
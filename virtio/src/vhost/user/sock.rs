@@ -0,0 +1,233 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::io::Read;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use libc::{
+    c_uint, c_void, iovec, msghdr, sendmsg, CMSG_DATA, CMSG_FIRSTHDR, CMSG_LEN, CMSG_SPACE,
+    MSG_NOSIGNAL, SCM_RIGHTS, SOL_SOCKET,
+};
+use util::byte_code::ByteCode;
+
+use super::super::super::errors::{Result, ResultExt};
+use super::message::{
+    VhostUserMsgHdr, VHOST_USER_MAX_REGIONS, VHOST_USER_REPLY_MASK, VHOST_USER_VERSION,
+};
+
+/// Timeout of sending and receiving messages, so that the vcpu or main thread is not
+/// blocked forever by a slave which is hung (5s).
+const VHOST_USER_SOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// The maximum size of the body of requests sent by the slave.
+const VHOST_USER_SLAVE_MSG_MAX_SIZE: usize = 4096;
+
+/// The unix domain socket connected with the vhost-user slave.
+pub struct VhostUserSock {
+    /// Path of the unix domain socket.
+    pub path: String,
+    /// The connected stream.
+    stream: UnixStream,
+}
+
+impl VhostUserSock {
+    /// Connect to the vhost-user slave listening on `path`.
+    pub fn connect(path: &str) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .chain_err(|| format!("Failed to connect to vhost-user socket {}", path))?;
+        Self::from_stream(path, stream)
+    }
+
+    /// Create the socket with the stream connected with the vhost-user slave, e.g. the
+    /// slave channel.
+    pub fn from_stream(path: &str, stream: UnixStream) -> Result<Self> {
+        stream
+            .set_read_timeout(Some(VHOST_USER_SOCK_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(VHOST_USER_SOCK_TIMEOUT)))
+            .chain_err(|| format!("Failed to set timeout of vhost-user socket {}", path))?;
+        Ok(VhostUserSock {
+            path: path.to_string(),
+            stream,
+        })
+    }
+
+    /// Send a message to the slave.
+    ///
+    /// # Arguments
+    ///
+    /// * `hdr` - The message header.
+    /// * `body` - The fixed-size body following the header.
+    /// * `payload` - The variable-size payload following the body.
+    /// * `fds` - The file descriptors passed with the message through `SCM_RIGHTS`.
+    pub fn send_msg<D: ByteCode>(
+        &self,
+        hdr: &VhostUserMsgHdr,
+        body: Option<&D>,
+        payload: &[u8],
+        fds: &[RawFd],
+    ) -> Result<()> {
+        if fds.len() > VHOST_USER_MAX_REGIONS {
+            bail!(
+                "Too many fds {} for vhost-user message, the maximum is {}",
+                fds.len(),
+                VHOST_USER_MAX_REGIONS
+            );
+        }
+
+        let mut iovs = vec![iovec {
+            iov_base: hdr.as_bytes().as_ptr() as *mut c_void,
+            iov_len: size_of::<VhostUserMsgHdr>(),
+        }];
+        if let Some(body) = body {
+            iovs.push(iovec {
+                iov_base: body.as_bytes().as_ptr() as *mut c_void,
+                iov_len: size_of::<D>(),
+            });
+        }
+        if !payload.is_empty() {
+            iovs.push(iovec {
+                iov_base: payload.as_ptr() as *mut c_void,
+                iov_len: payload.len(),
+            });
+        }
+        let total_len: usize = iovs.iter().map(|iov| iov.iov_len).sum();
+
+        // In `musl` toolchain, msghdr has private member `__pad0` and `__pad1`, it can't be
+        // initialized in normal way.
+        let mut mhdr: msghdr = unsafe { std::mem::zeroed() };
+        mhdr.msg_iov = iovs.as_mut_ptr();
+        mhdr.msg_iovlen = iovs.len() as _;
+
+        // Use u64 to make sure the control buffer is aligned for `cmsghdr`.
+        let mut cmsg_buffer: Vec<u64>;
+        if !fds.is_empty() {
+            let fds_len = std::mem::size_of_val(fds) as c_uint;
+            let cmsg_space = unsafe { CMSG_SPACE(fds_len) } as usize;
+            cmsg_buffer = vec![0_u64; (cmsg_space + size_of::<u64>() - 1) / size_of::<u64>()];
+            mhdr.msg_control = cmsg_buffer.as_mut_ptr() as *mut c_void;
+            mhdr.msg_controllen = cmsg_space as _;
+
+            // Safe because the control buffer is large enough to hold the header and the fds.
+            unsafe {
+                let cmsg = CMSG_FIRSTHDR(&mhdr as *const msghdr);
+                (*cmsg).cmsg_level = SOL_SOCKET;
+                (*cmsg).cmsg_type = SCM_RIGHTS;
+                (*cmsg).cmsg_len = CMSG_LEN(fds_len) as _;
+                std::ptr::copy_nonoverlapping(
+                    fds.as_ptr(),
+                    CMSG_DATA(cmsg) as *mut RawFd,
+                    fds.len(),
+                );
+            }
+        }
+
+        // MSG_NOSIGNAL: do not generate SIGPIPE when the slave has closed the connection.
+        let ret = unsafe { sendmsg(self.stream.as_raw_fd(), &mhdr, MSG_NOSIGNAL) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error())
+                .chain_err(|| format!("Failed to send msg {} to vhost-user slave", hdr.request));
+        }
+        if ret as usize != total_len {
+            bail!(
+                "Incomplete msg {} sent to vhost-user slave, expected {} bytes, sent {} bytes",
+                hdr.request,
+                total_len,
+                ret
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Send the reply of `request` to the slave.
+    pub fn send_reply<D: ByteCode>(&self, request: &VhostUserMsgHdr, body: &D) -> Result<()> {
+        let hdr = VhostUserMsgHdr {
+            request: request.request,
+            flags: VHOST_USER_VERSION | VHOST_USER_REPLY_MASK,
+            size: size_of::<D>() as u32,
+        };
+        self.send_msg(&hdr, Some(body), &[], &[])
+    }
+
+    /// Receive a request sent by the slave through the slave channel, and return the
+    /// header and the body of it.
+    pub fn recv_msg(&self) -> Result<(VhostUserMsgHdr, Vec<u8>)> {
+        let mut hdr = VhostUserMsgHdr::default();
+        (&self.stream)
+            .read_exact(hdr.as_mut_bytes())
+            .chain_err(|| "Failed to receive msg header from vhost-user slave")?;
+        if hdr.size as usize > VHOST_USER_SLAVE_MSG_MAX_SIZE {
+            bail!(
+                "Invalid msg size {} from vhost-user slave, the maximum is {}",
+                hdr.size,
+                VHOST_USER_SLAVE_MSG_MAX_SIZE
+            );
+        }
+        let mut body = vec![0_u8; hdr.size as usize];
+        (&self.stream)
+            .read_exact(&mut body)
+            .chain_err(|| "Failed to receive msg body from vhost-user slave")?;
+        Ok((hdr, body))
+    }
+
+    /// Receive the reply of `request` from the slave, and return the body of it.
+    pub fn recv_reply<D: ByteCode>(&self, request: &VhostUserMsgHdr) -> Result<D> {
+        let (body, _) = self.recv_reply_with_payload::<D>(request, 0)?;
+        Ok(body)
+    }
+
+    /// Receive the reply of `request` from the slave, and return the body and the
+    /// `payload_len` bytes of payload following the body.
+    pub fn recv_reply_with_payload<D: ByteCode>(
+        &self,
+        request: &VhostUserMsgHdr,
+        payload_len: usize,
+    ) -> Result<(D, Vec<u8>)> {
+        let mut hdr = VhostUserMsgHdr::default();
+        (&self.stream)
+            .read_exact(hdr.as_mut_bytes())
+            .chain_err(|| "Failed to receive reply header from vhost-user slave")?;
+        if !hdr.is_reply() || hdr.get_request() != request.get_request() {
+            bail!(
+                "Invalid reply from vhost-user slave, request {:?} flags 0x{:x}, expected request {:?}",
+                hdr.get_request(),
+                hdr.flags,
+                request.get_request()
+            );
+        }
+        if hdr.size as usize != size_of::<D>() + payload_len {
+            bail!(
+                "Invalid reply size {} from vhost-user slave, expected {}",
+                hdr.size,
+                size_of::<D>() + payload_len
+            );
+        }
+
+        let mut body = D::default();
+        (&self.stream)
+            .read_exact(body.as_mut_bytes())
+            .chain_err(|| "Failed to receive reply body from vhost-user slave")?;
+        let mut payload = vec![0_u8; payload_len];
+        (&self.stream)
+            .read_exact(&mut payload)
+            .chain_err(|| "Failed to receive reply payload from vhost-user slave")?;
+        Ok((body, payload))
+    }
+}
+
+impl AsRawFd for VhostUserSock {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
@@ -0,0 +1,605 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! A small vhost-user-blk slave backed by an in-memory disk, which is used to test
+//! the vhost-user master without external services.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{fence, AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use libc::{c_void, iovec, msghdr, recvmsg, CMSG_DATA, CMSG_FIRSTHDR, CMSG_LEN, CMSG_NXTHDR};
+use util::byte_code::ByteCode;
+
+use super::super::super::{
+    VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
+};
+use super::message::{
+    VhostUserConfig, VhostUserMemHdr, VhostUserMemoryRegion, VhostUserMsgHdr, VhostUserMsgReq,
+    VhostUserU64, VhostUserVringAddr, VhostUserVringState, VHOST_USER_F_PROTOCOL_FEATURES,
+    VHOST_USER_PROTOCOL_F_CONFIG, VHOST_USER_PROTOCOL_F_MQ, VHOST_USER_PROTOCOL_F_SLAVE_REQ,
+    VHOST_USER_REPLY_MASK, VHOST_USER_SLAVE_CONFIG_CHANGE_MSG, VHOST_USER_VERSION,
+    VHOST_USER_VRING_IDX_MASK, VHOST_USER_VRING_NOFD_MASK,
+};
+
+/// Number of sectors of the in-memory disk.
+pub const TEST_BLK_SECTORS: u64 = 16;
+/// Serial number reported by `VIRTIO_BLK_T_GET_ID`.
+pub const TEST_BLK_ID: &[u8] = b"vhost-user-blk-test";
+/// Size of the config space of virtio block device.
+pub const TEST_BLK_CONFIG_SIZE: usize = 60;
+
+const SECTOR_SIZE: u64 = 512;
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+const VRING_DESC_F_NEXT: u16 = 0x1;
+/// Interval of checking whether the slave should stop or disconnect (ms).
+const POLL_TIMEOUT_MS: i32 = 10;
+
+/// State of the slave, which can be inspected by the tests.
+#[derive(Default)]
+pub struct TestBlkState {
+    /// Requests received from the master, in order.
+    pub requests: Vec<VhostUserMsgReq>,
+    /// Virtio features acked by the master.
+    pub acked_features: u64,
+    /// Protocol features acked by the master.
+    pub acked_protocol_features: u64,
+    /// Config space of the device.
+    pub config: Vec<u8>,
+    /// Content of the disk.
+    pub disk: Vec<u8>,
+    /// Number of connections accepted from the master.
+    pub connections: usize,
+    /// The slave channel set up by the master.
+    pub slave: Option<UnixStream>,
+}
+
+/// The vhost-user-blk slave, serving the master in a separate thread.
+pub struct TestBlkBackend {
+    path: String,
+    /// State of the slave.
+    pub state: Arc<Mutex<TestBlkState>>,
+    stop: Arc<AtomicBool>,
+    disconnect: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestBlkBackend {
+    /// Start a slave listening on `path`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the unix domain socket.
+    /// * `features` - Virtio features supported by the slave.
+    /// * `num_queues` - The maximum number of queues supported by the slave.
+    pub fn new(path: &str, features: u64, num_queues: u16) -> Self {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+
+        let mut config = vec![0_u8; TEST_BLK_CONFIG_SIZE];
+        config[0..8].copy_from_slice(&TEST_BLK_SECTORS.to_le_bytes());
+        config[20..24].copy_from_slice(&(SECTOR_SIZE as u32).to_le_bytes());
+        config[34..36].copy_from_slice(&num_queues.to_le_bytes());
+        let state = Arc::new(Mutex::new(TestBlkState {
+            config,
+            disk: vec![0_u8; (TEST_BLK_SECTORS * SECTOR_SIZE) as usize],
+            ..Default::default()
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let disconnect = Arc::new(AtomicBool::new(false));
+
+        let thread_state = state.clone();
+        let thread_stop = stop.clone();
+        let thread_disconnect = disconnect.clone();
+        let features = features | 1 << VHOST_USER_F_PROTOCOL_FEATURES;
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if thread_stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+                thread_state.lock().unwrap().connections += 1;
+                let mut conn = Connection::new(stream, features, num_queues, thread_state.clone());
+                conn.serve(&thread_stop, &thread_disconnect);
+            }
+        });
+
+        TestBlkBackend {
+            path: path.to_string(),
+            state,
+            stop,
+            disconnect,
+            thread: Some(thread),
+        }
+    }
+
+    /// Change the capacity of the disk, and notify the master through the slave channel.
+    pub fn resize(&self, sectors: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.config[0..8].copy_from_slice(&sectors.to_le_bytes());
+        let hdr = VhostUserMsgHdr {
+            request: VHOST_USER_SLAVE_CONFIG_CHANGE_MSG,
+            flags: VHOST_USER_VERSION,
+            size: 0,
+        };
+        state
+            .slave
+            .as_ref()
+            .unwrap()
+            .write_all(hdr.as_bytes())
+            .unwrap();
+    }
+
+    /// Close the current connection, as if the slave has been restarted. The slave
+    /// must have been connected by the master.
+    pub fn disconnect(&self) {
+        self.disconnect.store(true, Ordering::SeqCst);
+        // Wait for the connection to be closed.
+        while self.disconnect.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl Drop for TestBlkBackend {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the thread which is waiting for new connection.
+        let _ = UnixStream::connect(&self.path);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Guest memory region shared by the master.
+struct MappedRegion {
+    region: VhostUserMemoryRegion,
+    /// Start of the mapping in the slave.
+    mmap_base: *mut u8,
+    mmap_size: usize,
+}
+
+impl MappedRegion {
+    fn host_addr(&self, region_base: u64, addr: u64, len: u64) -> Option<*mut u8> {
+        if addr < region_base || addr + len > region_base + self.region.memory_size {
+            return None;
+        }
+        let offset = self.region.mmap_offset + addr - region_base;
+        // Safe because the offset is checked to be inside the mapping.
+        Some(unsafe { self.mmap_base.add(offset as usize) })
+    }
+}
+
+impl Drop for MappedRegion {
+    fn drop(&mut self) {
+        // Safe because the mapping is created by this region and only unmapped here.
+        unsafe { libc::munmap(self.mmap_base as *mut c_void, self.mmap_size) };
+    }
+}
+
+#[derive(Default)]
+struct Vring {
+    size: u16,
+    desc: u64,
+    avail: u64,
+    used: u64,
+    last_avail_idx: u16,
+    kick: Option<File>,
+    call: Option<File>,
+    enabled: bool,
+}
+
+struct Connection {
+    stream: UnixStream,
+    features: u64,
+    num_queues: u16,
+    state: Arc<Mutex<TestBlkState>>,
+    regions: Vec<MappedRegion>,
+    vrings: Vec<Vring>,
+}
+
+impl Connection {
+    fn new(
+        stream: UnixStream,
+        features: u64,
+        num_queues: u16,
+        state: Arc<Mutex<TestBlkState>>,
+    ) -> Self {
+        let mut vrings = Vec::new();
+        vrings.resize_with(num_queues as usize, Vring::default);
+        Connection {
+            stream,
+            features,
+            num_queues,
+            state,
+            regions: Vec::new(),
+            vrings,
+        }
+    }
+
+    fn serve(&mut self, stop: &AtomicBool, disconnect: &AtomicBool) {
+        loop {
+            if stop.load(Ordering::SeqCst) || disconnect.swap(false, Ordering::SeqCst) {
+                return;
+            }
+
+            let mut pollfds = vec![libc::pollfd {
+                fd: self.stream.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            }];
+            let mut kicked_queues = Vec::new();
+            for (index, vring) in self.vrings.iter().enumerate() {
+                if let (true, Some(kick)) = (vring.enabled, &vring.kick) {
+                    pollfds.push(libc::pollfd {
+                        fd: kick.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    });
+                    kicked_queues.push(index);
+                }
+            }
+            // Safe because the pollfds are valid during the call.
+            let ret = unsafe {
+                libc::poll(
+                    pollfds.as_mut_ptr(),
+                    pollfds.len() as libc::nfds_t,
+                    POLL_TIMEOUT_MS,
+                )
+            };
+            if ret <= 0 {
+                continue;
+            }
+
+            if pollfds[0].revents != 0 && !self.handle_msg() {
+                return;
+            }
+            for (pollfd, index) in pollfds[1..].iter().zip(kicked_queues) {
+                if pollfd.revents & libc::POLLIN != 0 {
+                    let mut buf = [0_u8; 8];
+                    if let Some(kick) = self.vrings[index].kick.as_mut() {
+                        let _ = kick.read(&mut buf);
+                    }
+                    self.process_queue(index);
+                }
+            }
+        }
+    }
+
+    /// Receive a message and the fds passed with it from the master.
+    fn recv_msg(&mut self) -> Option<(VhostUserMsgHdr, Vec<u8>, Vec<File>)> {
+        let mut hdr = VhostUserMsgHdr::default();
+        let mut iov = iovec {
+            iov_base: hdr.as_mut_bytes().as_mut_ptr() as *mut c_void,
+            iov_len: size_of::<VhostUserMsgHdr>(),
+        };
+        let mut cmsg_buffer = vec![0_u64; 64];
+        // Safe because msghdr only contains integers and pointers.
+        let mut mhdr: msghdr = unsafe { std::mem::zeroed() };
+        mhdr.msg_iov = &mut iov;
+        mhdr.msg_iovlen = 1;
+        mhdr.msg_control = cmsg_buffer.as_mut_ptr() as *mut c_void;
+        mhdr.msg_controllen = (cmsg_buffer.len() * size_of::<u64>()) as _;
+
+        // Safe because the buffers are valid during the call.
+        let ret = unsafe { recvmsg(self.stream.as_raw_fd(), &mut mhdr, 0) };
+        if ret != size_of::<VhostUserMsgHdr>() as isize {
+            return None;
+        }
+
+        let mut files = Vec::new();
+        // Safe because the control messages are filled by the kernel.
+        unsafe {
+            let mut cmsg = CMSG_FIRSTHDR(&mhdr);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let fds_len = (*cmsg).cmsg_len as usize - CMSG_LEN(0) as usize;
+                    let fds = CMSG_DATA(cmsg) as *const RawFd;
+                    for i in 0..fds_len / size_of::<RawFd>() {
+                        files.push(File::from_raw_fd(std::ptr::read_unaligned(fds.add(i))));
+                    }
+                }
+                cmsg = CMSG_NXTHDR(&mhdr, cmsg);
+            }
+        }
+
+        let mut body = vec![0_u8; hdr.size as usize];
+        self.stream.read_exact(&mut body).ok()?;
+        Some((hdr, body, files))
+    }
+
+    fn reply<D: ByteCode>(&mut self, request: &VhostUserMsgHdr, body: &D, payload: &[u8]) {
+        let hdr = VhostUserMsgHdr {
+            request: request.request,
+            flags: VHOST_USER_VERSION | VHOST_USER_REPLY_MASK,
+            size: (size_of::<D>() + payload.len()) as u32,
+        };
+        let _ = self.stream.write_all(hdr.as_bytes());
+        let _ = self.stream.write_all(body.as_bytes());
+        let _ = self.stream.write_all(payload);
+    }
+
+    /// Handle one message from the master, return false if the connection is closed.
+    fn handle_msg(&mut self) -> bool {
+        let (hdr, body, mut files) = match self.recv_msg() {
+            Some(msg) => msg,
+            None => return false,
+        };
+        let request = hdr.get_request();
+        self.state.lock().unwrap().requests.push(request);
+
+        match request {
+            VhostUserMsgReq::GetFeatures => {
+                self.reply(&hdr, &VhostUserU64::new(self.features), &[]);
+            }
+            VhostUserMsgReq::SetFeatures => {
+                let features = VhostUserU64::from_bytes(&body).unwrap().value;
+                self.state.lock().unwrap().acked_features = features;
+                if features & (1 << VHOST_USER_F_PROTOCOL_FEATURES) == 0 {
+                    // Vrings are enabled once kicked if protocol features are not used.
+                    for vring in self.vrings.iter_mut() {
+                        vring.enabled = true;
+                    }
+                }
+            }
+            VhostUserMsgReq::GetProtocolFeatures => {
+                let features = 1 << VHOST_USER_PROTOCOL_F_MQ
+                    | 1 << VHOST_USER_PROTOCOL_F_CONFIG
+                    | 1 << VHOST_USER_PROTOCOL_F_SLAVE_REQ;
+                self.reply(&hdr, &VhostUserU64::new(features), &[]);
+            }
+            VhostUserMsgReq::SetProtocolFeatures => {
+                let features = VhostUserU64::from_bytes(&body).unwrap().value;
+                self.state.lock().unwrap().acked_protocol_features = features;
+            }
+            VhostUserMsgReq::GetQueueNum => {
+                self.reply(&hdr, &VhostUserU64::new(u64::from(self.num_queues)), &[]);
+            }
+            VhostUserMsgReq::SetMemTable => {
+                let hdr_len = size_of::<VhostUserMemHdr>();
+                let mem_hdr = VhostUserMemHdr::from_bytes(&body[..hdr_len]).unwrap();
+                let region_len = size_of::<VhostUserMemoryRegion>();
+                self.regions.clear();
+                for (index, file) in files.iter().enumerate().take(mem_hdr.nregions as usize) {
+                    let start = hdr_len + index * region_len;
+                    let region =
+                        *VhostUserMemoryRegion::from_bytes(&body[start..start + region_len])
+                            .unwrap();
+                    let mmap_size = (region.mmap_offset + region.memory_size) as usize;
+                    // Safe because the fd is a valid memory file passed by the master.
+                    let mmap_base = unsafe {
+                        libc::mmap(
+                            std::ptr::null_mut(),
+                            mmap_size,
+                            libc::PROT_READ | libc::PROT_WRITE,
+                            libc::MAP_SHARED,
+                            file.as_raw_fd(),
+                            0,
+                        )
+                    };
+                    assert_ne!(mmap_base, libc::MAP_FAILED);
+                    self.regions.push(MappedRegion {
+                        region,
+                        mmap_base: mmap_base as *mut u8,
+                        mmap_size,
+                    });
+                }
+            }
+            VhostUserMsgReq::SetVringNum => {
+                let state = VhostUserVringState::from_bytes(&body).unwrap();
+                self.vrings[state.index as usize].size = state.value as u16;
+            }
+            VhostUserMsgReq::SetVringAddr => {
+                let addr = *VhostUserVringAddr::from_bytes(&body).unwrap();
+                let desc = self.va_to_host(addr.desc_user_addr).unwrap();
+                let avail = self.va_to_host(addr.avail_user_addr).unwrap();
+                let used = self.va_to_host(addr.used_user_addr).unwrap();
+                let vring = &mut self.vrings[addr.index as usize];
+                vring.desc = desc as u64;
+                vring.avail = avail as u64;
+                vring.used = used as u64;
+            }
+            VhostUserMsgReq::SetVringBase => {
+                let state = VhostUserVringState::from_bytes(&body).unwrap();
+                self.vrings[state.index as usize].last_avail_idx = state.value as u16;
+            }
+            VhostUserMsgReq::GetVringBase => {
+                let state = *VhostUserVringState::from_bytes(&body).unwrap();
+                let vring = &mut self.vrings[state.index as usize];
+                vring.kick = None;
+                vring.enabled = false;
+                let reply = VhostUserVringState::new(state.index, u32::from(vring.last_avail_idx));
+                self.reply(&hdr, &reply, &[]);
+            }
+            VhostUserMsgReq::SetVringKick | VhostUserMsgReq::SetVringCall => {
+                let value = VhostUserU64::from_bytes(&body).unwrap().value;
+                let file = if value & VHOST_USER_VRING_NOFD_MASK == 0 {
+                    files.pop()
+                } else {
+                    None
+                };
+                let vring = &mut self.vrings[(value & VHOST_USER_VRING_IDX_MASK) as usize];
+                if request == VhostUserMsgReq::SetVringKick {
+                    vring.kick = file;
+                } else {
+                    vring.call = file;
+                }
+            }
+            VhostUserMsgReq::SetVringEnable => {
+                let state = VhostUserVringState::from_bytes(&body).unwrap();
+                self.vrings[state.index as usize].enabled = state.value != 0;
+            }
+            VhostUserMsgReq::GetConfig => {
+                let config =
+                    *VhostUserConfig::from_bytes(&body[..size_of::<VhostUserConfig>()]).unwrap();
+                let start = config.offset as usize;
+                let end = start + config.size as usize;
+                let payload = self.state.lock().unwrap().config[start..end].to_vec();
+                self.reply(&hdr, &config, &payload);
+            }
+            VhostUserMsgReq::SetSlaveReqFd => {
+                // Safe because the fd is owned by the file and moved to the stream.
+                self.state.lock().unwrap().slave = files
+                    .pop()
+                    .map(|file| unsafe { UnixStream::from_raw_fd(file.into_raw_fd()) });
+            }
+            VhostUserMsgReq::SetConfig => {
+                let config_len = size_of::<VhostUserConfig>();
+                let config = VhostUserConfig::from_bytes(&body[..config_len]).unwrap();
+                let start = config.offset as usize;
+                let end = start + config.size as usize;
+                self.state.lock().unwrap().config[start..end].copy_from_slice(&body[config_len..]);
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn va_to_host(&self, addr: u64) -> Option<*mut u8> {
+        self.regions
+            .iter()
+            .find_map(|r| r.host_addr(r.region.userspace_addr, addr, 1))
+    }
+
+    fn gpa_to_host(&self, addr: u64, len: u64) -> Option<*mut u8> {
+        self.regions
+            .iter()
+            .find_map(|r| r.host_addr(r.region.guest_phys_addr, addr, len))
+    }
+
+    /// Handle all available requests in the queue, and notify the master.
+    fn process_queue(&mut self, index: usize) {
+        let vring = &self.vrings[index];
+        let (size, avail, used) = (vring.size, vring.avail, vring.used);
+        // Safe because the vring addresses are translated to the shared mappings.
+        unsafe {
+            let avail_idx = std::ptr::read_volatile((avail + 2) as *const u16);
+            fence(Ordering::Acquire);
+            while self.vrings[index].last_avail_idx != avail_idx {
+                let last_avail_idx = self.vrings[index].last_avail_idx;
+                let slot = u64::from(last_avail_idx % size);
+                let head = std::ptr::read_volatile((avail + 4 + 2 * slot) as *const u16);
+                let len = self.handle_request(index, head);
+
+                let used_idx = std::ptr::read_volatile((used + 2) as *const u16);
+                let elem = used + 4 + 8 * u64::from(used_idx % size);
+                std::ptr::write_volatile(elem as *mut u32, u32::from(head));
+                std::ptr::write_volatile((elem + 4) as *mut u32, len);
+                fence(Ordering::Release);
+                std::ptr::write_volatile((used + 2) as *mut u16, used_idx.wrapping_add(1));
+                self.vrings[index].last_avail_idx = last_avail_idx.wrapping_add(1);
+            }
+        }
+        if let Some(call) = self.vrings[index].call.as_mut() {
+            let _ = call.write(&1_u64.to_le_bytes());
+        }
+    }
+
+    /// Handle a block request, return the number of bytes written to the guest.
+    fn handle_request(&mut self, index: usize, head: u16) -> u32 {
+        let vring = &self.vrings[index];
+        let mut descs = Vec::new();
+        let mut desc_index = head;
+        loop {
+            let desc = vring.desc + 16 * u64::from(desc_index);
+            // Safe because the descriptor table is inside the shared mapping.
+            let (addr, len, flags, next) = unsafe {
+                (
+                    std::ptr::read_unaligned(desc as *const u64),
+                    std::ptr::read_unaligned((desc + 8) as *const u32),
+                    std::ptr::read_unaligned((desc + 12) as *const u16),
+                    std::ptr::read_unaligned((desc + 14) as *const u16),
+                )
+            };
+            let host_addr = match self.gpa_to_host(addr, u64::from(len)) {
+                Some(host_addr) => host_addr,
+                None => return 0,
+            };
+            descs.push((host_addr, len as usize));
+            if flags & VRING_DESC_F_NEXT == 0 {
+                break;
+            }
+            if descs.len() >= vring.size as usize {
+                return 0;
+            }
+            desc_index = next;
+        }
+        if descs.len() < 2 {
+            return 0;
+        }
+
+        // Safe because all the descriptors are inside the shared mapping.
+        unsafe {
+            let (header, _) = descs[0];
+            let request_type = std::ptr::read_unaligned(header as *const u32);
+            let sector = std::ptr::read_unaligned(header.add(8) as *const u64);
+            let data = &descs[1..descs.len() - 1];
+            let mut written = 0_usize;
+            let mut state = self.state.lock().unwrap();
+            let mut offset = (sector * SECTOR_SIZE) as usize;
+            let data_len: usize = data.iter().map(|(_, len)| *len).sum();
+            let status = match request_type {
+                VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT if offset + data_len > state.disk.len() => {
+                    VIRTIO_BLK_S_IOERR
+                }
+                VIRTIO_BLK_T_IN => {
+                    for (host_addr, len) in data {
+                        std::ptr::copy_nonoverlapping(
+                            state.disk[offset..].as_ptr(),
+                            *host_addr,
+                            *len,
+                        );
+                        offset += len;
+                    }
+                    written = data_len;
+                    VIRTIO_BLK_S_OK
+                }
+                VIRTIO_BLK_T_OUT => {
+                    for (host_addr, len) in data {
+                        std::ptr::copy_nonoverlapping(
+                            *host_addr,
+                            state.disk[offset..].as_mut_ptr(),
+                            *len,
+                        );
+                        offset += len;
+                    }
+                    VIRTIO_BLK_S_OK
+                }
+                VIRTIO_BLK_T_FLUSH => VIRTIO_BLK_S_OK,
+                VIRTIO_BLK_T_GET_ID => {
+                    if let Some((host_addr, len)) = data.first() {
+                        written = std::cmp::min(*len, TEST_BLK_ID.len());
+                        std::ptr::copy_nonoverlapping(TEST_BLK_ID.as_ptr(), *host_addr, written);
+                    }
+                    VIRTIO_BLK_S_OK
+                }
+                _ => VIRTIO_BLK_S_UNSUPP,
+            };
+            let (status_addr, _) = descs[descs.len() - 1];
+            std::ptr::write_volatile(status_addr, status);
+            (written + 1) as u32
+        }
+    }
+}
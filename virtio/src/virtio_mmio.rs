@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cmp;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
 
@@ -22,11 +23,12 @@ use sysbus::{SysBus, SysBusDevOps, SysBusDevType, SysRes};
 use util::byte_code::ByteCode;
 use vmm_sys_util::eventfd::EventFd;
 
+use super::notify_stats::register_notify_stats;
 use super::{
     virtio_has_feature, Queue, QueueConfig, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
-    CONFIG_STATUS_ACKNOWLEDGE, CONFIG_STATUS_DRIVER, CONFIG_STATUS_DRIVER_OK, CONFIG_STATUS_FAILED,
-    CONFIG_STATUS_FEATURES_OK, NOTIFY_REG_OFFSET, QUEUE_TYPE_PACKED_VRING, QUEUE_TYPE_SPLIT_VRING,
-    VIRTIO_F_RING_PACKED, VIRTIO_MMIO_INT_CONFIG, VIRTIO_MMIO_INT_VRING,
+    VirtioNotifyStats, CONFIG_STATUS_ACKNOWLEDGE, CONFIG_STATUS_DRIVER, CONFIG_STATUS_DRIVER_OK,
+    CONFIG_STATUS_FAILED, CONFIG_STATUS_FEATURES_OK, NOTIFY_REG_OFFSET, QUEUE_TYPE_PACKED_VRING,
+    QUEUE_TYPE_SPLIT_VRING, VIRTIO_F_RING_PACKED, VIRTIO_MMIO_INT_CONFIG, VIRTIO_MMIO_INT_VRING,
 };
 use crate::errors::{ErrorKind, Result, ResultExt};
 
@@ -338,6 +340,8 @@ pub struct VirtioMmioDevice {
     queues: Vec<Arc<Mutex<Queue>>>,
     // System Resource of device.
     res: SysRes,
+    // Counters of notifications between guest and device.
+    notify_stats: Arc<VirtioNotifyStats>,
 }
 
 impl VirtioMmioDevice {
@@ -357,6 +361,7 @@ impl VirtioMmioDevice {
             mem_space: mem_space.clone(),
             queues: Vec::new(),
             res: SysRes::default(),
+            notify_stats: Arc::new(VirtioNotifyStats::default()),
         }
     }
 
@@ -377,6 +382,14 @@ impl VirtioMmioDevice {
             bail!("Mmio region space exhausted.");
         }
         self.set_sys_resource(sysbus, region_base, region_size)?;
+        self.device
+            .lock()
+            .unwrap()
+            .set_notify_stats(self.notify_stats.clone());
+        register_notify_stats(
+            format!("virtio-mmio@0x{:08x}", region_base),
+            &self.notify_stats,
+        );
         let dev = Arc::new(Mutex::new(self));
         sysbus.attach_device(&dev, region_base, region_size)?;
 
@@ -396,8 +409,12 @@ impl VirtioMmioDevice {
     /// Activate the virtio device, this function is called by vcpu thread when frontend
     /// virtio driver is ready and write `DRIVER_OK` to backend.
     fn activate(&mut self) -> Result<()> {
-        let queues_config =
-            &mut self.state.config_space.queues_config[0..self.state.config_space.queue_num];
+        // The queues used may be less than the queues offered, depending on the features negotiated.
+        let queue_num = cmp::min(
+            self.device.lock().unwrap().queue_num(),
+            self.state.config_space.queue_num,
+        );
+        let queues_config = &mut self.state.config_space.queues_config[0..queue_num];
         let cloned_mem_space = self.mem_space.clone();
         for q_config in queues_config.iter_mut() {
             q_config.addr_cache.desc_table_host = cloned_mem_space
@@ -430,6 +447,7 @@ impl VirtioMmioDevice {
 
         let interrupt_status = self.interrupt_status.clone();
         let interrupt_evt = self.interrupt_evt.try_clone().unwrap();
+        let notify_stats = self.notify_stats.clone();
         let cb = Arc::new(Box::new(
             move |int_type: &VirtioInterruptType, _queue: Option<&Queue>| {
                 let status = match int_type {
@@ -440,6 +458,7 @@ impl VirtioMmioDevice {
                 interrupt_evt
                     .write(1)
                     .chain_err(|| ErrorKind::EventFdWrite)?;
+                notify_stats.account_interrupt();
 
                 Ok(())
             },
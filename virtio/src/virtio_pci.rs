@@ -32,8 +32,10 @@ use pci::{
 use util::{byte_code::ByteCode, num_ops::round_up, unix::host_page_size};
 use vmm_sys_util::eventfd::EventFd;
 
+use crate::notify_stats::register_notify_stats;
 use crate::{
     virtio_has_feature, Queue, QueueConfig, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    VirtioNotifyStats,
 };
 use crate::{
     CONFIG_STATUS_ACKNOWLEDGE, CONFIG_STATUS_DRIVER, CONFIG_STATUS_DRIVER_OK, CONFIG_STATUS_FAILED,
@@ -492,6 +494,8 @@ pub struct VirtioPciDevice {
     queues: Arc<Mutex<Vec<Arc<Mutex<Queue>>>>>,
     /// Multi-Function flag.
     multi_func: bool,
+    /// Counters of notifications between guest and device.
+    notify_stats: Arc<VirtioNotifyStats>,
 }
 
 impl VirtioPciDevice {
@@ -522,6 +526,7 @@ impl VirtioPciDevice {
             interrupt_cb: None,
             queues: Arc::new(Mutex::new(Vec::with_capacity(queue_num))),
             multi_func,
+            notify_stats: Arc::new(VirtioNotifyStats::default()),
         }
     }
 
@@ -529,6 +534,7 @@ impl VirtioPciDevice {
         let cloned_common_cfg = self.common_config.clone();
         let cloned_msix = self.config.msix.clone();
         let dev_id = self.dev_id.clone();
+        let notify_stats = self.notify_stats.clone();
         let cb = Arc::new(Box::new(
             move |int_type: &VirtioInterruptType, queue: Option<&Queue>| {
                 let vector = match int_type {
@@ -546,6 +552,7 @@ impl VirtioPciDevice {
                     msix.lock()
                         .unwrap()
                         .notify(vector, dev_id.load(Ordering::Acquire));
+                    notify_stats.account_interrupt();
                 } else {
                     bail!("Failed to send interrupt, msix does not exist");
                 }
@@ -678,13 +685,16 @@ impl VirtioPciDevice {
                     )
             {
                 let queue_type = cloned_pci_device.common_config.lock().unwrap().queue_type;
+                // The queues used may be less than the queues offered, depending on the
+                // features negotiated.
+                let queue_num = cloned_pci_device.device.lock().unwrap().queue_num();
                 let queues_config = &mut cloned_pci_device
                     .common_config
                     .lock()
                     .unwrap()
                     .queues_config;
                 let mut locked_queues = cloned_pci_device.queues.lock().unwrap();
-                for q_config in queues_config.iter_mut() {
+                for q_config in queues_config.iter_mut().take(queue_num) {
                     q_config.addr_cache.desc_table_host = cloned_mem_space
                         .get_host_address(q_config.desc_table)
                         .unwrap_or(0);
@@ -942,6 +952,11 @@ impl PciDevOps for VirtioPciDevice {
         )?;
 
         self.assign_interrupt_cb();
+        self.device
+            .lock()
+            .unwrap()
+            .set_notify_stats(self.notify_stats.clone());
+        register_notify_stats(self.name.clone(), &self.notify_stats);
 
         let mut mem_region_size = ((VIRTIO_PCI_CAP_NOTIFY_OFFSET + VIRTIO_PCI_CAP_NOTIFY_LENGTH)
             as u64)